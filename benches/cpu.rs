@@ -0,0 +1,99 @@
+/* Instruction throughput on synthetic ROMs. Run with `cargo bench`.
+ * Each program loops forever from $0100 so the CPU never leaves ROM. */
+use std::{hint::black_box, time::{Duration, Instant}};
+
+use gba::{gba::prelude::Opcode, Emulator, RomSource};
+
+const STEPS: usize = 2_000_000;
+const SAMPLES: usize = 5;
+const FRAMES: usize = 600;
+
+fn emulator(program: &[u8]) -> Emulator {
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = 0x01;
+    rom[0x14B] = 0x33;
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+
+    let mut emulator = Emulator::new(RomSource::Bytes(rom)).unwrap();
+    emulator.console_mut().cpu.registers.pc = 0x100;
+    emulator
+}
+
+/* Best of SAMPLES runs, reported as instructions per second */
+fn bench<F: FnMut() -> usize>(name: &str, mut run: F) {
+    let mut best = Duration::MAX;
+    let mut count = 0;
+    for _ in 0..SAMPLES {
+        let start = Instant::now();
+        count = black_box(run());
+        best = best.min(start.elapsed());
+    }
+    let per_sec = count as f64 / best.as_secs_f64();
+    println!("{:<12} {:>8.2} M instr/s  {:>6.2} ns/instr", name, per_sec / 1e6, 1e9 / per_sec);
+}
+
+fn steps(program: &[u8]) -> impl FnMut() -> usize {
+    let mut emulator = emulator(program);
+    move || {
+        for _ in 0..STEPS {
+            let _ = emulator.step();
+        }
+        STEPS
+    }
+}
+
+/* Whole frames of `program`, as a multiple of the console's own speed */
+fn frames(name: &str, program: &[u8]) {
+    let mut emulator = emulator(program);
+    let (start, counters) = (Instant::now(), emulator.counters());
+    for _ in 0..FRAMES {
+        let _ = emulator.run_frame();
+    }
+    let rates = emulator.counters().rates_since(&counters, start.elapsed());
+    println!("{:<12} {:>8.2} x realtime  {:>6.2} M instr/s", name, rates.speed(), rates.instructions / 1e6);
+}
+
+fn main() {
+    let mut nops = vec![0x00; 0x40];
+    nops.extend_from_slice(&[0xC3, 0x00, 0x01]);
+    bench("nop", steps(&nops));
+
+    bench("alu", steps(&[
+        0x3C,               /* INC A */
+        0x80,               /* ADD A, B */
+        0xA8,               /* XOR B */
+        0x04,               /* INC B */
+        0x91,               /* SUB C */
+        0x2F,               /* CPL */
+        0x27,               /* DAA */
+        0x17,               /* RLA */
+        0x09,               /* ADD HL, BC */
+        0xC3, 0x00, 0x01,   /* JP $0100 */
+    ]));
+
+    bench("load", steps(&[
+        0x21, 0x00, 0xC0,   /* LD HL, $C000 */
+        0x22,               /* LD (HL+), A */
+        0x3A,               /* LD A, (HL-) */
+        0x7E,               /* LD A, (HL) */
+        0x77,               /* LD (HL), A */
+        0x47,               /* LD B, A */
+        0xF5,               /* PUSH AF */
+        0xC1,               /* POP BC */
+        0xC3, 0x03, 0x01,   /* JP $0103 */
+    ]));
+
+    frames("frames", &nops);
+
+    bench("decode", || {
+        let valid: Vec<u8> = (0..=0xFF_u8).filter(|&byte| Opcode::decode(byte).is_ok()).collect();
+        let mut count = 0;
+        while count < STEPS {
+            for &byte in valid.iter() {
+                let _ = black_box(Opcode::try_from(black_box(byte)));
+            }
+            count += valid.len();
+        }
+        count
+    });
+}
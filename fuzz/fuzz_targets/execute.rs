@@ -0,0 +1,19 @@
+#![no_main]
+
+/* Runs arbitrary bytes as a program on a flat 64kB bus. Decoding and
+ * execution must never panic, whatever the instruction stream does;
+ * debug overflow checks stay on under cargo-fuzz. Run with
+ * `cargo fuzz run execute`. */
+use gba::{cpu::prelude::CpuState, gba::prelude::Gba, mem::prelude::FlatBus};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_STEPS: usize = 4096;
+
+fuzz_target!(|data: &[u8]| {
+    let mut gba = Gba::with_bus(FlatBus::new(data));
+    for _ in 0..MAX_STEPS {
+        gba.step();
+        /* Carry on past illegal opcodes and sleeps to cover more of the input */
+        gba.cpu.state = CpuState::Running;
+    }
+});
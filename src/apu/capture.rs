@@ -0,0 +1,132 @@
+use super::mixer::{to_i16, NATIVE_RATE};
+
+/* Recording what the APU does: the mixed output as a WAV, or the register
+ * writes as a VGM that a chiptune player can render at any quality. */
+
+/* VGM timestamps count samples at this rate whatever the output rate */
+pub const VGM_RATE: u32 = 44_100;
+const VGM_HEADER_LEN: usize = 0x100;
+const VGM_VERSION: u32 = 0x0161;
+/* The DMG's 4 MiHz, as VGM expects it at $80 */
+const GB_CLOCK: u32 = 1 << 22;
+
+/* Offsets from $FF10 the initial dump skips or changes */
+const NR52: usize = 0x16;
+const WAVE_RAM: usize = 0x20;
+const TRIGGERS: [usize; 4] = [0x04, 0x09, 0x0E, 0x13];
+
+/* Mixed stereo output as 16-bit PCM */
+#[derive(Debug, Clone)]
+pub struct WavRecorder {
+    sample_rate: u32,
+    samples: Vec<i16>,
+}
+
+impl WavRecorder {
+    pub fn new(sample_rate: u32) -> Self {
+        Self { sample_rate, samples: Vec::new() }
+    }
+
+    /* Interleaved samples as `Mixer::take_samples` hands them out */
+    pub fn push(&mut self, samples: &[f32]) {
+        self.samples.extend(to_i16(samples));
+    }
+
+    /* Stereo frames recorded so far */
+    pub fn frames(&self) -> usize {
+        self.samples.len() / 2
+    }
+
+    /* A complete RIFF file: 16-bit stereo PCM at the rate recording started at */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data_len = self.samples.len() as u32 * 2;
+        let mut out = Vec::with_capacity(44 + data_len as usize);
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data_len).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16_u32.to_le_bytes());
+        /* PCM, two channels */
+        out.extend_from_slice(&1_u16.to_le_bytes());
+        out.extend_from_slice(&2_u16.to_le_bytes());
+        out.extend_from_slice(&self.sample_rate.to_le_bytes());
+        out.extend_from_slice(&(self.sample_rate * 4).to_le_bytes());
+        out.extend_from_slice(&4_u16.to_le_bytes());
+        out.extend_from_slice(&16_u16.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        for sample in &self.samples {
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+        out
+    }
+}
+
+/* Register writes with the time between them, in VGM 1.61 commands */
+#[derive(Debug, Clone)]
+pub struct VgmLog {
+    commands: Vec<u8>,
+    /* M-cycles since the last wait, scaled by VGM_RATE */
+    pending: u64,
+    samples: u32,
+}
+
+impl VgmLog {
+    /* Starts with the registers as they are so a log begun mid-game plays
+     * back the same. Power first and wave RAM before the channels; trigger
+     * bits are left out so nothing restarts. */
+    pub fn new(regs: &[u8; 0x30]) -> Self {
+        let mut log = Self { commands: Vec::new(), pending: 0, samples: 0 };
+        log.write(NR52 as u8, regs[NR52] & 0x80);
+        for reg in (WAVE_RAM..regs.len()).chain(0..NR52) {
+            let value = if TRIGGERS.contains(&reg) { regs[reg] & 0x7F } else { regs[reg] };
+            log.write(reg as u8, value);
+        }
+        log
+    }
+
+    /* `reg` is the offset from $FF10 */
+    pub fn write(&mut self, reg: u8, value: u8) {
+        self.flush_wait();
+        self.commands.extend_from_slice(&[0xB3, reg, value]);
+    }
+
+    /* Time passing at the APU's 1 MiHz */
+    pub fn advance(&mut self, cycles: usize) {
+        self.pending += cycles as u64 * VGM_RATE as u64;
+    }
+
+    /* Length so far in VGM_RATE samples */
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    fn flush_wait(&mut self) {
+        let mut samples = self.pending / NATIVE_RATE as u64;
+        self.pending %= NATIVE_RATE as u64;
+        self.samples += samples as u32;
+        while samples > 0 {
+            let wait = samples.min(0xFFFF) as u16;
+            self.commands.push(0x61);
+            self.commands.extend_from_slice(&wait.to_le_bytes());
+            samples -= wait as u64;
+        }
+    }
+
+    /* The finished file, header and end marker included */
+    pub fn finish(mut self) -> Vec<u8> {
+        self.flush_wait();
+        self.commands.push(0x66);
+
+        let mut out = vec![0; VGM_HEADER_LEN];
+        let len = (VGM_HEADER_LEN + self.commands.len()) as u32;
+        out[0x00..0x04].copy_from_slice(b"Vgm ");
+        out[0x04..0x08].copy_from_slice(&(len - 0x04).to_le_bytes());
+        out[0x08..0x0C].copy_from_slice(&VGM_VERSION.to_le_bytes());
+        out[0x18..0x1C].copy_from_slice(&self.samples.to_le_bytes());
+        /* Relative to the field itself */
+        out[0x34..0x38].copy_from_slice(&(VGM_HEADER_LEN as u32 - 0x34).to_le_bytes());
+        out[0x80..0x84].copy_from_slice(&GB_CLOCK.to_le_bytes());
+        out.extend_from_slice(&self.commands);
+        out
+    }
+}
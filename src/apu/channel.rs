@@ -0,0 +1,352 @@
+use std::io::ErrorKind;
+
+use crate::state::prelude::{Savestate, StateReader, StateWriter};
+
+/* Length counter shared by all four channels; the wave channel counts from 256 */
+#[derive(Debug, Default, Clone)]
+pub(super) struct Length {
+    pub counter: u16,
+    pub enabled: bool,
+}
+
+impl Length {
+    /* Returns false when the counter runs out and the channel should stop */
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+        true
+    }
+
+    /* NRx4 writes. `quiet` is set when the next frame sequencer step does not
+     * clock length; enabling length then takes an extra clock, and a trigger
+     * reloading an empty counter starts one short. Returns false if the
+     * extra clock ran the counter out without a trigger. */
+    pub fn write_control(&mut self, value: u8, max: u16, quiet: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = value & 0x40 != 0;
+        let trigger = value & 0x80 != 0;
+
+        let mut alive = true;
+        if quiet && !was_enabled && self.enabled && self.counter != 0 {
+            self.counter -= 1;
+            alive = self.counter != 0 || trigger;
+        }
+        if trigger && self.counter == 0 {
+            self.counter = max;
+            if self.enabled && quiet {
+                self.counter -= 1;
+            }
+        }
+        alive
+    }
+}
+
+/* Volume envelope of the square and noise channels, driven by NRx2 */
+#[derive(Debug, Default, Clone)]
+pub(super) struct Envelope {
+    pub volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn trigger(&mut self, nrx2: u8) {
+        self.volume = nrx2 >> 4;
+        self.timer = nrx2 & 0x07;
+    }
+
+    pub fn clock(&mut self, nrx2: u8) {
+        let period = nrx2 & 0x07;
+        if period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = period;
+            if nrx2 & 0x08 != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if nrx2 & 0x08 == 0 && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+/* Channel 1's frequency sweep, driven by NR10 */
+#[derive(Debug, Default, Clone)]
+pub(super) struct Sweep {
+    pub enabled: bool,
+    pub shadow: u16,
+    timer: u8,
+    /* A subtraction has been done since the last trigger */
+    pub negated: bool,
+}
+
+impl Sweep {
+    pub fn trigger(&mut self, nr10: u8, frequency: u16) {
+        self.shadow = frequency;
+        self.timer = Self::period(nr10);
+        self.enabled = nr10 & 0x77 != 0;
+        self.negated = false;
+    }
+
+    /* Period 0 is treated as 8 */
+    fn period(nr10: u8) -> u8 {
+        match (nr10 >> 4) & 0x07 {
+            0 => 8,
+            period => period,
+        }
+    }
+
+    /* The next frequency; anything past 2047 turns the channel off */
+    pub fn next(&mut self, nr10: u8) -> u16 {
+        let offset = self.shadow >> (nr10 & 0x07);
+        if nr10 & 0x08 != 0 {
+            self.negated = true;
+            self.shadow - offset
+        } else {
+            self.shadow + offset
+        }
+    }
+
+    /* Counts down the sweep period, true when a new frequency is due */
+    pub fn clock(&mut self, nr10: u8) -> bool {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer != 0 {
+            return false;
+        }
+        self.timer = Self::period(nr10);
+        self.enabled && nr10 & 0x70 != 0
+    }
+}
+
+const DUTY: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+#[derive(Debug, Default, Clone)]
+pub(super) struct Square {
+    pub on: bool,
+    pub length: Length,
+    pub envelope: Envelope,
+    /* T-cycles until the duty position advances */
+    timer: u32,
+    position: u8,
+}
+
+impl Square {
+    pub fn trigger(&mut self, frequency: u16) {
+        self.timer = (2048 - frequency as u32) * 4;
+    }
+
+    pub fn run(&mut self, mut dots: u32, frequency: u16) {
+        let period = (2048 - frequency as u32) * 4;
+        while dots >= self.timer {
+            dots -= self.timer;
+            self.timer = period;
+            self.position = (self.position + 1) & 0x07;
+        }
+        self.timer -= dots;
+    }
+
+    pub fn output(&self, nrx1: u8) -> u8 {
+        let high = DUTY[(nrx1 >> 6) as usize] >> self.position & 1 != 0;
+        if self.on && high { self.envelope.volume } else { 0 }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self { length: self.length.clone(), ..Self::default() };
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub(super) struct Wave {
+    pub on: bool,
+    pub length: Length,
+    timer: u32,
+    /* Nibble index 0-31 into wave RAM, high nibble first */
+    pub position: u8,
+}
+
+impl Wave {
+    pub fn trigger(&mut self, frequency: u16) {
+        self.timer = (2048 - frequency as u32) * 2;
+        self.position = 0;
+    }
+
+    pub fn run(&mut self, mut dots: u32, frequency: u16) {
+        let period = (2048 - frequency as u32) * 2;
+        while dots >= self.timer {
+            dots -= self.timer;
+            self.timer = period;
+            self.position = (self.position + 1) & 0x1F;
+        }
+        self.timer -= dots;
+    }
+
+    pub fn output(&self, nr32: u8, wave_ram: &[u8]) -> u8 {
+        if !self.on {
+            return 0;
+        }
+        let byte = wave_ram[self.position as usize / 2];
+        let sample = if self.position & 1 == 0 { byte >> 4 } else { byte & 0x0F };
+        match (nr32 >> 5) & 0x03 {
+            0 => 0,
+            shift => sample >> (shift - 1),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self { length: self.length.clone(), ..Self::default() };
+    }
+}
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Debug, Clone)]
+pub(super) struct Noise {
+    pub on: bool,
+    pub length: Length,
+    pub envelope: Envelope,
+    timer: u32,
+    lfsr: u16,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self { on: false, length: Length::default(), envelope: Envelope::default(), timer: 8, lfsr: 0x7FFF }
+    }
+}
+
+impl Noise {
+    fn period(nr43: u8) -> u32 {
+        NOISE_DIVISORS[(nr43 & 0x07) as usize] << (nr43 >> 4)
+    }
+
+    pub fn trigger(&mut self, nr43: u8) {
+        self.timer = Self::period(nr43);
+        self.lfsr = 0x7FFF;
+    }
+
+    pub fn run(&mut self, mut dots: u32, nr43: u8) {
+        /* Shifts 14 and 15 stop the LFSR */
+        if nr43 >> 4 >= 14 {
+            return;
+        }
+        while dots >= self.timer {
+            dots -= self.timer;
+            self.timer = Self::period(nr43);
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if nr43 & 0x08 != 0 {
+                self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+            }
+        }
+        self.timer -= dots;
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.on && self.lfsr & 1 == 0 { self.envelope.volume } else { 0 }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self { length: self.length.clone(), ..Self::default() };
+    }
+}
+
+impl Savestate for Length {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.counter);
+        w.bool(self.enabled);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.counter = r.u16()?;
+        self.enabled = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Savestate for Envelope {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.volume);
+        w.u8(self.timer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.volume = r.u8()? & 0x0F;
+        self.timer = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Savestate for Sweep {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.u16(self.shadow);
+        w.u8(self.timer);
+        w.bool(self.negated);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.enabled = r.bool()?;
+        self.shadow = r.u16()?;
+        self.timer = r.u8()?;
+        self.negated = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Savestate for Square {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.on);
+        self.length.save_state(w);
+        self.envelope.save_state(w);
+        w.u32(self.timer);
+        w.u8(self.position);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.on = r.bool()?;
+        self.length.load_state(r)?;
+        self.envelope.load_state(r)?;
+        self.timer = r.u32()?;
+        self.position = r.u8()? & 0x07;
+        Ok(())
+    }
+}
+
+impl Savestate for Wave {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.on);
+        self.length.save_state(w);
+        w.u32(self.timer);
+        w.u8(self.position);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.on = r.bool()?;
+        self.length.load_state(r)?;
+        self.timer = r.u32()?;
+        self.position = r.u8()? & 0x1F;
+        Ok(())
+    }
+}
+
+impl Savestate for Noise {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.on);
+        self.length.save_state(w);
+        self.envelope.save_state(w);
+        w.u32(self.timer);
+        w.u16(self.lfsr);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.on = r.bool()?;
+        self.length.load_state(r)?;
+        self.envelope.load_state(r)?;
+        self.timer = r.u32()?;
+        self.lfsr = r.u16()? & 0x7FFF;
+        Ok(())
+    }
+}
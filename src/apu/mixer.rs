@@ -0,0 +1,178 @@
+/* The APU is clocked once per M-cycle */
+pub const NATIVE_RATE: u32 = 1 << 20;
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+/* Per T-cycle charge factor of the DMG's output capacitor */
+const CAPACITOR_CHARGE: f64 = 0.999958;
+
+/* One of the four sound channels, for muting and soloing */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [Channel::Square1, Channel::Square2, Channel::Wave, Channel::Noise];
+    pub const NAMES: [&'static str; 4] = ["square1", "square2", "wave", "noise"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES.iter().position(|&known| known == name).map(|i| Self::ALL[i])
+    }
+
+    pub fn name(self) -> &'static str {
+        Self::NAMES[self.index()]
+    }
+
+    /* "wave, noise", as the `mute` setting lists them; empty for none */
+    pub fn parse_list(list: &str) -> Option<Vec<Self>> {
+        list.split(',').map(str::trim).filter(|name| !name.is_empty()).map(Self::from_name).collect()
+    }
+
+    /* 0-3, the bit NR51 and NR52 use for the channel */
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/* Turns the four DAC outputs into stereo frames at the host sample rate.
+ * Each output sample is the average of the native frames it spans, so the
+ * resampler doubles as a low-pass box filter; the high-pass filter then
+ * removes the DC offset the DACs leave behind. */
+#[derive(Debug)]
+pub struct Mixer {
+    sample_rate: u32,
+    high_pass: bool,
+    /* Advances by `sample_rate` per native frame, emitting a sample at NATIVE_RATE */
+    phase: u32,
+    sum: [f32; 2],
+    frames: u32,
+    capacitor: [f32; 2],
+    charge: f32,
+    /* Debug overrides; the channels keep running, they are just not heard */
+    muted: [bool; 4],
+    solo: Option<Channel>,
+    /* Interleaved left/right */
+    samples: Vec<f32>,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        let mut mixer = Self {
+            sample_rate: 0,
+            high_pass: true,
+            phase: 0,
+            sum: [0.0; 2],
+            frames: 0,
+            capacitor: [0.0; 2],
+            charge: 0.0,
+            muted: [false; 4],
+            solo: None,
+            samples: Vec::new(),
+        };
+        mixer.set_sample_rate(DEFAULT_SAMPLE_RATE);
+        mixer
+    }
+}
+
+impl Mixer {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /* Takes effect from the sample in progress, so pacing can trim the
+     * rate every frame without a click */
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate.clamp(1, NATIVE_RATE);
+        self.charge = CAPACITOR_CHARGE.powf(4.0 * NATIVE_RATE as f64 / self.sample_rate as f64) as f32;
+    }
+
+    pub fn set_high_pass(&mut self, enabled: bool) {
+        self.high_pass = enabled;
+        self.capacitor = [0.0; 2];
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel.index()] = muted;
+    }
+
+    pub fn muted(&self, channel: Channel) -> bool {
+        self.muted[channel.index()]
+    }
+
+    /* Only `channel` is heard while set, whether or not it is muted */
+    pub fn set_solo(&mut self, solo: Option<Channel>) {
+        self.solo = solo;
+    }
+
+    pub fn solo(&self) -> Option<Channel> {
+        self.solo
+    }
+
+    pub fn audible(&self, channel: Channel) -> bool {
+        match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.muted[channel.index()],
+        }
+    }
+
+    /* Mixes one native frame. `outputs` are the channels' digital levels,
+     * `dacs` which DACs are powered. VIN (NR50 bits 3 and 7) is ignored as
+     * no cartridge drives it. */
+    pub fn push(&mut self, outputs: [u8; 4], dacs: [bool; 4], nr50: u8, nr51: u8) {
+        let mut frame = [0.0; 2];
+        for channel in 0..4 {
+            if !dacs[channel] || !self.audible(Channel::ALL[channel]) {
+                continue;
+            }
+            /* Level 0 maps to +1 and 15 to -1, as on the DMG */
+            let analog = 1.0 - outputs[channel] as f32 / 7.5;
+            if nr51 & (0x10 << channel) != 0 {
+                frame[0] += analog;
+            }
+            if nr51 & (0x01 << channel) != 0 {
+                frame[1] += analog;
+            }
+        }
+        let volume = [((nr50 >> 4) & 0x07) + 1, (nr50 & 0x07) + 1];
+        for side in 0..2 {
+            self.sum[side] += frame[side] / 4.0 * volume[side] as f32 / 8.0;
+        }
+        self.frames += 1;
+
+        self.phase += self.sample_rate;
+        if self.phase >= NATIVE_RATE {
+            self.phase -= NATIVE_RATE;
+            self.emit();
+        }
+    }
+
+    fn emit(&mut self) {
+        for side in 0..2 {
+            let mut sample = self.sum[side] / self.frames as f32;
+            if self.high_pass {
+                let out = sample - self.capacitor[side];
+                self.capacitor[side] = sample - out * self.charge;
+                sample = out;
+            }
+            self.samples.push(sample);
+        }
+        self.sum = [0.0; 2];
+        self.frames = 0;
+
+        /* Nobody is draining the buffer; drop the oldest second */
+        if self.samples.len() > self.sample_rate as usize * 4 {
+            self.samples.drain(..self.sample_rate as usize * 2);
+        }
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+/* Interleaved f32 samples as signed 16-bit PCM */
+pub fn to_i16(samples: &[f32]) -> Vec<i16> {
+    samples.iter().map(|&sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).collect()
+}
@@ -0,0 +1,12 @@
+#![allow(unused)]
+
+mod capture;
+mod sound;
+mod channel;
+mod mixer;
+
+pub mod prelude {
+    pub use super::capture::{VgmLog, WavRecorder, VGM_RATE};
+    pub use super::mixer::{to_i16, Channel, Mixer, DEFAULT_SAMPLE_RATE, NATIVE_RATE};
+    pub use super::sound::Apu;
+}
@@ -0,0 +1,414 @@
+use std::{io::ErrorKind, ops::RangeInclusive};
+
+use crate::{debug, mem::prelude::MemoryMappedDevice, state::prelude::{Savestate, StateReader, StateWriter}, trace};
+
+use super::{capture::{VgmLog, WavRecorder}, channel::{Noise, Square, Sweep, Wave}, mixer::Mixer};
+
+/* Offsets from $FF10 */
+const NR10: usize = 0x00;
+const NR11: usize = 0x01;
+const NR12: usize = 0x02;
+const NR13: usize = 0x03;
+const NR14: usize = 0x04;
+const NR21: usize = 0x06;
+const NR22: usize = 0x07;
+const NR23: usize = 0x08;
+const NR24: usize = 0x09;
+const NR30: usize = 0x0A;
+const NR31: usize = 0x0B;
+const NR32: usize = 0x0C;
+const NR33: usize = 0x0D;
+const NR34: usize = 0x0E;
+const NR41: usize = 0x10;
+const NR42: usize = 0x11;
+const NR43: usize = 0x12;
+const NR44: usize = 0x13;
+const NR50: usize = 0x14;
+const NR51: usize = 0x15;
+const NR52: usize = 0x16;
+const WAVE_RAM: usize = 0x20;
+
+/* Bits that always read back as 1: write-only fields and unused bits */
+const READ_MASKS: [u8; 0x30] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF,
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF,
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF,
+    0xFF, 0xFF, 0x00, 0x00, 0xBF,
+    0x00, 0x00, 0x70,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/* $FF10-$FF3F. The frame sequencer has no clock of its own: it steps on
+ * each DIV-APU edge handed over by the timer, clocking length at 256 Hz,
+ * sweep at 128 Hz and the envelopes at 64 Hz. */
+#[derive(Debug)]
+pub struct Apu {
+    regs: [u8; 0x30],
+    /* `regs` with READ_MASKS and the NR52 status bits applied */
+    readback: [u8; 0x30],
+    /* Next frame sequencer step, 0-7 */
+    sequencer: u8,
+    square1: Square,
+    sweep: Sweep,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    pub mixer: Mixer,
+    /* Captures, while recording; neither is part of a savestate */
+    wav: Option<WavRecorder>,
+    vgm: Option<VgmLog>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        let mut apu = Self {
+            regs: [0; 0x30],
+            readback: [0; 0x30],
+            sequencer: 0,
+            square1: Square::default(),
+            sweep: Sweep::default(),
+            square2: Square::default(),
+            wave: Wave::default(),
+            noise: Noise::default(),
+            mixer: Mixer::default(),
+            wav: None,
+            vgm: None,
+        };
+        apu.refresh_all();
+        apu
+    }
+}
+
+impl Apu {
+    pub fn register(&self, addr: u16) -> &u8 {
+        match addr {
+            0xFF10..=0xFF3F => &self.readback[addr as usize - 0xFF10],
+            _ => panic!("Accessing memory ${:#04X}: Not a sound register", addr),
+        }
+    }
+
+    /* Raw access with no side effects; the value shows up in reads but does not reach the channels */
+    pub fn register_mut(&mut self, addr: u16) -> &mut u8 {
+        match addr {
+            0xFF10..=0xFF3F => &mut self.readback[addr as usize - 0xFF10],
+            _ => panic!("Accessing memory ${:#04X}: Not a sound register", addr),
+        }
+    }
+
+    pub fn powered(&self) -> bool {
+        self.regs[NR52] & 0x80 != 0
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        let reg = match addr {
+            0xFF10..=0xFF3F => addr as usize - 0xFF10,
+            _ => panic!("Accessing memory ${:#04X}: Not a sound register", addr),
+        };
+        if let Some(vgm) = &mut self.vgm {
+            vgm.write(reg as u8, value);
+        }
+        match reg {
+            WAVE_RAM.. => self.regs[reg] = value,
+            NR52 => self.set_power(value & 0x80 != 0),
+            /* While powered off the DMG still takes length writes, and nothing else */
+            _ if !self.powered() => match reg {
+                NR11 => self.square1.length.counter = 64 - (value & 0x3F) as u16,
+                NR21 => self.square2.length.counter = 64 - (value & 0x3F) as u16,
+                NR31 => self.wave.length.counter = 256 - value as u16,
+                NR41 => self.noise.length.counter = 64 - (value & 0x3F) as u16,
+                _ => (),
+            },
+            _ => {
+                self.regs[reg] = value;
+                self.apply_write(reg, value);
+            },
+        }
+        self.refresh(reg);
+        self.refresh_status();
+    }
+
+    fn apply_write(&mut self, reg: usize, value: u8) {
+        /* The next step leaves length alone, so enabling it now takes an extra clock */
+        let quiet = self.sequencer & 1 == 1;
+        if matches!(reg, NR14 | NR24 | NR34 | NR44) && value & 0x80 != 0 {
+            trace!(target: "gbemu::apu", "Channel {} triggered", (reg - NR14) / 5 + 1);
+        }
+        match reg {
+            /* Leaving negate mode after a subtraction stops the channel */
+            NR10 if self.sweep.negated && value & 0x08 == 0 => self.square1.on = false,
+            NR11 => self.square1.length.counter = 64 - (value & 0x3F) as u16,
+            NR12 if value & 0xF8 == 0 => self.square1.on = false,
+            NR14 => {
+                if !self.square1.length.write_control(value, 64, quiet) {
+                    self.square1.on = false;
+                }
+                if value & 0x80 != 0 {
+                    self.trigger_square1();
+                }
+            },
+            NR21 => self.square2.length.counter = 64 - (value & 0x3F) as u16,
+            NR22 if value & 0xF8 == 0 => self.square2.on = false,
+            NR24 => {
+                if !self.square2.length.write_control(value, 64, quiet) {
+                    self.square2.on = false;
+                }
+                if value & 0x80 != 0 {
+                    let frequency = self.frequency(NR23, NR24);
+                    self.square2.on = self.regs[NR22] & 0xF8 != 0;
+                    self.square2.envelope.trigger(self.regs[NR22]);
+                    self.square2.trigger(frequency);
+                }
+            },
+            NR30 if value & 0x80 == 0 => self.wave.on = false,
+            NR31 => self.wave.length.counter = 256 - value as u16,
+            NR34 => {
+                if !self.wave.length.write_control(value, 256, quiet) {
+                    self.wave.on = false;
+                }
+                if value & 0x80 != 0 {
+                    let frequency = self.frequency(NR33, NR34);
+                    self.wave.on = self.regs[NR30] & 0x80 != 0;
+                    self.wave.trigger(frequency);
+                }
+            },
+            NR41 => self.noise.length.counter = 64 - (value & 0x3F) as u16,
+            NR42 if value & 0xF8 == 0 => self.noise.on = false,
+            NR44 => {
+                if !self.noise.length.write_control(value, 64, quiet) {
+                    self.noise.on = false;
+                }
+                if value & 0x80 != 0 {
+                    self.noise.on = self.regs[NR42] & 0xF8 != 0;
+                    self.noise.envelope.trigger(self.regs[NR42]);
+                    self.noise.trigger(self.regs[NR43]);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn trigger_square1(&mut self) {
+        let frequency = self.frequency(NR13, NR14);
+        self.square1.on = self.regs[NR12] & 0xF8 != 0;
+        self.square1.envelope.trigger(self.regs[NR12]);
+        self.square1.trigger(frequency);
+        self.sweep.trigger(self.regs[NR10], frequency);
+        /* A non-zero shift runs the overflow check straight away */
+        if self.regs[NR10] & 0x07 != 0 && self.sweep.next(self.regs[NR10]) > 2047 {
+            self.square1.on = false;
+        }
+    }
+
+    fn set_power(&mut self, on: bool) {
+        if on != self.powered() {
+            debug!(target: "gbemu::apu", "Powered {}", if on { "on" } else { "off" });
+        }
+        if on && !self.powered() {
+            self.regs[NR52] = 0x80;
+            self.sequencer = 0;
+        } else if !on && self.powered() {
+            /* Everything but wave RAM and, on the DMG, the length counters is cleared */
+            self.regs[..WAVE_RAM].fill(0);
+            self.square1.reset();
+            self.sweep = Sweep::default();
+            self.square2.reset();
+            self.wave.reset();
+            self.noise.reset();
+            self.refresh_all();
+        }
+    }
+
+    fn frequency(&self, low: usize, high: usize) -> u16 {
+        self.regs[low] as u16 | ((self.regs[high] as u16 & 0x07) << 8)
+    }
+
+    /* One DIV-APU event: a falling edge of DIV bit 4 */
+    pub fn clock_frame_sequencer(&mut self) {
+        if !self.powered() {
+            return;
+        }
+        let step = self.sequencer;
+        self.sequencer = (step + 1) & 0x07;
+
+        if step & 1 == 0 {
+            self.square1.on &= self.square1.length.clock();
+            self.square2.on &= self.square2.length.clock();
+            self.wave.on &= self.wave.length.clock();
+            self.noise.on &= self.noise.length.clock();
+        }
+        if step == 2 || step == 6 {
+            self.clock_sweep();
+        }
+        if step == 7 {
+            self.square1.envelope.clock(self.regs[NR12]);
+            self.square2.envelope.clock(self.regs[NR22]);
+            self.noise.envelope.clock(self.regs[NR42]);
+        }
+        self.refresh_status();
+    }
+
+    fn clock_sweep(&mut self) {
+        let nr10 = self.regs[NR10];
+        if !self.sweep.clock(nr10) {
+            return;
+        }
+        let frequency = self.sweep.next(nr10);
+        if frequency > 2047 {
+            self.square1.on = false;
+        } else if nr10 & 0x07 != 0 {
+            self.sweep.shadow = frequency;
+            self.regs[NR13] = frequency as u8;
+            self.regs[NR14] = (self.regs[NR14] & !0x07) | (frequency >> 8) as u8;
+            if self.sweep.next(nr10) > 2047 {
+                self.square1.on = false;
+            }
+        }
+    }
+
+    /* Advance by `cycles` M-cycles, mixing one frame for each when built
+     * with `audio` */
+    pub fn tick(&mut self, cycles: usize) {
+        let square1 = self.frequency(NR13, NR14);
+        let square2 = self.frequency(NR23, NR24);
+        let wave = self.frequency(NR33, NR34);
+        if let Some(vgm) = &mut self.vgm {
+            vgm.advance(cycles);
+        }
+        for _ in 0..cycles {
+            if self.powered() {
+                self.square1.run(4, square1);
+                self.square2.run(4, square2);
+                self.wave.run(4, wave);
+                self.noise.run(4, self.regs[NR43]);
+            }
+            #[cfg(feature = "audio")]
+            self.mixer.push(self.outputs(), self.dacs(), self.regs[NR50], self.regs[NR51]);
+        }
+    }
+
+    /* A channel's DAC is powered by the top five bits of NRx2, or NR30 bit 7 */
+    pub fn dacs(&self) -> [bool; 4] {
+        [
+            self.regs[NR12] & 0xF8 != 0,
+            self.regs[NR22] & 0xF8 != 0,
+            self.regs[NR30] & 0x80 != 0,
+            self.regs[NR42] & 0xF8 != 0,
+        ]
+    }
+
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.mixer.set_sample_rate(rate);
+    }
+
+    /* Interleaved stereo samples mixed since the last call */
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        let samples = self.mixer.take_samples();
+        if let Some(wav) = &mut self.wav {
+            wav.push(&samples);
+        }
+        samples
+    }
+
+    /* Records the mixed output from here on, as it is taken */
+    pub fn start_wav(&mut self) {
+        self.wav = Some(WavRecorder::new(self.mixer.sample_rate()));
+    }
+
+    /* The WAV recorded since `start_wav`, if recording */
+    pub fn stop_wav(&mut self) -> Option<Vec<u8>> {
+        self.wav.take().map(|wav| wav.to_bytes())
+    }
+
+    /* Logs register writes from here on, starting with the current state */
+    pub fn start_vgm(&mut self) {
+        self.vgm = Some(VgmLog::new(&self.regs));
+    }
+
+    pub fn stop_vgm(&mut self) -> Option<Vec<u8>> {
+        self.vgm.take().map(VgmLog::finish)
+    }
+
+    /* Current digital output 0-15 of channels 1-4, before the DACs */
+    pub fn outputs(&self) -> [u8; 4] {
+        [
+            self.square1.output(self.regs[NR11]),
+            self.square2.output(self.regs[NR21]),
+            self.wave.output(self.regs[NR32], &self.regs[WAVE_RAM..]),
+            self.noise.output(),
+        ]
+    }
+
+    pub fn channel_on(&self, channel: usize) -> bool {
+        self.regs[NR52] & (1 << channel) != 0
+    }
+
+    fn refresh(&mut self, reg: usize) {
+        self.readback[reg] = self.regs[reg] | READ_MASKS[reg];
+    }
+
+    fn refresh_all(&mut self) {
+        for reg in 0..self.regs.len() {
+            self.refresh(reg);
+        }
+        self.refresh_status();
+    }
+
+    fn refresh_status(&mut self) {
+        let on = [self.square1.on, self.square2.on, self.wave.on, self.noise.on];
+        let status = on.iter().enumerate().fold(0, |bits, (i, &on)| bits | ((on as u8) << i));
+        self.regs[NR52] = (self.regs[NR52] & 0x80) | status;
+        self.refresh(NR52);
+    }
+}
+
+const CLAIMS: &[RangeInclusive<u16>] = &[0xFF10..=0xFF3F];
+
+impl MemoryMappedDevice for Apu {
+    fn claims(&self, _cgb: bool) -> &'static [RangeInclusive<u16>] {
+        CLAIMS
+    }
+
+    fn io_register(&self, addr: u16) -> &u8 {
+        self.register(addr)
+    }
+
+    fn io_register_mut(&mut self, addr: u16) -> &mut u8 {
+        self.register_mut(addr)
+    }
+
+    fn io_write(&mut self, addr: u16, value: u8) -> u8 {
+        self.write_register(addr, value);
+        0
+    }
+
+    fn tick(&mut self, cycles: usize) -> u8 {
+        Apu::tick(self, cycles);
+        0
+    }
+}
+
+impl Savestate for Apu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.regs);
+        w.u8(self.sequencer);
+        self.square1.save_state(w);
+        self.sweep.save_state(w);
+        self.square2.save_state(w);
+        self.wave.save_state(w);
+        self.noise.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        r.bytes(&mut self.regs)?;
+        self.sequencer = r.u8()? & 0x07;
+        self.square1.load_state(r)?;
+        self.sweep.load_state(r)?;
+        self.square2.load_state(r)?;
+        self.wave.load_state(r)?;
+        self.noise.load_state(r)?;
+        self.refresh_all();
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+use std::path::PathBuf;
+
+use crate::{gba::prelude::{FrameSkip, RomSource}, log::prelude::Filter, mem::prelude::{RawMap, SaveFormat}};
+
+use super::{settings::Config, toml::{self, ConfigError, Value}};
+
+pub const USAGE: &str = "\
+Usage: gba [OPTIONS] ROM
+       gba info [--json] ROM     Print the cartridge header and exit
+       gba batch [--frames N] [--threads N] [OPTIONS] ROM|DIR...
+                                 Run many ROMs headless in parallel and report how each did
+       gba debug [OPTIONS] ROM   Step through ROM from a command prompt; `help` lists the commands
+
+Settings (override the config file):
+    --config PATH            Config file [default: $XDG_CONFIG_HOME/gba/config.toml]
+    --palette <NAME|RRGGBB,RRGGBB,RRGGBB,RRGGBB>
+    --scale N                Screenshot scale factor
+    --colorize <off|auto|BUTTONS|N>
+                             Color DMG games as the CGB does: auto picks by title like its boot ROM,
+                             or force a combination such as `left+a`, or its number 0 to 50
+    --boot-rom PATH
+    --skip-boot              Start at $0100 without a boot ROM
+    --raw ADDR               Load ROM as a headerless binary at hex ADDR and start there, without a boot ROM
+    --raw-ram                Give a --raw binary 8kB of cartridge RAM at $A000
+    --save-dir PATH
+    --accuracy <fast|balanced|accurate>
+                             Scanline rendering and simplified timer and DMA, or every memory access
+                             on its own M-cycle [default: balanced]
+    --audio-latency MS
+    --sample-rate HZ
+    --mute CHANNELS          Leave channels out of the sound, e.g. `wave,noise`; square1, square2, wave or noise
+    --solo CHANNEL           Hear only this channel
+    --set SECTION.KEY=VALUE  Any other config setting
+
+Session:
+    --frames N               Exit after N frames [batch default: 600]
+    --screenshot PATH        Write the last frame as PNG, or PPM by extension
+    --record-wav PATH        Record the sound output as a WAV
+    --record-vgm PATH        Log sound register writes as a VGM for chiptune players
+    --record-video PATH      Record the screen as an animated GIF for a .gif path, otherwise uncompressed Y4M
+    --record-frames N        Stop recording the screen after N frames
+    --import-save PATH       Load battery RAM from another emulator's .sav before starting
+    --export-save PATH       Write battery RAM on exit, in --save-format
+    --save-format <rtc|raw>  With or without the VBA/BGB clock footer [default: rtc]
+    --load-slot N            Start from save slot N, 0 to 9; slot 0 is the autosave
+    --autosave SECS          Save to slot 0 every SECS seconds
+    --golden PATH            Compare the last frame with a PNG, writing a diff image and failing on change;
+                             a missing PNG is created
+    --turbo
+    --tui                    Play in the terminal, the screen drawn in half blocks beside the registers,
+                             I/O and last instructions; 1-4 mute a channel, 5-8 solo one and Ctrl-C quits
+                             (needs the `tui` feature)
+    --gamepad                Play with game controllers, picked up as they are plugged in (Linux);
+                             remap them in [pads.ID] sections of the config file
+    --speed MULTIPLIER
+    --frameskip <N|auto>
+    --link-listen ADDR | --link-connect ADDR
+    --camera PATH            What a Pocket Camera sees: a PNG, scaled to fill the sensor
+    --webcam DEVICE          Or a V4L2 webcam through ffmpeg (needs the `webcam` feature)
+    --printer DIR            Plug a Game Boy Printer into the link port, writing each printout to DIR as PNG
+    --gdb ADDR               Wait for a gdb remote connection on ADDR and run under it
+    --symbols PATH           RGBDS .sym labels for traces and `monitor break` [default: ROM.sym if present]
+    --script PATH            Run a script alongside the game; repeatable (needs the `scripting` feature)
+    --cdl PATH               Log ROM bytes used as code, data or DMA source to PATH, adding to it if present
+    --profile PATH           Count cycles per instruction; print hotspots and write callgrind data to PATH
+    --log SPEC               Log filter, e.g. `warn,gbemu::mem=debug` [default: $GBEMU_LOG]
+                             Targets: gbemu::cpu (trace: instructions), gbemu::cpu::irq,
+                             gbemu::mem (debug: MBC banking), gbemu::ppu, gbemu::apu, gbemu::timer,
+                             gbemu::sgb, gbemu::gdb, gbemu::script, gbemu::input";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Command {
+    #[default]
+    Run,
+    Info { json: bool },
+    /* Worker threads, one per host thread when unset */
+    Batch { threads: Option<usize> },
+    /* The debugger REPL on stdin and stdout */
+    Debug,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkMode {
+    Listen(String),
+    Connect(String),
+    /* A Game Boy Printer writing its printouts to a directory */
+    Printer(PathBuf),
+}
+
+/* Command line: session options, plus config overrides applied over the file */
+#[derive(Debug, Clone, Default)]
+pub struct Cli {
+    pub command: Command,
+    pub rom: PathBuf,
+    /* Every ROM given, for `batch`; otherwise the last is `rom` */
+    pub roms: Vec<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub frames: Option<u64>,
+    pub screenshot: Option<PathBuf>,
+    pub record_wav: Option<PathBuf>,
+    pub record_vgm: Option<PathBuf>,
+    pub record_video: Option<PathBuf>,
+    pub record_frames: Option<u32>,
+    pub golden: Option<PathBuf>,
+    pub import_save: Option<PathBuf>,
+    pub export_save: Option<PathBuf>,
+    pub save_format: SaveFormat,
+    pub load_slot: Option<u8>,
+    pub turbo: bool,
+    pub gamepad: bool,
+    pub tui: bool,
+    pub camera: Option<PathBuf>,
+    pub webcam: Option<String>,
+    pub speed: Option<f64>,
+    pub frame_skip: Option<FrameSkip>,
+    pub link: Option<LinkMode>,
+    pub log: Option<String>,
+    pub gdb: Option<String>,
+    pub symbols: Option<PathBuf>,
+    pub profile: Option<PathBuf>,
+    pub cdl: Option<PathBuf>,
+    pub scripts: Vec<PathBuf>,
+    /* Where `--raw` loads the ROM, which then needs no header */
+    pub raw_origin: Option<u16>,
+    pub raw_ram: bool,
+    /* (section, key, value) in the order given */
+    pub overrides: Vec<(String, String, Value)>,
+}
+
+impl Cli {
+    /* Parses arguments without the program name. `Ok(None)` means help was asked for. */
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Self>, String> {
+        let mut args = args.into_iter().peekable();
+        let mut cli = Self::default();
+        if args.next_if(|arg| arg == "info").is_some() {
+            cli.command = Command::Info { json: false };
+        } else if args.next_if(|arg| arg == "batch").is_some() {
+            cli.command = Command::Batch { threads: None };
+        } else if args.next_if(|arg| arg == "debug").is_some() {
+            cli.command = Command::Debug;
+        }
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("`{}` needs a value", arg));
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--json" => match &mut cli.command {
+                    Command::Info { json } => *json = true,
+                    _ => return Err("`--json` is only for `info`".to_string()),
+                },
+                "--threads" => match &mut cli.command {
+                    Command::Batch { threads } => *threads = Some(value()?.parse().map_err(|_| format!("`{}` needs a thread count", arg))?),
+                    _ => return Err("`--threads` is only for `batch`".to_string()),
+                },
+                "--config" => cli.config = Some(PathBuf::from(value()?)),
+                "--palette" => cli.set("video", "palette", Value::String(value()?)),
+                "--scale" => cli.set("video", "scale", number(&arg, &value()?)?),
+                "--colorize" => cli.set("video", "colorize", Value::String(value()?)),
+                "--boot-rom" => cli.set("core", "boot_rom", Value::String(value()?)),
+                "--skip-boot" => cli.set("core", "skip_boot", Value::Boolean(true)),
+                "--save-dir" => cli.set("core", "save_dir", Value::String(value()?)),
+                "--accuracy" => cli.set("core", "accuracy", Value::String(value()?)),
+                "--audio-latency" => cli.set("audio", "latency", number(&arg, &value()?)?),
+                "--sample-rate" => cli.set("audio", "sample_rate", number(&arg, &value()?)?),
+                "--mute" => cli.set("audio", "mute", Value::String(value()?)),
+                "--solo" => cli.set("audio", "solo", Value::String(value()?)),
+                "--set" => {
+                    let setting = value()?;
+                    let (name, value) = setting.split_once('=').ok_or_else(|| format!("`--set {}` is not KEY=VALUE", setting))?;
+                    let (section, key) = name.rsplit_once('.').unwrap_or(("", name));
+                    /* Bare words are taken as strings */
+                    let value = toml::parse_value(value).unwrap_or_else(|_| Value::String(value.to_string()));
+                    cli.set(section.trim(), key.trim(), value);
+                },
+                "--frames" => cli.frames = Some(value()?.parse().map_err(|_| format!("`{}` needs a frame count", arg))?),
+                "--screenshot" => cli.screenshot = Some(PathBuf::from(value()?)),
+                "--record-wav" => cli.record_wav = Some(PathBuf::from(value()?)),
+                "--record-vgm" => cli.record_vgm = Some(PathBuf::from(value()?)),
+                "--record-video" => cli.record_video = Some(PathBuf::from(value()?)),
+                "--record-frames" => cli.record_frames = Some(value()?.parse().map_err(|_| format!("`{}` needs a frame count", arg))?),
+                "--golden" => cli.golden = Some(PathBuf::from(value()?)),
+                "--import-save" => cli.import_save = Some(PathBuf::from(value()?)),
+                "--export-save" => cli.export_save = Some(PathBuf::from(value()?)),
+                "--save-format" => {
+                    let name = value()?;
+                    cli.save_format = SaveFormat::from_name(&name)
+                        .ok_or_else(|| format!("`{}` is not one of {}", name, SaveFormat::NAMES.join(", ")))?;
+                },
+                "--load-slot" => cli.load_slot = Some(value()?.parse().map_err(|_| format!("`{}` needs a slot number", arg))?),
+                "--autosave" => cli.set("core", "autosave", number(&arg, &value()?)?),
+                "--raw" => {
+                    let addr = value()?;
+                    let hex = addr.trim_start_matches('$').trim_start_matches("0x");
+                    cli.raw_origin = Some(u16::from_str_radix(hex, 16).map_err(|_| format!("`{}` needs a hex address", arg))?);
+                },
+                "--raw-ram" => cli.raw_ram = true,
+                "--turbo" => cli.turbo = true,
+                "--gamepad" => cli.gamepad = true,
+                "--tui" => cli.tui = true,
+                "--speed" => cli.speed = Some(value()?.trim_end_matches('x').parse().map_err(|_| format!("`{}` needs a multiplier", arg))?),
+                "--frameskip" => cli.frame_skip = Some(match value()?.as_str() {
+                    "auto" => FrameSkip::Auto,
+                    n => FrameSkip::Fixed(n.parse().map_err(|_| format!("`{}` needs a count or `auto`", arg))?),
+                }),
+                "--link-listen" => cli.link = Some(LinkMode::Listen(value()?)),
+                "--link-connect" => cli.link = Some(LinkMode::Connect(value()?)),
+                "--camera" => cli.camera = Some(PathBuf::from(value()?)),
+                "--webcam" => cli.webcam = Some(value()?),
+                "--printer" => cli.link = Some(LinkMode::Printer(PathBuf::from(value()?))),
+                "--gdb" => cli.gdb = Some(value()?),
+                "--symbols" => cli.symbols = Some(PathBuf::from(value()?)),
+                "--script" => cli.scripts.push(PathBuf::from(value()?)),
+                "--cdl" => cli.cdl = Some(PathBuf::from(value()?)),
+                "--profile" => cli.profile = Some(PathBuf::from(value()?)),
+                "--log" => {
+                    let spec = value()?;
+                    Filter::parse(&spec)?;
+                    cli.log = Some(spec);
+                },
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                _ => cli.roms.push(PathBuf::from(arg)),
+            }
+        }
+
+        cli.rom = cli.roms.last().cloned().ok_or("no ROM given")?;
+        Ok(Some(cli))
+    }
+
+    /* The ROM as a cart, or as a headerless binary with `--raw` */
+    pub fn rom_source(&self) -> std::io::Result<RomSource> {
+        Ok(match self.raw_origin {
+            Some(origin) => {
+                let map = if self.raw_ram { RawMap::RomRam } else { RawMap::RomOnly };
+                RomSource::RawBinary { data: std::fs::read(&self.rom)?, origin, map }
+            },
+            None => RomSource::from(self.rom.clone()),
+        })
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: Value) {
+        self.overrides.push((section.to_string(), key.to_string(), value));
+    }
+
+    /* The config file, or defaults when there is none at the default path,
+     * with the command line applied on top */
+    pub fn load_config(&self) -> Result<Config, ConfigError> {
+        let mut config = match (&self.config, Config::default_path()) {
+            (Some(path), _) => Config::load(path)?,
+            (None, Some(path)) if path.exists() => Config::load(path)?,
+            _ => Config::default(),
+        };
+        for (section, key, value) in &self.overrides {
+            config.set(section, key, value)?;
+        }
+        Ok(config)
+    }
+}
+
+fn number(arg: &str, value: &str) -> Result<Value, String> {
+    value.parse().map(Value::Integer).map_err(|_| format!("`{}` needs a number", arg))
+}
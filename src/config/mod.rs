@@ -0,0 +1,13 @@
+#![allow(unused)]
+
+#[cfg(feature = "frontend-sdl")]
+mod args;
+mod settings;
+mod toml;
+
+pub mod prelude {
+    #[cfg(feature = "frontend-sdl")]
+    pub use super::args::{Cli, Command, LinkMode, USAGE};
+    pub use super::settings::{Config, KeyBindings};
+    pub use super::toml::{parse as parse_toml, ConfigError, Document, Value};
+}
@@ -0,0 +1,345 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use crate::{apu::prelude::{Channel, DEFAULT_SAMPLE_RATE}, gba::prelude::{AccuracyLevel, GameShark, PacingMode}, input::prelude::{Binding, BindingTable, Button, PadBindings}, mem::prelude::RamFill, ppu::prelude::{ColorizeMode, Palette, ScaleMode}};
+
+use super::toml::{self, ConfigError, Value};
+
+/* Host key names for each `Button`, in `Button` order */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBindings(pub [String; 8]);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self(["Right", "Left", "Up", "Down", "X", "Z", "Backspace", "Return"].map(String::from))
+    }
+}
+
+impl KeyBindings {
+    pub const NAMES: [&'static str; 8] = ["right", "left", "up", "down", "a", "b", "select", "start"];
+
+    pub fn key(&self, button: Button) -> &str {
+        &self.0[button as usize]
+    }
+
+    pub fn set(&mut self, button: Button, key: &str) {
+        self.0[button as usize] = key.to_string();
+    }
+
+    /* Keys are matched case-insensitively */
+    pub fn button(&self, key: &str) -> Option<Button> {
+        self.0.iter().position(|bound| bound.eq_ignore_ascii_case(key)).map(|i| Button::from(i as u8))
+    }
+}
+
+/* Settings shared by the core and the frontend. Loaded from a TOML file:
+ *
+ *   [core]  boot_rom, skip_boot, save_dir, oam_bug, sgb, ram_fill, fixed_time,
+ *           autosave (seconds), crash_dir, accuracy (fast, balanced, accurate),
+ *           slot_dir, cheats (on or off), cheat_codes (GameShark, comma-separated)
+ *   [video] palette (preset name or four RRGGBB colors), scale,
+ *           scale_mode (integer, aspect, stretch), colorize (off, auto, a
+ *           boot ROM button combination like up+a, or its number)
+ *   [audio] latency (ms), sample_rate (Hz), mute (channels, comma-separated),
+ *           solo (one channel, or off); channels are square1, square2,
+ *           wave and noise
+ *   [keys]  right, left, up, down, a, b, select, start
+ *   [turbo] rate (taps per second), and the same buttons for keys that
+ *           tap them while held
+ *   [pads]  rumble (percent of the motor's strength pads shake with, 0 off)
+ *   [pads.ID] the same buttons for one game controller, each a list like
+ *           "button 1" or "axis 0+, axis 6+"; ID is the controller's name
+ *           through `pad_id`, and unlisted buttons keep the default layout
+ *   [games.ID] palette, colorize, accuracy, cheats, cheat_codes and slot_dir
+ *           for one game, over the settings above; ID is `Cart::header_hash`
+ *
+ * Every setting is optional and unknown keys are an error. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub boot_rom: Option<PathBuf>,
+    /* Start at $0100 without running any boot ROM */
+    pub skip_boot: bool,
+    /* Battery saves go next to the ROM when unset */
+    pub save_dir: Option<PathBuf>,
+    /* Accuracy: emulate OAM corruption from 16-bit inc/dec during mode 2 */
+    pub oam_bug: bool,
+    /* Run SGB-flagged carts as on a Super Game Boy, with border and palettes */
+    pub sgb: bool,
+    pub ram_fill: RamFill,
+    /* UNIX time the cartridge clock sees instead of the host's, for
+     * reproducible runs */
+    pub fixed_time: Option<u64>,
+    /* Seconds between saves to slot 0, off when unset */
+    pub autosave: Option<u32>,
+    /* Crash dumps go next to the battery save when unset */
+    pub crash_dir: Option<PathBuf>,
+    pub accuracy: AccuracyLevel,
+    /* Save slots go next to the battery save when unset */
+    pub slot_dir: Option<PathBuf>,
+    /* Off, `cheat_codes` are kept but not applied */
+    pub cheats: bool,
+    pub cheat_codes: Vec<GameShark>,
+    pub palette: Palette,
+    /* CGB colors for DMG games in place of `palette` */
+    pub colorize: ColorizeMode,
+    pub scale: u32,
+    /* How the frame fills a window of another size */
+    pub scale_mode: ScaleMode,
+    /* What windowed frontends pace to; headless runs always use the timer */
+    pub pacing: PacingMode,
+    pub audio_latency: u32,
+    pub sample_rate: u32,
+    /* Channels left out of the mix; the sound hardware still runs them */
+    pub mute: Vec<Channel>,
+    /* The one channel heard, over `mute` */
+    pub solo: Option<Channel>,
+    pub keys: KeyBindings,
+    pub turbo_rate: u32,
+    pub turbo_keys: Vec<(Button, String)>,
+    pub pads: BTreeMap<String, PadBindings>,
+    /* Percent */
+    pub rumble: u32,
+    /* Overrides by game ID, as written; see `for_game` */
+    pub games: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+/* The settings a `[games.ID]` section may override, and where they
+ * otherwise live */
+const GAME_SETTINGS: [(&str, &str); 6] = [
+    ("palette", "video"), ("colorize", "video"),
+    ("accuracy", "core"), ("cheats", "core"), ("cheat_codes", "core"), ("slot_dir", "core"),
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            boot_rom: None,
+            skip_boot: false,
+            save_dir: None,
+            oam_bug: false,
+            sgb: false,
+            ram_fill: RamFill::default(),
+            fixed_time: None,
+            autosave: None,
+            crash_dir: None,
+            accuracy: AccuracyLevel::default(),
+            slot_dir: None,
+            cheats: true,
+            cheat_codes: Vec::new(),
+            palette: Palette::default(),
+            colorize: ColorizeMode::default(),
+            scale: 1,
+            scale_mode: ScaleMode::default(),
+            pacing: PacingMode::default(),
+            audio_latency: 50,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            mute: Vec::new(),
+            solo: None,
+            keys: KeyBindings::default(),
+            turbo_rate: 10,
+            turbo_keys: Vec::new(),
+            pads: BTreeMap::new(),
+            rumble: 100,
+            games: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        for (section, table) in toml::parse(text)? {
+            for (key, value) in table {
+                config.set(&section, &key, &value)?;
+            }
+        }
+        Ok(config)
+    }
+
+    #[cfg(feature = "io")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(e.kind()))?;
+        Self::from_toml(&text)
+    }
+
+    /* $XDG_CONFIG_HOME/gba/config.toml, falling back to ~/.config */
+    #[cfg(feature = "io")]
+    pub fn default_path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(base.join("gba").join("config.toml"))
+    }
+
+    pub fn set(&mut self, section: &str, key: &str, value: &Value) -> Result<(), ConfigError> {
+        let name = if section.is_empty() { key.to_string() } else { format!("{}.{}", section, key) };
+        let error = |message: &str| ConfigError::Setting { key: name.clone(), message: message.to_string() };
+        let string = || match value {
+            Value::String(value) => Ok(value.as_str()),
+            _ => Err(error("expected a string")),
+        };
+        let boolean = || match value {
+            Value::Boolean(value) => Ok(*value),
+            _ => Err(error("expected true or false")),
+        };
+        let positive = |max: i64| match value {
+            Value::Integer(value) if (1..=max).contains(value) => Ok(*value as u32),
+            Value::Integer(_) => Err(error(&format!("expected 1 to {}", max))),
+            _ => Err(error("expected an integer")),
+        };
+
+        match (section, key) {
+            ("core", "boot_rom") => self.boot_rom = Some(PathBuf::from(string()?)),
+            ("core", "skip_boot") => self.skip_boot = boolean()?,
+            ("core", "save_dir") => self.save_dir = Some(PathBuf::from(string()?)),
+            ("core", "oam_bug") => self.oam_bug = boolean()?,
+            ("core", "sgb") => self.sgb = boolean()?,
+            ("core", "ram_fill") => {
+                self.ram_fill = RamFill::from_name(string()?)
+                    .ok_or_else(|| error("expected zero, ones, random or random:SEED"))?;
+            },
+            ("core", "fixed_time") => match value {
+                Value::Integer(time) if *time >= 0 => self.fixed_time = Some(*time as u64),
+                _ => return Err(error("expected UNIX seconds")),
+            },
+            ("core", "autosave") => self.autosave = Some(positive(24 * 60 * 60)?),
+            ("core", "crash_dir") => self.crash_dir = Some(PathBuf::from(string()?)),
+            ("core", "accuracy") => {
+                self.accuracy = AccuracyLevel::from_name(string()?)
+                    .ok_or_else(|| error(&format!("expected one of {}", AccuracyLevel::NAMES.join(", "))))?;
+            },
+            ("core", "slot_dir") => self.slot_dir = Some(PathBuf::from(string()?)),
+            ("core", "cheats") => self.cheats = boolean()?,
+            ("core", "cheat_codes") => {
+                self.cheat_codes = GameShark::parse_list(string()?)
+                    .ok_or_else(|| error("expected GameShark codes like \"010238CD\", writing to RAM"))?;
+            },
+            ("video", "palette") => {
+                let spec = string()?;
+                self.palette = Palette::from_name(spec).or_else(|| Palette::from_hex(spec))
+                    .ok_or_else(|| error("expected a preset name or four RRGGBB colors"))?;
+            },
+            ("video", "colorize") => {
+                self.colorize = ColorizeMode::from_name(string()?)
+                    .ok_or_else(|| error("expected off, auto, a combination like up+a, or 0 to 50"))?;
+            },
+            ("video", "scale") => self.scale = positive(16)?,
+            ("video", "scale_mode") => {
+                self.scale_mode = ScaleMode::from_name(string()?)
+                    .ok_or_else(|| error(&format!("expected one of {}", ScaleMode::NAMES.join(", "))))?;
+            },
+            ("video", "sync") => {
+                self.pacing = PacingMode::from_name(string()?)
+                    .ok_or_else(|| error(&format!("expected one of {}", PacingMode::NAMES.join(", "))))?;
+            },
+            ("audio", "latency") => self.audio_latency = positive(1000)?,
+            ("audio", "sample_rate") => self.sample_rate = positive(192_000)?,
+            ("audio", "mute") => {
+                self.mute = Channel::parse_list(string()?)
+                    .ok_or_else(|| error(&format!("expected channels from {}", Channel::NAMES.join(", "))))?;
+            },
+            ("audio", "solo") => {
+                self.solo = match string()? {
+                    "off" => None,
+                    name => Some(Channel::from_name(name)
+                        .ok_or_else(|| error(&format!("expected off or one of {}", Channel::NAMES.join(", "))))?),
+                };
+            },
+            ("keys", _) => match KeyBindings::NAMES.iter().position(|&button| button == key) {
+                Some(button) => self.keys.set(Button::from(button as u8), string()?),
+                None => return Err(error("not a button")),
+            },
+            ("turbo", "rate") => self.turbo_rate = positive(30)?,
+            ("turbo", _) => match KeyBindings::NAMES.iter().position(|&button| button == key) {
+                Some(button) => {
+                    let button = Button::from(button as u8);
+                    self.turbo_keys.retain(|(bound, _)| *bound != button);
+                    self.turbo_keys.push((button, string()?.to_string()));
+                },
+                None => return Err(error("not a button")),
+            },
+            ("pads", "rumble") => match value {
+                Value::Integer(percent) if (0..=100).contains(percent) => self.rumble = *percent as u32,
+                _ => return Err(error("expected a percentage, 0 to 100")),
+            },
+            (pad, _) if pad.starts_with("pads.") => match KeyBindings::NAMES.iter().position(|&button| button == key) {
+                Some(button) => {
+                    let inputs = PadBindings::parse_list(string()?)
+                        .ok_or_else(|| error("expected a list like \"button 1\" or \"axis 0+, axis 6+\""))?;
+                    self.pads.entry(pad["pads.".len()..].to_string()).or_default().set(Button::from(button as u8), inputs);
+                },
+                None => return Err(error("not a button")),
+            },
+            (game, _) if game.starts_with("games.") => {
+                let Some(&(_, home)) = GAME_SETTINGS.iter().find(|(setting, _)| *setting == key) else {
+                    return Err(error("not a per-game setting"));
+                };
+                /* Checked now so a bad override fails at load, not when the game starts */
+                Self::default().set(home, key, value).map_err(|e| match e {
+                    ConfigError::Setting { message, .. } => error(&message),
+                    e => e,
+                })?;
+                self.games.entry(game["games.".len()..].to_string()).or_default().insert(key.to_string(), value.clone());
+            },
+            _ => return Err(error("unknown setting")),
+        }
+        Ok(())
+    }
+
+    /* These settings with the `[games.ID]` overrides for game `id` on top */
+    pub fn for_game(&self, id: &str) -> Config {
+        let mut config = self.clone();
+        for (key, value) in self.games.get(id).into_iter().flatten() {
+            if let Some(&(_, home)) = GAME_SETTINGS.iter().find(|(setting, _)| setting == key) {
+                /* Validated when they were read */
+                let _ = config.set(home, key, value);
+            }
+        }
+        config
+    }
+
+    /* The `[keys]` and `[turbo]` bindings as one table for a `Keyboard` */
+    pub fn keyboard(&self) -> BindingTable<String> {
+        let mut table = BindingTable::new(self.turbo_rate);
+        for (button, key) in self.keys.0.iter().enumerate() {
+            table.bind(key.clone(), Binding::Hold(Button::from(button as u8)));
+        }
+        for (button, key) in &self.turbo_keys {
+            table.bind(key.clone(), Binding::Turbo(*button));
+        }
+        table
+    }
+
+    /* Rewrites `[pads.ID]` in the config file at `path` with the current
+     * bindings for that controller, leaving the rest of the file as it is */
+    #[cfg(feature = "io")]
+    pub fn save_pad_bindings<P: AsRef<Path>>(&self, path: P, id: &str) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(ConfigError::Io(e.kind())),
+        };
+        let bindings = self.pads.get(id).cloned().unwrap_or_default();
+        let table: Vec<(&str, Value)> = KeyBindings::NAMES.iter().enumerate()
+            .map(|(button, &name)| (name, Value::String(bindings.list(Button::from(button as u8)))))
+            .collect();
+        let text = toml::replace_section(&text, &format!("pads.{}", id), &table);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|e| ConfigError::Io(e.kind()))?;
+        }
+        std::fs::write(path, text).map_err(|e| ConfigError::Io(e.kind()))
+    }
+
+    /* Where the battery save for `rom` lives */
+    pub fn save_path(&self, rom: &Path) -> PathBuf {
+        let save = rom.with_extension("sav");
+        match (&self.save_dir, save.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => save,
+        }
+    }
+
+    /* Host audio buffer size in stereo frames for the configured latency */
+    pub fn audio_buffer_frames(&self) -> usize {
+        (self.sample_rate as u64 * self.audio_latency as u64 / 1000) as usize
+    }
+}
@@ -0,0 +1,180 @@
+use std::{collections::BTreeMap, fmt, io::ErrorKind};
+
+/* The subset of TOML the config file needs: `[section]` headers, bare
+ * keys, and string, integer, float and boolean values. */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(value) => write!(f, "{:?}", value),
+            Self::Integer(value) => write!(f, "{}", value),
+            Self::Float(value) => write!(f, "{}", value),
+            Self::Boolean(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/* Section name to its keys; keys above the first header go under "" */
+pub type Document = BTreeMap<String, BTreeMap<String, Value>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    Io(ErrorKind),
+    Syntax { line: usize, message: String },
+    /* A key that is not a setting, or a value of the wrong type or range */
+    Setting { key: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(kind) => write!(f, "{}", kind),
+            Self::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            Self::Setting { key, message } => write!(f, "`{}`: {}", key, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub fn parse(text: &str) -> Result<Document, ConfigError> {
+    let mut document = Document::new();
+    let mut section = String::new();
+    document.insert(section.clone(), BTreeMap::new());
+
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: &str| ConfigError::Syntax { line: index + 1, message: message.to_string() };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let name = strip_comment(header).trim_end().strip_suffix(']').ok_or_else(|| error("unterminated section header"))?.trim();
+            if !name.split('.').all(is_bare_key) {
+                return Err(error("invalid section name"));
+            }
+            section = name.to_string();
+            if document.insert(section.clone(), BTreeMap::new()).is_some() && !section.is_empty() {
+                return Err(error("duplicate section"));
+            }
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| error("expected `key = value`"))?;
+        let key = key.trim();
+        if !is_bare_key(key) {
+            return Err(error("invalid key"));
+        }
+        let value = parse_value(value.trim()).map_err(|message| error(&message))?;
+        let table = document.entry(section.clone()).or_default();
+        if table.insert(key.to_string(), value).is_some() {
+            return Err(error("duplicate key"));
+        }
+    }
+    Ok(document)
+}
+
+/* `text` with the section `name` replaced by `table`, or with it added at
+ * the end; everything else, comments included, is kept as written */
+pub fn replace_section(text: &str, name: &str, table: &[(&str, Value)]) -> String {
+    let header = |line: &str| line.trim().strip_prefix('[')
+        .map(|header| strip_comment(header).trim_end().trim_end_matches(']').trim().to_string());
+    let mut out = String::new();
+    let mut skipping = false;
+    for line in text.lines() {
+        if let Some(section) = header(line) {
+            skipping = section == name;
+        }
+        if !skipping {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+    out.push_str(&format!("[{}]\n", name));
+    for (key, value) in table {
+        out.push_str(&format!("{} = {}\n", key, value));
+    }
+    out
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/* Everything before a `#` that is not inside a string */
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => { escaped = true; continue; },
+            (Some(q), _) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &text[..i],
+            _ => (),
+        }
+        escaped = false;
+    }
+    text
+}
+
+/* A single value, optionally followed by a comment */
+pub fn parse_value(text: &str) -> Result<Value, String> {
+    let text = strip_comment(text).trim();
+    if let Some(body) = text.strip_prefix('"') {
+        let body = body.strip_suffix('"').ok_or("unterminated string")?;
+        return unescape(body).map(Value::String);
+    }
+    if let Some(body) = text.strip_prefix('\'') {
+        let body = body.strip_suffix('\'').ok_or("unterminated string")?;
+        return Ok(Value::String(body.to_string()));
+    }
+    match text {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        "" => return Err("missing value".to_string()),
+        _ => (),
+    }
+
+    let digits = text.replace('_', "");
+    if let Some(hex) = digits.strip_prefix("0x") {
+        return i64::from_str_radix(hex, 16).map(Value::Integer).map_err(|_| format!("invalid number `{}`", text));
+    }
+    if let Ok(value) = digits.parse::<i64>() {
+        return Ok(Value::Integer(value));
+    }
+    digits.parse::<f64>().map(Value::Float).map_err(|_| format!("invalid value `{}`", text))
+}
+
+fn unescape(body: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            return Err("unescaped quote in string".to_string());
+        }
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('t') => '\t',
+            other => return Err(format!("invalid escape `\\{}`", other.map(String::from).unwrap_or_default())),
+        });
+    }
+    Ok(out)
+}
@@ -0,0 +1,144 @@
+use super::register::types::{Flags, F8};
+
+/* Pure SM83 ALU operations. Each returns the result alongside the complete
+ * resulting F register; operations that leave some flags untouched take the
+ * current flags as input. */
+
+#[inline(always)]
+fn flags(z: bool, n: bool, h: bool, c: bool) -> F8 {
+    F8::from(
+        ((z as u8) << 7) |
+        ((n as u8) << 6) |
+        ((h as u8) << 5) |
+        ((c as u8) << 4))
+}
+
+// 8-bit Arithmetic {{{
+pub fn add8(a: u8, b: u8) -> (u8, F8) {
+    adc8(a, b, false)
+}
+
+pub fn adc8(a: u8, b: u8, carry: bool) -> (u8, F8) {
+    let wide = a as u16 + b as u16 + carry as u16;
+    let res = wide as u8;
+    (res, flags(res == 0, false, (a ^ b ^ res) & 0x10 != 0, wide > 0xFF))
+}
+
+pub fn sub8(a: u8, b: u8) -> (u8, F8) {
+    sbc8(a, b, false)
+}
+
+pub fn sbc8(a: u8, b: u8, carry: bool) -> (u8, F8) {
+    let wide = (a as u16).wrapping_sub(b as u16).wrapping_sub(carry as u16);
+    let res = wide as u8;
+    (res, flags(res == 0, true, (a ^ b ^ res) & 0x10 != 0, wide > 0xFF))
+}
+
+pub fn cp8(a: u8, b: u8) -> F8 {
+    sub8(a, b).1
+}
+
+pub fn and8(a: u8, b: u8) -> (u8, F8) {
+    let res = a & b;
+    (res, flags(res == 0, false, true, false))
+}
+
+pub fn xor8(a: u8, b: u8) -> (u8, F8) {
+    let res = a ^ b;
+    (res, flags(res == 0, false, false, false))
+}
+
+pub fn or8(a: u8, b: u8) -> (u8, F8) {
+    let res = a | b;
+    (res, flags(res == 0, false, false, false))
+}
+
+pub fn inc8(a: u8, f: F8) -> (u8, F8) {
+    let res = a.wrapping_add(1);
+    (res, flags(res == 0, false, a & 0x0F == 0x0F, f.is_set(Flags::Carry)))
+}
+
+pub fn dec8(a: u8, f: F8) -> (u8, F8) {
+    let res = a.wrapping_sub(1);
+    (res, flags(res == 0, true, a & 0x0F == 0x00, f.is_set(Flags::Carry)))
+}
+
+pub fn daa(a: u8, f: F8) -> (u8, F8) {
+    let n = f.is_set(Flags::Subtract);
+    let mut carry = f.is_set(Flags::Carry);
+    let mut adjust = 0;
+
+    if f.is_set(Flags::HalfCarry) || (!n && (a & 0x0F) > 0x09) {
+        adjust |= 0x06;
+    }
+    if carry || (!n && a > 0x99) {
+        adjust |= 0x60;
+        carry = true;
+    }
+
+    let res = if n { a.wrapping_sub(adjust) } else { a.wrapping_add(adjust) };
+    (res, flags(res == 0, n, false, carry))
+}
+// }}}
+
+// 16-bit Arithmetic {{{
+/* ADD HL, rr: Z is untouched, H and C come from bits 11 and 15 */
+pub fn add16(a: u16, b: u16, f: F8) -> (u16, F8) {
+    let wide = a as u32 + b as u32;
+    let res = wide as u16;
+    (res, flags(f.is_set(Flags::Zero), false, (a ^ b ^ res) & 0x1000 != 0, wide > 0xFFFF))
+}
+
+/* ADD SP, e8 and LD HL, SP+e8: H and C come from the unsigned add of the low byte */
+pub fn add_sp_e8(sp: u16, e: u8) -> (u16, F8) {
+    let res = sp.wrapping_add(e as i8 as u16);
+    let low = (sp & 0x00FF) + e as u16;
+    (res, flags(false, false, (sp ^ e as u16 ^ low) & 0x10 != 0, low > 0xFF))
+}
+// }}}
+
+// Rotate / Shift / Bit {{{
+pub fn rlc8(a: u8) -> (u8, F8) {
+    let res = a.rotate_left(1);
+    (res, flags(res == 0, false, false, a & 0x80 != 0))
+}
+
+pub fn rrc8(a: u8) -> (u8, F8) {
+    let res = a.rotate_right(1);
+    (res, flags(res == 0, false, false, a & 0x01 != 0))
+}
+
+pub fn rl8(a: u8, f: F8) -> (u8, F8) {
+    let res = (a << 1) | f.is_set(Flags::Carry) as u8;
+    (res, flags(res == 0, false, false, a & 0x80 != 0))
+}
+
+pub fn rr8(a: u8, f: F8) -> (u8, F8) {
+    let res = (a >> 1) | ((f.is_set(Flags::Carry) as u8) << 7);
+    (res, flags(res == 0, false, false, a & 0x01 != 0))
+}
+
+pub fn sla8(a: u8) -> (u8, F8) {
+    let res = a << 1;
+    (res, flags(res == 0, false, false, a & 0x80 != 0))
+}
+
+pub fn sra8(a: u8) -> (u8, F8) {
+    let res = (a >> 1) | (a & 0x80);
+    (res, flags(res == 0, false, false, a & 0x01 != 0))
+}
+
+pub fn srl8(a: u8) -> (u8, F8) {
+    let res = a >> 1;
+    (res, flags(res == 0, false, false, a & 0x01 != 0))
+}
+
+pub fn swap8(a: u8) -> (u8, F8) {
+    let res = a.rotate_left(4);
+    (res, flags(res == 0, false, false, false))
+}
+
+pub fn bit8(bit: u8, a: u8, f: F8) -> F8 {
+    flags(a & (1 << bit) == 0, false, true, f.is_set(Flags::Carry))
+}
+// }}}
@@ -0,0 +1,303 @@
+use std::{io::ErrorKind, sync::OnceLock};
+
+use crate::{
+    gba::opcode::{
+        Opcode,
+        types::{JumpCondition, LoadDirection, OpcodeIndirectRegister16, OpcodeRegister16, OpcodeRegister8},
+    },
+    state::prelude::{Savestate, StateReader, StateWriter},
+};
+
+use super::register::types::{Register16, Register8};
+
+/* Longest sequence, CALL and interrupt dispatch */
+const MAX_OPS: usize = 8;
+
+/* What the CPU is working through: an opcode, the second byte of a $CB
+ * instruction, or an interrupt dispatch */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Routine {
+    /* Between instructions */
+    #[default]
+    Idle,
+    Opcode(u8),
+    Prefixed(u8),
+    Dispatch,
+}
+
+/* The halves of the cache register, as Z (low) and W (high) on the chip,
+ * where immediates and memory operands wait until they are used */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Latch {
+    Z,
+    W,
+}
+
+/* The address a read or write goes to, with the register moves some make
+ * on the way */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pointer {
+    Register(Register16),
+    HLInc,
+    HLDec,
+    /* $FF00 plus C or Z */
+    HighC,
+    HighZ,
+    WZ,
+    WZNext,
+    /* Pops read at SP and move it up; pushes move it down, then write */
+    SPInc,
+    SPDec,
+}
+
+/* What a write puts on the bus */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register8),
+    Z,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum MicroOp {
+    /* Each of these takes an M-cycle */
+    /* The byte at PC, moving PC on */
+    FetchImm(Latch),
+    /* The second byte of a $CB instruction, which picks the rest */
+    FetchPrefixed,
+    Read(Pointer, Latch),
+    Write(Pointer, Operand),
+    #[default]
+    Idle,
+    /* INC / DEC of a pair through the IDU, which puts it on the address bus */
+    Idu(Register16, bool),
+
+    /* These take no time of their own and land on the cycle before them */
+    /* The instruction's register work, with Z for its memory operand */
+    Execute,
+    /* Cuts the sequence short unless the condition holds */
+    Check(JumpCondition),
+    /* Dispatch only: the vector is picked between the two pushes */
+    PickVector,
+}
+
+impl MicroOp {
+    pub fn takes_cycle(self) -> bool {
+        !matches!(self, Self::Execute | Self::Check(_) | Self::PickVector)
+    }
+}
+
+/* An instruction as the M-cycles it runs in, after the opcode fetch, and
+ * how far along it is. `tick` runs one cycle at a time and `step` the
+ * rest of them, so accesses land on the cycle they belong to and an
+ * interrupt is only ever sampled between instructions. */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Microcode {
+    routine: Routine,
+    ops: [MicroOp; MAX_OPS],
+    len: u8,
+    next: u8,
+    /* M-cycles run so far, the fetch included */
+    elapsed: u8,
+}
+
+static OPCODES: OnceLock<[Microcode; 256]> = OnceLock::new();
+static PREFIXED: OnceLock<[Microcode; 256]> = OnceLock::new();
+
+impl Microcode {
+    pub fn new(routine: Routine) -> Self {
+        match routine {
+            Routine::Opcode(byte) => OPCODES.get_or_init(|| std::array::from_fn(|i| Self::build(Routine::Opcode(i as u8))))[byte as usize],
+            Routine::Prefixed(byte) => PREFIXED.get_or_init(|| std::array::from_fn(|i| Self::build(Routine::Prefixed(i as u8))))[byte as usize],
+            _ => Self::build(routine),
+        }
+    }
+
+    fn of(routine: Routine, ops: &[MicroOp]) -> Self {
+        let mut code = Self { routine, len: ops.len() as u8, ..Self::default() };
+        code.ops[..ops.len()].copy_from_slice(ops);
+        code
+    }
+
+    fn build(routine: Routine) -> Self {
+        use MicroOp::*;
+        let seq = |ops: &[MicroOp]| Self::of(routine, ops);
+        match routine {
+            Routine::Idle => Self::default(),
+            Routine::Dispatch => seq(&[
+                Idle, Idle, Write(Pointer::SPDec, Operand::Register(Register8::PCHigh)), PickVector,
+                Write(Pointer::SPDec, Operand::Register(Register8::PCLow)), Idle, Execute,
+            ]),
+            Routine::Opcode(byte) => match Opcode::decode(byte) {
+                Ok(opcode) => Self::sequence(routine, opcode),
+                /* Never run; the CPU locks up on the fetch */
+                Err(_) => seq(&[]),
+            },
+            Routine::Prefixed(byte) => Self::sequence(routine, Opcode::decode_prefixed(byte)),
+        }
+    }
+
+    fn sequence(routine: Routine, opcode: Opcode) -> Self {
+        use MicroOp::*;
+        use Opcode::*;
+        use OpcodeRegister8::HL;
+        let seq = |ops: &[MicroOp]| Self::of(routine, ops);
+        let hl = Pointer::Register(Register16::HL);
+        let a = Operand::Register(Register8::A);
+        let push_pc = [
+            Write(Pointer::SPDec, Operand::Register(Register8::PCHigh)),
+            Write(Pointer::SPDec, Operand::Register(Register8::PCLow)),
+        ];
+        match opcode {
+            LoadR8(HL, src) => seq(&[Write(hl, Operand::Register(Register8::from(src)))]),
+            LoadR8(_, HL) => seq(&[Read(hl, Latch::Z), Execute]),
+            LoadImm8(HL) => seq(&[FetchImm(Latch::Z), Write(hl, Operand::Z)]),
+            LoadImm8(_) => seq(&[FetchImm(Latch::Z), Execute]),
+            LoadIndR16(reg, direction) => {
+                let pointer = match reg {
+                    OpcodeIndirectRegister16::HLInc => Pointer::HLInc,
+                    OpcodeIndirectRegister16::HLDec => Pointer::HLDec,
+                    _ => Pointer::Register(Register16::from(reg)),
+                };
+                match direction {
+                    LoadDirection::Memory => seq(&[Write(pointer, a)]),
+                    LoadDirection::Accumulator => seq(&[Read(pointer, Latch::Z), Execute]),
+                }
+            },
+            LoadIndOffImm8(LoadDirection::Memory) => seq(&[FetchImm(Latch::Z), Write(Pointer::HighZ, a)]),
+            LoadIndOffImm8(LoadDirection::Accumulator) => seq(&[FetchImm(Latch::Z), Read(Pointer::HighZ, Latch::Z), Execute]),
+            LoadIndOffRegC(LoadDirection::Memory) => seq(&[Write(Pointer::HighC, a)]),
+            LoadIndOffRegC(LoadDirection::Accumulator) => seq(&[Read(Pointer::HighC, Latch::Z), Execute]),
+            LoadIndImm16(LoadDirection::Memory) => seq(&[FetchImm(Latch::Z), FetchImm(Latch::W), Write(Pointer::WZ, a)]),
+            LoadIndImm16(LoadDirection::Accumulator) => seq(&[
+                FetchImm(Latch::Z), FetchImm(Latch::W), Read(Pointer::WZ, Latch::Z), Execute,
+            ]),
+
+            LoadImm16(_) => seq(&[FetchImm(Latch::Z), FetchImm(Latch::W), Execute]),
+            LoadIndImm16SP => seq(&[
+                FetchImm(Latch::Z), FetchImm(Latch::W),
+                Write(Pointer::WZ, Operand::Register(Register8::SPLow)),
+                Write(Pointer::WZNext, Operand::Register(Register8::SPHigh)),
+            ]),
+            LoadSPHL => seq(&[Idle, Execute]),
+            PushR16(reg) => {
+                let (high, low) = match reg {
+                    OpcodeRegister16::BC => (Register8::B, Register8::C),
+                    OpcodeRegister16::DE => (Register8::D, Register8::E),
+                    OpcodeRegister16::HL => (Register8::H, Register8::L),
+                    _ => (Register8::A, Register8::F),
+                };
+                seq(&[
+                    Idle,
+                    Write(Pointer::SPDec, Operand::Register(high)),
+                    Write(Pointer::SPDec, Operand::Register(low)),
+                ])
+            },
+            PopR16(_) => seq(&[Read(Pointer::SPInc, Latch::Z), Read(Pointer::SPInc, Latch::W), Execute]),
+            LoadHLOffSp => seq(&[FetchImm(Latch::Z), Idle, Execute]),
+
+            MathR8(_, HL) | TestBit(_, HL) => seq(&[Read(hl, Latch::Z), Execute]),
+            /* Read, modify, write back */
+            IncR8(HL) | DecR8(HL) | RotateShift(_, HL) | ResetBit(_, HL) | SetBit(_, HL) => seq(&[
+                Read(hl, Latch::Z), Execute, Write(hl, Operand::Z),
+            ]),
+            MathImm8(_) => seq(&[FetchImm(Latch::Z), Execute]),
+            IncR16(reg) => seq(&[Idu(Register16::from(reg), true)]),
+            DecR16(reg) => seq(&[Idu(Register16::from(reg), false)]),
+            AddR16(_) => seq(&[Idle, Execute]),
+            AddSPImm8 => seq(&[FetchImm(Latch::Z), Idle, Idle, Execute]),
+            Prefix => seq(&[FetchPrefixed]),
+
+            JumpImm16(condition) => seq(&[FetchImm(Latch::Z), FetchImm(Latch::W), Check(condition), Idle, Execute]),
+            JumpOffImm8(condition) => seq(&[FetchImm(Latch::Z), Check(condition), Idle, Execute]),
+            CallImm16(condition) => seq(&[
+                FetchImm(Latch::Z), FetchImm(Latch::W), Check(condition), Idle, push_pc[0], push_pc[1], Execute,
+            ]),
+            Return(JumpCondition::Always) | ReturnInterupt => seq(&[
+                Read(Pointer::SPInc, Latch::Z), Read(Pointer::SPInc, Latch::W), Idle, Execute,
+            ]),
+            /* The condition takes a cycle of its own */
+            Return(condition) => seq(&[
+                Idle, Check(condition), Read(Pointer::SPInc, Latch::Z), Read(Pointer::SPInc, Latch::W), Idle, Execute,
+            ]),
+            Restart(_) => seq(&[Idle, push_pc[0], push_pc[1], Execute]),
+
+            /* Everything else works on registers alone, within the fetch */
+            _ => seq(&[Execute]),
+        }
+    }
+
+    pub fn routine(&self) -> Routine {
+        self.routine
+    }
+
+    /* Whether there are steps left to run */
+    pub fn in_flight(&self) -> bool {
+        self.next < self.len
+    }
+
+    pub fn elapsed(&self) -> usize {
+        self.elapsed as usize
+    }
+
+    pub fn count_cycle(&mut self) {
+        self.elapsed += 1;
+    }
+
+    pub fn next_op(&mut self) -> Option<MicroOp> {
+        let op = self.ops[..self.len as usize].get(self.next as usize).copied()?;
+        self.next += 1;
+        Some(op)
+    }
+
+    /* The next step if it takes no cycle, to run along with the last one */
+    pub fn next_free(&mut self) -> Option<MicroOp> {
+        match self.ops[..self.len as usize].get(self.next as usize) {
+            Some(op) if !op.takes_cycle() => self.next_op(),
+            _ => None,
+        }
+    }
+
+    /* A condition failed: the instruction ends here */
+    pub fn cut(&mut self) {
+        self.next = self.len;
+    }
+
+    /* Carries on as the $CB instruction `byte` picks */
+    pub fn prefixed(&mut self, byte: u8) {
+        *self = Self { elapsed: self.elapsed, ..Self::new(Routine::Prefixed(byte)) };
+    }
+}
+
+impl Savestate for Microcode {
+    /* The steps themselves are rebuilt from the routine */
+    fn save_state(&self, w: &mut StateWriter) {
+        let (tag, byte) = match self.routine {
+            Routine::Idle => (0, 0),
+            Routine::Opcode(byte) => (1, byte),
+            Routine::Prefixed(byte) => (2, byte),
+            Routine::Dispatch => (3, 0),
+        };
+        w.u8(tag);
+        w.u8(byte);
+        w.u8(self.next);
+        w.u8(self.elapsed);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        let tag = r.u8()?;
+        let byte = r.u8()?;
+        let routine = match tag {
+            0 => Routine::Idle,
+            1 => Routine::Opcode(byte),
+            2 => Routine::Prefixed(byte),
+            3 => Routine::Dispatch,
+            _ => return Err(ErrorKind::InvalidData),
+        };
+        let code = Self { next: r.u8()?, elapsed: r.u8()?, ..Self::new(routine) };
+        if code.next > code.len || code.elapsed as usize > MAX_OPS {
+            return Err(ErrorKind::InvalidData);
+        }
+        *self = code;
+        Ok(())
+    }
+}
@@ -1,9 +1,14 @@
 #![allow(unused)]
 
+pub mod alu;
+pub mod interrupt;
+pub mod microcode;
 pub mod register;
 pub mod proc;
 
 pub mod prelude {
-    use super::register::{Registers, types::{Register8, Register16, Flags}};
-    use super::proc::Cpu;
+    pub use super::register::{Registers, types::{Register8, Register16, Flags, F8}};
+    pub use super::proc::{Cpu, CpuState};
+    pub use super::interrupt::Interrupt;
+    pub use super::microcode::{MicroOp, Microcode, Routine};
 }
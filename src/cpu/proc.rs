@@ -1,8 +1,82 @@
-use super::register::Registers;
+use std::io::ErrorKind;
+
+use crate::state::prelude::{Savestate, StateReader, StateWriter};
+
+use super::{microcode::Microcode, register::{types::F8, Registers}};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum CpuState {
+    #[default]
+    Running,
+    /* HALT: sleeping until an enabled interrupt is pending */
+    Halted,
+    /* STOP: sleeping until a joypad line goes low */
+    Stopped,
+    /* Executed the given illegal opcode; only a reset recovers */
+    Locked(u8),
+}
 
 #[derive(Debug, Default)]
 pub struct Cpu {
     pub registers: Registers,
     pub ime: u8,
+    /* EI takes effect after the instruction that follows it */
+    pub ime_pending: bool,
+    /* HALT with IME clear and an interrupt pending: the next opcode fetch
+     * does not move PC, so its byte is read twice */
+    pub halt_bug: bool,
+    /* WZ: immediates and memory operands between the cycles of an
+     * instruction, Z in the low byte */
     pub cache: u16,
+    /* The instruction in flight, one step per M-cycle */
+    pub microcode: Microcode,
+    pub state: CpuState,
+}
+
+impl Savestate for Cpu {
+    fn save_state(&self, w: &mut StateWriter) {
+        let r = &self.registers;
+        for value in [r.b, r.c, r.d, r.e, r.h, r.l, r.a, u8::from(r.f)] {
+            w.u8(value);
+        }
+        w.u16(r.sp);
+        w.u16(r.pc);
+        w.u8(self.ime);
+        w.u16(self.cache);
+        let (tag, opcode) = match self.state {
+            CpuState::Running => (0, 0),
+            CpuState::Halted => (1, 0),
+            CpuState::Stopped => (2, 0),
+            CpuState::Locked(opcode) => (3, opcode),
+        };
+        w.u8(tag);
+        w.u8(opcode);
+        w.u8(self.ime_pending as u8);
+        w.u8(self.halt_bug as u8);
+        self.microcode.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        let regs = &mut self.registers;
+        for reg in [&mut regs.b, &mut regs.c, &mut regs.d, &mut regs.e, &mut regs.h, &mut regs.l, &mut regs.a] {
+            *reg = r.u8()?;
+        }
+        regs.f = F8::from(r.u8()?);
+        regs.sp = r.u16()?;
+        regs.pc = r.u16()?;
+        self.ime = r.u8()?;
+        self.cache = r.u16()?;
+        let tag = r.u8()?;
+        let opcode = r.u8()?;
+        self.state = match tag {
+            0 => CpuState::Running,
+            1 => CpuState::Halted,
+            2 => CpuState::Stopped,
+            3 => CpuState::Locked(opcode),
+            _ => return Err(ErrorKind::InvalidData),
+        };
+        self.ime_pending = r.u8()? != 0;
+        self.halt_bug = r.u8()? != 0;
+        self.microcode.load_state(r)
+    }
 }
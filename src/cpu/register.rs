@@ -1,34 +1,12 @@
-use std::ops::Index;
-
 use self::types::{Register16, Register8, F8};
 
 // mod types {{{
 pub mod types {
-    use std::{ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not}, ptr::addr_of};
-
-    #[inline(always)]
-    pub fn as_u8_slice<T>(r: &T) -> &[u8] where T: Sized {
-        unsafe { std::slice::from_raw_parts((r as *const T) as *const u8, std::mem::size_of::<T>()) }
-    }
-
-    #[inline(always)]
-    pub fn as_u8_slice_mut<T>(r: &mut T) -> &mut [u8] where T: Sized {
-        unsafe { std::slice::from_raw_parts_mut((r as *mut T) as *mut u8, std::mem::size_of::<T>()) }
-    }
-
-    #[inline(always)]
-    pub fn as_u16_slice<T>(r: &T) -> &[u16] where T: Sized {
-        unsafe { std::slice::from_raw_parts((r as *const T) as *const u16, std::mem::size_of::<T>() / 2) }
-    }
-
-    #[inline(always)]
-    pub fn as_u16_slice_mut<T>(r: &mut T) -> &mut [u16] where T: Sized {
-        unsafe { std::slice::from_raw_parts_mut((r as *mut T) as *mut u16, std::mem::size_of::<T>() / 2) }
-    }
+    use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not};
 
     // enum Flags {{{
     #[repr(u8)]
-    #[derive(Debug)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum Flags {
         Zero = 0x80_u8,
         Subtract = 0x40_u8,
@@ -39,7 +17,7 @@ pub mod types {
     impl From<u8> for Flags {
         fn from(value: u8) -> Self {
             match value {
-                0x80 | 0x40 | 0x20 | 0x10 => unsafe { std::mem::transmute(value) },
+                0x80 | 0x40 | 0x20 | 0x10 => unsafe { std::mem::transmute::<u8, Flags>(value) },
                 _ => panic!("Value not a valid flag: {:?}", value),
             }
         }
@@ -62,13 +40,15 @@ pub mod types {
     impl Not for Flags {
         type Output = F8;
         fn not(self) -> Self::Output {
-            F8(!(self as u8))
+            F8(!(self as u8) & 0xF0)
         }
     }
     //}}}
 
     // struct F8 {{{
-    #[derive(Copy, Clone, Default, Debug)]
+    /* Every way of making an F8 keeps bits 0-3 clear, so whatever path
+     * writes F (POP AF, LD F via set_r8, the ALU) reads back masked */
+    #[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
     pub struct F8(u8);
 
     impl F8 {
@@ -105,9 +85,10 @@ pub mod types {
         }
     }
 
+    /* The low nibble of F does not exist in hardware and always reads 0 */
     impl From<u8> for F8 {
         fn from(value: u8) -> Self {
-            Self(value)
+            Self(value & 0xF0)
         }
     }
 
@@ -198,24 +179,24 @@ pub mod types {
     impl Not for F8 {
         type Output = Self;
         fn not(self) -> Self::Output {
-            Self(!self.0)
+            Self(!self.0 & 0xF0)
         }
     }
     //}}}
 
     // enum Register8 {{{
     #[repr(u8)]
-    #[derive(Copy, Clone)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum Register8 {
         B = 0, C, D, E, H, L, A, F, SPHigh, SPLow, PCHigh, PCLow,
     }
 
     impl From<u8> for Register8 {
         fn from(value: u8) -> Self {
+            use Register8::*;
             match value {
-                0..=11 => {
-                    unsafe { std::mem::transmute(value) }
-                },
+                0 => B, 1 => C, 2 => D, 3 => E, 4 => H, 5 => L,
+                6 => A, 7 => F, 8 => SPHigh, 9 => SPLow, 10 => PCHigh, 11 => PCLow,
                 _ => panic!("Invalid value for Register8: {:?}", value),
             }
         }
@@ -224,17 +205,16 @@ pub mod types {
 
     // enum Register16 {{{
     #[repr(u8)]
-    #[derive(Copy, Clone)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum Register16 {
         BC = 0, DE, HL, AF, SP, PC,
     }
 
     impl From<u8> for Register16 {
         fn from(value: u8) -> Self {
+            use Register16::*;
             match value {
-                0..=5 => {
-                    unsafe { std::mem::transmute(value) }
-                },
+                0 => BC, 1 => DE, 2 => HL, 3 => AF, 4 => SP, 5 => PC,
                 _ => panic!("Invalid value for Register16: {:?}", value),
             }
         }
@@ -257,20 +237,97 @@ pub struct Registers {
     pub pc: u16,
 }
 
+/* Pairs are composed high byte first, so `get_bc()` is `(b << 8) | c` */
 impl Registers {
-    pub fn get_r8(&self, value: Register8) -> u8 {
-        unsafe { self::types::as_u8_slice(self)[value as usize] }
+    pub fn get_bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    pub fn get_de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    pub fn get_hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+
+    pub fn get_af(&self) -> u16 {
+        u16::from_be_bytes([self.a, u8::from(self.f)])
     }
 
-    pub fn get_r16(&self, value: Register16) -> u16 {
-        unsafe { self::types::as_u16_slice(self)[value as usize] }
+    pub fn set_bc(&mut self, value: u16) {
+        [self.b, self.c] = value.to_be_bytes();
+    }
+
+    pub fn set_de(&mut self, value: u16) {
+        [self.d, self.e] = value.to_be_bytes();
+    }
+
+    pub fn set_hl(&mut self, value: u16) {
+        [self.h, self.l] = value.to_be_bytes();
+    }
+
+    pub fn set_af(&mut self, value: u16) {
+        let [a, f] = value.to_be_bytes();
+        self.a = a;
+        self.f = F8::from(f);
+    }
+
+    pub fn get_r8(&self, reg: Register8) -> u8 {
+        use Register8::*;
+        match reg {
+            B => self.b,
+            C => self.c,
+            D => self.d,
+            E => self.e,
+            H => self.h,
+            L => self.l,
+            A => self.a,
+            F => u8::from(self.f),
+            SPHigh => (self.sp >> 8) as u8,
+            SPLow => self.sp as u8,
+            PCHigh => (self.pc >> 8) as u8,
+            PCLow => self.pc as u8,
+        }
+    }
+
+    pub fn get_r16(&self, reg: Register16) -> u16 {
+        match reg {
+            Register16::BC => self.get_bc(),
+            Register16::DE => self.get_de(),
+            Register16::HL => self.get_hl(),
+            Register16::AF => self.get_af(),
+            Register16::SP => self.sp,
+            Register16::PC => self.pc,
+        }
     }
 
     pub fn set_r8(&mut self, reg: Register8, value: u8) {
-        unsafe { self::types::as_u8_slice_mut(self)[reg as usize] = value; }
+        use Register8::*;
+        match reg {
+            B => self.b = value,
+            C => self.c = value,
+            D => self.d = value,
+            E => self.e = value,
+            H => self.h = value,
+            L => self.l = value,
+            A => self.a = value,
+            F => self.f = F8::from(value),
+            SPHigh => self.sp = (self.sp & 0x00FF) | ((value as u16) << 8),
+            SPLow => self.sp = (self.sp & 0xFF00) | value as u16,
+            PCHigh => self.pc = (self.pc & 0x00FF) | ((value as u16) << 8),
+            PCLow => self.pc = (self.pc & 0xFF00) | value as u16,
+        }
     }
 
     pub fn set_r16(&mut self, reg: Register16, value: u16) {
-        unsafe { self::types::as_u16_slice_mut(self)[reg as usize] = value; }
+        match reg {
+            Register16::BC => self.set_bc(value),
+            Register16::DE => self.set_de(value),
+            Register16::HL => self.set_hl(value),
+            Register16::AF => self.set_af(value),
+            Register16::SP => self.sp = value,
+            Register16::PC => self.pc = value,
+        }
     }
 }
@@ -0,0 +1,330 @@
+use std::{collections::HashMap, fmt, sync::OnceLock};
+
+use super::{disasm::disassemble, trace::instruction_len};
+
+/* `asm!("ld a, $3E; add a, b")` as bytes for $0100, where test programs
+ * start, or `asm!(source, origin)` elsewhere. Bad source panics with the
+ * assembler's message. */
+#[macro_export]
+macro_rules! asm {
+    ($source:expr) => {
+        $crate::asm!($source, 0x0100)
+    };
+    ($source:expr, $origin:expr) => {
+        match $crate::debugger::prelude::assemble($source, $origin) {
+            Ok(bytes) => bytes,
+            Err(error) => panic!("{}", error),
+        }
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /* Counting from 1 */
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/* What follows the opcode */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Slot {
+    None,
+    /* STOP's ignored second byte */
+    Pad,
+    N8,
+    /* LDH's offset, which may also be given as the full $FFxx address */
+    High,
+    N16,
+    /* ADD SP and LD HL, SP + e8 */
+    Signed,
+    Relative,
+    /* RST vectors and bit numbers, part of the opcode itself */
+    Fixed(u8),
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Form {
+    opcode: u8,
+    prefixed: bool,
+    slot: Slot,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(i64),
+    Label(String),
+}
+
+/* An operand referring to a label not yet placed */
+struct Fixup {
+    at: usize,
+    slot: Slot,
+    label: String,
+    next: u16,
+    line: usize,
+}
+
+/* Registers, conditions and register-indirect operands, as spelt in the
+ * lookup keys */
+const NAMES: [&str; 21] = [
+    "A", "B", "C", "D", "E", "H", "L", "AF", "BC", "DE", "HL", "SP", "NZ", "Z", "NC",
+    "[BC]", "[DE]", "[HL]", "[HL+]", "[HL-]", "[C]",
+];
+
+const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBC", "AND", "XOR", "OR", "CP"];
+
+/* Every instruction the disassembler prints, keyed by its text with the
+ * immediate replaced by `#`, so the two cannot disagree on syntax */
+fn forms() -> &'static HashMap<String, Vec<Form>> {
+    static FORMS: OnceLock<HashMap<String, Vec<Form>>> = OnceLock::new();
+    FORMS.get_or_init(|| {
+        let mut forms: HashMap<String, Vec<Form>> = HashMap::new();
+        let mut add = |text: String, opcode: u8, prefixed: bool, slot: Slot| {
+            let (mnemonic, operands) = split(&text);
+            let operands: Vec<String> = operands.iter().map(|operand| operand.split_whitespace().collect()).collect();
+            forms.entry(key(&mnemonic, &operands)).or_default().push(Form { opcode, prefixed, slot });
+        };
+        for opcode in (0..=0xFF).filter(|&opcode| opcode != 0xCB) {
+            let text = disassemble(&[opcode, 0x34, 0x12], 0x0100, &|_| None);
+            if text.starts_with("DB ") {
+                continue;
+            }
+            match (instruction_len(opcode), opcode) {
+                (3, _) => add(text.replace("$1234", "#"), opcode, false, Slot::N16),
+                (2, 0x10) => add(text, opcode, false, Slot::Pad),
+                (2, 0x18 | 0x20 | 0x28 | 0x30 | 0x38) => add(text.replace("$0136", "#"), opcode, false, Slot::Relative),
+                (2, 0xE8 | 0xF8) => add(text.replace("$34", "#"), opcode, false, Slot::Signed),
+                (2, 0xE0 | 0xF0) => add(text.replace("$34", "#"), opcode, false, Slot::High),
+                (2, _) => add(text.replace("$34", "#"), opcode, false, Slot::N8),
+                _ if text.starts_with("RST") => add("RST #".to_string(), opcode, false, Slot::Fixed(opcode & 0x38)),
+                _ => add(text, opcode, false, Slot::None),
+            }
+        }
+        for opcode in 0..=0xFF {
+            let text = disassemble(&[0xCB, opcode], 0x0100, &|_| None);
+            match opcode >> 6 {
+                0 => add(text, opcode, true, Slot::None),
+                _ => {
+                    let bit = (opcode >> 3) & 7;
+                    add(text.replacen(&format!(" {},", bit), " #,", 1), opcode, true, Slot::Fixed(bit));
+                }
+            }
+        }
+        forms
+    })
+}
+
+fn key(mnemonic: &str, operands: &[String]) -> String {
+    match operands {
+        [] => mnemonic.to_string(),
+        _ => format!("{} {}", mnemonic, operands.join(",")),
+    }
+}
+
+/* The mnemonic, uppercased, and the operands as written */
+fn split(statement: &str) -> (String, Vec<&str>) {
+    let statement = statement.trim();
+    let (mnemonic, rest) = statement.split_once(char::is_whitespace).unwrap_or((statement, ""));
+    let operands = match rest.trim() {
+        "" => Vec::new(),
+        rest => rest.split(',').map(str::trim).collect(),
+    };
+    (mnemonic.to_uppercase(), operands)
+}
+
+/* Text for the key and the value standing in for its `#` */
+fn operand(text: &str) -> Result<(String, Option<Value>), String> {
+    let compact: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let upper = compact.to_uppercase().replace('(', "[").replace(')', "]");
+    let upper = match upper.as_str() {
+        "[HLI]" => "[HL+]".to_string(),
+        "[HLD]" => "[HL-]".to_string(),
+        "[$FF00+C]" | "[0XFF00+C]" => "[C]".to_string(),
+        _ => upper,
+    };
+    if NAMES.contains(&upper.as_str()) {
+        return Ok((upper, None));
+    }
+    if upper.starts_with('[') && upper.ends_with(']') {
+        return Ok(("[#]".to_string(), Some(value(&compact[1..compact.len() - 1])?)));
+    }
+    if let Some(offset) = upper.strip_prefix("SP+") {
+        return Ok(("SP+#".to_string(), Some(value(&compact[compact.len() - offset.len()..])?)));
+    }
+    if upper.starts_with("SP-") {
+        return match value(&compact[3..])? {
+            Value::Number(n) => Ok(("SP+#".to_string(), Some(Value::Number(-n)))),
+            Value::Label(_) => Err(format!("`{}` needs a number", text)),
+        };
+    }
+    Ok(("#".to_string(), Some(value(&compact)?)))
+}
+
+/* $ or 0x hex, % or 0b binary, decimal, or a label */
+fn value(text: &str) -> Result<Value, String> {
+    let (negative, digits) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let lower = digits.to_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix('$').or_else(|| lower.strip_prefix("0x")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix('%').or_else(|| lower.strip_prefix("0b")) {
+        i64::from_str_radix(binary, 2).ok()
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()
+    } else if text == digits && is_label(digits) {
+        return Ok(Value::Label(digits.to_string()));
+    } else {
+        None
+    };
+    match parsed {
+        Some(n) => Ok(Value::Number(if negative { -n } else { n })),
+        None => Err(format!("bad operand `{}`", text)),
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/* The operand bytes for `value`. `displacement` is for a signed literal
+ * after JR, taken as the offset itself; anything else is the target,
+ * measured from `next`, the address after the instruction. */
+fn encode(slot: Slot, value: i64, displacement: bool, next: u16) -> Result<Vec<u8>, String> {
+    let fits = |low: i64, high: i64| if (low..=high).contains(&value) { Ok(()) } else { Err(format!("{} is out of range", value)) };
+    match slot {
+        Slot::None | Slot::Fixed(_) => Ok(Vec::new()),
+        Slot::Pad => Ok(vec![0x00]),
+        Slot::N8 => fits(-0x80, 0xFF).map(|_| vec![value as u8]),
+        Slot::High if (0xFF00..=0xFFFF).contains(&value) => Ok(vec![value as u8]),
+        Slot::High => fits(0x00, 0xFF).map(|_| vec![value as u8]),
+        Slot::N16 => fits(-0x8000, 0xFFFF).map(|_| (value as u16).to_le_bytes().to_vec()),
+        Slot::Signed => fits(-0x80, 0x7F).map(|_| vec![value as u8]),
+        Slot::Relative if displacement => fits(-0x80, 0x7F).map(|_| vec![value as u8]),
+        Slot::Relative => {
+            let offset = value - next as i64;
+            match (-0x80..=0x7F).contains(&offset) {
+                true => Ok(vec![offset as u8]),
+                false => Err(format!("${:04X} is out of JR range", value)),
+            }
+        }
+    }
+}
+
+/* SM83 assembly in RGBDS syntax to bytes placed at `origin`. Statements
+ * are separated by newlines or `;`, `//` starts a comment, `name:` defines
+ * a label, and `db`/`dw` emit data. Also accepted: one-operand ALU forms
+ * (`sub b`), `ld [c], a` for LDH, `[hli]`/`[hld]`, `ldi`/`ldd` and
+ * parentheses for brackets. */
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+    let mut out = Vec::new();
+    let mut labels = HashMap::new();
+    let mut fixups = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| AsmError { line, message };
+        let text = text.split("//").next().unwrap_or_default();
+        for statement in text.split(';') {
+            let mut statement = statement.trim();
+            while let Some((name, rest)) = statement.split_once(':').filter(|(name, _)| is_label(name.trim())) {
+                let address = origin.wrapping_add(out.len() as u16);
+                if labels.insert(name.trim().to_string(), address).is_some() {
+                    return Err(error(format!("label `{}` defined twice", name.trim())));
+                }
+                statement = rest.trim();
+            }
+            if !statement.is_empty() {
+                let start = origin.wrapping_add(out.len() as u16);
+                statement_bytes(statement, start, line, &mut out, &mut fixups).map_err(error)?;
+            }
+        }
+    }
+    for fixup in fixups {
+        let address = *labels.get(&fixup.label).ok_or_else(|| AsmError { line: fixup.line, message: format!("unknown label `{}`", fixup.label) })?;
+        let bytes = encode(fixup.slot, address as i64, false, fixup.next).map_err(|message| AsmError { line: fixup.line, message })?;
+        out[fixup.at..fixup.at + bytes.len()].copy_from_slice(&bytes);
+    }
+    Ok(out)
+}
+
+fn statement_bytes(statement: &str, start: u16, line: usize, out: &mut Vec<u8>, fixups: &mut Vec<Fixup>) -> Result<(), String> {
+    let (mut mnemonic, texts) = split(statement);
+    if mnemonic == "DB" || mnemonic == "DW" {
+        let slot = if mnemonic == "DB" { Slot::N8 } else { Slot::N16 };
+        for text in texts {
+            let value = value(&text.split_whitespace().collect::<String>())?;
+            place(slot, value, false, start, line, out, fixups)?;
+        }
+        return Ok(());
+    }
+
+    let mut operands = Vec::new();
+    let mut values = Vec::new();
+    for text in &texts {
+        let (operand, value) = operand(text)?;
+        operands.push(operand);
+        values.extend(value);
+    }
+    match mnemonic.as_str() {
+        "LDI" | "LDD" => {
+            let replacement = if mnemonic == "LDI" { "[HL+]" } else { "[HL-]" };
+            operands.iter_mut().filter(|operand| *operand == "[HL]").for_each(|operand| *operand = replacement.to_string());
+            mnemonic = "LD".to_string();
+        }
+        "LD" if operands.iter().any(|operand| operand == "[C]") => mnemonic = "LDH".to_string(),
+        "JP" if operands == ["[HL]"] => operands[0] = "HL".to_string(),
+        alu if ALU.contains(&alu) && operands.len() == 1 => operands.insert(0, "A".to_string()),
+        _ => (),
+    }
+    let key = key(&mnemonic, &operands);
+    let candidates = forms().get(&key).ok_or_else(|| format!("unknown instruction `{}`", statement))?;
+    let form = match candidates[0].slot {
+        Slot::Fixed(_) => {
+            let wanted = match values.first() {
+                Some(Value::Number(n)) => *n,
+                _ => return Err(format!("`{}` needs a number", statement)),
+            };
+            *candidates.iter().find(|form| form.slot == Slot::Fixed(wanted as u8) && (0..=0xFF).contains(&wanted))
+                .ok_or_else(|| format!("{} is not valid in `{}`", wanted, statement))?
+        }
+        _ => candidates[0],
+    };
+
+    if form.prefixed {
+        out.push(0xCB);
+    }
+    out.push(form.opcode);
+    let len = if form.prefixed { 2 } else { instruction_len(form.opcode) };
+    let next = start.wrapping_add(len as u16);
+    match (form.slot, values.pop()) {
+        (Slot::None | Slot::Fixed(_) | Slot::Pad, _) | (_, None) => {
+            encode(form.slot, 0, false, next).map(|bytes| out.extend(bytes))
+        }
+        (slot, Some(value)) => {
+            let displacement = texts.last().is_some_and(|text| text.starts_with(['-', '+']));
+            place(slot, value, displacement, next, line, out, fixups)
+        }
+    }
+}
+
+/* Emit the operand now, or leave room for it until its label is placed */
+fn place(slot: Slot, value: Value, displacement: bool, next: u16, line: usize, out: &mut Vec<u8>, fixups: &mut Vec<Fixup>) -> Result<(), String> {
+    match value {
+        Value::Number(n) => encode(slot, n, displacement, next).map(|bytes| out.extend(bytes)),
+        Value::Label(label) => {
+            let width = if slot == Slot::N16 { 2 } else { 1 };
+            fixups.push(Fixup { at: out.len(), slot, label, next, line });
+            out.extend(std::iter::repeat_n(0, width));
+            Ok(())
+        }
+    }
+}
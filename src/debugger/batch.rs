@@ -0,0 +1,185 @@
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicUsize, Ordering}, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::prelude::Config,
+    gba::prelude::{panic_message, Emulator, RomSource},
+};
+
+use super::{golden::frame_hash, testrom::{collect_roms, SerialCapture}};
+
+/* Ten emulated seconds, enough to get most games to their title screen */
+pub const DEFAULT_BATCH_FRAMES: u64 = 600;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchStatus {
+    /* Every frame ran */
+    Ran,
+    /* The CPU hung on this illegal opcode */
+    Locked(u8),
+    Panicked(String),
+    /* The file could not be read or is not a ROM */
+    LoadFailed(String),
+}
+
+impl BatchStatus {
+    /* Whether this points at an emulator bug rather than a bad file or a
+     * game that hangs on hardware too */
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::Panicked(_) | Self::LoadFailed(_))
+    }
+}
+
+impl fmt::Display for BatchStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ran => write!(f, "ran"),
+            Self::Locked(opcode) => write!(f, "locked on ${:02X}", opcode),
+            Self::Panicked(message) => write!(f, "PANIC: {}", message),
+            Self::LoadFailed(message) => write!(f, "LOAD: {}", message),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchResult {
+    pub path: PathBuf,
+    pub status: BatchStatus,
+    pub frames: u64,
+    /* Bytes shifted out over the link port, as text */
+    pub serial: String,
+    /* CRC-32 of the last frame's RGB, None if no frame finished */
+    pub screen_hash: Option<u32>,
+    pub elapsed: Duration,
+}
+
+/* Runs many ROMs headless for a fixed number of frames each, spread over
+ * worker threads, one core per ROM. Cores never share anything, so the
+ * only cost of more threads is memory. */
+#[derive(Debug, Clone)]
+pub struct BatchRunner {
+    pub frames: u64,
+    pub threads: usize,
+    /* Every core is built from this; the clock is pinned unless it sets
+     * `fixed_time` itself so screen hashes repeat between runs */
+    pub config: Config,
+}
+
+impl Default for BatchRunner {
+    fn default() -> Self {
+        Self {
+            frames: DEFAULT_BATCH_FRAMES,
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            config: Config::default(),
+        }
+    }
+}
+
+impl BatchRunner {
+    /* Results come back in the order of `roms` */
+    pub fn run(&self, roms: &[PathBuf]) -> Vec<BatchResult> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(vec![None; roms.len()]);
+        thread::scope(|scope| {
+            for _ in 0..self.threads.clamp(1, roms.len().max(1)) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(rom) = roms.get(index) else { break };
+                    let result = self.run_one(rom);
+                    if let Ok(mut results) = results.lock() {
+                        results[index] = Some(result);
+                    }
+                });
+            }
+        });
+        results.into_inner().unwrap_or_default().into_iter().flatten().collect()
+    }
+
+    pub fn run_one(&self, path: &Path) -> BatchResult {
+        let start = Instant::now();
+        let mut result = BatchResult {
+            path: path.to_path_buf(),
+            status: BatchStatus::Ran,
+            frames: 0,
+            serial: String::new(),
+            screen_hash: None,
+            elapsed: Duration::ZERO,
+        };
+        /* From bytes, so there is no save path and nothing is written back */
+        let mut config = self.config.clone();
+        config.fixed_time.get_or_insert(0);
+        let loaded = std::fs::read(path).map_err(Into::into)
+            .and_then(|data| Emulator::with_config(RomSource::Bytes(data), &config));
+        let mut emulator = match loaded {
+            Ok(emulator) => emulator,
+            Err(e) => {
+                result.status = BatchStatus::LoadFailed(e.to_string());
+                result.elapsed = start.elapsed();
+                return result;
+            },
+        };
+        let serial = SerialCapture::default();
+        emulator.console_mut().mem.serial.connect(Box::new(serial.clone()));
+
+        let run = panic::catch_unwind(AssertUnwindSafe(|| {
+            while result.frames < self.frames {
+                let frame = emulator.run_frame();
+                result.frames += 1;
+                if frame.is_err() {
+                    break;
+                }
+            }
+        }));
+        result.status = match (run, emulator.locked()) {
+            (Err(payload), _) => BatchStatus::Panicked(panic_message(payload.as_ref())),
+            (Ok(()), Some(opcode)) => BatchStatus::Locked(opcode),
+            (Ok(()), None) => BatchStatus::Ran,
+        };
+        if result.frames != 0 && !matches!(result.status, BatchStatus::Panicked(_)) {
+            result.screen_hash = Some(frame_hash(&emulator.framebuffer()));
+        }
+        result.serial = String::from_utf8_lossy(&serial.0.borrow()).into_owned();
+        result.elapsed = start.elapsed();
+        result
+    }
+}
+
+/* Files as given, directories searched for .gb and .gbc in path order */
+pub fn expand_roms(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut roms = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut found = Vec::new();
+            collect_roms(path, &mut found);
+            found.sort();
+            roms.extend(found);
+        } else {
+            roms.push(path.clone());
+        }
+    }
+    roms
+}
+
+/* One line per ROM with the start of its serial output, then the totals */
+pub fn batch_summary(results: &[BatchResult]) -> String {
+    let names: Vec<String> = results.iter().map(|result| result.path.display().to_string()).collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0).max(3);
+    let mut out = format!("{:<width$}  {:>6}  {:>8}  {:>7}  Status\n", "ROM", "Frames", "Screen", "Seconds");
+    for (name, result) in names.iter().zip(results) {
+        let hash = result.screen_hash.map_or_else(|| "-".to_string(), |hash| format!("{:08X}", hash));
+        out.push_str(&format!("{:<width$}  {:>6}  {:>8}  {:>7.2}  {}", name, result.frames, hash, result.elapsed.as_secs_f64(), result.status));
+        if let Some(line) = result.serial.lines().map(str::trim).find(|line| !line.is_empty()) {
+            out.push_str(&format!("  serial: {:?}", line));
+        }
+        out.push('\n');
+    }
+    let failed = results.iter().filter(|result| result.status.is_failure()).count();
+    out.push_str(&format!("\n{} ROMs, {} failed\n", results.len(), failed));
+    out
+}
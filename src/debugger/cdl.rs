@@ -0,0 +1,66 @@
+use std::{io::ErrorKind, path::Path};
+
+use crate::error::prelude::GbError;
+
+/* One flag byte per ROM byte, indexed by file offset so every bank is
+ * tracked separately; the layout disassemblers expect of a .cdl file. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDataLog {
+    flags: Vec<u8>,
+}
+
+impl CodeDataLog {
+    /* Fetched by the CPU as an opcode or operand */
+    pub const CODE: u8 = 0x01;
+    /* Read by an instruction */
+    pub const DATA: u8 = 0x02;
+    /* Source of an OAM DMA */
+    pub const DMA: u8 = 0x04;
+    /* First byte of an instruction */
+    pub const OPCODE: u8 = 0x08;
+
+    pub fn new(rom_len: usize) -> Self {
+        Self { flags: vec![0; rom_len] }
+    }
+
+    /* A log from an earlier session, to keep accumulating into */
+    pub fn from_bytes(flags: Vec<u8>, rom_len: usize) -> Result<Self, GbError> {
+        match flags.len() == rom_len {
+            true => Ok(Self { flags }),
+            false => Err(GbError::Io(ErrorKind::InvalidData)),
+        }
+    }
+
+    #[cfg(feature = "io")]
+    pub fn load<P: AsRef<Path>>(path: P, rom_len: usize) -> Result<Self, GbError> {
+        Self::from_bytes(std::fs::read(path)?, rom_len)
+    }
+
+    #[cfg(feature = "io")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ErrorKind> {
+        std::fs::write(path, &self.flags).map_err(|e| e.kind())
+    }
+
+    pub fn mark(&mut self, offset: usize, flags: u8) {
+        if let Some(byte) = self.flags.get_mut(offset) {
+            *byte |= flags;
+        }
+    }
+
+    pub fn flags(&self, offset: usize) -> u8 {
+        self.flags.get(offset).copied().unwrap_or(0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.flags
+    }
+
+    /* ROM bytes with any of `flags` set */
+    pub fn count(&self, flags: u8) -> usize {
+        self.flags.iter().filter(|&&byte| byte & flags != 0).count()
+    }
+
+    pub fn clear(&mut self) {
+        self.flags.fill(0);
+    }
+}
@@ -0,0 +1,326 @@
+use std::{cell::RefCell, collections::BTreeMap, ops::RangeInclusive, rc::Rc};
+
+use crate::{
+    cpu::proc::CpuState,
+    gba::prelude::Gba,
+    mem::prelude::{Bus, HookId, Mem},
+};
+
+use super::{expr::{Expr, ExprError}, symbols::Symbols};
+
+/* Why execution handed control back to the debugger */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Step,
+    Breakpoint(u16),
+    /* The instruction just executed made a watched access */
+    Watchpoint { addr: u16, value: u8, write: bool },
+    /* The CPU hit an illegal opcode and will not run again */
+    Locked(u8),
+    /* The cycle budget ran out with nothing hit */
+    Timeout,
+}
+
+/* Stops before the instruction at its address. A hit only counts while
+ * `condition` holds and, if set, ROM `bank` is the one mapped there;
+ * execution only stops from hit `on_hit` on. */
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoint {
+    pub condition: Option<Expr>,
+    pub bank: Option<usize>,
+    pub on_hit: u32,
+    pub hits: u32,
+}
+
+impl Breakpoint {
+    pub fn when(condition: Expr) -> Self {
+        Self { condition: Some(condition), ..Self::default() }
+    }
+
+    pub fn on_hit(mut self, hit: u32) -> Self {
+        self.on_hit = hit;
+        self
+    }
+
+    pub fn in_bank(mut self, bank: usize) -> Self {
+        self.bank = Some(bank);
+        self
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+/* Stops after an instruction reads or writes anywhere in `range`,
+ * optionally only for one value or while `condition` holds */
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+    pub value: Option<u8>,
+    pub condition: Option<Expr>,
+}
+
+impl Watchpoint {
+    pub fn new(range: RangeInclusive<u16>, kind: WatchKind) -> Self {
+        Self { range, kind, value: None, condition: None }
+    }
+
+    pub fn value(mut self, value: u8) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    pub fn when(mut self, condition: Expr) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    fn matches(&self, access: &MemAccess) -> bool {
+        let kind = match self.kind {
+            WatchKind::Read => !access.write,
+            WatchKind::Write => access.write,
+            WatchKind::Access => true,
+        };
+        kind && self.range.contains(&access.addr) && self.value.is_none_or(|value| value == access.value)
+    }
+}
+
+/* An expression shown while paused, re-evaluated after every step and
+ * stop; `changed` marks the ones the last step moved */
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub text: String,
+    pub expr: Expr,
+    pub value: i64,
+    pub changed: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct MemAccess {
+    addr: u16,
+    value: u8,
+    write: bool,
+}
+
+/* Execution control shared by every debugging frontend: breakpoints on
+ * the address of the next instruction, memory watchpoints, single steps,
+ * and runs until one of them hits. */
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    watchpoints: BTreeMap<usize, (Watchpoint, Vec<HookId>)>,
+    next_watchpoint: usize,
+    watches: Vec<Watch>,
+    /* Filled by the memory hooks of every watchpoint, drained after each step */
+    accesses: Rc<RefCell<Vec<MemAccess>>>,
+}
+
+impl Debugger {
+    /* False if there already was one at `addr` */
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.set_breakpoint(addr, Breakpoint::default()).is_none()
+    }
+
+    /* Replaces any breakpoint already at `addr`, returning it */
+    pub fn set_breakpoint(&mut self, addr: u16, breakpoint: Breakpoint) -> Option<Breakpoint> {
+        self.breakpoints.insert(addr, breakpoint)
+    }
+
+    /* A breakpoint on a label, only hit in the label's bank when it is in
+     * switchable ROM. Returns where it went, None for an unknown name. */
+    pub fn add_symbol_breakpoint(&mut self, symbols: &Symbols, name: &str) -> Option<u16> {
+        let location = symbols.resolve(name)?;
+        let breakpoint = match location.bank {
+            Some(bank) if (0x4000..0x8000).contains(&location.pc) => Breakpoint::default().in_bank(bank),
+            _ => Breakpoint::default(),
+        };
+        self.set_breakpoint(location.pc, breakpoint);
+        Some(location.pc)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    pub fn breakpoint(&self, addr: u16) -> Option<&Breakpoint> {
+        self.breakpoints.get(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /* Watchpoints hook the console's memory, so they need the real bus */
+    pub fn add_watchpoint(&mut self, mem: &mut Mem, watchpoint: Watchpoint) -> usize {
+        let mut hooks = Vec::new();
+        for write in [false, true] {
+            let wanted = match watchpoint.kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::Access => true,
+            };
+            if !wanted {
+                continue;
+            }
+            let accesses = Rc::clone(&self.accesses);
+            let log = move |addr, value| accesses.borrow_mut().push(MemAccess { addr, value, write });
+            hooks.push(match write {
+                true => mem.on_write(watchpoint.range.clone(), log),
+                false => mem.on_read(watchpoint.range.clone(), log),
+            });
+        }
+        self.next_watchpoint += 1;
+        self.watchpoints.insert(self.next_watchpoint, (watchpoint, hooks));
+        self.next_watchpoint
+    }
+
+    pub fn remove_watchpoint(&mut self, mem: &mut Mem, id: usize) -> bool {
+        match self.watchpoints.remove(&id) {
+            Some((_, hooks)) => {
+                hooks.into_iter().for_each(|hook| { mem.remove_hook(hook); });
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> + '_ {
+        self.watchpoints.iter().map(|(&id, (watchpoint, _))| (id, watchpoint))
+    }
+
+    /* Watches `text`, an expression that may also name labels from
+     * `gba.symbols`, which stand for their address: `[wLives]` */
+    pub fn add_watch<B: Bus>(&mut self, gba: &Gba<B>, text: &str) -> Result<usize, ExprError> {
+        let expr = parse(gba, text)?;
+        let value = eval(gba, &expr);
+        self.watches.push(Watch { text: text.to_string(), expr, value, changed: false });
+        Ok(self.watches.len() - 1)
+    }
+
+    pub fn remove_watch(&mut self, index: usize) -> Option<Watch> {
+        (index < self.watches.len()).then(|| self.watches.remove(index))
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    /* Re-evaluates every watch; `step` and `resume` do this themselves */
+    pub fn update_watches<B: Bus>(&mut self, gba: &Gba<B>) {
+        for watch in &mut self.watches {
+            let value = eval(gba, &watch.expr);
+            watch.changed = value != watch.value;
+            watch.value = value;
+        }
+    }
+
+    /* `text` valued once, as a watch would be */
+    pub fn evaluate<B: Bus>(&self, gba: &Gba<B>, text: &str) -> Result<i64, ExprError> {
+        parse(gba, text).map(|expr| eval(gba, &expr))
+    }
+
+    /* Sets a register (`A`, `HL`, `PC`), a flag (`ZF`) or a byte of memory
+     * (`[$C000]`, `[wLives + 1]`) while paused. Memory goes through the bus,
+     * so writes to ROM reach the cartridge's mapper. */
+    pub fn assign<B: Bus>(&mut self, gba: &mut Gba<B>, target: &str, value: i64) -> Result<(), ExprError> {
+        let target = parse(gba, target)?;
+        let registers = &mut gba.cpu.registers;
+        match target {
+            Expr::Register(reg) => registers.set_r8(reg, value as u8),
+            Expr::Pair(reg) => registers.set_r16(reg, value as u16),
+            Expr::Flag(flag) if value != 0 => registers.f.set(flag),
+            Expr::Flag(flag) => registers.f.unset(flag),
+            Expr::Read(addr) => {
+                let addr = eval(gba, &addr) as u16;
+                gba.mem.set_u8(addr, value as u8);
+            },
+            _ => return Err(ExprError { position: 0, message: "expected a register, flag or [address]".to_string() }),
+        }
+        self.update_watches(gba);
+        Ok(())
+    }
+
+    /* Executes one instruction, or one idle slice while halted */
+    pub fn step<B: Bus>(&mut self, gba: &mut Gba<B>) -> StopReason {
+        self.accesses.borrow_mut().clear();
+        gba.step();
+        self.update_watches(gba);
+        match gba.cpu.state {
+            CpuState::Locked(opcode) => StopReason::Locked(opcode),
+            _ => self.watch_hit(gba).unwrap_or(StopReason::Step),
+        }
+    }
+
+    /* Runs until the next instruction sits on a breakpoint, leaving it
+     * unexecuted, a watchpoint fires, or roughly `budget` M-cycles have
+     * passed. The instruction at the current PC always runs, so resuming
+     * from a breakpoint moves past it. */
+    pub fn resume<B: Bus>(&mut self, gba: &mut Gba<B>, budget: usize) -> StopReason {
+        let reason = self.run(gba, budget);
+        self.update_watches(gba);
+        reason
+    }
+
+    fn run<B: Bus>(&mut self, gba: &mut Gba<B>, budget: usize) -> StopReason {
+        let mut cycles = 0;
+        self.accesses.borrow_mut().clear();
+        loop {
+            cycles += gba.step();
+            if let CpuState::Locked(opcode) = gba.cpu.state {
+                return StopReason::Locked(opcode);
+            }
+            if let Some(reason) = self.watch_hit(gba) {
+                return reason;
+            }
+            let pc = gba.cpu.registers.pc;
+            if gba.cpu.state == CpuState::Running && self.breakpoint_hit(gba, pc) {
+                return StopReason::Breakpoint(pc);
+            }
+            if cycles >= budget {
+                return StopReason::Timeout;
+            }
+        }
+    }
+
+    fn breakpoint_hit<B: Bus>(&mut self, gba: &Gba<B>, pc: u16) -> bool {
+        let Some(breakpoint) = self.breakpoints.get_mut(&pc) else { return false };
+        if breakpoint.bank.is_some_and(|bank| gba.mem.rom_bank_at(pc) != Some(bank)) {
+            return false;
+        }
+        if breakpoint.condition.as_ref().is_some_and(|condition| !condition.is_true(gba)) {
+            return false;
+        }
+        breakpoint.hits += 1;
+        breakpoint.hits >= breakpoint.on_hit
+    }
+
+    /* The first access logged since the last step that a watchpoint wants */
+    fn watch_hit<B: Bus>(&self, gba: &Gba<B>) -> Option<StopReason> {
+        let accesses = std::mem::take(&mut *self.accesses.borrow_mut());
+        accesses.iter().find(|access| self.watchpoints.values().any(|(watchpoint, _)| {
+            watchpoint.matches(access) && watchpoint.condition.as_ref().is_none_or(|condition| condition.is_true(gba))
+        }))
+        .map(|access| StopReason::Watchpoint { addr: access.addr, value: access.value, write: access.write })
+    }
+}
+
+/* Labels from the console's symbols stand for their addresses */
+fn parse<B: Bus>(gba: &Gba<B>, text: &str) -> Result<Expr, ExprError> {
+    let names: Vec<&str> = gba.symbols.iter().flat_map(|symbols| symbols.names()).collect();
+    Expr::parse_with(text, &names)
+}
+
+fn eval<B: Bus>(gba: &Gba<B>, expr: &Expr) -> i64 {
+    expr.eval_with(gba, &|name| {
+        gba.symbols.as_ref().and_then(|symbols| symbols.resolve(name)).map_or(0, |location| location.pc as i64)
+    })
+}
@@ -0,0 +1,93 @@
+use super::trace::instruction_len;
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "[HL]", "A"];
+const R16: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const R16_STACK: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const R16_MEM: [&str; 4] = ["[BC]", "[DE]", "[HL+]", "[HL-]"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB A,", "SBC A,", "AND A,", "XOR A,", "OR A,", "CP A,"];
+const ACCUMULATOR: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+const SHIFTS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const BITS: [&str; 3] = ["BIT", "RES", "SET"];
+
+/* One instruction in RGBDS syntax. `bytes` starts at the opcode and
+ * missing operands read as zero; `label` may name absolute addresses, so
+ * jumps read `CALL Main_Loop` rather than `CALL $0150`. */
+pub fn disassemble(bytes: &[u8], pc: u16, label: &dyn Fn(u16) -> Option<String>) -> String {
+    let byte = |i: usize| bytes.get(i).copied().unwrap_or(0);
+    let op = byte(0);
+    let n8 = format!("${:02X}", byte(1));
+    let n16 = u16::from_le_bytes([byte(1), byte(2)]);
+    let a16 = label(n16).unwrap_or_else(|| format!("${:04X}", n16));
+    let e8 = byte(1) as i8;
+    let relative = {
+        let target = pc.wrapping_add(instruction_len(op) as u16).wrapping_add(e8 as u16);
+        label(target).unwrap_or_else(|| format!("${:04X}", target))
+    };
+    let signed = if e8 < 0 { format!("-${:02X}", e8.unsigned_abs()) } else { format!("${:02X}", e8) };
+
+    let (x, y, z) = ((op >> 6) as usize, ((op >> 3) & 7) as usize, (op & 7) as usize);
+    let (p, q) = (y >> 1, y & 1);
+    match (x, z) {
+        (0, 0) => match y {
+            0 => "NOP".to_string(),
+            1 => format!("LD [{}], SP", a16),
+            2 => "STOP".to_string(),
+            3 => format!("JR {}", relative),
+            _ => format!("JR {}, {}", CONDITIONS[y - 4], relative),
+        },
+        (0, 1) if q == 0 => format!("LD {}, ${:04X}", R16[p], n16),
+        (0, 1) => format!("ADD HL, {}", R16[p]),
+        (0, 2) if q == 0 => format!("LD {}, A", R16_MEM[p]),
+        (0, 2) => format!("LD A, {}", R16_MEM[p]),
+        (0, 3) => format!("{} {}", if q == 0 { "INC" } else { "DEC" }, R16[p]),
+        (0, 4) => format!("INC {}", R8[y]),
+        (0, 5) => format!("DEC {}", R8[y]),
+        (0, 6) => format!("LD {}, {}", R8[y], n8),
+        (0, _) => ACCUMULATOR[y].to_string(),
+        (1, _) if op == 0x76 => "HALT".to_string(),
+        (1, _) => format!("LD {}, {}", R8[y], R8[z]),
+        (2, _) => format!("{} {}", ALU[y], R8[z]),
+        (_, 0) => match y {
+            0..=3 => format!("RET {}", CONDITIONS[y]),
+            4 => format!("LDH [{}], A", label(0xFF00 | byte(1) as u16).unwrap_or(n8)),
+            5 => format!("ADD SP, {}", signed),
+            6 => format!("LDH A, [{}]", label(0xFF00 | byte(1) as u16).unwrap_or(n8)),
+            _ => format!("LD HL, SP + {}", signed),
+        },
+        (_, 1) if q == 0 => format!("POP {}", R16_STACK[p]),
+        (_, 1) => ["RET", "RETI", "JP HL", "LD SP, HL"][p].to_string(),
+        (_, 2) => match y {
+            0..=3 => format!("JP {}, {}", CONDITIONS[y], a16),
+            4 => "LDH [C], A".to_string(),
+            5 => format!("LD [{}], A", a16),
+            6 => "LDH A, [C]".to_string(),
+            _ => format!("LD A, [{}]", a16),
+        },
+        (_, 3) => match y {
+            0 => format!("JP {}", a16),
+            1 => prefixed(byte(1)),
+            6 => "DI".to_string(),
+            7 => "EI".to_string(),
+            _ => illegal(op),
+        },
+        (_, 4) if y < 4 => format!("CALL {}, {}", CONDITIONS[y], a16),
+        (_, 5) if q == 0 => format!("PUSH {}", R16_STACK[p]),
+        (_, 5) if p == 0 => format!("CALL {}", a16),
+        (_, 6) => format!("{} {}", ALU[y], n8),
+        (_, 7) => format!("RST ${:02X}", y * 8),
+        _ => illegal(op),
+    }
+}
+
+fn prefixed(op: u8) -> String {
+    let (x, y, z) = ((op >> 6) as usize, ((op >> 3) & 7) as usize, (op & 7) as usize);
+    match x {
+        0 => format!("{} {}", SHIFTS[y], R8[z]),
+        _ => format!("{} {}, {}", BITS[x - 1], y, R8[z]),
+    }
+}
+
+fn illegal(op: u8) -> String {
+    format!("DB ${:02X}", op)
+}
@@ -0,0 +1,268 @@
+use std::fmt;
+
+use crate::{
+    cpu::register::types::{Flags, Register16, Register8},
+    gba::prelude::Gba,
+    mem::prelude::Bus,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or, And,
+    Eq, Ne, Lt, Le, Gt, Ge,
+    BitOr, BitXor, BitAnd,
+    Shl, Shr,
+    Add, Sub,
+    Mul, Div, Rem,
+}
+
+/* Debugger conditions such as `A == $3E && [HL] > 0x10`. Names are
+ * registers (A-L, F, AF-HL, SP, PC) or flags (ZF, NF, HF, CF), numbers are
+ * decimal, 0x or $ hex, and `[addr]` reads a byte without side effects.
+ * Comparisons and logic give 1 or 0; anything non-zero is true. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Register(Register8),
+    Pair(Register16),
+    Flag(Flags),
+    /* A name given to `parse_with`, valued by the caller */
+    Variable(String),
+    Read(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.position + 1, self.message)
+    }
+}
+
+/* Binary operators from loosest to tightest binding */
+const LEVELS: [&[(&str, BinaryOp)]; 9] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne), ("<=", BinaryOp::Le), (">=", BinaryOp::Ge), ("<", BinaryOp::Lt), (">", BinaryOp::Gt)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)],
+];
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Self, ExprError> {
+        Self::parse_with(text, &[])
+    }
+
+    /* Also accepts `variables`, which must not shadow registers or flags */
+    pub fn parse_with(text: &str, variables: &[&str]) -> Result<Self, ExprError> {
+        let mut parser = Parser { text, pos: 0, variables };
+        let expr = parser.binary(0)?;
+        parser.skip_space();
+        match parser.pos == text.len() {
+            true => Ok(expr),
+            false => Err(parser.error("unexpected input")),
+        }
+    }
+
+    pub fn eval<B: Bus>(&self, gba: &Gba<B>) -> i64 {
+        self.eval_with(gba, &|_| 0)
+    }
+
+    pub fn eval_with<B: Bus>(&self, gba: &Gba<B>, variables: &dyn Fn(&str) -> i64) -> i64 {
+        let eval = |expr: &Expr| expr.eval_with(gba, variables);
+        let registers = &gba.cpu.registers;
+        match self {
+            Self::Number(value) => *value,
+            Self::Register(reg) => registers.get_r8(*reg) as i64,
+            Self::Pair(reg) => registers.get_r16(*reg) as i64,
+            Self::Flag(flag) => registers.f.is_set(*flag) as i64,
+            Self::Variable(name) => variables(name),
+            Self::Read(addr) => gba.mem.peek(eval(addr) as u16) as i64,
+            Self::Unary(op, operand) => {
+                let value = eval(operand);
+                match op {
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Complement => !value,
+                }
+            },
+            /* Short-circuit so a false guard skips the rest */
+            Self::Binary(BinaryOp::And, lhs, rhs) => (eval(lhs) != 0 && eval(rhs) != 0) as i64,
+            Self::Binary(BinaryOp::Or, lhs, rhs) => (eval(lhs) != 0 || eval(rhs) != 0) as i64,
+            Self::Binary(op, lhs, rhs) => {
+                let (a, b) = (eval(lhs), eval(rhs));
+                match op {
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Shl => a.wrapping_shl(b as u32),
+                    BinaryOp::Shr => a.wrapping_shr(b as u32),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    /* Division by zero gives zero rather than stopping the run */
+                    BinaryOp::Div => a.checked_div(b).unwrap_or(0),
+                    BinaryOp::Rem => a.checked_rem(b).unwrap_or(0),
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            },
+        }
+    }
+
+    pub fn is_true<B: Bus>(&self, gba: &Gba<B>) -> bool {
+        self.eval(gba) != 0
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    variables: &'a [&'a str],
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ExprError {
+        ExprError { position: self.pos, message: message.to_string() }
+    }
+
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        if !self.rest().starts_with(token) {
+            return false;
+        }
+        /* `|` and `&` must not swallow the first half of `||` and `&&` */
+        let doubled = matches!(token, "|" | "&") && self.rest()[1..].starts_with(token);
+        if doubled {
+            return false;
+        }
+        self.pos += token.len();
+        true
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, ExprError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        'operators: loop {
+            for &(token, op) in LEVELS[level] {
+                if self.eat(token) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                    continue 'operators;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        let op = if self.eat("!") {
+            UnaryOp::Not
+        } else if self.eat("-") {
+            UnaryOp::Negate
+        } else if self.eat("~") {
+            UnaryOp::Complement
+        } else {
+            return self.primary();
+        };
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExprError> {
+        if self.eat("(") {
+            let expr = self.binary(0)?;
+            return match self.eat(")") {
+                true => Ok(expr),
+                false => Err(self.error("expected `)`")),
+            };
+        }
+        if self.eat("[") {
+            let expr = self.binary(0)?;
+            return match self.eat("]") {
+                true => Ok(Expr::Read(Box::new(expr))),
+                false => Err(self.error("expected `]`")),
+            };
+        }
+
+        self.skip_space();
+        let start = self.pos;
+        /* `.` for local labels such as `Main.loop` */
+        let word: String = self.rest().chars().take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '$' | '_' | '.')).collect();
+        if word.is_empty() {
+            return Err(self.error("expected a number, register or flag"));
+        }
+        self.pos += word.len();
+
+        let number = if let Some(hex) = word.strip_prefix('$').or_else(|| word.strip_prefix("0x")) {
+            i64::from_str_radix(hex, 16).ok()
+        } else {
+            word.parse().ok()
+        };
+        if let Some(value) = number {
+            return Ok(Expr::Number(value));
+        }
+        match name(&word) {
+            Some(expr) => Ok(expr),
+            None if self.variables.contains(&word.as_str()) => Ok(Expr::Variable(word)),
+            None => Err(ExprError { position: start, message: format!("unknown name `{}`", word) }),
+        }
+    }
+}
+
+fn name(word: &str) -> Option<Expr> {
+    use Register8::*;
+    Some(match word.to_ascii_uppercase().as_str() {
+        "A" => Expr::Register(A),
+        "B" => Expr::Register(B),
+        "C" => Expr::Register(C),
+        "D" => Expr::Register(D),
+        "E" => Expr::Register(E),
+        "H" => Expr::Register(H),
+        "L" => Expr::Register(L),
+        "F" => Expr::Register(F),
+        "AF" => Expr::Pair(Register16::AF),
+        "BC" => Expr::Pair(Register16::BC),
+        "DE" => Expr::Pair(Register16::DE),
+        "HL" => Expr::Pair(Register16::HL),
+        "SP" => Expr::Pair(Register16::SP),
+        "PC" => Expr::Pair(Register16::PC),
+        "ZF" => Expr::Flag(Flags::Zero),
+        "NF" => Expr::Flag(Flags::Subtract),
+        "HF" => Expr::Flag(Flags::HalfCarry),
+        "CF" => Expr::Flag(Flags::Carry),
+        _ => return None,
+    })
+}
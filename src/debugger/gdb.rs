@@ -0,0 +1,295 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{cpu::register::types::Register16, gba::prelude::Gba, info, mem::prelude::Bus};
+
+use super::control::{Debugger, StopReason};
+
+/* How long a continue runs between checks for an interrupt from gdb */
+const SLICE_CYCLES: usize = 17556;
+/* There is no SM83 target in gdb; registers go out as z80's first six */
+const REGISTERS: [Register16; 6] = [
+    Register16::AF, Register16::BC, Register16::DE, Register16::HL, Register16::SP, Register16::PC,
+];
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/* What the session loop should do after a packet */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GdbAction {
+    Reply(String),
+    Continue,
+    Step,
+    /* Reply, then close the connection */
+    Detach(String),
+    Kill,
+}
+
+/* gdb remote serial protocol over TCP, on top of `Debugger`. Supports
+ * register and memory access, software and hardware breakpoints, step,
+ * continue and interrupting a continue with ^C. */
+#[derive(Debug, Default)]
+pub struct GdbStub {
+    pub debugger: Debugger,
+    no_ack: bool,
+}
+
+impl GdbStub {
+    /* Waits for one debugger to connect and serves it until it detaches */
+    pub fn serve<B: Bus, A: ToSocketAddrs>(&mut self, gba: &mut Gba<B>, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        info!(target: "gbemu::gdb", "Waiting for gdb on {}", listener.local_addr()?);
+        let (stream, peer) = listener.accept()?;
+        info!(target: "gbemu::gdb", "gdb connected from {}", peer);
+        self.session(gba, stream)
+    }
+
+    pub fn session<B: Bus>(&mut self, gba: &mut Gba<B>, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        self.no_ack = false;
+        while let Some(packet) = self.read_packet(&mut stream)? {
+            match self.handle(gba, &packet) {
+                GdbAction::Reply(reply) => self.send(&mut stream, &reply)?,
+                GdbAction::Step => {
+                    let reason = self.debugger.step(gba);
+                    self.send(&mut stream, &stop_reply(reason))?;
+                },
+                GdbAction::Continue => {
+                    let reply = self.run(gba, &mut stream)?;
+                    self.send(&mut stream, &reply)?;
+                },
+                GdbAction::Detach(reply) => return self.send(&mut stream, &reply),
+                GdbAction::Kill => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /* Runs in slices, polling the socket for ^C in between */
+    fn run<B: Bus>(&mut self, gba: &mut Gba<B>, stream: &mut TcpStream) -> io::Result<String> {
+        loop {
+            let reason = self.debugger.resume(gba, SLICE_CYCLES);
+            if reason != StopReason::Timeout {
+                return Ok(stop_reply(reason));
+            }
+            stream.set_nonblocking(true)?;
+            let mut byte = [0];
+            let read = stream.read(&mut byte);
+            stream.set_nonblocking(false)?;
+            match read {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(_) if byte[0] == 0x03 => return Ok(format!("S{:02x}", SIGINT)),
+                Ok(_) => {},
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /* The next `$...#xx` packet, acknowledged; None once gdb hangs up */
+    fn read_packet(&mut self, stream: &mut TcpStream) -> io::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] != b'$' {
+                /* Acks, and ^C while already stopped */
+                continue;
+            }
+            let mut data = Vec::new();
+            loop {
+                if stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let valid = expected == Some(checksum_of(&data));
+            if !self.no_ack {
+                stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, stream: &mut TcpStream, reply: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", reply, checksum_of(reply.as_bytes()));
+        stream.write_all(packet.as_bytes())
+    }
+
+    /* Answers one packet without touching the connection */
+    pub fn handle<B: Bus>(&mut self, gba: &mut Gba<B>, packet: &str) -> GdbAction {
+        let reply = |reply: &str| GdbAction::Reply(reply.to_string());
+        let command = packet.get(..1).unwrap_or("");
+        let args = packet.get(1..).unwrap_or("");
+        match command {
+            "?" => reply(&format!("S{:02x}", SIGTRAP)),
+            "g" => reply(&REGISTERS.iter().map(|&reg| hex_le(gba.cpu.registers.get_r16(reg))).collect::<String>()),
+            "G" => {
+                let values: Option<Vec<u16>> = (0..REGISTERS.len())
+                    .map(|i| args.get(i * 4..i * 4 + 4).and_then(parse_le))
+                    .collect();
+                match values {
+                    Some(values) => {
+                        for (&reg, value) in REGISTERS.iter().zip(values) {
+                            gba.cpu.registers.set_r16(reg, value);
+                        }
+                        reply("OK")
+                    },
+                    None => reply("E01"),
+                }
+            },
+            "p" => match usize::from_str_radix(args, 16).ok().and_then(|i| REGISTERS.get(i)) {
+                Some(&reg) => reply(&hex_le(gba.cpu.registers.get_r16(reg))),
+                None => reply("E01"),
+            },
+            "P" => {
+                let register = args.split_once('=').and_then(|(index, value)| {
+                    Some((*REGISTERS.get(usize::from_str_radix(index, 16).ok()?)?, parse_le(value)?))
+                });
+                match register {
+                    Some((reg, value)) => {
+                        gba.cpu.registers.set_r16(reg, value);
+                        reply("OK")
+                    },
+                    None => reply("E01"),
+                }
+            },
+            "m" => match parse_range(args) {
+                Some((addr, len)) => {
+                    gba.mem.sync();
+                    reply(&(0..len).map(|i| format!("{:02x}", gba.mem.peek(addr.wrapping_add(i)))).collect::<String>())
+                },
+                None => reply("E01"),
+            },
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    let bytes = parse_bytes(data)?;
+                    (bytes.len() == len as usize).then_some((addr, bytes))
+                });
+                match write {
+                    Some((addr, bytes)) => {
+                        for (i, byte) in bytes.into_iter().enumerate() {
+                            gba.mem.set_u8(addr.wrapping_add(i as u16), byte);
+                        }
+                        reply("OK")
+                    },
+                    None => reply("E01"),
+                }
+            },
+            /* Software and hardware breakpoints are the same thing here */
+            "Z" | "z" => {
+                let mut parts = args.split(',');
+                let kind = parts.next();
+                let addr = parts.next().and_then(|addr| u16::from_str_radix(addr, 16).ok());
+                match (kind, addr) {
+                    (Some("0" | "1"), Some(addr)) => {
+                        if command == "Z" {
+                            self.debugger.add_breakpoint(addr);
+                        } else {
+                            self.debugger.remove_breakpoint(addr);
+                        }
+                        reply("OK")
+                    },
+                    /* Watchpoints are not supported */
+                    (Some(_), Some(_)) => reply(""),
+                    _ => reply("E01"),
+                }
+            },
+            "c" => GdbAction::Continue,
+            "s" => GdbAction::Step,
+            "D" => GdbAction::Detach("OK".to_string()),
+            "k" => GdbAction::Kill,
+            "H" | "T" => reply("OK"),
+            _ => match packet {
+                "QStartNoAckMode" => {
+                    self.no_ack = true;
+                    reply("OK")
+                },
+                _ if packet.starts_with("qSupported") => reply("PacketSize=1000;QStartNoAckMode+"),
+                "qAttached" => reply("1"),
+                "qC" => reply("QC1"),
+                "qfThreadInfo" => reply("m1"),
+                "qsThreadInfo" => reply("l"),
+                "vCont?" => reply("vCont;c;s"),
+                _ if packet.starts_with("vCont;c") => GdbAction::Continue,
+                _ if packet.starts_with("vCont;s") => GdbAction::Step,
+                /* `monitor CMD`, answered with hex-encoded text */
+                _ if packet.starts_with("qRcmd,") => {
+                    let command = parse_bytes(&packet[6..]).map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+                    match command.as_deref().map(str::trim).map(|command| command.split_once(' ').unwrap_or((command, ""))) {
+                        Some(("backtrace" | "bt", _)) => reply(&hex_text(&gba.trace_dump())),
+                        /* By label, since gdb knows nothing of .sym files or banks */
+                        Some(("break", name)) => {
+                            let addr = gba.symbols.as_ref().and_then(|symbols| self.debugger.add_symbol_breakpoint(symbols, name.trim()));
+                            match addr {
+                                Some(addr) => reply(&hex_text(&format!("Breakpoint at ${:04X}\n", addr))),
+                                None => reply(&hex_text(&format!("No symbol `{}`\n", name.trim()))),
+                            }
+                        },
+                        Some(_) => reply(&hex_text("Commands: backtrace, break LABEL\n")),
+                        None => reply("E01"),
+                    }
+                },
+                /* Empty means unsupported */
+                _ => reply(""),
+            },
+        }
+    }
+}
+
+pub fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Locked(_) => format!("S{:02x}", SIGILL),
+        StopReason::Watchpoint { addr, write, .. } => {
+            format!("T{:02x}{}:{:x};", SIGTRAP, if write { "watch" } else { "rwatch" }, addr)
+        },
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte))
+}
+
+/* Registers travel in target byte order */
+fn hex_le(value: u16) -> String {
+    value.to_le_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hex_text(text: &str) -> String {
+    text.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_le(hex: &str) -> Option<u16> {
+    match parse_bytes(hex)?.as_slice() {
+        &[low, high] => Some(u16::from_le_bytes([low, high])),
+        _ => None,
+    }
+}
+
+fn parse_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/* `addr,len` in hex */
+fn parse_range(args: &str) -> Option<(u16, u16)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((u16::from_str_radix(addr, 16).ok()?, u16::from_str_radix(len, 16).ok()?))
+}
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    error::prelude::GbError,
+    gba::prelude::Gba,
+    mem::prelude::{Boot, Cart},
+    ppu::{image, prelude::{Palette, SCREEN_HEIGHT, SCREEN_WIDTH}},
+};
+
+/* Matching pixels in a diff image are dimmed to this fraction */
+const DIM: u8 = 4;
+const CHANGED: [u8; 3] = [0xFF, 0x00, 0x00];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GoldenOutcome {
+    Matched,
+    /* `pixels` differ; the expected, actual and diff panels went to `artifact` */
+    Mismatched { pixels: usize, artifact: PathBuf },
+    /* No golden image existed, or blessing was asked for; one was written */
+    Blessed,
+}
+
+/* Runs a ROM headless for a fixed number of frames and compares the last
+ * one with a reference PNG, such as those shipped with dmg-acid2. The boot
+ * ROM is skipped and the grayscale palette used so the result depends on
+ * the PPU alone. */
+#[derive(Debug, Clone)]
+pub struct GoldenTest {
+    pub frames: u32,
+    pub palette: Palette,
+    /* Where mismatch images go; next to the golden image when unset */
+    pub artifacts: Option<PathBuf>,
+    /* Overwrite the golden image with the current output */
+    pub bless: bool,
+}
+
+impl Default for GoldenTest {
+    fn default() -> Self {
+        Self { frames: 120, palette: Palette::GRAYSCALE, artifacts: None, bless: false }
+    }
+}
+
+impl GoldenTest {
+    /* The last of `frames` frames as RGB8 */
+    pub fn capture(&self, gba: &mut Gba) -> Vec<u8> {
+        for _ in 0..self.frames {
+            gba.run_frame();
+        }
+        gba.mem.ppu.rgb_framebuffer_with(&self.palette)
+    }
+
+    #[cfg(feature = "io")]
+    pub fn check<P: AsRef<Path>, Q: AsRef<Path>>(&self, rom: P, golden: Q) -> Result<GoldenOutcome, GbError> {
+        let cart = Cart::new(rom.as_ref().to_string_lossy().into_owned())?;
+        let actual = self.capture(&mut Gba::with_boot(cart, Boot::Skip));
+        self.compare(&actual, golden.as_ref())
+    }
+
+    /* Compares a captured frame with the golden image at `golden` */
+    #[cfg(feature = "io")]
+    pub fn compare(&self, actual: &[u8], golden: &Path) -> Result<GoldenOutcome, GbError> {
+        if self.bless || !golden.exists() {
+            image::save(golden, SCREEN_WIDTH, SCREEN_HEIGHT, actual)?;
+            return Ok(GoldenOutcome::Blessed);
+        }
+        let expected = match image::load(golden)? {
+            (SCREEN_WIDTH, SCREEN_HEIGHT, rgb) => rgb,
+            (width, height, _) => return Err(GbError::Unsupported(format!("{}x{} golden images", width, height))),
+        };
+        let (pixels, diff) = diff(&expected, actual);
+        if pixels == 0 {
+            return Ok(GoldenOutcome::Matched);
+        }
+
+        let name = golden.file_stem().map_or_else(|| "golden".into(), |stem| stem.to_string_lossy().into_owned());
+        let dir = self.artifacts.clone().or_else(|| golden.parent().map(Path::to_path_buf)).unwrap_or_default();
+        let artifact = dir.join(format!("{}.diff.png", name));
+        let panels = side_by_side(&[&expected, actual, &diff], SCREEN_WIDTH, SCREEN_HEIGHT);
+        image::save(&artifact, SCREEN_WIDTH * 3, SCREEN_HEIGHT, &panels)?;
+        Ok(GoldenOutcome::Mismatched { pixels, artifact })
+    }
+}
+
+/* A cheap fingerprint of a frame, for tests that only need to notice change */
+pub fn frame_hash(rgb: &[u8]) -> u32 {
+    image::crc32(rgb.iter().copied())
+}
+
+/* Differing pixels, and an image with them in red over a dimmed copy of
+ * the expected frame */
+pub fn diff(expected: &[u8], actual: &[u8]) -> (usize, Vec<u8>) {
+    let mut pixels = 0;
+    let image = expected.chunks(3).zip(actual.chunks(3)).flat_map(|(want, got)| {
+        if want == got {
+            [want[0] / DIM, want[1] / DIM, want[2] / DIM]
+        } else {
+            pixels += 1;
+            CHANGED
+        }
+    })
+    .collect();
+    (pixels, image)
+}
+
+fn side_by_side(panels: &[&[u8]], width: usize, height: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(panels.len() * width * height * 3);
+    for y in 0..height {
+        for panel in panels {
+            out.extend_from_slice(&panel[y * width * 3..(y + 1) * width * 3]);
+        }
+    }
+    out
+}
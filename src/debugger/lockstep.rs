@@ -0,0 +1,155 @@
+use std::fmt;
+
+use crate::{
+    cpu::register::types::Register16,
+    gba::prelude::{AccuracyLevel, Gba},
+    input::prelude::InputSource,
+    mem::prelude::{Boot, Cart},
+    ppu::image::crc32,
+    state::prelude::{Savestate, StateReader, StateWriter},
+};
+
+/* Runs two consoles side by side on the same ROM and input, such as the
+ * same cart at two accuracy levels or before and after a change to the
+ * CPU, and finds the first instruction after which they disagree. Every
+ * `interval` instructions both are compared by registers and a hash of
+ * the address space; on a mismatch they are rewound to the last check and
+ * stepped one instruction at a time to pin it down. */
+
+pub const DEFAULT_INTERVAL: u64 = 1000;
+
+const REGISTERS: [Register16; 6] = [Register16::AF, Register16::BC, Register16::DE, Register16::HL, Register16::SP, Register16::PC];
+
+/* What two consoles are compared by */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /* AF, BC, DE, HL, SP and PC */
+    pub registers: [u16; 6],
+    pub ime: u8,
+    /* CRC-32 of $0000-$FFFF as the CPU sees it */
+    pub memory_hash: u32,
+}
+
+impl Snapshot {
+    pub fn of(gba: &Gba) -> Self {
+        Self {
+            registers: REGISTERS.map(|register| gba.cpu.registers.get_r16(register)),
+            ime: gba.cpu.ime,
+            memory_hash: crc32((0..=0xFFFF).map(|addr| gba.mem.peek(addr))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /* Instructions each console had run when they first differed */
+    pub instruction: u64,
+    pub a: Snapshot,
+    pub b: Snapshot,
+    /* The lowest address holding different bytes, if memory differs */
+    pub address: Option<u16>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Diverged after instruction {}", self.instruction)?;
+        for (name, snapshot) in [("A", &self.a), ("B", &self.b)] {
+            let [af, bc, de, hl, sp, pc] = snapshot.registers;
+            writeln!(f, "  {}: AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} IME={} memory {:08x}",
+                name, af, bc, de, hl, sp, pc, snapshot.ime, snapshot.memory_hash)?;
+        }
+        match self.address {
+            Some(addr) => writeln!(f, "  First differing byte at ${:04X}", addr),
+            None => writeln!(f, "  Memory matches"),
+        }
+    }
+}
+
+pub struct Lockstep {
+    pub a: Gba,
+    pub b: Gba,
+    /* Instructions between comparisons; each costs a pass over memory */
+    pub interval: u64,
+    instructions: u64,
+}
+
+impl Lockstep {
+    pub fn new(a: Gba, b: Gba) -> Self {
+        Self { a, b, interval: DEFAULT_INTERVAL, instructions: 0 }
+    }
+
+    /* The same cart at two accuracy levels */
+    pub fn accuracy(cart: Cart, boot: Boot, a: AccuracyLevel, b: AccuracyLevel) -> Self {
+        let [a, b] = [a, b].map(|accuracy| {
+            let mut gba = Gba::with_boot(cart.clone(), boot.clone());
+            gba.set_accuracy(accuracy);
+            gba
+        });
+        Self::new(a, b)
+    }
+
+    /* Instructions both consoles have run in step */
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /* Runs up to `instructions` more, stopping at the first divergence.
+     * `input` is polled once per comparison, numbered from 0, and both
+     * consoles get the same buttons at the same instruction. */
+    pub fn run(&mut self, instructions: u64, input: &mut dyn InputSource) -> Option<Divergence> {
+        let end = self.instructions + instructions;
+        while self.instructions < end {
+            let pressed = input.poll(self.instructions / self.interval.max(1)).0;
+            self.a.mem.set_pressed(pressed);
+            self.b.mem.set_pressed(pressed);
+            let saved = (save(&self.a), save(&self.b));
+            let count = self.interval.max(1).min(end - self.instructions);
+            for _ in 0..count {
+                self.step();
+            }
+            if Snapshot::of(&self.a) == Snapshot::of(&self.b) {
+                self.instructions += count;
+                continue;
+            }
+
+            /* Back to the last match and one instruction at a time */
+            let restored = load(&mut self.a, &saved.0) && load(&mut self.b, &saved.1);
+            let mut ran = match restored {
+                true => 0,
+                false => count,
+            };
+            while ran < count {
+                self.step();
+                ran += 1;
+                if Snapshot::of(&self.a) != Snapshot::of(&self.b) {
+                    break;
+                }
+            }
+            self.instructions += ran;
+            return Some(self.divergence());
+        }
+        None
+    }
+
+    fn step(&mut self) {
+        self.a.step();
+        self.b.step();
+    }
+
+    fn divergence(&self) -> Divergence {
+        let address = (0..=0xFFFF_u16).find(|&addr| self.a.mem.peek(addr) != self.b.mem.peek(addr));
+        Divergence { instruction: self.instructions, a: Snapshot::of(&self.a), b: Snapshot::of(&self.b), address }
+    }
+}
+
+fn save(gba: &Gba) -> Vec<u8> {
+    let mut w = StateWriter::new();
+    gba.cpu.save_state(&mut w);
+    gba.mem.save_state(&mut w);
+    w.into_inner()
+}
+
+fn load(gba: &mut Gba, state: &[u8]) -> bool {
+    let mut r = StateReader::new(state);
+    gba.cpu.load_state(&mut r).is_ok() && gba.mem.load_state(&mut r).is_ok()
+}
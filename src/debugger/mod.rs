@@ -0,0 +1,39 @@
+#![allow(unused)]
+
+mod asm;
+#[cfg(feature = "io")]
+mod batch;
+mod cdl;
+mod control;
+mod disasm;
+mod expr;
+#[cfg(feature = "io")]
+mod gdb;
+mod golden;
+mod lockstep;
+mod profiler;
+mod repl;
+mod search;
+mod symbols;
+mod testrom;
+mod trace;
+
+pub mod prelude {
+    pub use super::asm::{assemble, AsmError};
+    #[cfg(feature = "io")]
+    pub use super::batch::{batch_summary, expand_roms, BatchResult, BatchRunner, BatchStatus, DEFAULT_BATCH_FRAMES};
+    pub use super::cdl::CodeDataLog;
+    pub use super::disasm::disassemble;
+    pub use super::control::{Breakpoint, Debugger, StopReason, Watch, WatchKind, Watchpoint};
+    pub use super::expr::{BinaryOp, Expr, ExprError, UnaryOp};
+    #[cfg(feature = "io")]
+    pub use super::gdb::{GdbAction, GdbStub};
+    pub use super::golden::{diff as diff_frames, frame_hash, GoldenOutcome, GoldenTest};
+    pub use super::lockstep::{Divergence, Lockstep, Snapshot, DEFAULT_INTERVAL as DEFAULT_LOCKSTEP_INTERVAL};
+    pub use super::profiler::{Cost, Location, Profiler};
+    pub use super::repl::{Repl, ReplAction};
+    pub use super::search::{Candidate, CheatSearch, Comparison, SearchRegion, ValueSize};
+    pub use super::symbols::{location_of, Symbols};
+    pub use super::testrom::{summary as test_summary, TestRomResult, TestRomRunner, Verdict};
+    pub use super::trace::{instruction_len, TraceEntry, TraceRing, DEFAULT_TRACE_LEN};
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::{self, Write},
+};
+
+/* Where an instruction was fetched from, with the ROM bank mapped there at
+ * the time; code running from RAM has no bank */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    pub bank: Option<usize>,
+    pub pc: u16,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{:02X}:{:04X}", bank, self.pc),
+            None => write!(f, "--:{:04X}", self.pc),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Cost {
+    pub cycles: u64,
+    pub instructions: u64,
+}
+
+impl Cost {
+    fn add(&mut self, other: Cost) {
+        self.cycles += other.cycles;
+        self.instructions += other.instructions;
+    }
+}
+
+/* Executed M-cycles per instruction address, collected by `Gba::step`
+ * while one is installed in `Gba::profiler` */
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    costs: HashMap<Location, Cost>,
+    /* Halted, stopped or locked up: owed to no instruction */
+    idle: u64,
+}
+
+impl Profiler {
+    pub fn record(&mut self, location: Location, cycles: usize) {
+        self.costs.entry(location).or_default().add(Cost { cycles: cycles as u64, instructions: 1 });
+    }
+
+    pub fn record_idle(&mut self, cycles: usize) {
+        self.idle += cycles as u64;
+    }
+
+    pub fn cost(&self, location: Location) -> Cost {
+        self.costs.get(&location).copied().unwrap_or_default()
+    }
+
+    pub fn idle(&self) -> u64 {
+        self.idle
+    }
+
+    /* Every M-cycle seen, idle included */
+    pub fn total(&self) -> u64 {
+        self.costs.values().map(|cost| cost.cycles).sum::<u64>() + self.idle
+    }
+
+    /* Most cycles first, ties in address order */
+    pub fn hotspots(&self) -> Vec<(Location, Cost)> {
+        let mut hotspots: Vec<_> = self.costs.iter().map(|(&location, &cost)| (location, cost)).collect();
+        hotspots.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        hotspots
+    }
+
+    /* The same, summed per ROM bank; None collects code run from RAM */
+    pub fn banks(&self) -> Vec<(Option<usize>, Cost)> {
+        let mut banks: BTreeMap<Option<usize>, Cost> = BTreeMap::new();
+        for (location, &cost) in &self.costs {
+            banks.entry(location.bank).or_default().add(cost);
+        }
+        let mut banks: Vec<_> = banks.into_iter().collect();
+        banks.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        banks
+    }
+
+    pub fn clear(&mut self) {
+        self.costs.clear();
+        self.idle = 0;
+    }
+
+    /* Per-bank totals, then the `limit` hottest addresses */
+    pub fn report(&self, limit: usize) -> String {
+        let total = self.total();
+        let percent = |cycles: u64| if total == 0 { 0.0 } else { cycles as f64 * 100.0 / total as f64 };
+        let mut out = format!("{} M-cycles, {} idle ({:.1}%)\n\n", total, self.idle, percent(self.idle));
+
+        out.push_str("Bank        Cycles       %\n");
+        for (bank, cost) in self.banks() {
+            let bank = bank.map_or_else(|| "RAM".to_string(), |bank| format!("{:02X}", bank));
+            out.push_str(&format!("{:<4} {:>13} {:>7.2}\n", bank, cost.cycles, percent(cost.cycles)));
+        }
+
+        out.push_str("\nAddress     Cycles       %   Executed\n");
+        for (location, cost) in self.hotspots().into_iter().take(limit) {
+            out.push_str(&format!("{} {:>10} {:>7.2} {:>10}\n", location, cost.cycles, percent(cost.cycles), cost.instructions));
+        }
+        out
+    }
+
+    /* Flat profile in callgrind format, one function per ROM bank, for
+     * KCachegrind and callgrind_annotate */
+    pub fn write_callgrind<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let mut costs: Vec<_> = self.costs.iter().map(|(&location, &cost)| (location, cost)).collect();
+        costs.sort_by_key(|&(location, _)| location);
+        let instructions: u64 = costs.iter().map(|(_, cost)| cost.instructions).sum();
+
+        writeln!(w, "# callgrind format")?;
+        writeln!(w, "version: 1")?;
+        writeln!(w, "creator: gbemu")?;
+        writeln!(w, "positions: instr")?;
+        writeln!(w, "events: Cycles Instructions")?;
+        writeln!(w, "summary: {} {}", self.total(), instructions)?;
+        writeln!(w)?;
+        writeln!(w, "ob=rom")?;
+
+        let mut function = None;
+        for (location, cost) in costs {
+            if function != Some(location.bank) {
+                function = Some(location.bank);
+                match location.bank {
+                    Some(bank) => writeln!(w, "fn=bank_{:02X}", bank)?,
+                    None => writeln!(w, "fn=ram")?,
+                }
+            }
+            writeln!(w, "0x{:04X} {} {}", location.pc, cost.cycles, cost.instructions)?;
+        }
+        if self.idle > 0 {
+            writeln!(w, "fn=idle")?;
+            writeln!(w, "0x0000 {} 0", self.idle)?;
+        }
+        Ok(())
+    }
+}
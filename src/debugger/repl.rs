@@ -0,0 +1,268 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    cpu::register::types::{Flags, Register16},
+    gba::prelude::Gba,
+};
+
+use super::{
+    control::{Breakpoint, Debugger, StopReason, WatchKind, Watchpoint},
+    expr::Expr,
+    profiler::Location,
+    trace::instruction_len,
+};
+
+/* How long `continue` runs with nothing hit: a minute of frames */
+const CONTINUE_CYCLES: usize = 60 * 60 * 17556;
+
+const HELP: &str = "\
+step [N]                   Run N instructions [default: 1]           (s)
+continue                   Run until a breakpoint or watchpoint      (c)
+break [ADDR|LABEL [if COND]]
+                           Stop before ADDR, or list breakpoints     (b)
+delete [ADDR]              Remove a breakpoint, or all of them       (d)
+x ADDR [LEN]               Hex dump LEN bytes [default: 64]
+regs                       Registers, flags and CPU state            (r)
+disasm [ADDR] [N]          N instructions from ADDR [default: PC, 8] (dis)
+watch EXPR                 Show EXPR at every stop                   (w)
+watch read|write|access ADDR[..END]
+                           Stop after the CPU touches memory
+unwatch N                  Remove watch N
+print EXPR                 Evaluate once                             (p)
+set TARGET = VALUE         Assign a register, flag or [ADDR]
+quit                       (q)
+Addresses and values are expressions: $C000, HL+2, [wLives], Main.
+An empty line repeats the last command.";
+
+/* What the session loop should do after a line */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplAction {
+    Print(String),
+    Quit,
+}
+
+/* A line-oriented debugger on top of `Debugger`, for a terminal before
+ * there is any GUI: `gba debug ROM` reads commands from stdin and answers
+ * on stdout. */
+#[derive(Debug, Default)]
+pub struct Repl {
+    pub debugger: Debugger,
+    last: String,
+}
+
+impl Repl {
+    /* Prompts on `output` and answers each line of `input` until `quit`
+     * or the end of input */
+    pub fn run(&mut self, gba: &mut Gba, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        writeln!(output, "{}", here(gba))?;
+        write!(output, "(gba) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            match self.handle(gba, &line?) {
+                ReplAction::Print(text) if text.is_empty() => {},
+                ReplAction::Print(text) => writeln!(output, "{}", text)?,
+                ReplAction::Quit => return Ok(()),
+            }
+            write!(output, "(gba) ")?;
+            output.flush()?;
+        }
+        writeln!(output)
+    }
+
+    /* Answers one line without touching the terminal */
+    pub fn handle(&mut self, gba: &mut Gba, line: &str) -> ReplAction {
+        let line = match line.trim() {
+            "" => self.last.clone(),
+            line => line.to_string(),
+        };
+        self.last = line.clone();
+        let (command, args) = line.split_once(char::is_whitespace).unwrap_or((&line, ""));
+        let args = args.trim();
+        let result = match command {
+            "s" | "step" => self.step(gba, args),
+            "c" | "continue" => {
+                let reason = self.debugger.resume(gba, CONTINUE_CYCLES);
+                Ok(self.stopped(gba, reason))
+            },
+            "b" | "break" => self.set_breakpoint(gba, args),
+            "d" | "delete" => self.delete(gba, args),
+            "x" => self.dump(gba, args),
+            "r" | "regs" => Ok(registers(gba)),
+            "dis" | "disasm" => self.disassemble(gba, args),
+            "w" | "watch" => self.watch(gba, args),
+            "unwatch" => self.unwatch(gba, args),
+            "p" | "print" => self.value(gba, args).map(|value| format!("{} (${:X})", value, value)),
+            "set" => self.assign(gba, args),
+            "h" | "help" | "?" => Ok(HELP.to_string()),
+            "q" | "quit" => return ReplAction::Quit,
+            "" => Ok(String::new()),
+            _ => Err(format!("unknown command `{}`; try `help`", command)),
+        };
+        ReplAction::Print(result.unwrap_or_else(|e| e))
+    }
+
+    fn step(&mut self, gba: &mut Gba, args: &str) -> Result<String, String> {
+        let count = match args {
+            "" => 1,
+            count => self.value(gba, count)?.max(1),
+        };
+        let mut reason = StopReason::Step;
+        for _ in 0..count {
+            reason = self.debugger.step(gba);
+            if reason != StopReason::Step {
+                break;
+            }
+        }
+        Ok(self.stopped(gba, reason))
+    }
+
+    /* Why it stopped, the watches, and where it is now */
+    fn stopped(&self, gba: &mut Gba, reason: StopReason) -> String {
+        let mut out = match reason {
+            StopReason::Step => String::new(),
+            StopReason::Breakpoint(addr) => format!("Breakpoint at ${:04X}\n", addr),
+            StopReason::Watchpoint { addr, value, write: true } => format!("Wrote ${:02X} to ${:04X}\n", value, addr),
+            StopReason::Watchpoint { addr, value, write: false } => format!("Read ${:02X} from ${:04X}\n", value, addr),
+            StopReason::Locked(opcode) => format!("Locked up on illegal opcode ${:02X}\n", opcode),
+            StopReason::Timeout => format!("Nothing hit after {} M-cycles\n", CONTINUE_CYCLES),
+        };
+        for (index, watch) in self.debugger.watches().iter().enumerate() {
+            let changed = if watch.changed { " (changed)" } else { "" };
+            out.push_str(&format!("{}: {} = {} (${:X}){}\n", index, watch.text, watch.value, watch.value, changed));
+        }
+        out + &here(gba)
+    }
+
+    fn set_breakpoint(&mut self, gba: &mut Gba, args: &str) -> Result<String, String> {
+        if args.is_empty() {
+            let lines: Vec<String> = self.debugger.breakpoints().map(|addr| {
+                let breakpoint = self.debugger.breakpoint(addr).expect("listed");
+                format!("${:04X} {} hits", addr, breakpoint.hits)
+            }).collect();
+            return Ok(if lines.is_empty() { "No breakpoints".to_string() } else { lines.join("\n") });
+        }
+        let (target, condition) = match args.split_once(" if ") {
+            Some((target, condition)) => (target.trim(), Some(Expr::parse(condition).map_err(|e| e.to_string())?)),
+            None => (args, None),
+        };
+        let by_label = gba.symbols.as_ref().and_then(|symbols| self.debugger.add_symbol_breakpoint(symbols, target));
+        let addr = match by_label {
+            Some(addr) => addr,
+            None => {
+                let addr = self.value(gba, target)? as u16;
+                self.debugger.add_breakpoint(addr);
+                addr
+            },
+        };
+        if let Some(condition) = condition {
+            let breakpoint = self.debugger.breakpoint(addr).cloned().unwrap_or_default();
+            self.debugger.set_breakpoint(addr, Breakpoint { condition: Some(condition), ..breakpoint });
+        }
+        Ok(format!("Breakpoint at ${:04X}", addr))
+    }
+
+    fn delete(&mut self, gba: &mut Gba, args: &str) -> Result<String, String> {
+        if args.is_empty() {
+            self.debugger.clear_breakpoints();
+            return Ok("Deleted every breakpoint".to_string());
+        }
+        let addr = self.value(gba, args)? as u16;
+        match self.debugger.remove_breakpoint(addr) {
+            true => Ok(String::new()),
+            false => Err(format!("No breakpoint at ${:04X}", addr)),
+        }
+    }
+
+    /* Sixteen bytes a line, read without side effects */
+    fn dump(&mut self, gba: &mut Gba, args: &str) -> Result<String, String> {
+        let mut args = args.split_whitespace();
+        let addr = self.value(gba, args.next().ok_or("usage: x ADDR [LEN]")?)? as u16;
+        let len = args.next().map(|len| self.value(gba, len)).transpose()?.unwrap_or(64).clamp(1, 0x10000) as usize;
+        gba.mem.sync();
+        let lines: Vec<String> = (0..len).step_by(16).map(|row| {
+            let start = addr.wrapping_add(row as u16);
+            let bytes: Vec<String> = (0..(len - row).min(16)).map(|i| format!("{:02X}", gba.mem.peek(start.wrapping_add(i as u16)))).collect();
+            format!("{:04X}: {}", start, bytes.join(" "))
+        }).collect();
+        Ok(lines.join("\n"))
+    }
+
+    fn disassemble(&mut self, gba: &mut Gba, args: &str) -> Result<String, String> {
+        let mut args = args.split_whitespace();
+        let mut addr = match args.next() {
+            Some(addr) => self.value(gba, addr)? as u16,
+            None => gba.cpu.registers.pc,
+        };
+        let count = args.next().map(|count| self.value(gba, count)).transpose()?.unwrap_or(8);
+        gba.mem.sync();
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            lines.push(format!("{:04X}: {}", addr, gba.disassemble_at(addr)));
+            addr = addr.wrapping_add(instruction_len(gba.mem.peek(addr)) as u16);
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn watch(&mut self, gba: &mut Gba, args: &str) -> Result<String, String> {
+        let (kind, range) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let kind = match kind {
+            "read" => WatchKind::Read,
+            "write" => WatchKind::Write,
+            "access" => WatchKind::Access,
+            _ if args.is_empty() => return Ok(self.stopped(gba, StopReason::Step)),
+            _ => {
+                let index = self.debugger.add_watch(gba, args).map_err(|e| e.to_string())?;
+                let watch = &self.debugger.watches()[index];
+                return Ok(format!("{}: {} = {} (${:X})", index, watch.text, watch.value, watch.value));
+            },
+        };
+        let (start, end) = range.split_once("..").unwrap_or((range, range));
+        let start = self.value(gba, start)? as u16;
+        let end = self.value(gba, end)? as u16;
+        let id = self.debugger.add_watchpoint(&mut gba.mem, Watchpoint::new(start..=end, kind));
+        Ok(format!("Watchpoint {} on ${:04X}..${:04X}", id, start, end))
+    }
+
+    fn assign(&mut self, gba: &mut Gba, args: &str) -> Result<String, String> {
+        let (target, value) = args.split_once('=').ok_or("usage: set TARGET = VALUE")?;
+        let value = self.value(gba, value)?;
+        self.debugger.assign(gba, target.trim(), value).map_err(|e| e.to_string())?;
+        Ok(String::new())
+    }
+
+    fn unwatch(&mut self, gba: &mut Gba, args: &str) -> Result<String, String> {
+        let index = self.value(gba, args)? as usize;
+        match self.debugger.remove_watch(index) {
+            Some(watch) => Ok(format!("Removed {}", watch.text)),
+            None => Err(format!("No watch {}", index)),
+        }
+    }
+
+    fn value(&self, gba: &mut Gba, text: &str) -> Result<i64, String> {
+        gba.mem.sync();
+        self.debugger.evaluate(gba, text.trim()).map_err(|e| e.to_string())
+    }
+}
+
+fn registers(gba: &Gba) -> String {
+    let (cpu, r16) = (&gba.cpu, |register| gba.cpu.registers.get_r16(register));
+    let flags: String = [(Flags::Zero, 'Z'), (Flags::Subtract, 'N'), (Flags::HalfCarry, 'H'), (Flags::Carry, 'C')]
+        .iter()
+        .map(|&(flag, name)| if cpu.registers.f.is_set(flag) { name } else { '-' })
+        .collect();
+    format!(
+        "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} {} IME={} {:?}",
+        r16(Register16::AF), r16(Register16::BC), r16(Register16::DE), r16(Register16::HL),
+        cpu.registers.sp, cpu.registers.pc, flags, cpu.ime, cpu.state,
+    )
+}
+
+/* The next instruction, under its label when there is one */
+fn here(gba: &mut Gba) -> String {
+    gba.mem.sync();
+    let pc = gba.cpu.registers.pc;
+    let location = Location { bank: gba.mem.rom_bank_at(pc), pc };
+    let label = gba.symbols.as_ref().and_then(|symbols| symbols.describe(location));
+    let label = label.map_or(String::new(), |label| format!(" <{}>", label));
+    format!("{:04X}{}: {}", pc, label, gba.disassemble_at(pc))
+}
@@ -0,0 +1,161 @@
+use crate::mem::prelude::{Mem, Region};
+
+const CART_RAM_BANK: usize = Region::CartRam.size();
+const WRAM_BANK: usize = Region::WramX.size();
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SearchRegion {
+    Wram,
+    CartRam,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ValueSize {
+    #[default]
+    Byte,
+    /* Little endian, as the CPU's 16-bit loads and stores see it */
+    Word,
+}
+
+impl ValueSize {
+    fn len(&self) -> usize {
+        match self {
+            Self::Byte => 1,
+            Self::Word => 2,
+        }
+    }
+}
+
+/* How a value must have moved since the previous snapshot to stay a candidate */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    Equals(u16),
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+    /* new - old, without wrapping */
+    Delta(i32),
+}
+
+impl Comparison {
+    fn matches(&self, old: u16, new: u16) -> bool {
+        match *self {
+            Self::Equals(value) => new == value,
+            Self::Unchanged => new == old,
+            Self::Changed => new != old,
+            Self::Increased => new > old,
+            Self::Decreased => new < old,
+            Self::Delta(delta) => new as i32 - old as i32 == delta,
+        }
+    }
+}
+
+/* A value's position; cart RAM offsets span every bank */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Candidate {
+    pub region: SearchRegion,
+    pub offset: usize,
+}
+
+impl Candidate {
+    /* Where the CPU sees it once `bank` is mapped */
+    pub fn address(&self) -> u16 {
+        match self.region {
+            SearchRegion::Wram if self.offset < WRAM_BANK => Region::Wram0.base().0 + self.offset as u16,
+            SearchRegion::Wram => Region::WramX.base().0 + (self.offset % WRAM_BANK) as u16,
+            SearchRegion::CartRam => Region::CartRam.base().0 + (self.offset % CART_RAM_BANK) as u16,
+        }
+    }
+
+    pub fn bank(&self) -> usize {
+        match self.region {
+            SearchRegion::Wram => self.offset / WRAM_BANK,
+            SearchRegion::CartRam => self.offset / CART_RAM_BANK,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Snapshot {
+    wram: Vec<u8>,
+    cart_ram: Vec<u8>,
+}
+
+impl Snapshot {
+    fn take(mem: &Mem) -> Self {
+        Self { wram: mem.wram().to_vec(), cart_ram: mem.cart_ram().to_vec() }
+    }
+
+    fn region(&self, region: SearchRegion) -> &[u8] {
+        match region {
+            SearchRegion::Wram => &self.wram,
+            SearchRegion::CartRam => &self.cart_ram,
+        }
+    }
+
+    fn value(&self, candidate: Candidate, size: ValueSize) -> u16 {
+        let bytes = self.region(candidate.region);
+        match size {
+            ValueSize::Byte => bytes[candidate.offset] as u16,
+            ValueSize::Word => u16::from_le_bytes([bytes[candidate.offset], bytes[candidate.offset + 1]]),
+        }
+    }
+}
+
+/* Narrows RAM down to the addresses holding some value by comparing
+ * successive snapshots, the backend of a cheat finder. Only raw RAM is
+ * looked at, so the search never disturbs the running game. */
+#[derive(Debug, Clone)]
+pub struct CheatSearch {
+    size: ValueSize,
+    snapshot: Snapshot,
+    candidates: Vec<Candidate>,
+}
+
+impl CheatSearch {
+    /* Every value in WRAM and cart RAM starts out a candidate */
+    pub fn start(mem: &Mem, size: ValueSize) -> Self {
+        let snapshot = Snapshot::take(mem);
+        let mut candidates = Vec::new();
+        for region in [SearchRegion::Wram, SearchRegion::CartRam] {
+            let len = snapshot.region(region).len();
+            candidates.extend((0..len.saturating_sub(size.len() - 1))
+                .map(|offset| Candidate { region, offset })
+                /* A word split across two switched banks is never read as one */
+                .filter(|c| match region {
+                    SearchRegion::Wram => c.offset < WRAM_BANK || c.offset % WRAM_BANK + size.len() <= WRAM_BANK,
+                    SearchRegion::CartRam => c.offset % CART_RAM_BANK + size.len() <= CART_RAM_BANK,
+                }));
+        }
+        Self { size, snapshot, candidates }
+    }
+
+    /* Snapshots RAM again, keeping the candidates whose change since the
+     * last snapshot satisfies `comparison` */
+    pub fn filter(&mut self, mem: &Mem, comparison: Comparison) -> &[Candidate] {
+        let next = Snapshot::take(mem);
+        let (size, previous) = (self.size, &self.snapshot);
+        self.candidates.retain(|&c| comparison.matches(previous.value(c, size), next.value(c, size)));
+        self.snapshot = next;
+        &self.candidates
+    }
+
+    /* Takes a fresh baseline without narrowing, e.g. after a reset */
+    pub fn snapshot(&mut self, mem: &Mem) {
+        self.snapshot = Snapshot::take(mem);
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    /* A candidate's value as of the last snapshot */
+    pub fn value(&self, candidate: Candidate) -> u16 {
+        self.snapshot.value(candidate, self.size)
+    }
+
+    pub fn size(&self) -> ValueSize {
+        self.size
+    }
+}
@@ -0,0 +1,96 @@
+use std::{collections::{BTreeMap, HashMap}, path::Path};
+
+use crate::error::prelude::GbError;
+
+use super::profiler::Location;
+
+/* Labels from an RGBDS .sym file, one `BB:AAAA Name` per line. Banks only
+ * matter for ROM; RAM labels match whatever bank is mapped. */
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    by_name: HashMap<String, Location>,
+    by_location: BTreeMap<Location, String>,
+}
+
+impl Symbols {
+    /* Lines that are not `bank:address name` are skipped, as other
+     * debuggers do, so comments and tool-specific extras load fine */
+    pub fn parse(text: &str) -> Self {
+        let mut symbols = Self::default();
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            let Some((location, name)) = line.split_once(char::is_whitespace) else { continue };
+            let Some((bank, addr)) = location.split_once(':') else { continue };
+            if let (Ok(bank), Ok(addr)) = (usize::from_str_radix(bank, 16), u16::from_str_radix(addr, 16)) {
+                symbols.insert(location_of(bank, addr), name.trim());
+            }
+        }
+        symbols
+    }
+
+    #[cfg(feature = "io")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, GbError> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /* The first label given to a location is the one it is shown as */
+    pub fn insert(&mut self, location: Location, name: &str) {
+        self.by_name.insert(name.to_string(), location);
+        self.by_location.entry(location).or_insert_with(|| name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.by_name.keys().map(String::as_str)
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<Location> {
+        self.by_name.get(name).copied()
+    }
+
+    /* The label exactly at `location` */
+    pub fn name(&self, location: Location) -> Option<&str> {
+        self.by_location.get(&normalize(location)).map(String::as_str)
+    }
+
+    /* `Label` or `Label+$N` from the nearest label at or before `location`
+     * in the same bank and memory region */
+    pub fn describe(&self, location: Location) -> Option<String> {
+        let location = normalize(location);
+        let (found, name) = self.by_location.range(..=location).next_back()?;
+        if found.bank != location.bank || region(found.pc) != region(location.pc) {
+            return None;
+        }
+        match location.pc - found.pc {
+            0 => Some(name.clone()),
+            offset => Some(format!("{}+${:X}", name, offset)),
+        }
+    }
+}
+
+/* Where a .sym entry lives: ROM keeps its bank, RAM and I/O do not */
+pub fn location_of(bank: usize, addr: u16) -> Location {
+    Location { bank: (addr < 0x8000).then_some(bank), pc: addr }
+}
+
+fn normalize(location: Location) -> Location {
+    match location.pc {
+        0x8000.. => Location { bank: None, ..location },
+        _ => location,
+    }
+}
+
+/* ROM0, ROMX, then fixed 4kB areas above, so an offset never spans two */
+fn region(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x7FFF => addr >> 14,
+        _ => addr >> 12,
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    cpu::prelude::Register8,
+    gba::prelude::{panic_message, Gba},
+    link::prelude::{LinkCable, LinkMessage},
+    mem::prelude::{Boot, Cart},
+};
+
+/* Mooneye ends every test on `LD B, B` with Fibonacci numbers in B, C, D,
+ * E, H and L for a pass, or $42 in all of them for a failure */
+const LD_B_B: u8 = 0x40;
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: u8 = 0x42;
+
+/* Blargg's ROMs with cart RAM keep a status byte at $A000, this signature
+ * after it and the text they print from $A004 */
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_RUNNING: u8 = 0x80;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Passed,
+    Failed,
+    /* No result within the frame budget */
+    TimedOut,
+    /* The core panicked; the message is kept */
+    Crashed(String),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Passed => write!(f, "pass"),
+            Verdict::Failed => write!(f, "FAIL"),
+            Verdict::TimedOut => write!(f, "TIMEOUT"),
+            Verdict::Crashed(message) => write!(f, "CRASH: {}", message),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestRomResult {
+    pub name: String,
+    pub verdict: Verdict,
+    pub frames: u32,
+    /* What the ROM printed over serial or into cart RAM */
+    pub output: String,
+}
+
+/* Bytes shifted out with nothing on the other end, which is how blargg's
+ * ROMs print */
+#[derive(Debug, Clone, Default)]
+pub(super) struct SerialCapture(pub(super) Rc<RefCell<Vec<u8>>>);
+
+impl LinkCable for SerialCapture {
+    fn send(&mut self, message: LinkMessage) {
+        if let LinkMessage::Clock(byte) = message {
+            self.0.borrow_mut().push(byte);
+        }
+    }
+
+    fn poll(&mut self) -> Option<LinkMessage> {
+        None
+    }
+
+    fn connected(&self) -> bool {
+        false
+    }
+}
+
+/* Runs test ROMs headless until they report a result: blargg's through
+ * serial text or the $A000 signature, mooneye's through registers */
+#[derive(Debug, Copy, Clone)]
+pub struct TestRomRunner {
+    pub max_frames: u32,
+}
+
+impl Default for TestRomRunner {
+    /* Two emulated minutes; the slowest blargg suite needs under one */
+    fn default() -> Self {
+        Self { max_frames: 60 * 120 }
+    }
+}
+
+impl TestRomRunner {
+    #[cfg(feature = "io")]
+    pub fn run_path<P: AsRef<Path>>(&self, path: P) -> TestRomResult {
+        let path = path.as_ref();
+        let name = path.file_stem().map_or_else(|| path.display().to_string(), |stem| stem.to_string_lossy().into_owned());
+        match Cart::new(path.to_string_lossy().into_owned()) {
+            Ok(cart) => self.run(&name, &mut Gba::with_boot(cart, Boot::Skip)),
+            Err(e) => TestRomResult { name, verdict: Verdict::Crashed(e.to_string()), frames: 0, output: String::new() },
+        }
+    }
+
+    /* Every .gb and .gbc under `dir`, in path order */
+    #[cfg(feature = "io")]
+    pub fn run_dir<P: AsRef<Path>>(&self, dir: P) -> Vec<TestRomResult> {
+        let mut roms = Vec::new();
+        collect_roms(dir.as_ref(), &mut roms);
+        roms.sort();
+        roms.iter().map(|rom| self.run_path(rom)).collect()
+    }
+
+    /* Runs `gba` from where it stands. Its link cable is replaced to
+     * capture serial output. */
+    pub fn run(&self, name: &str, gba: &mut Gba) -> TestRomResult {
+        let serial = SerialCapture::default();
+        gba.mem.serial.connect(Box::new(serial.clone()));
+
+        let mut frames = 0;
+        let verdict = panic::catch_unwind(AssertUnwindSafe(|| {
+            while frames < self.max_frames {
+                let mut mooneye = None;
+                gba.run_frame_with(|gba| {
+                    if mooneye.is_none() && gba.mem.peek(gba.cpu.registers.pc) == LD_B_B {
+                        mooneye = mooneye_verdict(gba);
+                    }
+                });
+                frames += 1;
+                if let Some(verdict) = mooneye.or_else(|| blargg_verdict(gba, &serial.0.borrow())) {
+                    return verdict;
+                }
+            }
+            Verdict::TimedOut
+        }))
+        .unwrap_or_else(|payload| Verdict::Crashed(panic_message(payload.as_ref())));
+
+        let output = match blargg_text(gba) {
+            Some(text) => text,
+            None => String::from_utf8_lossy(&serial.0.borrow()).into_owned(),
+        };
+        TestRomResult { name: name.to_string(), verdict, frames, output }
+    }
+}
+
+/* One line per ROM, then the totals */
+pub fn summary(results: &[TestRomResult]) -> String {
+    let width = results.iter().map(|result| result.name.len()).max().unwrap_or(0).max(3);
+    let mut out = format!("{:<width$}  {:>6}  Result\n", "ROM", "Frames");
+    for result in results {
+        out.push_str(&format!("{:<width$}  {:>6}  {}\n", result.name, result.frames, result.verdict));
+    }
+    let passed = results.iter().filter(|result| result.verdict == Verdict::Passed).count();
+    out.push_str(&format!("\n{} of {} passed\n", passed, results.len()));
+    out
+}
+
+#[cfg(feature = "io")]
+pub(super) fn collect_roms(dir: &Path, roms: &mut Vec<std::path::PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            collect_roms(&path, roms);
+        } else if path.extension().is_some_and(|ext| ext == "gb" || ext == "gbc") {
+            roms.push(path);
+        }
+    }
+}
+
+fn mooneye_verdict(gba: &Gba) -> Option<Verdict> {
+    use Register8::*;
+    let registers = [B, C, D, E, H, L].map(|register| gba.cpu.registers.get_r8(register));
+    if registers == MOONEYE_PASS {
+        Some(Verdict::Passed)
+    } else if registers.iter().all(|&value| value == MOONEYE_FAIL) {
+        Some(Verdict::Failed)
+    } else {
+        None
+    }
+}
+
+fn blargg_verdict(gba: &Gba, serial: &[u8]) -> Option<Verdict> {
+    if gba.mem.dump_region(0xA001..=0xA003) == BLARGG_SIGNATURE {
+        return match gba.mem.peek(0xA000) {
+            BLARGG_RUNNING => None,
+            0x00 => Some(Verdict::Passed),
+            _ => Some(Verdict::Failed),
+        };
+    }
+    let text = String::from_utf8_lossy(serial);
+    if text.contains("Passed") {
+        Some(Verdict::Passed)
+    } else if text.contains("Failed") {
+        Some(Verdict::Failed)
+    } else {
+        None
+    }
+}
+
+fn blargg_text(gba: &Gba) -> Option<String> {
+    if gba.mem.dump_region(0xA001..=0xA003) != BLARGG_SIGNATURE {
+        return None;
+    }
+    let text: Vec<u8> = (0xA004..=0xBFFF).map(|addr| gba.mem.peek(addr)).take_while(|&byte| byte != 0).collect();
+    Some(String::from_utf8_lossy(&text).into_owned())
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+
+use super::{disasm::disassemble, profiler::Location};
+
+/* Enough to see how the CPU got somewhere without costing much per step */
+pub const DEFAULT_TRACE_LEN: usize = 256;
+
+/* Bytes an instruction takes, opcode included; CB-prefixed ones are all 2 */
+pub const fn instruction_len(opcode: u8) -> usize {
+    match opcode {
+        0x01 | 0x11 | 0x21 | 0x31 | 0x08 | 0xEA | 0xFA => 3,
+        0xC2 | 0xC3 | 0xCA | 0xD2 | 0xDA | 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => 3,
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => 2,
+        0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0x10 | 0xCB => 2,
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => 2,
+        0xE0 | 0xF0 | 0xE8 | 0xF8 => 2,
+        _ => 1,
+    }
+}
+
+/* An instruction as fetched, with the registers before it ran */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    /* The ROM bank `pc` was in, None outside ROM */
+    pub bank: Option<usize>,
+    /* The opcode and its operands; unused trailing bytes are zero */
+    pub bytes: [u8; 3],
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+}
+
+impl TraceEntry {
+    pub fn location(&self) -> Location {
+        Location { bank: self.bank, pc: self.pc }
+    }
+
+    /* One line, with `label` naming jump and load targets */
+    pub fn format(&self, label: &dyn Fn(u16) -> Option<String>) -> String {
+        let len = instruction_len(self.bytes[0]);
+        let bytes: Vec<String> = self.bytes[..len].iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("{:04X}: {:<8} {:<24} AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X}",
+            self.pc, bytes.join(" "), disassemble(&self.bytes, self.pc, label), self.af, self.bc, self.de, self.hl, self.sp)
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(&|_| None))
+    }
+}
+
+/* The last `capacity` instructions executed, overwritten oldest first.
+ * Filled in by `Gba::step` as it fetches; a capacity of 0 turns it off. */
+#[derive(Debug, Clone)]
+pub struct TraceRing {
+    entries: Vec<TraceEntry>,
+    /* Where the next entry goes */
+    next: usize,
+    capacity: usize,
+}
+
+impl Default for TraceRing {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRACE_LEN)
+    }
+}
+
+impl TraceRing {
+    pub fn new(capacity: usize) -> Self {
+        Self { entries: Vec::with_capacity(capacity), next: 0, capacity }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity != 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn push(&mut self, entry: TraceEntry) {
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else if let Some(slot) = self.entries.get_mut(self.next) {
+            *slot = entry;
+        }
+        if self.capacity != 0 {
+            self.next = (self.next + 1) % self.capacity;
+        }
+    }
+
+    /* Oldest first */
+    pub fn iter(&self) -> impl Iterator<Item = &TraceEntry> + '_ {
+        let (newer, older) = self.entries.split_at(if self.entries.len() < self.capacity { 0 } else { self.next });
+        older.iter().chain(newer)
+    }
+
+    /* The most recent instruction */
+    pub fn last(&self) -> Option<&TraceEntry> {
+        self.iter().last()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.next = 0;
+    }
+}
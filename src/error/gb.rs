@@ -0,0 +1,62 @@
+use std::{error::Error, fmt, io};
+
+use crate::{gba::prelude::DecodeError, mem::prelude::HeaderError};
+
+/* Everything the public constructors and the facade's run methods can
+ * fail with */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GbError {
+    /* A file, socket or savestate could not be read or was malformed */
+    Io(io::ErrorKind),
+    /* The cartridge image itself is unusable */
+    Rom(HeaderError),
+    /* The CPU met one of the unused opcodes and locked up */
+    Decode(DecodeError),
+    /* Valid, but needs hardware that is not emulated */
+    Unsupported(String),
+}
+
+impl fmt::Display for GbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(kind) => write!(f, "{}", io::Error::from(*kind)),
+            Self::Rom(e) => write!(f, "bad cartridge: {}", e),
+            Self::Decode(e) => write!(f, "CPU locked up: {}", e),
+            Self::Unsupported(what) => write!(f, "{} not supported", what),
+        }
+    }
+}
+
+impl Error for GbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Rom(e) => Some(e),
+            Self::Decode(e) => Some(e),
+            Self::Io(_) | Self::Unsupported(_) => None,
+        }
+    }
+}
+
+impl From<io::ErrorKind> for GbError {
+    fn from(kind: io::ErrorKind) -> Self {
+        Self::Io(kind)
+    }
+}
+
+impl From<io::Error> for GbError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e.kind())
+    }
+}
+
+impl From<HeaderError> for GbError {
+    fn from(e: HeaderError) -> Self {
+        Self::Rom(e)
+    }
+}
+
+impl From<DecodeError> for GbError {
+    fn from(e: DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
@@ -0,0 +1,7 @@
+#![allow(unused)]
+
+mod gb;
+
+pub mod prelude {
+    pub use super::gb::GbError;
+}
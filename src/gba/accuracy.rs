@@ -0,0 +1,68 @@
+use crate::{mem::prelude::Bus, ppu::prelude::Renderer};
+
+use super::console::Gba;
+
+/* One setting trading speed for fidelity, picking the renderer, how CPU
+ * memory accesses line up with the peripherals and whether the timer and
+ * OAM DMA model their edge cases */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccuracyLevel {
+    /* Scanline renderer; TIMA reloads the cycle it overflows and OAM DMA
+     * copies everything at once */
+    Fast,
+    /* Pixel FIFO, exact timer reloads and timed OAM DMA, with each
+     * instruction's accesses made against the bus as of its first cycle */
+    #[default]
+    Balanced,
+    /* As Balanced, with every CPU read and write on its own M-cycle */
+    Accurate,
+}
+
+impl AccuracyLevel {
+    pub const NAMES: [&'static str; 3] = ["fast", "balanced", "accurate"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fast" => Some(Self::Fast),
+            "balanced" => Some(Self::Balanced),
+            "accurate" => Some(Self::Accurate),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        Self::NAMES[*self as usize]
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        match self {
+            Self::Fast => Renderer::Scanline,
+            _ => Renderer::Fifo,
+        }
+    }
+
+    pub fn mcycle_memory(&self) -> bool {
+        *self == Self::Accurate
+    }
+
+    /* The TIMA reload delay and OAM DMA taking 160 cycles */
+    pub fn exact_edges(&self) -> bool {
+        *self != Self::Fast
+    }
+}
+
+impl Gba {
+    /* Meant to be set before running, as `Emulator::new` does */
+    pub fn set_accuracy(&mut self, accuracy: AccuracyLevel) {
+        self.accuracy = accuracy;
+        self.mem.ppu.set_renderer(accuracy.renderer());
+        self.mem.timer.set_exact(accuracy.exact_edges());
+        self.mem.exact_dma = accuracy.exact_edges();
+    }
+}
+
+impl<B: Bus> Gba<B> {
+    pub fn accuracy(&self) -> AccuracyLevel {
+        self.accuracy
+    }
+}
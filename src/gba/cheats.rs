@@ -0,0 +1,45 @@
+use std::fmt;
+
+use crate::mem::prelude::Mem;
+
+/* GameShark codes: eight hex digits `TTVVLLHH`, a type, the value, and
+ * the address low byte first. The device writes every code into RAM once
+ * a frame. Types other than 01 pick a RAM bank on the CGB; here every
+ * type writes to whichever bank is mapped. */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GameShark {
+    pub kind: u8,
+    pub value: u8,
+    pub addr: u16,
+}
+
+impl GameShark {
+    /* None for anything that is not eight hex digits writing to RAM */
+    pub fn parse(code: &str) -> Option<Self> {
+        let code = code.trim();
+        if code.len() != 8 {
+            return None;
+        }
+        let raw = u32::from_str_radix(code, 16).ok()?;
+        let [kind, value, low, high] = raw.to_be_bytes();
+        let addr = u16::from_le_bytes([low, high]);
+        matches!(addr, 0xA000..=0xDFFF | 0xFF80..=0xFFFE).then_some(Self { kind, value, addr })
+    }
+
+    /* The codes of a `cheat_codes` setting, "0142A0C0, 01070BC0"; one bad
+     * code makes the whole setting an error */
+    pub fn parse_list(codes: &str) -> Option<Vec<Self>> {
+        codes.split(',').filter(|code| !code.trim().is_empty()).map(Self::parse).collect()
+    }
+
+    pub fn apply(&self, mem: &mut Mem) {
+        mem[self.addr] = self.value;
+    }
+}
+
+impl fmt::Display for GameShark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [low, high] = self.addr.to_le_bytes();
+        write!(f, "{:02X}{:02X}{:02X}{:02X}", self.kind, self.value, low, high)
+    }
+}
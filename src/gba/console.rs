@@ -1,403 +1,614 @@
 
-use std::{fmt::write, io::ErrorKind};
+use std::{fmt::write, io::ErrorKind, path::Path};
 
 use crate::{
     cpu::{
-        proc::Cpu, 
+        alu,
+        interrupt::Interrupt,
+        microcode::{Latch, MicroOp, Microcode, Operand, Pointer, Routine},
+        proc::{Cpu, CpuState},
         register::types::{
             Flags, Register16, Register8, F8
         }
     }, 
     gba::opcode::{
         Opcode,
-        types::{JumpCondition, LoadDirection, MathOp, OpcodeIndirectRegister16, OpcodeRegister8}, 
+        types::{JumpCondition, LoadDirection, MathOp, OpcodeRegister8, ShiftOp},
     },
     mem::prelude::{
-        Cart, Mem, BOOT_ROM
+        Boot, BootRom, Bus, Cart, Mem, MemoryMap
+    },
+    debug, trace,
+    debugger::prelude::{disassemble, instruction_len, Location, Profiler, Symbols, TraceEntry, TraceRing},
+    error::prelude::GbError,
+    state::prelude::Xxh64,
+    ppu::{
+        image,
+        prelude::{Palette, SCREEN_HEIGHT, SCREEN_WIDTH}
     }
 };
 
-use super::opcode::types::OpcodeRegister16;
+use super::{accuracy::AccuracyLevel, counters::Counters, timing::{self, CycleValidator}};
+
+/* 154 lines of 114 M-cycles each */
+pub const CYCLES_PER_FRAME: usize = 17556;
+/* Longest an idle CPU skips ahead with no event in sight */
+const IDLE_CYCLES: usize = 114;
 
-pub struct Gba<'a> {
+pub struct Gba<B = Mem> {
     pub cpu: Cpu,
-    pub mem: Mem<'a>,
-    pub boot_rom: &'static [u8],
+    pub mem: B,
+    pub cycle_validator: CycleValidator,
+    /* Cycle attribution per instruction; off unless installed */
+    pub profiler: Option<Profiler>,
+    /* The last instructions executed, for crash dumps and backtraces */
+    pub trace: TraceRing,
+    /* Labels for traces, disassembly and breakpoints, from a .sym file */
+    pub symbols: Option<Symbols>,
+    pub(super) accuracy: AccuracyLevel,
+    /* M-cycles of the current instruction already passed to the bus */
+    pub(super) ticked: usize,
+    /* The M-cycle of the current instruction the next bus access falls on */
+    access: usize,
+    /* What the profiler and validator need from the fetch, for when the
+     * instruction is over */
+    fetched: Option<Fetched>,
+    /* M-cycles run and instructions executed, for `counters` */
+    pub(super) cycles: u64,
+    instructions: u64,
 }
 
-#[derive(Debug)]
-pub enum OpcodeExecuteError {
-    UnsupportedOpcode(String),
-    MissingOpcodeSupport(String),
+#[derive(Debug, Copy, Clone)]
+struct Fetched {
+    pc: u16,
+    byte: u8,
+    location: Option<Location>,
+    validate: bool,
+    taken: bool,
 }
 
-impl<'a> Gba<'a> {
-    pub fn new(rom: String) -> Result<Self, ErrorKind> {
-        let mut cpu = Self { cpu: Cpu::default(), mem: Mem::new(Cart::new(rom)?), boot_rom: &BOOT_ROM };
-        /* Execute Boot ROM */
-        Ok(cpu)
+impl Gba {
+    #[cfg(feature = "io")]
+    pub fn new(rom: String, boot: Boot) -> Result<Self, GbError> {
+        Ok(Self::with_boot(Cart::new(rom)?, boot))
     }
 
-    pub fn execute(&mut self, opcode: Opcode) -> usize {
-        let mut cycles = 1;
+    pub fn from_cart(cart: Cart) -> Self {
+        Self::with_boot(cart, Boot::default())
+    }
 
-        use Opcode::*;
-        match opcode {
-            // 8-bit Loading {{{
-            LoadR8(dst, src) => {
-                let src = match src {
-                    OpcodeRegister8::HL => {
-                        cycles += 1;
-                        let addr = self.cpu.registers.get_r16(Register16::HL);
-                        self.mem.get_u8(addr)
-                    },
-                    _ => self.cpu.registers.get_r8(Register8::from(src)),
-                };
-                match dst {
-                    OpcodeRegister8::HL => {
-                        cycles += 1;
-                        let addr = self.cpu.registers.get_r16(Register16::HL);
-                        self.mem.set_u8(addr, src);
-                    },
-                    _ => self.cpu.registers.set_r8(Register8::from(dst), src),
-                };
+    pub fn with_boot(cart: Cart, boot: Boot) -> Self {
+        /* A raw binary has no logo for a boot ROM to check */
+        let boot = if cart.raw_origin.is_some() { Boot::Skip } else { boot };
+        let mut gba = Self::with_bus(Mem::new(cart));
+        match boot {
+            Boot::Embedded => gba.mem.map_boot_rom(BootRom::embedded()),
+            Boot::Rom(rom) => {
+                /* CGB features need both a CGB boot ROM and a cart that asks for them */
+                let cgb = rom.is_cgb() && gba.mem.cart().header.is_cgb();
+                gba.mem.set_cgb_mode(cgb);
+                gba.mem.map_boot_rom(rom);
+            },
+            Boot::Skip => gba.skip_boot(),
+        }
+        gba
+    }
+
+    /* Registers and I/O as the DMG boot ROM leaves them at $0100, or at
+     * the start of a raw binary */
+    fn skip_boot(&mut self) {
+        let registers = [
+            (Register8::A, 0x01), (Register8::F, 0xB0), (Register8::B, 0x00), (Register8::C, 0x13),
+            (Register8::D, 0x00), (Register8::E, 0xD8), (Register8::H, 0x01), (Register8::L, 0x4D),
+        ];
+        for (reg, value) in registers {
+            self.cpu.registers.set_r8(reg, value);
+        }
+        self.cpu.registers.sp = 0xFFFE;
+        self.cpu.registers.pc = self.mem.cart().raw_origin.unwrap_or(0x0100);
+
+        /* NR52 first so the sound registers take; NR14 retriggers channel 1 as the logo chime left it */
+        let io: [(u16, u8); 23] = [
+            (0xFF26, 0xF1), (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF), (0xFF14, 0xBF),
+            (0xFF16, 0x3F), (0xFF19, 0xBF), (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF),
+            (0xFF1E, 0xBF), (0xFF20, 0xFF), (0xFF23, 0xBF), (0xFF24, 0x77), (0xFF25, 0xF3),
+            (0xFF00, 0xCF), (0xFF02, 0x7E), (0xFF0F, 0xE1), (0xFF40, 0x91), (0xFF47, 0xFC), (0xFF50, 0x01),
+        ];
+        for (addr, value) in io {
+            self.mem.set_u8(addr, value);
+        }
+        /* DMA reads back $FF, but writing it would copy $DF00 into OAM */
+        self.mem.set_dma_page(0xFF);
+        self.mem.timer.set_internal_counter(0xABCC);
+        self.mem.sync();
+    }
+
+    /* Power cycles the console without reloading the cart, running the
+     * boot ROM again if there is one. A hard reset also clears cart RAM
+     * that has no battery behind it. */
+    pub fn reset(&mut self, hard: bool) {
+        self.cpu = Cpu::default();
+        self.mem.reset(hard);
+        if !self.mem.has_boot_rom() {
+            self.skip_boot();
+        }
+        self.ticked = 0;
+        self.access = 0;
+        self.fetched = None;
+    }
+
+    /* Boots a different cart in place, returning the old one. Its save RAM
+     * goes with it, so write that out first. The boot ROM, settings and
+     * hooks stay; symbols and the trace belonged to the old ROM. */
+    pub fn swap_cart(&mut self, cart: Cart) -> Cart {
+        let old = self.mem.swap_cart(cart);
+        self.symbols = None;
+        self.trace = TraceRing::new(self.trace.capacity());
+        self.reset(true);
+        old
+    }
+
+    /* Totals for speed displays; see `Counters` */
+    pub fn counters(&self) -> Counters {
+        Counters { t_cycles: self.cycles * 4, instructions: self.instructions, frames: self.mem.ppu.frames }
+    }
+
+    /* XXH64 of the architectural state: the CPU's registers, IME, a
+     * pending EI and sleep state, and what `Mem::hash_state` covers. Two
+     * consoles that ran the same inputs from the same state hash alike. */
+    pub fn state_hash(&mut self) -> u64 {
+        self.mem.sync();
+        let (cpu, mut hasher) = (&self.cpu, Xxh64::default());
+        for register in [Register16::AF, Register16::BC, Register16::DE, Register16::HL] {
+            hasher.update(&cpu.registers.get_r16(register).to_le_bytes());
+        }
+        hasher.update(&cpu.registers.sp.to_le_bytes());
+        hasher.update(&cpu.registers.pc.to_le_bytes());
+        let state = match cpu.state {
+            CpuState::Running => [0, 0],
+            CpuState::Halted => [1, 0],
+            CpuState::Stopped => [2, 0],
+            CpuState::Locked(opcode) => [3, opcode],
+        };
+        hasher.update(&[cpu.ime, cpu.ime_pending as u8, state[0], state[1]]);
+        self.mem.hash_state(&mut hasher);
+        hasher.digest()
+    }
+
+    /* Writes the current frame as PNG, or PPM when the path ends in `.ppm` */
+    #[cfg(feature = "io")]
+    pub fn screenshot<P: AsRef<Path>>(&self, path: P, palette: &Palette) -> Result<(), ErrorKind> {
+        let rgb = self.mem.ppu.rgb_framebuffer_with(palette);
+        image::save(path, SCREEN_WIDTH, SCREEN_HEIGHT, &rgb)
+    }
+
+    pub fn dump_memory_map(&self) -> MemoryMap {
+        self.mem.memory_map()
+    }
+}
+
+impl<B: Bus> Gba<B> {
+    pub fn with_bus(mem: B) -> Self {
+        Self {
+            cpu: Cpu::default(),
+            mem,
+            cycle_validator: CycleValidator::default(),
+            profiler: None,
+            trace: TraceRing::default(),
+            symbols: None,
+            accuracy: AccuracyLevel::default(),
+            ticked: 0,
+            access: 0,
+            fetched: None,
+            cycles: 0,
+            instructions: 0,
+        }
+    }
+
+    /* Runs for one frame's worth of normal-speed M-cycles, returning the
+     * CPU cycles executed; twice as many in double speed */
+    pub fn run_frame(&mut self) -> usize {
+        self.run_frame_with(|_| {})
+    }
+
+    /* The same, calling `after_step` between instructions */
+    pub fn run_frame_with<F: FnMut(&mut Self)>(&mut self, mut after_step: F) -> usize {
+        let (mut cycles, mut halves) = (0, 0);
+        while halves < CYCLES_PER_FRAME * 2 {
+            let step = self.step();
+            cycles += step;
+            halves += if self.mem.double_speed() { step } else { step * 2 };
+            after_step(self);
+        }
+        /* Leave the frame's audio and registers current for the frontend */
+        self.mem.sync();
+        cycles
+    }
+
+    /* Runs one instruction, or finishes the one `tick` is partway through,
+     * returning the M-cycles that took */
+    pub fn step(&mut self) -> usize {
+        let cycles = if !self.mid_instruction() && self.cpu.state != CpuState::Running {
+            self.idle(IDLE_CYCLES)
+        } else {
+            let start = self.resume_bus();
+            let total = loop {
+                if let Some(total) = self.run_cycle() {
+                    break total;
+                }
+            };
+            self.mem.tick(total - self.ticked);
+            total - start
+        };
+        self.cycles += cycles as u64;
+        cycles
+    }
+
+    /* Lines the bus counters up with the cycles of the current instruction
+     * already run, returning how many that is */
+    pub(super) fn resume_bus(&mut self) -> usize {
+        let elapsed = self.cpu.microcode.elapsed();
+        (self.ticked, self.access) = (elapsed, elapsed);
+        elapsed
+    }
+
+    /* Runs one M-cycle: the next step of the instruction in flight or the
+     * start of a new one, then any steps that take no time of their own.
+     * Returns the instruction's M-cycles once it is over. */
+    pub(super) fn run_cycle(&mut self) -> Option<usize> {
+        match self.cpu.microcode.next_op() {
+            Some(op) => self.micro_op(op),
+            None => self.begin_instruction(),
+        }
+        self.cpu.microcode.count_cycle();
+        while let Some(op) = self.cpu.microcode.next_free() {
+            self.micro_op(op);
+        }
+        match self.cpu.microcode.in_flight() {
+            true => None,
+            false => Some(self.finish_instruction()),
+        }
+    }
+
+    /* The first M-cycle of an instruction. With IME set, a pending
+     * interrupt takes its place: five M-cycles pushing PC and jumping to a
+     * vector, two waits, the two pushes and the jump. The vector is only
+     * picked between the pushes, so the high byte of PC landing on IE at
+     * $FFFF can switch to another interrupt, or cancel it and jump to
+     * $0000 with IF left alone (mooneye's ie_push). */
+    fn begin_instruction(&mut self) {
+        if self.cpu.ime != 0 && self.mem.peek(0xFF0F) & self.mem.peek(0xFFFF) & 0x1F != 0 {
+            self.cpu.ime = 0;
+            self.cpu.microcode = Microcode::new(Routine::Dispatch);
+            if let Some(op) = self.cpu.microcode.next_op() {
+                self.micro_op(op);
+            }
+            return;
+        }
+        /* After the check, so the instruction after EI always runs first */
+        if std::mem::take(&mut self.cpu.ime_pending) {
+            self.cpu.ime = 1;
+        }
+
+        let pc = self.cpu.registers.pc;
+        /* Before executing, since the instruction may switch banks */
+        let location = self.profiler.is_some().then(|| Location { bank: self.mem.rom_bank_at(pc), pc });
+        self.bus_cycle();
+        let byte = self.mem.fetch(pc, true);
+        if self.trace.enabled() {
+            self.record_trace(pc, byte);
+        }
+        if !std::mem::take(&mut self.cpu.halt_bug) {
+            self.cpu.registers.pc = pc.wrapping_add(1);
+        }
+        let opcode = match Opcode::try_from(byte) {
+            Ok(opcode) => opcode,
+            Err(_) => {
+                self.cpu.state = CpuState::Locked(byte);
+                return;
             },
-            LoadImm8(dst) => {
-                let (val, cyc) = self.fetch_byte();
-                match dst {
-                    OpcodeRegister8::HL => {
-                        cycles += 1;
-                        let addr = self.cpu.registers.get_r16(Register16::HL);
-                        self.mem.set_u8(addr, val);
-                    },
-                    _ => self.cpu.registers.set_r8(Register8::from(dst), val),
-                };
-                cycles += cyc;
-            },
-            LoadIndR16(src, direction) => {
-                cycles += 2;
-                let addr = match src {
-                    OpcodeIndirectRegister16::HLInc => {
-                        let hl = self.cpu.registers.get_r16(Register16::HL);
-                        self.cpu.registers.set_r16(Register16::HL, hl + 1);
-                        hl
-                    },
-                    OpcodeIndirectRegister16::HLDec => {
-                        let hl = self.cpu.registers.get_r16(Register16::HL);
-                        self.cpu.registers.set_r16(Register16::HL, hl - 1);
-                        hl
-                    },
-                    _ => self.cpu.registers.get_r16(Register16::from(src)),
+        };
+        self.instructions += 1;
+
+        /* Compare against the canonical table in debug builds only */
+        let validate = cfg!(debug_assertions) && self.cycle_validator.enabled;
+        let taken = validate && timing::is_conditional(byte) && self.condition_met(byte);
+        self.fetched = Some(Fetched { pc, byte, location, validate, taken });
+        trace!(target: "gbemu::cpu", "{:04X}: {:02X} {:?} A={:02X} BC={:04X} DE={:04X} HL={:04X} SP={:04X}",
+            pc, byte, opcode, self.cpu.registers.a, self.cpu.registers.get_r16(Register16::BC),
+            self.cpu.registers.get_r16(Register16::DE), self.cpu.registers.get_r16(Register16::HL), self.cpu.registers.sp);
+        self.cpu.microcode = Microcode::new(Routine::Opcode(byte));
+    }
+
+    /* Hands the cycles of a finished instruction to the profiler and
+     * validator, and leaves the CPU between instructions */
+    fn finish_instruction(&mut self) -> usize {
+        let code = std::mem::take(&mut self.cpu.microcode);
+        let cycles = code.elapsed();
+        if let Some(fetched) = self.fetched.take() {
+            if let (Some(profiler), Some(location)) = (&mut self.profiler, fetched.location) {
+                profiler.record(location, cycles);
+            }
+            if fetched.validate {
+                match code.routine() {
+                    Routine::Prefixed(byte) => self.cycle_validator.check_prefixed(fetched.pc, byte, cycles),
+                    _ => self.cycle_validator.check(fetched.pc, fetched.byte, fetched.taken, cycles),
+                }
+            }
+        }
+        cycles
+    }
+
+    fn micro_op(&mut self, op: MicroOp) {
+        match op {
+            MicroOp::FetchImm(latch) => {
+                let (byte, _) = self.fetch_byte();
+                self.set_latch(latch, byte);
+            },
+            MicroOp::FetchPrefixed => {
+                let (byte, _) = self.fetch_byte();
+                self.cpu.microcode.prefixed(byte);
+            },
+            MicroOp::Read(pointer, latch) => {
+                let addr = self.pointer(pointer);
+                let value = self.bus_read(addr);
+                self.set_latch(latch, value);
+            },
+            MicroOp::Write(pointer, operand) => {
+                let value = match operand {
+                    Operand::Register(reg) => self.cpu.registers.get_r8(reg),
+                    Operand::Z => self.cpu.cache as u8,
                 };
-                if let LoadDirection::Memory = direction 
-                    { self.mem.set_u8(addr, self.cpu.registers.a); }
-                else { self.cpu.registers.a = self.mem.get_u8(addr); }
-            },
-            LoadIndOffImm8(direction) => {
-                let (off, cyc) = self.fetch_byte();
-                let addr = 0xFF00 + off as u16;
-                cycles += cyc + 1;
-                if let LoadDirection::Memory = direction 
-                    { self.mem.set_u8(addr, self.cpu.registers.a); } 
-                else { self.cpu.registers.a = self.mem.get_u8(addr); }
-            },
-            LoadIndOffRegC(direction) => {
-                cycles += 1;
-                let addr = 0xFF00 + self.cpu.registers.c as u16;
-                if let LoadDirection::Memory = direction 
-                    { self.mem.set_u8(addr, self.cpu.registers.a); } 
-                else { self.cpu.registers.a = self.mem.get_u8(addr); }
-            },
-            LoadIndImm16(direction) => {
-                let (addr, cyc) = self.fetch_word();
-                cycles += cyc + 1;
-                if let LoadDirection::Memory = direction 
-                    { self.mem.set_u8(addr, self.cpu.registers.a); } 
-                else { self.cpu.registers.a = self.mem.get_u8(addr); }
-            },
-            //}}}
-            // 16-bit Loading {{{
-            LoadImm16(dst) => {
-                let (val, cyc) = self.fetch_word();
-                cycles += cyc;
-                self.cpu.registers.set_r16(Register16::from(dst), val);
-            },
-            LoadIndImm16SP => {
-                let (addr, cyc) = self.fetch_word();
-                cycles += cyc + 2;
-                self.mem.set_u16(addr, self.cpu.registers.sp);
-            },
-            LoadSPHL => {
-                cycles += 1;
-                self.cpu.registers.sp = self.cpu.registers.get_r16(Register16::HL);
-            },
-            PushR16(src) => {
-                let src = self.cpu.registers.get_r16(Register16::from(src));
-                cycles += 1 + self.push(src)
-            },
-            PopR16(dst) => {
-                cycles += 2;
-                let val = self.mem.get_u16(self.cpu.registers.sp);
-                self.cpu.registers.sp += 2;
-                self.cpu.registers.set_r16(Register16::from(dst), val);
+                let addr = self.pointer(pointer);
+                self.bus_write(addr, value);
+            },
+            MicroOp::Idle => self.internal_cycle(),
+            MicroOp::Idu(reg, increment) => {
+                let value = self.cpu.registers.get_r16(reg);
+                self.idu_access(value);
+                self.internal_cycle();
+                self.cpu.registers.set_r16(reg, if increment { value.wrapping_add(1) } else { value.wrapping_sub(1) });
+            },
+            MicroOp::Execute => match self.cpu.microcode.routine() {
+                Routine::Opcode(byte) => if let Ok(opcode) = Opcode::decode(byte) {
+                    self.execute(opcode);
+                },
+                Routine::Prefixed(byte) => self.execute(Opcode::decode_prefixed(byte)),
+                Routine::Dispatch => self.cpu.registers.pc = self.cpu.cache,
+                Routine::Idle => (),
+            },
+            MicroOp::Check(condition) => if !self.condition_holds(condition) {
+                self.cpu.microcode.cut();
+            },
+            MicroOp::PickVector => self.pick_vector(),
+        }
+    }
+
+    fn set_latch(&mut self, latch: Latch, value: u8) {
+        self.cpu.cache = match latch {
+            Latch::Z => (self.cpu.cache & 0xFF00) | value as u16,
+            Latch::W => (self.cpu.cache & 0x00FF) | ((value as u16) << 8),
+        };
+    }
+
+    /* The address for an access, moving HL or SP along where it says so */
+    fn pointer(&mut self, pointer: Pointer) -> u16 {
+        let registers = &mut self.cpu.registers;
+        match pointer {
+            Pointer::Register(reg) => registers.get_r16(reg),
+            Pointer::HLInc => {
+                let hl = registers.get_r16(Register16::HL);
+                registers.set_r16(Register16::HL, hl.wrapping_add(1));
+                hl
+            },
+            Pointer::HLDec => {
+                let hl = registers.get_r16(Register16::HL);
+                registers.set_r16(Register16::HL, hl.wrapping_sub(1));
+                hl
+            },
+            Pointer::HighC => 0xFF00 | registers.c as u16,
+            Pointer::HighZ => 0xFF00 | (self.cpu.cache & 0x00FF),
+            Pointer::WZ => self.cpu.cache,
+            Pointer::WZNext => self.cpu.cache.wrapping_add(1),
+            Pointer::SPInc => {
+                registers.sp = registers.sp.wrapping_add(1);
+                registers.sp.wrapping_sub(1)
+            },
+            Pointer::SPDec => {
+                registers.sp = registers.sp.wrapping_sub(1);
+                registers.sp
             },
-            LoadHLOffSp => {
-                let (off, cyc) = self.fetch_byte();
-                cycles += cyc + 1;
-                let sp = self.cpu.registers.sp;
-                let (addr, over) = sp.overflowing_add(off as u16);
-                let val = self.mem.get_u16(addr);
-
-                self.cpu.registers.f &= !(Flags::Zero | Flags::Subtract);
-                if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= Flags::Carry; }
-                if ((sp & 0x000F) as u8 + (off & 0x0F)) & 0x10 != 0 {
-                    self.cpu.registers.f |= Flags::HalfCarry;
-                } else {
-                    self.cpu.registers.f &= !Flags::HalfCarry;
-                }
+        }
+    }
+
+    /* Between the pushes of a dispatch: the vector goes in WZ for the jump,
+     * or $0000 when the push took the interrupt away */
+    fn pick_vector(&mut self) {
+        let pending = self.mem.peek(0xFF0F) & self.mem.peek(0xFFFF) & 0x1F;
+        let pc = self.cpu.registers.pc;
+        self.cpu.cache = match pending {
+            0 => {
+                debug!(target: "gbemu::cpu::irq", "Dispatch from {:04X} cancelled by the push", pc);
+                0x0000
+            },
+            _ => {
+                let bit = pending.trailing_zeros() as u16;
+                debug!(target: "gbemu::cpu::irq", "Dispatching {:02X} from {:04X}", 1 << bit, pc);
+                self.mem.set_u8(0xFF0F, self.mem.peek(0xFF0F) & !(1 << bit));
+                0x40 + bit * 8
             },
-            //}}}
-            // 8-bit Arithmetic {{{
-            MathR8(op, src) => {
-                let (src, cyc) = self.fetch_register_8(src);
-                cycles += cyc;
-                match op {
-                    MathOp::Add => {
-                        let (val, over) = self.cpu.registers.a.overflowing_add(src);
-
-                        self.cpu.registers.f &= !Flags::Subtract;
-                        if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
-                        if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                        if ((self.cpu.registers.a & 0x0F) + (src & 0x0F)) == 0x10 {
-                            self.cpu.registers.f |= Flags::HalfCarry;
-                        } else {
-                            self.cpu.registers.f &= !Flags::HalfCarry;
-                        }
-                        
-                        self.cpu.registers.a = val;
-                    },
-                    MathOp::Adc => {
-                        let (val, over) = self.cpu.registers.a.overflowing_add(src + self.cpu.registers.f.is_set(Flags::Carry) as u8);
-
-                        self.cpu.registers.f &= !Flags::Subtract;
-                        if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
-                        if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                        if ((self.cpu.registers.a & 0x0F) + (src & 0x0F)) == 0x10 {
-                            self.cpu.registers.f |= Flags::HalfCarry;
-                        } else {
-                            self.cpu.registers.f &= !Flags::HalfCarry;
-                        }
+        };
+    }
 
-                        self.cpu.registers.a = val;
-                    },
-                    MathOp::Sub => {
-                        let (val, over) = self.cpu.registers.a.overflowing_sub(src);
-                        
-                        self.cpu.registers.f |= Flags::Subtract;
-                        if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
-                        if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                        if ((self.cpu.registers.a & 0x0F) - (src & 0x0F)) == 0x10 {
-                            self.cpu.registers.f |= Flags::HalfCarry;
-                        } else {
-                            self.cpu.registers.f &= !Flags::HalfCarry;
-                        }
-                        
-                        self.cpu.registers.a = val;
-                    },
-                    MathOp::Sbc => {
-                        let (val, over) = self.cpu.registers.a.overflowing_sub(src - self.cpu.registers.f.is_set(Flags::Carry) as u8);
-
-                        self.cpu.registers.f |= Flags::Subtract;
-                        if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
-                        if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                        if ((self.cpu.registers.a & 0x0F) - (src & 0x0F)) == 0x10 {
-                            self.cpu.registers.f |= Flags::HalfCarry;
-                        } else {
-                            self.cpu.registers.f &= !Flags::HalfCarry;
-                        }
-                        
-                        self.cpu.registers.a = val;
-                    },
-                    MathOp::And => {
-                        self.cpu.registers.a &= src;
+    fn record_trace(&mut self, pc: u16, opcode: u8) {
+        let mut bytes = [opcode, 0, 0];
+        for (i, byte) in bytes.iter_mut().enumerate().take(instruction_len(opcode)).skip(1) {
+            *byte = self.mem.peek(pc.wrapping_add(i as u16));
+        }
+        let registers = &self.cpu.registers;
+        self.trace.push(TraceEntry {
+            pc,
+            bank: self.mem.rom_bank_at(pc),
+            bytes,
+            af: registers.get_r16(Register16::AF),
+            bc: registers.get_r16(Register16::BC),
+            de: registers.get_r16(Register16::DE),
+            hl: registers.get_r16(Register16::HL),
+            sp: registers.sp,
+        });
+    }
 
-                        self.cpu.registers.f &= !(Flags::Subtract | Flags::Carry);
-                        self.cpu.registers.f |= Flags::HalfCarry;
-                        if self.cpu.registers.a == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                    },
-                    MathOp::Xor => {
-                        self.cpu.registers.a ^= src;
+    /* The trace ring oldest first, one instruction a line, with a line for
+     * each label passed through */
+    pub fn trace_dump(&self) -> String {
+        let mut out = String::new();
+        for entry in self.trace.iter() {
+            if let Some(name) = self.symbols.as_ref().and_then(|symbols| symbols.name(entry.location())) {
+                out.push_str(&format!("{}:\n", name));
+            }
+            out.push_str(&entry.format(&|target| self.label(target, entry.location())));
+            out.push('\n');
+        }
+        out
+    }
 
-                        self.cpu.registers.f &= !(Flags::Subtract | Flags::Carry | Flags::HalfCarry);
-                        if self.cpu.registers.a == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                    },
-                    MathOp::Or => {
-                        self.cpu.registers.a |= src;
+    /* The instruction at `pc` as it would run now */
+    pub fn disassemble_at(&self, pc: u16) -> String {
+        let bytes: Vec<u8> = (0..3).map(|i| self.mem.peek(pc.wrapping_add(i))).collect();
+        let from = Location { bank: self.mem.rom_bank_at(pc), pc };
+        disassemble(&bytes, pc, &|target| self.label(target, from))
+    }
 
-                        self.cpu.registers.f &= !(Flags::Subtract | Flags::Carry | Flags::HalfCarry);
-                        if self.cpu.registers.a == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                    },
-                    MathOp::Cp => {
-                        let (val, over) = self.cpu.registers.a.overflowing_sub(src);
-
-                        self.cpu.registers.f |= Flags::Subtract;
-                        if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
-                        if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                        if ((self.cpu.registers.a & 0x0F) - (src & 0x0F)) == 0x10 {
-                            self.cpu.registers.f |= Flags::HalfCarry;
-                        } else {
-                            self.cpu.registers.f &= !Flags::HalfCarry;
-                        }
-                    },
-                };
-            },
-            MathImm8(op) => {
-                match op {
-                    MathOp::Add => {
-                        let (src, cyc) = self.fetch_byte();
-                        let (val, over) = self.cpu.registers.a.overflowing_add(src);
-
-                        self.cpu.registers.f &= !Flags::Subtract;
-                        if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
-                        if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                        if ((self.cpu.registers.a & 0x0F) + (src & 0x0F)) == 0x10 {
-                            self.cpu.registers.f |= Flags::HalfCarry;
-                        } else {
-                            self.cpu.registers.f &= !Flags::HalfCarry;
-                        }
+    /* The label at `target` as seen from an instruction at `from`, which
+     * decides the bank when both are in the same ROM half */
+    fn label(&self, target: u16, from: Location) -> Option<String> {
+        let symbols = self.symbols.as_ref()?;
+        let bank = match target < 0x8000 && target >> 14 == from.pc >> 14 && from.pc < 0x8000 {
+            true => from.bank,
+            false => self.mem.rom_bank_at(target),
+        };
+        symbols.name(Location { bank, pc: target }).map(str::to_string)
+    }
 
-                        self.cpu.registers.a = val;
-                        cycles += cyc;
-                    },
-                    MathOp::Adc => {
-                        let (src, cyc) = self.fetch_byte();
-                        let (val, over) = self.cpu.registers.a.overflowing_add(src + self.cpu.registers.f.is_set(Flags::Carry) as u8);
-
-                        self.cpu.registers.f &= !Flags::Subtract;
-                        if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                        if ((self.cpu.registers.a & 0x0F) + (src & 0x0F) + (self.cpu.registers.f.is_set(Flags::Carry) as u8)) == 0x10 {
-                            self.cpu.registers.f |= Flags::HalfCarry;
-                        } else {
-                            self.cpu.registers.f &= !Flags::HalfCarry;
-                        }
-                        if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
+    /* The CPU asleep or locked up while the peripherals keep running.
+     * Nothing can wake it before the next event, so skip straight there,
+     * or at most `limit` M-cycles. */
+    pub(super) fn idle(&mut self, limit: usize) -> usize {
+        /* Stop mode halts the system clock: no DIV, timer, LCD or sound */
+        if self.cpu.state == CpuState::Stopped {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.record_idle(limit);
+            }
+            if self.wake_pending() {
+                self.cpu.state = CpuState::Running;
+            }
+            return limit;
+        }
+        let cycles = match self.wake_pending() {
+            true => 1,
+            false => self.mem.until_event().unwrap_or(limit).clamp(1, limit),
+        };
+        self.mem.tick(cycles);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record_idle(cycles);
+        }
+        if self.wake_pending() {
+            self.cpu.state = CpuState::Running;
+        }
+        cycles
+    }
 
-                        cycles += cyc;
-                        self.cpu.registers.a = val;
-                    },
-                    MathOp::Sub => {
-                        let (src, cyc) = self.fetch_byte();
-                        let (val, over) = self.cpu.registers.a.overflowing_sub(src);
-
-                        self.cpu.registers.f |= Flags::Subtract;
-                        if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
-                        if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                        if ((self.cpu.registers.a & 0x0F) - (src & 0x0F)) == 0x10 {
-                            self.cpu.registers.f |= Flags::HalfCarry;
-                        } else {
-                            self.cpu.registers.f &= !Flags::HalfCarry;
-                        }
+    fn wake_pending(&self) -> bool {
+        let pending = self.mem.peek(0xFF0F) & 0x1F;
+        match self.cpu.state {
+            CpuState::Halted => pending & self.mem.peek(0xFFFF) != 0,
+            /* Any selected input line going low, whatever IE says */
+            CpuState::Stopped => self.mem.peek(0xFF00) & 0x0F != 0x0F,
+            _ => false,
+        }
+    }
 
-                        cycles += cyc;
-                        self.cpu.registers.a = val;
-                    },
-                    MathOp::Sbc => {
-                        let (src, cyc) = self.fetch_byte();
-                        let (val, over) = self.cpu.registers.a.overflowing_sub(src - self.cpu.registers.f.is_set(Flags::Carry) as u8);
-
-                        self.cpu.registers.f |= Flags::Subtract;
-                        if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
-                        if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                        if ((self.cpu.registers.a & 0x0F) - (src & 0x0F) - self.cpu.registers.f.is_set(Flags::Carry) as u8) == 0x10 {
-                            self.cpu.registers.f |= Flags::HalfCarry;
-                        } else {
-                            self.cpu.registers.f &= !Flags::HalfCarry;
-                        }
+    /* Condition encoded in bits 3-4 of conditional JR / JP / CALL / RET */
+    fn condition_met(&self, opcode: u8) -> bool {
+        let f = self.cpu.registers.f;
+        match (opcode >> 3) & 0x03 {
+            0 => !f.is_set(Flags::Zero),
+            1 => f.is_set(Flags::Zero),
+            2 => !f.is_set(Flags::Carry),
+            _ => f.is_set(Flags::Carry),
+        }
+    }
 
-                        cycles += cyc;
-                        self.cpu.registers.a = val;
-                    },
-                    MathOp::Cp => {
-                        let (src, cyc) = self.fetch_byte();
-                        let (val, over) = self.cpu.registers.a.overflowing_sub(src);
-
-                        self.cpu.registers.f |= Flags::Subtract;
-                        if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
-                        if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                        if ((self.cpu.registers.a & 0x0F) - (src & 0x0F)) == 0x10 {
-                            self.cpu.registers.f |= Flags::HalfCarry;
-                        } else {
-                            self.cpu.registers.f &= !Flags::HalfCarry;
-                        }
+    /* The condition of a JR / JP / CALL / RET as decoded */
+    fn condition_holds(&self, condition: JumpCondition) -> bool {
+        match condition {
+            JumpCondition::Always => true,
+            JumpCondition::SetFlag(flag) => self.cpu.registers.f.is_set(flag),
+            JumpCondition::UnsetFlag(flag) => !self.cpu.registers.f.is_set(flag),
+        }
+    }
 
-                        cycles += cyc;
-                    },
-                    MathOp::And => {
-                        let (val, cyc) = self.fetch_byte();
-                        cycles += cyc;
-                        self.cpu.registers.a &= val;
-
-                        self.cpu.registers.f &= !(Flags::Subtract | Flags::Carry);
-                        self.cpu.registers.f |= Flags::HalfCarry;
-                        if self.cpu.registers.a == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                    },
-                    MathOp::Or => {
-                        let (val, cyc) = self.fetch_byte();
-                        cycles += cyc;
-                        self.cpu.registers.a |= val;
+    /* An 8-bit operand, with (HL) as the byte a read left in Z */
+    fn operand(&self, reg: OpcodeRegister8) -> u8 {
+        match reg {
+            OpcodeRegister8::HL => self.cpu.cache as u8,
+            _ => self.cpu.registers.get_r8(Register8::from(reg)),
+        }
+    }
 
-                        self.cpu.registers.f &= !(Flags::Subtract | Flags::Carry | Flags::HalfCarry);
-                        if self.cpu.registers.a == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                    },
-                    MathOp::Xor => {
-                        let (val, cyc) = self.fetch_byte();
-                        cycles += cyc;
-                        self.cpu.registers.a ^= val;
+    /* An (HL) result goes to Z for the write that follows */
+    fn set_operand(&mut self, reg: OpcodeRegister8, value: u8) {
+        match reg {
+            OpcodeRegister8::HL => self.cpu.cache = (self.cpu.cache & 0xFF00) | value as u16,
+            _ => self.cpu.registers.set_r8(Register8::from(reg), value),
+        }
+    }
 
-                        self.cpu.registers.f &= !(Flags::Subtract | Flags::Carry | Flags::HalfCarry);
-                        if self.cpu.registers.a == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                    },
-                };
+    /* The register work of an instruction, run once its earlier steps
+     * have fetched immediates and memory operands into WZ. Whatever only
+     * moves data between WZ and memory has nothing left to do here. */
+    fn execute(&mut self, opcode: Opcode) {
+        let z = self.cpu.cache as u8;
+
+        use Opcode::*;
+        match opcode {
+            // 8-bit Loading {{{
+            LoadR8(dst, src) => {
+                let value = self.operand(src);
+                self.set_operand(dst, value);
+            },
+            LoadImm8(dst) => self.set_operand(dst, z),
+            LoadIndR16(_, LoadDirection::Accumulator)
+            | LoadIndOffImm8(LoadDirection::Accumulator)
+            | LoadIndOffRegC(LoadDirection::Accumulator)
+            | LoadIndImm16(LoadDirection::Accumulator) => self.cpu.registers.a = z,
+            LoadIndR16(_, LoadDirection::Memory)
+            | LoadIndOffImm8(LoadDirection::Memory)
+            | LoadIndOffRegC(LoadDirection::Memory)
+            | LoadIndImm16(LoadDirection::Memory) => (),
+            //}}}
+            // 16-bit Loading {{{
+            LoadImm16(dst) | PopR16(dst) => self.cpu.registers.set_r16(Register16::from(dst), self.cpu.cache),
+            LoadIndImm16SP | PushR16(_) => (),
+            LoadSPHL => self.cpu.registers.sp = self.cpu.registers.get_r16(Register16::HL),
+            LoadHLOffSp => {
+                let (val, f) = alu::add_sp_e8(self.cpu.registers.sp, z);
+                self.cpu.registers.set_r16(Register16::HL, val);
+                self.cpu.registers.f = f;
             },
+            //}}}
+            // 8-bit Arithmetic {{{
+            MathR8(op, src) => self.math(op, self.operand(src)),
+            MathImm8(op) => self.math(op, z),
             IncR8(reg) => {
-                let mut val = 0;
-                match reg {
-                    OpcodeRegister8::HL => {
-                        let addr = self.cpu.registers.get_r16(Register16::HL);
-                        val = self.mem.get_u8(addr);
-                        self.mem.set_u8(addr, val + 1);
-                        cycles += 2;
-                    },
-                    _ => {
-                        let reg = Register8::from(reg);
-                        val = self.cpu.registers.get_r8(reg);
-                        self.cpu.registers.set_r8(reg, val + 1);
-                    },
-                };
-
-                self.cpu.registers.f &= !Flags::Subtract;
-                if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                if val & 0x08 != 0 { self.cpu.registers.f |= Flags::HalfCarry; } else { self.cpu.registers.f &= !Flags::HalfCarry; }
+                let (val, f) = alu::inc8(self.operand(reg), self.cpu.registers.f);
+                self.set_operand(reg, val);
+                self.cpu.registers.f = f;
             },
             DecR8(reg) => {
-                let mut val = 0;
-                match reg {
-                    OpcodeRegister8::HL => {
-                        let addr = self.cpu.registers.get_r16(Register16::HL);
-                        val = self.mem.get_u8(addr);
-                        self.mem.set_u8(addr, val + 1);
-                        cycles += 2;
-                    },
-                    _ => {
-                        let reg = Register8::from(reg);
-                        val = self.cpu.registers.get_r8(reg);
-                        self.cpu.registers.set_r8(reg, val + 1);
-                    },
-                };
-
-                self.cpu.registers.f &= !Flags::Subtract;
-                if val == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                if (val & 0x0F) - 1 != 0 { self.cpu.registers.f |= Flags::HalfCarry; } else { self.cpu.registers.f &= !Flags::HalfCarry; }
+                let (val, f) = alu::dec8(self.operand(reg), self.cpu.registers.f);
+                self.set_operand(reg, val);
+                self.cpu.registers.f = f;
             },
             ComplementCarryFlag => {
                 self.cpu.registers.f ^= Flags::Carry;
@@ -408,26 +619,9 @@ impl<'a> Gba<'a> {
                 self.cpu.registers.f &= !(Flags::Subtract | Flags::HalfCarry);
             },
             DecimalAdjustAccumulator => {
-                if self.cpu.registers.f.is_set(Flags::Subtract) {
-                    if self.cpu.registers.f.is_set(Flags::Carry) || self.cpu.registers.a > 0x99 {
-                        self.cpu.registers.a += 0x60;
-                        self.cpu.registers.f |= Flags::Carry;
-                    }
-                    if self.cpu.registers.f.is_set(Flags::HalfCarry) || (self.cpu.registers.a & 0x0F) > 0x09 {
-                        self.cpu.registers.a += 0x06;
-                    }
-                }
-                else {
-                    if self.cpu.registers.f.is_set(Flags::Carry) {
-                        self.cpu.registers.a -= 0x60;
-                    }
-                    if self.cpu.registers.f.is_set(Flags::HalfCarry) {
-                        self.cpu.registers.a -= 0x06;
-                    }
-                }
-
-                if self.cpu.registers.a == 0 { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                self.cpu.registers.f &= !Flags::HalfCarry;
+                let (a, f) = alu::daa(self.cpu.registers.a, self.cpu.registers.f);
+                self.cpu.registers.a = a;
+                self.cpu.registers.f = f;
             },
             ComplementAccumulator => {
                 self.cpu.registers.a ^= 0xFF;
@@ -435,208 +629,182 @@ impl<'a> Gba<'a> {
             },
             //}}}
             // 16-bit Arithmetic {{{
-            IncR16(src) => {
-                cycles += 1;
-                crate::cpu::register::types::as_u16_slice_mut(&mut self.cpu.registers)[Register16::from(src) as usize] += 1;
-            },
-            DecR16(src) => {
-                cycles += 1;
-                crate::cpu::register::types::as_u16_slice_mut(&mut self.cpu.registers)[Register16::from(src) as usize] -= 1;
-            },
+            /* Done by the IDU step */
+            IncR16(_) | DecR16(_) => (),
             AddR16(src) => {
                 let hl = self.cpu.registers.get_r16(Register16::HL);
                 let src = self.cpu.registers.get_r16(Register16::from(src));
-                let (val, over) = hl.overflowing_add(src);
-
-                cycles += 1;
+                let (val, f) = alu::add16(hl, src, self.cpu.registers.f);
                 self.cpu.registers.set_r16(Register16::HL, val);
-
-                self.cpu.registers.f &= !Flags::Subtract;
-                if over { self.cpu.registers.f |= Flags::Carry; } else { self.cpu.registers.f &= !Flags::Carry; }
-                if ((hl & 0x0F00) + (src & 0x0F00)) & 0x1000 != 0 {
-                    self.cpu.registers.f |= Flags::HalfCarry;
-                } else {
-                    self.cpu.registers.f &= !Flags::HalfCarry;
-                }
+                self.cpu.registers.f = f;
             },
             AddSPImm8 => {
-                let (off, cyc) = self.fetch_byte();
-                let sp = self.cpu.registers.sp;
-                let (sp, over) = if off as i8 >= 0 { sp.overflowing_add(off as u16) } else { sp.overflowing_sub(-(off as i8) as u16) };
+                let (sp, f) = alu::add_sp_e8(self.cpu.registers.sp, z);
                 self.cpu.registers.sp = sp;
-
-                self.cpu.registers.f &= !(Flags::Zero | Flags::Subtract);
-                if over { self.cpu.registers.f |= Flags::Zero; } else { self.cpu.registers.f &= !Flags::Zero; }
-                if ((sp & 0x000F) + (off as u16 & 0x000F)) == 0x0010 {
-                    self.cpu.registers.f |= Flags::HalfCarry;
-                } else {
-                    self.cpu.registers.f &= !Flags::HalfCarry;
-                }
+                self.cpu.registers.f = f;
             },
             //}}}
             // Rotate, Shift, and Bit {{{
+            /* The accumulator forms always clear Z, unlike their $CB counterparts */
             RotateLeftCircularAccumulator => {
-                let (a, c) = self.cpu.registers.a.overflowing_shl(1);
-                self.cpu.registers.f = if c { F8::from(Flags::Carry as u8) } else { F8::from(0) };
-                self.cpu.registers.a = a | (c as u8);
+                let (a, f) = alu::rlc8(self.cpu.registers.a);
+                self.cpu.registers.a = a;
+                self.cpu.registers.f = f & Flags::Carry;
             },
             RotateLeftAccumulator => {
-                let (a, c) = self.cpu.registers.a.overflowing_shl(1);
-                let old_c = self.cpu.registers.f.is_set(Flags::Carry) as u8;
-                self.cpu.registers.f = if c { F8::from(Flags::Carry as u8) } else { F8::from(0) };
-                self.cpu.registers.a = a | old_c;
+                let (a, f) = alu::rl8(self.cpu.registers.a, self.cpu.registers.f);
+                self.cpu.registers.a = a;
+                self.cpu.registers.f = f & Flags::Carry;
             },
             RotateRightCircularAccumulator => {
-                let (a, c) = self.cpu.registers.a.overflowing_shr(1);
-                self.cpu.registers.f = if c { F8::from(Flags::Carry as u8) } else { F8::from(0) };
-                self.cpu.registers.a = a | ((c as u8) << 7);
+                let (a, f) = alu::rrc8(self.cpu.registers.a);
+                self.cpu.registers.a = a;
+                self.cpu.registers.f = f & Flags::Carry;
             },
             RotateRightAccumulator => {
-                let (a, c) = self.cpu.registers.a.overflowing_shr(1);
-                let old_c = self.cpu.registers.f.is_set(Flags::Carry) as u8;
-                self.cpu.registers.f = if c { F8::from(Flags::Carry as u8) } else { F8::from(0) };
-                self.cpu.registers.a = a | (old_c << 7);
+                let (a, f) = alu::rr8(self.cpu.registers.a, self.cpu.registers.f);
+                self.cpu.registers.a = a;
+                self.cpu.registers.f = f & Flags::Carry;
+            },
+            /* The fetch of the second byte swaps in its own steps */
+            Prefix => (),
+            RotateShift(op, reg) => {
+                let (val, f) = (self.operand(reg), self.cpu.registers.f);
+                let (val, f) = match op {
+                    ShiftOp::Rlc => alu::rlc8(val),
+                    ShiftOp::Rrc => alu::rrc8(val),
+                    ShiftOp::Rl => alu::rl8(val, f),
+                    ShiftOp::Rr => alu::rr8(val, f),
+                    ShiftOp::Sla => alu::sla8(val),
+                    ShiftOp::Sra => alu::sra8(val),
+                    ShiftOp::Swap => alu::swap8(val),
+                    ShiftOp::Srl => alu::srl8(val),
+                };
+                self.set_operand(reg, val);
+                self.cpu.registers.f = f;
             },
+            TestBit(bit, reg) => self.cpu.registers.f = alu::bit8(bit, self.operand(reg), self.cpu.registers.f),
+            ResetBit(bit, reg) => self.set_operand(reg, self.operand(reg) & !(1 << bit)),
+            SetBit(bit, reg) => self.set_operand(reg, self.operand(reg) | (1 << bit)),
             //}}}
             // Control Flow {{{
-            JumpImm16(condition) => {
-                let (addr, cyc) = self.fetch_word();
-                cycles += cyc + self.jump(addr, condition);
-            },
-            JumpHL => {
-                let addr = self.cpu.registers.get_r16(Register16::HL);
-                self.cpu.registers.pc = addr;
-            },
-            JumpOffImm8(condition) => {
-                let (off, cyc) = self.fetch_byte();
-                let addr = self.cpu.registers.pc + (off as i8) as u16;
-                cycles += cyc + self.jump(addr, condition)
-            },
-            CallImm16(condition) => {
-                let (addr, cyc) = self.fetch_word();
-                cycles += cyc + self.call(addr, condition);
-            },
-            Return(condition) => {
-                cycles += match condition {
-                    JumpCondition::Always => {
-                        self.cpu.registers.pc = self.mem.get_u16(self.cpu.registers.sp);
-                        self.cpu.registers.sp += 2;
-                        4
-                    },
-                    JumpCondition::SetFlag(flag) => {
-                        if self.cpu.registers.f.is_set(flag) {
-                            self.cpu.registers.pc = self.mem.get_u16(self.cpu.registers.sp);
-                            self.cpu.registers.sp += 2;
-                            4
-                        } else { 1 }
-                    },
-                    JumpCondition::UnsetFlag(flag) => {
-                        if !self.cpu.registers.f.is_set(flag) {
-                            self.cpu.registers.pc = self.mem.get_u16(self.cpu.registers.sp);
-                            self.cpu.registers.sp += 2;
-                            4
-                        } else { 1 }
-                    }
-                };
-            },
+            /* Conditions that failed have already cut the steps short */
+            JumpImm16(_) | CallImm16(_) | Return(_) => self.cpu.registers.pc = self.cpu.cache,
+            JumpHL => self.cpu.registers.pc = self.cpu.registers.get_r16(Register16::HL),
+            JumpOffImm8(_) => self.cpu.registers.pc = self.cpu.registers.pc.wrapping_add(z as i8 as u16),
             ReturnInterupt => {
-                self.cpu.registers.pc = self.mem.get_u16(self.cpu.registers.pc);
-                self.cpu.registers.sp += 2;
+                debug!(target: "gbemu::cpu::irq", "RETI");
+                self.cpu.registers.pc = self.cpu.cache;
+                /* Unlike EI there is no delay; a pending interrupt is taken next */
                 self.cpu.ime = 1;
-                cycles += 3;
-            },
-            Restart(vector) => {
-                cycles += 1 + self.push(self.cpu.registers.pc);
-                self.cpu.registers.pc = vector as u16;
             },
+            Restart(vector) => self.cpu.registers.pc = vector as u16,
             //}}}
             // Misc. {{{
-            DisableInterrupts => self.cpu.ime = 0,
-            EnableInterrupts => self.cpu.ime = 1,
+            Halt => {
+                debug!(target: "gbemu::cpu", "HALT at {:04X}", self.cpu.registers.pc.wrapping_sub(1));
+                let pending = self.mem.peek(0xFF0F) & self.mem.peek(0xFFFF) & 0x1F;
+                match (self.cpu.ime, pending) {
+                    /* Falls straight through, but fails to advance PC */
+                    (0, 1..) => self.cpu.halt_bug = true,
+                    _ => self.cpu.state = CpuState::Halted,
+                }
+            },
+            Stop => {
+                let pending = self.mem.peek(0xFF0F) & self.mem.peek(0xFFFF) & 0x1F != 0;
+                /* A selected button already held keeps the CPU out of stop mode */
+                let held = self.mem.peek(0xFF00) & 0x0F != 0x0F;
+                debug!(target: "gbemu::cpu", "STOP at {:04X}", self.cpu.registers.pc.wrapping_sub(1));
+                match held {
+                    /* It halts instead, skipping the padding byte unless an
+                     * interrupt is already pending; DIV keeps counting */
+                    true => if !pending {
+                        self.fetch_byte();
+                        self.cpu.state = CpuState::Halted;
+                    },
+                    /* With KEY1 armed it switches speed, which resets DIV, and carries on */
+                    false if self.mem.speed_switch() => {
+                        self.fetch_byte();
+                    },
+                    false => {
+                        if !pending {
+                            self.fetch_byte();
+                        }
+                        self.mem.reset_div();
+                        self.cpu.state = CpuState::Stopped;
+                    },
+                }
+            },
+            DisableInterrupts => {
+                debug!(target: "gbemu::cpu::irq", "DI");
+                self.cpu.ime = 0;
+            },
+            EnableInterrupts => {
+                debug!(target: "gbemu::cpu::irq", "EI");
+                self.cpu.ime_pending = self.cpu.ime == 0;
+            },
             Noop => (),
             //}}}
-            _ => panic!("Cannot execute opcode: {:?}", opcode),
         };
-
-        cycles
-    }
-
-    pub fn call(&mut self, addr: u16, condition: JumpCondition) -> usize {
-        use JumpCondition::*;
-        match condition {
-            Always => {
-                let cyc = self.push(self.cpu.registers.pc);
-                self.cpu.registers.pc = addr;
-                cyc + 1
-            },
-            SetFlag(flag) => {
-                if self.cpu.registers.f.is_set(flag) {
-                    let cyc = self.push(self.cpu.registers.pc);
-                    self.cpu.registers.pc = addr;
-                    cyc + 1
-                } else { 0 }
-            },
-            UnsetFlag(flag) => {
-                if !self.cpu.registers.f.is_set(flag) {
-                    let cyc = self.push(self.cpu.registers.pc);
-                    self.cpu.registers.pc = addr;
-                    cyc + 1
-                } else { 0 }
-            },
-        }
     }
 
-    pub fn push(&mut self, val: u16) -> usize {
-        self.cpu.registers.sp -= 2;
-        self.mem.set_u16(self.cpu.registers.sp, val);
-        2
+    pub fn math(&mut self, op: MathOp, src: u8) {
+        let a = self.cpu.registers.a;
+        let carry = self.cpu.registers.f.is_set(Flags::Carry);
+        let (a, f) = match op {
+            MathOp::Add => alu::add8(a, src),
+            MathOp::Adc => alu::adc8(a, src, carry),
+            MathOp::Sub => alu::sub8(a, src),
+            MathOp::Sbc => alu::sbc8(a, src, carry),
+            MathOp::And => alu::and8(a, src),
+            MathOp::Xor => alu::xor8(a, src),
+            MathOp::Or => alu::or8(a, src),
+            MathOp::Cp => (a, alu::cp8(a, src)),
+        };
+        self.cpu.registers.a = a;
+        self.cpu.registers.f = f;
     }
 
-    pub fn jump(&mut self, addr: u16, condition: JumpCondition) -> usize {
-        use JumpCondition::*;
-        match condition {
-            Always => { self.cpu.registers.pc = addr; 1 },
-            SetFlag(flag) => {
-                if self.cpu.registers.f.is_set(flag) {
-                    self.cpu.registers.pc = addr;
-                    1
-                } else { 0 }
-            },
-            UnsetFlag(flag) => {
-                if !self.cpu.registers.f.is_set(flag) {
-                    self.cpu.registers.pc = addr;
-                    1
-                } else { 0 }
-            },
+    /* Brings the bus up to the second M-cycle of the instruction, where
+     * the IDU drives the old register value, before reporting the access */
+    fn idu_access(&mut self, addr: u16) {
+        if self.ticked == 0 {
+            self.mem.tick(1);
+            self.ticked = 1;
         }
+        self.mem.idu_access(addr);
     }
 
-    pub fn fetch_register_8(&self, reg: OpcodeRegister8) -> (u8, usize) {
-        match reg {
-            OpcodeRegister8::HL => (self.mem.get_u8(self.cpu.registers.get_r16(Register16::HL)), 1),
-            _ => (self.cpu.registers.get_r8(Register8::from(reg)), 0),
+    /* Counts off the M-cycle of a bus access. With per-cycle memory timing
+     * the bus is first brought up to it, so the access sees the
+     * peripherals as of its own cycle rather than the instruction's first. */
+    fn bus_cycle(&mut self) {
+        if self.accuracy.mcycle_memory() && self.ticked < self.access {
+            self.mem.tick(self.access - self.ticked);
+            self.ticked = self.access;
         }
+        self.access += 1;
     }
 
-    #[inline(always)]
-    pub fn fetch_register_16(&self, reg: OpcodeRegister16) -> (u16, usize) {
-        (self.cpu.registers.get_r16(Register16::from(reg)), 1)
+    /* A cycle with no bus access, e.g. for the ALU or IDU */
+    fn internal_cycle(&mut self) {
+        self.access += 1;
     }
 
-    pub fn fetch_byte(&mut self) -> (u8, usize) {
-        let byte = self.mem.get_u8(self.cpu.registers.pc);
-        self.cpu.registers.pc += 1;
-        (byte, 1)
+    fn bus_read(&mut self, addr: u16) -> u8 {
+        self.bus_cycle();
+        self.mem.read(addr)
     }
 
-    pub fn fetch_word(&mut self) -> (u16, usize) {
-        let word = self.mem.get_u16(self.cpu.registers.pc);
-        self.cpu.registers.pc += 2;
-        (word, 2)
+    fn bus_write(&mut self, addr: u16, value: u8) {
+        self.bus_cycle();
+        self.mem.set_u8(addr, value);
     }
 
-    pub fn execute_boot_rom(&mut self) {
-        todo!("Execute Boot ROM")
+    pub fn fetch_byte(&mut self) -> (u8, usize) {
+        self.bus_cycle();
+        let byte = self.mem.fetch(self.cpu.registers.pc, false);
+        self.cpu.registers.pc = self.cpu.registers.pc.wrapping_add(1);
+        (byte, 1)
     }
-
 }
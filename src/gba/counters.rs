@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use super::speed::HostClock;
+
+/* The CPU clock at normal speed, in T-cycles a second */
+pub const CLOCK_RATE: f64 = 4_194_304.0;
+
+/* Running totals for speed displays and benchmarks, kept from when the
+ * console was made through resets and savestate loads. Snapshots are
+ * cheap to copy; the difference of two over the host time between them
+ * gives rates. */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Counters {
+    /* CPU clock cycles, four per M-cycle, so twice as many a frame in
+     * double speed */
+    pub t_cycles: u64,
+    /* Instructions executed; interrupt dispatches and idle cycles are not */
+    pub instructions: u64,
+    /* Frames the LCD finished, counted as it enters VBlank */
+    pub frames: u64,
+}
+
+impl Counters {
+    /* Per second of host time from `earlier` to these, `elapsed` apart */
+    pub fn rates_since(&self, earlier: &Counters, elapsed: Duration) -> CounterRates {
+        let secs = elapsed.as_secs_f64();
+        let rate = |now: u64, then: u64| match secs > 0.0 {
+            true => now.saturating_sub(then) as f64 / secs,
+            false => 0.0,
+        };
+        CounterRates {
+            t_cycles: rate(self.t_cycles, earlier.t_cycles),
+            instructions: rate(self.instructions, earlier.instructions),
+            frames: rate(self.frames, earlier.frames),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct CounterRates {
+    pub t_cycles: f64,
+    pub instructions: f64,
+    pub frames: f64,
+}
+
+impl CounterRates {
+    /* 1.0 when the CPU clock runs as fast as on hardware */
+    pub fn speed(&self) -> f64 {
+        self.t_cycles / CLOCK_RATE
+    }
+}
+
+/* Turns counters into rates once a second of host time, for a display
+ * that should not flicker */
+#[derive(Debug)]
+pub struct RateSampler {
+    clock: Box<dyn HostClock>,
+    last: Option<(Duration, Counters)>,
+    rates: CounterRates,
+}
+
+impl RateSampler {
+    pub fn new(clock: Box<dyn HostClock>) -> Self {
+        Self { clock, last: None, rates: CounterRates::default() }
+    }
+
+    /* Takes the latest counters, returning new rates when a second has
+     * passed since the last ones */
+    pub fn sample(&mut self, counters: Counters) -> Option<CounterRates> {
+        let now = self.clock.now();
+        let Some((then, earlier)) = self.last else {
+            self.last = Some((now, counters));
+            return None;
+        };
+        let elapsed = now.saturating_sub(then);
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+        self.rates = counters.rates_since(&earlier, elapsed);
+        self.last = Some((now, counters));
+        Some(self.rates)
+    }
+
+    /* The rates over the last whole second sampled */
+    pub fn rates(&self) -> CounterRates {
+        self.rates
+    }
+}
@@ -0,0 +1,27 @@
+use std::{any::Any, panic, sync::Mutex};
+
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+/* Chains onto the current hook, keeping the message and location so a
+ * crash dump written once the stack has unwound can say what went wrong */
+pub fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Ok(mut last) = LAST_PANIC.lock() {
+            *last = Some(info.to_string());
+        }
+        previous(info);
+    }));
+}
+
+/* The text a caught panic was raised with */
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload.downcast_ref::<String>().cloned()
+        .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+        .unwrap_or_default()
+}
+
+/* The most recent panic seen by the hook */
+pub fn last_panic() -> Option<String> {
+    LAST_PANIC.lock().ok().and_then(|last| last.clone())
+}
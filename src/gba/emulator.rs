@@ -0,0 +1,841 @@
+use std::{io::ErrorKind, path::{Path, PathBuf}, time::Duration};
+
+use crate::{
+    apu::prelude::{to_i16, Channel},
+    config::prelude::Config,
+    cpu::{prelude::CpuState, register::types::Register16},
+    error::prelude::GbError,
+    input::prelude::{Button, InputSource},
+    mem::prelude::{Boot, BootRom, Cart, RawMap, SaveFormat, Sensor},
+    ppu::prelude::{ColorizeMode, RgbaFrame, Shader, VideoFormat, VideoRecorder, SCREEN_HEIGHT, SCREEN_WIDTH},
+    sgb::prelude::Sgb,
+    state::prelude::{
+        migrate, slot_path, Savestate, Slot, StateHashes, StateReader, StateWriter, OLDEST_STATE_VERSION, SLOTS, STATE_MAGIC,
+        STATE_VERSION,
+    },
+};
+
+use super::{
+    cheats::GameShark,
+    console::Gba,
+    counters::Counters,
+    events::{FrameStats, Notification, StatsMeter},
+    opcode::DecodeError,
+    speed::HostClock,
+    time::{FixedTime, TimeSource, WallClock},
+};
+
+/* Oldest notifications are dropped past this, for frontends that never take them */
+const MAX_NOTIFICATIONS: usize = 32;
+
+/// Where to load a cartridge image from. Either may hold a zip archive,
+/// recognised by its contents, in which case its first ROM is loaded.
+#[derive(Debug, Clone)]
+pub enum RomSource {
+    #[cfg(feature = "io")]
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+    /// A program with no cartridge header, loaded at `origin` in an
+    /// otherwise blank ROM and run from there with the boot ROM skipped.
+    /// For CPU tests and homebrew fragments; `map` picks whether there is
+    /// cartridge RAM.
+    RawBinary { data: Vec<u8>, origin: u16, map: RawMap },
+}
+
+#[cfg(feature = "io")]
+impl From<PathBuf> for RomSource {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+#[cfg(feature = "io")]
+impl From<&str> for RomSource {
+    fn from(path: &str) -> Self {
+        Self::Path(PathBuf::from(path))
+    }
+}
+
+type FrameFn = Box<dyn FnMut(u64, &Gba) -> FrameControl>;
+
+/// What an `on_frame` callback wants done after the frame it was called for.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum FrameControl {
+    #[default]
+    Continue,
+    Pause,
+}
+
+/// The rumble motor changing strength at the end of `frame`: 0 stopped,
+/// 1 full on, and in between when the game pulses it within the frame.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RumbleEvent {
+    pub frame: u64,
+    pub strength: f32,
+}
+
+impl From<Vec<u8>> for RomSource {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+/// The stable entry point for embedding the emulator.
+///
+/// Everything a frontend needs goes through here; the `Gba` console
+/// underneath is reachable through `console`/`console_mut` but its layout
+/// may change between versions.
+pub struct Emulator {
+    gba: Gba,
+    /* Where battery RAM is saved; None for ROMs loaded from memory */
+    save_path: Option<PathBuf>,
+    time: Box<dyn TimeSource>,
+    /* Frames run since power-on */
+    frame: u64,
+    paused: bool,
+    on_frame: Option<FrameFn>,
+    video: Option<VideoRecorder>,
+    shader: Option<Box<dyn Shader>>,
+    /* Last strength reported, and changes not yet taken */
+    rumble: f32,
+    rumble_events: Vec<RumbleEvent>,
+    /* Written into RAM after every frame while enabled */
+    cheats: Vec<GameShark>,
+    cheats_enabled: bool,
+    /* Where save slots go instead of beside the battery save */
+    slot_dir: Option<PathBuf>,
+    notifications: Vec<Notification>,
+    /* Whether the link cable had a peer after the last frame */
+    linked: bool,
+    /* Host time for frame statistics; None leaves fps and speed at 0 */
+    clock: Option<Box<dyn HostClock>>,
+    stats: StatsMeter,
+    /* Hashes at recent frame boundaries while hashing is on */
+    hashes: Option<StateHashes>,
+    /* The first frame a peer's or movie's hash disagreed with ours */
+    desync: Option<u64>,
+}
+
+impl Emulator {
+    /// Loads a cartridge and powers the console on.
+    pub fn new(rom: RomSource) -> Result<Self, GbError> {
+        Self::with_config(rom, &Config::default())
+    }
+
+    /// Loads a cartridge with the core settings (boot ROM, save directory,
+    /// palette, sample rate) taken from `config`. A configured boot ROM
+    /// that is missing or the wrong size is an error; with none configured
+    /// the built-in DMG one runs.
+    pub fn with_config(rom: RomSource, config: &Config) -> Result<Self, GbError> {
+        let boot = match &config.boot_rom {
+            _ if config.skip_boot => Boot::Skip,
+            #[cfg(feature = "io")]
+            Some(path) => Boot::Rom(BootRom::load(path)?),
+            #[cfg(not(feature = "io"))]
+            Some(_) => return Err(GbError::Unsupported("boot ROM files without the `io` feature".to_string())),
+            None => Boot::Embedded,
+        };
+        let (cart, save_path) = Self::open(rom, config)?;
+        Self::power_on(cart, boot, save_path, config)
+    }
+
+    /* The cart and where its battery save belongs */
+    fn open(rom: RomSource, config: &Config) -> Result<(Cart, Option<PathBuf>), GbError> {
+        Ok(match rom {
+            #[cfg(feature = "io")]
+            RomSource::Path(path) => (Cart::new(path.to_string_lossy().into_owned())?, Some(config.save_path(&path))),
+            RomSource::Bytes(bytes) => (Cart::from_bytes(bytes)?, None),
+            RomSource::RawBinary { data, origin, map } => (Cart::raw(&data, origin, map)?, None),
+        })
+    }
+
+    /// Loads a cartridge, and optionally a boot ROM, straight from memory
+    /// for hosts without a filesystem. The config's `boot_rom` path is not
+    /// read: with `boot` None the built-in DMG boot ROM runs, unless
+    /// `skip_boot` is set.
+    pub fn from_bytes(rom: &[u8], boot: Option<&[u8]>, config: &Config) -> Result<Self, GbError> {
+        let boot = match boot {
+            _ if config.skip_boot => Boot::Skip,
+            Some(boot) => Boot::Rom(BootRom::from_bytes(boot.to_vec())?),
+            None => Boot::Embedded,
+        };
+        Self::power_on(Cart::from_bytes(rom.to_vec())?, boot, None, config)
+    }
+
+    fn power_on(cart: Cart, boot: Boot, save_path: Option<PathBuf>, config: &Config) -> Result<Self, GbError> {
+        let sgb = config.sgb && cart.header.supports_sgb();
+        let time: Box<dyn TimeSource> = match config.fixed_time {
+            Some(time) => Box::new(FixedTime(time)),
+            None => Box::new(WallClock),
+        };
+        let mut emulator = Self {
+            gba: Gba::with_boot(cart, boot), save_path, time, frame: 0, paused: false, on_frame: None, video: None, shader: None,
+            rumble: 0.0, rumble_events: Vec::new(), cheats: Vec::new(), cheats_enabled: true, slot_dir: None,
+            notifications: Vec::new(), linked: false, clock: Self::default_clock(), stats: StatsMeter::default(),
+            hashes: None, desync: None,
+        };
+        emulator.gba.mem.fill_ram(config.ram_fill);
+        emulator.gba.set_accuracy(config.for_game(&emulator.game_id()).accuracy);
+        if sgb {
+            emulator.gba.mem.sgb = Some(Sgb::default());
+        }
+        emulator.apply_config(config);
+        emulator.read_battery()?;
+        Ok(emulator)
+    }
+
+    #[cfg(feature = "io")]
+    fn default_clock() -> Option<Box<dyn HostClock>> {
+        Some(Box::new(super::speed::SystemClock::default()))
+    }
+
+    #[cfg(not(feature = "io"))]
+    fn default_clock() -> Option<Box<dyn HostClock>> {
+        None
+    }
+
+    /* Loads the battery save beside the ROM, if there is one */
+    fn read_battery(&mut self) -> Result<(), GbError> {
+        #[cfg(feature = "io")]
+        if let Some(path) = self.save_path.as_ref().filter(|path| path.exists() && self.has_battery()) {
+            let save = std::fs::read(path)?;
+            self.gba.mem.load_battery(&save, self.time.unix_time())?;
+        }
+        Ok(())
+    }
+
+    /// Applies the settings that can change while running, with the
+    /// cart's `[games.ID]` overrides on top.
+    pub fn apply_config(&mut self, config: &Config) {
+        let config = &config.for_game(&self.game_id());
+        self.gba.mem.ppu.set_palette(config.palette);
+        self.set_colorize(config.colorize);
+        self.gba.mem.apu.set_sample_rate(config.sample_rate);
+        for channel in Channel::ALL {
+            self.set_channel_muted(channel, config.mute.contains(&channel));
+        }
+        self.set_solo(config.solo);
+        self.gba.mem.oam_bug = config.oam_bug;
+        self.cheats = config.cheat_codes.clone();
+        self.cheats_enabled = config.cheats;
+        self.slot_dir = config.slot_dir.clone();
+    }
+
+    /// Names the cart's section of per-game settings, `[games.ID]`.
+    pub fn game_id(&self) -> String {
+        self.gba.mem.cart().header_hash()
+    }
+
+    /// Where battery-backed RAM for this cart is kept, following the
+    /// configured save directory.
+    pub fn save_path(&self) -> Option<&Path> {
+        self.save_path.as_deref()
+    }
+
+    /// Sets where the cartridge clock reads the date from when saves are
+    /// loaded and written; the host's clock unless `fixed_time` is set.
+    pub fn set_time_source(&mut self, time: Box<dyn TimeSource>) {
+        self.time = time;
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.gba.mem.cart().header.cart_type.has_battery()
+    }
+
+    /// Writes battery-backed RAM to `save_path`, with the real-time clock
+    /// appended in the format VBA and SameBoy use so a clock keeps running
+    /// across restarts. Does nothing for carts without a battery or ROMs
+    /// loaded from memory.
+    #[cfg(feature = "io")]
+    pub fn save_battery(&mut self) -> Result<(), GbError> {
+        match &self.save_path {
+            Some(path) if self.has_battery() => {
+                std::fs::write(path, self.gba.mem.battery(self.time.unix_time()))?;
+                self.notify(Notification::BatterySaved);
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Battery RAM as a .sav for other emulators or flash carts. `Rtc`
+    /// appends the clock footer VBA, BGB and SameBoy read, for carts with
+    /// a clock; `Raw` is the RAM alone.
+    pub fn export_save(&self, format: SaveFormat) -> Vec<u8> {
+        match format {
+            SaveFormat::Rtc => self.gba.mem.battery(self.time.unix_time()),
+            SaveFormat::Raw => self.gba.mem.cart_ram().to_vec(),
+        }
+    }
+
+    /// Replaces battery RAM with a .sav from another emulator, with or
+    /// without a clock footer. A clock catches up on the time since the
+    /// footer was written.
+    pub fn import_save(&mut self, save: &[u8]) -> Result<(), GbError> {
+        Ok(self.gba.mem.load_battery(save, self.time.unix_time())?)
+    }
+
+    /// Power cycles the console with the same cart, re-running the boot ROM
+    /// unless it was skipped. Cart RAM survives a soft reset; a hard reset
+    /// clears it unless it is battery-backed. Settings, hooks and the link
+    /// cable stay, and the frame count starts again from 0.
+    pub fn reset(&mut self, hard: bool) {
+        self.gba.reset(hard);
+        self.frame = 0;
+    }
+
+    /// Swaps in another cartridge without restarting, for a ROM dropped on
+    /// the window or picked from a menu. The old cart's battery RAM is
+    /// written out first and the new one's save loaded, then the console
+    /// hard resets into it. On a load error the old cart keeps running.
+    pub fn swap_cart(&mut self, rom: RomSource, config: &Config) -> Result<(), GbError> {
+        let (cart, save_path) = Self::open(rom, config)?;
+        #[cfg(feature = "io")]
+        self.save_battery()?;
+        self.gba.swap_cart(cart);
+        if config.sgb && self.gba.mem.cart().header.supports_sgb() {
+            self.gba.mem.sgb = Some(Sgb::default());
+        }
+        self.gba.set_accuracy(config.for_game(&self.game_id()).accuracy);
+        self.apply_config(config);
+        self.save_path = save_path;
+        self.frame = 0;
+        self.read_battery()
+    }
+
+    pub fn console(&self) -> &Gba {
+        &self.gba
+    }
+
+    pub fn console_mut(&mut self) -> &mut Gba {
+        &mut self.gba
+    }
+
+    /// Executes a single instruction, returning the M-cycles it took.
+    ///
+    /// Once the CPU has locked up on an illegal opcode this keeps running
+    /// the peripherals but returns `GbError::Decode`.
+    pub fn step(&mut self) -> Result<usize, GbError> {
+        let cycles = self.gba.step();
+        self.check_locked().map(|_| cycles)
+    }
+
+    /// Runs for one frame's worth of M-cycles, returning the cycles executed.
+    /// While paused nothing runs and this returns 0.
+    ///
+    /// The whole frame runs even if the CPU locks up partway, so there is
+    /// still a picture to show alongside the `GbError::Decode`.
+    pub fn run_frame(&mut self) -> Result<usize, GbError> {
+        if self.paused {
+            return Ok(0);
+        }
+        let cycles = self.gba.run_frame();
+        self.end_frame(cycles);
+        if let Some(on_frame) = &mut self.on_frame {
+            if on_frame(self.frame, &self.gba) == FrameControl::Pause {
+                self.paused = true;
+            }
+        }
+        self.check_locked().map(|_| cycles)
+    }
+
+    /// Finishes a frame of `cycles` M-cycles the console has just run:
+    /// counts it, writes cheats into RAM, captures video, samples rumble
+    /// and the link cable, and records frame statistics and the state
+    /// hash. `run_frame` calls this; a frontend that runs the console
+    /// itself calls it once after every frame.
+    pub fn end_frame(&mut self, cycles: usize) {
+        self.frame += 1;
+        self.apply_cheats();
+        self.capture_frame();
+        self.sample_rumble();
+        self.sample_link();
+        self.record_frame(cycles);
+        self.hash_frame();
+    }
+
+    /// Runs exactly one frame, paused or not, and leaves the emulator paused.
+    pub fn frame_advance(&mut self) -> Result<usize, GbError> {
+        self.paused = false;
+        let cycles = self.run_frame();
+        self.paused = true;
+        cycles
+    }
+
+    /// Resumes and runs until `frame_count` reaches `frame`, then pauses.
+    /// Stops early, also paused, if `on_frame` asks to or the CPU locks up.
+    /// Returns the frame reached.
+    pub fn run_until_frame(&mut self, frame: u64) -> Result<u64, GbError> {
+        self.paused = false;
+        while self.frame < frame && !self.paused {
+            if let Err(e) = self.run_frame() {
+                self.paused = true;
+                return Err(e);
+            }
+        }
+        self.paused = true;
+        Ok(self.frame)
+    }
+
+    /// Frames run since power-on. Loading a state does not change it.
+    pub fn frame_count(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.stats.restart();
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Called after every frame with its number and the console. Returning
+    /// `FrameControl::Pause` pauses before the next one, for stopping at a
+    /// known frame or when something shows up on screen.
+    pub fn set_on_frame<F>(&mut self, on_frame: F) where F: FnMut(u64, &Gba) -> FrameControl + 'static {
+        self.on_frame = Some(Box::new(on_frame));
+    }
+
+    pub fn clear_on_frame(&mut self) {
+        self.on_frame = None;
+    }
+
+    fn check_locked(&self) -> Result<(), GbError> {
+        match self.locked() {
+            Some(opcode) => Err(GbError::Decode(DecodeError(opcode))),
+            None => Ok(()),
+        }
+    }
+
+    /// The illegal opcode the CPU hung on, if any. A locked console keeps
+    /// running its peripherals but never executes another instruction.
+    pub fn locked(&self) -> Option<u8> {
+        match self.gba.cpu.state {
+            CpuState::Locked(opcode) => Some(opcode),
+            _ => None,
+        }
+    }
+
+    /// The last completed frame as packed RGB888, 160x144, through the
+    /// configured palette.
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.gba.mem.ppu.rgb_framebuffer()
+    }
+
+    /// The same as `framebuffer`, written over `rgb` so a frontend can
+    /// reuse one buffer every frame.
+    pub fn render_framebuffer(&self, rgb: &mut Vec<u8>) {
+        self.gba.mem.ppu.render_rgb(rgb);
+    }
+
+    /// The last completed frame as a tightly packed RGBA8 texture, after
+    /// the shader if one is set. Scale it to the window with `ScaleMode`.
+    pub fn rgba_frame(&mut self) -> RgbaFrame {
+        let frame = self.gba.mem.ppu.rgba_framebuffer();
+        match &mut self.shader {
+            Some(shader) => shader.apply(frame),
+            None => frame,
+        }
+    }
+
+    /// Post-processes every frame `rgba_frame` hands out, for CPU filters
+    /// or to pass the texture on to a GPU shader. Screenshots and
+    /// recordings are left untouched.
+    pub fn set_shader<S>(&mut self, shader: S) where S: Shader + 'static {
+        self.shader = Some(Box::new(shader));
+    }
+
+    pub fn clear_shader(&mut self) {
+        self.shader = None;
+    }
+
+    /// Colors a DMG game as the CGB would, with the palettes its boot ROM
+    /// picks for the title or a combination forced, in place of the DMG
+    /// palette. Has no effect on CGB carts.
+    pub fn set_colorize(&mut self, mode: ColorizeMode) {
+        let mem = &mut self.gba.mem;
+        mem.ppu.colorization = mode.colorization(&mem.cart().header, mem.ppu.color_correction);
+    }
+
+    /// The last frame inside its Super Game Boy border as packed RGB888,
+    /// 256x224, colored through the SGB palettes. None unless the cart is
+    /// SGB-capable and `sgb` is enabled in the config.
+    pub fn sgb_framebuffer(&self) -> Option<Vec<u8>> {
+        let sgb = self.gba.mem.sgb.as_ref()?;
+        Some(sgb.render(self.gba.mem.ppu.framebuffer()))
+    }
+
+    /// Interleaved stereo samples produced since the last call, in -1.0..=1.0
+    /// at the configured sample rate. Only the most recent second or so is
+    /// kept if the frontend stops draining them.
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.gba.mem.apu.take_samples()
+    }
+
+    /// `audio_samples` as signed 16-bit PCM.
+    pub fn audio_samples_i16(&mut self) -> Vec<i16> {
+        to_i16(&self.audio_samples())
+    }
+
+    /// Output sample rate in Hz, 48 kHz by default. Resampled from the
+    /// APU's native 1 MiHz.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.gba.mem.apu.set_sample_rate(rate);
+    }
+
+    /// Starts recording the audio handed out by `audio_samples` as a WAV at
+    /// the current sample rate, replacing any recording in progress.
+    pub fn start_wav_capture(&mut self) {
+        self.gba.mem.apu.start_wav();
+    }
+
+    /// Stops recording and returns the WAV file, or None if not recording.
+    pub fn stop_wav_capture(&mut self) -> Option<Vec<u8>> {
+        self.gba.mem.apu.stop_wav()
+    }
+
+    /// Starts logging sound register writes for a VGM file, beginning with
+    /// the registers as they stand so playback starts from the same sound.
+    /// Works without the `audio` feature.
+    pub fn start_vgm_log(&mut self) {
+        self.gba.mem.apu.start_vgm();
+    }
+
+    /// Stops logging and returns the VGM file, or None if not logging.
+    pub fn stop_vgm_log(&mut self) -> Option<Vec<u8>> {
+        self.gba.mem.apu.stop_vgm()
+    }
+
+    /// Starts recording every frame run from here on, through the configured
+    /// palette, stopping by itself after `max_frames` if given. Replaces
+    /// any recording in progress.
+    pub fn start_video_capture(&mut self, format: VideoFormat, max_frames: Option<u32>) {
+        self.video = Some(VideoRecorder::new(format, SCREEN_WIDTH, SCREEN_HEIGHT, max_frames));
+    }
+
+    /// Stops recording and returns the Y4M or GIF file, or None if not
+    /// recording.
+    pub fn stop_video_capture(&mut self) -> Option<Vec<u8>> {
+        self.video.take().map(VideoRecorder::finish)
+    }
+
+    /* Adds the last frame to the video being recorded */
+    fn capture_frame(&mut self) {
+        if let Some(video) = &mut self.video {
+            video.push(&self.gba.mem.ppu.rgb_framebuffer());
+        }
+    }
+
+    /// Frames recorded so far, while recording.
+    pub fn video_frames(&self) -> Option<u32> {
+        self.video.as_ref().map(VideoRecorder::frames)
+    }
+
+    /// Silences a sound channel in the output without affecting emulation,
+    /// for ripping one part of a track or chasing a channel's bugs.
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.gba.mem.apu.mixer.set_muted(channel, muted);
+    }
+
+    pub fn channel_muted(&self, channel: Channel) -> bool {
+        self.gba.mem.apu.mixer.muted(channel)
+    }
+
+    /// Plays only `channel`, overriding the mutes until set back to None.
+    pub fn set_solo(&mut self, solo: Option<Channel>) {
+        self.gba.mem.apu.mixer.set_solo(solo);
+    }
+
+    pub fn solo(&self) -> Option<Channel> {
+        self.gba.mem.apu.mixer.solo()
+    }
+
+    /// What the Pocket Camera sees from now on. False, and nothing
+    /// changes, for any other cart.
+    pub fn set_camera_sensor<S>(&mut self, sensor: S) -> bool where S: Sensor + 'static {
+        match &mut self.gba.mem.camera {
+            Some(camera) => { camera.set_sensor(Box::new(sensor)); true },
+            None => false,
+        }
+    }
+
+    /* Writes the enabled cheat codes into RAM, as a GameShark does once a
+     * frame */
+    fn apply_cheats(&mut self) {
+        if !self.cheats_enabled {
+            return;
+        }
+        for cheat in &self.cheats {
+            cheat.apply(&mut self.gba.mem);
+        }
+    }
+
+    /// Switches the configured cheat codes on or off, as the `cheats`
+    /// setting does, with a `Notification` to say so.
+    pub fn set_cheats_enabled(&mut self, enabled: bool) {
+        self.cheats_enabled = enabled;
+        self.notify(Notification::CheatsToggled(enabled));
+    }
+
+    pub fn cheats_enabled(&self) -> bool {
+        self.cheats_enabled
+    }
+
+    /* Records a `RumbleEvent` if the motor's strength over the frame just
+     * run differs from the last one recorded */
+    fn sample_rumble(&mut self) {
+        let Some(strength) = self.gba.mem.take_rumble() else {
+            return;
+        };
+        if strength != self.rumble {
+            self.rumble = strength;
+            self.rumble_events.push(RumbleEvent { frame: self.frame, strength });
+        }
+    }
+
+    /// Rumble changes since the last call, oldest first. Always empty for
+    /// carts without a motor.
+    pub fn take_rumble_events(&mut self) -> Vec<RumbleEvent> {
+        std::mem::take(&mut self.rumble_events)
+    }
+
+    /* Raises `LinkConnected` or `LinkDisconnected` when the link cable's
+     * peer came or went since the last frame */
+    fn sample_link(&mut self) {
+        let linked = self.gba.mem.serial.connected();
+        if linked != self.linked {
+            self.linked = linked;
+            self.notify(if linked { Notification::LinkConnected } else { Notification::LinkDisconnected });
+        }
+    }
+
+    /* Counts a frame of `cycles` M-cycles towards `frame_stats` */
+    fn record_frame(&mut self, cycles: usize) {
+        let now = self.clock.as_ref().map_or(Duration::ZERO, |clock| clock.now());
+        self.stats.record(now, cycles);
+    }
+
+    /// Hashes the console at the end of every frame from now on, keeping
+    /// the last `history` hashes so a peer or movie that reports late can
+    /// still be checked. Forgets any earlier hashes and desync.
+    pub fn enable_state_hashing(&mut self, history: usize) {
+        self.hashes = Some(StateHashes::new(history));
+        self.desync = None;
+    }
+
+    pub fn disable_state_hashing(&mut self) {
+        self.hashes = None;
+    }
+
+    /* Records the state hash for the frame just run while hashing is on */
+    fn hash_frame(&mut self) {
+        if let Some(hashes) = &mut self.hashes {
+            hashes.push(self.frame, self.gba.state_hash());
+        }
+    }
+
+    /// The hash recorded at the end of `frame`, if it is still kept.
+    pub fn state_hash(&self, frame: u64) -> Option<u64> {
+        self.hashes.as_ref()?.get(frame)
+    }
+
+    /// The newest frame hashed and its hash.
+    pub fn last_state_hash(&self) -> Option<(u64, u64)> {
+        self.hashes.as_ref()?.last()
+    }
+
+    /// Compares another run's hash for `frame` against ours: a netplay
+    /// peer's, or one stored in a movie. None when `frame` has not been
+    /// hashed or has been forgotten. The first mismatch raises a `Desync`
+    /// notification and is kept for `first_desync`.
+    pub fn check_state_hash(&mut self, frame: u64, expected: u64) -> Option<bool> {
+        let matches = self.state_hash(frame)? == expected;
+        if !matches && self.desync.is_none() {
+            self.desync = Some(frame);
+            self.notify(Notification::Desync(frame));
+        }
+        Some(matches)
+    }
+
+    /// The first frame `check_state_hash` found diverged, if any.
+    pub fn first_desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    /// Cycles, instructions and frames run so far, for benchmarks or a
+    /// speed display; `Counters::rates_since` turns two into rates.
+    pub fn counters(&self) -> Counters {
+        self.gba.counters()
+    }
+
+    /// Frame rate and emulation speed, averaged over the last half second
+    /// of host time.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.stats.stats()
+    }
+
+    /// Sets the host clock `frame_stats` measures against. With the `io`
+    /// feature it is the system clock; without, there is none until set.
+    pub fn set_host_clock(&mut self, clock: Box<dyn HostClock>) {
+        self.clock = Some(clock);
+        self.stats.restart();
+    }
+
+    /// Notifications since the last call, oldest first. Only the newest
+    /// are kept when they are never taken.
+    pub fn take_notifications(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    fn notify(&mut self, notification: Notification) {
+        if self.notifications.len() == MAX_NOTIFICATIONS {
+            self.notifications.remove(0);
+        }
+        self.notifications.push(notification);
+    }
+
+    /// Presses or releases a button, raising the joypad interrupt on a press.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.gba.mem.set_button(button, pressed);
+    }
+
+    /// Sets every button from `source` for the frame about to run, for
+    /// frontends that merge keyboards, pads or recorded input.
+    pub fn poll_input(&mut self, source: &mut dyn InputSource) {
+        let state = source.poll(self.frame);
+        self.gba.mem.set_pressed(state.0);
+    }
+
+    /// Serializes the CPU, memory and peripherals. The ROM itself is not
+    /// included, so a state can only be loaded back into the same cart.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(&STATE_MAGIC);
+        w.u8(STATE_VERSION);
+        w.u16(self.cart_id());
+        self.gba.cpu.save_state(&mut w);
+        self.gba.mem.save_state(&mut w);
+        w.into_inner()
+    }
+
+    /// Restores a state made by `save_state`, this version's or any since
+    /// `OLDEST_STATE_VERSION`; older ones are migrated on the way in. A
+    /// state from a newer version, or one too old to migrate, is
+    /// `GbError::Unsupported`. On error the console may be left partially
+    /// restored.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), GbError> {
+        let mut r = StateReader::new(state);
+        let mut magic = [0; 4];
+        r.bytes(&mut magic)?;
+        if magic != STATE_MAGIC {
+            return Err(GbError::Io(ErrorKind::InvalidData));
+        }
+        let version = r.u8()?;
+        if version > STATE_VERSION {
+            return Err(GbError::Unsupported(format!("savestates from a newer version ({})", version)));
+        }
+        if version < OLDEST_STATE_VERSION {
+            return Err(GbError::Unsupported(format!("savestates from version {}", version)));
+        }
+        if r.u16()? != self.cart_id() {
+            return Err(GbError::Io(ErrorKind::InvalidInput));
+        }
+        let body = migrate(version, &state[state.len() - r.remaining()..])?;
+        let mut r = StateReader::new(&body);
+        self.gba.cpu.load_state(&mut r)?;
+        self.gba.mem.load_state(&mut r)?;
+        if r.remaining() != 0 {
+            return Err(GbError::Io(ErrorKind::InvalidData));
+        }
+        Ok(())
+    }
+
+    /// Saves a state with a timestamp and thumbnail to numbered slot
+    /// `slot`, 0 to 9, beside the battery save. Slot 0 is the one autosave
+    /// uses.
+    #[cfg(feature = "io")]
+    pub fn save_slot(&mut self, slot: u8) -> Result<(), GbError> {
+        let path = self.slot_path(slot)?;
+        if let Some(dir) = &self.slot_dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.slot().to_bytes())?;
+        self.notify(Notification::StateSaved(slot));
+        Ok(())
+    }
+
+    /// Restores the state saved to `slot`.
+    #[cfg(feature = "io")]
+    pub fn load_slot(&mut self, slot: u8) -> Result<(), GbError> {
+        let data = std::fs::read(self.slot_path(slot)?)?;
+        self.load_slot_bytes(&data)?;
+        self.notify(Notification::StateLoaded(slot));
+        Ok(())
+    }
+
+    /// What is saved in `slot`, for a slot picker; None when it is empty.
+    #[cfg(feature = "io")]
+    pub fn slot_info(&self, slot: u8) -> Result<Option<Slot>, GbError> {
+        match std::fs::read(self.slot_path(slot)?) {
+            Ok(data) => Ok(Some(Slot::from_bytes(&data)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Restores a slot file written by `save_slot` or `write_crash_dump`.
+    pub fn load_slot_bytes(&mut self, data: &[u8]) -> Result<(), GbError> {
+        self.load_state(&Slot::from_bytes(data)?.state)
+    }
+
+    /// Writes the console as it was when `message` happened, as a slot
+    /// file that `load_slot_bytes` takes, plus a readable report next to
+    /// it. Returns the slot file's path.
+    #[cfg(feature = "io")]
+    pub fn write_crash_dump(&self, dir: &Path, message: &str) -> Result<PathBuf, GbError> {
+        let stem = self.save_path.as_deref().and_then(Path::file_stem).map_or("rom".into(), |stem| stem.to_string_lossy());
+        let path = dir.join(format!("{}-{}.crash", stem, self.time.unix_time()));
+        std::fs::write(&path, self.slot().to_bytes())?;
+        std::fs::write(path.with_extension("crash.txt"), self.crash_report(message))?;
+        Ok(path)
+    }
+
+    fn crash_report(&self, message: &str) -> String {
+        let (cpu, mem) = (&self.gba.cpu, &self.gba.mem);
+        let r16 = |register| cpu.registers.get_r16(register);
+        format!(
+            "{}\ncart: {} ({:#06X})\nAF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} IME={} IE={:02X} IF={:02X} {:?}\n",
+            message, mem.cart().header.title_str(), self.cart_id(),
+            r16(Register16::AF), r16(Register16::BC), r16(Register16::DE), r16(Register16::HL),
+            cpu.registers.sp, cpu.registers.pc, cpu.ime, mem.peek(0xFFFF), mem.peek(0xFF0F), cpu.state,
+        ) + "\nLast instructions, oldest first:\n" + &self.gba.trace_dump()
+    }
+
+    fn slot(&self) -> Slot {
+        Slot::new(self.save_state(), self.time.unix_time(), &self.framebuffer())
+    }
+
+    fn slot_path(&self, slot: u8) -> Result<PathBuf, GbError> {
+        if slot >= SLOTS {
+            return Err(GbError::Io(ErrorKind::InvalidInput));
+        }
+        match (&self.save_path, &self.slot_dir) {
+            (Some(path), None) => Ok(slot_path(path, slot)),
+            (Some(path), Some(dir)) => Ok(dir.join(slot_path(path, slot).file_name().unwrap_or_default())),
+            /* Named after the game, with nothing else to go by */
+            (None, Some(dir)) => Ok(slot_path(&dir.join(self.game_id()), slot)),
+            (None, None) => Err(GbError::Unsupported("save slots for a ROM loaded from memory".to_string())),
+        }
+    }
+
+    /* Global checksum from the header, enough to catch loading the wrong game */
+    fn cart_id(&self) -> u16 {
+        self.gba.mem.cart().header.checksum
+    }
+}
@@ -0,0 +1,87 @@
+use std::{fmt, time::Duration};
+
+use super::speed::FRAME_DURATION;
+
+/* How long frame statistics are averaged over before they update */
+const STATS_WINDOW: Duration = Duration::from_millis(500);
+
+/* Something a frontend may want to tell the player about, such as with
+ * a message drawn over the picture. The `Display` text is short enough
+ * for that. */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Notification {
+    StateSaved(u8),
+    StateLoaded(u8),
+    /* Battery RAM written to the save file */
+    BatterySaved,
+    CheatsToggled(bool),
+    /* A link cable peer came or went */
+    LinkConnected,
+    LinkDisconnected,
+    /* Another run's state hash disagreed at this frame */
+    Desync(u64),
+}
+
+impl fmt::Display for Notification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StateSaved(slot) => write!(f, "Saved state {}", slot),
+            Self::StateLoaded(slot) => write!(f, "Loaded state {}", slot),
+            Self::BatterySaved => write!(f, "Game saved"),
+            Self::CheatsToggled(true) => write!(f, "Cheats on"),
+            Self::CheatsToggled(false) => write!(f, "Cheats off"),
+            Self::LinkConnected => write!(f, "Link cable connected"),
+            Self::LinkDisconnected => write!(f, "Link cable disconnected"),
+            Self::Desync(frame) => write!(f, "Desynced at frame {}", frame),
+        }
+    }
+}
+
+/* How fast the emulator is running, for an FPS counter */
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FrameStats {
+    /* Frames emulated per second of host time */
+    pub fps: f64,
+    /* 1.0 at the console's own speed */
+    pub speed: f64,
+    /* M-cycles the last frame took */
+    pub cycles_per_frame: usize,
+}
+
+/* Averages frame times over STATS_WINDOW so the numbers hold still long
+ * enough to read */
+#[derive(Debug, Clone, Default)]
+pub struct StatsMeter {
+    window_start: Option<Duration>,
+    frames: u32,
+    stats: FrameStats,
+}
+
+impl StatsMeter {
+    /* A frame of `cycles` finished at host time `now` */
+    pub fn record(&mut self, now: Duration, cycles: usize) {
+        self.stats.cycles_per_frame = cycles;
+        let Some(start) = self.window_start else {
+            self.window_start = Some(now);
+            return;
+        };
+        self.frames += 1;
+        let elapsed = now.saturating_sub(start);
+        if elapsed >= STATS_WINDOW {
+            self.stats.fps = self.frames as f64 / elapsed.as_secs_f64();
+            self.stats.speed = self.stats.fps * FRAME_DURATION.as_secs_f64();
+            self.window_start = Some(now);
+            self.frames = 0;
+        }
+    }
+
+    /* Starts over, for after a pause when host time jumped */
+    pub fn restart(&mut self) {
+        self.window_start = None;
+        self.frames = 0;
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+}
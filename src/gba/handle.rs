@@ -0,0 +1,285 @@
+use std::{
+    io::ErrorKind,
+    mem,
+    path::PathBuf,
+    sync::{mpsc::{self, Receiver, Sender, SyncSender, TryRecvError}, Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use crate::{apu::prelude::Channel, config::prelude::Config, ppu::prelude::VideoFormat, error::prelude::GbError, input::prelude::Button, error};
+
+use super::{emulator::{Emulator, RomSource}, pacing::PacingMode, speed::SpeedControl};
+
+/* About a quarter second of audio queued before more is dropped */
+const AUDIO_FRAMES: usize = 16;
+
+#[derive(Debug)]
+enum Request {
+    Pause,
+    Resume,
+    FrameAdvance,
+    Stop,
+    Button(Button, bool),
+    Turbo(bool),
+    AudioQueued(usize),
+    SwapCart(RomSource),
+    Mute(Channel, bool),
+    Solo(Option<Channel>),
+    /* Path, format by its extension, and frame limit */
+    RecordVideo(PathBuf, Option<u32>),
+    StopVideo,
+}
+
+/* The core swaps each finished frame into the shared slot and the
+ * frontend swaps it out for its own buffer when it wants to draw, so
+ * neither side waits on the other for longer than a swap. */
+#[derive(Debug, Default)]
+struct TripleBuffer {
+    shared: Mutex<(Vec<u8>, bool)>,
+}
+
+impl TripleBuffer {
+    fn publish(&self, back: &mut Vec<u8>) {
+        let mut shared = self.shared.lock().unwrap();
+        mem::swap(&mut shared.0, back);
+        shared.1 = true;
+    }
+
+    /* False when nothing new has been published since the last take */
+    fn take(&self, front: &mut Vec<u8>) -> bool {
+        let mut shared = self.shared.lock().unwrap();
+        if !shared.1 {
+            return false;
+        }
+        mem::swap(&mut shared.0, front);
+        shared.1 = false;
+        true
+    }
+}
+
+/// An emulator running on its own thread, paced to real time.
+///
+/// Frames and audio are handed over without blocking either side: the
+/// newest frame replaces any the frontend has not picked up, and audio
+/// queues for a few frames before more of it is dropped. Dropping the
+/// handle stops the thread.
+pub struct EmulatorHandle {
+    requests: Sender<Request>,
+    frames: Arc<TripleBuffer>,
+    front: Vec<u8>,
+    audio: Receiver<Vec<f32>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EmulatorHandle {
+    /// Loads the cartridge on a new thread and starts running it. Load
+    /// errors are reported here rather than from the thread.
+    pub fn spawn(rom: RomSource, config: Config) -> Result<Self, GbError> {
+        let (requests, inbox) = mpsc::channel();
+        let (audio_out, audio) = mpsc::sync_channel(AUDIO_FRAMES);
+        let (ready_out, ready) = mpsc::sync_channel(1);
+        let frames = Arc::new(TripleBuffer::default());
+
+        let shared = Arc::clone(&frames);
+        let thread = thread::spawn(move || {
+            /* Built here since the console holds hooks and a cable that stay on this thread */
+            let emulator = match Emulator::with_config(rom, &config) {
+                Ok(emulator) => emulator,
+                Err(e) => {
+                    let _ = ready_out.send(Err(e));
+                    return;
+                },
+            };
+            let _ = ready_out.send(Ok(()));
+            run(emulator, &config, inbox, &shared, audio_out);
+        });
+
+        match ready.recv() {
+            Ok(Ok(())) => Ok(Self { requests, frames, front: Vec::new(), audio, thread: Some(thread) }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(GbError::Io(ErrorKind::Other)),
+        }
+    }
+
+    pub fn pause(&self) {
+        self.request(Request::Pause);
+    }
+
+    pub fn resume(&self) {
+        self.request(Request::Resume);
+    }
+
+    /// Runs a single frame and pauses, whether or not it was paused before.
+    pub fn frame_advance(&self) {
+        self.request(Request::FrameAdvance);
+    }
+
+    /// Stops the thread and waits for it to finish the current frame.
+    pub fn stop(&mut self) {
+        self.request(Request::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Whether the emulation thread is still alive; it only exits when
+    /// stopped or if the core panics.
+    pub fn running(&self) -> bool {
+        self.thread.as_ref().is_some_and(|thread| !thread.is_finished())
+    }
+
+    pub fn set_button(&self, button: Button, pressed: bool) {
+        self.request(Request::Button(button, pressed));
+    }
+
+    /// Runs as fast as the host allows instead of at 59.7 fps.
+    pub fn set_turbo(&self, turbo: bool) {
+        self.request(Request::Turbo(turbo));
+    }
+
+    /// Reports how many stereo frames the host audio device still has
+    /// queued. With `video.sync = "audio"` the thread paces itself to keep
+    /// this at the configured latency; either way the sample rate is
+    /// trimmed slightly so the queue neither drains nor overflows.
+    pub fn set_audio_queued(&self, frames: usize) {
+        self.request(Request::AudioQueued(frames));
+    }
+
+    /// Mutes or unmutes a sound channel, as `Emulator::set_channel_muted`,
+    /// for a per-channel hotkey.
+    pub fn set_channel_muted(&self, channel: Channel, muted: bool) {
+        self.request(Request::Mute(channel, muted));
+    }
+
+    pub fn set_solo(&self, solo: Option<Channel>) {
+        self.request(Request::Solo(solo));
+    }
+
+    /// Starts recording a clip to `path`, a GIF for a .gif path and Y4M
+    /// otherwise, for a record hotkey. It is written when stopped, at
+    /// `max_frames` or when the thread stops, whichever comes first.
+    pub fn record_video(&self, path: PathBuf, max_frames: Option<u32>) {
+        self.request(Request::RecordVideo(path, max_frames));
+    }
+
+    pub fn stop_video(&self) {
+        self.request(Request::StopVideo);
+    }
+
+    /// Loads a different ROM in place of the running one, for drag and drop.
+    /// The old cart's save is written first; a ROM that fails to load is
+    /// logged and the old one keeps running.
+    pub fn swap_cart(&self, rom: RomSource) {
+        self.request(Request::SwapCart(rom));
+    }
+
+    /// The newest frame as packed RGB888, 160x144, if one has completed
+    /// since the last call.
+    pub fn frame(&mut self) -> Option<&[u8]> {
+        self.frames.take(&mut self.front).then_some(&self.front)
+    }
+
+    /// Interleaved stereo samples queued since the last call, as
+    /// `Emulator::audio_samples`.
+    pub fn audio_samples(&self) -> Vec<f32> {
+        self.audio.try_iter().flatten().collect()
+    }
+
+    fn request(&self, request: Request) {
+        /* A dead thread has nothing left to control */
+        let _ = self.requests.send(request);
+    }
+}
+
+impl Drop for EmulatorHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run(mut emulator: Emulator, config: &Config, inbox: Receiver<Request>, frames: &TripleBuffer, audio: SyncSender<Vec<f32>>) {
+    let mut speed = SpeedControl::default();
+    /* Presents happen on the frontend's thread, out of reach of this loop */
+    speed.pacer.set_mode(match config.pacing {
+        PacingMode::Vsync => PacingMode::Timer,
+        mode => mode,
+    });
+    speed.pacer.set_audio_target(config.audio_buffer_frames(), config.sample_rate);
+    let (mut paused, mut queued, mut rate) = (false, None, config.sample_rate);
+    let mut video: Option<(PathBuf, Option<u32>)> = None;
+    /* Rendered into, then traded for whichever buffer was published last */
+    let mut back = Vec::new();
+    loop {
+        /* Block while paused, otherwise just drain what has arrived */
+        let request = match paused {
+            true => inbox.recv().map_err(|_| TryRecvError::Disconnected),
+            false => inbox.try_recv(),
+        };
+        match request {
+            Ok(Request::Pause) => {
+                paused = true;
+                emulator.pause();
+            },
+            Ok(Request::Resume) => {
+                paused = false;
+                emulator.resume();
+                speed.pacer.reset();
+            },
+            Ok(Request::FrameAdvance) => {
+                paused = true;
+                let _ = emulator.frame_advance();
+                emulator.render_framebuffer(&mut back);
+                frames.publish(&mut back);
+                let _ = audio.try_send(emulator.audio_samples());
+            },
+            Ok(Request::Stop) | Err(TryRecvError::Disconnected) => {
+                write_video(&mut emulator, video.take());
+                if let Err(e) = emulator.save_battery() {
+                    error!(target: "gbemu::mem", "Failed to write the battery save: {}", e);
+                }
+                return;
+            },
+            Ok(Request::Button(button, pressed)) => emulator.set_button(button, pressed),
+            Ok(Request::Turbo(turbo)) => speed.set_turbo(turbo),
+            Ok(Request::AudioQueued(frames)) => queued = Some(frames),
+            Ok(Request::RecordVideo(path, max_frames)) => {
+                write_video(&mut emulator, video.take());
+                emulator.start_video_capture(VideoFormat::from_path(&path), max_frames);
+                video = Some((path, max_frames));
+            },
+            Ok(Request::StopVideo) => write_video(&mut emulator, video.take()),
+            Ok(Request::Mute(channel, muted)) => emulator.set_channel_muted(channel, muted),
+            Ok(Request::Solo(solo)) => emulator.set_solo(solo),
+            Ok(Request::SwapCart(rom)) => {
+                if let Err(e) = emulator.swap_cart(rom, config) {
+                    error!(target: "gbemu::mem", "Failed to swap carts: {}", e);
+                }
+            },
+            Err(TryRecvError::Empty) => {
+                /* A locked CPU still shows its last picture; nothing to report here */
+                let _ = emulator.run_frame();
+                if video.as_ref().is_some_and(|&(_, max_frames)| max_frames.is_some() && emulator.video_frames() == max_frames) {
+                    write_video(&mut emulator, video.take());
+                }
+                emulator.render_framebuffer(&mut back);
+                frames.publish(&mut back);
+                let _ = audio.try_send(emulator.audio_samples());
+                speed.end_frame_with_audio(queued.take());
+                let trimmed = (config.sample_rate as f64 * speed.audio_ratio()).round() as u32;
+                if trimmed != rate {
+                    rate = trimmed;
+                    emulator.set_sample_rate(rate);
+                }
+            },
+        }
+    }
+}
+
+fn write_video(emulator: &mut Emulator, video: Option<(PathBuf, Option<u32>)>) {
+    let Some((path, _)) = video else { return };
+    if let Some(clip) = emulator.stop_video_capture() {
+        if let Err(e) = std::fs::write(&path, clip) {
+            error!(target: "gbemu::ppu", "Failed to write `{}`: {}", path.display(), e);
+        }
+    }
+}
@@ -1,2 +1,31 @@
+use crate::{cpu::proc::CpuState, mem::prelude::Bus};
+
 use super::console::Gba;
 
+impl<B: Bus> Gba<B> {
+    /* Advances the console by exactly one M-cycle, returning true when the
+     * next call starts a new instruction. Each call runs one step of the
+     * instruction's microcode, so its bus access lands on its own cycle.
+     * Mixing in `step` finishes the instruction first. */
+    pub fn tick(&mut self) -> bool {
+        let done = if !self.mid_instruction() && self.cpu.state != CpuState::Running {
+            self.idle(1);
+            true
+        } else {
+            let start = self.resume_bus();
+            let done = self.run_cycle().is_some();
+            /* Accesses may already have moved the bus on */
+            self.mem.tick(start + 1 - self.ticked);
+            done
+        };
+        self.cycles += 1;
+        /* Bring the peripherals up to date so they can be looked at */
+        self.mem.sync();
+        done
+    }
+
+    /* Whether `tick` is partway through an instruction */
+    pub fn mid_instruction(&self) -> bool {
+        self.cpu.microcode.in_flight()
+    }
+}
@@ -1,10 +1,35 @@
 #![allow(unused)]
 
+pub mod accuracy;
+pub mod cheats;
 pub mod console;
+pub mod counters;
+pub mod crash;
+pub mod emulator;
+pub mod events;
+#[cfg(feature = "io")]
+pub mod handle;
 pub mod opcode;
 pub mod mcycle;
+pub mod pacing;
+pub mod speed;
+pub mod time;
+pub mod timing;
 
 pub mod prelude {
+    pub use super::accuracy::AccuracyLevel;
+    pub use super::cheats::GameShark;
     pub use super::console::Gba;
-    pub use super::opcode::Opcode;
+    pub use super::counters::{CounterRates, Counters, RateSampler, CLOCK_RATE};
+    pub use super::crash::{install_panic_hook, last_panic, panic_message};
+    pub use super::emulator::{Emulator, FrameControl, RomSource, RumbleEvent};
+    pub use super::events::{FrameStats, Notification, StatsMeter};
+    #[cfg(feature = "io")]
+    pub use super::handle::EmulatorHandle;
+    pub use super::opcode::{DecodeError, Opcode};
+    pub use super::pacing::{Pacer, PacingMode};
+    pub use super::speed::{FrameSkip, HostClock, SpeedControl};
+    #[cfg(feature = "io")]
+    pub use super::speed::SystemClock;
+    pub use super::time::{FixedTime, TimeSource, WallClock};
 }
@@ -1,6 +1,8 @@
+use std::sync::OnceLock;
+
 use crate::cpu::register::types::Flags;
 
-use self::types::{JumpCondition, LoadDirection, MathOp, OpcodeIndirectRegister16, OpcodeRegister16, OpcodeRegister8};
+use self::types::{JumpCondition, LoadDirection, MathOp, OpcodeIndirectRegister16, OpcodeRegister16, OpcodeRegister8, ShiftOp};
 
 // mod types {{{
 pub mod types {
@@ -8,7 +10,7 @@ pub mod types {
 
     // enum OpcodeRegister8 {{{
     #[repr(u8)]
-    #[derive(Debug)]
+    #[derive(Debug, Copy, Clone)]
     pub enum OpcodeRegister8 {
         B = 0, C, D, E, H, L, HL, A
     }
@@ -16,19 +18,25 @@ pub mod types {
     impl From<u8> for OpcodeRegister8 {
         fn from(value: u8) -> Self {
             match value {
-                0..=7 => unsafe { std::mem::transmute(value) },
+                0..=7 => unsafe { std::mem::transmute::<u8, OpcodeRegister8>(value) },
                 _ => panic!("Unrecognized value for OpcodeRegister8: {:?}", value),
             }
         }
     }
 
+    /* (HL) is a memory operand; callers handle it before converting */
     impl From<OpcodeRegister8> for Register8 {
         fn from(value: OpcodeRegister8) -> Self {
             use OpcodeRegister8::*;
             match value {
-                HL => Register8::F,
+                B => Register8::B,
+                C => Register8::C,
+                D => Register8::D,
+                E => Register8::E,
+                H => Register8::H,
+                L => Register8::L,
                 A => Register8::A,
-                _ => unsafe { std::mem::transmute(value as u8) },
+                HL => panic!("(HL) is not a register"),
             }
         }
     }
@@ -36,7 +44,7 @@ pub mod types {
 
     // enum OpcodeRegister16Indirect {{{
     #[repr(u8)]
-    #[derive(Debug)]
+    #[derive(Debug, Copy, Clone)]
     pub enum OpcodeIndirectRegister16 {
         BC = 0, DE, HLInc, HLDec,
     }
@@ -44,7 +52,7 @@ pub mod types {
     impl From<u8> for OpcodeIndirectRegister16 {
         fn from(value: u8) -> Self {
             match value {
-                0..=3 => unsafe { std::mem::transmute(value) },
+                0..=3 => unsafe { std::mem::transmute::<u8, OpcodeIndirectRegister16>(value) },
                 _ => panic!("Unrecognized value for OpcodeRegister16Indirect: {:?}", value),
             }
         }
@@ -63,7 +71,7 @@ pub mod types {
     // }}}
 
     // enum LoadDirection {{{
-    #[derive(Debug)]
+    #[derive(Debug, Copy, Clone)]
     pub enum LoadDirection {
         Memory,
         Accumulator,
@@ -71,30 +79,48 @@ pub mod types {
     // }}}
 
     // enum OpcodeRegister16 {{{
-    #[repr(u8)]
-    #[derive(Debug)]
+    /* Encoding 3 is SP for loads and arithmetic but AF for PUSH / POP */
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum OpcodeRegister16 {
-        BC = 0, DE, HL, AF,
+        BC, DE, HL, SP, AF,
     }
 
     impl From<u8> for OpcodeRegister16 {
         fn from(value: u8) -> Self {
             match value {
-                0..=3 => unsafe { std::mem::transmute(value) },
+                0 => Self::BC,
+                1 => Self::DE,
+                2 => Self::HL,
+                3 => Self::SP,
                 _ => panic!("Unrecognized value for OpcodeRegister16: {:?}", value),
             }
         }
     }
 
+    impl OpcodeRegister16 {
+        pub fn from_stack(value: u8) -> Self {
+            match value {
+                3 => Self::AF,
+                _ => Self::from(value),
+            }
+        }
+    }
+
     impl From<OpcodeRegister16> for Register16 {
         fn from(value: OpcodeRegister16) -> Self {
-            unsafe { std::mem::transmute(value) }
+            match value {
+                OpcodeRegister16::BC => Register16::BC,
+                OpcodeRegister16::DE => Register16::DE,
+                OpcodeRegister16::HL => Register16::HL,
+                OpcodeRegister16::SP => Register16::SP,
+                OpcodeRegister16::AF => Register16::AF,
+            }
         }
     }
     // }}}
 
     // enum JumpCondition {{{
-    #[derive(Debug)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum JumpCondition {
         Always,
         SetFlag(Flags),
@@ -112,16 +138,45 @@ pub mod types {
     impl From<u8> for MathOp {
         fn from(value: u8) -> Self {
             match value {
-                0..=7 => unsafe { std::mem::transmute(value) },
+                0..=7 => unsafe { std::mem::transmute::<u8, MathOp>(value) },
                 _ => panic!("Unrecognized value for MathOp: `${:#02X}`", value),
             }
         }
     }
     //}}}
+
+    // enum ShiftOp {{{
+    #[repr(u8)]
+    #[derive(Debug, Copy, Clone)]
+    pub enum ShiftOp {
+        Rlc = 0, Rrc, Rl, Rr, Sla, Sra, Swap, Srl,
+    }
+
+    impl From<u8> for ShiftOp {
+        fn from(value: u8) -> Self {
+            match value {
+                0..=7 => unsafe { std::mem::transmute::<u8, ShiftOp>(value) },
+                _ => panic!("Unrecognized value for ShiftOp: `${:#02X}`", value),
+            }
+        }
+    }
+    //}}}
 }
 //}}}
 
-#[derive(Debug)]
+/* One of the 11 unused opcodes, which hang the CPU on hardware */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DecodeError(pub u8);
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Illegal opcode `${:02X}`", self.0)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Debug, Copy, Clone)]
 pub enum Opcode {
     // 8-bit Loads {{{
     LoadR8(OpcodeRegister8, OpcodeRegister8),
@@ -164,6 +219,12 @@ pub enum Opcode {
     RotateRightCircularAccumulator,
     RotateLeftAccumulator,
     RotateRightAccumulator,
+    /* $CB, the actual operation is in the following byte */
+    Prefix,
+    RotateShift(ShiftOp, OpcodeRegister8),
+    TestBit(u8, OpcodeRegister8),
+    ResetBit(u8, OpcodeRegister8),
+    SetBit(u8, OpcodeRegister8),
     // }}}
 
     // Control Flow {{{
@@ -185,26 +246,60 @@ pub enum Opcode {
     // }}}
 }
 
-impl From<u8> for Opcode {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for Opcode {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::decode(value)
+    }
+}
+
+/* Every byte decoded once up front, so the hot path is a single load */
+static DECODE_TABLE: OnceLock<[Result<Opcode, DecodeError>; 256]> = OnceLock::new();
+
+impl Opcode {
+    #[inline(always)]
+    pub fn decode(value: u8) -> Result<Self, DecodeError> {
+        DECODE_TABLE.get_or_init(|| std::array::from_fn(|i| Self::decode_slow(i as u8)))[value as usize]
+    }
+
+    /* Second byte of a $CB instruction; every value is defined */
+    pub fn decode_prefixed(value: u8) -> Self {
+        use Opcode::*;
+        let reg = OpcodeRegister8::from(value & 0x07);
+        let bit = (value >> 3) & 0x07;
+        match value >> 6 {
+            0 => RotateShift(ShiftOp::from(bit), reg),
+            1 => TestBit(bit, reg),
+            2 => ResetBit(bit, reg),
+            _ => SetBit(bit, reg),
+        }
+    }
+
+    fn decode_slow(value: u8) -> Result<Self, DecodeError> {
         use Opcode::*;
         let low = value & 0x0F;
         let high = value >> 4;
-        match value {
+        Ok(match value {
             0x00 => Noop,
             0x07 => RotateLeftCircularAccumulator,
             0x08 => LoadIndImm16SP,
             0x0F => RotateRightCircularAccumulator,
             0x10 => Stop,
             0x17 => RotateLeftAccumulator,
+            0x18 => JumpOffImm8(JumpCondition::Always),
             0x1F => RotateRightAccumulator,
             0x20 => JumpOffImm8(JumpCondition::UnsetFlag(Flags::Zero)),
             0x27 => DecimalAdjustAccumulator,
+            0x28 => JumpOffImm8(JumpCondition::SetFlag(Flags::Zero)),
             0x2F => ComplementAccumulator,
             0x30 => JumpOffImm8(JumpCondition::UnsetFlag(Flags::Carry)),
             0x37 => SetCarryFlag,
+            0x38 => JumpOffImm8(JumpCondition::SetFlag(Flags::Carry)),
             0x3F => ComplementCarryFlag,
 
+            0x76 => Halt,
+
             0x40..=0x7F => LoadR8(OpcodeRegister8::from((value & 0x38) >> 3), OpcodeRegister8::from(value & 0x07)),
             0x80..=0xBF => MathR8(MathOp::from((value & 0x38) >> 3), OpcodeRegister8::from(value & 0x07)),
 
@@ -215,7 +310,7 @@ impl From<u8> for Opcode {
             0xC8 => Return(JumpCondition::SetFlag(Flags::Zero)),
             0xC9 => Return(JumpCondition::Always),
             0xCA => JumpImm16(JumpCondition::SetFlag(Flags::Zero)),
-            0xCB => panic!("Incomplete 8-bit Opcode: `$CB`"),
+            0xCB => Prefix,
             0xCC => CallImm16(JumpCondition::SetFlag(Flags::Zero)),
             0xCD => CallImm16(JumpCondition::Always),
             0xD0 => Return(JumpCondition::UnsetFlag(Flags::Carry)),
@@ -252,19 +347,19 @@ impl From<u8> for Opcode {
                     0x0C => IncR8(OpcodeRegister8::from((high << 1) | 1)),
                     0x0D => DecR8(OpcodeRegister8::from((high << 1) | 1)),
                     0x0E => LoadImm8(OpcodeRegister8::from((high << 1) | 1)),
-                    _ => panic!("Uncaught Opcode: `${:#02X}`", value),
+                    _ => return Err(DecodeError(value)),
                 },
                 0x0C..=0x0F => match low {
-                    0x01 => PopR16(OpcodeRegister16::from(high & 0x03)),
-                    0x05 => PushR16(OpcodeRegister16::from(high & 0x03)),
+                    0x01 => PopR16(OpcodeRegister16::from_stack(high & 0x03)),
+                    0x05 => PushR16(OpcodeRegister16::from_stack(high & 0x03)),
                     0x06 => MathImm8(MathOp::from((high & 0x03) << 1)),
                     0x07 => Restart((high & 0x03) << 4),
                     0x0E => MathImm8(MathOp::from(((high & 0x03) << 1) | 1)),
                     0x0F => Restart(((high & 0x03) << 4) | 0x08),
-                    _ => panic!("Uncaught Opcode: `${:#02X}`", value),
+                    _ => return Err(DecodeError(value)),
                 },
-                _ => unreachable!(),
+                _ => return Err(DecodeError(value)),
             },
-        }
+        })
     }
 }
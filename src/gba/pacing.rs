@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use super::speed::HostClock;
+
+/* Frames owed at most after a stall; the rest is forgotten */
+const MAX_CATCHUP: u32 = 8;
+/* Displays within this of the emulated rate show exactly one frame per
+ * refresh, with the audio resampled to make up the difference */
+const VSYNC_LOCK: f64 = 0.01;
+/* Widest audio rate adjustment for queue drift, about 9 cents of pitch */
+const MAX_RATE_DELTA: f64 = 0.005;
+/* Weight of the newest refresh interval in the running estimate */
+const REFRESH_SMOOTHING: f64 = 0.125;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum PacingMode {
+    /* Sleep to each frame's deadline on the host clock */
+    #[default]
+    Timer,
+    /* The frontend blocks in its vsynced present; run what each refresh covers */
+    Vsync,
+    /* Wait for the host audio queue to drain to its target depth */
+    Audio,
+}
+
+impl PacingMode {
+    pub const NAMES: [&'static str; 3] = ["timer", "vsync", "audio"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "timer" => Some(Self::Timer),
+            "vsync" => Some(Self::Vsync),
+            "audio" => Some(Self::Audio),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct AudioTarget {
+    /* Stereo frames to keep queued on the host */
+    frames: usize,
+    sample_rate: u32,
+}
+
+/* Holds the frontend loop to the emulated frame rate. Whatever the clock
+ * source, deadlines are kept on an absolute schedule so sleep overshoot
+ * and refresh mismatch do not add up, and the audio rate is nudged to
+ * keep the host queue from draining or overflowing. */
+#[derive(Debug)]
+pub struct Pacer {
+    pub mode: PacingMode,
+    clock: Box<dyn HostClock>,
+    /* Timer: when the current frame is due */
+    deadline: Option<Duration>,
+    /* Vsync: the last present, the smoothed refresh interval and emulated time not yet run */
+    last_vsync: Option<Duration>,
+    refresh: Option<Duration>,
+    owed: Duration,
+    audio: Option<AudioTarget>,
+    queued: Option<usize>,
+}
+
+impl Pacer {
+    pub fn new(mode: PacingMode, clock: Box<dyn HostClock>) -> Self {
+        Self { mode, clock, deadline: None, last_vsync: None, refresh: None, owed: Duration::ZERO, audio: None, queued: None }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    pub fn set_mode(&mut self, mode: PacingMode) {
+        self.mode = mode;
+        self.reset();
+    }
+
+    /* Forget the schedule, after a pause or a speed change */
+    pub fn reset(&mut self) {
+        self.deadline = None;
+        self.last_vsync = None;
+        self.owed = Duration::ZERO;
+    }
+
+    /* How deep the host audio queue should be kept, in stereo frames */
+    pub fn set_audio_target(&mut self, frames: usize, sample_rate: u32) {
+        self.audio = Some(AudioTarget { frames, sample_rate });
+    }
+
+    /* When the current frame is due on the timer schedule, if one is running */
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+
+    /* The measured display refresh interval */
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh
+    }
+
+    /* Frames of `frame` length to run before the next present. Always one,
+     * except in vsync mode: none when the display outpaces emulation,
+     * several when it lags or presents were missed. */
+    pub fn frames_due(&mut self, frame: Duration) -> u32 {
+        if self.mode != PacingMode::Vsync {
+            return 1;
+        }
+        let now = self.clock.now();
+        let Some(last) = self.last_vsync.replace(now) else {
+            return 1;
+        };
+        let interval = now.saturating_sub(last);
+        if interval > frame * MAX_CATCHUP {
+            /* A stall, not a refresh: carry on without a burst of frames */
+            self.owed = Duration::ZERO;
+            return 1;
+        }
+
+        let refresh = match self.refresh {
+            Some(refresh) => refresh.mul_f64(1.0 - REFRESH_SMOOTHING) + interval.mul_f64(REFRESH_SMOOTHING),
+            None => interval,
+        };
+        self.refresh = Some(refresh);
+        if self.vsync_locked(frame) {
+            return 1;
+        }
+
+        self.owed += interval;
+        let due = (self.owed.as_nanos() / frame.as_nanos().max(1)) as u32;
+        self.owed -= frame * due;
+        due.min(MAX_CATCHUP)
+    }
+
+    /* Called once a frame and its audio are handed to the host. `queued`
+     * is what the host audio queue still holds, in stereo frames, if the
+     * frontend has measured it since the last frame. */
+    pub fn end_frame(&mut self, frame: Duration, queued: Option<usize>) {
+        if queued.is_some() {
+            self.queued = queued;
+        }
+        match (self.mode, self.audio, queued) {
+            (PacingMode::Vsync, _, _) => {},
+            (PacingMode::Audio, Some(target), Some(queued)) => {
+                /* The sound card is the clock: wait for it to play the excess */
+                let excess = queued.saturating_sub(target.frames) as f64 / target.sample_rate.max(1) as f64;
+                let wait = Duration::from_secs_f64(excess).min(frame * 2);
+                if !wait.is_zero() {
+                    self.clock.sleep(wait);
+                }
+            },
+            _ => self.wait_deadline(frame),
+        }
+    }
+
+    /* Factor for the APU output rate. Above one while the host queue runs
+     * low, below while it fills, and scaled by the refresh mismatch when
+     * vsync shows one frame per refresh. */
+    pub fn audio_ratio(&self, frame: Duration) -> f64 {
+        let base = match (self.vsync_locked(frame), self.refresh) {
+            (true, Some(refresh)) => refresh.as_secs_f64() / frame.as_secs_f64(),
+            _ => 1.0,
+        };
+        match (self.audio, self.queued) {
+            (Some(target), Some(queued)) if target.frames > 0 => {
+                let drift = (queued as f64 - target.frames as f64) / target.frames as f64;
+                base * (1.0 - drift.clamp(-1.0, 1.0) * MAX_RATE_DELTA)
+            },
+            _ => base,
+        }
+    }
+
+    fn vsync_locked(&self, frame: Duration) -> bool {
+        self.mode == PacingMode::Vsync
+            && self.refresh.is_some_and(|refresh| (refresh.as_secs_f64() / frame.as_secs_f64() - 1.0).abs() < VSYNC_LOCK)
+    }
+
+    fn wait_deadline(&mut self, frame: Duration) {
+        let now = self.clock.now();
+        let deadline = self.deadline.unwrap_or(now) + frame;
+        if deadline > now {
+            self.clock.sleep(deadline - now);
+            self.deadline = Some(deadline);
+        } else if now - deadline > frame * MAX_CATCHUP {
+            /* Too far behind to catch up, resynchronise instead */
+            self.deadline = Some(now);
+        } else {
+            self.deadline = Some(deadline);
+        }
+    }
+}
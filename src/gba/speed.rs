@@ -0,0 +1,154 @@
+use std::{fmt::Debug, thread, time::{Duration, Instant}};
+
+use super::pacing::{Pacer, PacingMode};
+
+/* 4194304 Hz / 70224 T-cycles per frame ~= 59.73 fps */
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+
+/* Frames rendered at most this often while skipping */
+const DISPLAY_INTERVAL: Duration = Duration::from_nanos(16_666_667);
+const MAX_AUTO_SKIP: u32 = 8;
+/* OS sleeps overshoot by up to a scheduler tick; the last stretch yields instead */
+const SPIN: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum FrameSkip {
+    #[default]
+    Off,
+    /* Render one frame, then skip `n` */
+    Fixed(u32),
+    /* Skip rendering whenever emulation falls behind or outpaces the display */
+    Auto,
+}
+
+/* Host wall clock used for pacing. Hosts without `Instant` or blocking
+ * sleeps (wasm32-unknown-unknown) supply their own. */
+pub trait HostClock: Debug {
+    /* Monotonic time since an arbitrary, fixed origin */
+    fn now(&self) -> Duration;
+    fn sleep(&self, duration: Duration);
+}
+
+#[cfg(feature = "io")]
+#[derive(Debug)]
+pub struct SystemClock {
+    origin: Instant,
+}
+
+#[cfg(feature = "io")]
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self { origin: Instant::now() }
+    }
+}
+
+#[cfg(feature = "io")]
+impl HostClock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        if duration > SPIN {
+            thread::sleep(duration - SPIN);
+        }
+        while Instant::now() < until {
+            thread::yield_now();
+        }
+    }
+}
+
+/* Paces the run loop. Skipped frames still run the full PPU timing,
+ * only the pixel output is dropped. */
+#[derive(Debug)]
+pub struct SpeedControl {
+    pub multiplier: f64,
+    pub turbo: bool,
+    pub frame_skip: FrameSkip,
+    pub pacer: Pacer,
+    last_render: Option<Duration>,
+    skipped: u32,
+}
+
+#[cfg(feature = "io")]
+impl Default for SpeedControl {
+    fn default() -> Self {
+        Self::with_clock(Box::new(SystemClock::default()))
+    }
+}
+
+impl SpeedControl {
+    pub fn with_clock(clock: Box<dyn HostClock>) -> Self {
+        Self {
+            multiplier: 1.0,
+            turbo: false,
+            frame_skip: FrameSkip::default(),
+            pacer: Pacer::new(PacingMode::Timer, clock),
+            last_render: None,
+            skipped: 0,
+        }
+    }
+
+    pub fn set_multiplier(&mut self, multiplier: f64) {
+        self.multiplier = multiplier.max(0.01);
+        self.pacer.reset();
+    }
+
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+        self.pacer.reset();
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        FRAME_DURATION.div_f64(self.multiplier)
+    }
+
+    /* Decide whether the upcoming frame should be rendered */
+    pub fn render_next(&mut self) -> bool {
+        let now = self.pacer.now();
+        let render = match self.frame_skip {
+            FrameSkip::Off => true,
+            FrameSkip::Fixed(n) => self.skipped >= n,
+            FrameSkip::Auto => {
+                let behind = !self.turbo && self.pacer.deadline().is_some_and(|deadline| now > deadline);
+                let too_soon = (self.turbo || self.multiplier > 1.0)
+                    && self.last_render.is_some_and(|last| now - last < DISPLAY_INTERVAL);
+                self.skipped >= MAX_AUTO_SKIP || !(behind || too_soon)
+            },
+        };
+
+        if render {
+            self.skipped = 0;
+            self.last_render = Some(now);
+        } else {
+            self.skipped += 1;
+        }
+        render
+    }
+
+    /* Frames to run before the next present; see `Pacer::frames_due` */
+    pub fn frames_due(&mut self) -> u32 {
+        match self.turbo {
+            true => 1,
+            false => self.pacer.frames_due(self.frame_duration()),
+        }
+    }
+
+    /* Wait until the next frame is due, unless running unlocked */
+    pub fn end_frame(&mut self) {
+        self.end_frame_with_audio(None);
+    }
+
+    /* The same, given the stereo frames still queued for the host audio device */
+    pub fn end_frame_with_audio(&mut self, queued: Option<usize>) {
+        if !self.turbo {
+            self.pacer.end_frame(self.frame_duration(), queued);
+        }
+    }
+
+    /* Factor for the APU sample rate that keeps the host audio queue steady */
+    pub fn audio_ratio(&self) -> f64 {
+        self.pacer.audio_ratio(self.frame_duration())
+    }
+}
@@ -0,0 +1,28 @@
+use std::{fmt::Debug, time::{SystemTime, UNIX_EPOCH}};
+
+/* The date as the cartridge clock sees it: read when a battery save is
+ * loaded, to catch the RTC up, and stamped into saves. Everything else in
+ * the core runs off emulated cycles alone. */
+pub trait TimeSource: Debug {
+    /* Seconds since the UNIX epoch */
+    fn unix_time(&self) -> u64;
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct WallClock;
+
+impl TimeSource for WallClock {
+    fn unix_time(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+    }
+}
+
+/* A date that never moves, for movies, netplay and tests */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FixedTime(pub u64);
+
+impl TimeSource for FixedTime {
+    fn unix_time(&self) -> u64 {
+        self.0
+    }
+}
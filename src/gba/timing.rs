@@ -0,0 +1,142 @@
+use crate::warn;
+
+/* Expected M-cycles for every primary opcode as (not taken, taken).
+ * Unconditional instructions use the same value twice, illegal opcodes
+ * are 0 and $CB counts only the prefix fetch; see `prefixed_cycles`. */
+pub static OPCODE_CYCLES: [(u8, u8); 256] = {
+    const fn row(r: [u8; 16]) -> [(u8, u8); 16] {
+        let mut out = [(0, 0); 16];
+        let mut i = 0;
+        while i < 16 {
+            out[i] = (r[i], r[i]);
+            i += 1;
+        }
+        out
+    }
+
+    let rows: [[u8; 16]; 16] = [
+        [1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1],
+        [1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1],
+        [2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1],
+        [2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1],
+        [1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1],
+        [1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1],
+        [1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1],
+        [2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1],
+        [1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1],
+        [1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1],
+        [1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1],
+        [1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1],
+        [2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 1, 3, 6, 2, 4],
+        [2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4],
+        [3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4],
+        [3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4],
+    ];
+
+    let mut table = [(0, 0); 256];
+    let mut r = 0;
+    while r < 16 {
+        let cols = row(rows[r]);
+        let mut c = 0;
+        while c < 16 {
+            table[(r << 4) | c] = cols[c];
+            c += 1;
+        }
+        r += 1;
+    }
+
+    /* Conditional JR / RET / JP / CALL */
+    let mut cc = 0;
+    while cc < 4 {
+        let off = cc << 3;
+        table[0x20 + off] = (2, 3);
+        table[0xC0 + off] = (2, 5);
+        table[0xC2 + off] = (3, 4);
+        table[0xC4 + off] = (3, 6);
+        cc += 1;
+    }
+    table
+};
+
+pub fn is_conditional(opcode: u8) -> bool {
+    matches!(opcode, 0x20 | 0x28 | 0x30 | 0x38
+        | 0xC0 | 0xC8 | 0xD0 | 0xD8
+        | 0xC2 | 0xCA | 0xD2 | 0xDA
+        | 0xC4 | 0xCC | 0xD4 | 0xDC)
+}
+
+/* Whole $CB instruction, prefix included */
+pub fn prefixed_cycles(opcode: u8) -> usize {
+    match (opcode & 0x07, opcode >> 6) {
+        (6, 1) => 3,
+        (6, _) => 4,
+        _ => 2,
+    }
+}
+
+pub fn expected_cycles(opcode: u8, taken: bool) -> usize {
+    let (not_taken, taken_cycles) = OPCODE_CYCLES[opcode as usize];
+    (if taken { taken_cycles } else { not_taken }) as usize
+}
+
+#[derive(Debug, Clone)]
+pub struct CycleMismatch {
+    pub pc: u16,
+    pub opcode: u8,
+    pub taken: bool,
+    pub expected: usize,
+    pub actual: usize,
+}
+
+#[derive(Debug)]
+pub struct CycleValidator {
+    pub enabled: bool,
+    pub mismatches: Vec<CycleMismatch>,
+    reported: [bool; 256],
+    reported_prefixed: [bool; 256],
+}
+
+impl Default for CycleValidator {
+    fn default() -> Self {
+        Self { enabled: false, mismatches: Vec::new(), reported: [false; 256], reported_prefixed: [false; 256] }
+    }
+}
+
+impl CycleValidator {
+    pub fn check(&mut self, pc: u16, opcode: u8, taken: bool, actual: usize) {
+        let expected = expected_cycles(opcode, taken);
+        if expected == 0 || expected == actual {
+            return;
+        }
+
+        let mismatch = CycleMismatch { pc, opcode, taken, expected, actual };
+        if !self.reported[opcode as usize] {
+            self.reported[opcode as usize] = true;
+            warn!(target: "gbemu::cpu", "Cycle mismatch for opcode `${:02X}` at `${:04X}`{}: expected {}, got {}",
+                opcode, pc, if is_conditional(opcode) { if taken { " (taken)" } else { " (not taken)" } } else { "" },
+                expected, actual);
+        }
+        self.mismatches.push(mismatch);
+    }
+
+    /* `opcode` is the byte following $CB; mismatches are recorded against $CB */
+    pub fn check_prefixed(&mut self, pc: u16, opcode: u8, actual: usize) {
+        let expected = prefixed_cycles(opcode);
+        if expected == actual {
+            return;
+        }
+
+        if !self.reported_prefixed[opcode as usize] {
+            self.reported_prefixed[opcode as usize] = true;
+            warn!(target: "gbemu::cpu", "Cycle mismatch for opcode `$CB ${:02X}` at `${:04X}`: expected {}, got {}",
+                opcode, pc, expected, actual);
+        }
+        self.mismatches.push(CycleMismatch { pc, opcode: 0xCB, taken: false, expected, actual });
+    }
+
+    pub fn clear(&mut self) {
+        self.mismatches.clear();
+        self.reported = [false; 256];
+        self.reported_prefixed = [false; 256];
+    }
+}
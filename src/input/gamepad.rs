@@ -0,0 +1,415 @@
+use std::fmt;
+
+use super::joypad::Button;
+
+/* Game controllers. Buttons and axes are numbered as the Linux joystick
+ * API numbers them, which is also what the pad backend reads; bindings
+ * map them onto the Game Boy's eight buttons, per controller. The backend
+ * reads the kernel's devices itself rather than going through gilrs, as
+ * the crate takes no dependencies, so pads only work on Linux for now. */
+
+/* How far an axis must lean, out of 32767, to count as a d-pad press */
+pub const AXIS_THRESHOLD: i16 = 0x4000;
+
+/* One control on a pad: a button, or one direction of an axis */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PadInput {
+    Button(u8),
+    /* Axis number and whether it is the positive direction */
+    Axis(u8, bool),
+}
+
+impl PadInput {
+    /* `button 3`, `axis 1-` or `axis 1+` */
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (kind, number) = spec.trim().split_once(' ')?;
+        match kind {
+            "button" => number.trim().parse().ok().map(Self::Button),
+            "axis" => {
+                let number = number.trim();
+                let positive = match number.chars().last()? {
+                    '+' => true,
+                    '-' => false,
+                    _ => return None,
+                };
+                number[..number.len() - 1].parse().ok().map(|axis| Self::Axis(axis, positive))
+            },
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PadInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Button(number) => write!(f, "button {}", number),
+            Self::Axis(number, positive) => write!(f, "axis {}{}", number, if *positive { '+' } else { '-' }),
+        }
+    }
+}
+
+/* The pad controls that press each `Button`, in `Button` order */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PadBindings(pub [Vec<PadInput>; 8]);
+
+/* An Xbox-style layout: the left stick and the hat move, the east and
+ * south face buttons are A and B as they sit on a Game Boy, and Back and
+ * Start are Select and Start */
+impl Default for PadBindings {
+    fn default() -> Self {
+        use PadInput::{Axis, Button};
+        Self([
+            vec![Axis(0, true), Axis(6, true)],
+            vec![Axis(0, false), Axis(6, false)],
+            vec![Axis(1, false), Axis(7, false)],
+            vec![Axis(1, true), Axis(7, true)],
+            vec![Button(1)],
+            vec![Button(0)],
+            vec![Button(6)],
+            vec![Button(7)],
+        ])
+    }
+}
+
+impl PadBindings {
+    pub fn inputs(&self, button: Button) -> &[PadInput] {
+        &self.0[button as usize]
+    }
+
+    pub fn set(&mut self, button: Button, inputs: Vec<PadInput>) {
+        self.0[button as usize] = inputs;
+    }
+
+    /* A comma-separated list as the config file spells it */
+    pub fn parse_list(spec: &str) -> Option<Vec<PadInput>> {
+        spec.split(',').filter(|spec| !spec.trim().is_empty()).map(PadInput::parse).collect()
+    }
+
+    pub fn list(&self, button: Button) -> String {
+        self.inputs(button).iter().map(PadInput::to_string).collect::<Vec<_>>().join(", ")
+    }
+
+    /* One bit per `Button` for a pad in `state` */
+    pub fn pressed(&self, state: &PadState) -> u8 {
+        (0..8).filter(|&button| self.0[button].iter().any(|&input| state.active(input)))
+            .fold(0, |pressed, button| pressed | 1 << button)
+    }
+}
+
+/* What a pad's controls are doing, built up from its events */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PadState {
+    buttons: Vec<bool>,
+    axes: Vec<i16>,
+}
+
+impl PadState {
+    pub fn set_button(&mut self, number: u8, pressed: bool) {
+        grow(&mut self.buttons, number)[number as usize] = pressed;
+    }
+
+    pub fn set_axis(&mut self, number: u8, value: i16) {
+        grow(&mut self.axes, number)[number as usize] = value;
+    }
+
+    pub fn active(&self, input: PadInput) -> bool {
+        match input {
+            PadInput::Button(number) => self.buttons.get(number as usize).copied().unwrap_or(false),
+            PadInput::Axis(number, positive) => {
+                let value = self.axes.get(number as usize).copied().unwrap_or(0);
+                if positive { value >= AXIS_THRESHOLD } else { value <= -AXIS_THRESHOLD }
+            },
+        }
+    }
+}
+
+fn grow<T: Default + Clone>(values: &mut Vec<T>, number: u8) -> &mut Vec<T> {
+    if values.len() <= number as usize {
+        values.resize(number as usize + 1, T::default());
+    }
+    values
+}
+
+/* Config names for controllers: the name the driver reports, lowercased,
+ * with runs of anything else than letters and digits as one `_` */
+pub fn pad_id(name: &str) -> String {
+    let mut id = String::new();
+    for c in name.trim().chars() {
+        if c.is_ascii_alphanumeric() {
+            id.push(c.to_ascii_lowercase());
+        } else if !id.is_empty() && !id.ends_with('_') {
+            id.push('_');
+        }
+    }
+    id.trim_end_matches('_').to_string()
+}
+
+#[cfg(all(feature = "io", target_os = "linux"))]
+pub use self::linux::{Gamepad, Gamepads};
+
+/* The one call into libc: uploading a force feedback effect, which has
+ * no write() form. Kept apart so the rest of the pad code stays safe. */
+#[cfg(all(feature = "io", target_os = "linux"))]
+mod ff {
+    use std::{
+        ffi::{c_int, c_ulong},
+        fs::File,
+        os::fd::AsRawFd,
+    };
+
+    const FF_RUMBLE: u16 = 0x50;
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    /* struct ff_effect with the rumble member of its union, padded out to
+     * the largest member so the kernel copies the size it expects */
+    #[repr(C)]
+    struct FfEffect {
+        kind: u16,
+        id: i16,
+        direction: u16,
+        trigger: [u16; 2],
+        replay: [u16; 2],
+        effect: FfUnion,
+    }
+
+    /* Strong then weak magnitude, then ff_periodic_effect's remaining
+     * fields, whose pointer sets the union's alignment */
+    #[repr(C)]
+    struct FfUnion {
+        magnitudes: [u16; 9],
+        custom_len: u32,
+        custom_data: *mut i16,
+    }
+
+    /* _IOW('E', 0x80, struct ff_effect) */
+    fn eviocsff() -> c_ulong {
+        1 << 30 | (std::mem::size_of::<FfEffect>() as c_ulong) << 16 | (b'E' as c_ulong) << 8 | 0x80
+    }
+
+    /* Uploads a rumble effect with both motors at `magnitude` to the evdev
+     * node `file`, replacing effect `id`, or a new one if -1. Returns the
+     * ID the kernel gave it, or None if the device refused. */
+    pub fn upload_rumble(file: &File, id: i16, magnitude: u16) -> Option<i16> {
+        let mut effect = FfEffect {
+            kind: FF_RUMBLE,
+            id,
+            direction: 0,
+            trigger: [0; 2],
+            /* A length of 0 plays until stopped */
+            replay: [0; 2],
+            effect: FfUnion { magnitudes: [0; 9], custom_len: 0, custom_data: std::ptr::null_mut() },
+        };
+        effect.effect.magnitudes[..2].copy_from_slice(&[magnitude; 2]);
+        // SAFETY: the descriptor is open for as long as `file` is borrowed,
+        // the request encodes the size of `FfEffect`, which is laid out as
+        // the kernel's struct ff_effect, and the kernel only reads it and
+        // writes back its `id` before returning. A rumble effect never has
+        // the kernel follow `custom_data`, which stays null.
+        let result = unsafe { ioctl(file.as_raw_fd(), eviocsff(), &mut effect as *mut FfEffect) };
+        (result >= 0).then_some(effect.id)
+    }
+}
+
+/* The joystick API needs no libraries: each /dev/input/jsN yields 8-byte
+ * events, and on open a burst of them describing the current state.
+ * Rumble goes through the pad's evdev node instead, whose force feedback
+ * takes one ioctl to upload an effect and an event to play or stop it. */
+#[cfg(all(feature = "io", target_os = "linux"))]
+mod linux {
+    use std::{
+        collections::BTreeMap,
+        ffi::c_long,
+        fs::{self, File, OpenOptions},
+        io::{ErrorKind, Read, Write},
+        os::unix::fs::OpenOptionsExt,
+        path::{Path, PathBuf},
+    };
+
+    use crate::{debug, info};
+
+    use super::{super::source::{ButtonState, InputSource}, ff, pad_id, PadBindings, PadState};
+
+    const O_NONBLOCK: i32 = 0o4000;
+    const JS_EVENT_BUTTON: u8 = 0x01;
+    const JS_EVENT_AXIS: u8 = 0x02;
+    const JS_EVENT_INIT: u8 = 0x80;
+    /* Polls between looks for newly plugged pads, about a second of frames */
+    const RESCAN_POLLS: u32 = 60;
+    const EV_FF: u16 = 0x15;
+
+    /* A pad's motors, through its /dev/input/eventN */
+    #[derive(Debug)]
+    struct ForceFeedback {
+        file: File,
+        /* The uploaded effect, -1 before the first */
+        effect: i16,
+    }
+
+    impl ForceFeedback {
+        /* The event node beside joystick `node`, if it can be written */
+        fn open(node: &str) -> Option<Self> {
+            let event = fs::read_dir(format!("/sys/class/input/{}/device", node)).ok()?
+                .flatten()
+                .find_map(|entry| entry.file_name().to_str().filter(|name| name.starts_with("event")).map(String::from))?;
+            let file = OpenOptions::new().read(true).write(true).open(format!("/dev/input/{}", event)).ok()?;
+            Some(Self { file, effect: -1 })
+        }
+
+        /* Both motors at `strength`, 0 to 1; false if the pad refused */
+        fn set(&mut self, strength: f32) -> bool {
+            if strength <= 0.0 {
+                return self.effect < 0 || self.play(false);
+            }
+            let magnitude = (strength.min(1.0) * u16::MAX as f32) as u16;
+            match ff::upload_rumble(&self.file, self.effect, magnitude) {
+                Some(effect) => self.effect = effect,
+                None => return false,
+            }
+            self.play(true)
+        }
+
+        /* Writes a struct input_event, its timestamp left zero */
+        fn play(&mut self, on: bool) -> bool {
+            let mut event = vec![0; 2 * std::mem::size_of::<c_long>()];
+            event.extend_from_slice(&EV_FF.to_ne_bytes());
+            event.extend_from_slice(&(self.effect as u16).to_ne_bytes());
+            event.extend_from_slice(&(on as i32).to_ne_bytes());
+            self.file.write_all(&event).is_ok()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct Gamepad {
+        pub path: PathBuf,
+        /* As the driver reports it, and as `pad_id` makes it a config name */
+        pub name: String,
+        pub id: String,
+        pub state: PadState,
+        file: File,
+        /* None for pads without motors, or whose event node is not ours */
+        rumble: Option<ForceFeedback>,
+    }
+
+    impl Gamepad {
+        pub fn open(path: &Path) -> Option<Self> {
+            let file = OpenOptions::new().read(true).custom_flags(O_NONBLOCK).open(path).ok()?;
+            let node = path.file_name()?.to_str()?;
+            let name = fs::read_to_string(format!("/sys/class/input/{}/device/name", node))
+                .map(|name| name.trim().to_string())
+                .unwrap_or_else(|_| node.to_string());
+            let rumble = ForceFeedback::open(node);
+            Some(Self { path: path.to_path_buf(), id: pad_id(&name), name, state: PadState::default(), file, rumble })
+        }
+
+        pub fn can_rumble(&self) -> bool {
+            self.rumble.is_some()
+        }
+
+        /* Shakes the pad at `strength`, 0 to 1, until told otherwise */
+        pub fn set_rumble(&mut self, strength: f32) {
+            if let Some(rumble) = &mut self.rumble {
+                if !rumble.set(strength) {
+                    debug!(target: "gbemu::input", "Gamepad {} refused force feedback", self.name);
+                    self.rumble = None;
+                }
+            }
+        }
+
+        /* Applies the events waiting; false once the pad is unplugged */
+        fn update(&mut self) -> bool {
+            let mut event = [0; 8];
+            loop {
+                match self.file.read(&mut event) {
+                    Ok(8) => (),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+                    _ => return false,
+                }
+                let value = i16::from_le_bytes([event[4], event[5]]);
+                match event[6] & !JS_EVENT_INIT {
+                    JS_EVENT_BUTTON => self.state.set_button(event[7], value != 0),
+                    JS_EVENT_AXIS => self.state.set_axis(event[7], value),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    /* Every pad plugged in, found again as they come and go. Their
+     * buttons are merged, so any pad can play; add them to an
+     * `InputMerger` to play alongside the keyboard. */
+    #[derive(Debug, Default)]
+    pub struct Gamepads {
+        pads: Vec<Gamepad>,
+        polls: u32,
+        /* Strength every pad shakes at, so newly plugged ones join in */
+        rumble: f32,
+        /* By controller ID; others get the default layout */
+        pub bindings: BTreeMap<String, PadBindings>,
+    }
+
+    impl Gamepads {
+        pub fn new(bindings: BTreeMap<String, PadBindings>) -> Self {
+            Self { bindings, ..Self::default() }
+        }
+
+        pub fn pads(&self) -> &[Gamepad] {
+            &self.pads
+        }
+
+        /* Reads every pad and returns the buttons held on any of them */
+        pub fn read(&mut self) -> ButtonState {
+            if self.polls % RESCAN_POLLS == 0 {
+                self.rescan();
+            }
+            self.polls = self.polls.wrapping_add(1);
+
+            self.pads.retain_mut(|pad| {
+                let connected = pad.update();
+                if !connected {
+                    info!(target: "gbemu::input", "Gamepad disconnected: {}", pad.name);
+                }
+                connected
+            });
+            let default = PadBindings::default();
+            ButtonState(self.pads.iter().fold(0, |pressed, pad| pressed | self.bindings.get(&pad.id).unwrap_or(&default).pressed(&pad.state)))
+        }
+
+        /* Every pad at `strength`, 0 to 1 */
+        pub fn set_rumble(&mut self, strength: f32) {
+            self.rumble = strength;
+            for pad in &mut self.pads {
+                pad.set_rumble(strength);
+            }
+        }
+
+        fn rescan(&mut self) {
+            let Ok(entries) = fs::read_dir("/dev/input") else {
+                return;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_joystick = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("js"));
+                if !is_joystick || self.pads.iter().any(|pad| pad.path == path) {
+                    continue;
+                }
+                if let Some(mut pad) = Gamepad::open(&path) {
+                    info!(target: "gbemu::input", "Gamepad connected: {} as [pads.{}]", pad.name, pad.id);
+                    if self.rumble > 0.0 {
+                        pad.set_rumble(self.rumble);
+                    }
+                    self.pads.push(pad);
+                }
+            }
+        }
+    }
+
+    /* Pads are read as they are now, whatever the frame */
+    impl InputSource for Gamepads {
+        fn poll(&mut self, _frame: u64) -> ButtonState {
+            self.read()
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::{io::ErrorKind, ops::RangeInclusive};
+
+use crate::{cpu::interrupt::Interrupt, mem::prelude::MemoryMappedDevice, state::prelude::{Savestate, StateReader, StateWriter}};
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Button {
+    Right = 0,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl From<u8> for Button {
+    fn from(value: u8) -> Self {
+        match value {
+            0..=7 => unsafe { std::mem::transmute::<u8, Button>(value) },
+            _ => panic!("Invalid value for Button: {:?}", value),
+        }
+    }
+}
+
+/* P1 / JOYP: bits 4 and 5 select the d-pad and button rows, the low
+ * nibble reads back the selected rows active-low. */
+#[derive(Debug)]
+pub struct Joypad {
+    pub p1: u8,
+    /* One bit per `Button`, set while held */
+    pressed: u8,
+    /* Low nibble with no row selected; the SGB puts the player ID here */
+    idle: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self { p1: 0xFF, pressed: 0, idle: 0x0F }
+    }
+}
+
+impl Joypad {
+    pub fn pressed(&self) -> u8 {
+        self.pressed
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.pressed & (1 << button as u8) != 0
+    }
+
+    pub fn write_register(&mut self, value: u8) {
+        self.p1 = 0xC0 | (value & 0x30) | (self.p1 & 0x0F);
+        self.refresh();
+    }
+
+    pub fn set_idle_lines(&mut self, lines: u8) {
+        self.idle = lines & 0x0F;
+        self.refresh();
+    }
+
+    /* Returns the IF bits to raise, set when a selected line goes low */
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> u8 {
+        let mask = 1 << button as u8;
+        self.set_pressed(if pressed { self.pressed | mask } else { self.pressed & !mask })
+    }
+
+    pub fn set_pressed(&mut self, pressed: u8) -> u8 {
+        let before = self.p1 & 0x0F;
+        self.pressed = pressed;
+        self.refresh();
+        if before & !self.p1 & 0x0F != 0 { Interrupt::Joypad as u8 } else { 0 }
+    }
+
+    fn refresh(&mut self) {
+        let mut lines = if self.p1 & 0x30 == 0x30 { self.idle } else { 0x0F };
+        if self.p1 & 0x10 == 0 { lines &= !(self.pressed & 0x0F); }
+        if self.p1 & 0x20 == 0 { lines &= !(self.pressed >> 4); }
+        self.p1 = (self.p1 & 0xF0) | lines;
+    }
+}
+
+const CLAIMS: &[RangeInclusive<u16>] = &[0xFF00..=0xFF00];
+
+impl MemoryMappedDevice for Joypad {
+    fn claims(&self, _cgb: bool) -> &'static [RangeInclusive<u16>] {
+        CLAIMS
+    }
+
+    fn io_register(&self, _addr: u16) -> &u8 {
+        &self.p1
+    }
+
+    fn io_register_mut(&mut self, _addr: u16) -> &mut u8 {
+        &mut self.p1
+    }
+
+    fn io_write(&mut self, _addr: u16, value: u8) -> u8 {
+        self.write_register(value);
+        0
+    }
+}
+
+impl Savestate for Joypad {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.p1);
+        w.u8(self.pressed);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.p1 = r.u8()?;
+        self.pressed = r.u8()?;
+        Ok(())
+    }
+}
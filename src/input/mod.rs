@@ -0,0 +1,13 @@
+#![allow(unused)]
+
+mod gamepad;
+mod joypad;
+mod source;
+
+pub mod prelude {
+    pub use super::gamepad::{pad_id, PadBindings, PadInput, PadState, AXIS_THRESHOLD};
+    #[cfg(all(feature = "io", target_os = "linux"))]
+    pub use super::gamepad::{Gamepad, Gamepads};
+    pub use super::joypad::{Button, Joypad};
+    pub use super::source::{turbo_down, Binding, BindingTable, ButtonState, InputMerger, InputSource, Keyboard};
+}
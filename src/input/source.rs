@@ -0,0 +1,191 @@
+use std::collections::BTreeSet;
+
+use super::joypad::Button;
+
+/* Input independent of where it comes from. A source turns whatever the
+ * host reads, keys, pads, a movie or the network, into the buttons held
+ * for a frame; bindings say which host control holds which button. */
+
+/* 4 MiHz over the 70224 T-cycles of a frame */
+const FRAME_RATE: (u64, u64) = (1 << 22, 70224);
+
+/* Buttons held for one frame, one bit per `Button` as `Joypad` takes them */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ButtonState(pub u8);
+
+impl ButtonState {
+    pub fn is_pressed(self, button: Button) -> bool {
+        self.0 & 1 << button as u8 != 0
+    }
+
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        match pressed {
+            true => self.0 |= 1 << button as u8,
+            false => self.0 &= !(1 << button as u8),
+        }
+    }
+
+    pub fn with(mut self, button: Button) -> Self {
+        self.set(button, true);
+        self
+    }
+}
+
+/* Something that decides which buttons are held each frame */
+pub trait InputSource {
+    /* The buttons held for `frame`, counted from power-on. Sources should
+     * depend on nothing else that varies between runs, so that replays
+     * and both sides of a netplay session see the same input. */
+    fn poll(&mut self, frame: u64) -> ButtonState;
+}
+
+impl<F> InputSource for F where F: FnMut(u64) -> ButtonState {
+    fn poll(&mut self, frame: u64) -> ButtonState {
+        self(frame)
+    }
+}
+
+/* What a host control does: hold a button, or tap it over and over */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Binding {
+    Hold(Button),
+    Turbo(Button),
+}
+
+/* Host controls to the buttons they press. A control may press several
+ * buttons and a button may have several controls. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindingTable<K> {
+    bindings: Vec<(K, Binding)>,
+    /* Turbo taps per second */
+    pub turbo_rate: u32,
+}
+
+impl<K: PartialEq> Default for BindingTable<K> {
+    fn default() -> Self {
+        Self::new(10)
+    }
+}
+
+impl<K: PartialEq> BindingTable<K> {
+    pub fn new(turbo_rate: u32) -> Self {
+        Self { bindings: Vec::new(), turbo_rate }
+    }
+
+    pub fn bind(&mut self, control: K, binding: Binding) {
+        if !self.bindings.iter().any(|(bound, existing)| *bound == control && *existing == binding) {
+            self.bindings.push((control, binding));
+        }
+    }
+
+    /* Drops everything bound to `button`, held or turbo, to rebind it */
+    pub fn unbind(&mut self, button: Button) {
+        self.bindings.retain(|(_, binding)| !matches!(binding, Binding::Hold(bound) | Binding::Turbo(bound) if *bound == button));
+    }
+
+    pub fn controls(&self, button: Button) -> impl Iterator<Item = (&K, Binding)> {
+        self.bindings.iter()
+            .filter(move |(_, binding)| matches!(binding, Binding::Hold(bound) | Binding::Turbo(bound) if *bound == button))
+            .map(|(control, binding)| (control, *binding))
+    }
+
+    /* The buttons `held` controls press on `frame`. Turbo buttons are down
+     * for the first half of each tap, timed from the frame number alone. */
+    pub fn resolve<'a>(&self, held: impl IntoIterator<Item = &'a K>, frame: u64) -> ButtonState where K: 'a {
+        let tap = turbo_down(frame, self.turbo_rate);
+        let mut state = ButtonState::default();
+        for control in held {
+            for (bound, binding) in &self.bindings {
+                match binding {
+                    Binding::Hold(button) if bound == control => state.set(*button, true),
+                    Binding::Turbo(button) if bound == control && tap => state.set(*button, true),
+                    _ => (),
+                }
+            }
+        }
+        state
+    }
+}
+
+/* Whether a turbo button at `rate` taps per second is down on `frame` */
+pub fn turbo_down(frame: u64, rate: u32) -> bool {
+    /* Half taps elapsed by the start of the frame */
+    (frame * FRAME_RATE.1 * 2 * rate as u64 / FRAME_RATE.0) % 2 == 0
+}
+
+/* Keys by name as a windowing backend reports them, matched without
+ * regard to case */
+#[derive(Debug, Clone, Default)]
+pub struct Keyboard {
+    pub bindings: BindingTable<String>,
+    held: BTreeSet<String>,
+}
+
+impl Keyboard {
+    pub fn new(bindings: BindingTable<String>) -> Self {
+        let mut table = BindingTable::new(bindings.turbo_rate);
+        for (key, binding) in bindings.bindings {
+            table.bind(key.to_ascii_lowercase(), binding);
+        }
+        Self { bindings: table, held: BTreeSet::new() }
+    }
+
+    pub fn key_down(&mut self, key: &str) {
+        self.held.insert(key.to_ascii_lowercase());
+    }
+
+    pub fn key_up(&mut self, key: &str) {
+        self.held.remove(&key.to_ascii_lowercase());
+    }
+
+    /* After losing focus, when key-up events go elsewhere */
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+}
+
+impl InputSource for Keyboard {
+    fn poll(&mut self, frame: u64) -> ButtonState {
+        self.bindings.resolve(&self.held, frame)
+    }
+}
+
+/* Several sources played together, say a keyboard and two pads. Every
+ * source is polled each frame in the order added and their buttons are
+ * combined, so the result depends only on what each source returned. */
+#[derive(Default)]
+pub struct InputMerger {
+    sources: Vec<Box<dyn InputSource>>,
+    /* Let Left+Right and Up+Down through; the hardware never sees them,
+     * and some games glitch on them. Otherwise both directions are dropped. */
+    pub allow_opposites: bool,
+}
+
+impl InputMerger {
+    pub fn add<S>(&mut self, source: S) where S: InputSource + 'static {
+        self.sources.push(Box::new(source));
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+}
+
+impl InputSource for InputMerger {
+    fn poll(&mut self, frame: u64) -> ButtonState {
+        let mut state = self.sources.iter_mut().fold(ButtonState::default(), |state, source| ButtonState(state.0 | source.poll(frame).0));
+        if !self.allow_opposites {
+            for (a, b) in [(Button::Left, Button::Right), (Button::Up, Button::Down)] {
+                if state.is_pressed(a) && state.is_pressed(b) {
+                    state.set(a, false);
+                    state.set(b, false);
+                }
+            }
+        }
+        state
+    }
+}
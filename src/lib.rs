@@ -0,0 +1,6227 @@
+pub mod apu;
+pub mod config;
+pub mod cpu;
+pub mod debugger;
+pub mod error;
+pub mod mem;
+pub mod gba;
+pub mod input;
+pub mod link;
+pub mod log;
+pub mod ppu;
+#[cfg(feature = "io")]
+pub mod rom_library;
+pub mod scheduler;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod sgb;
+pub mod state;
+pub mod timer;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod wasm;
+
+pub use crate::{
+    apu::prelude::Channel,
+    error::prelude::GbError,
+    gba::prelude::{Emulator, RomSource},
+    input::prelude::Button,
+    ppu::prelude::{Palette, SCREEN_HEIGHT, SCREEN_WIDTH},
+    sgb::prelude::{SGB_HEIGHT, SGB_WIDTH},
+};
+#[cfg(feature = "io")]
+pub use crate::gba::prelude::EmulatorHandle;
+
+#[cfg(test)]
+mod gba_test {
+    use std::path::PathBuf;
+
+    use crate::{
+        gba::prelude::Gba,
+        mem::prelude::{Cart, NINTENDO_GRAPHIC},
+    };
+
+    /* A blank 32kB image with `title` in its header, the rest filled in a
+     * field at a time: `rom(b"GAME").cart_type(0x03).ram_size(0x02).build()` */
+    struct Rom(Vec<u8>);
+
+    fn rom(title: &[u8]) -> Rom {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14B] = 0x33;
+        Rom(rom)
+    }
+
+    #[cfg_attr(not(feature = "io"), allow(unused))]
+    impl Rom {
+        fn logo(mut self) -> Self {
+            self.0[0x104..0x134].copy_from_slice(&NINTENDO_GRAPHIC);
+            self
+        }
+
+        fn cgb(mut self, flag: u8) -> Self {
+            self.0[0x143] = flag;
+            self
+        }
+
+        fn sgb(mut self, flag: u8) -> Self {
+            self.0[0x146] = flag;
+            self
+        }
+
+        /* Nintendo's "01" goes in the old licensee byte, anything else in
+         * the new code */
+        fn licensee(mut self, code: &[u8; 2]) -> Self {
+            match code {
+                b"01" => self.0[0x14B] = 0x01,
+                code => self.0[0x144..0x146].copy_from_slice(code),
+            }
+            self
+        }
+
+        fn cart_type(mut self, code: u8) -> Self {
+            self.0[0x147] = code;
+            self
+        }
+
+        fn rom_size(mut self, code: u8) -> Self {
+            self.0[0x148] = code;
+            self
+        }
+
+        fn ram_size(mut self, code: u8) -> Self {
+            self.0[0x149] = code;
+            self
+        }
+
+        /* `program` at the entry point */
+        fn program(self, program: &[u8]) -> Self {
+            self.at(0x100, program)
+        }
+
+        /* `bytes` anywhere in the image, header or not */
+        fn at(mut self, offset: usize, bytes: &[u8]) -> Self {
+            self.0[offset..offset + bytes.len()].copy_from_slice(bytes);
+            self
+        }
+
+        /* Padded with zeros, or cut, to `len` bytes */
+        fn size(mut self, len: usize) -> Self {
+            self.0.resize(len, 0);
+            self
+        }
+
+        /* The header checksum the boot ROM checks, then the global one
+         * over everything else; set last */
+        fn checksums(mut self) -> Self {
+            self.0[0x14D] = self.0[0x134..0x14D].iter().fold(0_u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1));
+            self.0[0x14E..0x150].fill(0);
+            let sum = self.0.iter().fold(0_u16, |sum, &byte| sum.wrapping_add(byte as u16));
+            self.0[0x14E..0x150].copy_from_slice(&sum.to_be_bytes());
+            self
+        }
+
+        fn build(self) -> Vec<u8> {
+            self.0
+        }
+    }
+
+    /* Header-only ROM with `program` at the entry point and PC pointing at it */
+    fn console(program: &[u8]) -> Gba {
+        let mut gba = Gba::from_cart(Cart::from_bytes(rom(b"").cart_type(0x01).program(program).build()).unwrap());
+        gba.cpu.registers.pc = 0x100;
+        gba
+    }
+
+    /* An empty directory of the test's own under the system temp dir */
+    #[cfg_attr(not(feature = "io"), allow(unused))]
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gbemu-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // mod wrapping {{{
+    mod wrapping {
+        use super::console;
+        use crate::cpu::register::types::{Flags, Register16};
+
+        #[test]
+        fn inc_hl_wraps_to_zero() {
+            let mut gba = console(&[0x23]);
+            gba.cpu.registers.set_r16(Register16::HL, 0xFFFF);
+            gba.step();
+            assert_eq!(gba.cpu.registers.get_r16(Register16::HL), 0x0000);
+        }
+
+        #[test]
+        fn dec_bc_wraps_to_ffff() {
+            let mut gba = console(&[0x0B]);
+            gba.step();
+            assert_eq!(gba.cpu.registers.get_r16(Register16::BC), 0xFFFF);
+        }
+
+        #[test]
+        fn inc_dec_r8_wrap() {
+            let mut gba = console(&[0x04, 0x05]);
+            gba.cpu.registers.b = 0xFF;
+            gba.step();
+            assert_eq!(gba.cpu.registers.b, 0x00);
+            assert!(gba.cpu.registers.f.is_set(Flags::Zero));
+            assert!(gba.cpu.registers.f.is_set(Flags::HalfCarry));
+            gba.step();
+            assert_eq!(gba.cpu.registers.b, 0xFF);
+            assert!(gba.cpu.registers.f.is_set(Flags::Subtract));
+        }
+
+        #[test]
+        fn hl_post_increment_and_decrement_wrap() {
+            let mut gba = console(&[0x22, 0x3A]);
+            gba.cpu.registers.a = 0x1F;
+            gba.cpu.registers.set_r16(Register16::HL, 0xFFFF);
+            gba.step();
+            assert_eq!(gba.mem.get_u8(0xFFFF_u16), 0x1F);
+            assert_eq!(gba.cpu.registers.get_r16(Register16::HL), 0x0000);
+            gba.step();
+            assert_eq!(gba.cpu.registers.get_r16(Register16::HL), 0xFFFF);
+        }
+
+        #[test]
+        fn stack_pointer_wraps_on_push_and_pop() {
+            let mut gba = console(&[0xC5, 0xC1]);
+            gba.cpu.registers.sp = 0x0000;
+            gba.step();
+            assert_eq!(gba.cpu.registers.sp, 0xFFFE);
+            gba.step();
+            assert_eq!(gba.cpu.registers.sp, 0x0000);
+        }
+
+        #[test]
+        fn relative_jump_backwards() {
+            let mut gba = console(&[0x00, 0x20, 0xFD]);
+            gba.step();
+            gba.step();
+            assert_eq!(gba.cpu.registers.pc, 0x0100);
+        }
+
+        #[test]
+        fn program_counter_wraps() {
+            let mut gba = console(&[]);
+            gba.cpu.registers.pc = 0xFFFF;
+            gba.step();
+            assert_eq!(gba.cpu.registers.pc, 0x0000);
+        }
+    }
+    // }}}
+
+    // mod link {{{
+    mod link {
+        use crate::link::prelude::{LocalLink, Serial};
+
+        #[test]
+        fn local_pair_exchanges_bytes() {
+            let (a, b) = LocalLink::pair();
+            let (mut master, mut slave) = (Serial::default(), Serial::default());
+            master.connect(Box::new(a));
+            slave.connect(Box::new(b));
+
+            slave.write_register(0xFF01, 0x99);
+            slave.write_register(0xFF02, 0x80);
+            master.write_register(0xFF01, 0x42);
+            master.write_register(0xFF02, 0x81);
+
+            let (mut master_irq, mut slave_irq) = (0, 0);
+            for _ in 0..1024 {
+                slave_irq |= slave.tick(4);
+                master_irq |= master.tick(4);
+            }
+            assert_eq!((master.sb, slave.sb), (0x99, 0x42));
+            assert!(!master.transferring() && !slave.transferring());
+            assert_eq!((master_irq, slave_irq), (0x08, 0x08));
+        }
+
+        #[test]
+        fn unarmed_slave_keeps_its_byte() {
+            let (a, b) = LocalLink::pair();
+            let (mut master, mut slave) = (Serial::default(), Serial::default());
+            master.connect(Box::new(a));
+            slave.connect(Box::new(b));
+
+            slave.write_register(0xFF01, 0x99);
+            master.write_register(0xFF01, 0x42);
+            master.write_register(0xFF02, 0x81);
+
+            let (mut master_irq, mut slave_irq) = (0, 0);
+            for _ in 0..1024 {
+                slave_irq |= slave.tick(4);
+                master_irq |= master.tick(4);
+            }
+            assert_eq!((master.sb, slave.sb), (0xFF, 0x99));
+            assert_eq!((master_irq, slave_irq), (0x08, 0x00));
+        }
+    }
+    // }}}
+
+    // mod printer {{{
+    mod printer {
+        use crate::link::prelude::{Printer, Serial, PAPER_WIDTH};
+
+        /* Clocks one byte out as the game does and returns the printer's */
+        fn transfer(serial: &mut Serial, byte: u8) -> u8 {
+            serial.write_register(0xFF01, byte);
+            serial.write_register(0xFF02, 0x81);
+            while serial.transferring() {
+                serial.tick(4);
+            }
+            serial.sb
+        }
+
+        /* Sends a whole packet, returning the alive byte and status */
+        fn packet(serial: &mut Serial, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+            let mut body = vec![command, compressed as u8];
+            body.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            body.extend_from_slice(data);
+            let checksum = body.iter().fold(0_u16, |sum, &byte| sum.wrapping_add(byte as u16));
+            for byte in [0x88, 0x33].into_iter().chain(body).chain(checksum.to_le_bytes()) {
+                assert_eq!(transfer(serial, byte), 0x00);
+            }
+            (transfer(serial, 0x00), transfer(serial, 0x00))
+        }
+
+        #[test]
+        fn prints_a_strip_through_the_palette() {
+            let (printer, printouts) = Printer::new();
+            let mut serial = Serial::default();
+            serial.connect(Box::new(printer));
+
+            assert_eq!(packet(&mut serial, 0x01, false, &[]), (0x81, 0x00));
+            /* Two tile rows of color 3, as five RLE runs of 128 $FF bytes */
+            let tiles = [0xFE, 0xFF].repeat(5);
+            assert_eq!(packet(&mut serial, 0x04, true, &tiles).1, 0x08);
+            assert_eq!(packet(&mut serial, 0x04, false, &[]).1, 0x08);
+            /* One sheet, no feed before and three after, the usual palette */
+            assert_eq!(packet(&mut serial, 0x02, false, &[0x01, 0x03, 0xE4, 0x40]).1, 0x02);
+            assert!(printouts.try_recv().is_ok_and(|printout| printout.height == 16
+                && printout.rgb.len() == PAPER_WIDTH * 16 * 3
+                && printout.rgb.iter().all(|&value| value == 0x00)));
+
+            /* Busy for a few status polls, then done */
+            let statuses: Vec<u8> = (0..5).map(|_| packet(&mut serial, 0x0F, false, &[]).1).collect();
+            assert_eq!(statuses, [0x02, 0x02, 0x02, 0x00, 0x00]);
+        }
+
+        #[test]
+        fn flags_bad_checksums_and_feeds_on_unplug() {
+            let (printer, printouts) = Printer::new();
+            let mut serial = Serial::default();
+            serial.connect(Box::new(printer));
+            for byte in [0x88, 0x33, 0x0F, 0x00, 0x00, 0x00, 0x55, 0x55] {
+                transfer(&mut serial, byte);
+            }
+            assert_eq!((transfer(&mut serial, 0), transfer(&mut serial, 0)), (0x81, 0x01));
+
+            packet(&mut serial, 0x04, false, &[0x00; 0x280]);
+            /* No feed after, so the strip stays on the printer until unplugged */
+            packet(&mut serial, 0x02, false, &[0x01, 0x10, 0xE4, 0x40]);
+            assert!(printouts.try_recv().is_err());
+            drop(serial.disconnect());
+            assert!(printouts.try_recv().is_ok_and(|printout| printout.height == 16 && printout.rgb[0] == 0xFF));
+        }
+    }
+    // }}}
+
+    // mod alu {{{
+    mod alu {
+        use crate::cpu::{alu, register::types::F8};
+
+        fn reference_flags(z: bool, n: bool, h: bool, c: bool) -> u8 {
+            (if z { 0x80 } else { 0 }) | (if n { 0x40 } else { 0 }) | (if h { 0x20 } else { 0 }) | (if c { 0x10 } else { 0 })
+        }
+
+        fn reference_add(a: u8, b: u8, c: bool) -> (u8, u8) {
+            let sum = a as u32 + b as u32 + c as u32;
+            let half = (a & 0x0F) as u32 + (b & 0x0F) as u32 + c as u32;
+            ((sum & 0xFF) as u8, reference_flags(sum & 0xFF == 0, false, half > 0x0F, sum > 0xFF))
+        }
+
+        fn reference_sub(a: u8, b: u8, c: bool) -> (u8, u8) {
+            let diff = a as i32 - b as i32 - c as i32;
+            let half = (a & 0x0F) as i32 - (b & 0x0F) as i32 - c as i32;
+            ((diff & 0xFF) as u8, reference_flags(diff & 0xFF == 0, true, half < 0, diff < 0))
+        }
+
+        fn pairs() -> impl Iterator<Item = (u8, u8)> {
+            (0..=0xFFFF_u32).map(|v| ((v >> 8) as u8, v as u8))
+        }
+
+        #[test]
+        fn add8_matches_reference() {
+            for (a, b) in pairs() {
+                let (res, f) = alu::add8(a, b);
+                assert_eq!((res, u8::from(f)), reference_add(a, b, false), "add8({:#04X}, {:#04X})", a, b);
+            }
+        }
+
+        #[test]
+        fn adc8_matches_reference() {
+            for (a, b) in pairs() {
+                for c in [false, true] {
+                    let (res, f) = alu::adc8(a, b, c);
+                    assert_eq!((res, u8::from(f)), reference_add(a, b, c), "adc8({:#04X}, {:#04X}, {})", a, b, c);
+                }
+            }
+        }
+
+        #[test]
+        fn sub8_and_cp8_match_reference() {
+            for (a, b) in pairs() {
+                let (res, f) = alu::sub8(a, b);
+                assert_eq!((res, u8::from(f)), reference_sub(a, b, false), "sub8({:#04X}, {:#04X})", a, b);
+                assert_eq!(u8::from(alu::cp8(a, b)), reference_sub(a, b, false).1);
+            }
+        }
+
+        #[test]
+        fn sbc8_matches_reference() {
+            for (a, b) in pairs() {
+                for c in [false, true] {
+                    let (res, f) = alu::sbc8(a, b, c);
+                    assert_eq!((res, u8::from(f)), reference_sub(a, b, c), "sbc8({:#04X}, {:#04X}, {})", a, b, c);
+                }
+            }
+        }
+
+        #[test]
+        fn logic_ops_match_reference() {
+            for (a, b) in pairs() {
+                assert_eq!(alu::and8(a, b).0, a & b);
+                assert_eq!(u8::from(alu::and8(a, b).1), reference_flags(a & b == 0, false, true, false));
+                assert_eq!(u8::from(alu::or8(a, b).1), reference_flags(a | b == 0, false, false, false));
+                assert_eq!(u8::from(alu::xor8(a, b).1), reference_flags(a ^ b == 0, false, false, false));
+            }
+        }
+
+        #[test]
+        fn inc8_dec8_preserve_carry() {
+            for a in 0..=0xFF_u8 {
+                for f in [0x00_u8, 0x10] {
+                    let (res, flags) = alu::inc8(a, F8::from(f));
+                    let (ref_res, ref_flags) = reference_add(a, 1, false);
+                    assert_eq!((res, u8::from(flags)), (ref_res, (ref_flags & 0xE0) | f));
+
+                    let (res, flags) = alu::dec8(a, F8::from(f));
+                    let (ref_res, ref_flags) = reference_sub(a, 1, false);
+                    assert_eq!((res, u8::from(flags)), (ref_res, (ref_flags & 0xE0) | f));
+                }
+            }
+        }
+
+        #[test]
+        fn daa_produces_bcd_sums_and_differences() {
+            let bcd = |v: u8| ((v / 10) << 4) | (v % 10);
+            for x in 0..100_u8 {
+                for y in 0..100_u8 {
+                    let (sum, f) = alu::add8(bcd(x), bcd(y));
+                    let (res, f) = alu::daa(sum, f);
+                    assert_eq!(res, bcd((x + y) % 100), "daa after {} + {}", x, y);
+                    assert_eq!(u8::from(f), reference_flags(res == 0, false, false, x + y > 99));
+
+                    let (diff, f) = alu::sub8(bcd(x), bcd(y));
+                    let (res, f) = alu::daa(diff, f);
+                    assert_eq!(res, bcd(((100 + x as u16 - y as u16) % 100) as u8), "daa after {} - {}", x, y);
+                    assert_eq!(u8::from(f), reference_flags(res == 0, true, false, y > x));
+                }
+            }
+        }
+
+        #[test]
+        fn rotates_and_shifts() {
+            for a in 0..=0xFF_u8 {
+                assert_eq!(alu::rlc8(a).0, a.rotate_left(1));
+                assert_eq!(alu::rrc8(a).0, a.rotate_right(1));
+                assert_eq!(alu::rl8(a, F8::from(0x10)).0, (a << 1) | 1);
+                assert_eq!(alu::rr8(a, F8::from(0x10)).0, (a >> 1) | 0x80);
+                assert_eq!(alu::sra8(a).0 as i8, (a as i8) >> 1);
+                assert_eq!(alu::srl8(a).0, a >> 1);
+                assert_eq!(alu::swap8(a).0, ((a & 0x0F) << 4) | ((a & 0xF0) >> 4));
+                assert_eq!(alu::sla8(a).1.is_set(crate::cpu::register::types::Flags::Carry), a & 0x80 != 0);
+            }
+        }
+
+        #[test]
+        fn add16_half_carry_from_bit_11() {
+            let operands: Vec<u16> = (0..=0xFFFF_u32).step_by(0x0101).map(|v| v as u16)
+                .chain([0x0001, 0x07FF, 0x0800, 0x0FFF, 0x1000, 0x8000, 0xFFFF])
+                .collect();
+            for a in 0..=0xFFFF_u16 {
+                for &b in operands.iter() {
+                    for z in [0x00_u8, 0x80] {
+                        let (res, f) = alu::add16(a, b, F8::from(z | 0x70));
+                        let sum = a as u32 + b as u32;
+                        let half = (a & 0x0FFF) as u32 + (b & 0x0FFF) as u32 > 0x0FFF;
+                        assert_eq!(res, sum as u16);
+                        assert_eq!(u8::from(f), z | reference_flags(false, false, half, sum > 0xFFFF), "add16({:#06X}, {:#06X})", a, b);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn add_sp_e8_matches_reference() {
+            for sp in 0..=0xFFFF_u16 {
+                for e in 0..=0xFF_u8 {
+                    let (res, f) = alu::add_sp_e8(sp, e);
+                    let half = (sp & 0x0F) + (e & 0x0F) as u16 > 0x0F;
+                    let carry = (sp & 0xFF) + e as u16 > 0xFF;
+                    assert_eq!(res, (sp as i32 + e as i8 as i32) as u16);
+                    assert_eq!(u8::from(f), reference_flags(false, false, half, carry), "add_sp_e8({:#06X}, {:#04X})", sp, e);
+                }
+            }
+        }
+    }
+    // }}}
+
+    // mod facade {{{
+    mod facade {
+        use std::io::ErrorKind;
+
+        use super::console;
+        use crate::{asm, cpu::{prelude::CpuState, register::types::Register16}, Button, Emulator, GbError, RomSource};
+
+        fn emulator(checksum: u8) -> Emulator {
+            Emulator::new(RomSource::Bytes(super::rom(b"").cart_type(0x01).at(0x14F, &[checksum]).build())).unwrap()
+        }
+
+        #[test]
+        fn joypad_reads_selected_row_and_raises_interrupt() {
+            let mut emu = emulator(0);
+            emu.console_mut().mem.set_u8(0xFF00_u16, 0x20);
+
+            emu.set_button(Button::A, true);
+            assert_eq!(emu.console().mem.get_u8(0xFF00_u16) & 0x0F, 0x0F);
+            assert_eq!(emu.console().mem.get_u8(0xFF0F_u16) & 0x10, 0);
+
+            emu.set_button(Button::Down, true);
+            assert_eq!(emu.console().mem.get_u8(0xFF00_u16) & 0x0F, 0x07);
+            assert_eq!(emu.console().mem.get_u8(0xFF0F_u16) & 0x10, 0x10);
+
+            emu.console_mut().mem.set_u8(0xFF00_u16, 0x10);
+            assert_eq!(emu.console().mem.get_u8(0xFF00_u16) & 0x0F, 0x0E);
+        }
+
+        #[test]
+        fn save_state_round_trips() {
+            let mut emu = emulator(0);
+            emu.console_mut().mem.set_u8(0xC000_u16, 0x42);
+            emu.run_frame().unwrap();
+            let state = emu.save_state();
+            let pc = emu.console().cpu.registers.pc;
+
+            emu.console_mut().mem.set_u8(0xC000_u16, 0x00);
+            emu.run_frame().unwrap();
+            emu.load_state(&state).unwrap();
+
+            assert_eq!(emu.console().cpu.registers.pc, pc);
+            assert_eq!(emu.console().mem.get_u8(0xC000_u16), 0x42);
+            assert_eq!(emu.save_state(), state);
+        }
+
+        #[test]
+        fn save_state_rejects_other_carts_and_truncation() {
+            let state = emulator(0).save_state();
+            assert_eq!(emulator(1).load_state(&state), Err(GbError::Io(ErrorKind::InvalidInput)));
+            assert_eq!(emulator(0).load_state(&state[..state.len() - 1]), Err(GbError::Io(ErrorKind::UnexpectedEof)));
+        }
+
+        #[test]
+        fn constructors_report_a_gb_error() {
+            use std::error::Error;
+
+            use crate::mem::prelude::HeaderError;
+
+            let short = Emulator::new(RomSource::Bytes(vec![0; 0x100])).err();
+            assert_eq!(short, Some(GbError::Rom(HeaderError::TooShort(0x100))));
+            assert!(short.unwrap().source().is_some());
+
+            #[cfg(feature = "io")]
+            {
+                let missing = Emulator::new(RomSource::from("/nonexistent/rom.gb")).err();
+                assert_eq!(missing, Some(GbError::Io(ErrorKind::NotFound)));
+            }
+
+            let unsupported = Emulator::new(RomSource::Bytes(super::rom(b"").cart_type(0xFD).build())).err().unwrap();
+            assert!(matches!(unsupported, GbError::Unsupported(_)));
+            assert!(unsupported.to_string().ends_with("cartridges not supported"));
+        }
+
+        #[test]
+        fn clearing_past_oam_does_not_panic() {
+            /* Runs on past OAM into the unusable $FEA0-$FEFF, as many games do */
+            let mut gba = console(&asm!("ld hl, $FE00; ld a, $AA; ld c, 0; loop: ld [hl+], a; dec c; jr nz, loop; halt"));
+            gba.mem.set_u8(0xFF40_u16, 0x00);
+            gba.run_frame();
+            assert_eq!(gba.cpu.state, CpuState::Halted);
+            assert_eq!(gba.cpu.registers.get_r16(Register16::HL), 0xFF00);
+            assert_eq!((gba.mem.get_u8(0xFE9F_u16), gba.mem.get_u8(0xFEA0_u16)), (0xAA, 0xFF));
+        }
+
+        #[test]
+        fn indexing_any_address_does_not_panic() {
+            let mut gba = console(&[]);
+            for addr in 0x0000..=0xFFFF_u16 {
+                let value = gba.mem[addr];
+                gba.mem[addr] = !value;
+            }
+            /* Unusable memory, unmapped I/O and ROM take writes without keeping them */
+            for addr in [0xFEA0_u16, 0xFF7F, 0x0100] {
+                let before = gba.mem[addr];
+                gba.mem[addr] = 0x12;
+                assert_eq!(gba.mem[addr], before);
+            }
+            assert_eq!((gba.mem[0xFEFF_u16], gba.mem[0xFF7F_u16]), (0xFF, 0xFF));
+        }
+
+        #[test]
+        fn rendering_reuses_the_callers_buffer() {
+            let mut emu = emulator(0);
+            emu.run_frame().unwrap();
+            let mut rgb = Vec::new();
+            emu.render_framebuffer(&mut rgb);
+            let buffer = rgb.as_ptr();
+            emu.run_frame().unwrap();
+            emu.render_framebuffer(&mut rgb);
+            assert_eq!((rgb.as_ptr(), rgb), (buffer, emu.framebuffer()));
+        }
+
+        #[test]
+        fn running_a_locked_cpu_is_an_error() {
+            let mut emu = emulator(0);
+            emu.console_mut().mem.map_boot_rom(crate::mem::prelude::BootRom::from_bytes(vec![0xD3; 0x100]).unwrap());
+            let error = emu.step().unwrap_err();
+            assert_eq!(error, GbError::Decode(crate::gba::prelude::DecodeError(0xD3)));
+            assert_eq!(error.to_string(), "CPU locked up: Illegal opcode `$D3`");
+            assert!(emu.run_frame().is_err());
+        }
+    }
+    // }}}
+
+    // mod state_migrations {{{
+    mod state_migrations {
+        use crate::{
+            config::prelude::Config,
+            gba::prelude::{Emulator, RomSource},
+            ppu::image::inflate,
+            state::prelude::STATE_VERSION,
+            GbError,
+        };
+
+        /* States saved by each release from OLDEST_STATE_VERSION on, deflated:
+         * `fixture()` after three frames, with C counting where the loop is */
+        const FIXTURES: [(u8, &[u8]); 2] = [
+            (10, include_bytes!("state/fixtures/v10.state.deflate")),
+            (11, include_bytes!("state/fixtures/v11.state.deflate")),
+        ];
+
+        fn fixture() -> Emulator {
+            let rom = super::rom(b"").program(&crate::asm!("ld a, $42; ld [$C000], a; ld b, $99; ld sp, $DFFE; loop: inc c; jr loop")).build();
+            let config = Config::from_toml("[core]\nskip_boot = true").unwrap();
+            Emulator::with_config(RomSource::Bytes(rom), &config).unwrap()
+        }
+
+        fn state(version: u8) -> Vec<u8> {
+            let (_, deflated) = FIXTURES.iter().find(|&&(v, _)| v == version).unwrap();
+            inflate(deflated, 0x20000).unwrap()
+        }
+
+        #[test]
+        fn states_from_earlier_releases_load() {
+            assert_eq!(FIXTURES.last().unwrap().0, STATE_VERSION);
+            /* A format change without a version bump shows up here first */
+            let (mut current, latest) = (fixture(), state(STATE_VERSION));
+            for _ in 0..3 {
+                current.run_frame().unwrap();
+            }
+            assert_eq!(current.save_state(), latest);
+            let mut next = fixture();
+            next.load_state(&latest).unwrap();
+            next.run_frame().unwrap();
+
+            for (version, _) in FIXTURES {
+                let saved = state(version);
+                assert_eq!(saved[4], version);
+                let mut emu = fixture();
+                emu.load_state(&saved).unwrap();
+                let registers = &emu.console().cpu.registers;
+                assert_eq!((registers.pc, registers.sp, registers.b, registers.c), (0x010B, 0xDFFE, 0x99, 0x80), "v{}", version);
+                assert_eq!(emu.console().mem.peek(0xC000), 0x42);
+                /* Migrated, it runs on as if this release had saved it */
+                assert_eq!(emu.console_mut().state_hash(), current.console_mut().state_hash(), "v{}", version);
+                emu.run_frame().unwrap();
+                assert_eq!(emu.console_mut().state_hash(), next.console_mut().state_hash(), "v{}", version);
+            }
+        }
+
+        #[test]
+        fn versions_out_of_reach_are_unsupported() {
+            let mut state = state(STATE_VERSION);
+            state[4] = STATE_VERSION + 1;
+            let newer = fixture().load_state(&state).unwrap_err();
+            assert_eq!(newer.to_string(), format!("savestates from a newer version ({}) not supported", STATE_VERSION + 1));
+            state[4] = 9;
+            assert_eq!(fixture().load_state(&state), Err(GbError::Unsupported("savestates from version 9".to_string())));
+        }
+    }
+    // }}}
+
+    // mod wasm {{{
+    mod wasm {
+        use crate::{ppu::prelude::{SCREEN_HEIGHT, SCREEN_WIDTH}, wasm::exports::*};
+
+        #[test]
+        fn exports_run_a_frame_from_a_host_buffer() {
+            let rom = gb_alloc(0x8000);
+            unsafe {
+                let bytes = std::slice::from_raw_parts_mut(rom, 0x8000);
+                bytes[0x147] = 0x01;
+                bytes[0x14B] = 0x33;
+
+                let web = gb_new(rom, 0x8000);
+                assert!(!web.is_null());
+                assert!(gb_run_frame(web) as usize >= 17556);
+                let rgba = std::slice::from_raw_parts(gb_framebuffer(web), SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+                assert!(rgba.chunks_exact(4).all(|pixel| pixel[3] == 0xFF));
+                gb_free(web);
+            }
+        }
+
+        #[test]
+        fn exports_reject_short_roms() {
+            let rom = gb_alloc(0x100);
+            assert!(unsafe { gb_new(rom, 0x100) }.is_null());
+        }
+    }
+    // }}}
+
+    // mod decode {{{
+    mod decode {
+        use super::console;
+        use crate::{
+            cpu::prelude::{CpuState, Flags, Register16},
+            debugger::prelude::disassemble,
+            gba::{
+                opcode::types::{JumpCondition, LoadDirection, OpcodeRegister16},
+                prelude::{DecodeError, Opcode},
+            },
+        };
+
+        const ILLEGAL: [u8; 11] = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
+        const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "[HL]", "A"];
+
+        /* The opcode table as Pan Docs lays it out, in RGBDS syntax; $40-$BF
+         * are the regular LD and ALU blocks and are filled in below */
+        const PRIMARY: [&str; 128] = [
+            "NOP", "LD BC, n16", "LD [BC], A", "INC BC", "INC B", "DEC B", "LD B, n8", "RLCA",
+            "LD [a16], SP", "ADD HL, BC", "LD A, [BC]", "DEC BC", "INC C", "DEC C", "LD C, n8", "RRCA",
+            "STOP", "LD DE, n16", "LD [DE], A", "INC DE", "INC D", "DEC D", "LD D, n8", "RLA",
+            "JR e8", "ADD HL, DE", "LD A, [DE]", "DEC DE", "INC E", "DEC E", "LD E, n8", "RRA",
+            "JR NZ, e8", "LD HL, n16", "LD [HL+], A", "INC HL", "INC H", "DEC H", "LD H, n8", "DAA",
+            "JR Z, e8", "ADD HL, HL", "LD A, [HL+]", "DEC HL", "INC L", "DEC L", "LD L, n8", "CPL",
+            "JR NC, e8", "LD SP, n16", "LD [HL-], A", "INC SP", "INC [HL]", "DEC [HL]", "LD [HL], n8", "SCF",
+            "JR C, e8", "ADD HL, SP", "LD A, [HL-]", "DEC SP", "INC A", "DEC A", "LD A, n8", "CCF",
+            "RET NZ", "POP BC", "JP NZ, a16", "JP a16", "CALL NZ, a16", "PUSH BC", "ADD A, n8", "RST $00",
+            "RET Z", "RET", "JP Z, a16", "PREFIX", "CALL Z, a16", "CALL a16", "ADC A, n8", "RST $08",
+            "RET NC", "POP DE", "JP NC, a16", "-", "CALL NC, a16", "PUSH DE", "SUB A, n8", "RST $10",
+            "RET C", "RETI", "JP C, a16", "-", "CALL C, a16", "-", "SBC A, n8", "RST $18",
+            "LDH [n8], A", "POP HL", "LDH [C], A", "-", "-", "PUSH HL", "AND A, n8", "RST $20",
+            "ADD SP, e8", "JP HL", "LD [a16], A", "-", "-", "-", "XOR A, n8", "RST $28",
+            "LDH A, [n8]", "POP AF", "LDH A, [C]", "DI", "-", "PUSH AF", "OR A, n8", "RST $30",
+            "LD HL, SP + e8", "LD SP, HL", "LD A, [a16]", "EI", "-", "-", "CP A, n8", "RST $38",
+        ];
+        const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBC", "AND", "XOR", "OR", "CP"];
+        const SHIFTS: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+
+        fn reference(byte: u8) -> String {
+            match byte {
+                0x00..=0x3F => PRIMARY[byte as usize].to_string(),
+                0x76 => "HALT".to_string(),
+                0x40..=0x7F => format!("LD {}, {}", R8[(byte as usize >> 3) & 7], R8[byte as usize & 7]),
+                0x80..=0xBF => format!("{} A, {}", ALU[(byte as usize >> 3) & 7], R8[byte as usize & 7]),
+                _ => PRIMARY[byte as usize - 0x80].to_string(),
+            }
+        }
+
+        fn reference_prefixed(byte: u8) -> String {
+            let (row, bit, reg) = (byte >> 6, (byte >> 3) & 7, R8[byte as usize & 7]);
+            match row {
+                0 => format!("{} {}", SHIFTS[bit as usize], reg),
+                _ => format!("{} {}, {}", ["BIT", "RES", "SET"][row as usize - 1], bit, reg),
+            }
+        }
+
+        /* A decoded opcode spelled like the reference, written against the
+         * variants rather than the encoding so the two check each other */
+        fn mnemonic(opcode: Opcode) -> String {
+            use Opcode::*;
+            let r16 = |reg: OpcodeRegister16| format!("{:?}", reg);
+            let cond = |condition: JumpCondition| match condition {
+                JumpCondition::Always => String::new(),
+                JumpCondition::UnsetFlag(Flags::Zero) => "NZ, ".to_string(),
+                JumpCondition::SetFlag(Flags::Zero) => "Z, ".to_string(),
+                JumpCondition::UnsetFlag(Flags::Carry) => "NC, ".to_string(),
+                JumpCondition::SetFlag(Flags::Carry) => "C, ".to_string(),
+                other => panic!("no such condition {:?}", other),
+            };
+            let load = |direction: LoadDirection, mem: &str, op: &str| match direction {
+                LoadDirection::Memory => format!("{} [{}], A", op, mem),
+                LoadDirection::Accumulator => format!("{} A, [{}]", op, mem),
+            };
+            match opcode {
+                LoadR8(dst, src) => format!("LD {}, {}", R8[dst as usize], R8[src as usize]),
+                LoadImm8(dst) => format!("LD {}, n8", R8[dst as usize]),
+                LoadIndR16(reg, direction) => load(direction, ["BC", "DE", "HL+", "HL-"][reg as usize], "LD"),
+                LoadIndOffImm8(direction) => load(direction, "n8", "LDH"),
+                LoadIndOffRegC(direction) => load(direction, "C", "LDH"),
+                LoadIndImm16(direction) => load(direction, "a16", "LD"),
+                LoadImm16(reg) => format!("LD {}, n16", r16(reg)),
+                LoadIndImm16SP => "LD [a16], SP".to_string(),
+                LoadSPHL => "LD SP, HL".to_string(),
+                PushR16(reg) => format!("PUSH {}", r16(reg)),
+                PopR16(reg) => format!("POP {}", r16(reg)),
+                LoadHLOffSp => "LD HL, SP + e8".to_string(),
+                MathR8(op, reg) => format!("{} A, {}", ALU[op as usize], R8[reg as usize]),
+                MathImm8(op) => format!("{} A, n8", ALU[op as usize]),
+                IncR8(reg) => format!("INC {}", R8[reg as usize]),
+                DecR8(reg) => format!("DEC {}", R8[reg as usize]),
+                ComplementCarryFlag => "CCF".to_string(),
+                SetCarryFlag => "SCF".to_string(),
+                DecimalAdjustAccumulator => "DAA".to_string(),
+                ComplementAccumulator => "CPL".to_string(),
+                IncR16(reg) => format!("INC {}", r16(reg)),
+                DecR16(reg) => format!("DEC {}", r16(reg)),
+                AddR16(reg) => format!("ADD HL, {}", r16(reg)),
+                AddSPImm8 => "ADD SP, e8".to_string(),
+                RotateLeftCircularAccumulator => "RLCA".to_string(),
+                RotateRightCircularAccumulator => "RRCA".to_string(),
+                RotateLeftAccumulator => "RLA".to_string(),
+                RotateRightAccumulator => "RRA".to_string(),
+                Prefix => "PREFIX".to_string(),
+                RotateShift(op, reg) => format!("{} {}", SHIFTS[op as usize], R8[reg as usize]),
+                TestBit(bit, reg) => format!("BIT {}, {}", bit, R8[reg as usize]),
+                ResetBit(bit, reg) => format!("RES {}, {}", bit, R8[reg as usize]),
+                SetBit(bit, reg) => format!("SET {}, {}", bit, R8[reg as usize]),
+                JumpImm16(condition) => format!("JP {}a16", cond(condition)),
+                JumpHL => "JP HL".to_string(),
+                JumpOffImm8(condition) => format!("JR {}e8", cond(condition)),
+                CallImm16(condition) => format!("CALL {}a16", cond(condition)),
+                Return(JumpCondition::Always) => "RET".to_string(),
+                Return(condition) => format!("RET {}", cond(condition).trim_end_matches(", ")),
+                ReturnInterupt => "RETI".to_string(),
+                Restart(vector) => format!("RST ${:02X}", vector),
+                Halt => "HALT".to_string(),
+                Stop => "STOP".to_string(),
+                DisableInterrupts => "DI".to_string(),
+                EnableInterrupts => "EI".to_string(),
+                Noop => "NOP".to_string(),
+            }
+        }
+
+        #[test]
+        fn only_the_unused_opcodes_fail_to_decode() {
+            for byte in 0..=0xFF_u8 {
+                let decoded = Opcode::try_from(byte);
+                if ILLEGAL.contains(&byte) {
+                    assert_eq!(decoded.unwrap_err(), DecodeError(byte));
+                } else {
+                    assert!(decoded.is_ok(), "`${:02X}` failed to decode", byte);
+                }
+            }
+        }
+
+        #[test]
+        fn illegal_opcode_locks_cpu_but_not_peripherals() {
+            let mut gba = console(&[0xDD, 0x3C]);
+            gba.mem.set_u8(0xFF40_u16, 0x80);
+            assert_eq!(gba.step(), 1);
+            assert_eq!(gba.cpu.state, CpuState::Locked(0xDD));
+
+            gba.run_frame();
+            assert_eq!(gba.cpu.registers.pc, 0x101);
+            assert_eq!(gba.cpu.registers.a, 0);
+            assert_ne!(gba.mem.get_u8(0xFF0F_u16) & 0x01, 0);
+        }
+
+        #[test]
+        fn prefixed_opcodes_match_reference_timing() {
+            let mut gba = console(&[
+                0xCB, 0x37, /* SWAP A */
+                0xCB, 0x7E, /* BIT 7, (HL) */
+                0xCB, 0xC6, /* SET 0, (HL) */
+                0xCB, 0x11, /* RL C */
+            ]);
+            gba.cycle_validator.enabled = true;
+            gba.cpu.registers.a = 0x1E;
+            gba.cpu.registers.c = 0x80;
+            gba.cpu.registers.set_r16(Register16::HL, 0xC000);
+
+            assert_eq!(gba.step(), 2);
+            assert_eq!(gba.cpu.registers.a, 0xE1);
+            assert_eq!(gba.step(), 3);
+            assert_eq!(u8::from(gba.cpu.registers.f), 0xA0);
+            assert_eq!(gba.step(), 4);
+            assert_eq!(gba.mem.get_u8(0xC000_u16), 0x01);
+            assert_eq!(gba.step(), 2);
+            assert_eq!((gba.cpu.registers.c, u8::from(gba.cpu.registers.f)), (0x00, 0x90));
+            assert!(gba.cycle_validator.mismatches.is_empty());
+        }
+
+        #[test]
+        fn every_opcode_decodes_as_the_reference_table() {
+            for byte in 0..=0xFF_u8 {
+                match (Opcode::decode(byte), reference(byte)) {
+                    (Err(error), expected) => assert_eq!((error, expected.as_str()), (DecodeError(byte), "-")),
+                    (Ok(opcode), expected) => assert_eq!(mnemonic(opcode), expected, "${:02X} decoded as {:?}", byte, opcode),
+                }
+                let prefixed = Opcode::decode_prefixed(byte);
+                assert_eq!(mnemonic(prefixed), reference_prefixed(byte), "$CB ${:02X} decoded as {:?}", byte, prefixed);
+            }
+        }
+
+        #[test]
+        fn disassembly_matches_the_reference_table() {
+            let none = |_| None;
+            for byte in 0..=0xFF_u8 {
+                /* Operands $34 $12 at $0100, so JR lands on $0136 */
+                let relative = if reference(byte).starts_with("JR") { "$0136" } else { "$34" };
+                let expected = match reference(byte).as_str() {
+                    "-" => format!("DB ${:02X}", byte),
+                    "PREFIX" => reference_prefixed(0x34),
+                    text => text.replace("n16", "$1234").replace("a16", "$1234").replace("n8", "$34").replace("e8", relative),
+                };
+                assert_eq!(disassemble(&[byte, 0x34, 0x12], 0x100, &none), expected, "${:02X}", byte);
+                assert_eq!(disassemble(&[0xCB, byte], 0x100, &none), reference_prefixed(byte), "$CB ${:02X}", byte);
+            }
+        }
+
+        #[test]
+        fn relative_jumps_decode() {
+            let mut gba = console(&[0x18, 0x02, 0x00, 0x00, 0x28, 0xFC]);
+            gba.cycle_validator.enabled = true;
+            gba.step();
+            assert_eq!(gba.cpu.registers.pc, 0x104);
+            gba.step();
+            assert_eq!(gba.cpu.registers.pc, 0x106);
+            assert!(gba.cycle_validator.mismatches.is_empty());
+        }
+    }
+    // }}}
+
+    // mod assembler {{{
+    mod assembler {
+        use super::console;
+        use crate::{
+            asm,
+            cpu::prelude::Register8,
+            debugger::prelude::{assemble, disassemble, instruction_len},
+        };
+
+        #[test]
+        fn every_instruction_survives_a_round_trip() {
+            let none = |_| None;
+            for byte in 0..=0xFF_u8 {
+                /* $F0 makes the signed forms negative */
+                let mut bytes = [byte, 0xF0, 0x7F];
+                let text = disassemble(&bytes, 0x100, &none);
+                if byte == 0x10 {
+                    /* STOP's second byte is not part of its text */
+                    bytes[1] = 0x00;
+                }
+                assert_eq!(assemble(&text, 0x100), Ok(bytes[..instruction_len(byte)].to_vec()), "{}", text);
+
+                let text = disassemble(&[0xCB, byte], 0x100, &none);
+                assert_eq!(assemble(&text, 0x100), Ok(vec![0xCB, byte]), "{}", text);
+            }
+        }
+
+        #[test]
+        fn readable_programs_run() {
+            assert_eq!(asm!("ld a, $3E; add a, b; jr nz, -2"), [0x3E, 0x3E, 0x80, 0x20, 0xFE]);
+
+            let mut gba = console(&asm!("
+                ld b, 5
+                xor a
+            loop:
+                add b       // A = 5 + 4 + 3 + 2 + 1
+                dec b
+                jr nz, loop
+                ld [$C000], a
+                halt
+            "));
+            for _ in 0..20 {
+                gba.step();
+            }
+            assert_eq!(gba.cpu.registers.get_r8(Register8::A), 15);
+            assert_eq!(gba.mem[0xC000_u16], 15);
+        }
+
+        #[test]
+        fn alternative_spellings() {
+            assert_eq!(asm!("sub b"), asm!("sub a, b"));
+            assert_eq!(asm!("ld [c], a; ld a, ($ff00+c)"), [0xE2, 0xF2]);
+            assert_eq!(asm!("ldh [$FF44], a; ldh a, [$44]"), [0xE0, 0x44, 0xF0, 0x44]);
+            assert_eq!(asm!("ldi a, [hl]; ld [hld], a; jp (hl)"), [0x2A, 0x32, 0xE9]);
+            assert_eq!(asm!("ld hl, sp - 2; add sp, %10"), [0xF8, 0xFE, 0xE8, 0x02]);
+            assert_eq!(asm!("rst $38; bit 7, h; dw $1234, end; end:", 0xC000), [0xFF, 0xCB, 0x7C, 0x34, 0x12, 0x07, 0xC0]);
+        }
+
+        #[test]
+        fn mistakes_name_the_line() {
+            let message = |source| assemble(source, 0x100).unwrap_err().to_string();
+            assert_eq!(message("nop\nld b, [hl+]"), "line 2: unknown instruction `ld b, [hl+]`");
+            assert_eq!(message("jp nowhere"), "line 1: unknown label `nowhere`");
+            assert_eq!(message("rst $09"), "line 1: 9 is not valid in `rst $09`");
+            assert_eq!(message("ld a, 256"), "line 1: 256 is out of range");
+            assert_eq!(message("jr $0200"), "line 1: $0200 is out of JR range");
+            assert_eq!(message("a: nop; a: nop"), "line 1: label `a` defined twice");
+        }
+    }
+    // }}}
+
+    // mod fuzz {{{
+    mod fuzz {
+        use crate::{cpu::prelude::CpuState, gba::prelude::Gba, mem::prelude::FlatBus};
+
+        /* Same loop as fuzz/fuzz_targets/execute.rs over a fixed xorshift corpus */
+        #[test]
+        fn random_programs_never_panic() {
+            let mut seed = 0x2545_F491_4F6C_DD1D_u64;
+            for _ in 0..64 {
+                let image: Vec<u8> = (0..0x10000).map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    seed as u8
+                }).collect();
+
+                let mut gba = Gba::with_bus(FlatBus::new(&image));
+                for _ in 0..4096 {
+                    gba.step();
+                    gba.cpu.state = CpuState::Running;
+                }
+            }
+        }
+    }
+    // }}}
+
+    // mod dump {{{
+    mod dump {
+        use super::console;
+        use crate::mem::prelude::{hexdump, io_register_name};
+
+        #[test]
+        fn dump_region_skips_unusable_memory() {
+            let mut gba = console(&[]);
+            gba.mem.set_u8(0xC000_u16, 0x42);
+            assert_eq!(gba.mem.dump_region(0xC000..=0xC001), vec![0x42, 0x00]);
+            assert_eq!(gba.mem.dump_region(0xFE9F..=0xFEA0), vec![0x00, 0xFF]);
+            assert_eq!(gba.mem.dump_region(0x0000..=0xFFFF).len(), 0x10000);
+        }
+
+        #[test]
+        fn memory_map_lists_named_io() {
+            let mut gba = console(&[]);
+            gba.mem.set_u8(0xFF47_u16, 0xE4);
+            let map = gba.dump_memory_map();
+            assert_eq!(map.rom_bank, 1);
+            let bgp = map.io.iter().find(|reg| reg.name == "BGP").unwrap();
+            assert_eq!((bgp.addr, bgp.value), (0xFF47, 0xE4));
+            assert!(map.to_string().contains("BGP   $FF47 = $E4"));
+        }
+
+        #[test]
+        fn hexdump_annotates_io_rows() {
+            let dump = hexdump(0xFF40, &[0x91, 0x85, 0x00, 0x00]);
+            assert_eq!(dump, "FF40: 91 85 00 00                                      |....| LCDC=91 STAT=85 SCY=00 SCX=00\n");
+            assert_eq!(io_register_name(0xFF3A), Some("WAVE"));
+            assert_eq!(io_register_name(0xC000), None);
+        }
+    }
+    // }}}
+
+    // mod vram {{{
+    mod vram {
+        use super::console;
+        use crate::ppu::prelude::{TileMap, MAP_SIZE};
+
+        #[test]
+        fn tiles_decode_in_vram_order() {
+            let mut gba = console(&[]);
+            /* Tile 1, row 0: colors 0,1,2,3,0,1,2,3 */
+            gba.mem.set_u8(0x8010_u16, 0x55);
+            gba.mem.set_u8(0x8011_u16, 0x33);
+            let tile = gba.mem.ppu.tile_data(1);
+            assert_eq!(tile[..8], [0, 1, 2, 3, 0, 1, 2, 3]);
+            assert!(tile[8..].iter().all(|&color| color == 0));
+            assert_eq!(gba.mem.ppu.tile_sheet()[8..16], [0, 1, 2, 3, 0, 1, 2, 3]);
+        }
+
+        #[test]
+        fn maps_follow_lcdc_selection() {
+            let mut gba = console(&[]);
+            gba.mem.set_u8(0xFF40_u16, 0x50);
+            gba.mem.set_u8(0xFF47_u16, 0xE4);
+            gba.mem.set_u8(0x8010_u16, 0xFF);
+            /* Tile 1 at the bottom-right of the $9C00 map only */
+            gba.mem.set_u8(0x9FFF_u16, 0x01);
+
+            let window = gba.mem.ppu.map_image(TileMap::Window);
+            let background = gba.mem.ppu.map_image(TileMap::Background);
+            assert_eq!(window.len(), MAP_SIZE * MAP_SIZE);
+            assert_eq!(window[248 * MAP_SIZE + 248], 1);
+            assert_eq!(background[248 * MAP_SIZE + 248], 0);
+        }
+
+        #[test]
+        fn sprites_read_oam_entries() {
+            let mut gba = console(&[]);
+            for (i, byte) in [0x10, 0x08, 0x42, 0xB0].into_iter().enumerate() {
+                gba.mem.set_u8(0xFE04 + i as u16, byte);
+            }
+            let sprites = gba.mem.ppu.sprites();
+            assert_eq!(sprites.len(), 40);
+            let sprite = sprites[1];
+            assert_eq!((sprite.screen_x(), sprite.screen_y(), sprite.tile), (0, 0, 0x42));
+            assert!(sprite.behind_background() && sprite.flip_x() && !sprite.flip_y());
+            assert_eq!(sprite.dmg_palette(), 1);
+        }
+    }
+    // }}}
+
+    // mod stat {{{
+    mod stat {
+        use crate::ppu::prelude::Ppu;
+
+        const STAT_IRQ: u8 = 0x02;
+
+        fn lcd(stat: u8, lyc: u8) -> Ppu {
+            let mut ppu = Ppu::default();
+            ppu.write_register(0xFF45, lyc);
+            ppu.write_register(0xFF40, 0x80);
+            ppu.write_register(0xFF41, stat);
+            ppu.tick(0);
+            ppu
+        }
+
+        /* STAT interrupts raised over `lines` scanlines, tagged with the
+         * (LY, dot) reached by the tick that raised them */
+        fn stat_irqs(ppu: &mut Ppu, lines: usize) -> Vec<(u8, usize)> {
+            let mut irqs = Vec::new();
+            for cycle in 1..=lines * 114 {
+                if ppu.tick(1) & STAT_IRQ != 0 {
+                    irqs.push((ppu.ly, (cycle % 114) * 4));
+                }
+            }
+            irqs
+        }
+
+        #[test]
+        fn hblank_fires_once_per_line() {
+            let mut ppu = lcd(0x08, 0xFF);
+            assert_eq!(stat_irqs(&mut ppu, 3).iter().map(|&(ly, _)| ly).collect::<Vec<_>>(), [0, 1, 2]);
+        }
+
+        #[test]
+        fn lyc_blocks_following_hblank() {
+            /* HBlank of line 0 raises the line, LY=LYC=1 keeps it high
+             * through line 1 so its HBlank does not fire again */
+            let mut ppu = lcd(0x48, 1);
+            assert_eq!(stat_irqs(&mut ppu, 3).iter().map(|&(ly, _)| ly).collect::<Vec<_>>(), [0, 2]);
+        }
+
+        #[test]
+        fn hblank_blocks_vblank() {
+            let mut ppu = lcd(0x18, 0xFF);
+            let irqs = stat_irqs(&mut ppu, 154);
+            assert_eq!(irqs.len(), 144);
+            assert!(irqs.iter().all(|&(ly, _)| ly < 144));
+        }
+
+        #[test]
+        fn vblank_alone_fires_at_line_144() {
+            let mut ppu = lcd(0x10, 0xFF);
+            assert_eq!(stat_irqs(&mut ppu, 154), [(144, 0)]);
+        }
+
+        #[test]
+        fn oam_and_lyc_on_same_line_fire_once() {
+            let mut ppu = lcd(0x60, 2);
+            let lines: Vec<u8> = stat_irqs(&mut ppu, 5).iter().map(|&(ly, _)| ly).collect();
+            /* Line 2 enters OAM scan and matches LYC at the same moment, and
+             * LY=LYC holding through line 2 blocks the OAM scan of line 3 */
+            assert_eq!(lines, [0, 1, 2, 4, 5]);
+        }
+
+        #[test]
+        fn enabling_an_active_source_fires() {
+            let mut ppu = lcd(0x00, 0);
+            ppu.write_register(0xFF41, 0x40);
+            assert_eq!(ppu.tick(0) & STAT_IRQ, STAT_IRQ);
+            ppu.write_register(0xFF41, 0x48);
+            assert_eq!(ppu.tick(0) & STAT_IRQ, 0);
+        }
+    }
+    // }}}
+
+    // mod sprites {{{
+    mod sprites {
+        use crate::ppu::prelude::Ppu;
+
+        /* Tile 1 is solid color 3, tile 2 solid color 1 */
+        fn lcd() -> Ppu {
+            let mut ppu = Ppu::default();
+            ppu.vram[0x10..0x20].fill(0xFF);
+            for row in 0..8 {
+                ppu.vram[0x20 + row * 2] = 0xFF;
+            }
+            ppu.bgp = 0xE4;
+            ppu.obp0 = 0xE4;
+            ppu.obp1 = 0x44;
+            ppu.write_register(0xFF40, 0x93);
+            ppu
+        }
+
+        fn sprite(ppu: &mut Ppu, index: usize, x: u8, attributes: u8) {
+            ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[16, x, 1, attributes]);
+        }
+
+        /* Renders line 0 and returns its shades */
+        fn line(ppu: &mut Ppu) -> Vec<u8> {
+            ppu.tick(114);
+            ppu.framebuffer()[..160].to_vec()
+        }
+
+        #[test]
+        fn ten_sprites_per_line_in_oam_order() {
+            let mut ppu = lcd();
+            /* Placed right to left so OAM order and X order disagree */
+            for i in 0..12 {
+                sprite(&mut ppu, i, 8 + (11 - i as u8) * 12, 0);
+            }
+            assert_eq!(ppu.scan_oam().len(), 10);
+            let shades = line(&mut ppu);
+            assert_eq!(shades[0], 0);
+            assert_eq!(shades[12], 0);
+            assert_eq!(shades[24], 3);
+            assert_eq!(shades[132], 3);
+        }
+
+        #[test]
+        fn limit_can_be_disabled() {
+            let mut ppu = lcd();
+            ppu.set_sprite_limit(false);
+            for i in 0..12 {
+                sprite(&mut ppu, i, 8 + i as u8 * 12, 0);
+            }
+            assert_eq!(ppu.scan_oam().len(), 12);
+            assert!(line(&mut ppu).iter().step_by(12).take(12).all(|&shade| shade == 3));
+        }
+
+        #[test]
+        fn smaller_x_wins_then_lower_index() {
+            let mut ppu = lcd();
+            sprite(&mut ppu, 0, 20, 0x10);
+            sprite(&mut ppu, 1, 16, 0x00);
+            sprite(&mut ppu, 2, 40, 0x00);
+            sprite(&mut ppu, 3, 40, 0x10);
+            let shades = line(&mut ppu);
+            /* Sprite 1 covers 8..16, sprite 0 shows through from 16 */
+            assert_eq!(shades[12..20], [3, 3, 3, 3, 1, 1, 1, 1]);
+            assert_eq!(shades[32], 3);
+        }
+
+        #[test]
+        fn background_priority_hides_only_over_nonzero_bg() {
+            let mut ppu = lcd();
+            ppu.vram[0x1800] = 2;
+            sprite(&mut ppu, 0, 12, 0x80);
+            let shades = line(&mut ppu);
+            assert_eq!(shades[4..12], [1, 1, 1, 1, 3, 3, 3, 3]);
+        }
+
+        #[test]
+        fn tall_sprites_use_even_tile_and_flip() {
+            let mut ppu = lcd();
+            ppu.write_register(0xFF40, 0x97);
+            /* Tile 0 is blank; with Y flip line 0 reads row 15, which is tile 1 */
+            ppu.oam[..4].copy_from_slice(&[16, 8, 0x01, 0x40]);
+            assert_eq!(line(&mut ppu)[0], 3);
+        }
+    }
+    // }}}
+
+    // mod window {{{
+    mod window {
+        use crate::ppu::prelude::Ppu;
+
+        /* Window map is all tile 1, whose rows 0-2 are colors 3, 1 and 2 */
+        fn lcd(wx: u8, wy: u8) -> Ppu {
+            let mut ppu = Ppu::default();
+            ppu.vram[0x10..0x16].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0x00, 0x00, 0xFF]);
+            ppu.vram[0x1C00..0x2000].fill(1);
+            ppu.bgp = 0xE4;
+            ppu.wx = wx;
+            ppu.wy = wy;
+            ppu.write_register(0xFF40, 0xF1);
+            ppu
+        }
+
+        fn next_line(ppu: &mut Ppu) -> Vec<u8> {
+            let row = ppu.ly as usize * 160;
+            ppu.tick(114);
+            ppu.framebuffer()[row..row + 160].to_vec()
+        }
+
+        #[test]
+        fn starts_at_wy_and_wx_minus_7() {
+            let mut ppu = lcd(17, 1);
+            assert!(next_line(&mut ppu).iter().all(|&shade| shade == 0));
+            let line = next_line(&mut ppu);
+            assert!(line[..10].iter().all(|&shade| shade == 0));
+            assert!(line[10..].iter().all(|&shade| shade == 3));
+            assert!(next_line(&mut ppu)[10..].iter().all(|&shade| shade == 1));
+        }
+
+        #[test]
+        fn wx_below_7_clips_the_left_edge() {
+            let mut ppu = lcd(3, 0);
+            /* Window column 4 onwards lands at x=0: tile 1 then the 8px boundary at x=4 */
+            ppu.vram[0x1C00] = 0;
+            let line = next_line(&mut ppu);
+            assert_eq!(line[..6], [0, 0, 0, 0, 3, 3]);
+        }
+
+        #[test]
+        fn hidden_lines_do_not_advance_the_window_row() {
+            let mut ppu = lcd(7, 0);
+            assert!(next_line(&mut ppu).iter().all(|&shade| shade == 3));
+            ppu.write_register(0xFF40, 0xD1);
+            next_line(&mut ppu);
+            next_line(&mut ppu);
+            ppu.write_register(0xFF40, 0xF1);
+            assert!(next_line(&mut ppu).iter().all(|&shade| shade == 1));
+            assert!(next_line(&mut ppu).iter().all(|&shade| shade == 2));
+        }
+
+        #[test]
+        fn wx_past_166_hides_the_window() {
+            let mut ppu = lcd(167, 0);
+            assert!(next_line(&mut ppu).iter().all(|&shade| shade == 0));
+        }
+    }
+    // }}}
+
+    // mod fifo {{{
+    mod fifo {
+        use crate::ppu::prelude::{LcdMode, Ppu, Renderer};
+
+        /* Every BG tile is tile 1, solid color 3 */
+        fn lcd(renderer: Renderer) -> Ppu {
+            let mut ppu = Ppu::default();
+            ppu.set_renderer(renderer);
+            ppu.vram[0x10..0x20].fill(0xFF);
+            ppu.vram[0x1800..0x1C00].fill(1);
+            ppu.bgp = 0xE4;
+            ppu.write_register(0xFF40, 0x91);
+            ppu
+        }
+
+        /* M-cycles line 0 spends in mode 3 */
+        fn drawing_cycles(ppu: &mut Ppu) -> usize {
+            ppu.tick(20);
+            let mut cycles = 0;
+            while ppu.mode() == LcdMode::Drawing {
+                ppu.tick(1);
+                cycles += 1;
+            }
+            cycles
+        }
+
+        #[test]
+        fn mode_3_lasts_172_dots_unscrolled() {
+            assert_eq!(drawing_cycles(&mut lcd(Renderer::Fifo)), 43);
+            assert_eq!(drawing_cycles(&mut lcd(Renderer::Scanline)), 43);
+        }
+
+        #[test]
+        fn fine_scroll_and_sprites_lengthen_mode_3() {
+            let mut ppu = lcd(Renderer::Fifo);
+            ppu.scx = 3;
+            assert_eq!(drawing_cycles(&mut ppu), 44);
+
+            let mut ppu = lcd(Renderer::Fifo);
+            ppu.write_register(0xFF40, 0x93);
+            ppu.oam[..4].copy_from_slice(&[16, 40, 0, 0]);
+            assert!(drawing_cycles(&mut ppu) > 43);
+        }
+
+        #[test]
+        fn mid_line_bgp_write_lands_on_the_next_pixel() {
+            let mut ppu = lcd(Renderer::Fifo);
+            /* Dot 160: pixels go out from dot 93, so 0..68 are done */
+            ppu.tick(40);
+            ppu.bgp = 0x24;
+            ppu.tick(74);
+            let line = &ppu.framebuffer()[..160];
+            assert!(line[..68].iter().all(|&shade| shade == 3));
+            assert!(line[68..].iter().all(|&shade| shade == 0));
+
+            let mut ppu = lcd(Renderer::Scanline);
+            ppu.tick(40);
+            ppu.bgp = 0x24;
+            ppu.tick(74);
+            assert!(ppu.framebuffer()[..160].iter().all(|&shade| shade == 0));
+        }
+
+        #[test]
+        fn mid_line_scx_write_moves_the_next_fetch() {
+            let mut ppu = lcd(Renderer::Fifo);
+            /* Column 1 of the map is blank; scrolling by 8 mid-line pulls it in */
+            ppu.vram[0x1801] = 0;
+            ppu.vram[0x1800 + 11] = 0;
+            ppu.tick(40);
+            ppu.scx = 8;
+            ppu.tick(74);
+            let line = &ppu.framebuffer()[..160];
+            assert_eq!(line[8], 0);
+            assert_eq!(line[80], 0);
+            assert_eq!(line[88], 3);
+        }
+
+        #[test]
+        fn renderers_agree_on_a_static_frame() {
+            let mut fifo = lcd(Renderer::Fifo);
+            let mut scanline = lcd(Renderer::Scanline);
+            for ppu in [&mut fifo, &mut scanline] {
+                ppu.scx = 5;
+                ppu.scy = 3;
+                ppu.wx = 60;
+                ppu.wy = 20;
+                ppu.vram[0x1C00..0x2000].fill(2);
+                ppu.vram[0x22..0x30].fill(0xAA);
+                ppu.oam[..8].copy_from_slice(&[30, 3, 2, 0x20, 40, 100, 1, 0x90]);
+                ppu.obp1 = 0x1B;
+                ppu.write_register(0xFF40, 0xF3);
+                ppu.tick(114 * 154);
+            }
+            assert_eq!(fifo.framebuffer(), scanline.framebuffer());
+        }
+    }
+    // }}}
+
+    // mod apu {{{
+    mod apu {
+        use super::console;
+        use crate::gba::prelude::Gba;
+
+        /* Powered on with DIV just reset, so DIV-APU edges land every 2048 M-cycles */
+        fn apu() -> Gba {
+            let mut gba = console(&[]);
+            gba.mem.set_u8(0xFF26_u16, 0x80);
+            gba.mem.set_u8(0xFF04_u16, 0);
+            gba
+        }
+
+        fn steps(gba: &mut Gba, count: usize) {
+            gba.mem.tick(2048 * count);
+        }
+
+        fn square1_on(gba: &Gba) -> bool {
+            gba.mem.get_u8(0xFF26_u16) & 0x01 != 0
+        }
+
+        /* Square 1 with its DAC on and `length` clocks left */
+        fn trigger(gba: &mut Gba, length: u8, control: u8) {
+            gba.mem.set_u8(0xFF12_u16, 0xF0);
+            gba.mem.set_u8(0xFF11_u16, 64 - length);
+            gba.mem.set_u8(0xFF14_u16, control);
+        }
+
+        #[test]
+        fn registers_read_back_through_masks() {
+            const MASKS: [u8; 0x20] = [
+                0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF,
+                0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF, 0xFF, 0x00, 0x00, 0xBF,
+                0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            ];
+            let mut gba = apu();
+            for (i, &mask) in MASKS.iter().enumerate() {
+                let addr = 0xFF10 + i as u16;
+                if addr == 0xFF26 {
+                    continue;
+                }
+                gba.mem.set_u8(addr, 0x00);
+                assert_eq!(gba.mem.get_u8(addr), mask, "{:#06X}", addr);
+                gba.mem.set_u8(addr, 0xFF);
+                assert_eq!(gba.mem.get_u8(addr), 0xFF, "{:#06X}", addr);
+                /* Don't leave channels running into the next register */
+                gba.mem.set_u8(addr, 0x00);
+            }
+        }
+
+        #[test]
+        fn power_off_clears_registers_but_not_wave_ram() {
+            let mut gba = apu();
+            gba.mem.set_u8(0xFF24_u16, 0x77);
+            gba.mem.set_u8(0xFF30_u16, 0x5A);
+            gba.mem.set_u8(0xFF26_u16, 0x00);
+            assert_eq!(gba.mem.get_u8(0xFF24_u16), 0x00);
+            assert_eq!(gba.mem.get_u8(0xFF26_u16), 0x70);
+            gba.mem.set_u8(0xFF24_u16, 0x33);
+            assert_eq!(gba.mem.get_u8(0xFF24_u16), 0x00);
+            assert_eq!(gba.mem.get_u8(0xFF30_u16), 0x5A);
+        }
+
+        #[test]
+        fn length_runs_out_on_div_apu_steps() {
+            let mut gba = apu();
+            trigger(&mut gba, 2, 0xC0);
+            assert!(square1_on(&gba));
+            /* Steps 0 and 2 clock length, step 1 does not */
+            steps(&mut gba, 2);
+            assert!(square1_on(&gba));
+            gba.mem.tick(2047);
+            assert!(square1_on(&gba));
+            gba.mem.tick(1);
+            assert!(!square1_on(&gba));
+        }
+
+        #[test]
+        fn div_write_clocks_the_frame_sequencer() {
+            let mut gba = apu();
+            trigger(&mut gba, 1, 0xC0);
+            /* DIV bit 4 is now set; resetting DIV drops it */
+            gba.mem.tick(1024);
+            assert!(square1_on(&gba));
+            gba.mem.set_u8(0xFF04_u16, 0);
+            assert!(!square1_on(&gba));
+        }
+
+        #[test]
+        fn enabling_length_before_a_quiet_step_takes_an_extra_clock() {
+            let mut gba = apu();
+            steps(&mut gba, 1);
+            trigger(&mut gba, 1, 0x80);
+            assert!(square1_on(&gba));
+            gba.mem.set_u8(0xFF14_u16, 0x40);
+            assert!(!square1_on(&gba));
+        }
+
+        #[test]
+        fn trigger_before_a_quiet_step_reloads_63() {
+            let mut gba = apu();
+            steps(&mut gba, 1);
+            /* Run the counter out with the extra clock, then trigger from zero */
+            trigger(&mut gba, 1, 0x80);
+            gba.mem.set_u8(0xFF14_u16, 0x40);
+            gba.mem.set_u8(0xFF14_u16, 0xC0);
+            /* Length clocks on every other step from here */
+            steps(&mut gba, 125);
+            assert!(square1_on(&gba));
+            steps(&mut gba, 1);
+            assert!(!square1_on(&gba));
+        }
+
+        /* Channel 2's DAC on at level 0, a constant +1 before panning and volume */
+        fn dc(gba: &mut Gba, nr50: u8, nr51: u8) -> Vec<f32> {
+            gba.mem.apu.mixer.set_high_pass(false);
+            gba.mem.set_u8(0xFF17_u16, 0x08);
+            gba.mem.set_u8(0xFF24_u16, nr50);
+            gba.mem.set_u8(0xFF25_u16, nr51);
+            gba.mem.apu.take_samples();
+            gba.mem.tick(1 << 14);
+            gba.mem.apu.take_samples()
+        }
+
+        #[test]
+        fn nr51_pans_and_nr50_scales_each_side() {
+            let mut gba = apu();
+            let samples = dc(&mut gba, 0x73, 0x20);
+            assert!(samples.chunks(2).all(|frame| frame == [0.25, 0.0]));
+            let samples = dc(&mut gba, 0x73, 0x22);
+            assert!(samples.chunks(2).all(|frame| frame == [0.25, 0.125]));
+        }
+
+        #[cfg(feature = "audio")]
+        #[test]
+        fn muted_and_unsoloed_channels_are_silent() {
+            use crate::apu::prelude::Channel;
+
+            let mut gba = apu();
+            gba.mem.apu.mixer.set_muted(Channel::Square2, true);
+            assert!(dc(&mut gba, 0x77, 0x22).iter().all(|&sample| sample == 0.0));
+            gba.mem.apu.mixer.set_solo(Some(Channel::Square2));
+            assert!(dc(&mut gba, 0x77, 0x22).iter().all(|&sample| sample == 0.25));
+            gba.mem.apu.mixer.set_solo(Some(Channel::Noise));
+            assert!(dc(&mut gba, 0x77, 0x22).iter().all(|&sample| sample == 0.0));
+            /* Still running underneath */
+            assert!(gba.mem.apu.dacs()[1]);
+
+            gba.mem.apu.mixer.set_solo(None);
+            gba.mem.apu.mixer.set_muted(Channel::Square2, false);
+            assert!(dc(&mut gba, 0x77, 0x22).iter().all(|&sample| sample == 0.25));
+        }
+
+        #[cfg(feature = "audio")]
+        #[test]
+        fn resamples_to_the_configured_rate() {
+            let mut gba = apu();
+            gba.mem.apu.set_sample_rate(44_100);
+            gba.mem.apu.take_samples();
+            gba.mem.tick(1 << 20);
+            assert_eq!(gba.mem.apu.take_samples().len(), 2 * 44_100);
+        }
+
+        #[cfg(feature = "audio")]
+        #[test]
+        fn high_pass_removes_dc_offset() {
+            let mut gba = apu();
+            dc(&mut gba, 0x77, 0xFF);
+            gba.mem.apu.mixer.set_high_pass(true);
+            gba.mem.tick(1 << 20);
+            let samples = gba.mem.apu.take_samples();
+            assert!(samples[0] > 0.2);
+            assert!(samples[samples.len() - 2..].iter().all(|sample| sample.abs() < 0.01));
+        }
+
+        #[cfg(feature = "audio")]
+        #[test]
+        fn wav_capture_records_taken_samples() {
+            let mut gba = apu();
+            gba.mem.apu.take_samples();
+            gba.mem.apu.start_wav();
+            gba.mem.tick(1 << 14);
+            let taken = gba.mem.apu.take_samples().len();
+            let wav = gba.mem.apu.stop_wav().unwrap();
+            assert!(gba.mem.apu.stop_wav().is_none());
+
+            assert_eq!(&wav[..4], b"RIFF");
+            assert_eq!(&wav[8..16], b"WAVEfmt ");
+            assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 48_000);
+            assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()) as usize, taken * 2);
+            assert_eq!(wav.len(), 44 + taken * 2);
+        }
+
+        #[test]
+        fn vgm_log_times_register_writes() {
+            let mut gba = apu();
+            gba.mem.set_u8(0xFF24_u16, 0x77);
+            gba.mem.apu.start_vgm();
+            gba.mem.tick(1 << 20);
+            gba.mem.set_u8(0xFF12_u16, 0xF0);
+            let vgm = gba.mem.apu.stop_vgm().unwrap();
+
+            let u32_at = |pos: usize| u32::from_le_bytes(vgm[pos..pos + 4].try_into().unwrap());
+            assert_eq!(&vgm[..4], b"Vgm ");
+            assert_eq!(u32_at(0x04) as usize, vgm.len() - 4);
+            assert_eq!(u32_at(0x18), 44_100);
+            assert_eq!(u32_at(0x80), 1 << 22);
+            /* Power, wave RAM, then the rest with NR50 as it was */
+            let data = &vgm[0x34 + u32_at(0x34) as usize..];
+            assert_eq!(&data[..3], &[0xB3, 0x16, 0x80]);
+            let dump = 1 + 0x10 + 0x16;
+            assert_eq!(&data[(dump - 2) * 3..(dump - 1) * 3], &[0xB3, 0x14, 0x77]);
+            assert_eq!(&data[dump * 3..], &[0x61, 0x44, 0xAC, 0xB3, 0x02, 0xF0, 0x66]);
+        }
+
+        #[cfg(not(feature = "audio"))]
+        #[test]
+        fn channels_run_without_mixing() {
+            let mut gba = apu();
+            gba.mem.set_u8(0xFF17_u16, 0xF0);
+            gba.mem.set_u8(0xFF19_u16, 0x80);
+            gba.mem.tick(1 << 16);
+            assert!(gba.mem.apu.channel_on(1));
+            assert!(gba.mem.apu.take_samples().is_empty());
+        }
+
+        #[test]
+        fn timer_counts_on_the_selected_div_bit() {
+            let mut gba = console(&[]);
+            gba.mem.set_u8(0xFF06_u16, 0xF0);
+            gba.mem.set_u8(0xFF05_u16, 0xFE);
+            gba.mem.set_u8(0xFF07_u16, 0x05);
+            gba.mem.tick(4);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0xFF);
+            gba.mem.tick(4);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x00);
+            gba.mem.tick(1);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0xF0);
+            assert_eq!(gba.mem.get_u8(0xFF0F_u16) & 0x04, 0x04);
+        }
+    }
+    // }}}
+
+    // mod config {{{
+    #[cfg_attr(not(feature = "frontend-sdl"), allow(unused))]
+    mod config {
+        use std::path::{Path, PathBuf};
+
+        use crate::{
+            config::prelude::{Config, ConfigError},
+            gba::prelude::AccuracyLevel,
+            input::prelude::Button,
+            ppu::prelude::{Palette, ScaleMode},
+        };
+
+        #[cfg(feature = "frontend-sdl")]
+        fn cli(args: &[&str]) -> crate::config::prelude::Cli {
+            crate::config::prelude::Cli::parse(args.iter().map(|arg| arg.to_string())).unwrap().unwrap()
+        }
+
+        #[test]
+        fn loads_every_section() {
+            let config = Config::from_toml(r#"
+                # comment
+                [core]
+                boot_rom = "dmg_boot.bin"
+                save_dir = '/saves'   # trailing comment
+
+                [video]
+                palette = "pocket"
+                scale = 3
+                scale_mode = "aspect"
+
+                [audio]
+                latency = 80
+                sample_rate = 44_100
+
+                [keys]
+                a = "K"
+            "#).unwrap();
+            assert_eq!(config.boot_rom, Some(PathBuf::from("dmg_boot.bin")));
+            assert_eq!(config.palette, Palette::POCKET);
+            assert_eq!(config.scale, 3);
+            assert_eq!(config.scale_mode, ScaleMode::Aspect);
+            assert_eq!(config.audio_buffer_frames(), 3528);
+            assert_eq!(config.keys.key(Button::A), "K");
+            assert_eq!(config.keys.button("return"), Some(Button::Start));
+            assert_eq!(config.save_path(Path::new("roms/tetris.gb")), PathBuf::from("/saves/tetris.sav"));
+        }
+
+        #[test]
+        fn reports_bad_lines_and_settings() {
+            assert_eq!(Config::from_toml("[video]\nscale 3"),
+                Err(ConfigError::Syntax { line: 2, message: "expected `key = value`".to_string() }));
+            assert!(matches!(Config::from_toml("[video]\nscael = 3"), Err(ConfigError::Setting { key, .. }) if key == "video.scael"));
+            assert!(matches!(Config::from_toml("[video]\nscale = \"big\""), Err(ConfigError::Setting { .. })));
+        }
+
+        #[cfg(feature = "frontend-sdl")]
+        #[test]
+        fn command_line_overrides_the_file() {
+            let cli = cli(&["--scale", "2", "--set", "keys.start=Space", "--set", "audio.latency=20", "--autosave", "30", "--accuracy", "fast", "game.gb"]);
+            assert_eq!(cli.rom, PathBuf::from("game.gb"));
+            let mut config = Config::from_toml("[video]\nscale = 4").unwrap();
+            for (section, key, value) in &cli.overrides {
+                config.set(section, key, value).unwrap();
+            }
+            assert_eq!(config.scale, 2);
+            assert_eq!(config.audio_latency, 20);
+            assert_eq!(config.keys.key(Button::Start), "Space");
+            assert_eq!(config.autosave, Some(30));
+            assert_eq!(config.accuracy, AccuracyLevel::Fast);
+        }
+
+        #[cfg(feature = "frontend-sdl")]
+        #[test]
+        fn channels_muted_from_the_command_line() {
+            use crate::{apu::prelude::Channel, gba::prelude::{Emulator, RomSource}};
+
+            let cli = cli(&["--mute", "wave, noise", "--solo", "square2", "game.gb"]);
+            let mut config = Config::default();
+            for (section, key, value) in &cli.overrides {
+                config.set(section, key, value).unwrap();
+            }
+            assert_eq!((config.mute.as_slice(), config.solo), ([Channel::Wave, Channel::Noise].as_slice(), Some(Channel::Square2)));
+            let emulator = Emulator::with_config(RomSource::Bytes(super::rom(b"").build()), &config).unwrap();
+            assert!(emulator.channel_muted(Channel::Noise) && !emulator.channel_muted(Channel::Square1));
+            assert_eq!(emulator.solo(), Some(Channel::Square2));
+
+            assert!(Config::from_toml("[audio]\nmute = \"wave, pulse\"").is_err());
+            assert!(Config::from_toml("[audio]\nsolo = \"wave, noise\"").is_err());
+            assert_eq!(Config::from_toml("[audio]\nsolo = \"off\"").unwrap().solo, None);
+        }
+
+        #[cfg(feature = "frontend-sdl")]
+        #[test]
+        fn command_line_errors() {
+            use crate::config::prelude::Cli;
+
+            assert!(Cli::parse(["--scale".to_string()]).is_err());
+            assert!(Cli::parse(["--bogus".to_string(), "game.gb".to_string()]).is_err());
+            assert!(Cli::parse(["--save-format", "sgm", "game.gb"].map(String::from)).is_err());
+            assert!(Cli::parse(Vec::new()).is_err());
+            assert!(Cli::parse(["-h".to_string()]).unwrap().is_none());
+            assert!(Cli::parse(["--raw", "C000x", "test.bin"].map(String::from)).is_err());
+        }
+
+        #[cfg(feature = "frontend-sdl")]
+        #[test]
+        fn raw_binaries_from_the_command_line() {
+            let raw = cli(&["--raw", "$0150", "--raw-ram", "test.bin"]);
+            assert_eq!((raw.raw_origin, raw.raw_ram), (Some(0x0150), true));
+            assert_eq!(cli(&["--raw", "0x200", "test.bin"]).raw_origin, Some(0x0200));
+            assert_eq!(cli(&["test.bin"]).raw_origin, None);
+        }
+    }
+    // }}}
+    // mod game_settings {{{
+    mod game_settings {
+        use crate::{
+            config::prelude::Config,
+            gba::prelude::{AccuracyLevel, Emulator, GameShark, RomSource},
+            mem::prelude::Cart,
+            ppu::prelude::Palette,
+        };
+
+        fn rom() -> Vec<u8> {
+            super::rom(b"GOLFER").build()
+        }
+
+        fn config(game: &str) -> Config {
+            let text = format!("[core]\nskip_boot = true\n[games.{}]\npalette = \"pocket\"\naccuracy = \"accurate\"\ncheat_codes = \"0142A0C0, 01070BC0\"\n", game);
+            Config::from_toml(&text).unwrap()
+        }
+
+        #[test]
+        fn overrides_only_their_game() {
+            let config = config("0123abcd");
+            let game = config.for_game("0123abcd");
+            assert_eq!((game.palette, game.accuracy), (Palette::POCKET, AccuracyLevel::Accurate));
+            assert_eq!(game.cheat_codes, vec![GameShark { kind: 0x01, value: 0x42, addr: 0xC0A0 }, GameShark { kind: 0x01, value: 0x07, addr: 0xC00B }]);
+            assert_eq!(game.cheat_codes[0].to_string(), "0142A0C0");
+            let other = config.for_game("ffffffff");
+            assert_eq!((other.palette, other.accuracy), (Palette::default(), AccuracyLevel::default()));
+            assert!(other.cheat_codes.is_empty());
+        }
+
+        #[test]
+        fn bad_overrides_fail_at_load() {
+            assert!(Config::from_toml("[games.0123abcd]\nsample_rate = 48000\n").is_err());
+            assert!(Config::from_toml("[games.0123abcd]\naccuracy = \"perfect\"\n").is_err());
+            /* ROM is not RAM */
+            assert!(Config::from_toml("[games.0123abcd]\ncheat_codes = \"01420040\"\n").is_err());
+        }
+
+        #[test]
+        fn applied_when_the_cart_goes_in() {
+            let id = Cart::from_bytes(rom()).unwrap().header_hash();
+            assert_eq!(id.len(), 8);
+            let mut emulator = Emulator::with_config(RomSource::Bytes(rom()), &config(&id)).unwrap();
+            assert_eq!(emulator.game_id(), id);
+            assert_eq!(emulator.console().mem.ppu.palette, Palette::POCKET);
+            assert_eq!(emulator.console().accuracy(), AccuracyLevel::Accurate);
+            emulator.run_frame().unwrap();
+            assert_eq!((emulator.console().mem.peek(0xC0A0), emulator.console().mem.peek(0xC00B)), (0x42, 0x07));
+
+            /* Switched off for the game, the codes stay put */
+            let mut config = config(&id);
+            config.games.get_mut(&id).unwrap().insert("cheats".to_string(), crate::config::prelude::Value::Boolean(false));
+            let mut emulator = Emulator::with_config(RomSource::Bytes(rom()), &config).unwrap();
+            emulator.run_frame().unwrap();
+            assert_ne!(emulator.console().mem.peek(0xC0A0), 0x42);
+        }
+
+        #[cfg(feature = "io")]
+        #[test]
+        fn slot_dir_gives_memory_roms_slots() {
+            let dir = super::scratch("game-slots");
+            let id = Cart::from_bytes(rom()).unwrap().header_hash();
+            let mut config = config(&id);
+            config.slot_dir = Some(dir.clone());
+            let mut emulator = Emulator::with_config(RomSource::Bytes(rom()), &config).unwrap();
+            emulator.save_slot(2).unwrap();
+            assert!(dir.join(format!("{}.ss2", id)).exists());
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+    // }}}
+
+    // mod gamepad {{{
+    mod gamepad {
+        use crate::{
+            config::prelude::Config,
+            input::prelude::{pad_id, Button, PadBindings, PadInput, PadState},
+        };
+
+        #[test]
+        fn default_layout_reads_sticks_hats_and_buttons() {
+            let bindings = PadBindings::default();
+            let mut state = PadState::default();
+            assert_eq!(bindings.pressed(&state), 0);
+            state.set_axis(0, -0x7FFF);
+            state.set_axis(7, 0x7FFF);
+            state.set_button(1, true);
+            /* A light touch is not a press */
+            state.set_axis(1, -0x1000);
+            assert_eq!(bindings.pressed(&state), 1 << Button::Left as u8 | 1 << Button::Down as u8 | 1 << Button::A as u8);
+        }
+
+        #[test]
+        fn inputs_round_trip_through_their_names() {
+            for input in [PadInput::Button(12), PadInput::Axis(3, true), PadInput::Axis(0, false)] {
+                assert_eq!(PadInput::parse(&input.to_string()), Some(input));
+            }
+            assert_eq!(PadInput::parse("axis 2"), None);
+            assert_eq!(PadBindings::parse_list("button 2, axis 6+"), Some(vec![PadInput::Button(2), PadInput::Axis(6, true)]));
+            assert_eq!(pad_id("Microsoft X-Box 360 pad"), "microsoft_x_box_360_pad");
+        }
+
+        #[test]
+        fn config_remaps_one_controller() {
+            let config = Config::from_toml("[pads.snes_pad]\na = \"button 2\"\nup = \"axis 1-\"\n").unwrap();
+            let pad = &config.pads["snes_pad"];
+            assert_eq!(pad.inputs(Button::A), &[PadInput::Button(2)]);
+            assert_eq!(pad.inputs(Button::Up), &[PadInput::Axis(1, false)]);
+            /* Unlisted buttons keep the defaults */
+            assert_eq!(pad.inputs(Button::Start), PadBindings::default().inputs(Button::Start));
+            assert!(Config::from_toml("[pads.snes_pad]\nturbo = \"button 2\"\n").is_err());
+            assert!(Config::from_toml("[pads.snes_pad]\na = \"trigger\"\n").is_err());
+        }
+
+        #[cfg(feature = "io")]
+        #[test]
+        fn remaps_are_saved_into_the_config_file() {
+            let dir = super::scratch("pads");
+            let path = dir.join("config.toml");
+            std::fs::write(&path, "# mine\n[video]\nscale = 2\n\n[pads.old]\na = \"button 5\"\n").unwrap();
+
+            let mut config = Config::load(&path).unwrap();
+            config.pads.entry("old".to_string()).or_default().set(Button::A, vec![PadInput::Button(3)]);
+            config.save_pad_bindings(&path, "old").unwrap();
+            let text = std::fs::read_to_string(&path).unwrap();
+            assert!(text.starts_with("# mine\n[video]\nscale = 2\n"));
+            assert_eq!(text.matches("[pads.old]").count(), 1);
+            assert_eq!(Config::load(&path).unwrap(), config);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+    // }}}
+
+    // mod input_sources {{{
+    mod input_sources {
+        use crate::{
+            config::prelude::Config,
+            gba::prelude::{Emulator, RomSource},
+            input::prelude::{turbo_down, Binding, BindingTable, Button, ButtonState, InputMerger, InputSource, Keyboard},
+        };
+
+        #[test]
+        fn keyboard_follows_the_config_case_insensitively() {
+            let config = Config::from_toml("[keys]\na = \"K\"\n[turbo]\nrate = 10\nb = \"J\"\n").unwrap();
+            let mut keyboard = Keyboard::new(config.keyboard());
+            keyboard.key_down("k");
+            keyboard.key_down("Return");
+            assert_eq!(keyboard.poll(0), ButtonState::default().with(Button::A).with(Button::Start));
+            keyboard.key_up("K");
+            keyboard.release_all();
+            assert_eq!(keyboard.poll(0), ButtonState::default());
+
+            /* Ten taps a second is three frames down, three up */
+            keyboard.key_down("J");
+            let taps: Vec<bool> = (0..7).map(|frame| keyboard.poll(frame).is_pressed(Button::B)).collect();
+            assert_eq!(taps, [true, true, true, false, false, false, true]);
+        }
+
+        #[test]
+        fn turbo_keeps_its_rate_over_a_long_run() {
+            let presses = (0..60 * 60).filter(|&frame| turbo_down(frame, 15) && !turbo_down(frame + 1, 15)).count();
+            /* 3600 frames at 59.73 fps are 60.27 seconds */
+            assert_eq!(presses, 904);
+        }
+
+        #[test]
+        fn rebinding_replaces_every_control_for_a_button() {
+            let mut table = BindingTable::default();
+            table.bind("x", Binding::Hold(Button::A));
+            table.bind("s", Binding::Turbo(Button::A));
+            table.bind("x", Binding::Hold(Button::A));
+            assert_eq!(table.controls(Button::A).count(), 2);
+            table.unbind(Button::A);
+            table.bind("c", Binding::Hold(Button::A));
+            assert_eq!(table.resolve(&["x", "c"], 0), ButtonState::default().with(Button::A));
+        }
+
+        #[test]
+        fn merged_sources_combine_and_cancel_opposites() {
+            let mut merger = InputMerger::default();
+            merger.add(|_| ButtonState::default().with(Button::Left).with(Button::A));
+            merger.add(|frame: u64| if frame % 2 == 0 { ButtonState::default().with(Button::Right) } else { ButtonState::default().with(Button::Start) });
+            assert_eq!(merger.poll(0), ButtonState::default().with(Button::A));
+            assert_eq!(merger.poll(1), ButtonState::default().with(Button::Left).with(Button::A).with(Button::Start));
+            merger.allow_opposites = true;
+            assert!(merger.poll(0).is_pressed(Button::Right));
+
+            let mut emulator = Emulator::new(RomSource::Bytes(super::rom(b"").build())).unwrap();
+            emulator.poll_input(&mut merger);
+            assert!(emulator.console().mem.joypad.is_pressed(Button::Right));
+        }
+    }
+    // }}}
+
+    // mod boot {{{
+    mod boot {
+        use crate::{
+            cpu::register::types::Register8,
+            gba::prelude::Gba,
+            mem::prelude::{Boot, BootRom, Cart},
+        };
+
+        /* A cart the DMG boot ROM accepts: logo and header checksum in place, JR -2 at $0100 */
+        fn cart() -> Cart {
+            Cart::from_bytes(super::rom(b"").program(&[0x00, 0x18, 0xFE, 0x00]).cart_type(0x01).logo().checksums().build()).unwrap()
+        }
+
+        #[test]
+        fn boot_rom_shadows_the_cart_until_ff50() {
+            let gba = Gba::with_boot(cart(), Boot::Embedded);
+            assert_eq!(gba.cpu.registers.pc, 0);
+            assert_eq!(gba.mem.get_u8(0x0000_u16), 0x31);
+
+            /* LD A,1; LDH ($50),A; then NOPs into the cart */
+            let mut image = vec![0; 0x100];
+            image[..4].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+            let mut gba = Gba::with_boot(cart(), Boot::Rom(BootRom::from_bytes(image).unwrap()));
+            assert_eq!(gba.mem.get_u8(0x0001_u16), 0x01);
+            while gba.cpu.registers.pc != 0x100 {
+                gba.step();
+            }
+            assert!(!gba.mem.boot_rom_mapped());
+            assert_eq!(gba.mem.get_u8(0x0001_u16), 0x00);
+        }
+
+        #[test]
+        fn embedded_boot_rom_reaches_the_cart() {
+            let mut gba = Gba::with_boot(cart(), Boot::Embedded);
+            let mut steps = 0;
+            while gba.cpu.registers.pc != 0x100 && steps < 10_000_000 {
+                gba.step();
+                steps += 1;
+            }
+            assert_eq!(gba.cpu.registers.pc, 0x100, "boot ROM hung at ${:04X}", gba.cpu.registers.pc);
+            assert!(!gba.mem.boot_rom_mapped());
+            assert_eq!(gba.cpu.registers.a, 0x01);
+            assert_eq!(gba.cpu.registers.sp, 0xFFFE);
+        }
+
+        #[test]
+        fn skip_leaves_post_boot_state() {
+            let gba = Gba::with_boot(cart(), Boot::Skip);
+            assert_eq!(gba.cpu.registers.pc, 0x100);
+            assert_eq!(gba.cpu.registers.get_r8(Register8::A), 0x01);
+            assert_eq!(gba.cpu.registers.get_r8(Register8::F), 0xB0);
+            assert!(!gba.mem.boot_rom_mapped());
+            assert_eq!(gba.mem.get_u8(0xFF40_u16), 0x91);
+            assert_eq!(gba.mem.get_u8(0xFF26_u16), 0xF1);
+            assert_eq!(gba.mem.get_u8(0xFF04_u16), 0xAB);
+            assert_eq!((gba.mem.get_u8(0xFF46_u16), gba.mem.dma_active()), (0xFF, false));
+        }
+
+        #[test]
+        fn images_are_checked_by_size_and_identified_by_checksum() {
+            assert!(BootRom::from_bytes(vec![0; 0x200]).is_err());
+            assert_eq!(BootRom::from_bytes(vec![0; 0x100]).unwrap().model(), None);
+            assert_eq!(BootRom::embedded().model(), Some("DMG"));
+
+            /* CGB images leave the cart header visible */
+            let cgb = BootRom::from_bytes(vec![0xAA; 0x900]).unwrap();
+            let mut gba = Gba::with_boot(cart(), Boot::Rom(cgb));
+            assert_eq!(gba.mem.get_u8(0x0200_u16), 0xAA);
+            assert_eq!(gba.mem.get_u8(0x0101_u16), 0x18);
+            gba.mem.set_u8(0xFF50_u16, 0x11);
+            assert_eq!(gba.mem.get_u8(0x0200_u16), 0x00);
+        }
+
+        #[test]
+        fn emulator_takes_both_images_as_bytes() {
+            use std::io::ErrorKind;
+
+            use crate::{config::prelude::Config, Emulator, GbError};
+
+            let rom = cart().data;
+            let mut config = Config { boot_rom: Some("ignored.bin".into()), ..Config::default() };
+            let emulator = Emulator::from_bytes(&rom, Some(&[0x18; 0x100]), &config).unwrap();
+            assert_eq!(emulator.console().cpu.registers.pc, 0);
+            assert_eq!(emulator.console().mem.peek(0x0000), 0x18);
+            assert_eq!(Emulator::from_bytes(&rom, None, &config).unwrap().console().mem.peek(0x0000), 0x31);
+            assert_eq!(Emulator::from_bytes(&rom, Some(&[0; 3]), &config).err(), Some(GbError::Io(ErrorKind::InvalidData)));
+
+            config.skip_boot = true;
+            let emulator = Emulator::from_bytes(&rom, Some(&[0x18; 0x100]), &config).unwrap();
+            assert_eq!(emulator.console().cpu.registers.pc, 0x100);
+            assert!(emulator.save_path().is_none());
+        }
+    }
+    // }}}
+
+    // mod cart_ram {{{
+    mod cart_ram {
+        use crate::{gba::prelude::Gba, mem::prelude::{Cart, Controller, NINTENDO_GRAPHIC}};
+
+        fn cart(cart_type: u8, ram_size: u8, rom_len: usize) -> Gba {
+            let mut rom = super::rom(b"").cart_type(cart_type).ram_size(ram_size).size(rom_len).build();
+            /* Tag each bank with its number */
+            for bank in 0..rom_len / 0x4000 {
+                rom[bank * 0x4000 + 0x200] = bank as u8;
+            }
+            Gba::from_cart(Cart::from_bytes(rom).unwrap())
+        }
+
+        #[test]
+        fn ram_is_gated_and_banked_through_mbc1() {
+            let mut gba = cart(0x03, 0x03, 0x8000);
+            assert_eq!(gba.mem.cart_ram().len(), 0x8000);
+            gba.mem.set_u8(0xA000_u16, 0x12);
+            assert_eq!(gba.mem.get_u8(0xA000_u16), 0xFF);
+
+            gba.mem.set_u8(0x0000_u16, 0x0A);
+            gba.mem.set_u8(0xA000_u16, 0x12);
+            gba.mem.set_u8(0x6000_u16, 0x01);
+            gba.mem.set_u8(0x4000_u16, 0x02);
+            assert_eq!(gba.mem.ram_bank(), 2);
+            assert_eq!(gba.mem.get_u8(0xA000_u16), 0x00);
+            gba.mem.set_u8(0xA000_u16, 0x34);
+            assert_eq!(gba.mem.cart_ram()[0x4000], 0x34);
+
+            gba.mem.set_u8(0x4000_u16, 0x00);
+            assert_eq!(gba.mem.get_u8(0xA000_u16), 0x12);
+            gba.mem.set_u8(0x0000_u16, 0x00);
+            assert_eq!(gba.mem.get_u8(0xA000_u16), 0xFF);
+        }
+
+        #[test]
+        fn absent_ram_reads_open_bus_and_wram_echoes() {
+            let mut gba = cart(0x01, 0x00, 0x8000);
+            gba.mem.set_u8(0x0000_u16, 0x0A);
+            gba.mem.set_u8(0xA000_u16, 0x12);
+            assert_eq!(gba.mem.get_u8(0xA000_u16), 0xFF);
+
+            gba.mem.set_u8(0xC123_u16, 0x56);
+            assert_eq!(gba.mem.get_u8(0xE123_u16), 0x56);
+            gba.mem.set_u8(0xFDFF_u16, 0x78);
+            assert_eq!(gba.mem.get_u8(0xDDFF_u16), 0x78);
+        }
+
+        #[test]
+        fn rom_banks_switch_per_controller() {
+            /* MBC1 maps bank 0 requests to 1 */
+            let mut gba = cart(0x01, 0x00, 0x20000);
+            gba.mem.set_u8(0x2000_u16, 0x00);
+            assert_eq!(gba.mem.get_u8(0x4200_u16), 1);
+            gba.mem.set_u8(0x2000_u16, 0x05);
+            assert_eq!(gba.mem.get_u8(0x4200_u16), 5);
+
+            /* MBC5 can map bank 0 high */
+            let mut gba = cart(0x19, 0x00, 0x20000);
+            gba.mem.set_u8(0x2000_u16, 0x00);
+            assert_eq!(gba.mem.get_u8(0x4200_u16), 0);
+            gba.mem.set_u8(0x2000_u16, 0x07);
+            assert_eq!(gba.mem.rom_bank(), 7);
+
+            /* MBC2 RAM is 512 nibbles mirrored through $A000-$BFFF */
+            let mut gba = cart(0x06, 0x00, 0x20000);
+            gba.mem.set_u8(0x0000_u16, 0x0A);
+            gba.mem.set_u8(0xA001_u16, 0x3C);
+            assert_eq!(gba.mem.get_u8(0xA201_u16), 0xFC);
+            gba.mem.set_u8(0x0100_u16, 0x03);
+            assert_eq!(gba.mem.get_u8(0x4200_u16), 3);
+        }
+
+        #[test]
+        fn mbc1_multicarts_pick_games_with_bank2() {
+            let multicart = |games: usize| {
+                let mut rom = super::rom(b"").cart_type(0x01).size(0x100000).build();
+                for bank in 0..rom.len() / 0x4000 {
+                    rom[bank * 0x4000 + 0x200] = bank as u8;
+                }
+                for game in 0..games {
+                    rom[game * 0x40000 + 0x104..game * 0x40000 + 0x134].copy_from_slice(&NINTENDO_GRAPHIC);
+                }
+                Gba::from_cart(Cart::from_bytes(rom).unwrap())
+            };
+
+            let mut gba = multicart(4);
+            assert_eq!(gba.mem.controller(), Controller::MBC1M);
+            gba.mem.set_u8(0x4000_u16, 0x01);
+            gba.mem.set_u8(0x2000_u16, 0x01);
+            assert_eq!(gba.mem.get_u8(0x4200_u16), 0x11);
+            /* Bit 4 of BANK1 counts towards the zero check only */
+            gba.mem.set_u8(0x2000_u16, 0x10);
+            assert_eq!(gba.mem.get_u8(0x4200_u16), 0x10);
+            gba.mem.set_u8(0x6000_u16, 0x01);
+            assert_eq!(gba.mem.get_u8(0x0200_u16), 0x10);
+
+            /* A lone header is an ordinary 1MB MBC1 game */
+            let mut gba = multicart(1);
+            assert_eq!(gba.mem.controller(), Controller::MBC1);
+            gba.mem.set_u8(0x4000_u16, 0x01);
+            gba.mem.set_u8(0x2000_u16, 0x01);
+            assert_eq!(gba.mem.get_u8(0x4200_u16), 0x21);
+        }
+
+        #[test]
+        fn huc1_switches_ram_for_the_ir_port() {
+            let mut gba = cart(0xFF, 0x03, 0x20000);
+            assert_eq!(gba.mem.controller(), Controller::HuC1);
+            gba.mem.set_u8(0x4000_u16, 0x02);
+            gba.mem.set_u8(0xA000_u16, 0x12);
+            assert_eq!(gba.mem.get_u8(0xA000_u16), 0x12);
+
+            gba.mem.set_u8(0x0000_u16, 0x0E);
+            gba.mem.set_u8(0xA000_u16, 0x01);
+            assert_eq!(gba.mem.get_u8(0xA000_u16), 0xC0);
+            gba.mem.set_u8(0x0000_u16, 0x00);
+            assert_eq!(gba.mem.get_u8(0xA000_u16), 0x12);
+            assert_eq!(gba.mem.cart_ram()[0x4000], 0x12);
+
+            gba.mem.set_u8(0x2000_u16, 0x00);
+            assert_eq!(gba.mem.get_u8(0x4200_u16), 0);
+        }
+
+        #[test]
+        fn mbc30_reaches_eight_ram_banks_and_4mb() {
+            let mut gba = cart(0x13, 0x05, 0x400000);
+            assert_eq!(gba.mem.controller(), Controller::MBC30);
+            gba.mem.set_u8(0x2000_u16, 0x85);
+            assert_eq!((gba.mem.rom_bank(), gba.mem.get_u8(0x4200_u16)), (0x85, 0x85));
+
+            gba.mem.set_u8(0x0000_u16, 0x0A);
+            for bank in [3, 7] {
+                gba.mem.set_u8(0x4000_u16, bank);
+                gba.mem.set_u8(0xA000_u16, bank);
+            }
+            gba.mem.set_u8(0x4000_u16, 0x03);
+            assert_eq!(gba.mem.get_u8(0xA000_u16), 3);
+            assert_eq!(gba.mem.cart_ram()[7 * 0x2000], 7);
+
+            /* The same header with 32kB of RAM and 2MB is a plain MBC3 */
+            assert_eq!(cart(0x13, 0x03, 0x200000).mem.controller(), Controller::MBC3);
+        }
+    }
+    // }}}
+
+    // mod camera {{{
+    mod camera {
+        use crate::{
+            gba::prelude::{Emulator, RomSource},
+            mem::prelude::{StillImage, IMAGE_OFFSET, SENSOR_HEIGHT, SENSOR_WIDTH},
+        };
+
+        fn camera() -> Emulator {
+            let rom = super::rom(b"").cart_type(0xFC).ram_size(0x04).size(0x10000).at(0x8200, &[2]).build();
+            Emulator::new(RomSource::Bytes(rom)).unwrap()
+        }
+
+        /* Sets up the sensor, captures, and waits for it */
+        fn capture(emulator: &mut Emulator) -> Vec<u8> {
+            let mem = &mut emulator.console_mut().mem;
+            mem.set_u8(0x0000_u16, 0x0A);
+            mem.set_u8(0x4000_u16, 0x10);
+            /* Exposure at unity, every matrix position 0x40, 0x80, 0xC0 */
+            mem.set_u8(0xA002_u16, 0x08);
+            mem.set_u8(0xA003_u16, 0x00);
+            for position in 0..16_u16 {
+                for (i, threshold) in [0x40, 0x80, 0xC0].into_iter().enumerate() {
+                    mem.set_u8(0xA006 + position * 3 + i as u16, threshold);
+                }
+            }
+            mem.set_u8(0xA000_u16, 0x01);
+            assert_eq!(mem.get_u8(0xA000_u16) & 0x01, 0x01);
+            /* Registers are mirrored every $80 bytes */
+            assert_eq!(mem.get_u8(0xA080_u16) & 0x01, 0x01);
+            let mut cycles = 0;
+            while mem.get_u8(0xA000_u16) & 0x01 != 0 {
+                mem.tick(4);
+                cycles += 4;
+            }
+            assert!((32446..=32446 + 512 + 16 * 0x800 + 4).contains(&cycles));
+            mem.set_u8(0x4000_u16, 0x00);
+            (0..0xE00_u16).map(|i| mem.get_u8(0xA000 + IMAGE_OFFSET as u16 + i)).collect()
+        }
+
+        #[test]
+        fn boots_and_maps_bank_zero_and_ram() {
+            let mut emulator = camera();
+            let mem = &mut emulator.console_mut().mem;
+            mem.set_u8(0x2000_u16, 0x02);
+            assert_eq!(mem.get_u8(0x4200_u16), 2);
+            mem.set_u8(0x0000_u16, 0x0A);
+            mem.set_u8(0x4000_u16, 0x03);
+            mem.set_u8(0xA000_u16, 0x5A);
+            assert_eq!(mem.cart_ram()[3 * 0x2000], 0x5A);
+            /* With the registers mapped, writes go to the sensor */
+            mem.set_u8(0x4000_u16, 0x13);
+            mem.set_u8(0xA001_u16, 0x77);
+            assert_eq!(mem.get_u8(0xA001_u16), 0x00);
+            assert_eq!(mem.cart_ram()[3 * 0x2000 + 1], 0x00);
+        }
+
+        #[test]
+        fn captures_dither_the_sensor_image() {
+            /* Mid grey passes two thresholds of three: color 1 */
+            let mut emulator = camera();
+            let tiles = capture(&mut emulator);
+            assert!(tiles.chunks_exact(2).all(|row| row == [0xFF, 0x00]));
+
+            /* Dark on the left half, bright on the right */
+            let mut emulator = camera();
+            let mut luma = vec![0x00; SENSOR_WIDTH * SENSOR_HEIGHT];
+            luma.chunks_exact_mut(SENSOR_WIDTH).for_each(|row| row[SENSOR_WIDTH / 2..].fill(0xFF));
+            assert!(emulator.set_camera_sensor(StillImage(luma)));
+            let tiles = capture(&mut emulator);
+            assert_eq!(&tiles[..2], &[0xFF, 0xFF]);
+            assert_eq!(&tiles[15 * 16..15 * 16 + 2], &[0x00, 0x00]);
+        }
+    }
+    // }}}
+
+    // mod rumble {{{
+    mod rumble {
+        use crate::{config::prelude::Config, gba::prelude::{Emulator, RumbleEvent}};
+
+        /* MBC5+RUMBLE+RAM+BATTERY with 128kB of RAM, all NOPs */
+        fn rumble_cart() -> Emulator {
+            let rom = super::rom(b"").cart_type(0x1E).ram_size(0x04).size(0x10000).build();
+            Emulator::from_bytes(&rom, None, &Config { skip_boot: true, ..Config::default() }).unwrap()
+        }
+
+        #[test]
+        fn motor_bit_is_not_a_ram_bank_bit() {
+            let mut emulator = rumble_cart();
+            let mem = &mut emulator.console_mut().mem;
+            mem.set_u8(0x0000_u16, 0x0A);
+            mem.set_u8(0x4000_u16, 0x09);
+            mem.set_u8(0xA000_u16, 0x5A);
+            assert_eq!(mem.cart_ram()[0x2000], 0x5A);
+            assert_eq!(mem.cart_ram()[9 * 0x2000], 0x00);
+        }
+
+        #[test]
+        fn strength_changes_become_events() {
+            let mut emulator = rumble_cart();
+            emulator.run_frame().unwrap();
+            assert!(emulator.take_rumble_events().is_empty());
+
+            emulator.console_mut().mem.set_u8(0x4000_u16, 0x08);
+            emulator.run_frame().unwrap();
+            emulator.run_frame().unwrap();
+            assert_eq!(emulator.take_rumble_events(), vec![RumbleEvent { frame: 2, strength: 1.0 }]);
+
+            /* On for half a frame's M-cycles, then off through a whole frame */
+            emulator.console_mut().mem.tick(70224 / 8);
+            emulator.console_mut().mem.set_u8(0x4000_u16, 0x00);
+            emulator.run_frame().unwrap();
+            emulator.run_frame().unwrap();
+            let events = emulator.take_rumble_events();
+            assert_eq!(events.len(), 2);
+            assert!((0.3..0.37).contains(&events[0].strength), "{:?}", events);
+            assert_eq!(events[1], RumbleEvent { frame: 5, strength: 0.0 });
+        }
+
+        #[test]
+        fn carts_without_a_motor_never_rumble() {
+            let rom = super::rom(b"").cart_type(0x1B).ram_size(0x04).size(0x10000).build();
+            let mut emulator = Emulator::from_bytes(&rom, None, &Config { skip_boot: true, ..Config::default() }).unwrap();
+            emulator.console_mut().mem.set_u8(0x4000_u16, 0x08);
+            emulator.run_frame().unwrap();
+            assert!(emulator.take_rumble_events().is_empty());
+        }
+
+        #[test]
+        fn intensity_is_a_percentage() {
+            assert_eq!(Config::default().rumble, 100);
+            assert_eq!(Config::from_toml("[pads]\nrumble = 0\n").unwrap().rumble, 0);
+            assert!(Config::from_toml("[pads]\nrumble = 101\n").is_err());
+        }
+    }
+    // }}}
+
+    // mod oam_bug {{{
+    mod oam_bug {
+        use super::console;
+        use crate::{cpu::register::types::Register16, gba::prelude::Gba};
+
+        /* INC HL with HL in OAM, issued so its second M-cycle lands on row 2 of mode 2 */
+        fn inc_hl_in_oam(enabled: bool) -> Gba {
+            let mut gba = console(&[0x23]);
+            gba.mem.oam_bug = enabled;
+            for (i, byte) in gba.mem.ppu.oam.iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+            gba.cpu.registers.set_r16(Register16::HL, 0xFE40);
+            gba.mem.set_u8(0xFF40_u16, 0x80);
+            gba.mem.tick(1);
+            gba.step();
+            gba
+        }
+
+        #[test]
+        fn inc_during_oam_scan_corrupts_the_row_being_read() {
+            let gba = inc_hl_in_oam(true);
+            let oam = &gba.mem.ppu.oam;
+            let (a, b, c) = (0x1110_u16, 0x0908_u16, 0x0D0C_u16);
+            assert_eq!(u16::from_le_bytes([oam[16], oam[17]]), ((a ^ c) & (b ^ c)) ^ c);
+            assert_eq!(oam[18..24], [10, 11, 12, 13, 14, 15]);
+            assert_eq!(oam[8..16], [8, 9, 10, 11, 12, 13, 14, 15]);
+            assert_eq!(gba.cpu.registers.get_r16(Register16::HL), 0xFE41);
+        }
+
+        #[test]
+        fn corruption_is_opt_in() {
+            let gba = inc_hl_in_oam(false);
+            assert!(gba.mem.ppu.oam.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+        }
+    }
+    // }}}
+
+    // mod log {{{
+    mod log {
+        use crate::log::prelude::{Filter, Level};
+
+        #[test]
+        fn targets_match_on_path_boundaries_and_longest_wins() {
+            let filter = Filter::parse("warn, gbemu::cpu=trace, gbemu::cpu::irq=off, gbemu::mem").unwrap();
+            assert_eq!(filter.level("gbemu::cpu"), Level::Trace);
+            assert_eq!(filter.level("gbemu::cpu::irq"), Level::Off);
+            assert_eq!(filter.level("gbemu::cpuid"), Level::Warn);
+            assert_eq!(filter.level("gbemu::mem"), Level::Trace);
+            assert!(filter.enabled("gbemu::ppu", Level::Error));
+            assert!(!filter.enabled("gbemu::ppu", Level::Info));
+            assert_eq!(filter.max_level(), Level::Trace);
+        }
+
+        #[test]
+        fn empty_spec_logs_nothing() {
+            let filter = Filter::parse("").unwrap();
+            assert!(!filter.enabled("gbemu::cpu", Level::Error));
+            assert!(Filter::parse("gbemu::mem=loud").is_err());
+        }
+    }
+    // }}}
+
+    // mod info {{{
+    mod info {
+        use crate::{
+            mem::prelude::{Cart, CartInfo, Controller, HeaderError},
+            GbError,
+        };
+
+        #[test]
+        fn odd_header_bytes_are_reported_not_fatal() {
+            /* An SGB flag, cart type and destination with no meaning */
+            let mut rom = super::rom(b"TETRIS").sgb(0x07).cart_type(0x22).at(0x14A, &[0x05]).licensee(b"01").checksums().build();
+
+            let info = CartInfo::new(&Cart::parse(rom.clone()).unwrap());
+            assert_eq!(info.title, "TETRIS");
+            assert_eq!(info.mapper, None);
+            assert_eq!(info.licensee, "01");
+            assert!(!info.sgb);
+            assert!(info.header_checksum_valid && info.global_checksum_valid);
+            assert!(info.to_json().contains("\"cart_type\": \"UNKNOWN ($22)\""));
+
+            rom[0x147] = 0x1B;
+            assert_eq!(CartInfo::new(&Cart::parse(rom.clone()).unwrap()).mapper, Some(Controller::MBC5));
+            rom[0x148] = 0x52;
+            assert_eq!(Cart::parse(rom).err(), Some(GbError::Rom(HeaderError::RomSize(0x52))));
+            assert_eq!(Cart::parse(vec![0; 0x100]).err(), Some(GbError::Rom(HeaderError::TooShort(0x100))));
+        }
+
+        #[cfg(feature = "frontend-sdl")]
+        #[test]
+        fn info_subcommand_parses() {
+            use std::path::PathBuf;
+
+            use crate::config::prelude::{Cli, Command};
+
+            let cli = Cli::parse(["info", "--json", "game.gb"].map(String::from)).unwrap().unwrap();
+            assert_eq!(cli.command, Command::Info { json: true });
+            assert!(Cli::parse(["--json", "game.gb"].map(String::from)).is_err());
+
+            let cli = Cli::parse(["batch", "--threads", "4", "--frames", "60", "roms", "extra.gb"].map(String::from)).unwrap().unwrap();
+            assert_eq!(cli.command, Command::Batch { threads: Some(4) });
+            assert_eq!(cli.roms, [PathBuf::from("roms"), PathBuf::from("extra.gb")]);
+            assert_eq!(cli.frames, Some(60));
+            assert!(Cli::parse(["--threads", "4", "game.gb"].map(String::from)).is_err());
+
+            let cli = Cli::parse(["debug", "--skip-boot", "game.gb"].map(String::from)).unwrap().unwrap();
+            assert_eq!(cli.command, Command::Debug);
+            assert_eq!(cli.rom, PathBuf::from("game.gb"));
+        }
+    }
+    // }}}
+
+    // mod header {{{
+    mod header {
+        use crate::mem::prelude::Cart;
+
+        fn header(title: &[u8], cgb: u8) -> Cart {
+            /* Sixteen-byte titles run over the CGB flag, so they go in last */
+            let rom = super::rom(b"").size(0x150).program(&[0x00, 0xC3, 0x50, 0x01]).logo().cgb(cgb)
+                .at(0x134, title)
+                .at(0x144, b"01")
+                .at(0x14C, &[0x02, 0xAB, 0x12, 0x34])
+                .build();
+            Cart::parse(rom).unwrap()
+        }
+
+        #[test]
+        fn fields_come_from_their_own_bytes() {
+            let cart = header(b"ZELDA", 0x00);
+            let header = &cart.header;
+            assert_eq!(header.entry_point, [0x00, 0xC3, 0x50, 0x01]);
+            assert!(header.logo_valid());
+            assert_eq!(header.licensee_code(), "01");
+            assert_eq!(header.mask_rom_version, 0x02);
+            assert_eq!(header.compliment_check, 0xAB);
+            assert_eq!(header.checksum, 0x1234);
+        }
+
+        #[test]
+        fn titles_trim_padding_and_skip_cgb_fields() {
+            assert_eq!(header(b"SUPER MARIOLAND ", 0x00).header.title_str(), "SUPER MARIOLAND");
+            assert_eq!(header(b"SIXTEEN_CHARS_XY", 0x00).header.title_str(), "SIXTEEN_CHARS_XY");
+            assert_eq!(header(b"FIFTEEN_CHARS_X", 0x80).header.title_str(), "FIFTEEN_CHARS_X");
+
+            let crystal = header(b"PM_CRYSTAL\0BYTE", 0xC0);
+            assert_eq!(crystal.header.title_str(), "PM_CRYSTAL");
+            assert_eq!(crystal.header.manufacturer_code(), Some("BYTE"));
+            assert_eq!(header(b"PM_CRYSTAL\0BYTE", 0x00).header.manufacturer_code(), None);
+            assert_eq!(header(b"BAD\xFFTITLE", 0x00).header.title_str(), "BAD");
+        }
+    }
+    // }}}
+
+    // mod short_roms {{{
+    mod short_roms {
+        use crate::{
+            config::prelude::Config,
+            gba::prelude::Emulator,
+            mem::prelude::{Cart, CartInfo},
+        };
+
+        #[test]
+        fn a_bare_header_runs_as_32k() {
+            let rom = super::rom(b"").size(0x150).build();
+            let cart = Cart::from_bytes(rom.clone()).unwrap();
+            assert_eq!((cart.data.len(), cart.data_len), (0x8000, 0x150));
+            assert!(cart.data[0x150..].iter().all(|&byte| byte == 0xFF));
+
+            let config = Config::from_toml("[core]\nskip_boot = true").unwrap();
+            let mut emulator = Emulator::from_bytes(&rom, None, &config).unwrap();
+            emulator.run_frame().unwrap();
+            assert_eq!(emulator.console().mem.peek(0x7FFF), 0xFF);
+        }
+
+        #[test]
+        fn truncated_banks_wrap_at_the_header_size() {
+            /* MBC1 with 128kB in the header, cut off 8kB into the third bank */
+            let rom = super::rom(b"").cart_type(0x01).rom_size(0x02).size(0xA000).at(0x8000, &[0x22]).checksums().build();
+
+            let cart = Cart::from_bytes(rom).unwrap();
+            assert_eq!((cart.data.len(), cart.data_len), (0x20000, 0xA000));
+            let info = CartInfo::new(&cart);
+            assert!(info.global_checksum_valid);
+            assert_eq!((info.rom_size, info.file_size), (0x20000, 0xA000));
+
+            let mut emulator = Emulator::from_bytes(&cart.data[..0xA000], None, &Config::default()).unwrap();
+            let mem = &mut emulator.console_mut().mem;
+            for (bank, expected) in [(2, 0x22), (5, 0xFF), (10, 0x22)] {
+                mem.set_u8(0x2000_u16, bank);
+                assert_eq!(mem.peek(0x4000), expected, "bank {}", bank);
+            }
+        }
+    }
+    // }}}
+
+    // mod archive {{{
+    mod archive {
+        use std::io::ErrorKind;
+
+        use crate::{error::prelude::GbError, mem::prelude::Cart, ppu::image::{crc32, inflate}};
+
+        fn rom(title: &[u8]) -> Vec<u8> {
+            super::rom(title).logo().build()
+        }
+
+        /* Deflate as a single stored block */
+        fn stored_block(data: &[u8]) -> Vec<u8> {
+            let len = data.len() as u16;
+            let mut out = vec![0x01];
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(&(!len).to_le_bytes());
+            out.extend_from_slice(data);
+            out
+        }
+
+        /* (name, method, contents) entries, each stored or deflated */
+        fn zip(entries: &[(&str, u16, &[u8])]) -> Vec<u8> {
+            let (mut out, mut directory) = (Vec::new(), Vec::new());
+            for &(name, method, data) in entries {
+                let packed = if method == 8 { stored_block(data) } else { data.to_vec() };
+                let mut fields = Vec::new();
+                fields.extend_from_slice(&method.to_le_bytes());
+                fields.extend_from_slice(&[0; 4]);
+                fields.extend_from_slice(&crc32(data.iter().copied()).to_le_bytes());
+                fields.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+                fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                fields.extend_from_slice(&[0; 2]);
+
+                directory.extend_from_slice(&[0x50, 0x4B, 0x01, 0x02, 20, 0, 20, 0, 0, 0]);
+                directory.extend_from_slice(&fields);
+                directory.extend_from_slice(&[0; 10]);
+                directory.extend_from_slice(&(out.len() as u32).to_le_bytes());
+                directory.extend_from_slice(name.as_bytes());
+
+                out.extend_from_slice(&[0x50, 0x4B, 0x03, 0x04, 20, 0, 0, 0]);
+                out.extend_from_slice(&fields);
+                out.extend_from_slice(name.as_bytes());
+                out.extend_from_slice(&packed);
+            }
+            let offset = out.len() as u32;
+            out.extend_from_slice(&directory);
+            out.extend_from_slice(&[0x50, 0x4B, 0x05, 0x06, 0, 0, 0, 0]);
+            out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+            out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+            out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+            out.extend_from_slice(&offset.to_le_bytes());
+            out.extend_from_slice(&[0; 2]);
+            out
+        }
+
+        #[test]
+        fn loads_the_first_rom_in_an_archive() {
+            let (first, second) = (rom(b"FIRST"), rom(b"SECOND"));
+            let archive = zip(&[("readme.txt", 0, b"hello"), ("dir/First.GB", 8, &first), ("second.gbc", 0, &second)]);
+            let cart = Cart::from_bytes(archive).unwrap();
+            assert_eq!(cart.header.title_str(), "FIRST");
+            assert_eq!(cart.data, first);
+
+            let cart = Cart::from_bytes(zip(&[("second.gbc", 0, &second)])).unwrap();
+            assert_eq!(cart.header.title_str(), "SECOND");
+        }
+
+        #[test]
+        fn bad_archives_are_errors() {
+            assert_eq!(Cart::from_bytes(zip(&[("readme.txt", 0, b"hello")])).err(), Some(GbError::Io(ErrorKind::NotFound)));
+
+            let mut archive = zip(&[("game.gb", 0, &rom(b"GAME"))]);
+            archive[0x40] ^= 0xFF;
+            assert_eq!(Cart::from_bytes(archive).err(), Some(GbError::Io(ErrorKind::InvalidData)));
+
+            let archive = zip(&[("game.gb", 14, &rom(b"GAME"))]);
+            assert!(matches!(Cart::from_bytes(archive), Err(GbError::Unsupported(_))));
+
+            let mut truncated = zip(&[("game.gb", 0, &rom(b"GAME"))]);
+            truncated.truncate(0x1000);
+            assert!(Cart::from_bytes(truncated).is_err());
+        }
+
+        #[test]
+        fn inflating_stops_at_the_declared_size() {
+            /* 78 bytes of deflate that expand to 64kB of zeros */
+            let mut bomb = vec![0xED, 0xC1, 0x01, 0x01, 0x00, 0x00, 0x00, 0x80, 0x90, 0xFE, 0xAF, 0xEE, 0x08, 0x0A];
+            bomb.resize(77, 0x00);
+            bomb.push(0x6A);
+            assert_eq!(inflate(&bomb, 0x10000), Ok(vec![0; 0x10000]));
+            assert_eq!(inflate(&bomb, 0x8000), Err(ErrorKind::InvalidData));
+            assert_eq!(inflate(&stored_block(&[0; 0x100]), 0xFF), Err(ErrorKind::InvalidData));
+
+            /* Past the largest ROM a header can declare, nothing is inflated */
+            let mut archive = zip(&[("game.gb", 8, &rom(b"GAME"))]);
+            let end = archive.len() - 22;
+            let directory = u32::from_le_bytes(archive[end + 16..end + 20].try_into().unwrap()) as usize;
+            archive[directory + 24..directory + 28].copy_from_slice(&0x0100_0000_u32.to_le_bytes());
+            assert_eq!(Cart::from_bytes(archive).err(), Some(GbError::Io(ErrorKind::InvalidData)));
+        }
+    }
+    // }}}
+
+    // mod registers {{{
+    mod registers {
+        use super::console;
+        use crate::cpu::register::{types::{Register16, Register8}, Registers};
+
+        #[test]
+        fn pairs_compose_high_byte_first() {
+            let mut regs = Registers::default();
+            regs.set_r16(Register16::BC, 0x1234);
+            assert_eq!((regs.b, regs.c), (0x12, 0x34));
+            regs.h = 0xAB;
+            regs.l = 0xCD;
+            assert_eq!(regs.get_hl(), 0xABCD);
+            regs.set_r8(Register8::SPHigh, 0xC0);
+            assert_eq!(regs.get_r16(Register16::SP), 0xC000);
+        }
+
+        #[test]
+        fn f_low_nibble_reads_zero() {
+            let mut regs = Registers::default();
+            regs.set_af(0x12FF);
+            assert_eq!(regs.get_af(), 0x12F0);
+            regs.set_r8(Register8::F, 0x0F);
+            assert_eq!(regs.get_r8(Register8::F), 0x00);
+        }
+
+        #[test]
+        fn pop_af_masks_f() {
+            /* LD SP,$D000; LD BC,$12FF; PUSH BC; POP AF; PUSH AF; POP DE */
+            let mut gba = console(&[0x31, 0x00, 0xD0, 0x01, 0xFF, 0x12, 0xC5, 0xF1, 0xF5, 0xD1]);
+            for _ in 0..6 {
+                gba.step();
+            }
+            assert_eq!(gba.cpu.registers.get_af(), 0x12F0);
+            assert_eq!(gba.cpu.registers.get_de(), 0x12F0);
+            assert_eq!(gba.mem.get_u16(0xCFFE_u16), 0x12F0);
+        }
+
+        #[test]
+        fn ld_sp_and_push_af_use_the_right_pairs() {
+            /* LD SP,$D000; LD A,$42; PUSH AF */
+            let mut gba = console(&[0x31, 0x00, 0xD0, 0x3E, 0x42, 0xF5]);
+            gba.cpu.registers.set_af(0x00B0);
+            for _ in 0..3 {
+                gba.step();
+            }
+            assert_eq!(gba.cpu.registers.sp, 0xCFFE);
+            assert_eq!(gba.mem.get_u16(0xCFFE_u16), 0x42B0);
+        }
+    }
+    // }}}
+
+    // mod read_masks {{{
+    mod read_masks {
+        use super::console;
+        use crate::mem::prelude::read_mask;
+
+        #[test]
+        fn unused_bits_read_as_one() {
+            let mut gba = console(&[]);
+            let mem = &mut gba.mem;
+            for (addr, value, read) in [(0xFF0F_u16, 0x01, 0xE1), (0xFF07, 0x05, 0xFD), (0xFF02, 0x01, 0x7F), (0xFF00, 0x30, 0xFF)] {
+                mem.set_u8(addr, value);
+                assert_eq!(mem.read(addr), read, "{:04X}", addr);
+            }
+            mem.set_u8(0xFF41_u16, 0x00);
+            assert_eq!(mem.read(0xFF41) & 0x80, 0x80);
+            assert_eq!(mem.read(0xFF1A), 0x7F | mem.peek(0xFF1A));
+        }
+
+        #[test]
+        fn missing_registers_read_ff() {
+            let mut gba = console(&[]);
+            for addr in [0xFF03, 0xFF08, 0xFF4C, 0xFF4F, 0xFF50, 0xFF55, 0xFF68, 0xFF7F] {
+                gba.mem.set_u8(addr, 0x00);
+                assert_eq!((gba.mem.read(addr), gba.mem.peek(addr)), (0xFF, 0xFF), "{:04X}", addr);
+            }
+        }
+
+        #[test]
+        fn cgb_mode_has_its_own_masks() {
+            assert_eq!((read_mask(0xFF02, false), read_mask(0xFF02, true)), (0x7E, 0x7C));
+            assert_eq!((read_mask(0xFF68, false), read_mask(0xFF68, true)), (0xFF, 0x40));
+            let mut gba = console(&[]);
+            gba.mem.set_cgb_mode(true);
+            gba.mem.set_u8(0xFF68_u16, 0x05);
+            assert_eq!(gba.mem.read(0xFF68), 0x45);
+        }
+    }
+    // }}}
+    // mod address {{{
+    mod address {
+        use super::console;
+        use crate::mem::prelude::{Address, Region};
+
+        #[test]
+        fn regions_tile_the_address_space() {
+            assert_eq!(Region::ALL.iter().map(|region| region.size()).sum::<usize>(), 0x10000);
+            for region in Region::ALL {
+                let range = region.range();
+                assert_eq!((Address(*range.start()).region(), Address(*range.end()).region()), (region, region));
+            }
+            assert_eq!((Region::Oam.size(), Region::Hram.size(), Region::Ie.base()), (0xA0, 0x7F, Address(0xFFFF)));
+        }
+
+        #[test]
+        fn offsets_belong_to_one_region() {
+            let oam = Address(0xFE10);
+            assert_eq!((oam.region(), oam.offset()), (Region::Oam, 0x10));
+            assert_eq!((oam.offset_in(Region::Oam), oam.offset_in(Region::Vram)), (Some(0x10), None));
+            assert_eq!(Address(0x9800).offset_in(Region::Vram), Some(0x1800));
+            assert!(Region::Io.contains(0xFF40_u16) && !Region::Io.contains(0xFF80_u16));
+            assert_eq!(Address(0xFEFE).wrapping_add(2), Address(0xFF00));
+            assert_eq!(Address(0xFF9E).to(Address(0xFFA1)).count(), 4);
+            assert_eq!((Address::from(0xC000_u16).to_string(), u16::from(Address(0x1234))), ("$C000".to_string(), 0x1234));
+        }
+
+        #[test]
+        fn echo_mirrors_both_wram_halves() {
+            assert_eq!(Address(0xE000).unecho(), Address(0xC000));
+            assert_eq!(Address(0xFDFF).unecho(), Address(0xDDFF));
+            assert_eq!(Address(0xFE00).unecho(), Address(0xFE00));
+
+            let mut gba = console(&[]);
+            gba.mem.set_u8(Address(0xC005), 0x12);
+            gba.mem.set_u8(Address(0xFDFF), 0x34);
+            assert_eq!((gba.mem.read(0xE005), gba.mem.read(0xDDFF)), (0x12, 0x34));
+            assert_eq!(gba.mem[Address(0xE005)], 0x12);
+        }
+    }
+    // }}}
+    // mod devices {{{
+    mod devices {
+        use super::console;
+        use crate::mem::prelude::{DeviceId, DeviceMap};
+
+        #[test]
+        fn every_register_has_one_owner() {
+            let mut gba = console(&[]);
+            let devices = gba.mem.devices();
+            assert_eq!(devices.get(0xFF00), Some(DeviceId::Joypad));
+            assert_eq!(devices.get(0xFF05), Some(DeviceId::Timer));
+            assert_eq!(devices.get(0xFF26), Some(DeviceId::Apu));
+            assert_eq!(devices.get(0xFF46), Some(DeviceId::Dma));
+            assert_eq!((devices.get(0xFF0F), devices.get(0xFF4D), devices.get(0xFF80)), (None, None, None));
+            assert_eq!(devices.claimed_by(DeviceId::Serial).collect::<Vec<_>>(), [0xFF01, 0xFF02]);
+            assert_eq!(devices.claimed_by(DeviceId::Ppu).count(), 11);
+
+            gba.mem.set_cgb_mode(true);
+            assert_eq!(gba.mem.devices().get(0xFF4F), Some(DeviceId::Ppu));
+            assert_eq!(gba.mem.devices().claimed_by(DeviceId::Ppu).count(), 16);
+        }
+
+        #[test]
+        #[should_panic(expected = "claimed by both")]
+        fn overlapping_claims_are_refused() {
+            let mut map = DeviceMap::default();
+            map.claim(DeviceId::Timer, &[0xFF04..=0xFF07]);
+            map.claim(DeviceId::Serial, &[0xFF02..=0xFF04]);
+        }
+
+        #[test]
+        fn writes_and_time_go_through_the_registry() {
+            let mut gba = console(&[]);
+            /* TIMA one short of overflow, counting every 4 M-cycles */
+            gba.mem.set_u8(0xFF05_u16, 0xFF);
+            gba.mem.set_u8(0xFF07_u16, 0x05);
+            assert_eq!(gba.mem.device(DeviceId::Timer).io_register(0xFF05), &0xFF);
+            gba.mem.tick(8);
+            assert_ne!(gba.mem.get_u8(0xFF0F_u16) & 0x04, 0);
+
+            gba.mem.set_u8(0xFF46_u16, 0xC1);
+            assert_eq!((gba.mem.read(0xFF46), gba.mem.dma_active()), (0xC1, true));
+        }
+    }
+    // }}}
+    // mod hooks {{{
+    mod hooks {
+        use std::{cell::RefCell, rc::Rc};
+
+        use super::console;
+
+        type Log = Rc<RefCell<Vec<(&'static str, u16, u8)>>>;
+
+        fn logger(log: &Log, name: &'static str) -> impl FnMut(u16, u8) + 'static {
+            let log = log.clone();
+            move |addr, value| log.borrow_mut().push((name, addr, value))
+        }
+
+        #[test]
+        fn hooks_see_the_address_and_value_in_range() {
+            let mut gba = console(&[]);
+            let log = Log::default();
+            gba.mem.on_write(0xC000..=0xC0FF, logger(&log, "write"));
+            gba.mem.on_read(0xC010..=0xC010, logger(&log, "read"));
+
+            gba.mem.set_u8(0xC010_u16, 0x42);
+            gba.mem.set_u8(0xC100_u16, 0x99);
+            assert_eq!(gba.mem.get_u8(0xC010_u16), 0x42);
+            gba.mem.get_u8(0xC011_u16);
+            assert_eq!(*log.borrow(), [("write", 0xC010, 0x42), ("read", 0xC010, 0x42)]);
+        }
+
+        #[test]
+        fn overlapping_hooks_fire_in_the_order_added() {
+            let mut gba = console(&[]);
+            let log = Log::default();
+            gba.mem.on_write(0xC000..=0xC0FF, logger(&log, "first"));
+            gba.mem.on_write(0xC000..=0xC000, logger(&log, "second"));
+            gba.mem.on_write(0xC000..=0xDFFF, logger(&log, "third"));
+
+            gba.mem.set_u8(0xC000_u16, 0x01);
+            assert_eq!(*log.borrow(), [("first", 0xC000, 0x01), ("second", 0xC000, 0x01), ("third", 0xC000, 0x01)]);
+        }
+
+        #[test]
+        fn removed_hooks_stop_firing() {
+            let mut gba = console(&[]);
+            let log = Log::default();
+            let removed = gba.mem.on_write(0xC000..=0xC000, logger(&log, "removed"));
+            gba.mem.on_write(0xC000..=0xC000, logger(&log, "kept"));
+
+            assert!(gba.mem.remove_hook(removed));
+            assert!(!gba.mem.remove_hook(removed));
+            gba.mem.set_u8(0xC000_u16, 0x07);
+            assert_eq!(*log.borrow(), [("kept", 0xC000, 0x07)]);
+        }
+
+        #[test]
+        fn clearing_drops_every_hook() {
+            let mut gba = console(&[]);
+            let log = Log::default();
+            let read = gba.mem.on_read(0xC000..=0xC000, logger(&log, "read"));
+            gba.mem.on_write(0xC000..=0xC000, logger(&log, "write"));
+
+            gba.mem.clear_hooks();
+            gba.mem.set_u8(0xC000_u16, 0x07);
+            gba.mem.get_u8(0xC000_u16);
+            assert!(log.borrow().is_empty());
+            assert!(!gba.mem.remove_hook(read));
+        }
+    }
+    // }}}
+
+    // mod cgb_banks {{{
+    mod cgb_banks {
+        use super::console;
+
+        #[test]
+        fn svbk_switches_d000_and_its_echo() {
+            let mut gba = console(&[]);
+            let mem = &mut gba.mem;
+            mem.set_cgb_mode(true);
+            for bank in 1..8_u8 {
+                mem.set_u8(0xFF70_u16, bank);
+                mem.set_u8(0xD123_u16, bank * 0x10);
+            }
+            mem.set_u8(0xC123_u16, 0xAA);
+            for bank in 1..8_u8 {
+                mem.set_u8(0xFF70_u16, bank);
+                assert_eq!((mem.read(0xD123), mem.read(0xF123)), (bank * 0x10, bank * 0x10));
+                assert_eq!(mem.read(0xE123), 0xAA);
+            }
+            /* Bank 0 selects bank 1, and only the low three bits count */
+            mem.set_u8(0xFF70_u16, 0x00);
+            assert_eq!((mem.read(0xD123), mem.read(0xFF70)), (0x10, 0xF8));
+            mem.set_u8(0xFF70_u16, 0x0A);
+            assert_eq!((mem.read(0xD123), mem.read(0xFF70)), (0x20, 0xFA));
+            assert_eq!(mem.wram().len(), 0x8000);
+        }
+
+        #[test]
+        fn vbk_switches_vram() {
+            let mut gba = console(&[]);
+            let mem = &mut gba.mem;
+            mem.set_cgb_mode(true);
+            mem.set_u8(0x8010_u16, 0x11);
+            mem.set_u8(0xFF4F_u16, 0x01);
+            assert_eq!((mem.read(0x8010), mem.read(0xFF4F)), (0x00, 0xFF));
+            mem.set_u8(0x9FFF_u16, 0x22);
+            mem.set_u8(0xFF4F_u16, 0xFE);
+            assert_eq!((mem.read(0x8010), mem.read(0x9FFF), mem.read(0xFF4F)), (0x11, 0x00, 0xFE));
+            /* Rendering reads bank 0 whatever the CPU has mapped */
+            assert_eq!((mem.ppu.vram[0x0010], mem.ppu.vram[0x3FFF]), (0x11, 0x22));
+        }
+
+        #[test]
+        fn dmg_ignores_bank_selects() {
+            let mut gba = console(&[]);
+            let mem = &mut gba.mem;
+            mem.set_u8(0xD000_u16, 0x33);
+            mem.set_u8(0x8000_u16, 0x44);
+            mem.set_u8(0xFF70_u16, 0x02);
+            mem.set_u8(0xFF4F_u16, 0x01);
+            assert_eq!((mem.read(0xD000), mem.read(0x8000)), (0x33, 0x44));
+            assert_eq!((mem.read(0xFF70), mem.read(0xFF4F)), (0xFF, 0xFF));
+            assert_eq!(mem.wram().len(), 0x2000);
+        }
+
+        #[test]
+        fn banks_survive_a_savestate() {
+            use crate::state::prelude::{Savestate, StateReader, StateWriter};
+
+            let mut gba = console(&[]);
+            gba.mem.set_cgb_mode(true);
+            gba.mem.set_u8(0xFF70_u16, 0x05);
+            gba.mem.set_u8(0xD000_u16, 0x55);
+            gba.mem.set_u8(0xFF4F_u16, 0x01);
+            gba.mem.set_u8(0x8000_u16, 0x66);
+            let mut w = StateWriter::new();
+            gba.mem.save_state(&mut w);
+            let state = w.into_inner();
+            let mut restored = console(&[]);
+            restored.mem.load_state(&mut StateReader::new(&state)).unwrap();
+            assert_eq!((restored.mem.read(0xD000), restored.mem.read(0x8000)), (0x55, 0x66));
+        }
+    }
+    // }}}
+    // mod speed_switch {{{
+    mod speed_switch {
+        use super::console;
+        use crate::{cpu::proc::CpuState, gba::prelude::Gba, input::prelude::Button};
+
+        /* LD A,1; LDH ($4D),A; STOP */
+        const SWITCH: [u8; 6] = [0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00];
+
+        #[test]
+        fn stop_with_key1_armed_doubles_cpu_and_timer_only() {
+            let mut gba = console(&SWITCH);
+            gba.mem.set_cgb_mode(true);
+            gba.mem.set_u8(0xFF40_u16, 0x80);
+            for _ in 0..3 {
+                gba.step();
+            }
+            assert_eq!(gba.cpu.state, CpuState::Running);
+            assert_eq!(gba.mem.get_u8(0xFF4D_u16), 0xFE);
+            assert_eq!(gba.mem.get_u8(0xFF04_u16), 0x00);
+
+            /* One line of PPU time is two lines' worth of CPU cycles */
+            let ly = gba.mem.get_u8(0xFF44_u16);
+            gba.mem.tick(114);
+            assert_eq!(gba.mem.get_u8(0xFF44_u16), ly);
+            assert_eq!(gba.mem.get_u8(0xFF04_u16), 1);
+            gba.mem.tick(114);
+            assert_eq!(gba.mem.get_u8(0xFF44_u16), ly + 1);
+
+            /* Arming again and stopping returns to normal speed */
+            gba.mem.set_u8(0xFF4D_u16, 0x01);
+            assert!(gba.mem.speed_switch());
+            assert_eq!(gba.mem.get_u8(0xFF4D_u16), 0x7E);
+        }
+
+        #[test]
+        fn dmg_mode_ignores_key1() {
+            let mut gba = console(&SWITCH);
+            for _ in 0..3 {
+                gba.step();
+            }
+            assert_eq!(gba.cpu.state, CpuState::Stopped);
+            assert_eq!(gba.mem.get_u8(0xFF4D_u16), 0xFF);
+            assert!(!gba.mem.double_speed());
+        }
+
+        /* LD A,$20; LDH ($00),A to select the d-pad; STOP; INC A as the
+         * padding byte; INC B; JR -2 */
+        fn stopper() -> Gba {
+            let mut gba = console(&[0x3E, 0x20, 0xE0, 0x00, 0x10, 0x3C, 0x04, 0x18, 0xFE]);
+            gba.mem.timer.set_internal_counter(0xAB00);
+            gba.step();
+            gba.step();
+            gba
+        }
+
+        #[test]
+        fn stop_skips_its_padding_and_freezes_div_until_a_button() {
+            let mut gba = stopper();
+            gba.step();
+            assert_eq!(gba.cpu.state, CpuState::Stopped);
+            assert_eq!(gba.cpu.registers.pc, 0x106);
+            for _ in 0..10 {
+                gba.step();
+            }
+            assert_eq!(gba.cpu.state, CpuState::Stopped);
+            assert_eq!(gba.mem.get_u8(0xFF04_u16), 0x00);
+
+            /* IE is clear; the input line going low is enough */
+            gba.mem.set_button(Button::Right, true);
+            gba.step();
+            assert_eq!(gba.cpu.state, CpuState::Running);
+            gba.step();
+            assert_eq!((gba.cpu.registers.a, gba.cpu.registers.b), (0x20, 0x01));
+        }
+
+        #[test]
+        fn unselected_buttons_do_not_wake_it() {
+            let mut gba = stopper();
+            gba.step();
+            gba.mem.set_button(Button::Start, true);
+            gba.step();
+            assert_eq!(gba.cpu.state, CpuState::Stopped);
+        }
+
+        #[test]
+        fn held_buttons_turn_stop_into_halt() {
+            let mut gba = stopper();
+            gba.mem.set_button(Button::Left, true);
+            gba.step();
+            assert_eq!(gba.cpu.state, CpuState::Halted);
+            assert_eq!(gba.cpu.registers.pc, 0x106);
+            assert_eq!(gba.mem.get_u8(0xFF04_u16), 0xAB);
+
+            /* With an interrupt pending it is a one-byte no-op */
+            let mut gba = stopper();
+            gba.mem.set_button(Button::Left, true);
+            gba.mem.set_u8(0xFFFF_u16, 0x04);
+            gba.mem.set_u8(0xFF0F_u16, 0x04);
+            gba.step();
+            assert_eq!(gba.cpu.state, CpuState::Running);
+            assert_eq!(gba.cpu.registers.pc, 0x105);
+        }
+
+        #[test]
+        fn pending_interrupts_keep_the_padding_byte() {
+            let mut gba = stopper();
+            gba.mem.set_u8(0xFFFF_u16, 0x04);
+            gba.mem.set_u8(0xFF0F_u16, 0x04);
+            gba.step();
+            assert_eq!(gba.cpu.state, CpuState::Stopped);
+            assert_eq!(gba.cpu.registers.pc, 0x105);
+            assert_eq!(gba.mem.get_u8(0xFF04_u16), 0x00);
+        }
+    }
+    // }}}
+
+    // mod sgb {{{
+    mod sgb {
+        use super::console;
+        use crate::{config::prelude::Config, gba::prelude::Gba, sgb::prelude::Sgb, Emulator, RomSource, SGB_HEIGHT, SGB_WIDTH};
+
+        fn sgb_console() -> Gba {
+            let mut gba = console(&[]);
+            gba.mem.sgb = Some(Sgb::default());
+            gba
+        }
+
+        /* Reset pulse, 128 bits LSB first with the lines released between, then the stop bit */
+        fn send(gba: &mut Gba, packet: &[u8; 16]) {
+            gba.mem.set_u8(0xFF00_u16, 0x00);
+            gba.mem.set_u8(0xFF00_u16, 0x30);
+            for bit in 0..128 {
+                let one = packet[bit / 8] >> (bit % 8) & 1 != 0;
+                gba.mem.set_u8(0xFF00_u16, if one { 0x10 } else { 0x20 });
+                gba.mem.set_u8(0xFF00_u16, 0x30);
+            }
+            gba.mem.set_u8(0xFF00_u16, 0x20);
+            gba.mem.set_u8(0xFF00_u16, 0x30);
+        }
+
+        #[test]
+        fn pal01_packet_sets_palettes() {
+            let mut gba = sgb_console();
+            let mut packet = [0; 16];
+            packet[0] = 0x01;
+            for (i, color) in [0x7FFF_u16, 0x001F, 0x03E0, 0x7C00, 0x1234, 0x2345, 0x3456].iter().enumerate() {
+                packet[1 + i * 2..3 + i * 2].copy_from_slice(&color.to_le_bytes());
+            }
+            send(&mut gba, &packet);
+
+            let sgb = gba.mem.sgb.as_ref().unwrap();
+            assert_eq!(sgb.palettes[0], [0x7FFF, 0x001F, 0x03E0, 0x7C00]);
+            assert_eq!(sgb.palettes[1], [0x7FFF, 0x1234, 0x2345, 0x3456]);
+            assert_eq!(sgb.palettes[3][0], 0x7FFF);
+        }
+
+        #[test]
+        fn mlt_req_cycles_player_ids_through_p1() {
+            let mut gba = sgb_console();
+            let mut packet = [0; 16];
+            packet[0] = 0x11 << 3 | 1;
+            packet[1] = 0x01;
+            send(&mut gba, &packet);
+            assert_eq!(gba.mem.sgb.as_ref().unwrap().players(), 2);
+            assert_eq!(gba.mem.get_u8(0xFF00_u16) & 0x0F, 0x0F);
+
+            for expected in [0x0E, 0x0F, 0x0E] {
+                gba.mem.set_u8(0xFF00_u16, 0x10);
+                gba.mem.set_u8(0xFF00_u16, 0x30);
+                assert_eq!(gba.mem.get_u8(0xFF00_u16) & 0x0F, expected);
+            }
+        }
+
+        #[test]
+        fn bordered_frame_only_for_sgb_carts_when_enabled() {
+            let mut rom = super::rom(b"").sgb(0x03).cart_type(0x01).build();
+            let config = Config { sgb: true, ..Config::default() };
+
+            let emu = Emulator::with_config(RomSource::Bytes(rom.clone()), &config).unwrap();
+            let frame = emu.sgb_framebuffer().unwrap();
+            assert_eq!(frame.len(), SGB_WIDTH * SGB_HEIGHT * 3);
+            /* Blank screen in palette 0 color 0, shown through the empty border */
+            assert!(frame.iter().all(|&channel| channel == 0xFF));
+
+            assert!(Emulator::new(RomSource::Bytes(rom.clone())).unwrap().sgb_framebuffer().is_none());
+            rom[0x146] = 0x00;
+            assert!(Emulator::with_config(RomSource::Bytes(rom), &config).unwrap().sgb_framebuffer().is_none());
+        }
+
+        #[test]
+        fn border_tiles_draw_over_the_backdrop() {
+            let mut sgb = Sgb::default();
+            /* Tile 1, plane 0 set on the top row: color 1 */
+            sgb.border_tiles[32] = 0xFF;
+            sgb.border_map[0] = 0x1001;
+            sgb.border_palettes[0][1] = 0x001F;
+
+            let frame = sgb.render(&[0; 160 * 144]);
+            assert_eq!(frame[..3], [0xFF, 0x00, 0x00]);
+            assert_eq!(frame[SGB_WIDTH * 3..SGB_WIDTH * 3 + 3], [0xFF, 0xFF, 0xFF]);
+        }
+    }
+    // }}}
+
+    // mod scheduler {{{
+    mod scheduler {
+        use super::console;
+        use crate::{cpu::proc::CpuState, scheduler::prelude::{Event, Scheduler}};
+
+        #[test]
+        fn events_come_out_in_deadline_order() {
+            let mut scheduler = Scheduler::default();
+            scheduler.schedule(Event::Serial, 30);
+            scheduler.schedule(Event::Ppu, 10);
+            scheduler.schedule(Event::TimerOverflow, 20);
+            scheduler.schedule(Event::Serial, 5);
+            assert_eq!(scheduler.until_next(), Some(5));
+
+            scheduler.advance(20);
+            assert_eq!(scheduler.pop_due(), Some(Event::Serial));
+            assert_eq!(scheduler.pop_due(), Some(Event::Ppu));
+            assert_eq!(scheduler.pop_due(), Some(Event::TimerOverflow));
+            assert_eq!(scheduler.pop_due(), None);
+        }
+
+        #[test]
+        fn peripherals_catch_up_on_register_reads() {
+            let mut gba = console(&[]);
+            gba.mem.advance(100);
+            assert_eq!(gba.mem.peek(0xFF04), 0);
+            assert_eq!(gba.mem.read(0xFF04), 1);
+        }
+
+        #[test]
+        fn halted_cpu_skips_to_the_timer_overflow() {
+            let setup = |gba: &mut crate::gba::prelude::Gba| {
+                gba.mem.set_u8(0xFF05_u16, 0xF0);
+                gba.mem.set_u8(0xFF07_u16, 0x05);
+                gba.mem.set_u8(0xFFFF_u16, 0x04);
+            };
+            let mut halted = console(&[0x76]);
+            setup(&mut halted);
+            let mut cycles = halted.step();
+            let mut steps = 0;
+            while halted.cpu.state == CpuState::Halted {
+                cycles += halted.step();
+                steps += 1;
+            }
+
+            /* The same wait one cycle at a time */
+            let mut reference = console(&[0x76]);
+            setup(&mut reference);
+            let mut expected = reference.step();
+            while reference.mem.peek(0xFF0F) & 0x04 == 0 {
+                reference.mem.tick(1);
+                expected += 1;
+            }
+            assert_eq!(cycles, expected);
+            assert!(steps <= 2, "{} steps", steps);
+        }
+    }
+    // }}}
+
+    // mod handle {{{
+    #[cfg(feature = "io")]
+    mod handle {
+        use std::{thread, time::Duration};
+
+        use crate::{config::prelude::Config, Button, EmulatorHandle, RomSource};
+
+        fn rom() -> RomSource {
+            RomSource::Bytes(super::rom(b"").cart_type(0x01).build())
+        }
+
+        fn wait_for_frame(handle: &mut EmulatorHandle) -> Vec<u8> {
+            for _ in 0..200 {
+                if let Some(frame) = handle.frame() {
+                    return frame.to_vec();
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            panic!("no frame from the emulation thread");
+        }
+
+        #[test]
+        fn runs_frames_until_stopped() {
+            let mut handle = EmulatorHandle::spawn(rom(), Config::default()).unwrap();
+            handle.set_turbo(true);
+            handle.set_button(Button::Start, true);
+            assert_eq!(wait_for_frame(&mut handle).len(), 160 * 144 * 3);
+            assert!(handle.running());
+
+            handle.pause();
+            thread::sleep(Duration::from_millis(20));
+            handle.frame();
+            thread::sleep(Duration::from_millis(20));
+            assert!(handle.frame().is_none());
+
+            handle.frame_advance();
+            wait_for_frame(&mut handle);
+            thread::sleep(Duration::from_millis(20));
+            assert!(handle.frame().is_none());
+
+            handle.resume();
+            wait_for_frame(&mut handle);
+            handle.stop();
+            assert!(!handle.running());
+        }
+
+        #[test]
+        fn load_errors_come_back_from_spawn() {
+            assert!(EmulatorHandle::spawn(RomSource::Bytes(vec![0; 0x100]), Config::default()).is_err());
+        }
+    }
+    // }}}
+
+    // mod gdb {{{
+    #[cfg(feature = "io")]
+    mod gdb {
+        use std::{io::{Read, Write}, net::{TcpListener, TcpStream}, thread};
+
+        use super::console;
+        use crate::debugger::prelude::{GdbAction, GdbStub, StopReason};
+
+        fn reply(text: &str) -> GdbAction {
+            GdbAction::Reply(text.to_string())
+        }
+
+        #[test]
+        fn register_and_memory_packets() {
+            let mut gba = console(&[0x00, 0x3C]);
+            let mut stub = GdbStub::default();
+            gba.cpu.registers.set_bc(0x1234);
+
+            let GdbAction::Reply(registers) = stub.handle(&mut gba, "g") else { panic!() };
+            assert_eq!(&registers[4..8], "3412");
+            assert_eq!(&registers[20..24], "0001");
+
+            assert_eq!(stub.handle(&mut gba, "P5=0002"), reply("OK"));
+            assert_eq!(gba.cpu.registers.pc, 0x0200);
+            assert_eq!(stub.handle(&mut gba, "m100,2"), reply("003c"));
+            assert_eq!(stub.handle(&mut gba, "Mc000,2:abcd"), reply("OK"));
+            assert_eq!(gba.mem.peek(0xC001), 0xCD);
+            assert_eq!(stub.handle(&mut gba, "m100"), reply("E01"));
+            assert_eq!(stub.handle(&mut gba, "vMustReplyEmpty"), reply(""));
+        }
+
+        #[test]
+        fn continue_stops_on_breakpoints() {
+            let mut gba = console(&[0x00, 0x00, 0x00, 0x18, 0xFB]);
+            let mut stub = GdbStub::default();
+            assert_eq!(stub.handle(&mut gba, "Z0,103,1"), reply("OK"));
+            assert_eq!(stub.handle(&mut gba, "c"), GdbAction::Continue);
+
+            assert_eq!(stub.debugger.resume(&mut gba, 1000), StopReason::Breakpoint(0x103));
+            /* Resuming steps off the breakpoint and loops back onto it */
+            assert_eq!(stub.debugger.resume(&mut gba, 1000), StopReason::Breakpoint(0x103));
+            assert_eq!(stub.handle(&mut gba, "z0,103,1"), reply("OK"));
+            assert_eq!(stub.debugger.resume(&mut gba, 1000), StopReason::Timeout);
+        }
+
+        #[test]
+        fn session_acks_and_answers_over_tcp() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let client = thread::spawn(move || {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                stream.write_all(b"$?#3f$s#73$D#44").unwrap();
+                let mut received = String::new();
+                stream.read_to_string(&mut received).unwrap();
+                received
+            });
+
+            let mut gba = console(&[0x00]);
+            let (stream, _) = listener.accept().unwrap();
+            GdbStub::default().session(&mut gba, stream).unwrap();
+
+            assert_eq!(client.join().unwrap(), "+$S05#b8+$S05#b8+$OK#9a");
+            assert_eq!(gba.cpu.registers.pc, 0x101);
+        }
+    }
+    // }}}
+
+    // mod repl {{{
+    mod repl {
+        use std::io::Cursor;
+
+        use super::console;
+        use crate::{
+            asm,
+            debugger::prelude::{Repl, ReplAction},
+        };
+
+        fn print(text: &str) -> ReplAction {
+            ReplAction::Print(text.to_string())
+        }
+
+        #[test]
+        fn commands_drive_the_debugger() {
+            let mut gba = console(&asm!("ld a, $3E; ld [$C000], a; inc a; jr -3"));
+            let mut repl = Repl::default();
+
+            assert_eq!(repl.handle(&mut gba, "step"), print("0102: LD [$C000], A"));
+            assert_eq!(repl.handle(&mut gba, ""), print("0105: INC A"));
+            assert_eq!(repl.handle(&mut gba, "regs"), print("AF=3E00 BC=0000 DE=0000 HL=0000 SP=0000 PC=0105 ---- IME=0 Running"));
+            assert_eq!(repl.handle(&mut gba, "x $C000 4"), print("C000: 3E 00 00 00"));
+            assert_eq!(repl.handle(&mut gba, "disasm $0100 2"), print("0100: LD A, $3E\n0102: LD [$C000], A"));
+
+            assert_eq!(repl.handle(&mut gba, "break $0106 if A == $41"), print("Breakpoint at $0106"));
+            assert_eq!(repl.handle(&mut gba, "watch A"), print("0: A = 62 ($3E)"));
+            assert_eq!(repl.handle(&mut gba, "c"), print("Breakpoint at $0106\n0: A = 65 ($41) (changed)\n0106: JR $0105"));
+            assert_eq!(repl.handle(&mut gba, "print A + 1"), print("66 ($42)"));
+            assert_eq!(repl.handle(&mut gba, "set [$C000] = 7"), print(""));
+            assert_eq!(gba.mem.peek(0xC000), 7);
+            assert_eq!(repl.handle(&mut gba, "delete $0106"), print(""));
+            assert_eq!(repl.handle(&mut gba, "delete $0106"), print("No breakpoint at $0106"));
+            assert_eq!(repl.handle(&mut gba, "frobnicate"), print("unknown command `frobnicate`; try `help`"));
+            assert_eq!(repl.handle(&mut gba, "quit"), ReplAction::Quit);
+        }
+
+        #[test]
+        fn watchpoints_stop_after_the_access() {
+            let mut gba = console(&asm!("ld a, $3E; ld [$C000], a; ld a, [$C000]; halt"));
+            let mut repl = Repl::default();
+
+            assert_eq!(repl.handle(&mut gba, "watch write $C000..$C0FF"), print("Watchpoint 1 on $C000..$C0FF"));
+            assert_eq!(repl.handle(&mut gba, "continue"), print("Wrote $3E to $C000\n0105: LD A, [$C000]"));
+            assert_eq!(repl.handle(&mut gba, "watch read $C000"), print("Watchpoint 2 on $C000..$C000"));
+            assert_eq!(repl.handle(&mut gba, "continue"), print("Read $3E from $C000\n0108: HALT"));
+        }
+
+        #[test]
+        fn sessions_run_over_any_stream() {
+            let mut gba = console(&asm!("nop; nop; nop"));
+            let mut output = Vec::new();
+            Repl::default().run(&mut gba, Cursor::new("step 2\nquit\nstep\n"), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), "0100: NOP\n(gba) 0102: NOP\n(gba) ");
+            assert_eq!(gba.cpu.registers.pc, 0x0102);
+        }
+    }
+    // }}}
+
+    // mod tui {{{
+    #[cfg(feature = "tui")]
+    mod tui {
+        use super::console;
+        use crate::{apu::prelude::Channel, asm, tui::prelude::{key_names, ChannelKey, TuiView}};
+
+        #[test]
+        fn terminal_bytes_name_keys() {
+            assert_eq!(key_names(b"\x1b[A\x1bOD\rzx\x7f \x03"), ["Up", "Left", "Return", "Z", "X", "Backspace", "Space", "Ctrl-C"]);
+            assert_eq!(key_names(b"\x1b"), ["Escape"]);
+            assert!(key_names(b"\x01~").is_empty());
+        }
+
+        #[test]
+        fn digits_mute_and_solo_channels() {
+            assert_eq!(ChannelKey::from_key("1"), Some(ChannelKey::Mute(Channel::Square1)));
+            assert_eq!(ChannelKey::from_key("4"), Some(ChannelKey::Mute(Channel::Noise)));
+            assert_eq!(ChannelKey::from_key("7"), Some(ChannelKey::Solo(Channel::Wave)));
+            assert_eq!((ChannelKey::from_key("9"), ChannelKey::from_key("Z")), (None, None));
+        }
+
+        #[test]
+        fn frames_rewrite_only_changed_lines() {
+            let mut gba = console(&asm!("ld a, $3E; inc a; halt"));
+            gba.step();
+            let mut view = TuiView::default();
+            view.trace_lines = 1;
+            let mut rgb = vec![0xFF; 2 * 4 * 3];
+            rgb[7 * 3..8 * 3].fill(0x00);
+
+            let lines = view.lines(&mut gba, &rgb, 2, 4);
+            assert_eq!(lines.len(), 3);
+            assert_eq!(lines[0], "\x1b[38;2;255;255;255;48;2;255;255;255m▀▀\x1b[0m AF=3E00 BC=0000");
+            assert!(lines[1].starts_with("\x1b[38;2;255;255;255;48;2;255;255;255m▀\x1b[38;2;255;255;255;48;2;0;0;0m▀\x1b[0m DE=0000"));
+            assert!(lines[2].starts_with("0100: 3E 3E    LD A, $3E"), "{}", lines[2]);
+
+            let first = view.draw(lines.clone());
+            assert!(first.starts_with("\x1b[2J\x1b[1;1H"));
+            assert_eq!(view.draw(lines.clone()), "");
+
+            let mut changed = lines;
+            changed[1] = "moved".to_string();
+            changed.pop();
+            assert_eq!(view.draw(changed), "\x1b[2;1Hmoved\x1b[K\x1b[3;1H\x1b[K");
+        }
+    }
+    // }}}
+
+    // mod conditions {{{
+    mod conditions {
+        use super::console;
+        use crate::debugger::prelude::{Breakpoint, Debugger, Expr, StopReason, WatchKind, Watchpoint};
+
+        #[test]
+        fn expressions_over_registers_flags_and_memory() {
+            let mut gba = console(&[]);
+            gba.cpu.registers.a = 0x3E;
+            gba.cpu.registers.b = 0x11;
+            gba.cpu.registers.set_hl(0xC000);
+            gba.mem.set_u8(0xC000_u16, 0x42);
+
+            let eval = |text: &str| Expr::parse(text).unwrap().eval(&gba);
+            assert_eq!(eval("A==0x3E && B>0x10"), 1);
+            assert_eq!(eval("a == $3e && b > $11"), 0);
+            assert_eq!(eval("[HL] + [hl + 1]"), 0x42);
+            assert_eq!(eval("1 << 4 + 1 | 1"), 33);
+            assert_eq!(eval("-(2 * 3) % 4 == -2 || !ZF"), 1);
+            assert_eq!(eval("PC / 0"), 0);
+
+            assert_eq!(Expr::parse("A ==").unwrap_err().position, 4);
+            assert_eq!(Expr::parse("A == Q").unwrap_err().message, "unknown name `Q`");
+            assert!(Expr::parse("(A").is_err());
+        }
+
+        #[test]
+        fn conditional_breakpoints_and_hit_counts() {
+            /* INC A; JR -3 */
+            let mut gba = console(&[0x3C, 0x18, 0xFD]);
+            let mut debugger = Debugger::default();
+            debugger.set_breakpoint(0x100, Breakpoint::when(Expr::parse("A == 5").unwrap()));
+            assert_eq!(debugger.resume(&mut gba, 1000), StopReason::Breakpoint(0x100));
+            assert_eq!(gba.cpu.registers.a, 5);
+
+            debugger.set_breakpoint(0x100, Breakpoint::default().on_hit(3));
+            assert_eq!(debugger.resume(&mut gba, 1000), StopReason::Breakpoint(0x100));
+            assert_eq!(gba.cpu.registers.a, 8);
+            assert_eq!(debugger.breakpoint(0x100).unwrap().hits, 3);
+        }
+
+        #[test]
+        fn write_watchpoints_match_values() {
+            /* LD HL,$C000; LD (HL),1; LD (HL),7; JR -2 */
+            let mut gba = console(&[0x21, 0x00, 0xC0, 0x36, 0x01, 0x36, 0x07, 0x18, 0xFE]);
+            let mut debugger = Debugger::default();
+            let id = debugger.add_watchpoint(&mut gba.mem, Watchpoint::new(0xC000..=0xC000, WatchKind::Write).value(7));
+
+            let hit = StopReason::Watchpoint { addr: 0xC000, value: 7, write: true };
+            assert_eq!(debugger.resume(&mut gba, 1000), hit);
+            assert_eq!(gba.cpu.registers.pc, 0x107);
+
+            assert!(debugger.remove_watchpoint(&mut gba.mem, id));
+            assert_eq!(debugger.resume(&mut gba, 100), StopReason::Timeout);
+        }
+    }
+    // }}}
+
+    // mod lockstep {{{
+    mod lockstep {
+        use crate::{
+            debugger::prelude::Lockstep,
+            gba::prelude::{AccuracyLevel, Gba},
+            input::prelude::ButtonState,
+            mem::prelude::{Boot, Cart},
+        };
+
+        /* 1499 INC A past the header, then switching to ROM bank 2, which
+         * differs at $4000 when `patched` */
+        fn bank_switcher(patched: bool) -> Gba {
+            let rom = super::rom(b"").cart_type(0x01).rom_size(0x01).size(0x10000)
+                .at(0x150, &[0x3C; 1499])
+                .at(0x150 + 1499, &[0x3E, 0x02, 0xEA, 0x00, 0x20])
+                .at(0x8000, &[if patched { 0x01 } else { 0x00 }])
+                .build();
+            let mut gba = Gba::from_cart(Cart::from_bytes(rom).unwrap());
+            gba.cpu.registers.pc = 0x150;
+            gba
+        }
+
+        #[test]
+        fn pins_down_the_first_differing_instruction() {
+            let mut lockstep = Lockstep::new(bank_switcher(false), bank_switcher(true));
+            lockstep.interval = 200;
+            let divergence = lockstep.run(2500, &mut |_| ButtonState::default()).unwrap();
+            assert_eq!(divergence.instruction, 1501);
+            assert_eq!(lockstep.instructions(), 1501);
+            assert_eq!(divergence.a.registers, divergence.b.registers);
+            assert_ne!(divergence.a.memory_hash, divergence.b.memory_hash);
+            assert_eq!(divergence.address, Some(0x4000));
+            assert!(divergence.to_string().starts_with("Diverged after instruction 1501\n"));
+        }
+
+        #[test]
+        fn identical_consoles_run_to_the_end() {
+            let cart = Cart::from_bytes(super::rom(b"").build()).unwrap();
+            let mut lockstep = Lockstep::accuracy(cart, Boot::Skip, AccuracyLevel::Balanced, AccuracyLevel::Balanced);
+            lockstep.interval = 300;
+            let mut polled = Vec::new();
+            assert_eq!(lockstep.run(1000, &mut |check| { polled.push(check); ButtonState(check as u8) }), None);
+            assert_eq!(lockstep.instructions(), 1000);
+            assert_eq!(polled, [0, 1, 2, 3]);
+            assert_eq!(lockstep.a.mem.peek(0xFF00), lockstep.b.mem.peek(0xFF00));
+        }
+    }
+    // }}}
+    // mod profiler {{{
+    mod profiler {
+        use super::console;
+        use crate::debugger::prelude::{Location, Profiler};
+
+        #[test]
+        fn cycles_are_attributed_per_pc_and_bank() {
+            /* LD A,1; LD ($2000),A; INC A; JR -3 */
+            let mut gba = console(&[0x3E, 0x01, 0xEA, 0x00, 0x20, 0x3C, 0x18, 0xFD]);
+            gba.profiler = Some(Profiler::default());
+            let cycles: usize = (0..2 + 2 * 10).map(|_| gba.step()).sum();
+
+            let profiler = gba.profiler.as_ref().unwrap();
+            let at = |pc| profiler.cost(Location { bank: Some(0), pc });
+            assert_eq!(at(0x100).cycles, 2);
+            assert_eq!(at(0x102).cycles, 4);
+            assert_eq!((at(0x105).cycles, at(0x105).instructions), (10, 10));
+            assert_eq!((at(0x106).cycles, at(0x106).instructions), (30, 10));
+            assert_eq!(profiler.total(), cycles as u64);
+
+            let hotspots = profiler.hotspots();
+            assert_eq!(hotspots[0].0, Location { bank: Some(0), pc: 0x106 });
+            let banks = profiler.banks();
+            assert_eq!(banks.len(), 1);
+            assert_eq!((banks[0].0, banks[0].1.cycles, banks[0].1.instructions), (Some(0), cycles as u64, 22));
+            assert!(profiler.report(2).contains("00:0106         30"));
+        }
+
+        #[test]
+        fn callgrind_output_groups_addresses_by_bank() {
+            let mut profiler = Profiler::default();
+            profiler.record(Location { bank: Some(2), pc: 0x4000 }, 3);
+            profiler.record(Location { bank: Some(1), pc: 0x4000 }, 2);
+            profiler.record(Location { bank: Some(1), pc: 0x4000 }, 2);
+            profiler.record(Location { bank: None, pc: 0xC000 }, 1);
+            profiler.record_idle(5);
+
+            let mut out = Vec::new();
+            profiler.write_callgrind(&mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert!(out.starts_with("# callgrind format\n"));
+            assert!(out.contains("summary: 13 4\n"));
+            let body = out.split_once("ob=rom\n").unwrap().1;
+            assert_eq!(body, "fn=ram\n0xC000 1 1\nfn=bank_01\n0x4000 4 2\nfn=bank_02\n0x4000 3 1\nfn=idle\n0x0000 5 0\n");
+        }
+    }
+    // }}}
+
+    // mod cdl {{{
+    mod cdl {
+        use super::console;
+        use crate::debugger::prelude::CodeDataLog;
+
+        #[test]
+        fn rom_bytes_are_logged_by_use() {
+            /* LD A,($0200); LD A,$01; LD ($2000),A; LD A,($4000); LD A,$02; LDH ($46),A; HALT */
+            let mut gba = console(&[0xFA, 0x00, 0x02, 0x3E, 0x01, 0xEA, 0x00, 0x20, 0xFA, 0x00, 0x40, 0x3E, 0x02, 0xE0, 0x46, 0x76]);
+            gba.mem.cdl = Some(CodeDataLog::new(gba.mem.cart().data.len()));
+            (0..7).for_each(|_| { gba.step(); });
+
+            let cdl = gba.mem.cdl.as_ref().unwrap();
+            let code = CodeDataLog::CODE | CodeDataLog::OPCODE;
+            assert_eq!(cdl.flags(0x100), code);
+            assert_eq!(cdl.flags(0x101), CodeDataLog::CODE);
+            assert_eq!(cdl.flags(0x103), code);
+            assert_eq!(cdl.flags(0x10F), code);
+            assert_eq!(cdl.flags(0x110), 0);
+            assert_eq!(cdl.flags(0x200), CodeDataLog::DATA | CodeDataLog::DMA);
+            assert_eq!(cdl.flags(0x29F), CodeDataLog::DMA);
+            assert_eq!(cdl.flags(0x2A0), 0);
+            /* $4000 in bank 1 is the second bank of the file */
+            assert_eq!(cdl.flags(0x4000), CodeDataLog::DATA);
+            assert_eq!(cdl.count(CodeDataLog::DATA), 2);
+            assert_eq!(cdl.count(CodeDataLog::OPCODE), 7);
+            assert_eq!(cdl.count(CodeDataLog::DMA), 0xA0);
+            assert_eq!(cdl.as_bytes().len(), 0x8000);
+        }
+
+        #[test]
+        fn logs_reload_only_for_the_same_rom_size() {
+            let mut cdl = CodeDataLog::new(0x8000);
+            cdl.mark(0x4001, CodeDataLog::DATA);
+            let reloaded = CodeDataLog::from_bytes(cdl.as_bytes().to_vec(), 0x8000).unwrap();
+            assert_eq!(reloaded.flags(0x4001), CodeDataLog::DATA);
+            assert!(CodeDataLog::from_bytes(vec![0; 0x4000], 0x8000).is_err());
+        }
+    }
+    // }}}
+
+    // mod script {{{
+    #[cfg(feature = "scripting")]
+    mod script {
+        use std::{cell::Cell, rc::Rc};
+
+        use super::console;
+        use crate::{
+            script::prelude::{Overlay, OverlayText, Script, ScriptContext, ScriptHost, TextScript},
+            Emulator, RomSource,
+        };
+
+        #[test]
+        fn text_scripts_react_to_writes_and_draw() {
+            /* XOR A; LD ($C0A0),A; LD ($C0A0),A; HALT */
+            let mut gba = console(&[0xAF, 0xEA, 0xA0, 0xC0, 0xEA, 0xA0, 0xC0, 0x76]);
+            let source = "
+                var lives = 2   # refills left
+                on write $C0A0
+                    if value == 0 && lives > 0
+                        [addr] = 9
+                        lives = lives - 1
+                    else
+                        log \"out of lives at {PC:x}\"
+                    end
+                end
+                on frame_end
+                    text 1, frame + 2, \"L{lives} {[$C0A0]:x}\"
+                end
+            ";
+            let mut host = ScriptHost::default();
+            host.add(&mut gba.mem, Box::new(TextScript::parse(source).unwrap()));
+            host.run_frame(&mut gba);
+
+            assert_eq!(gba.mem.peek(0xC0A0), 9);
+            let text = OverlayText { x: 1, y: 2, text: "L0 9".to_string(), color: [0xFF; 3] };
+            assert_eq!(host.overlay().texts(), &[text]);
+
+            host.clear(&mut gba.mem);
+            gba.mem.set_u8(0xC0A0_u16, 0);
+            assert_eq!(gba.mem.peek(0xC0A0), 0);
+        }
+
+        #[test]
+        fn script_errors_name_the_line() {
+            let error = |source: &str| TextScript::parse(source).unwrap_err();
+            assert_eq!(error("on frame_end\n  A = 1\n").message, "missing `end`");
+            assert_eq!(error("var A = 1").line, 1);
+            assert_eq!(error("on frame_end\n\n  addr = 1\nend").line, 3);
+            assert_eq!(error("on write $10000\nend").message, "`$10000` is not an address");
+            assert_eq!(error("on frame_end\n  jump 3\nend").to_string(), "line 2: unknown statement `jump 3`");
+        }
+
+        #[derive(Default)]
+        struct Events {
+            frames: Rc<Cell<u32>>,
+            saves: Rc<Cell<u32>>,
+            loads: Rc<Cell<u32>>,
+        }
+
+        impl Script for Events {
+            fn on_frame_end(&mut self, ctx: &mut ScriptContext) {
+                self.frames.set(self.frames.get() + 1);
+                ctx.registers_mut().b = 0x42;
+            }
+
+            fn on_save_state(&mut self, _ctx: &mut ScriptContext) {
+                self.saves.set(self.saves.get() + 1);
+            }
+
+            fn on_load_state(&mut self, _ctx: &mut ScriptContext) {
+                self.loads.set(self.loads.get() + 1);
+            }
+        }
+
+        #[test]
+        fn rust_scripts_see_frames_and_savestates() {
+            let mut emu = Emulator::new(RomSource::Bytes(super::rom(b"").cart_type(0x01).build())).unwrap();
+            let events = Events::default();
+            let (frames, saves, loads) = (Rc::clone(&events.frames), Rc::clone(&events.saves), Rc::clone(&events.loads));
+            let mut host = ScriptHost::default();
+            host.add(&mut emu.console_mut().mem, Box::new(events));
+
+            host.run_frame(emu.console_mut());
+            host.run_frame(emu.console_mut());
+            let state = host.save_state(&mut emu);
+            host.load_state(&mut emu, &state).unwrap();
+            assert_eq!((frames.get(), saves.get(), loads.get()), (2, 1, 1));
+            assert_eq!(emu.console().cpu.registers.b, 0x42);
+        }
+
+        #[test]
+        fn overlay_text_is_drawn_with_a_shadow() {
+            let mut rgb = vec![0xFF; 8 * 8 * 3];
+            let mut overlay = Overlay::default();
+            overlay.text(0, 0, "1", [0xFF, 0, 0]);
+            overlay.draw(&mut rgb, 8, 8);
+
+            let pixel = |x: usize, y: usize| &rgb[(y * 8 + x) * 3..(y * 8 + x) * 3 + 3];
+            assert_eq!(pixel(1, 0), &[0xFF, 0, 0]);
+            assert_eq!(pixel(2, 0), &[0xFF; 3]);
+            assert_eq!(pixel(2, 1), &[0; 3]);
+            assert_eq!(pixel(2, 4), &[0xFF, 0, 0]);
+        }
+    }
+    // }}}
+    // mod pacing {{{
+    mod pacing {
+        use std::{cell::Cell, rc::Rc, time::Duration};
+
+        use crate::{
+            config::prelude::Config,
+            gba::{prelude::{HostClock, Pacer, PacingMode}, speed::FRAME_DURATION},
+        };
+
+        /* Sleeps always run `overshoot` long, as OS sleeps do */
+        #[derive(Debug, Clone, Default)]
+        struct FakeClock {
+            now: Rc<Cell<Duration>>,
+            overshoot: Duration,
+        }
+
+        impl FakeClock {
+            fn advance(&self, duration: Duration) {
+                self.now.set(self.now.get() + duration);
+            }
+        }
+
+        impl HostClock for FakeClock {
+            fn now(&self) -> Duration {
+                self.now.get()
+            }
+
+            fn sleep(&self, duration: Duration) {
+                self.advance(duration + self.overshoot);
+            }
+        }
+
+        #[test]
+        fn timer_overshoot_does_not_accumulate() {
+            let clock = FakeClock { overshoot: Duration::from_millis(1), ..FakeClock::default() };
+            let mut pacer = Pacer::new(PacingMode::Timer, Box::new(clock.clone()));
+            for _ in 0..600 {
+                clock.advance(Duration::from_millis(5));
+                pacer.end_frame(FRAME_DURATION, None);
+            }
+            /* The schedule starts from the first frame's end */
+            let drift = clock.now().abs_diff(Duration::from_millis(5) + FRAME_DURATION * 600);
+            assert!(drift <= Duration::from_millis(1), "{:?}", drift);
+        }
+
+        #[test]
+        fn vsync_near_the_frame_rate_locks_and_resamples() {
+            let clock = FakeClock::default();
+            let mut pacer = Pacer::new(PacingMode::Vsync, Box::new(clock.clone()));
+            let refresh = Duration::from_nanos(16_666_667);
+            for _ in 0..120 {
+                clock.advance(refresh);
+                assert_eq!(pacer.frames_due(FRAME_DURATION), 1);
+                pacer.end_frame(FRAME_DURATION, None);
+            }
+            /* 60 Hz runs 0.45% fast, so each emulated second needs fewer samples */
+            let ratio = pacer.audio_ratio(FRAME_DURATION);
+            assert!((ratio - 59.7275 / 60.0).abs() < 0.0005, "{}", ratio);
+        }
+
+        #[test]
+        fn vsync_on_a_fast_display_holds_the_frame_rate() {
+            let clock = FakeClock::default();
+            let mut pacer = Pacer::new(PacingMode::Vsync, Box::new(clock.clone()));
+            let refresh = Duration::from_secs(1) / 144;
+            let mut frames = 0;
+            for _ in 0..144 * 10 {
+                clock.advance(refresh);
+                frames += pacer.frames_due(FRAME_DURATION);
+            }
+            /* 59.73 fps over ten seconds, less the first refresh */
+            assert!((596..=598).contains(&frames), "{}", frames);
+            assert_eq!(pacer.audio_ratio(FRAME_DURATION), 1.0);
+
+            /* A long stall resumes without a burst */
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(pacer.frames_due(FRAME_DURATION), 1);
+        }
+
+        #[test]
+        fn audio_waits_out_the_excess_and_trims_the_rate() {
+            let clock = FakeClock::default();
+            let mut pacer = Pacer::new(PacingMode::Audio, Box::new(clock.clone()));
+            pacer.set_audio_target(2400, 48_000);
+
+            pacer.end_frame(FRAME_DURATION, Some(2880));
+            assert_eq!(clock.now(), Duration::from_millis(10));
+            assert!(pacer.audio_ratio(FRAME_DURATION) < 1.0);
+
+            pacer.end_frame(FRAME_DURATION, Some(1200));
+            assert_eq!(clock.now(), Duration::from_millis(10));
+            assert!((pacer.audio_ratio(FRAME_DURATION) - 1.0025).abs() < 1e-9);
+
+            /* Without a report it falls back to the timer but keeps the last depth */
+            pacer.end_frame(FRAME_DURATION, None);
+            assert_eq!(clock.now(), Duration::from_millis(10) + FRAME_DURATION);
+            assert!(pacer.audio_ratio(FRAME_DURATION) > 1.0);
+        }
+
+        #[test]
+        fn sync_is_configurable() {
+            let config = Config::from_toml("[video]\nsync = \"audio\"\n").unwrap();
+            assert_eq!(config.pacing, PacingMode::Audio);
+            assert!(Config::from_toml("[video]\nsync = \"gsync\"\n").is_err());
+        }
+    }
+    // }}}
+    // mod rtc {{{
+    mod rtc {
+        use crate::{gba::prelude::Gba, mem::prelude::{convert_save, split_save, Cart, Rtc, SaveFormat, RTC_FOOTER_LEN}};
+
+        const SECOND: usize = 1 << 20;
+
+        /* MBC3+TIMER+RAM+BATTERY with 8kB of RAM */
+        fn mbc3_timer() -> Gba {
+            let mut gba = Gba::from_cart(Cart::from_bytes(super::rom(b"").cart_type(0x10).ram_size(0x02).build()).unwrap());
+            gba.mem.set_u8(0x0000_u16, 0x0A);
+            gba
+        }
+
+        fn latch(gba: &mut Gba) {
+            gba.mem.set_u8(0x6000_u16, 0x00);
+            gba.mem.set_u8(0x6000_u16, 0x01);
+        }
+
+        fn read(gba: &mut Gba, register: u8) -> u8 {
+            gba.mem.set_u8(0x4000_u16, register);
+            gba.mem.get_u8(0xA000_u16)
+        }
+
+        #[test]
+        fn counters_tick_and_latch() {
+            let mut gba = mbc3_timer();
+            gba.mem.set_u8(0x4000_u16, 0x08);
+            gba.mem.set_u8(0xA000_u16, 58);
+            gba.mem.tick(SECOND * 2);
+            assert_eq!(read(&mut gba, 0x08), 0, "reads see the latched copy");
+
+            latch(&mut gba);
+            assert_eq!(read(&mut gba, 0x08), 0);
+            assert_eq!(read(&mut gba, 0x09), 1);
+
+            /* Halted, the clock stands still */
+            gba.mem.set_u8(0x4000_u16, 0x0C);
+            gba.mem.set_u8(0xA000_u16, 0x40);
+            gba.mem.tick(SECOND * 5);
+            latch(&mut gba);
+            assert_eq!(read(&mut gba, 0x09), 1);
+            assert_eq!(read(&mut gba, 0x08), 0);
+
+            /* RAM banks still map normally */
+            gba.mem.set_u8(0x4000_u16, 0x00);
+            gba.mem.set_u8(0xA000_u16, 0x77);
+            assert_eq!(gba.mem.cart_ram()[0], 0x77);
+        }
+
+        #[test]
+        fn days_overflow_into_the_carry() {
+            let mut rtc = Rtc::default();
+            for (register, value) in [(0, 59), (1, 59), (2, 23), (3, 0xFF), (4, 0x01)] {
+                rtc.write(register, value);
+            }
+            rtc.tick(SECOND);
+            assert_eq!(rtc.time(), (0, 0, 0, 0));
+            rtc.write_latch(0);
+            rtc.write_latch(1);
+            assert_eq!(*rtc.latched(4), 0x80);
+
+            /* Out-of-range seconds wrap at 64 without carrying */
+            rtc.write(0, 62);
+            rtc.advance(2 + 61);
+            assert_eq!(rtc.time(), (1, 1, 0, 0));
+        }
+
+        #[test]
+        fn saves_carry_the_clock_across_restarts() {
+            let mut gba = mbc3_timer();
+            gba.mem.set_u8(0x4000_u16, 0x09);
+            gba.mem.set_u8(0xA000_u16, 30);
+            gba.mem.set_u8(0x4000_u16, 0x00);
+            gba.mem.set_u8(0xA005_u16, 0x42);
+
+            let save = gba.mem.battery(1_000_000);
+            assert_eq!(save.len(), 0x2000 + RTC_FOOTER_LEN);
+            assert_eq!(&save[0x2004..0x2008], &30u32.to_le_bytes());
+
+            let mut restarted = mbc3_timer();
+            restarted.mem.load_battery(&save, 1_000_000 + 86400 + 3600 + 45).unwrap();
+            assert_eq!(restarted.mem.cart_ram()[5], 0x42);
+            assert_eq!(restarted.mem.rtc.as_ref().unwrap().time(), (45, 30, 1, 1));
+
+            /* Older 44-byte footers with a 32-bit timestamp load too, as does bare RAM */
+            let mut short = save[..0x2000 + 44].to_vec();
+            short[0x2000 + 40..].copy_from_slice(&1_000_000u32.to_le_bytes());
+            restarted.mem.load_battery(&short, 1_000_010).unwrap();
+            assert_eq!(restarted.mem.rtc.as_ref().unwrap().time(), (10, 30, 0, 0));
+            restarted.mem.load_battery(&save[..0x2000], 0).unwrap();
+            assert!(restarted.mem.load_battery(&save[..0x1000], 0).is_err());
+        }
+
+        #[test]
+        fn saves_convert_between_footer_formats() {
+            let mut gba = mbc3_timer();
+            gba.mem.set_u8(0x4000_u16, 0x0A);
+            gba.mem.set_u8(0xA000_u16, 5);
+            let save = gba.mem.battery(1_000_000);
+
+            let raw = convert_save(&save, SaveFormat::Raw, 0);
+            assert_eq!(raw, gba.mem.cart_ram());
+            assert_eq!(split_save(&raw), (&raw[..], None));
+
+            /* A 32-bit footer is widened without the clock moving on */
+            let mut vba = save[..0x2000 + 44].to_vec();
+            vba[0x2000 + 40..].copy_from_slice(&1_000_000u32.to_le_bytes());
+            assert_eq!(convert_save(&vba, SaveFormat::Rtc, 2_000_000), save);
+
+            /* Bare RAM gains a stopped clock stamped now */
+            let footer = convert_save(&raw, SaveFormat::Rtc, 7);
+            let clock = Rtc::from_footer(split_save(&footer).1.unwrap(), 7).unwrap();
+            assert_eq!((clock.time(), Rtc::footer_time(&footer[0x2000..])), ((0, 0, 0, 0), Some(7)));
+            assert_eq!(split_save(&save[..0x2000 + 20]).1, None);
+        }
+    }
+    // }}}
+    // mod test_roms {{{
+    mod test_roms {
+        use super::console;
+        use crate::{debugger::prelude::{test_summary, TestRomRunner, Verdict}, gba::prelude::Gba};
+
+        /* Prints the string at $C000 over serial, a byte per transfer, then spins */
+        fn serial_print(text: &str) -> Gba {
+            let mut gba = console(&[
+                0x21, 0x00, 0xC0, /* LD HL, $C000 */
+                0x2A, 0xB7, 0x28, 0x08, /* LD A, (HL+); OR A; JR Z, +8 */
+                0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, /* LDH (SB), A; LD A, $81; LDH (SC), A */
+                0x18, 0xF4, 0x18, 0xFE,
+            ]);
+            for (i, byte) in text.bytes().enumerate() {
+                gba.mem.set_u8(0xC000 + i as u16, byte);
+            }
+            gba
+        }
+
+        #[test]
+        fn blargg_results_come_over_serial() {
+            let runner = TestRomRunner::default();
+            let result = runner.run("serial", &mut serial_print("cpu_instrs\n\nPassed all tests\n"));
+            assert_eq!(result.verdict, Verdict::Passed);
+            assert_eq!(result.frames, 1);
+            assert!(result.output.starts_with("cpu_instrs"));
+
+            let result = runner.run("serial", &mut serial_print("Failed #2\n"));
+            assert_eq!(result.verdict, Verdict::Failed);
+        }
+
+        #[test]
+        fn mooneye_results_come_from_registers() {
+            /* LD B..L, then the LD B, B breakpoint */
+            let mut program = vec![0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34, 0x40, 0x18, 0xFE];
+            let runner = TestRomRunner { max_frames: 4 };
+            assert_eq!(runner.run("pass", &mut console(&program)).verdict, Verdict::Passed);
+
+            program[1] = 0x42;
+            assert_eq!(runner.run("partial", &mut console(&program)).verdict, Verdict::TimedOut);
+            for i in [1, 3, 5, 7, 9, 11] {
+                program[i] = 0x42;
+            }
+            let results = [runner.run("fail", &mut console(&program)), runner.run("spin", &mut console(&[0x18, 0xFE]))];
+            assert_eq!(results[0].verdict, Verdict::Failed);
+            assert_eq!(results[1].verdict, Verdict::TimedOut);
+            assert_eq!(results[1].frames, 4);
+            assert!(test_summary(&results).ends_with("0 of 2 passed\n"));
+        }
+
+        /* Point GBEMU_TEST_ROMS at a directory holding blargg's cpu_instrs,
+         * instr_timing and mem_timing and mooneye's acceptance suite, then
+         * `cargo test --release -- --ignored --nocapture suites` */
+        #[cfg(feature = "io")]
+        #[test]
+        #[ignore]
+        fn suites() {
+            let dir = std::env::var("GBEMU_TEST_ROMS").expect("GBEMU_TEST_ROMS names the test ROM directory");
+            let results = TestRomRunner::default().run_dir(&dir);
+            assert!(!results.is_empty(), "no ROMs under {}", dir);
+            println!("{}", test_summary(&results));
+            let failed = results.iter().filter(|result| result.verdict != Verdict::Passed).count();
+            assert_eq!(failed, 0, "{} of {} test ROMs failed", failed, results.len());
+        }
+    }
+    // }}}
+    // mod golden {{{
+    #[cfg_attr(not(feature = "io"), allow(unused))]
+    mod golden {
+        use super::console;
+        use crate::{
+            debugger::prelude::{frame_hash, GoldenOutcome, GoldenTest},
+            ppu::{image, prelude::{SCREEN_HEIGHT, SCREEN_WIDTH}},
+        };
+
+        /* 5x3 RGB, rows filtered Sub, Paeth and Average, fixed Huffman codes */
+        const FIXED_RGB: [u8; 90] = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x05,
+            0x00, 0x00, 0x00, 0x03, 0x08, 0x02, 0x00, 0x00, 0x00, 0xD4, 0x54, 0x52, 0xAF, 0x00, 0x00, 0x00, 0x21, 0x49, 0x44, 0x41,
+            0x54, 0x78, 0xDA, 0x63, 0x64, 0x60, 0x38, 0xA1, 0xC1, 0xFB, 0x08, 0x8E, 0x58, 0xD8, 0xA3, 0xFE, 0xB3, 0xF3, 0x22, 0x10,
+            0x33, 0x77, 0x7B, 0xB2, 0x84, 0xC9, 0x47, 0x38, 0x02, 0x00, 0x78, 0x27, 0x10, 0xC2, 0x56, 0xFC, 0xDC, 0x77, 0x00, 0x00,
+            0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ];
+
+        /* 38x38 grayscale, dynamic Huffman codes */
+        const DYNAMIC_GRAY: [u8; 100] = [
+            0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x26,
+            0x00, 0x00, 0x00, 0x26, 0x08, 0x00, 0x00, 0x00, 0x00, 0x8D, 0x56, 0xB6, 0x72, 0x00, 0x00, 0x00, 0x2B, 0x49, 0x44, 0x41,
+            0x54, 0x78, 0xDA, 0xED, 0xCE, 0x51, 0x15, 0x00, 0x00, 0x08, 0xC2, 0xC0, 0x95, 0xB4, 0x24, 0x25, 0x35, 0x82, 0x04, 0x18,
+            0x7B, 0xFC, 0x1F, 0xCB, 0xE4, 0x3F, 0xA4, 0x6A, 0xD8, 0xFC, 0x27, 0xD5, 0xD0, 0xA6, 0x4D, 0x9B, 0x36, 0x6D, 0x45, 0x07,
+            0xA5, 0x29, 0xC3, 0x04, 0xCD, 0x7E, 0x24, 0x11, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+        ];
+
+        #[test]
+        fn png_decoding() {
+            let (width, height, rgb) = image::read_png(&FIXED_RGB).unwrap();
+            assert_eq!((width, height), (5, 3));
+            for (i, pixel) in rgb.chunks(3).enumerate() {
+                let (x, y) = (i % 5, i / 5);
+                assert_eq!(pixel, [(x * 40 + y * 7) as u8, (x * 13 + y * 90) as u8, (200 - x * 30 - y) as u8]);
+            }
+
+            let (width, height, rgb) = image::read_png(&DYNAMIC_GRAY).unwrap();
+            assert_eq!((width, height), (38, 38));
+            for (i, pixel) in rgb.chunks(3).enumerate() {
+                let (x, y) = (i % 38, i / 38);
+                let shade = [0xFF, 0xAA, 0x55, 0x00][((x * 7) ^ (y * 3) ^ (x * y)) & 3];
+                assert_eq!(pixel, [shade; 3], "({}, {})", x, y);
+            }
+
+            /* What we write reads back */
+            let mut png = Vec::new();
+            image::write_png(&mut png, 5, 3, &rgb[..45]).unwrap();
+            assert_eq!(image::read_png(&png).unwrap(), (5, 3, rgb[..45].to_vec()));
+            assert!(image::read_png(&png[..40]).is_err());
+        }
+
+        #[cfg(feature = "io")]
+        #[test]
+        fn frames_are_compared_with_golden_images() {
+            let dir = super::scratch("golden-compare");
+            let golden = dir.join("spin.png");
+            let test = GoldenTest { frames: 2, ..GoldenTest::default() };
+            let mut actual = test.capture(&mut console(&[0x18, 0xFE]));
+            assert_eq!(actual.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+
+            assert_eq!(test.compare(&actual, &golden).unwrap(), GoldenOutcome::Blessed);
+            assert_eq!(test.compare(&actual, &golden).unwrap(), GoldenOutcome::Matched);
+
+            let hash = frame_hash(&actual);
+            actual[3..6].copy_from_slice(&[1, 2, 3]);
+            assert_ne!(frame_hash(&actual), hash);
+            let artifact = dir.join("spin.diff.png");
+            assert_eq!(test.compare(&actual, &golden).unwrap(), GoldenOutcome::Mismatched { pixels: 1, artifact: artifact.clone() });
+
+            /* Expected, actual and diff side by side, the change in red */
+            let (width, height, panels) = image::load(&artifact).unwrap();
+            assert_eq!((width, height), (SCREEN_WIDTH * 3, SCREEN_HEIGHT));
+            let at = |x: usize| &panels[x * 3..x * 3 + 3];
+            assert_eq!(at(SCREEN_WIDTH + 1), [1, 2, 3]);
+            assert_eq!(at(SCREEN_WIDTH * 2 + 1), [0xFF, 0, 0]);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        /* Point GBEMU_GOLDEN at a directory of ROMs, each with its reference
+         * PNG beside it (dmg-acid2.gb and dmg-acid2.png), then
+         * `cargo test --release -- --ignored golden_images` */
+        #[cfg(feature = "io")]
+        #[test]
+        #[ignore]
+        fn golden_images() {
+            let dir = std::env::var("GBEMU_GOLDEN").expect("GBEMU_GOLDEN names the golden image directory");
+            let mut failed = Vec::new();
+            for rom in std::fs::read_dir(&dir).unwrap().flatten().map(|entry| entry.path()) {
+                if rom.extension().is_some_and(|ext| ext == "gb") {
+                    match GoldenTest::default().check(&rom, rom.with_extension("png")) {
+                        Ok(GoldenOutcome::Mismatched { pixels, artifact }) => failed.push(format!("{}: {} pixels, {}", rom.display(), pixels, artifact.display())),
+                        Err(e) => failed.push(format!("{}: {}", rom.display(), e)),
+                        Ok(_) => {},
+                    }
+                }
+            }
+            assert!(failed.is_empty(), "{}", failed.join("\n"));
+        }
+    }
+    // }}}
+
+    // mod video {{{
+    mod video {
+        use crate::ppu::prelude::{VideoFormat, VideoRecorder};
+
+        /* A GIF decoder just good enough to read back what the recorder
+         * writes: (delay, RGB) per frame */
+        fn decode_gif(gif: &[u8], width: usize, height: usize) -> Vec<(u16, Vec<u8>)> {
+            assert_eq!(&gif[..6], b"GIF89a");
+            let mut pos = 13 + 19;
+            let mut frames = Vec::new();
+            while gif[pos] != 0x3B {
+                assert_eq!(&gif[pos..pos + 4], &[0x21, 0xF9, 0x04, 0x00]);
+                let delay = u16::from_le_bytes([gif[pos + 4], gif[pos + 5]]);
+                pos += 8;
+                assert_eq!(gif[pos], 0x2C);
+                let colors = 2 << (gif[pos + 9] & 0x07);
+                let palette = &gif[pos + 10..pos + 10 + colors * 3];
+                pos += 10 + colors * 3;
+                let bits = gif[pos] as u32;
+                pos += 1;
+                let mut data = Vec::new();
+                while gif[pos] != 0 {
+                    data.extend_from_slice(&gif[pos + 1..pos + 1 + gif[pos] as usize]);
+                    pos += 1 + gif[pos] as usize;
+                }
+                pos += 1;
+
+                let (clear, end) = (1 << bits, (1 << bits) + 1);
+                let mut table: Vec<Vec<u8>> = Vec::new();
+                let (mut width_bits, mut bit) = (bits + 1, 0);
+                let mut prev: Option<usize> = None;
+                let mut indices = Vec::new();
+                loop {
+                    let code = (0..width_bits).fold(0, |code, i| {
+                        let b = bit + i as usize;
+                        code | (((data[b / 8] >> (b % 8)) & 1) as usize) << i
+                    });
+                    bit += width_bits as usize;
+                    if code == clear {
+                        table = (0..clear).map(|i| vec![i as u8]).collect();
+                        table.extend([vec![], vec![]]);
+                        (width_bits, prev) = (bits + 1, None);
+                        continue;
+                    }
+                    if code == end {
+                        break;
+                    }
+                    let entry = match prev {
+                        None => table[code].clone(),
+                        Some(prev) => {
+                            let entry = if code < table.len() { table[code].clone() } else { [&table[prev][..], &table[prev][..1]].concat() };
+                            if table.len() < 4096 {
+                                table.push([&table[prev][..], &entry[..1]].concat());
+                            }
+                            entry
+                        },
+                    };
+                    if table.len() == 1 << width_bits && width_bits < 12 {
+                        width_bits += 1;
+                    }
+                    indices.extend_from_slice(&entry);
+                    prev = Some(code);
+                }
+                assert_eq!(indices.len(), width * height);
+                frames.push((delay, indices.iter().flat_map(|&i| palette[i as usize * 3..i as usize * 3 + 3].to_vec()).collect()));
+            }
+            frames
+        }
+
+        /* Stripes of four shades, or noise with far more than 256 colors */
+        fn frame(seed: u32, noisy: bool) -> Vec<u8> {
+            let mut state = seed | 1;
+            (0..160 * 144 * 3).map(|i| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                if noisy { state as u8 } else { [0x00, 0x55, 0xAA, 0xFF][(i / 3 / 7 + seed as usize) % 4] }
+            }).collect()
+        }
+
+        #[test]
+        fn gif_frames_decode_back() {
+            let mut video = VideoRecorder::new(VideoFormat::Gif, 160, 144, None);
+            let frames = [frame(1, false), frame(2, false), frame(2, false), frame(3, false)];
+            for frame in &frames {
+                video.push(frame);
+            }
+            let decoded = decode_gif(&video.finish(), 160, 144);
+            /* The repeat only lengthens the frame before it */
+            assert_eq!(decoded.len(), 3);
+            assert_eq!(decoded[0].1, frames[0]);
+            assert_eq!(decoded[1].1, frames[1]);
+            assert_eq!(decoded[2].1, frames[3]);
+            let delays: Vec<u16> = decoded.iter().map(|&(delay, _)| delay).collect();
+            assert_eq!(delays, [1, 4, 1]);
+        }
+
+        #[test]
+        fn gif_cuts_busy_frames_down_to_332() {
+            let mut video = VideoRecorder::new(VideoFormat::Gif, 160, 144, None);
+            let busy = frame(7, true);
+            video.push(&busy);
+            let decoded = decode_gif(&video.finish(), 160, 144);
+            for (out, original) in decoded[0].1.chunks(3).zip(busy.chunks(3)) {
+                assert_eq!(out[0] & 0xE0, original[0] & 0xE0);
+                assert_eq!(out[2] & 0xC0, original[2] & 0xC0);
+            }
+        }
+
+        #[test]
+        fn y4m_stops_at_the_limit() {
+            let mut video = VideoRecorder::new(VideoFormat::Y4m, 160, 144, Some(2));
+            for seed in 0..3 {
+                video.push(&frame(seed, false));
+            }
+            assert!(video.full());
+            let y4m = video.finish();
+            let header = b"YUV4MPEG2 W160 H144 F4194304:70224 Ip A1:1 C444\n";
+            assert!(y4m.starts_with(header));
+            assert_eq!(y4m.len(), header.len() + 2 * (6 + 160 * 144 * 3));
+            /* White is full luma and neutral chroma */
+            let mut white = VideoRecorder::new(VideoFormat::Y4m, 1, 1, None);
+            white.push(&[0xFF; 3]);
+            assert!(white.finish().ends_with(b"FRAME\n\xFF\x80\x80"));
+        }
+    }
+    // }}}
+    // mod output {{{
+    mod output {
+        use crate::{
+            gba::prelude::{Emulator, RomSource},
+            ppu::prelude::{RgbaFrame, ScaleMode, Viewport, SCREEN_HEIGHT, SCREEN_WIDTH},
+        };
+
+        #[test]
+        fn rgba_frames_are_tightly_packed_and_opaque() {
+            let mut emulator = Emulator::new(RomSource::Bytes(super::rom(b"").build())).unwrap();
+            emulator.run_frame().unwrap();
+            let frame = emulator.rgba_frame();
+            assert_eq!((frame.width, frame.height, frame.stride), (SCREEN_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH * 4));
+            assert_eq!(frame.pixels.len(), frame.stride * frame.height);
+            let rgb = emulator.framebuffer();
+            assert!(frame.pixels.chunks_exact(4).zip(rgb.chunks_exact(3)).all(|(rgba, rgb)| rgba[..3] == *rgb && rgba[3] == 0xFF));
+
+            /* The shader sees every frame handed out, and may resize it */
+            emulator.set_shader(|frame: RgbaFrame| frame.scale(2));
+            assert_eq!(emulator.rgba_frame().width, SCREEN_WIDTH * 2);
+            emulator.clear_shader();
+            assert_eq!(emulator.rgba_frame(), frame);
+        }
+
+        #[test]
+        fn integer_scaling_repeats_pixels() {
+            let frame = RgbaFrame::from_rgb(&[1, 2, 3, 4, 5, 6], 2, 1);
+            let scaled = frame.scale(3);
+            assert_eq!((scaled.width, scaled.height, scaled.stride), (6, 3, 24));
+            for y in 0..3 {
+                assert_eq!(scaled.pixel(2, y), [1, 2, 3, 0xFF]);
+                assert_eq!(scaled.pixel(3, y), [4, 5, 6, 0xFF]);
+            }
+        }
+
+        #[test]
+        fn viewports_fit_and_centre_the_frame() {
+            let screen = (SCREEN_WIDTH, SCREEN_HEIGHT);
+            assert_eq!(ScaleMode::Integer.viewport((800, 600), screen), Viewport { x: 80, y: 12, width: 640, height: 576 });
+            /* 10:9 from the height, letterboxed on the sides */
+            assert_eq!(ScaleMode::Aspect.viewport((800, 600), screen), Viewport { x: 67, y: 0, width: 666, height: 600 });
+            assert_eq!(ScaleMode::Aspect.viewport((400, 900), screen), Viewport { x: 0, y: 270, width: 400, height: 360 });
+            assert_eq!(ScaleMode::Stretch.viewport((800, 600), screen), Viewport { x: 0, y: 0, width: 800, height: 600 });
+            /* Never below 1x, even when the window is too small */
+            assert_eq!(ScaleMode::Integer.viewport((100, 100), screen).width, SCREEN_WIDTH);
+            assert_eq!(ScaleMode::from_name("aspect"), Some(ScaleMode::Aspect));
+        }
+    }
+    // }}}
+    // mod colorize {{{
+    mod colorize {
+        use crate::{
+            config::prelude::Config,
+            gba::prelude::{Emulator, RomSource},
+            mem::prelude::Cart,
+            ppu::prelude::{ColorCorrection, Colorization, ColorizeMode, Ppu},
+        };
+
+        /* A DMG cart with `title`, by Nintendo through the old code or the
+         * new one as `licensee` says */
+        fn rom(title: &[u8], licensee: &[u8; 2]) -> Vec<u8> {
+            super::rom(title).licensee(licensee).build()
+        }
+
+        fn combination(title: &[u8], licensee: &[u8; 2]) -> usize {
+            Colorization::combination_for(&Cart::from_bytes(rom(title, licensee)).unwrap().header)
+        }
+
+        #[test]
+        fn picks_by_title_checksum_for_nintendo_games() {
+            assert_eq!(combination(b"TETRIS", b"01"), 3);
+            assert_eq!(combination(b"POKEMON RED", b"01"), 13);
+            /* Unknown titles and other publishers get the default */
+            assert_eq!(combination(b"NOT A REAL GAME", b"01"), 0);
+            assert_eq!(combination(b"TETRIS", b"08"), 0);
+        }
+
+        #[test]
+        fn shared_checksums_go_by_the_fourth_letter() {
+            assert_eq!(combination(b"SUPER MARIOLAND", b"01"), 22);
+            assert_eq!(combination(b"POKEMON BLUE", b"01"), 11);
+            /* $61 with another fourth letter matches nothing */
+            assert_eq!(combination(b"POKXMON BLUE", b"01"), 0);
+        }
+
+        #[test]
+        fn overrides_by_buttons_or_number() {
+            assert_eq!(ColorizeMode::from_name("left+b"), Some(ColorizeMode::Combination(7)));
+            assert_eq!(ColorizeMode::from_name("50"), Some(ColorizeMode::Combination(50)));
+            assert_eq!(ColorizeMode::from_name("51"), None);
+            assert_eq!(ColorizeMode::from_name("sideways"), None);
+            let config = Config::from_toml("[video]\ncolorize = \"auto\"\n").unwrap();
+            assert_eq!(config.colorize, ColorizeMode::Auto);
+            assert!(Config::from_toml("[video]\ncolorize = \"blue\"\n").is_err());
+
+            /* Down+A: white, yellow, red, black everywhere */
+            let orange = Colorization::combination(3, ColorCorrection::Off).unwrap();
+            assert_eq!(orange.bg, [[0xFF, 0xFF, 0xFF], [0xFF, 0xFF, 0x00], [0xFF, 0x00, 0x00], [0x00, 0x00, 0x00]]);
+            assert_eq!((orange.obj0, orange.obj1), (orange.bg, orange.bg));
+        }
+
+        #[test]
+        fn sprites_take_the_palette_for_their_obp() {
+            let mut ppu = Ppu::default();
+            /* Tile 1 solid color 3 under a sprite on OBP1 at x 8..16 */
+            ppu.vram[0x10..0x20].fill(0xFF);
+            ppu.bgp = 0xE4;
+            /* Color 3 to shade 1, which differs between the palettes */
+            ppu.obp1 = 0x40;
+            ppu.oam[..4].copy_from_slice(&[16, 16, 1, 0x10]);
+            ppu.write_register(0xFF40, 0x93);
+            ppu.tick(114);
+            /* Up+A: red BG, green OBJ0, blue OBJ1 */
+            let colors = Colorization::combination(43, ColorCorrection::Off).unwrap();
+            let rgb = ppu.colorized_framebuffer(&colors);
+            assert_eq!(rgb[..3], colors.bg[0]);
+            assert_eq!(rgb[8 * 3..9 * 3], colors.obj1[1]);
+            assert_ne!(colors.obj1[1], colors.obj0[1]);
+        }
+
+        #[test]
+        fn cgb_carts_are_left_alone() {
+            let mut dmg = rom(b"TETRIS", b"01");
+            let config = Config { colorize: ColorizeMode::Auto, skip_boot: true, ..Config::default() };
+            let emulator = Emulator::with_config(RomSource::Bytes(dmg.clone()), &config).unwrap();
+            assert_eq!(emulator.console().mem.ppu.colorization, Colorization::combination(3, ColorCorrection::Off));
+
+            dmg[0x143] = 0x80;
+            let mut emulator = Emulator::with_config(RomSource::Bytes(dmg), &config).unwrap();
+            assert!(emulator.console().mem.ppu.colorization.is_none());
+            emulator.set_colorize(ColorizeMode::Combination(0));
+            assert!(emulator.console().mem.ppu.colorization.is_none());
+        }
+    }
+    // }}}
+    // mod mcycle {{{
+    mod mcycle {
+        use super::console;
+        use crate::gba::prelude::Gba;
+
+        /* LD A, $12; INC HL; CALL $0110; JR -9 ... $0110: PUSH BC; POP BC; RET */
+        fn program() -> Gba {
+            let mut program = vec![0x3E, 0x12, 0x23, 0xCD, 0x10, 0x01, 0x18, 0xF7];
+            program.resize(0x10, 0x00);
+            program.extend_from_slice(&[0xC5, 0xC1, 0xC9]);
+            console(&program)
+        }
+
+        #[test]
+        fn ticks_add_up_to_steps() {
+            let (mut stepped, mut ticked) = (program(), program());
+            for _ in 0..200 {
+                let cycles = stepped.step();
+                for cycle in 1..=cycles {
+                    assert_eq!(ticked.tick(), cycle == cycles, "at {:04X}", stepped.cpu.registers.pc);
+                }
+                stepped.mem.sync();
+                let registers = |gba: &Gba| (gba.cpu.registers.get_af(), gba.cpu.registers.get_hl(), gba.cpu.registers.sp, gba.cpu.registers.pc);
+                assert_eq!(registers(&ticked), registers(&stepped));
+                assert_eq!(ticked.mem.timer.internal_counter(), stepped.mem.timer.internal_counter());
+                assert_eq!(ticked.mem.peek(0xFF44), stepped.mem.peek(0xFF44));
+            }
+        }
+
+        #[test]
+        fn each_tick_is_one_m_cycle() {
+            let mut gba = program();
+            while gba.cpu.registers.pc != 0x0103 || gba.mid_instruction() {
+                gba.tick();
+            }
+            /* CALL: the fetch, the two address bytes, a wait, two pushes */
+            let counter = gba.mem.timer.internal_counter();
+            assert!(!gba.tick());
+            assert_eq!(gba.cpu.registers.pc, 0x0104);
+            assert!(gba.mid_instruction());
+            for i in 1..=4 {
+                assert!(!gba.tick());
+                assert_eq!(gba.mem.timer.internal_counter(), counter.wrapping_add(4 * (i + 1)));
+            }
+            /* Only the high byte of the return address is on the stack yet */
+            assert_eq!((gba.cpu.registers.pc, gba.cpu.registers.sp), (0x0106, 0xFFFF));
+            assert!(gba.tick());
+            assert_eq!((gba.cpu.registers.pc, gba.cpu.registers.sp), (0x0110, 0xFFFE));
+
+            /* `step` finishes an instruction in flight */
+            gba.tick();
+            assert_eq!(gba.step(), 3);
+            assert!(!gba.mid_instruction());
+
+            /* Halted, each tick is still one cycle */
+            gba.cpu.state = crate::cpu::proc::CpuState::Halted;
+            gba.mem.sync();
+            let counter = gba.mem.timer.internal_counter();
+            assert!(gba.tick());
+            assert_eq!(gba.mem.timer.internal_counter(), counter.wrapping_add(4));
+        }
+    }
+    // }}}
+    // mod halt {{{
+    #[cfg_attr(not(feature = "io"), allow(unused))]
+    mod halt {
+        use super::console;
+        use crate::{
+            cpu::prelude::CpuState,
+            debugger::prelude::{TestRomRunner, Verdict},
+            gba::prelude::Gba,
+        };
+
+        /* LD A, $04; LDH (IE), A; LDH (IF), A: a timer interrupt pending and enabled */
+        fn timer_pending(rest: &[u8]) -> Gba {
+            let mut program = vec![0x3E, 0x04, 0xE0, 0xFF, 0xE0, 0x0F];
+            program.extend_from_slice(rest);
+            let mut gba = console(&program);
+            gba.cpu.registers.sp = 0xDFF0;
+            gba.cpu.registers.b = 0;
+            (0..3).for_each(|_| { gba.step(); });
+            gba
+        }
+
+        #[test]
+        fn halt_with_ime_clear_reads_the_next_byte_twice() {
+            /* HALT; INC B; JR -2, IME still clear */
+            let mut gba = timer_pending(&[0x76, 0x04, 0x18, 0xFE]);
+            gba.step();
+            assert_eq!(gba.cpu.state, CpuState::Running);
+            assert!(gba.cpu.halt_bug);
+            gba.step();
+            assert_eq!((gba.cpu.registers.b, gba.cpu.registers.pc), (1, 0x0107));
+            gba.step();
+            assert_eq!((gba.cpu.registers.b, gba.cpu.registers.pc), (2, 0x0108));
+            assert_eq!(gba.mem.peek(0xFF0F) & 0x04, 0x04);
+        }
+
+        #[test]
+        fn ei_waits_one_instruction() {
+            /* EI; INC B; NOP */
+            let mut gba = timer_pending(&[0xFB, 0x04, 0x00]);
+            gba.step();
+            assert_eq!(gba.cpu.ime, 0);
+            gba.step();
+            assert_eq!((gba.cpu.ime, gba.cpu.registers.b), (1, 1));
+            assert_eq!(gba.step(), 5);
+            assert_eq!(gba.cpu.registers.pc, 0x0050);
+            assert_eq!(gba.mem.get_u16(gba.cpu.registers.sp), 0x0108);
+            assert_eq!((gba.cpu.ime, gba.mem.peek(0xFF0F) & 0x04), (0, 0));
+        }
+
+        #[test]
+        fn ei_then_halt_services_the_interrupt_after_halt() {
+            /* EI; HALT; NOP */
+            let mut gba = timer_pending(&[0xFB, 0x76, 0x00]);
+            gba.step();
+            gba.step();
+            assert_eq!(gba.cpu.state, CpuState::Halted);
+            assert!(!gba.cpu.halt_bug);
+            gba.step();
+            assert_eq!(gba.cpu.state, CpuState::Running);
+            assert_eq!(gba.step(), 5);
+            assert_eq!(gba.cpu.registers.pc, 0x0050);
+            assert_eq!(gba.mem.get_u16(gba.cpu.registers.sp), 0x0108);
+        }
+
+        /* mooneye's halt_ime0_ei, halt_ime0_nointr_timing and
+         * halt_ime1_timing from GBEMU_TEST_ROMS */
+        #[cfg(feature = "io")]
+        #[test]
+        #[ignore]
+        fn mooneye_halt() {
+            let dir = std::env::var("GBEMU_TEST_ROMS").expect("GBEMU_TEST_ROMS names the test ROM directory");
+            let results: Vec<_> = TestRomRunner::default().run_dir(&dir).into_iter()
+                .filter(|result| ["halt_ime0_ei", "halt_ime0_nointr_timing", "halt_ime1_timing"].contains(&result.name.as_str()))
+                .collect();
+            assert!(!results.is_empty(), "no halt ROMs under {}", dir);
+            for result in results {
+                assert_eq!(result.verdict, Verdict::Passed, "{}", result.name);
+            }
+        }
+    }
+    // }}}
+    // mod ie_push {{{
+    #[cfg_attr(not(feature = "io"), allow(unused))]
+    mod ie_push {
+        use super::console;
+        use crate::{
+            debugger::prelude::{TestRomRunner, Verdict},
+            gba::prelude::{AccuracyLevel, Gba},
+        };
+
+        /* About to dispatch from `pc` with SP at $0000, so PC's high byte is
+         * pushed onto IE */
+        fn dispatching(pc: u16, ie: u8, pending: u8, accuracy: AccuracyLevel) -> Gba {
+            let mut gba = console(&[0x00]);
+            gba.set_accuracy(accuracy);
+            gba.mem.set_u8(0xFFFF_u16, ie);
+            gba.mem.set_u8(0xFF0F_u16, pending);
+            gba.cpu.registers.pc = pc;
+            gba.cpu.registers.sp = 0x0000;
+            gba.cpu.ime = 1;
+            gba
+        }
+
+        #[test]
+        fn dispatch_takes_five_cycles_and_pushes_pc() {
+            for accuracy in [AccuracyLevel::Fast, AccuracyLevel::Balanced, AccuracyLevel::Accurate] {
+                let mut gba = dispatching(0x0100, 0x05, 0x05, accuracy);
+                gba.cpu.registers.sp = 0xDFF0;
+                assert_eq!(gba.step(), 5);
+                assert_eq!(gba.cpu.registers.pc, 0x0040);
+                assert_eq!((gba.cpu.registers.sp, gba.mem.get_u16(0xDFEE_u16)), (0xDFEE, 0x0100));
+                assert_eq!((gba.cpu.ime, gba.mem.peek(0xFF0F) & 0x1F), (0, 0x04));
+            }
+        }
+
+        #[test]
+        fn pushing_onto_ie_changes_the_vector() {
+            for accuracy in [AccuracyLevel::Fast, AccuracyLevel::Balanced, AccuracyLevel::Accurate] {
+                /* $04 replaces IE: only the timer is left enabled */
+                let mut gba = dispatching(0x0400, 0x05, 0x05, accuracy);
+                assert_eq!(gba.step(), 5);
+                assert_eq!(gba.cpu.registers.pc, 0x0050, "{:?}", accuracy);
+                assert_eq!((gba.mem.peek(0xFFFF), gba.mem.peek(0xFFFE)), (0x04, 0x00));
+                assert_eq!(gba.mem.peek(0xFF0F) & 0x1F, 0x01);
+            }
+        }
+
+        #[test]
+        fn pushing_onto_ie_can_cancel_the_dispatch() {
+            for accuracy in [AccuracyLevel::Fast, AccuracyLevel::Balanced, AccuracyLevel::Accurate] {
+                /* $02 leaves nothing pending: PC goes to $0000 and IF keeps its bit */
+                let mut gba = dispatching(0x0200, 0x01, 0x01, accuracy);
+                assert_eq!(gba.step(), 5);
+                assert_eq!(gba.cpu.registers.pc, 0x0000, "{:?}", accuracy);
+                assert_eq!((gba.cpu.registers.sp, gba.cpu.ime), (0xFFFE, 0));
+                assert_eq!(gba.mem.peek(0xFF0F) & 0x1F, 0x01);
+            }
+        }
+
+        /* mooneye's ie_push and intr_timing from GBEMU_TEST_ROMS */
+        #[cfg(feature = "io")]
+        #[test]
+        #[ignore]
+        fn mooneye_interrupts() {
+            let dir = std::env::var("GBEMU_TEST_ROMS").expect("GBEMU_TEST_ROMS names the test ROM directory");
+            let results: Vec<_> = TestRomRunner::default().run_dir(&dir).into_iter()
+                .filter(|result| ["ie_push", "intr_timing"].contains(&result.name.as_str()))
+                .collect();
+            assert!(!results.is_empty(), "no interrupt ROMs under {}", dir);
+            for result in results {
+                assert_eq!(result.verdict, Verdict::Passed, "{}", result.name);
+            }
+        }
+    }
+    // }}}
+    // mod overlays {{{
+    mod overlays {
+        use crate::ppu::prelude::{DebugOverlays, Layer, Palette, Ppu, Renderer, TileMap, MAP_SIZE, SCREEN_WIDTH};
+
+        /* Solid tile 1 for a sprite at 8..16, the window from 100 */
+        fn lcd(renderer: Renderer) -> Ppu {
+            let mut ppu = Ppu::default();
+            ppu.set_renderer(renderer);
+            ppu.vram[0x10..0x20].fill(0xFF);
+            ppu.bgp = 0xE4;
+            ppu.obp0 = 0xE4;
+            ppu.wx = 107;
+            ppu.oam[..4].copy_from_slice(&[16, 16, 1, 0]);
+            ppu.write_register(0xFF40, 0xB3);
+            ppu.tick(114);
+            ppu
+        }
+
+        #[test]
+        fn layers_record_each_source() {
+            for renderer in [Renderer::Fifo, Renderer::Scanline] {
+                let ppu = lcd(renderer);
+                let layers = &ppu.layers()[..SCREEN_WIDTH];
+                assert_eq!((layers[4], layers[8], layers[15], layers[16]), (Layer::Background, Layer::Sprite, Layer::Sprite, Layer::Background), "{:?}", renderer);
+                assert_eq!((layers[99], layers[100]), (Layer::Background, Layer::Window), "{:?}", renderer);
+            }
+        }
+
+        #[test]
+        fn overlays_only_change_the_rgb_output() {
+            let mut ppu = lcd(Renderer::Fifo);
+            let (shades, plain) = (ppu.framebuffer().to_vec(), ppu.rgb_framebuffer_with(&Palette::GRAYSCALE));
+            ppu.set_overlays(DebugOverlays { layers: true, sprite_boxes: true, ..DebugOverlays::default() });
+            let rgb = ppu.rgb_framebuffer_with(&Palette::GRAYSCALE);
+            assert_eq!(ppu.framebuffer(), shades);
+            /* White background blended with blue, the window with green */
+            assert_eq!(rgb[4 * 3..4 * 3 + 3], [0x9F, 0xBF, 0xFF]);
+            assert_eq!(rgb[120 * 3..120 * 3 + 3], [0x9F, 0xFF, 0x9F]);
+            /* The sprite's top edge is outlined */
+            assert_eq!(rgb[8 * 3..8 * 3 + 3], [0xFF, 0xFF, 0x00]);
+            assert_ne!(rgb, plain);
+
+            ppu.set_overlays(DebugOverlays::default());
+            assert_eq!(ppu.rgb_framebuffer_with(&Palette::GRAYSCALE), plain);
+        }
+
+        #[test]
+        fn viewport_wraps_around_the_map() {
+            let mut ppu = lcd(Renderer::Fifo);
+            ppu.scx = 200;
+            ppu.scy = 250;
+            ppu.set_overlays(DebugOverlays { viewport: true, ..DebugOverlays::default() });
+            let rgb = ppu.map_rgb(TileMap::Background, &Palette::GRAYSCALE);
+            let at = |x: usize, y: usize| rgb[(y * MAP_SIZE + x) * 3..(y * MAP_SIZE + x) * 3 + 3].to_vec();
+            assert_eq!(at(200, 250), [0xFF, 0x00, 0xFF]);
+            /* Right edge at 200 + 159 - 256, bottom at 250 + 143 - 256 */
+            assert_eq!(at(103, 137), [0xFF, 0x00, 0xFF]);
+            assert_eq!(at(104, 137), [0xFF, 0xFF, 0xFF]);
+            assert_eq!(at(150, 100), [0xFF, 0xFF, 0xFF]);
+        }
+    }
+    // }}}
+    // mod determinism {{{
+    mod determinism {
+        use crate::{
+            config::prelude::Config,
+            gba::prelude::{Emulator, FixedTime, RomSource},
+            mem::prelude::{split_save, RamFill, Rtc, SaveFormat},
+        };
+
+        /* MBC3+TIMER+RAM+BATTERY with 8kB of RAM */
+        fn mbc3_timer(config: &Config) -> Emulator {
+            Emulator::with_config(RomSource::Bytes(super::rom(b"").cart_type(0x10).ram_size(0x02).build()), config).unwrap()
+        }
+
+        #[test]
+        fn ram_fill_is_reproducible() {
+            assert_eq!(RamFill::from_name("random:42"), Some(RamFill::Random(42)));
+            assert_eq!(RamFill::from_name("ones"), Some(RamFill::Ones));
+            assert_eq!(RamFill::from_name("random:x"), None);
+
+            let fill = |fill: RamFill, region| { let mut ram = [0; 13]; fill.fill(&mut ram, region); ram };
+            assert_eq!(fill(RamFill::Random(7), 0), fill(RamFill::Random(7), 0));
+            assert_ne!(fill(RamFill::Random(7), 0), fill(RamFill::Random(8), 0));
+            assert_ne!(fill(RamFill::Random(7), 0), fill(RamFill::Random(7), 1));
+
+            let config = Config::from_toml("[core]\nram_fill = \"ones\"\nskip_boot = true").unwrap();
+            let emulator = mbc3_timer(&config);
+            let mem = &emulator.console().mem;
+            assert_eq!((mem.peek(0xC000), mem.peek(0xFF80), mem.peek(0xFFFF)), (0xFF, 0xFF, 0x00));
+            assert!(mem.cart_ram().iter().all(|&byte| byte == 0xFF));
+
+            let random = Config::from_toml("[core]\nram_fill = \"random:3\"").unwrap();
+            assert_eq!(mbc3_timer(&random).console().mem.cart_ram(), mbc3_timer(&random).console().mem.cart_ram());
+        }
+
+        #[test]
+        fn fixed_time_stamps_saves() {
+            let config = Config::from_toml("[core]\nfixed_time = 1234").unwrap();
+            assert_eq!(config.fixed_time, Some(1234));
+            assert!(Config::from_toml("[core]\nfixed_time = -1").is_err());
+
+            let mut emulator = mbc3_timer(&config);
+            let save = emulator.export_save(SaveFormat::Rtc);
+            assert_eq!(Rtc::footer_time(split_save(&save).1.unwrap()), Some(1234));
+
+            /* Loaded a day later, the clock catches up by exactly that */
+            emulator.set_time_source(Box::new(FixedTime(1234 + 86400)));
+            emulator.import_save(&save).unwrap();
+            assert_eq!(emulator.console().mem.rtc.as_ref().unwrap().time(), (0, 0, 0, 1));
+        }
+    }
+    // }}}
+
+    // mod state_hash {{{
+    mod state_hash {
+        use crate::{
+            config::prelude::Config,
+            gba::prelude::{Emulator, Notification, RomSource},
+            state::prelude::{xxh64, Xxh64},
+        };
+
+        /* Spins on `jr -2` at $0100 */
+        fn spinner() -> Emulator {
+            let rom = super::rom(b"").program(&[0x18, 0xFE]).build();
+            let config = Config::from_toml("[core]\nskip_boot = true").unwrap();
+            Emulator::with_config(RomSource::Bytes(rom), &config).unwrap()
+        }
+
+        #[test]
+        fn xxh64_matches_the_reference() {
+            assert_eq!(xxh64(b"", 0), 0xEF46DB3751D8E999);
+            assert_eq!(xxh64(b"a", 0), 0xD24EC4F1A98C6E5B);
+            assert_eq!(xxh64(b"abc", 0), 0x44BC2CF5AD770999);
+            assert_eq!(xxh64(b"Nobody inspects the spammish repetition", 0), 0xFBCEA83C8A378BF1);
+
+            let bytes: Vec<u8> = (0..100).collect();
+            let mut streamed = Xxh64::new(7);
+            for chunk in bytes.chunks(13) {
+                streamed.update(chunk);
+            }
+            assert_eq!(streamed.digest(), xxh64(&bytes, 7));
+        }
+
+        #[test]
+        fn diverging_runs_report_the_frame() {
+            let (mut a, mut b) = (spinner(), spinner());
+            a.enable_state_hashing(4);
+            b.enable_state_hashing(4);
+            for _ in 0..6 {
+                a.run_frame().unwrap();
+                b.run_frame().unwrap();
+                let (frame, hash) = a.last_state_hash().unwrap();
+                assert_eq!(b.check_state_hash(frame, hash), Some(true));
+            }
+            assert_eq!((a.state_hash(2), a.state_hash(3).is_some()), (None, true));
+
+            b.console_mut().mem.set_u8(0xC123_u16, 0x42);
+            a.run_frame().unwrap();
+            b.run_frame().unwrap();
+            let hash = a.state_hash(7).unwrap();
+            assert_eq!(b.check_state_hash(7, hash), Some(false));
+            assert_eq!(b.check_state_hash(6, a.state_hash(6).unwrap()), Some(true));
+            assert_eq!(b.check_state_hash(8, hash), None);
+            assert_eq!(b.first_desync(), Some(7));
+            assert_eq!(b.take_notifications(), [Notification::Desync(7)]);
+
+            /* Only the first divergence is announced */
+            b.check_state_hash(7, hash);
+            assert!(b.take_notifications().is_empty());
+        }
+    }
+    // }}}
+
+    // mod netplay {{{
+    #[cfg_attr(not(feature = "io"), allow(unused))]
+    mod netplay {
+        use super::console;
+        use crate::{
+            error::prelude::GbError,
+            gba::prelude::Gba,
+            link::prelude::{link_consoles, LocalInputs, Lockstep},
+        };
+
+        /* Shifts the direction lines out each transfer and keeps what came back in $FF80 */
+        fn exchanger(sc: u8) -> Gba {
+            console(&[
+                0x3E, 0x20, 0xE0, 0x00,
+                0xF0, 0x00, 0xE0, 0x01, 0x3E, sc, 0xE0, 0x02,
+                0xF0, 0x02, 0x87, 0x38, 0xFB,
+                0xF0, 0x01, 0xE0, 0x80, 0x18, 0xED,
+            ])
+        }
+
+        fn machine() -> [Gba; 2] {
+            let (mut master, mut slave) = (exchanger(0x81), exchanger(0x80));
+            link_consoles(&mut master, &mut slave);
+            [master, slave]
+        }
+
+        #[cfg(feature = "io")]
+        #[test]
+        fn lockstep_runs_both_machines_identically() {
+            let (a, b) = LocalInputs::pair();
+            let mut sides = [(Lockstep::new(Box::new(a), 0, 2, 0), machine()), (Lockstep::new(Box::new(b), 1, 2, 0), machine())];
+
+            for frame in 0..12 {
+                for (lockstep, [p0, p1]) in sides.iter_mut() {
+                    let pressed = [0x0A, 0x05][lockstep.player()];
+                    /* The peer's input is always `delay` frames ahead, so one thread never waits */
+                    lockstep.run_frame([p0, p1], pressed).unwrap();
+                    let expected = if frame < 2 { [0, 0] } else { [0x0A, 0x05] };
+                    assert_eq!([p0.mem.joypad.pressed(), p1.mem.joypad.pressed()], expected, "frame {}", frame);
+                }
+            }
+
+            let [(_, first), (_, second)] = &sides;
+            for (a, b) in first.iter().zip(second) {
+                assert_eq!(a.cpu.registers.pc, b.cpu.registers.pc);
+                assert_eq!(a.mem.peek(0xFF80), b.mem.peek(0xFF80));
+            }
+            /* Each console received the other's pressed directions, active low */
+            assert_eq!(first[0].mem.peek(0xFF80) & 0x0F, 0x0A);
+            assert_eq!(first[1].mem.peek(0xFF80) & 0x0F, 0x05);
+        }
+
+        #[test]
+        fn lockstep_refuses_a_different_game() {
+            let (a, b) = LocalInputs::pair();
+            let (mut ours, _theirs) = (Lockstep::new(Box::new(a), 0, 2, 0x1234), Lockstep::new(Box::new(b), 1, 2, 0x4321));
+            assert!(matches!(ours.poll(), Err(GbError::Unsupported(_))));
+        }
+    }
+    // }}}
+
+    // mod cheat_search {{{
+    mod cheat_search {
+        use super::console;
+        use crate::{
+            debugger::prelude::{Candidate, CheatSearch, Comparison, SearchRegion, ValueSize},
+            gba::prelude::Gba,
+            mem::prelude::Cart,
+        };
+
+        #[test]
+        fn filters_narrow_to_the_changing_byte() {
+            let mut gba = console(&[]);
+            let mut search = CheatSearch::start(&gba.mem, ValueSize::Byte);
+            assert_eq!(search.candidates().len(), 0x2000);
+
+            gba.mem.set_u8(0xC010_u16, 10);
+            gba.mem.set_u8(0xC020_u16, 10);
+            assert_eq!(search.filter(&gba.mem, Comparison::Increased).len(), 2);
+
+            gba.mem.set_u8(0xC010_u16, 7);
+            gba.mem.set_u8(0xC020_u16, 12);
+            let found = search.filter(&gba.mem, Comparison::Delta(-3)).to_vec();
+            assert_eq!(found, [Candidate { region: SearchRegion::Wram, offset: 0x10 }]);
+            assert_eq!((found[0].address(), search.value(found[0])), (0xC010, 7));
+
+            assert_eq!(search.filter(&gba.mem, Comparison::Unchanged).len(), 1);
+            assert!(search.filter(&gba.mem, Comparison::Equals(8)).is_empty());
+        }
+
+        #[test]
+        fn words_are_found_in_banked_cart_ram() {
+            let mut gba = Gba::from_cart(Cart::from_bytes(super::rom(b"").cart_type(0x03).ram_size(0x03).build()).unwrap());
+            let mut search = CheatSearch::start(&gba.mem, ValueSize::Word);
+            /* No word straddles two banks */
+            assert_eq!(search.candidates().len(), 0x1FFF + 4 * 0x1FFF);
+
+            gba.mem.cart_ram_mut()[0x2100..0x2102].copy_from_slice(&1000_u16.to_le_bytes());
+            let found = search.filter(&gba.mem, Comparison::Equals(1000)).to_vec();
+            assert_eq!(found.len(), 1);
+            assert_eq!((found[0].region, found[0].bank(), found[0].address()), (SearchRegion::CartRam, 1, 0xA100));
+        }
+    }
+    // }}}
+
+    // mod slots {{{
+    #[cfg(feature = "io")]
+    mod slots {
+        use crate::{
+            config::prelude::Config,
+            error::prelude::GbError,
+            gba::prelude::{Emulator, FixedTime, RomSource},
+            state::prelude::{Slot, AUTOSAVE_SLOT, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH},
+        };
+
+        fn emulator(name: &str) -> Emulator {
+            let dir = super::scratch(&format!("slots-{}", name));
+            let path = dir.join("game.gb");
+            std::fs::write(&path, super::rom(b"").build()).unwrap();
+            let config = Config::from_toml("[core]\nskip_boot = true\nfixed_time = 1000").unwrap();
+            Emulator::with_config(RomSource::Path(path), &config).unwrap()
+        }
+
+        #[test]
+        fn slots_round_trip_with_metadata() {
+            let mut emulator = emulator("round-trip");
+            assert_eq!(emulator.slot_info(3).unwrap(), None);
+            emulator.run_frame().unwrap();
+            let pc = emulator.console().cpu.registers.pc;
+            emulator.save_slot(3).unwrap();
+
+            emulator.set_time_source(Box::new(FixedTime(2000)));
+            emulator.save_slot(AUTOSAVE_SLOT).unwrap();
+            emulator.run_frame().unwrap();
+            emulator.load_slot(3).unwrap();
+            assert_eq!(emulator.console().cpu.registers.pc, pc);
+
+            let slot = emulator.slot_info(3).unwrap().unwrap();
+            assert_eq!(slot.timestamp, 1000);
+            assert_eq!(slot.thumbnail.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3);
+            assert_eq!(emulator.slot_info(AUTOSAVE_SLOT).unwrap().unwrap().timestamp, 2000);
+            assert_eq!(Slot::from_bytes(&slot.to_bytes()), Ok(slot));
+
+            assert_eq!(emulator.save_slot(10), Err(GbError::Io(std::io::ErrorKind::InvalidInput)));
+            let mut in_memory = Emulator::new(RomSource::Bytes(super::rom(b"").build())).unwrap();
+            assert!(matches!(in_memory.save_slot(1), Err(GbError::Unsupported(_))));
+        }
+
+        #[test]
+        fn crash_dumps_load_back() {
+            let mut emulator = emulator("crash");
+            emulator.run_frame().unwrap();
+            let dir = emulator.save_path().unwrap().parent().unwrap().to_path_buf();
+            let path = emulator.write_crash_dump(&dir, "panicked at src/cpu.rs:1:1").unwrap();
+            assert_eq!(path.file_name().unwrap(), "game-1000.crash");
+
+            let report = std::fs::read_to_string(path.with_extension("crash.txt")).unwrap();
+            assert!(report.starts_with("panicked at src/cpu.rs:1:1\n"));
+            assert!(report.contains(&format!("PC={:04X}", emulator.console().cpu.registers.pc)));
+            assert!(report.ends_with(&emulator.console().trace_dump()));
+
+            let pc = emulator.console().cpu.registers.pc;
+            emulator.run_frame().unwrap();
+            emulator.load_slot_bytes(&std::fs::read(&path).unwrap()).unwrap();
+            assert_eq!(emulator.console().cpu.registers.pc, pc);
+        }
+    }
+    // }}}
+
+    // mod rom_library {{{
+    #[cfg(feature = "io")]
+    mod rom_library {
+        use crate::{
+            config::prelude::Config,
+            mem::prelude::Controller,
+            rom_library::prelude::scan,
+            state::prelude::{slot_path, Slot, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH},
+        };
+
+        fn rom(title: &[u8], cart_type: u8, cgb: u8) -> Vec<u8> {
+            super::rom(title).cart_type(cart_type).cgb(cgb).build()
+        }
+
+        #[test]
+        fn catalogues_sorted_with_saves_and_slots() {
+            let dir = super::scratch("library");
+            std::fs::create_dir_all(dir.join("nested")).unwrap();
+            std::fs::write(dir.join("zebra.gb"), rom(b"ZEBRA", 0x00, 0x00)).unwrap();
+            std::fs::write(dir.join("nested/apple.GBC"), rom(b"apple", 0x1B, 0xC0)).unwrap();
+            std::fs::write(dir.join("short.gb"), [0; 0x100]).unwrap();
+            std::fs::write(dir.join("notes.txt"), b"not a rom").unwrap();
+            let frame = vec![0x40; crate::SCREEN_WIDTH * crate::SCREEN_HEIGHT * 3];
+            let slot = Slot::new(vec![1, 2, 3], 1234, &frame);
+            std::fs::write(slot_path(&dir.join("zebra.sav"), 4), slot.to_bytes()).unwrap();
+
+            let library = scan(&dir, &Config::default());
+            let titles: Vec<&str> = library.roms.iter().map(|rom| rom.title.as_str()).collect();
+            assert_eq!(titles, ["apple", "ZEBRA"]);
+            let (apple, zebra) = (&library.roms[0], &library.roms[1]);
+            assert_eq!((apple.mapper, apple.cgb, apple.size), (Some(Controller::MBC5), true, 0x8000));
+            assert_eq!((apple.last_played, apple.slots.len()), (None, 0));
+            assert_eq!((zebra.mapper, zebra.cgb, zebra.header_hash.len()), (Some(Controller::None), false, 8));
+            assert_eq!(zebra.last_played, Some(1234));
+            assert_eq!((zebra.slots[0].slot, zebra.slots[0].thumbnail.len()), (4, THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3));
+            assert_eq!(library.skipped.len(), 1);
+            assert_eq!(library.skipped[0].0, dir.join("short.gb"));
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+    // }}}
+
+    // mod notifications {{{
+    mod notifications {
+        use crate::{
+            gba::prelude::{Emulator, Notification, RomSource, StatsMeter},
+            link::prelude::LocalLink,
+        };
+
+        fn emulator() -> Emulator {
+            Emulator::new(RomSource::Bytes(super::rom(b"").build())).unwrap()
+        }
+
+        #[test]
+        fn link_and_cheat_changes_are_reported_once() {
+            let mut emulator = emulator();
+            emulator.run_frame().unwrap();
+            assert!(emulator.take_notifications().is_empty());
+
+            let (cable, _peer) = LocalLink::pair();
+            emulator.console_mut().mem.serial.connect(Box::new(cable));
+            emulator.set_cheats_enabled(false);
+            emulator.run_frame().unwrap();
+            emulator.run_frame().unwrap();
+            assert_eq!(emulator.take_notifications(), [Notification::CheatsToggled(false), Notification::LinkConnected]);
+            assert!(!emulator.cheats_enabled());
+
+            emulator.console_mut().mem.serial.disconnect();
+            emulator.run_frame().unwrap();
+            let notifications = emulator.take_notifications();
+            assert_eq!(notifications, [Notification::LinkDisconnected]);
+            assert_eq!(notifications[0].to_string(), "Link cable disconnected");
+        }
+
+        #[test]
+        fn untaken_notifications_are_bounded() {
+            let mut emulator = emulator();
+            for i in 0..100 {
+                emulator.set_cheats_enabled(i % 2 == 0);
+            }
+            let notifications = emulator.take_notifications();
+            assert_eq!(notifications.len(), 32);
+            assert_eq!(notifications.last(), Some(&Notification::CheatsToggled(false)));
+        }
+
+        #[cfg(feature = "io")]
+        #[test]
+        fn slots_and_battery_saves_are_reported() {
+            let dir = super::scratch("notifications");
+            let path = dir.join("game.gb");
+            std::fs::write(&path, super::rom(b"").cart_type(0x03).ram_size(0x02).build()).unwrap();
+            let mut emulator = Emulator::new(RomSource::Path(path)).unwrap();
+            emulator.save_slot(5).unwrap();
+            emulator.load_slot(5).unwrap();
+            emulator.save_battery().unwrap();
+            assert_eq!(emulator.take_notifications(), [Notification::StateSaved(5), Notification::StateLoaded(5), Notification::BatterySaved]);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn stats_average_over_half_a_second() {
+            let frame = crate::gba::speed::FRAME_DURATION;
+            let mut meter = StatsMeter::default();
+            for i in 0..30 {
+                meter.record(frame * i, 17556);
+                assert_eq!(meter.stats().fps, 0.0);
+            }
+            meter.record(frame * 30, 17556);
+            let stats = meter.stats();
+            assert!((stats.fps - 59.73).abs() < 0.01, "{}", stats.fps);
+            assert!((stats.speed - 1.0).abs() < 1e-9);
+            assert_eq!(stats.cycles_per_frame, 17556);
+
+            /* Twice as many frames in the same time */
+            for i in 1..=60 {
+                meter.record(frame * 30 + frame / 2 * i, 17556);
+            }
+            assert!((meter.stats().speed - 2.0).abs() < 1e-6);
+        }
+
+        #[test]
+        fn run_frame_counts_cycles() {
+            let mut emulator = emulator();
+            emulator.run_frame().unwrap();
+            assert!(emulator.frame_stats().cycles_per_frame >= 17556);
+        }
+    }
+    // }}}
+
+    // mod trace {{{
+    mod trace {
+        use super::console;
+        use crate::debugger::prelude::{TraceEntry, TraceRing};
+
+        #[test]
+        fn ring_keeps_the_newest_entries_in_order() {
+            let mut ring = TraceRing::new(3);
+            for pc in 0..5 {
+                ring.push(TraceEntry { pc, ..TraceEntry::default() });
+            }
+            assert_eq!(ring.iter().map(|entry| entry.pc).collect::<Vec<_>>(), [2, 3, 4]);
+            assert_eq!(ring.last().map(|entry| entry.pc), Some(4));
+
+            let mut off = TraceRing::new(0);
+            off.push(TraceEntry::default());
+            assert!(off.is_empty() && !off.enabled());
+        }
+
+        #[test]
+        fn steps_record_operands_and_registers() {
+            /* LD HL,$C0DE; LD A,$42; LDH ($80),A */
+            let mut gba = console(&[0x21, 0xDE, 0xC0, 0x3E, 0x42, 0xE0, 0x80]);
+            for _ in 0..3 {
+                gba.step();
+            }
+            let entries: Vec<_> = gba.trace.iter().copied().collect();
+            assert_eq!(entries.iter().map(|entry| entry.pc).collect::<Vec<_>>(), [0x100, 0x103, 0x105]);
+            assert_eq!(entries[0].bytes, [0x21, 0xDE, 0xC0]);
+            assert_eq!(entries[2].bytes, [0xE0, 0x80, 0x00]);
+            /* Registers are as they were before each instruction */
+            assert_eq!((entries[1].hl, entries[2].af >> 8), (0xC0DE, 0x42));
+
+            let dump = gba.trace_dump();
+            assert_eq!(dump.lines().count(), 3);
+            assert!(dump.lines().next().unwrap().starts_with("0100: 21 DE C0"));
+        }
+
+        #[cfg(feature = "io")]
+        #[test]
+        fn gdb_backtrace_is_the_trace() {
+            use crate::debugger::prelude::{GdbAction, GdbStub};
+
+            let mut gba = console(&[0x21, 0xDE, 0xC0, 0x3E, 0x42, 0xE0, 0x80]);
+            for _ in 0..3 {
+                gba.step();
+            }
+            let hex: String = "backtrace".bytes().map(|byte| format!("{:02x}", byte)).collect();
+            let GdbAction::Reply(reply) = GdbStub::default().handle(&mut gba, &format!("qRcmd,{}", hex)) else { panic!() };
+            assert!(reply.starts_with(&"0100: ".bytes().map(|byte| format!("{:02x}", byte)).collect::<String>()));
+        }
+    }
+    // }}}
+
+    // mod symbols {{{
+    mod symbols {
+        use super::console;
+        use crate::{
+            debugger::prelude::{disassemble, location_of, Debugger, Location, StopReason, Symbols},
+            gba::prelude::Gba,
+            mem::prelude::{Boot, Cart},
+        };
+
+        const SYM: &str = "; File generated by rgblink\n00:0100 Start\n00:0150 Main_Loop\n01:4000 Far\n02:4000 Farther\n00:c000 wCounter ; in WRAM\nbogus line\n";
+
+        #[test]
+        fn parses_rgbds_files() {
+            let symbols = Symbols::parse(SYM);
+            assert_eq!(symbols.len(), 5);
+            assert_eq!(symbols.resolve("Farther"), Some(Location { bank: Some(2), pc: 0x4000 }));
+            assert_eq!(symbols.resolve("wCounter"), Some(location_of(0, 0xC000)));
+            assert_eq!(symbols.name(Location { bank: Some(1), pc: 0x4000 }), Some("Far"));
+            assert_eq!(symbols.describe(Location { bank: Some(0), pc: 0x0153 }).as_deref(), Some("Main_Loop+$3"));
+            /* RAM labels hold whatever bank is mapped */
+            assert_eq!(symbols.describe(Location { bank: None, pc: 0xC002 }).as_deref(), Some("wCounter+$2"));
+            assert_eq!(symbols.describe(Location { bank: Some(3), pc: 0x4001 }), None);
+        }
+
+        #[test]
+        fn disassembles_every_opcode() {
+            let none = |_| None;
+            for op in 0..=0xFF_u8 {
+                assert!(!disassemble(&[op, 0x34, 0x12], 0x100, &none).is_empty());
+                assert!(!disassemble(&[0xCB, op], 0x100, &none).is_empty());
+            }
+            assert_eq!(disassemble(&[0x21, 0xDE, 0xC0], 0, &none), "LD HL, $C0DE");
+            assert_eq!(disassemble(&[0x20, 0xFE], 0x150, &none), "JR NZ, $0150");
+            assert_eq!(disassemble(&[0xF8, 0xFF], 0, &none), "LD HL, SP + -$01");
+            assert_eq!(disassemble(&[0xCB, 0x7C], 0, &none), "BIT 7, H");
+            assert_eq!(disassemble(&[0xD3], 0, &none), "DB $D3");
+        }
+
+        #[test]
+        fn traces_print_labels() {
+            /* CALL Main_Loop, with Main_Loop: JR Main_Loop */
+            let mut program = vec![0; 0x52];
+            program[..3].copy_from_slice(&[0xCD, 0x50, 0x01]);
+            program[0x50..].copy_from_slice(&[0x18, 0xFE]);
+            let mut gba = console(&program);
+            gba.symbols = Some(Symbols::parse(SYM));
+            assert_eq!(gba.disassemble_at(0x100), "CALL Main_Loop");
+            for _ in 0..3 {
+                gba.step();
+            }
+            let dump = gba.trace_dump();
+            let lines: Vec<_> = dump.lines().collect();
+            /* The label comes round again with each pass through the loop */
+            assert_eq!(lines.len(), 6);
+            assert_eq!(lines[0], "Start:");
+            assert!(lines[1].contains("CALL Main_Loop"));
+            assert_eq!(lines[2], "Main_Loop:");
+            assert!(lines[3].contains("JR Main_Loop"));
+            assert_eq!(lines[4], "Main_Loop:");
+        }
+
+        #[test]
+        fn breakpoints_by_label_respect_banks() {
+            /* JP $4000, where every bank is JR -2 */
+            let mut rom = super::rom(b"").cart_type(0x01).size(0x10000).program(&[0xC3, 0x00, 0x40]).build();
+            for bank in 1..4 {
+                rom[bank * 0x4000..bank * 0x4000 + 2].copy_from_slice(&[0x18, 0xFE]);
+            }
+            let mut gba = Gba::with_boot(Cart::from_bytes(rom).unwrap(), Boot::Skip);
+            let symbols = Symbols::parse(SYM);
+
+            let mut debugger = Debugger::default();
+            assert_eq!(debugger.add_symbol_breakpoint(&symbols, "Farther"), Some(0x4000));
+            assert_eq!(debugger.add_symbol_breakpoint(&symbols, "Nowhere"), None);
+            /* Bank 1 is mapped, so the bank 2 label never stops */
+            assert_eq!(debugger.resume(&mut gba, 10_000), StopReason::Timeout);
+
+            debugger.add_symbol_breakpoint(&symbols, "Far");
+            gba.cpu.registers.pc = 0x100;
+            assert_eq!(debugger.resume(&mut gba, 10_000), StopReason::Breakpoint(0x4000));
+        }
+    }
+    // }}}
+
+    // mod watches {{{
+    mod watches {
+        use super::console;
+        use crate::{
+            cpu::register::types::Flags,
+            debugger::prelude::{Debugger, Symbols},
+        };
+
+        #[test]
+        fn watches_follow_each_step() {
+            /* LD A,$05; INC A; LD ($C010),A */
+            let mut gba = console(&[0x3E, 0x05, 0x3C, 0xEA, 0x10, 0xC0]);
+            gba.symbols = Some(Symbols::parse("00:c010 wLives\n"));
+            let mut debugger = Debugger::default();
+            debugger.add_watch(&gba, "A").unwrap();
+            debugger.add_watch(&gba, "[wLives] * 2").unwrap();
+            assert!(debugger.add_watch(&gba, "[wNowhere]").is_err());
+
+            debugger.step(&mut gba);
+            debugger.step(&mut gba);
+            let values: Vec<_> = debugger.watches().iter().map(|watch| (watch.value, watch.changed)).collect();
+            assert_eq!(values, [(6, true), (0, false)]);
+            debugger.step(&mut gba);
+            assert_eq!((debugger.watches()[1].value, debugger.watches()[1].changed), (12, true));
+
+            assert_eq!(debugger.remove_watch(0).map(|watch| watch.text), Some("A".to_string()));
+            assert!(debugger.remove_watch(5).is_none());
+        }
+
+        #[test]
+        fn registers_flags_and_memory_can_be_set() {
+            let mut gba = console(&[]);
+            gba.symbols = Some(Symbols::parse("00:c010 wLives\n"));
+            let mut debugger = Debugger::default();
+            debugger.add_watch(&gba, "[wLives + 1]").unwrap();
+
+            debugger.assign(&mut gba, "hl", 0xBEEF).unwrap();
+            debugger.assign(&mut gba, "PC", 0x0200).unwrap();
+            debugger.assign(&mut gba, "CF", 1).unwrap();
+            debugger.assign(&mut gba, "[wLives + 1]", 9).unwrap();
+            assert_eq!(gba.cpu.registers.get_hl(), 0xBEEF);
+            assert_eq!(gba.cpu.registers.pc, 0x0200);
+            assert!(gba.cpu.registers.f.is_set(Flags::Carry));
+            assert_eq!(gba.mem.peek(0xC011), 9);
+            assert_eq!((debugger.watches()[0].value, debugger.watches()[0].changed), (9, true));
+
+            debugger.assign(&mut gba, "CF", 0).unwrap();
+            assert!(!gba.cpu.registers.f.is_set(Flags::Carry));
+            assert!(debugger.assign(&mut gba, "A + 1", 0).is_err());
+        }
+    }
+    // }}}
+
+    // mod frame_control {{{
+    mod frame_control {
+        use std::{cell::Cell, rc::Rc};
+
+        use crate::gba::prelude::{Emulator, FrameControl, RomSource};
+
+        fn emulator() -> Emulator {
+            Emulator::new(RomSource::Bytes(super::rom(b"").build())).unwrap()
+        }
+
+        #[test]
+        fn frame_advance_runs_one_frame_and_pauses() {
+            let mut emulator = emulator();
+            assert!(emulator.run_frame().unwrap() > 0);
+            assert!(emulator.frame_advance().unwrap() > 0);
+            assert!(emulator.paused());
+            assert_eq!(emulator.run_frame(), Ok(0));
+            assert_eq!(emulator.frame_count(), 2);
+
+            emulator.resume();
+            emulator.run_frame().unwrap();
+            assert_eq!(emulator.frame_count(), 3);
+        }
+
+        #[test]
+        fn runs_until_a_frame_or_the_callback_pauses() {
+            let mut emulator = emulator();
+            assert_eq!(emulator.run_until_frame(10), Ok(10));
+            assert!(emulator.paused());
+
+            let seen = Rc::new(Cell::new(0));
+            let counter = Rc::clone(&seen);
+            emulator.set_on_frame(move |frame, _| {
+                counter.set(counter.get() + 1);
+                if frame == 14 { FrameControl::Pause } else { FrameControl::Continue }
+            });
+            assert_eq!(emulator.run_until_frame(100), Ok(14));
+            assert_eq!(seen.get(), 4);
+
+            emulator.clear_on_frame();
+            assert_eq!(emulator.run_until_frame(16), Ok(16));
+            assert_eq!(seen.get(), 4);
+        }
+    }
+    // }}}
+
+    // mod counters {{{
+    mod counters {
+        use std::{cell::Cell, rc::Rc, time::Duration};
+
+        use crate::{
+            config::prelude::Config,
+            gba::prelude::{Counters, Emulator, HostClock, RateSampler, RomSource, CLOCK_RATE},
+        };
+
+        #[derive(Debug)]
+        struct FakeClock(Rc<Cell<Duration>>);
+
+        impl HostClock for FakeClock {
+            fn now(&self) -> Duration {
+                self.0.get()
+            }
+
+            fn sleep(&self, duration: Duration) {
+                self.0.set(self.0.get() + duration);
+            }
+        }
+
+        /* JP $0100 forever */
+        fn spin() -> Emulator {
+            let rom = super::rom(b"").program(&[0xC3, 0x00, 0x01]).build();
+            Emulator::with_config(RomSource::Bytes(rom), &Config::from_toml("[core]\nskip_boot = true").unwrap()).unwrap()
+        }
+
+        #[test]
+        fn count_cycles_instructions_and_frames() {
+            let mut emulator = spin();
+            assert_eq!(emulator.counters(), Counters::default());
+            let cycles: usize = (0..3).map(|_| emulator.run_frame().unwrap()).sum();
+            let counters = emulator.counters();
+            assert_eq!(counters.t_cycles, cycles as u64 * 4);
+            /* Four M-cycles a jump */
+            assert_eq!(counters.instructions, cycles as u64 / 4);
+            assert_eq!(counters.frames, 3);
+
+            emulator.reset(true);
+            assert_eq!(emulator.counters(), counters);
+        }
+
+        #[test]
+        fn rates_come_once_a_second() {
+            let now = Rc::new(Cell::new(Duration::ZERO));
+            let mut sampler = RateSampler::new(Box::new(FakeClock(now.clone())));
+            assert_eq!(sampler.sample(Counters::default()), None);
+            now.set(Duration::from_millis(500));
+            assert_eq!(sampler.sample(Counters { t_cycles: 1, instructions: 1, frames: 1 }), None);
+
+            now.set(Duration::from_secs(2));
+            let counters = Counters { t_cycles: 2 * CLOCK_RATE as u64, instructions: 1000, frames: 120 };
+            let rates = sampler.sample(counters).unwrap();
+            assert_eq!((rates.instructions, rates.frames), (500.0, 60.0));
+            assert_eq!(rates.speed(), 1.0);
+            assert_eq!(sampler.rates(), rates);
+            assert_eq!(counters.rates_since(&counters, Duration::ZERO).frames, 0.0);
+        }
+    }
+    // }}}
+    // mod reset {{{
+    mod reset {
+        use crate::{
+            config::prelude::Config,
+            gba::prelude::{Emulator, RomSource},
+            mem::prelude::RamFill,
+        };
+
+        /* MBC1 with 8kB of RAM, with a battery or without, that the boot ROM accepts */
+        fn emulator(battery: bool, skip_boot: bool) -> Emulator {
+            let rom = super::rom(b"").program(&[0x00, 0x18, 0xFE, 0x00]).logo()
+                .cart_type(if battery { 0x03 } else { 0x02 }).ram_size(0x02).checksums().build();
+            let config = Config { skip_boot, ram_fill: RamFill::Zero, ..Config::default() };
+            let mut emulator = Emulator::with_config(RomSource::Bytes(rom), &config).unwrap();
+            emulator.run_frame().unwrap();
+            emulator.console_mut().mem.cart_ram_mut()[0] = 0x42;
+            emulator.console_mut().mem.set_u8(0xC000_u16, 0x99);
+            emulator
+        }
+
+        #[test]
+        fn soft_reset_keeps_cart_ram() {
+            let mut emulator = emulator(false, true);
+            emulator.console_mut().cpu.registers.pc = 0x1234;
+            emulator.reset(false);
+            let gba = emulator.console();
+            assert_eq!(gba.cpu.registers.pc, 0x100);
+            assert_eq!(gba.cpu.registers.sp, 0xFFFE);
+            assert_eq!(gba.mem.peek(0xFF40), 0x91);
+            assert_eq!(gba.mem.cart_ram()[0], 0x42);
+            assert_eq!(gba.mem.peek(0xC000), 0x00);
+            assert_eq!(emulator.frame_count(), 0);
+        }
+
+        #[test]
+        fn hard_reset_clears_ram_without_a_battery() {
+            let mut volatile = emulator(false, true);
+            volatile.reset(true);
+            assert_eq!(volatile.console().mem.cart_ram()[0], 0x00);
+
+            let mut battery = emulator(true, true);
+            battery.reset(true);
+            assert_eq!(battery.console().mem.cart_ram()[0], 0x42);
+        }
+
+        #[test]
+        fn reset_runs_the_boot_rom_again() {
+            let mut emulator = emulator(true, false);
+            emulator.reset(false);
+            let gba = emulator.console_mut();
+            assert_eq!(gba.cpu.registers.pc, 0x0000);
+            assert!(gba.mem.boot_rom_mapped());
+            let mut steps = 0;
+            while gba.cpu.registers.pc != 0x100 && steps < 10_000_000 {
+                gba.step();
+                steps += 1;
+            }
+            assert_eq!(gba.cpu.registers.pc, 0x100, "boot ROM hung at ${:04X}", gba.cpu.registers.pc);
+            assert!(!gba.mem.boot_rom_mapped());
+            assert_eq!(gba.mem.cart_ram()[0], 0x42);
+        }
+    }
+    // }}}
+
+    // mod swap {{{
+    mod swap {
+        use crate::{gba::prelude::Gba, mem::prelude::Cart};
+
+        /* A cart of `cart_type` with 8kB of RAM titled `title` */
+        fn rom(title: &[u8], cart_type: u8) -> Vec<u8> {
+            super::rom(title).cart_type(cart_type).ram_size(0x02).build()
+        }
+
+        #[test]
+        fn swapping_boots_the_new_cart() {
+            let mut gba = Gba::from_cart(Cart::from_bytes(rom(b"FIRST", 0x03)).unwrap());
+            gba.run_frame();
+            gba.mem.cart_ram_mut()[0] = 0x42;
+
+            let old = gba.swap_cart(Cart::from_bytes(rom(b"SECOND", 0x01)).unwrap());
+            assert_eq!(old.header.title_str(), "FIRST");
+            assert_eq!(gba.mem.cart().header.title_str(), "SECOND");
+            assert_eq!(gba.mem.cart_ram()[0], 0x00);
+            assert_eq!(gba.cpu.registers.pc, 0x0000);
+            assert!(gba.mem.boot_rom_mapped());
+        }
+
+        #[cfg(feature = "io")]
+        #[test]
+        fn swapping_flushes_and_loads_battery_saves() {
+            use crate::{config::prelude::Config, gba::prelude::{Emulator, RomSource}};
+
+            let dir = super::scratch("swap");
+            let (first, second) = (dir.join("first.gb"), dir.join("second.gb"));
+            std::fs::write(&first, rom(b"FIRST", 0x03)).unwrap();
+            std::fs::write(&second, rom(b"SECOND", 0x03)).unwrap();
+            let mut save = vec![0; 0x2000];
+            save[0] = 0x24;
+            std::fs::write(second.with_extension("sav"), &save).unwrap();
+
+            let config = Config { skip_boot: true, ..Config::default() };
+            let mut emulator = Emulator::with_config(RomSource::Path(first.clone()), &config).unwrap();
+            emulator.run_frame().unwrap();
+            emulator.console_mut().mem.cart_ram_mut()[0] = 0x42;
+
+            assert!(emulator.swap_cart(RomSource::Bytes(vec![0; 0x100]), &config).is_err());
+            assert_eq!(emulator.console().mem.cart().header.title_str(), "FIRST");
+
+            emulator.swap_cart(RomSource::Path(second.clone()), &config).unwrap();
+            assert_eq!(std::fs::read(first.with_extension("sav")).unwrap()[0], 0x42);
+            assert_eq!(emulator.save_path(), Some(second.with_extension("sav").as_path()));
+            assert_eq!(emulator.console().mem.cart_ram()[0], 0x24);
+            assert_eq!(emulator.console().cpu.registers.pc, 0x0100);
+            assert_eq!(emulator.frame_count(), 0);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+    // }}}
+
+    // mod timer_edges {{{
+    mod timer_edges {
+        use crate::gba::prelude::Gba;
+
+        use super::console;
+
+        /* TIMA at $FF with TAC on bit 3 (16 T-cycles), one M-cycle from overflowing */
+        fn about_to_overflow() -> Gba {
+            let mut gba = console(&[]);
+            gba.mem.set_u8(0xFF06_u16, 0x80);
+            gba.mem.set_u8(0xFF05_u16, 0xFF);
+            gba.mem.set_u8(0xFF07_u16, 0x05);
+            gba.mem.set_u8(0xFF0F_u16, 0x00);
+            gba.mem.timer.set_internal_counter(0x000C);
+            gba
+        }
+
+        fn timer_irq(gba: &Gba) -> bool {
+            gba.mem.get_u8(0xFF0F_u16) & 0x04 != 0
+        }
+
+        #[test]
+        fn tima_reads_zero_for_a_cycle_before_the_reload() {
+            let mut gba = about_to_overflow();
+            gba.mem.tick(1);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x00);
+            assert!(!timer_irq(&gba));
+            gba.mem.tick(1);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x80);
+            assert!(timer_irq(&gba));
+        }
+
+        #[test]
+        fn writing_tima_before_the_reload_cancels_it() {
+            let mut gba = about_to_overflow();
+            gba.mem.tick(1);
+            gba.mem.set_u8(0xFF05_u16, 0x42);
+            gba.mem.tick(2);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x42);
+            assert!(!timer_irq(&gba));
+        }
+
+        #[test]
+        fn writes_in_the_reload_cycle() {
+            let mut gba = about_to_overflow();
+            gba.mem.tick(2);
+            /* TMA wins over a TIMA write, and a TMA write lands in both */
+            gba.mem.set_u8(0xFF05_u16, 0x42);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x80);
+            gba.mem.set_u8(0xFF06_u16, 0x90);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x90);
+            /* A cycle later both behave normally again */
+            gba.mem.tick(1);
+            gba.mem.set_u8(0xFF06_u16, 0xA0);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x90);
+            gba.mem.set_u8(0xFF05_u16, 0x42);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x42);
+        }
+
+        #[test]
+        fn div_reset_with_the_selected_bit_high_clocks_tima() {
+            let mut gba = about_to_overflow();
+            gba.mem.set_u8(0xFF05_u16, 0x10);
+            gba.mem.set_u8(0xFF04_u16, 0x00);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x11);
+            /* With the bit low there is no edge */
+            gba.mem.set_u8(0xFF04_u16, 0x00);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x11);
+        }
+
+        #[test]
+        fn div_reset_can_overflow_tima() {
+            let mut gba = about_to_overflow();
+            gba.mem.set_u8(0xFF04_u16, 0x00);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x00);
+            gba.mem.tick(1);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x80);
+            assert!(timer_irq(&gba));
+        }
+
+        #[test]
+        fn disabling_the_timer_with_the_bit_high_clocks_tima() {
+            let mut gba = about_to_overflow();
+            gba.mem.set_u8(0xFF05_u16, 0x10);
+            gba.mem.set_u8(0xFF07_u16, 0x01);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x11);
+        }
+
+        #[test]
+        fn overflow_is_scheduled_for_the_reload() {
+            let mut gba = about_to_overflow();
+            assert_eq!(gba.mem.timer.cycles_to_overflow(), Some(2));
+            gba.mem.tick(1);
+            assert_eq!(gba.mem.timer.cycles_to_overflow(), Some(1));
+        }
+
+        #[test]
+        fn reload_survives_a_savestate() {
+            use crate::state::prelude::{Savestate, StateReader, StateWriter};
+
+            let mut gba = about_to_overflow();
+            gba.mem.tick(1);
+            let mut w = StateWriter::new();
+            gba.mem.save_state(&mut w);
+            let state = w.into_inner();
+            let mut restored = about_to_overflow();
+            restored.mem.load_state(&mut StateReader::new(&state)).unwrap();
+            restored.mem.tick(1);
+            assert_eq!(restored.mem.get_u8(0xFF05_u16), 0x80);
+            assert!(timer_irq(&restored));
+        }
+    }
+    // }}}
+
+    // mod io_inspector {{{
+    mod io_inspector {
+        use crate::{
+            mem::prelude::{decode_register, IoRegister},
+            ppu::prelude::{Palette, PaletteRam},
+        };
+
+        use super::console;
+
+        #[test]
+        fn registers_split_into_named_fields() {
+            let mut gba = console(&[]);
+            gba.mem.set_u8(0xFF40_u16, 0x93);
+            gba.mem.set_u8(0xFF07_u16, 0x05);
+            let map = gba.dump_memory_map();
+            let lcdc = map.io.iter().find(|reg| reg.name == "LCDC").unwrap();
+            assert_eq!(lcdc.field("LCD enable"), Some(1));
+            assert_eq!(lcdc.field("Window enable"), Some(0));
+            assert_eq!(lcdc.field("Tiles $8000"), Some(1));
+            assert_eq!(lcdc.fields().len(), 8);
+
+            let tac = map.io.iter().find(|reg| reg.name == "TAC").unwrap();
+            let shown: Vec<String> = tac.fields().iter().map(|field| field.to_string()).collect();
+            assert_eq!(shown, ["Enable=1", "Clock select=$1"]);
+        }
+
+        #[test]
+        fn multi_bit_fields_are_shifted_down() {
+            let fields = decode_register(0xFF11, 0xBF);
+            assert_eq!((fields[0].name, fields[0].value, fields[0].mask()), ("Duty", 2, 0xC0));
+            assert_eq!((fields[1].name, fields[1].value, fields[1].mask()), ("Length", 0x3F, 0x3F));
+            let stat = IoRegister { addr: 0xFF41, name: "STAT", value: 0x47 };
+            assert_eq!(stat.field("LYC interrupt"), Some(1));
+            assert_eq!(stat.field("Mode"), Some(3));
+            assert!(decode_register(0xFF44, 0x90).is_empty());
+        }
+
+        #[test]
+        fn cgb_palette_ram_auto_increments() {
+            let mut gba = console(&[]);
+            gba.mem.set_cgb_mode(true);
+            gba.mem.set_u8(0xFF68_u16, 0x88);
+            for byte in [0xFF, 0x7F, 0x1F, 0x00] {
+                gba.mem.set_u8(0xFF69_u16, byte);
+            }
+            assert_eq!(gba.mem.get_u8(0xFF68_u16), 0xCC);
+            let palettes = gba.mem.ppu.cgb_palettes(PaletteRam::Background);
+            assert_eq!(&palettes[1][..2], &[0x7FFF, 0x001F]);
+            let rgb = gba.mem.ppu.cgb_palettes_rgb(PaletteRam::Background);
+            assert_eq!(&rgb[1][..2], &[[0xFF, 0xFF, 0xFF], [0xFF, 0x00, 0x00]]);
+
+            /* Without auto increment the index stays put */
+            gba.mem.set_u8(0xFF6A_u16, 0x02);
+            gba.mem.set_u8(0xFF6B_u16, 0xE0);
+            gba.mem.set_u8(0xFF6B_u16, 0x03);
+            assert_eq!(gba.mem.get_u8(0xFF6B_u16), 0x03);
+            assert_eq!(gba.mem.ppu.cgb_palettes(PaletteRam::Object)[0][1], 0x0003);
+            assert_eq!(gba.mem.peek(0xFF6A), 0x42);
+        }
+
+        #[test]
+        fn dmg_palettes_map_each_color_index() {
+            let mut gba = console(&[]);
+            gba.mem.ppu.set_palette(Palette::GRAYSCALE);
+            gba.mem.set_u8(0xFF47_u16, 0xE4);
+            gba.mem.set_u8(0xFF48_u16, 0x1B);
+            let [bgp, obp0, _] = gba.mem.ppu.dmg_palettes_rgb();
+            assert_eq!(bgp, Palette::GRAYSCALE.0);
+            assert_eq!(obp0[0], Palette::GRAYSCALE.0[3]);
+            assert_eq!(obp0[3], Palette::GRAYSCALE.0[0]);
+        }
+    }
+    // }}}
+
+    // mod accuracy {{{
+    mod accuracy {
+        use crate::{
+            cpu::register::types::Register16,
+            gba::prelude::{AccuracyLevel, Gba},
+            ppu::prelude::Renderer,
+        };
+
+        use super::console;
+
+        /* LDH A, [DIV] with DIV two M-cycles from ticking over */
+        fn read_div(accuracy: AccuracyLevel) -> Gba {
+            let mut gba = console(&[0xF0, 0x04]);
+            gba.set_accuracy(accuracy);
+            gba.mem.sync();
+            gba.mem.timer.set_internal_counter(0x00F8);
+            gba.step();
+            gba.mem.sync();
+            gba
+        }
+
+        #[test]
+        fn accurate_reads_land_on_their_own_cycle() {
+            let balanced = read_div(AccuracyLevel::Balanced);
+            let accurate = read_div(AccuracyLevel::Accurate);
+            assert_eq!(balanced.cpu.registers.a, 0x00);
+            assert_eq!(accurate.cpu.registers.a, 0x01);
+            /* Either way the instruction takes the same time overall */
+            assert_eq!(balanced.mem.timer.internal_counter(), 0x0104);
+            assert_eq!(accurate.mem.timer.internal_counter(), 0x0104);
+        }
+
+        /* PUSH BC with SP at $FF06, so the low byte resets DIV */
+        fn push_into_div(accuracy: AccuracyLevel) -> u16 {
+            let mut gba = console(&[0xC5]);
+            gba.set_accuracy(accuracy);
+            gba.cpu.registers.sp = 0xFF06;
+            gba.cpu.registers.set_r16(Register16::BC, 0x4200);
+            gba.mem.sync();
+            gba.mem.timer.set_internal_counter(0x1000);
+            assert_eq!(gba.step(), 4);
+            gba.mem.sync();
+            assert_eq!(gba.mem.peek(0xFF05), 0x42);
+            gba.mem.timer.internal_counter()
+        }
+
+        #[test]
+        fn accurate_pushes_after_the_internal_cycle() {
+            /* Fetch, decrement SP, high byte, then DIV is reset on the last cycle */
+            assert_eq!(push_into_div(AccuracyLevel::Accurate), 4);
+            assert_eq!(push_into_div(AccuracyLevel::Balanced), 16);
+        }
+
+        #[test]
+        fn fast_reloads_tima_on_overflow() {
+            let mut gba = console(&[]);
+            gba.set_accuracy(AccuracyLevel::Fast);
+            gba.mem.set_u8(0xFF06_u16, 0x80);
+            gba.mem.set_u8(0xFF05_u16, 0xFF);
+            gba.mem.set_u8(0xFF07_u16, 0x05);
+            gba.mem.set_u8(0xFF0F_u16, 0x00);
+            gba.mem.timer.set_internal_counter(0x000C);
+            gba.mem.tick(1);
+            assert_eq!(gba.mem.get_u8(0xFF05_u16), 0x80);
+            assert_eq!(gba.mem.get_u8(0xFF0F_u16) & 0x04, 0x04);
+        }
+
+        fn dma_source(gba: &mut Gba) {
+            for i in 0..0xA0_u16 {
+                gba.mem.set_u8(0xC000 + i, i as u8);
+            }
+        }
+
+        #[test]
+        fn oam_dma_takes_a_cycle_a_byte() {
+            let mut gba = console(&[]);
+            dma_source(&mut gba);
+            gba.mem.set_u8(0xFF46_u16, 0xC0);
+            assert!(gba.mem.dma_active());
+            assert_eq!(gba.mem.peek(0xFE00), 0xFF);
+            gba.mem.set_u8(0xFE00_u16, 0x55);
+            gba.mem.tick(160);
+            assert_eq!(&gba.mem.ppu.oam[0x9D..], &[0x9D, 0x9E, 0x00]);
+            assert!(gba.mem.dma_active());
+            /* The scheduler catches the end without an explicit sync */
+            gba.mem.advance(1);
+            assert!(!gba.mem.dma_active());
+            assert_eq!(gba.mem.get_u8(0xFE00_u16), 0x00);
+            assert_eq!(gba.mem.get_u8(0xFE9F_u16), 0x9F);
+        }
+
+        #[test]
+        fn fast_oam_dma_is_instant() {
+            let mut gba = console(&[]);
+            gba.set_accuracy(AccuracyLevel::Fast);
+            assert_eq!(gba.mem.ppu.renderer, Renderer::Scanline);
+            dma_source(&mut gba);
+            /* Echo pages read WRAM */
+            gba.mem.set_u8(0xFF46_u16, 0xE0);
+            assert!(!gba.mem.dma_active());
+            assert_eq!(gba.mem.get_u8(0xFE9F_u16), 0x9F);
+        }
+
+        #[test]
+        fn dma_in_flight_survives_a_savestate() {
+            use crate::state::prelude::{Savestate, StateReader, StateWriter};
+
+            let mut gba = console(&[]);
+            dma_source(&mut gba);
+            gba.mem.set_u8(0xFF46_u16, 0xC0);
+            gba.mem.tick(50);
+            let mut w = StateWriter::new();
+            gba.mem.save_state(&mut w);
+            let mut restored = console(&[]);
+            restored.mem.load_state(&mut StateReader::new(&w.into_inner())).unwrap();
+            assert!(restored.mem.dma_active());
+            restored.mem.tick(111);
+            assert!(!restored.mem.dma_active());
+            assert_eq!(restored.mem.ppu.oam, gba.mem.wram()[..0xA0]);
+        }
+
+        #[test]
+        fn names_round_trip() {
+            for name in AccuracyLevel::NAMES {
+                assert_eq!(AccuracyLevel::from_name(name).unwrap().name(), name);
+            }
+            assert_eq!(AccuracyLevel::default(), AccuracyLevel::Balanced);
+        }
+    }
+    // }}}
+
+    // mod batch {{{
+    #[cfg(feature = "io")]
+    mod batch {
+        use std::path::{Path, PathBuf};
+
+        use crate::debugger::prelude::{batch_summary, expand_roms, BatchRunner, BatchStatus};
+
+        fn write_rom(dir: &Path, name: &str, program: &[u8]) -> PathBuf {
+            let path = dir.join(name);
+            std::fs::write(&path, super::rom(b"").program(program).build()).unwrap();
+            path
+        }
+
+        fn runner() -> BatchRunner {
+            let mut runner = BatchRunner { frames: 5, threads: 2, ..BatchRunner::default() };
+            runner.config.skip_boot = true;
+            runner
+        }
+
+        #[test]
+        fn reports_each_rom_in_order() {
+            let dir = super::scratch("batch-order");
+            let roms = vec![
+                /* LD A, 'H'; LDH (SB), A; LD A, $81; LDH (SC), A; JR -2 */
+                write_rom(&dir, "serial.gb", &[0x3E, b'H', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]),
+                write_rom(&dir, "locked.gb", &[0xD3]),
+                dir.join("missing.gb"),
+                write_rom(&dir, "loop.gb", &[0x18, 0xFE]),
+            ];
+            let results = runner().run(&roms);
+            let paths: Vec<&PathBuf> = results.iter().map(|result| &result.path).collect();
+            assert_eq!(paths, roms.iter().collect::<Vec<_>>());
+
+            assert_eq!(results[0].status, BatchStatus::Ran);
+            assert_eq!(results[0].serial, "H");
+            assert_eq!(results[0].frames, 5);
+            assert_eq!(results[1].status, BatchStatus::Locked(0xD3));
+            assert_eq!(results[1].frames, 1);
+            assert!(matches!(results[2].status, BatchStatus::LoadFailed(_)));
+            assert_eq!(results[2].screen_hash, None);
+            assert!(results[3].screen_hash.is_some());
+
+            let summary = batch_summary(&results);
+            assert!(summary.contains("serial: \"H\""));
+            assert!(summary.ends_with("4 ROMs, 1 failed\n"), "{}", summary);
+            let _ = std::fs::remove_dir_all(dir);
+        }
+
+        #[test]
+        fn screen_hashes_repeat() {
+            let dir = super::scratch("batch-repeat");
+            let rom = write_rom(&dir, "loop.gb", &[0x18, 0xFE]);
+            let single = BatchRunner { threads: 1, ..runner() };
+            let first = single.run(std::slice::from_ref(&rom));
+            let second = runner().run(&[rom.clone(), rom]);
+            assert_eq!(first[0].screen_hash, second[0].screen_hash);
+            assert_eq!(second[0].screen_hash, second[1].screen_hash);
+            let _ = std::fs::remove_dir_all(dir);
+        }
+
+        #[test]
+        fn directories_expand_to_sorted_roms() {
+            let dir = super::scratch("batch-expand");
+            std::fs::create_dir_all(dir.join("sub")).unwrap();
+            let b = write_rom(&dir, "b.gb", &[]);
+            let a = write_rom(&dir.join("sub"), "a.gbc", &[]);
+            std::fs::write(dir.join("notes.txt"), "").unwrap();
+            let extra = PathBuf::from("elsewhere.gb");
+            assert_eq!(expand_roms(&[dir.clone(), extra.clone()]), vec![b, a, extra]);
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+    // }}}
+
+    // mod interrupts {{{
+    mod interrupts {
+        use crate::{gba::prelude::Gba, mem::prelude::{Boot, Cart}};
+
+        /* Timer handler at $0050 raises VBlank and re-enables interrupts so
+         * the VBlank handler at $0040 nests inside it. Main spins at $0100. */
+        fn nesting() -> Gba {
+            let rom = super::rom(b"").cart_type(0x01)
+                /* INC B; RETI */
+                .at(0x40, &[0x04, 0xD9])
+                /* LD A,$01; LDH (IF),A; EI; NOP; INC C; RETI */
+                .at(0x50, &[0x3E, 0x01, 0xE0, 0x0F, 0xFB, 0x00, 0x0C, 0xD9])
+                /* NOP; JR -3 */
+                .program(&[0x00, 0x18, 0xFD])
+                .build();
+
+            /* Skipped so the vectors are the cart's, not the boot ROM's */
+            let mut gba = Gba::with_boot(Cart::from_bytes(rom).unwrap(), Boot::Skip);
+            gba.cpu.registers.sp = 0xD000;
+            (gba.cpu.registers.b, gba.cpu.registers.c) = (0, 0);
+            gba.cpu.ime = 1;
+            gba.mem.set_u8(0xFFFF_u16, 0x05);
+            gba.mem.set_u8(0xFF0F_u16, 0x04);
+            gba
+        }
+
+        #[test]
+        fn nested_handlers_return_through_the_stack() {
+            let mut gba = nesting();
+            assert_eq!(gba.step(), 5);
+            assert_eq!((gba.cpu.registers.pc, gba.cpu.registers.sp, gba.cpu.ime), (0x50, 0xCFFE, 0));
+            assert_eq!(gba.mem.get_u16(0xCFFE_u16), 0x0100);
+            assert_eq!(gba.mem.peek(0xFF0F) & 0x04, 0);
+
+            /* LD, LDH, EI, then the NOP that EI's delay lets through */
+            for _ in 0..4 {
+                gba.step();
+            }
+            assert_eq!(gba.cpu.registers.pc, 0x56);
+            assert_eq!(gba.step(), 5);
+            assert_eq!((gba.cpu.registers.pc, gba.cpu.registers.sp), (0x40, 0xCFFC));
+            assert_eq!(gba.mem.get_u16(0xCFFC_u16), 0x0056);
+
+            gba.step();
+            assert_eq!(gba.step(), 4);
+            assert_eq!((gba.cpu.registers.pc, gba.cpu.registers.sp, gba.cpu.ime), (0x56, 0xCFFE, 1));
+            gba.step();
+            assert_eq!(gba.step(), 4);
+            assert_eq!((gba.cpu.registers.pc, gba.cpu.registers.sp, gba.cpu.ime), (0x100, 0xD000, 1));
+            assert_eq!((gba.cpu.registers.b, gba.cpu.registers.c), (1, 1));
+            assert_eq!(gba.mem.peek(0xFF0F) & 0x1F, 0);
+        }
+
+        #[test]
+        fn reti_enables_interrupts_without_a_delay() {
+            let mut gba = nesting();
+            gba.mem.set_u8(0xFFFF_u16, 0x01);
+            gba.mem.set_u8(0xFF0F_u16, 0x00);
+            gba.cpu.ime = 0;
+            /* Mid-handler at RETI, with VBlank already waiting */
+            gba.cpu.registers.pc = 0x41;
+            gba.cpu.registers.sp = 0xCFFE;
+            gba.mem.set_u16(0xCFFE_u16, 0x0100);
+            gba.mem.set_u8(0xFF0F_u16, 0x01);
+
+            assert_eq!(gba.step(), 4);
+            assert_eq!(gba.cpu.registers.pc, 0x100);
+            /* Taken before the instruction at the return address runs */
+            assert_eq!(gba.step(), 5);
+            assert_eq!((gba.cpu.registers.pc, gba.cpu.registers.sp), (0x40, 0xCFFE));
+            assert_eq!(gba.mem.get_u16(0xCFFE_u16), 0x0100);
+        }
+    }
+    // }}}
+    // mod control_flow {{{
+    mod control_flow {
+        use super::console;
+        use crate::gba::{prelude::Gba, timing::expected_cycles};
+
+        const Z: u8 = 0x80;
+        const C: u8 = 0x10;
+
+        /* `program` at $0100 with the stack at $D000 holding $1234 and F = `flags` */
+        fn at(program: &[u8], flags: u8) -> Gba {
+            let mut gba = console(program);
+            gba.cpu.registers.sp = 0xCFFE;
+            gba.mem.set_u16(0xCFFE_u16, 0x1234);
+            gba.cpu.registers.set_af(flags as u16);
+            gba
+        }
+
+        /* (pc, sp, M-cycles) after one instruction */
+        fn run(program: &[u8], flags: u8) -> (u16, u16, usize) {
+            let mut gba = at(program, flags);
+            let cycles = gba.step();
+            (gba.cpu.registers.pc, gba.cpu.registers.sp, cycles)
+        }
+
+        #[test]
+        fn conditional_returns_take_five_or_two() {
+            assert_eq!(run(&[0xC0], 0), (0x1234, 0xD000, 5));
+            assert_eq!(run(&[0xC0], Z), (0x0101, 0xCFFE, 2));
+            assert_eq!(run(&[0xC8], Z), (0x1234, 0xD000, 5));
+            assert_eq!(run(&[0xD0], C), (0x0101, 0xCFFE, 2));
+            assert_eq!(run(&[0xD8], C), (0x1234, 0xD000, 5));
+            /* RET and RETI have no condition to check */
+            assert_eq!(run(&[0xC9], 0), (0x1234, 0xD000, 4));
+            assert_eq!(run(&[0xD9], 0), (0x1234, 0xD000, 4));
+        }
+
+        #[test]
+        fn conditional_jumps_take_four_or_three() {
+            assert_eq!(run(&[0xC2, 0x00, 0x02], 0), (0x0200, 0xCFFE, 4));
+            assert_eq!(run(&[0xC2, 0x00, 0x02], Z), (0x0103, 0xCFFE, 3));
+            assert_eq!(run(&[0xDA, 0x00, 0x02], C), (0x0200, 0xCFFE, 4));
+            assert_eq!(run(&[0xC3, 0x00, 0x02], Z), (0x0200, 0xCFFE, 4));
+            assert_eq!(run(&[0xE9], 0).2, 1);
+        }
+
+        #[test]
+        fn conditional_calls_take_six_or_three() {
+            let mut gba = at(&[0xCC, 0x00, 0x02], Z);
+            assert_eq!(gba.step(), 6);
+            assert_eq!((gba.cpu.registers.pc, gba.cpu.registers.sp), (0x0200, 0xCFFC));
+            assert_eq!(gba.mem.get_u16(0xCFFC_u16), 0x0103);
+            assert_eq!(run(&[0xCC, 0x00, 0x02], 0), (0x0103, 0xCFFE, 3));
+            assert_eq!(run(&[0xD4, 0x00, 0x02], C), (0x0103, 0xCFFE, 3));
+            assert_eq!(run(&[0xCD, 0x00, 0x02], 0), (0x0200, 0xCFFC, 6));
+            assert_eq!(run(&[0xEF], 0), (0x0028, 0xCFFC, 4));
+        }
+
+        #[test]
+        fn relative_jumps_go_backwards_and_wrap() {
+            assert_eq!(run(&[0x18, 0xFE], 0), (0x0100, 0xCFFE, 3));
+            assert_eq!(run(&[0x18, 0x80], 0), (0x0082, 0xCFFE, 3));
+            assert_eq!(run(&[0x18, 0x7F], 0), (0x0181, 0xCFFE, 3));
+            assert_eq!(run(&[0x20, 0xF0], Z), (0x0102, 0xCFFE, 2));
+            assert_eq!(run(&[0x20, 0xF0], 0), (0x00F2, 0xCFFE, 3));
+
+            /* From the top of HRAM round to the bottom of ROM */
+            let mut gba = at(&[], 0);
+            gba.mem.set_u8(0xFFFC_u16, 0x18);
+            gba.mem.set_u8(0xFFFD_u16, 0x05);
+            gba.cpu.registers.pc = 0xFFFC;
+            gba.step();
+            assert_eq!(gba.cpu.registers.pc, 0x0003);
+        }
+
+        #[test]
+        fn control_flow_matches_the_timing_table() {
+            let conditional = [0x20, 0x28, 0x30, 0x38, 0xC0, 0xC8, 0xD0, 0xD8, 0xC2, 0xCA, 0xD2, 0xDA, 0xC4, 0xCC, 0xD4, 0xDC];
+            let always = [0x18, 0xC3, 0xC9, 0xCD, 0xD9, 0xE9, 0xC7, 0xCF, 0xD7, 0xDF, 0xE7, 0xEF, 0xF7, 0xFF];
+            for opcode in conditional.into_iter().chain(always) {
+                for flags in [0, Z, C, Z | C] {
+                    let mut gba = at(&[opcode, 0x00, 0x02], flags);
+                    let cycles = gba.step();
+                    /* Bits 3-4 pick NZ, Z, NC or C */
+                    let taken = !conditional.contains(&opcode) || match (opcode >> 3) & 0x03 {
+                        0 => flags & Z == 0,
+                        1 => flags & Z != 0,
+                        2 => flags & C == 0,
+                        _ => flags & C != 0,
+                    };
+                    assert_eq!(cycles, expected_cycles(opcode, taken), "${:02X} with F=${:02X}", opcode, flags);
+                }
+            }
+        }
+    }
+    // }}}
+    // mod microcode {{{
+    mod microcode {
+        use super::console;
+        use crate::{
+            gba::{prelude::Gba, timing::{expected_cycles, is_conditional, prefixed_cycles}},
+            state::prelude::{Savestate, StateReader, StateWriter},
+        };
+
+        /* CALL $0110 ... $0110: SET 0,(HL) */
+        fn program() -> Gba {
+            let mut program = vec![0xCD, 0x10, 0x01];
+            program.resize(0x10, 0x00);
+            program.extend_from_slice(&[0xCB, 0xC6]);
+            let mut gba = console(&program);
+            gba.cpu.registers.sp = 0xD000;
+            gba.cpu.registers.set_hl(0xC000);
+            gba
+        }
+
+        #[test]
+        fn every_opcode_matches_the_timing_table() {
+            for opcode in 0..=0xFF {
+                /* Control flow has its own tests; STOP and HALT sleep */
+                if expected_cycles(opcode, false) == 0 || is_conditional(opcode) || matches!(opcode, 0x10 | 0x76 | 0xCB) {
+                    continue;
+                }
+                let mut gba = console(&[opcode, 0x00, 0xC0]);
+                gba.cpu.registers.sp = 0xD000;
+                assert_eq!(gba.step(), expected_cycles(opcode, false), "${:02X}", opcode);
+            }
+            for opcode in 0..=0xFF {
+                let mut gba = console(&[0xCB, opcode]);
+                assert_eq!(gba.step(), prefixed_cycles(opcode), "$CB ${:02X}", opcode);
+            }
+        }
+
+        #[test]
+        fn read_modify_write_lands_on_the_last_cycle() {
+            let mut gba = program();
+            gba.step();
+            /* The two fetches and the read leave memory alone */
+            for _ in 0..3 {
+                assert!(!gba.tick());
+                assert_eq!(gba.mem.peek(0xC000), 0x00);
+            }
+            assert!(gba.tick());
+            assert_eq!(gba.mem.peek(0xC000), 0x01);
+        }
+
+        #[test]
+        fn interrupts_wait_for_the_instruction_in_flight() {
+            let mut gba = program();
+            gba.cpu.ime = 1;
+            gba.mem.set_u8(0xFFFF_u16, 0x04);
+            gba.tick();
+            gba.mem.set_u8(0xFF0F_u16, 0x04);
+            while gba.mid_instruction() {
+                gba.tick();
+            }
+            assert_eq!((gba.cpu.registers.pc, gba.cpu.registers.sp), (0x0110, 0xCFFE));
+
+            /* Sampled at the boundary: the dispatch comes next */
+            for _ in 0..4 {
+                assert!(!gba.tick());
+            }
+            assert!(gba.tick());
+            assert_eq!((gba.cpu.registers.pc, gba.cpu.registers.sp), (0x0050, 0xCFFC));
+            assert_eq!(gba.mem.get_u16(0xCFFC_u16), 0x0110);
+        }
+
+        #[test]
+        fn savestates_resume_mid_instruction() {
+            let mut gba = program();
+            for _ in 0..3 {
+                gba.tick();
+            }
+            let mut w = StateWriter::new();
+            gba.cpu.save_state(&mut w);
+            gba.mem.save_state(&mut w);
+            let state = w.into_inner();
+
+            let mut restored = program();
+            let mut r = StateReader::new(&state);
+            restored.cpu.load_state(&mut r).unwrap();
+            restored.mem.load_state(&mut r).unwrap();
+            assert!(restored.mid_instruction());
+            assert_eq!(restored.step(), gba.step());
+            assert_eq!((restored.cpu.registers.pc, restored.cpu.registers.sp), (0x0110, 0xCFFE));
+            assert_eq!(restored.mem.get_u16(0xCFFE_u16), 0x0103);
+        }
+    }
+    // }}}
+    // mod raw_binary {{{
+    mod raw_binary {
+        use crate::{
+            error::prelude::GbError,
+            gba::prelude::{Emulator, RomSource},
+            mem::prelude::{CartInfo, HeaderError, RawMap},
+        };
+
+        fn raw(data: &[u8], origin: u16, map: RawMap) -> Result<Emulator, GbError> {
+            Emulator::new(RomSource::RawBinary { data: data.to_vec(), origin, map })
+        }
+
+        #[test]
+        fn runs_from_the_origin_without_a_header() {
+            /* LD A,$42; LD ($C000),A; JR -2, sitting where the header would be */
+            let mut emulator = raw(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x18, 0xFE], 0x0148, RawMap::RomOnly).unwrap();
+            assert_eq!(emulator.console().cpu.registers.pc, 0x0148);
+            assert!(!emulator.console().mem.boot_rom_mapped());
+            for _ in 0..3 {
+                emulator.console_mut().step();
+            }
+            assert_eq!(emulator.console().mem.peek(0xC000), 0x42);
+            assert_eq!(emulator.console().cpu.registers.pc, 0x014D);
+            /* The rest of the ROM is blank */
+            assert_eq!(emulator.console().mem.peek(0x0000), 0xFF);
+            /* The file as the ROM up to the program's end */
+            assert_eq!(CartInfo::new(emulator.console().mem.cart()).file_size, 0x014F);
+
+            emulator.reset(true);
+            assert_eq!(emulator.console().cpu.registers.pc, 0x0148);
+        }
+
+        #[test]
+        fn the_map_decides_on_cart_ram() {
+            /* LD ($A000),A; LD B,(HL) with HL pointed there by the test */
+            let program = [0xEA, 0x00, 0xA0, 0x46];
+            for (map, expected) in [(RawMap::RomOnly, 0xFF), (RawMap::RomRam, 0x5A)] {
+                let mut emulator = raw(&program, 0x0000, map).unwrap();
+                let gba = emulator.console_mut();
+                gba.cpu.registers.a = 0x5A;
+                gba.cpu.registers.set_hl(0xA000);
+                gba.step();
+                gba.step();
+                assert_eq!(gba.cpu.registers.b, expected, "{:?}", map);
+            }
+        }
+
+        #[test]
+        fn binaries_must_fit_in_rom() {
+            assert!(raw(&[0; 0x8000], 0x0000, RawMap::RomOnly).is_ok());
+            assert_eq!(raw(&[0x00, 0x00], 0x7FFF, RawMap::RomOnly).err(), Some(GbError::Rom(HeaderError::PastRomEnd(0x8000))));
+        }
+    }
+    // }}}
+}
@@ -0,0 +1,142 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+};
+
+use crate::error::prelude::GbError;
+
+/* A master clocks a byte out with `Clock`, the slave answers with the byte
+ * that was sitting in its own shift register. */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LinkMessage {
+    Clock(u8),
+    Reply(u8),
+}
+
+impl LinkMessage {
+    fn encode(&self) -> [u8; 2] {
+        match *self {
+            Self::Clock(byte) => [0, byte],
+            Self::Reply(byte) => [1, byte],
+        }
+    }
+
+    fn decode(bytes: [u8; 2]) -> Option<Self> {
+        match bytes[0] {
+            0 => Some(Self::Clock(bytes[1])),
+            1 => Some(Self::Reply(bytes[1])),
+            _ => None,
+        }
+    }
+}
+
+pub trait LinkCable {
+    fn send(&mut self, message: LinkMessage);
+    /* Non-blocking, returns None when nothing has arrived */
+    fn poll(&mut self) -> Option<LinkMessage>;
+    fn connected(&self) -> bool;
+}
+
+// struct LocalLink {{{
+/* Two consoles in the same process, e.g. stepped alternately on one thread */
+pub struct LocalLink {
+    tx: Sender<LinkMessage>,
+    rx: Receiver<LinkMessage>,
+    connected: bool,
+}
+
+impl LocalLink {
+    pub fn pair() -> (Self, Self) {
+        let (tx_a, rx_b) = channel();
+        let (tx_b, rx_a) = channel();
+        (Self { tx: tx_a, rx: rx_a, connected: true }, Self { tx: tx_b, rx: rx_b, connected: true })
+    }
+}
+
+impl LinkCable for LocalLink {
+    fn send(&mut self, message: LinkMessage) {
+        if self.tx.send(message).is_err() {
+            self.connected = false;
+        }
+    }
+
+    fn poll(&mut self) -> Option<LinkMessage> {
+        match self.rx.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => { self.connected = false; None },
+        }
+    }
+
+    fn connected(&self) -> bool {
+        self.connected
+    }
+}
+// }}}
+
+// struct TcpLink {{{
+#[cfg(feature = "io")]
+pub struct TcpLink {
+    stream: TcpStream,
+    pending: Vec<u8>,
+    connected: bool,
+}
+
+#[cfg(feature = "io")]
+impl TcpLink {
+    /* Blocks until the other console connects */
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> Result<Self, GbError> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Self::from_stream(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, GbError> {
+        Self::from_stream(TcpStream::connect(addr)?)
+    }
+
+    fn from_stream(stream: TcpStream) -> Result<Self, GbError> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self { stream, pending: Vec::with_capacity(2), connected: true })
+    }
+}
+
+#[cfg(feature = "io")]
+impl LinkCable for TcpLink {
+    fn send(&mut self, message: LinkMessage) {
+        let bytes = message.encode();
+        let mut written = 0;
+        while written < bytes.len() && self.connected {
+            match self.stream.write(&bytes[written..]) {
+                Ok(0) => self.connected = false,
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(_) => self.connected = false,
+            }
+        }
+    }
+
+    fn poll(&mut self) -> Option<LinkMessage> {
+        let mut buf = [0; 2];
+        while self.connected && self.pending.len() < 2 {
+            match self.stream.read(&mut buf[..2 - self.pending.len()]) {
+                Ok(0) => self.connected = false,
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+                Err(_) => self.connected = false,
+            }
+        }
+        if self.pending.len() < 2 {
+            return None;
+        }
+        let message = LinkMessage::decode([self.pending[0], self.pending[1]]);
+        self.pending.clear();
+        message
+    }
+
+    fn connected(&self) -> bool {
+        self.connected
+    }
+}
+// }}}
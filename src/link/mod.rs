@@ -0,0 +1,19 @@
+#![allow(unused)]
+
+mod cable;
+mod netplay;
+mod printer;
+mod serial;
+
+pub mod prelude {
+    pub use super::cable::{LinkCable, LinkMessage, LocalLink};
+    #[cfg(feature = "io")]
+    pub use super::cable::TcpLink;
+    pub use super::netplay::{
+        link_consoles, run_linked_frame, InputTransport, LocalInputs, Lockstep, NetplayMessage, DEFAULT_INPUT_DELAY,
+    };
+    #[cfg(feature = "io")]
+    pub use super::netplay::{TcpInputs, UdpInputs};
+    pub use super::printer::{Printer, Printout, PAPER_WIDTH};
+    pub use super::serial::Serial;
+}
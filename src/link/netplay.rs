@@ -0,0 +1,401 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    time::{Duration, Instant},
+};
+
+use crate::{error::prelude::GbError, gba::{console::CYCLES_PER_FRAME, prelude::Gba}};
+
+use super::cable::LocalLink;
+
+/* Input for a frame is only exchanged `delay` frames ahead of it being run,
+ * which hides that much round trip before either side has to wait */
+pub const DEFAULT_INPUT_DELAY: u32 = 2;
+/* Inputs repeated in every UDP datagram, so a lost one costs nothing */
+const UDP_REDUNDANCY: usize = 8;
+/* How often a stalled UDP side repeats itself */
+const RESEND_INTERVAL: Duration = Duration::from_millis(20);
+const MESSAGE_LEN: usize = 6;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetplayMessage {
+    /* First thing sent, so mismatched games or delays fail up front */
+    Hello { cart: u16, delay: u8 },
+    Input { frame: u32, pressed: u8 },
+}
+
+impl NetplayMessage {
+    fn encode(&self) -> [u8; MESSAGE_LEN] {
+        match *self {
+            Self::Hello { cart, delay } => {
+                let [lo, hi] = cart.to_le_bytes();
+                [0, lo, hi, delay, 0, 0]
+            },
+            Self::Input { frame, pressed } => {
+                let [a, b, c, d] = frame.to_le_bytes();
+                [1, a, b, c, d, pressed]
+            },
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        match bytes[0] {
+            0 => Some(Self::Hello { cart: u16::from_le_bytes([bytes[1], bytes[2]]), delay: bytes[3] }),
+            1 => Some(Self::Input { frame: u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]), pressed: bytes[5] }),
+            _ => None,
+        }
+    }
+}
+
+pub trait InputTransport {
+    fn send(&mut self, message: NetplayMessage);
+    /* Non-blocking, returns None when nothing has arrived */
+    fn poll(&mut self) -> Option<NetplayMessage>;
+    fn connected(&self) -> bool;
+    /* Called while waiting on the peer; lossy transports repeat themselves */
+    fn resend(&mut self) {}
+}
+
+// struct LocalInputs {{{
+/* Both players in the same process, for tests and local play */
+pub struct LocalInputs {
+    tx: Sender<NetplayMessage>,
+    rx: Receiver<NetplayMessage>,
+    connected: bool,
+}
+
+impl LocalInputs {
+    pub fn pair() -> (Self, Self) {
+        let (tx_a, rx_b) = channel();
+        let (tx_b, rx_a) = channel();
+        (Self { tx: tx_a, rx: rx_a, connected: true }, Self { tx: tx_b, rx: rx_b, connected: true })
+    }
+}
+
+impl InputTransport for LocalInputs {
+    fn send(&mut self, message: NetplayMessage) {
+        if self.tx.send(message).is_err() {
+            self.connected = false;
+        }
+    }
+
+    fn poll(&mut self) -> Option<NetplayMessage> {
+        match self.rx.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => { self.connected = false; None },
+        }
+    }
+
+    fn connected(&self) -> bool {
+        self.connected
+    }
+}
+// }}}
+
+// struct TcpInputs {{{
+#[cfg(feature = "io")]
+pub struct TcpInputs {
+    stream: TcpStream,
+    pending: Vec<u8>,
+    connected: bool,
+}
+
+#[cfg(feature = "io")]
+impl TcpInputs {
+    /* Blocks until the other player connects */
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> Result<Self, GbError> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Self::from_stream(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, GbError> {
+        Self::from_stream(TcpStream::connect(addr)?)
+    }
+
+    fn from_stream(stream: TcpStream) -> Result<Self, GbError> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self { stream, pending: Vec::with_capacity(MESSAGE_LEN), connected: true })
+    }
+}
+
+#[cfg(feature = "io")]
+impl InputTransport for TcpInputs {
+    fn send(&mut self, message: NetplayMessage) {
+        let bytes = message.encode();
+        let mut written = 0;
+        while written < bytes.len() && self.connected {
+            match self.stream.write(&bytes[written..]) {
+                Ok(0) => self.connected = false,
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(_) => self.connected = false,
+            }
+        }
+    }
+
+    fn poll(&mut self) -> Option<NetplayMessage> {
+        let mut buf = [0; MESSAGE_LEN];
+        while self.connected && self.pending.len() < MESSAGE_LEN {
+            match self.stream.read(&mut buf[..MESSAGE_LEN - self.pending.len()]) {
+                Ok(0) => self.connected = false,
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+                Err(_) => self.connected = false,
+            }
+        }
+        if self.pending.len() < MESSAGE_LEN {
+            return None;
+        }
+        let message = NetplayMessage::decode(&self.pending);
+        self.pending.clear();
+        message
+    }
+
+    fn connected(&self) -> bool {
+        self.connected
+    }
+}
+// }}}
+
+// struct UdpInputs {{{
+/* Every datagram carries the last few messages sent. Duplicates are harmless
+ * to the receiver, so nothing is ever acknowledged. */
+#[cfg(feature = "io")]
+pub struct UdpInputs {
+    socket: UdpSocket,
+    history: VecDeque<[u8; MESSAGE_LEN]>,
+    received: VecDeque<NetplayMessage>,
+    connected: bool,
+}
+
+#[cfg(feature = "io")]
+impl UdpInputs {
+    /* Blocks until the first datagram arrives and answers whoever sent it */
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> Result<Self, GbError> {
+        let socket = UdpSocket::bind(addr)?;
+        let mut buf = [0; MESSAGE_LEN * UDP_REDUNDANCY];
+        let (len, peer) = socket.recv_from(&mut buf)?;
+        socket.connect(peer)?;
+        let mut inputs = Self::from_socket(socket)?;
+        inputs.unpack(&buf[..len]);
+        Ok(inputs)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, GbError> {
+        let peer = addr.to_socket_addrs()?.next().ok_or(ErrorKind::AddrNotAvailable)?;
+        let local: SocketAddr = match peer {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(peer)?;
+        Self::from_socket(socket)
+    }
+
+    fn from_socket(socket: UdpSocket) -> Result<Self, GbError> {
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, history: VecDeque::with_capacity(UDP_REDUNDANCY), received: VecDeque::new(), connected: true })
+    }
+
+    fn unpack(&mut self, datagram: &[u8]) {
+        self.received.extend(datagram.chunks_exact(MESSAGE_LEN).filter_map(NetplayMessage::decode));
+    }
+}
+
+#[cfg(feature = "io")]
+impl InputTransport for UdpInputs {
+    fn send(&mut self, message: NetplayMessage) {
+        if self.history.len() == UDP_REDUNDANCY {
+            self.history.pop_front();
+        }
+        self.history.push_back(message.encode());
+        self.resend();
+    }
+
+    fn poll(&mut self) -> Option<NetplayMessage> {
+        let mut buf = [0; MESSAGE_LEN * UDP_REDUNDANCY];
+        while self.received.is_empty() && self.connected {
+            match self.socket.recv(&mut buf) {
+                Ok(len) => self.unpack(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                /* An ICMP unreachable from a peer that has not bound yet */
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => break,
+                Err(_) => self.connected = false,
+            }
+        }
+        self.received.pop_front()
+    }
+
+    fn connected(&self) -> bool {
+        self.connected
+    }
+
+    fn resend(&mut self) {
+        let datagram: Vec<u8> = self.history.iter().flatten().copied().collect();
+        match self.socket.send(&datagram) {
+            Ok(_) => {},
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::ConnectionRefused) => {},
+            Err(_) => self.connected = false,
+        }
+    }
+}
+// }}}
+
+// struct Lockstep {{{
+/* Both machines emulate both consoles, linked through a local cable, and
+ * only the joypads cross the network. Frame N runs once both players' input
+ * for it is known, so the two runs stay identical as long as both consoles
+ * were started from the same ROM, config, clock and RAM fill. */
+pub struct Lockstep {
+    transport: Box<dyn InputTransport>,
+    player: usize,
+    delay: u32,
+    cart: u16,
+    frame: u32,
+    /* Ours for `frame` onwards, in order */
+    local: VecDeque<u8>,
+    remote: BTreeMap<u32, u8>,
+    greeted: bool,
+    timeout: Duration,
+}
+
+impl Lockstep {
+    /* `player` 0 or 1 picks which console is ours; the two sides must pick
+     * differently but agree on the delay */
+    pub fn new(mut transport: Box<dyn InputTransport>, player: usize, delay: u32, cart: u16) -> Self {
+        assert!(player < 2, "netplay is two players");
+        let delay = delay.min(u8::MAX as u32);
+        transport.send(NetplayMessage::Hello { cart, delay: delay as u8 });
+        /* Nobody has pressed anything before the first frame */
+        Self {
+            transport,
+            player,
+            delay,
+            cart,
+            frame: 0,
+            local: std::iter::repeat_n(0, delay as usize).collect(),
+            remote: (0..delay).map(|frame| (frame, 0)).collect(),
+            greeted: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    pub fn delay(&self) -> u32 {
+        self.delay
+    }
+
+    /* The next frame to be run */
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /* Our joypad for the frame `delay` frames from now. Call once per frame. */
+    pub fn submit(&mut self, pressed: u8) {
+        let frame = self.frame + self.local.len() as u32;
+        self.local.push_back(pressed);
+        self.transport.send(NetplayMessage::Input { frame, pressed });
+    }
+
+    /* Both joypads for the current frame, player 0 first, advancing past it;
+     * None while the peer's has not arrived */
+    pub fn poll(&mut self) -> Result<Option<[u8; 2]>, GbError> {
+        while let Some(message) = self.transport.poll() {
+            match message {
+                NetplayMessage::Hello { cart, .. } if cart != self.cart => {
+                    return Err(GbError::Unsupported(format!("netplay with a different game ({:#06X})", cart)));
+                },
+                NetplayMessage::Hello { delay, .. } if delay as u32 != self.delay => {
+                    return Err(GbError::Unsupported(format!("netplay with a different input delay ({})", delay)));
+                },
+                NetplayMessage::Hello { .. } => self.greeted = true,
+                /* Repeats of frames already run */
+                NetplayMessage::Input { frame, .. } if frame < self.frame => {},
+                NetplayMessage::Input { frame, pressed } => { self.remote.insert(frame, pressed); },
+            }
+        }
+
+        let ready = self.greeted && !self.local.is_empty() && self.remote.contains_key(&self.frame);
+        if !ready {
+            return match self.transport.connected() {
+                true => Ok(None),
+                false => Err(GbError::Io(ErrorKind::ConnectionAborted)),
+            };
+        }
+        let ours = self.local.pop_front().unwrap_or(0);
+        let theirs = self.remote.remove(&self.frame).unwrap_or(0);
+        self.frame += 1;
+        Ok(Some(if self.player == 0 { [ours, theirs] } else { [theirs, ours] }))
+    }
+
+    /* `poll` until the peer catches up, or fail once the timeout passes */
+    #[cfg(feature = "io")]
+    pub fn wait(&mut self) -> Result<[u8; 2], GbError> {
+        let start = Instant::now();
+        let mut resent = start;
+        loop {
+            if let Some(inputs) = self.poll()? {
+                return Ok(inputs);
+            }
+            if start.elapsed() > self.timeout {
+                return Err(GbError::Io(ErrorKind::TimedOut));
+            }
+            if resent.elapsed() > RESEND_INTERVAL {
+                self.transport.resend();
+                resent = Instant::now();
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /* Submits our joypad, waits for the peer's and runs both consoles a
+     * frame. `consoles` is in player order; ours is `consoles[player]`. */
+    #[cfg(feature = "io")]
+    pub fn run_frame(&mut self, consoles: [&mut Gba; 2], pressed: u8) -> Result<usize, GbError> {
+        self.submit(pressed);
+        let inputs = self.wait()?;
+        Ok(run_linked_frame(consoles, inputs))
+    }
+}
+// }}}
+
+/* Plugs two consoles into each other through an in-process cable */
+pub fn link_consoles(a: &mut Gba, b: &mut Gba) {
+    let (cable_a, cable_b) = LocalLink::pair();
+    a.mem.serial.connect(Box::new(cable_a));
+    b.mem.serial.connect(Box::new(cable_b));
+}
+
+/* Applies each console's joypad, then runs both a frame with their
+ * instructions interleaved so link transfers see each other promptly.
+ * Returns the first console's CPU cycles. */
+pub fn run_linked_frame(consoles: [&mut Gba; 2], inputs: [u8; 2]) -> usize {
+    let [a, b] = consoles;
+    a.mem.set_pressed(inputs[0]);
+    b.mem.set_pressed(inputs[1]);
+
+    let (mut cycles, mut halves) = (0, [0; 2]);
+    while halves.iter().any(|&h| h < CYCLES_PER_FRAME * 2) {
+        /* Whichever is behind goes next, the first on a tie */
+        let (gba, i) = if halves[0] <= halves[1] { (&mut *a, 0) } else { (&mut *b, 1) };
+        let step = gba.step();
+        halves[i] += if gba.mem.double_speed() { step } else { step * 2 };
+        if i == 0 {
+            cycles += step;
+        }
+    }
+    a.mem.sync();
+    b.mem.sync();
+    cycles
+}
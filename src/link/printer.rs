@@ -0,0 +1,258 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use super::cable::{LinkCable, LinkMessage};
+
+/* The Game Boy Printer at the other end of the link cable. The game sends
+ * packets of
+ *
+ *   $88 $33 command compression length(2) data... checksum(2) $00 $00
+ *
+ * and the printer answers the last two bytes with $81, to say it is there,
+ * and its status. Image data is tiles, 20 to a row, which the print
+ * command puts on paper through a BGP-style palette. */
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const ALIVE: u8 = 0x81;
+/* 2 rows of 20 tiles, the most one data packet holds */
+const DATA_LEN: usize = 0x280;
+/* 9 packets of 2 tile rows, 160x144 */
+const BUFFER_LEN: usize = 9 * DATA_LEN;
+pub const PAPER_WIDTH: usize = 160;
+/* Status packets the printer stays busy for after printing */
+const BUSY_POLLS: u8 = 4;
+
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM: u8 = 0x01;
+const STATUS_PRINTING: u8 = 0x02;
+const STATUS_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED: u8 = 0x08;
+const STATUS_PACKET_ERROR: u8 = 0x10;
+
+/* Shades paper takes, lightest first */
+const INK: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+/* A finished strip of paper as packed RGB888, PAPER_WIDTH wide. Prints
+ * without a feed after them are joined into one strip with the next. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Printout {
+    pub height: usize,
+    pub rgb: Vec<u8>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Magic(usize),
+    Header,
+    Data,
+    Checksum,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    state: State,
+    /* The packet so far: command, compression, length, then data */
+    packet: Vec<u8>,
+    checksum: u16,
+    received: u16,
+    status: u8,
+    busy: u8,
+    /* Decompressed tile data waiting for a print command */
+    buffer: Vec<u8>,
+    /* Printed lines not yet fed out, as RGB */
+    strip: Vec<u8>,
+    out: Sender<Printout>,
+    reply: Option<u8>,
+}
+
+impl Printer {
+    /* The printer for a link port, and where its strips come out */
+    pub fn new() -> (Self, Receiver<Printout>) {
+        let (out, printouts) = channel();
+        let printer = Self {
+            state: State::Magic(0),
+            packet: Vec::new(),
+            checksum: 0,
+            received: 0,
+            status: 0,
+            busy: 0,
+            buffer: Vec::new(),
+            strip: Vec::new(),
+            out,
+            reply: None,
+        };
+        (printer, printouts)
+    }
+
+    /* Shifts one byte in and returns the byte shifted out for it */
+    fn exchange(&mut self, byte: u8) -> u8 {
+        match self.state {
+            State::Magic(i) => {
+                self.state = match byte == MAGIC[i] {
+                    true if i + 1 == MAGIC.len() => {
+                        self.packet.clear();
+                        self.checksum = 0;
+                        State::Header
+                    },
+                    true => State::Magic(i + 1),
+                    /* Out of step: look for the start of a packet again */
+                    false => State::Magic((byte == MAGIC[0]) as usize),
+                };
+            },
+            State::Header => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == 4 {
+                    self.state = if self.data_len() == 0 { State::Checksum } else { State::Data };
+                }
+            },
+            State::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == 4 + self.data_len() {
+                    self.state = State::Checksum;
+                }
+            },
+            State::Checksum => {
+                self.packet.push(byte);
+                if self.packet.len() == 6 + self.data_len() {
+                    let at = self.packet.len() - 2;
+                    self.received = u16::from_le_bytes([self.packet[at], self.packet[at + 1]]);
+                    self.state = State::Alive;
+                }
+            },
+            State::Alive => {
+                self.state = State::Status;
+                return ALIVE;
+            },
+            State::Status => {
+                self.state = State::Magic(0);
+                self.run_command();
+                return self.status;
+            },
+        }
+        0x00
+    }
+
+    fn data_len(&self) -> usize {
+        u16::from_le_bytes([self.packet[2], self.packet[3]]) as usize
+    }
+
+    fn run_command(&mut self) {
+        if self.received != self.checksum {
+            self.status |= STATUS_CHECKSUM;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM;
+        let (command, compressed) = (self.packet[0], self.packet[1] & 0x01 != 0);
+        let data = self.packet[4..4 + self.data_len()].to_vec();
+        match command {
+            CMD_INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy = 0;
+            },
+            CMD_DATA => {
+                let data = if compressed { decompress(&data) } else { data };
+                self.buffer.extend_from_slice(&data);
+                self.buffer.truncate(BUFFER_LEN);
+                if !self.buffer.is_empty() {
+                    self.status |= STATUS_UNPROCESSED;
+                }
+                if self.buffer.len() == BUFFER_LEN {
+                    self.status |= STATUS_FULL;
+                }
+            },
+            CMD_PRINT if data.len() >= 4 => {
+                self.print(data[1], data[2]);
+                self.status = (self.status & !(STATUS_UNPROCESSED | STATUS_FULL)) | STATUS_PRINTING;
+                self.busy = BUSY_POLLS;
+            },
+            CMD_STATUS => {
+                if self.busy > 0 {
+                    self.busy -= 1;
+                    if self.busy == 0 {
+                        self.status &= !STATUS_PRINTING;
+                    }
+                }
+            },
+            _ => self.status |= STATUS_PACKET_ERROR,
+        }
+    }
+
+    /* Puts the buffered tiles on paper. The high nibble of `margins` is
+     * feed before, the low feed after; a feed after ends the strip. */
+    fn print(&mut self, margins: u8, palette: u8) {
+        if margins >> 4 != 0 {
+            self.feed();
+        }
+        let rows = self.buffer.len() / (PAPER_WIDTH / 8 * 16);
+        for y in 0..rows * 8 {
+            for x in 0..PAPER_WIDTH {
+                let tile = (y / 8) * (PAPER_WIDTH / 8) + x / 8;
+                let at = tile * 16 + (y % 8) * 2;
+                let bit = 7 - x % 8;
+                let color = (self.buffer[at] >> bit & 1) | (self.buffer[at + 1] >> bit & 1) << 1;
+                let shade = palette >> (color * 2) & 0x03;
+                self.strip.extend_from_slice(&[INK[shade as usize]; 3]);
+            }
+        }
+        self.buffer.clear();
+        if margins & 0x0F != 0 {
+            self.feed();
+        }
+    }
+
+    /* Tears off what has been printed since the last feed */
+    fn feed(&mut self) {
+        if self.strip.is_empty() {
+            return;
+        }
+        let rgb = std::mem::take(&mut self.strip);
+        /* Nobody collecting printouts is no reason to stop printing */
+        let _ = self.out.send(Printout { height: rgb.len() / (PAPER_WIDTH * 3), rgb });
+    }
+}
+
+/* The game's RLE: a control byte with bit 7 set repeats the next byte
+ * (control & $7F) + 2 times, otherwise control + 1 literal bytes follow */
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut bytes = data.iter().copied();
+    while let Some(control) = bytes.next() {
+        if control & 0x80 != 0 {
+            let Some(byte) = bytes.next() else { break };
+            out.extend(std::iter::repeat_n(byte, (control & 0x7F) as usize + 2));
+        } else {
+            out.extend(bytes.by_ref().take(control as usize + 1));
+        }
+    }
+    out
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.feed();
+    }
+}
+
+impl LinkCable for Printer {
+    /* The printer never drives the clock; it answers each byte at once */
+    fn send(&mut self, message: LinkMessage) {
+        if let LinkMessage::Clock(byte) = message {
+            self.reply = Some(self.exchange(byte));
+        }
+    }
+
+    fn poll(&mut self) -> Option<LinkMessage> {
+        self.reply.take().map(LinkMessage::Reply)
+    }
+
+    fn connected(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,179 @@
+use std::{io::ErrorKind, ops::RangeInclusive};
+
+use crate::{cpu::interrupt::Interrupt, mem::prelude::MemoryMappedDevice, state::prelude::{Savestate, StateReader, StateWriter}};
+
+use super::cable::{LinkCable, LinkMessage};
+
+/* 8 bits at 8192 Hz */
+pub const TRANSFER_CYCLES: usize = 1024;
+/* How long a master waits past the transfer time for the peer's reply */
+const REPLY_TIMEOUT: usize = 4 * 17556;
+/* A connected cable is checked for the peer's bytes once a line */
+const POLL_CYCLES: usize = 114;
+
+/* SB / SC with internal (master) and external (slave) clocking */
+#[derive(Default)]
+pub struct Serial {
+    pub sb: u8,
+    pub sc: u8,
+    cable: Option<Box<dyn LinkCable>>,
+    remaining: usize,
+    waited: usize,
+    reply: Option<u8>,
+}
+
+impl Serial {
+    pub fn connect(&mut self, cable: Box<dyn LinkCable>) {
+        self.cable = Some(cable);
+    }
+
+    pub fn disconnect(&mut self) -> Option<Box<dyn LinkCable>> {
+        self.cable.take()
+    }
+
+    pub fn connected(&self) -> bool {
+        self.cable.as_ref().is_some_and(|cable| cable.connected())
+    }
+
+    pub fn transferring(&self) -> bool {
+        self.sc & 0x80 != 0
+    }
+
+    fn internal_clock(&self) -> bool {
+        self.sc & 0x01 != 0
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xFF01 => self.sb = value,
+            0xFF02 => {
+                self.sc = value;
+                if self.transferring() && self.internal_clock() {
+                    self.remaining = TRANSFER_CYCLES;
+                    self.waited = 0;
+                    self.reply = None;
+                    if let Some(cable) = self.cable.as_mut() {
+                        cable.send(LinkMessage::Clock(self.sb));
+                    }
+                }
+            },
+            _ => panic!("Accessing memory ${:#04X}: Not a serial register", addr),
+        }
+    }
+
+    /* M-cycles until a transfer can complete, None when idle and unplugged */
+    pub fn cycles_to_event(&self) -> Option<usize> {
+        let transfer = (self.transferring() && self.internal_clock()).then_some(self.remaining.max(1));
+        match self.cable {
+            Some(_) => Some(transfer.map_or(POLL_CYCLES, |cycles| cycles.min(POLL_CYCLES))),
+            None => transfer,
+        }
+    }
+
+    /* Advance by `cycles` M-cycles, returning the IF bits to raise */
+    pub fn tick(&mut self, cycles: usize) -> u8 {
+        let mut irq = 0;
+
+        while let Some(message) = self.cable.as_mut().and_then(|cable| cable.poll()) {
+            match message {
+                LinkMessage::Clock(byte) => {
+                    /* The peer is driving the clock. Armed for it, we swap
+                     * shift registers; otherwise ours stays put and the peer
+                     * reads the line idling high. */
+                    let armed = self.transferring() && !self.internal_clock();
+                    let out = if armed { self.sb } else { 0xFF };
+                    if let Some(cable) = self.cable.as_mut() {
+                        cable.send(LinkMessage::Reply(out));
+                    }
+                    if armed {
+                        self.sb = byte;
+                        self.sc &= 0x7F;
+                        irq |= Interrupt::Serial as u8;
+                    }
+                },
+                LinkMessage::Reply(byte) => self.reply = Some(byte),
+            }
+        }
+
+        if self.transferring() && self.internal_clock() {
+            if self.remaining > cycles {
+                self.remaining -= cycles;
+            } else {
+                self.remaining = 0;
+                self.waited += cycles;
+                let byte = match self.reply {
+                    Some(byte) => Some(byte),
+                    /* Nothing on the other end shifts in ones */
+                    None if !self.connected() || self.waited > REPLY_TIMEOUT => Some(0xFF),
+                    None => None,
+                };
+                if let Some(byte) = byte {
+                    self.sb = byte;
+                    self.sc &= 0x7F;
+                    self.reply = None;
+                    irq |= Interrupt::Serial as u8;
+                }
+            }
+        }
+        irq
+    }
+}
+
+const CLAIMS: &[RangeInclusive<u16>] = &[0xFF01..=0xFF02];
+
+impl MemoryMappedDevice for Serial {
+    fn claims(&self, _cgb: bool) -> &'static [RangeInclusive<u16>] {
+        CLAIMS
+    }
+
+    fn io_register(&self, addr: u16) -> &u8 {
+        match addr {
+            0xFF01 => &self.sb,
+            0xFF02 => &self.sc,
+            _ => panic!("Accessing memory ${:#04X}: Not a serial register", addr),
+        }
+    }
+
+    fn io_register_mut(&mut self, addr: u16) -> &mut u8 {
+        match addr {
+            0xFF01 => &mut self.sb,
+            0xFF02 => &mut self.sc,
+            _ => panic!("Accessing memory ${:#04X}: Not a serial register", addr),
+        }
+    }
+
+    fn io_write(&mut self, addr: u16, value: u8) -> u8 {
+        self.write_register(addr, value);
+        0
+    }
+
+    fn tick(&mut self, cycles: usize) -> u8 {
+        Serial::tick(self, cycles)
+    }
+
+    fn cpu_clocked(&self) -> bool {
+        true
+    }
+}
+
+impl Savestate for Serial {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.sb);
+        w.u8(self.sc);
+        w.u32(self.remaining as u32);
+        w.u32(self.waited as u32);
+        w.bool(self.reply.is_some());
+        w.u8(self.reply.unwrap_or(0));
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.sb = r.u8()?;
+        self.sc = r.u8()?;
+        self.remaining = r.u32()? as usize;
+        self.waited = r.u32()? as usize;
+        let has_reply = r.bool()?;
+        let reply = r.u8()?;
+        self.reply = has_reply.then_some(reply);
+        Ok(())
+    }
+}
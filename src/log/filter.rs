@@ -0,0 +1,86 @@
+use std::fmt::{self, Display};
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off = 0,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "off" => Some(Self::Off),
+            "error" => Some(Self::Error),
+            "warn" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            "trace" => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Off => "OFF",
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+            Self::Trace => "TRACE",
+        };
+        f.pad(name)
+    }
+}
+
+/* Per-target levels from a spec like `warn,gbemu::mem=debug,gbemu::cpu=trace`.
+ * A bare level sets the default, a bare target enables everything under it.
+ * Targets match on `::` boundaries and the longest match wins, so
+ * `gbemu::cpu` covers `gbemu::cpu::irq` unless that is given its own level. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    default: Option<Level>,
+    targets: Vec<(String, Level)>,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut filter = Self::default();
+        for directive in spec.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let level = Level::from_name(level.trim()).ok_or_else(|| format!("unknown log level `{}`", level))?;
+                    filter.targets.push((target.trim().to_string(), level));
+                },
+                None => match Level::from_name(directive) {
+                    Some(level) => filter.default = Some(level),
+                    None => filter.targets.push((directive.to_string(), Level::Trace)),
+                },
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn level(&self, target: &str) -> Level {
+        self.targets.iter()
+            .filter(|(prefix, _)| target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with("::")))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|&(_, level)| level)
+            .or(self.default)
+            .unwrap_or(Level::Off)
+    }
+
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        level != Level::Off && level <= self.level(target)
+    }
+
+    pub fn max_level(&self) -> Level {
+        self.targets.iter().map(|&(_, level)| level).chain(self.default).max().unwrap_or(Level::Off)
+    }
+}
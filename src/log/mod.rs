@@ -0,0 +1,39 @@
+#![allow(unused)]
+
+mod filter;
+#[macro_use]
+mod macros;
+
+use std::{
+    fmt::Arguments,
+    sync::{atomic::{AtomicU8, Ordering}, RwLock},
+};
+
+use self::filter::{Filter, Level};
+
+/* Checked before taking the lock so disabled call sites cost one load */
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(0);
+static FILTER: RwLock<Option<Filter>> = RwLock::new(None);
+
+/* Installs `spec` (see `Filter::parse`) as the process-wide filter */
+pub fn init(spec: &str) -> Result<(), String> {
+    let filter = Filter::parse(spec)?;
+    MAX_LEVEL.store(filter.max_level() as u8, Ordering::Relaxed);
+    *FILTER.write().unwrap_or_else(|e| e.into_inner()) = Some(filter);
+    Ok(())
+}
+
+#[inline(always)]
+pub fn enabled(target: &str, level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+        && FILTER.read().is_ok_and(|filter| filter.as_ref().is_some_and(|filter| filter.enabled(target, level)))
+}
+
+pub fn write(target: &str, level: Level, args: Arguments) {
+    eprintln!("{:5} {}: {}", level, target, args);
+}
+
+pub mod prelude {
+    pub use super::filter::{Filter, Level};
+    pub use super::{enabled, init};
+}
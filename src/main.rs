@@ -1,11 +1,413 @@
-mod cpu;
-mod mem;
-mod gba;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    process::exit,
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
 
+use gba::{
+    config::prelude::{Cli, Command, Config, LinkMode, USAGE},
+    debugger::prelude::{batch_summary, expand_roms, BatchRunner, CodeDataLog, GdbStub, GoldenOutcome, GoldenTest, Profiler, Repl, Symbols},
+    error::prelude::GbError,
+    gba::prelude::{install_panic_hook, last_panic, Gba, SpeedControl},
+    input::prelude::{InputMerger, InputSource},
+    link::prelude::{Printer, Printout, TcpLink, PAPER_WIDTH},
+    mem::prelude::{Cart, CartInfo, StillImage},
+    state::prelude::AUTOSAVE_SLOT,
+    ppu::{image, prelude::VideoFormat},
+    Emulator, SCREEN_HEIGHT, SCREEN_WIDTH, SGB_HEIGHT, SGB_WIDTH,
+};
 
-fn main() {
+#[cfg(target_os = "linux")]
+use std::{cell::RefCell, rc::Rc};
+
+#[cfg(target_os = "linux")]
+use gba::input::prelude::Gamepads;
+#[cfg(feature = "tui")]
+use gba::tui::prelude::{ChannelKey, Terminal, TuiView};
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2)
+}
+
+fn info(rom: &Path, json: bool) -> ! {
+    let data = std::fs::read(rom).unwrap_or_else(|e| { eprintln!("Failed to read `{}`: {}", rom.display(), e); exit(1) });
+    let cart = Cart::parse(data).unwrap_or_else(|e| { eprintln!("`{}`: {}", rom.display(), e); exit(1) });
+    let info = CartInfo::new(&cart);
+    if json { println!("{}", info.to_json()) } else { print!("{}", info) }
+    exit(0)
+}
+
+fn batch(cli: &Cli, config: Config, threads: Option<usize>) -> ! {
+    let mut runner = BatchRunner { config, ..BatchRunner::default() };
+    if let Some(frames) = cli.frames {
+        runner.frames = frames;
+    }
+    if let Some(threads) = threads {
+        runner.threads = threads;
+    }
+    let roms = expand_roms(&cli.roms);
+    if roms.is_empty() {
+        eprintln!("No ROMs found");
+        exit(2);
+    }
+    let results = runner.run(&roms);
+    print!("{}", batch_summary(&results));
+    exit(if results.iter().any(|result| result.status.is_failure()) { 1 } else { 0 })
+}
+
+fn plug_cable(gba: &mut Gba, cable: Result<TcpLink, GbError>, addr: &str) {
+    match cable {
+        Ok(cable) => gba.mem.serial.connect(Box::new(cable)),
+        Err(e) => { eprintln!("Failed to open link cable on `{}`: {}", addr, e); exit(1) },
+    }
+}
+
+/* Numbered past any printouts already in `dir`, so runs do not overwrite each other */
+fn write_printouts(dir: &Path, printouts: Receiver<Printout>) {
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        eprintln!("Failed to create `{}`: {}", dir.display(), e);
+        exit(1);
+    }
+    let mut number = 1;
+    for printout in printouts.try_iter() {
+        let mut path = dir.join(format!("print-{:03}.png", number));
+        while path.exists() {
+            number += 1;
+            path = dir.join(format!("print-{:03}.png", number));
+        }
+        if let Err(e) = image::save(&path, PAPER_WIDTH, printout.height, &printout.rgb) {
+            eprintln!("Failed to write `{}`: {:?}", path.display(), e);
+            exit(1);
+        }
+        eprintln!("Printed `{}`", path.display());
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn load_scripts(paths: &[std::path::PathBuf], mem: &mut gba::mem::prelude::Mem) -> gba::script::prelude::ScriptHost {
+    use gba::script::prelude::{ScriptHost, TextScript};
+
+    let mut host = ScriptHost::default();
+    for path in paths {
+        let source = std::fs::read_to_string(path).unwrap_or_else(|e| { eprintln!("Failed to read `{}`: {}", path.display(), e); exit(1) });
+        match TextScript::parse(&source) {
+            Ok(script) => host.add(mem, Box::new(script)),
+            Err(e) => { eprintln!("`{}`, {}", path.display(), e); exit(2) },
+        }
+    }
+    host
 }
 
-#[cfg(test)]
-mod gba_test {
+fn main() {
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(Some(cli)) => cli,
+        Ok(None) => usage(),
+        Err(e) => { eprintln!("{}", e); usage() },
+    };
+    if let Command::Info { json } = cli.command {
+        info(&cli.rom, json);
+    }
+    if let Some(spec) = cli.log.clone().or_else(|| std::env::var("GBEMU_LOG").ok()) {
+        if let Err(e) = gba::log::init(&spec) {
+            eprintln!("Bad log filter: {}", e);
+            exit(2);
+        }
+    }
+    install_panic_hook();
+    let config = cli.load_config().unwrap_or_else(|e| { eprintln!("Bad config: {}", e); exit(2) });
+    if let Command::Batch { threads } = cli.command {
+        batch(&cli, config, threads);
+    }
+
+    let mut speed = SpeedControl::default();
+    speed.set_turbo(cli.turbo);
+    if let Some(multiplier) = cli.speed {
+        speed.set_multiplier(multiplier);
+    }
+    if let Some(frame_skip) = cli.frame_skip {
+        speed.frame_skip = frame_skip;
+    }
+
+    let mut emulator = match cli.rom_source().map_err(GbError::from).and_then(|rom| Emulator::with_config(rom, &config)) {
+        Ok(emulator) => emulator,
+        Err(e) => { eprintln!("Failed to load `{}`: {}", cli.rom.display(), e); exit(1) },
+    };
+    if let Some(path) = &cli.import_save {
+        let imported = std::fs::read(path).map_err(Into::into).and_then(|save| emulator.import_save(&save));
+        if let Err(e) = imported {
+            eprintln!("Failed to import `{}`: {}", path.display(), e);
+            exit(1);
+        }
+    }
+    if let Some(slot) = cli.load_slot {
+        if let Err(e) = emulator.load_slot(slot) {
+            eprintln!("Failed to load slot {}: {}", slot, e);
+            exit(1);
+        }
+    }
+    if cli.record_wav.is_some() {
+        emulator.start_wav_capture();
+    }
+    if cli.record_vgm.is_some() {
+        emulator.start_vgm_log();
+    }
+    if let Some(path) = &cli.record_video {
+        emulator.start_video_capture(VideoFormat::from_path(path), cli.record_frames);
+    }
+    if let Some(path) = &cli.camera {
+        match image::load(path) {
+            Ok((width, height, rgb)) => { emulator.set_camera_sensor(StillImage::from_rgb(&rgb, width, height)); },
+            Err(e) => { eprintln!("Failed to read `{}`: {:?}", path.display(), e); exit(1) },
+        }
+    }
+    if let Some(device) = &cli.webcam {
+        #[cfg(feature = "webcam")]
+        match gba::mem::prelude::Webcam::ffmpeg(device) {
+            Ok(webcam) => { emulator.set_camera_sensor(webcam); },
+            Err(e) => { eprintln!("Failed to open webcam `{}`: {}", device, e); exit(1) },
+        }
+        #[cfg(not(feature = "webcam"))]
+        {
+            eprintln!("Built without the `webcam` feature, can't open `{}`", device);
+            exit(2);
+        }
+    }
+    let gba = emulator.console_mut();
+
+    let mut printouts = None;
+    match &cli.link {
+        Some(LinkMode::Listen(addr)) => plug_cable(gba, TcpLink::listen(addr), addr),
+        Some(LinkMode::Connect(addr)) => plug_cable(gba, TcpLink::connect(addr), addr),
+        Some(LinkMode::Printer(_)) => {
+            let (printer, out) = Printer::new();
+            gba.mem.serial.connect(Box::new(printer));
+            printouts = Some(out);
+        },
+        None => {},
+    }
+
+    let symbols = cli.symbols.clone().or_else(|| Some(cli.rom.with_extension("sym")).filter(|path| path.exists()));
+    if let Some(path) = &symbols {
+        match Symbols::load(path) {
+            Ok(symbols) => gba.symbols = Some(symbols),
+            Err(e) => { eprintln!("Failed to load `{}`: {}", path.display(), e); exit(1) },
+        }
+    }
+
+    if let Some(addr) = &cli.gdb {
+        if let Err(e) = GdbStub::default().serve(gba, addr.as_str()) {
+            eprintln!("gdb session on `{}` failed: {}", addr, e);
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if cli.command == Command::Debug {
+        let stdin = std::io::stdin();
+        if let Err(e) = Repl::default().run(gba, stdin.lock(), std::io::stdout()) {
+            eprintln!("Debugger session failed: {}", e);
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(path) = &cli.cdl {
+        let rom_len = gba.mem.cart().data.len();
+        gba.mem.cdl = Some(match path.exists() {
+            true => CodeDataLog::load(path, rom_len).unwrap_or_else(|e| {
+                eprintln!("Failed to load `{}`: {}", path.display(), e);
+                exit(1)
+            }),
+            false => CodeDataLog::new(rom_len),
+        });
+    }
+    if cli.profile.is_some() {
+        gba.profiler = Some(Profiler::default());
+    }
+
+    #[cfg(feature = "scripting")]
+    let mut scripts = load_scripts(&cli.scripts, &mut gba.mem);
+    #[cfg(not(feature = "scripting"))]
+    if !cli.scripts.is_empty() {
+        eprintln!("Built without the `scripting` feature");
+        exit(2);
+    }
+
+    let mut input = InputMerger::default();
+    /* Shared with the input merger so rumble can reach the pads */
+    #[cfg(target_os = "linux")]
+    let pads = cli.gamepad.then(|| Rc::new(RefCell::new(Gamepads::new(config.pads.clone()))));
+    #[cfg(target_os = "linux")]
+    if let Some(pads) = pads.clone() {
+        input.add(move |frame| pads.borrow_mut().poll(frame));
+    }
+    #[cfg(not(target_os = "linux"))]
+    if cli.gamepad {
+        eprintln!("Gamepads are only supported on Linux");
+        exit(2);
+    }
+    #[cfg(feature = "tui")]
+    let mut tui = cli.tui.then(|| {
+        let (terminal, keys) = Terminal::open(config.keys.clone()).unwrap_or_else(|e| { eprintln!("Failed to set up the terminal: {}", e); exit(1) });
+        input.add(keys);
+        (terminal, TuiView::default())
+    });
+    #[cfg(not(feature = "tui"))]
+    if cli.tui {
+        eprintln!("Built without the `tui` feature");
+        exit(2);
+    }
+
+    let frames = cli.frames;
+    let autosave = config.autosave.map(|secs| Duration::from_secs(secs as u64));
+    let mut saved = Instant::now();
+    let mut frame = 0;
+    let run = panic::catch_unwind(AssertUnwindSafe(|| while frames.is_none_or(|frames| frame < frames) {
+        #[cfg(feature = "tui")]
+        if tui.as_ref().is_some_and(|(terminal, _)| terminal.quit_requested()) {
+            break;
+        }
+        #[cfg(feature = "tui")]
+        for key in tui.as_ref().map(|(terminal, _)| terminal.take_channel_keys()).unwrap_or_default() {
+            match key {
+                ChannelKey::Mute(channel) => emulator.set_channel_muted(channel, !emulator.channel_muted(channel)),
+                ChannelKey::Solo(channel) => emulator.set_solo((emulator.solo() != Some(channel)).then_some(channel)),
+            }
+        }
+        let recording = emulator.video_frames().is_some();
+        let gba = emulator.console_mut();
+        if !input.is_empty() {
+            gba.mem.set_pressed(input.poll(frame).0);
+        }
+        /* Always render the final frame so screenshots and golden images are current, and every frame of a video */
+        let last = frames.is_some_and(|frames| frame + 1 == frames);
+        let render = speed.render_next() || last || recording;
+        gba.mem.ppu.skip_render = !render;
+        #[cfg(feature = "scripting")]
+        let cycles = scripts.run_frame(gba);
+        #[cfg(not(feature = "scripting"))]
+        let cycles = gba.run_frame();
+        /* No audio output yet; drain so the buffer does not fill */
+        gba.mem.apu.take_samples();
+        emulator.end_frame(cycles);
+        #[cfg(feature = "tui")]
+        if let Some((terminal, view)) = tui.as_mut().filter(|_| render) {
+            let (rgb, width, height) = match emulator.sgb_framebuffer() {
+                Some(rgb) => (rgb, SGB_WIDTH, SGB_HEIGHT),
+                None => (emulator.framebuffer(), SCREEN_WIDTH, SCREEN_HEIGHT),
+            };
+            let lines = view.lines(emulator.console_mut(), &rgb, width, height);
+            /* Nothing to show an error on if the terminal has gone away */
+            let _ = terminal.draw(&view.draw(lines));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(pads) = &pads {
+            for event in emulator.take_rumble_events() {
+                pads.borrow_mut().set_rumble(event.strength * config.rumble as f32 / 100.0);
+            }
+        }
+        speed.end_frame();
+        frame += 1;
+
+        if autosave.is_some_and(|interval| saved.elapsed() >= interval) {
+            if let Err(e) = emulator.save_slot(AUTOSAVE_SLOT) {
+                eprintln!("Autosave failed: {}", e);
+            }
+            saved = Instant::now();
+        }
+        for notification in emulator.take_notifications() {
+            eprintln!("{}", notification);
+        }
+    }));
+    /* Put the terminal back before anything else is printed */
+    #[cfg(feature = "tui")]
+    drop(tui);
+    if run.is_err() {
+        /* The hook has already printed the panic */
+        let dir = config.crash_dir.clone()
+            .or_else(|| emulator.save_path().and_then(Path::parent).map(Path::to_path_buf))
+            .unwrap_or_default();
+        match emulator.write_crash_dump(&dir, &last_panic().unwrap_or_default()) {
+            Ok(path) => eprintln!("Wrote a crash dump to `{}`", path.display()),
+            Err(e) => eprintln!("Failed to write a crash dump: {}", e),
+        }
+        exit(101);
+    }
+    if let (Some(LinkMode::Printer(dir)), Some(printouts)) = (&cli.link, printouts) {
+        /* Unplugging feeds out whatever is still on the printer */
+        emulator.console_mut().mem.serial.disconnect();
+        write_printouts(dir, printouts);
+    }
+    let gba = emulator.console();
+
+    if let (Some(path), Some(profiler)) = (&cli.profile, &gba.profiler) {
+        eprint!("{}", profiler.report(20));
+        let mut callgrind = Vec::new();
+        profiler.write_callgrind(&mut callgrind).expect("writing to memory");
+        if let Err(e) = std::fs::write(path, callgrind) {
+            eprintln!("Failed to write `{}`: {}", path.display(), e);
+            exit(1);
+        }
+    }
+
+    if let (Some(path), Some(cdl)) = (&cli.cdl, &gba.mem.cdl) {
+        if let Err(e) = cdl.save(path) {
+            eprintln!("Failed to write `{}`: {:?}", path.display(), e);
+            exit(1);
+        }
+    }
+
+    if let Err(e) = emulator.save_battery() {
+        eprintln!("Failed to write the battery save: {}", e);
+        exit(1);
+    }
+    if let Some(path) = &cli.export_save {
+        if let Err(e) = std::fs::write(path, emulator.export_save(cli.save_format)) {
+            eprintln!("Failed to write `{}`: {}", path.display(), e);
+            exit(1);
+        }
+    }
+
+    let captures = [
+        (&cli.record_wav, emulator.stop_wav_capture()),
+        (&cli.record_vgm, emulator.stop_vgm_log()),
+        (&cli.record_video, emulator.stop_video_capture()),
+    ];
+    for (path, capture) in captures {
+        if let (Some(path), Some(capture)) = (path, capture) {
+            if let Err(e) = std::fs::write(path, capture) {
+                eprintln!("Failed to write `{}`: {}", path.display(), e);
+                exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = &cli.screenshot {
+        let scale = config.scale as usize;
+        let (frame, width, height) = match emulator.sgb_framebuffer() {
+            Some(frame) => (frame, SGB_WIDTH, SGB_HEIGHT),
+            None => (emulator.framebuffer(), SCREEN_WIDTH, SCREEN_HEIGHT),
+        };
+        let rgb = image::scale(&frame, width, height, scale);
+        if let Err(e) = image::save(path, width * scale, height * scale, &rgb) {
+            eprintln!("Failed to write `{}`: {:?}", path.display(), e);
+            exit(1);
+        }
+    }
+
+    if let Some(path) = &cli.golden {
+        /* The framebuffer is already in the configured palette */
+        match GoldenTest::default().compare(&emulator.framebuffer(), path) {
+            Ok(GoldenOutcome::Matched) => {},
+            Ok(GoldenOutcome::Blessed) => eprintln!("Wrote golden image `{}`", path.display()),
+            Ok(GoldenOutcome::Mismatched { pixels, artifact }) => {
+                eprintln!("{} pixels differ from `{}`; see `{}`", pixels, path.display(), artifact.display());
+                exit(1);
+            },
+            Err(e) => { eprintln!("Failed to compare with `{}`: {}", path.display(), e); exit(1) },
+        }
+    }
 }
@@ -0,0 +1,155 @@
+use std::{fmt, ops::RangeInclusive};
+
+/* A CPU address. Mem still indexes by anything Into<u16>, which this is;
+ * the point is for code that cares which region an address lands in, so
+ * an offset into VRAM cannot be taken from an OAM or echo address
+ * without saying so. */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address(pub u16);
+
+/* The CPU's memory map, one variant per stretch with its own hardware */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Region {
+    Rom0,
+    RomX,
+    Vram,
+    CartRam,
+    Wram0,
+    WramX,
+    Echo,
+    Oam,
+    Unusable,
+    Io,
+    Hram,
+    Ie,
+}
+
+impl Region {
+    pub const ALL: [Self; 12] = [
+        Self::Rom0, Self::RomX, Self::Vram, Self::CartRam, Self::Wram0, Self::WramX,
+        Self::Echo, Self::Oam, Self::Unusable, Self::Io, Self::Hram, Self::Ie,
+    ];
+
+    pub const fn range(self) -> RangeInclusive<u16> {
+        match self {
+            Self::Rom0     => 0x0000..=0x3FFF,
+            Self::RomX     => 0x4000..=0x7FFF,
+            Self::Vram     => 0x8000..=0x9FFF,
+            Self::CartRam  => 0xA000..=0xBFFF,
+            Self::Wram0    => 0xC000..=0xCFFF,
+            Self::WramX    => 0xD000..=0xDFFF,
+            Self::Echo     => 0xE000..=0xFDFF,
+            Self::Oam      => 0xFE00..=0xFE9F,
+            Self::Unusable => 0xFEA0..=0xFEFF,
+            Self::Io       => 0xFF00..=0xFF7F,
+            Self::Hram     => 0xFF80..=0xFFFE,
+            Self::Ie       => 0xFFFF..=0xFFFF,
+        }
+    }
+
+    pub const fn base(self) -> Address {
+        Address(*self.range().start())
+    }
+
+    pub const fn size(self) -> usize {
+        (*self.range().end() - *self.range().start()) as usize + 1
+    }
+
+    pub fn contains(self, addr: impl Into<Address>) -> bool {
+        self.range().contains(&addr.into().0)
+    }
+
+    /* As the Pan Docs memory map labels it */
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Rom0     => "ROM0",
+            Self::RomX     => "ROMX",
+            Self::Vram     => "VRAM",
+            Self::CartRam  => "SRAM",
+            Self::Wram0    => "WRAM0",
+            Self::WramX    => "WRAMX",
+            Self::Echo     => "ECHO",
+            Self::Oam      => "OAM",
+            Self::Unusable => "UNUSED",
+            Self::Io       => "IO",
+            Self::Hram     => "HRAM",
+            Self::Ie       => "IE",
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Address {
+    pub const fn region(self) -> Region {
+        match self.0 {
+            0x0000..=0x3FFF => Region::Rom0,
+            0x4000..=0x7FFF => Region::RomX,
+            0x8000..=0x9FFF => Region::Vram,
+            0xA000..=0xBFFF => Region::CartRam,
+            0xC000..=0xCFFF => Region::Wram0,
+            0xD000..=0xDFFF => Region::WramX,
+            0xE000..=0xFDFF => Region::Echo,
+            0xFE00..=0xFE9F => Region::Oam,
+            0xFEA0..=0xFEFF => Region::Unusable,
+            0xFF00..=0xFF7F => Region::Io,
+            0xFF80..=0xFFFE => Region::Hram,
+            0xFFFF          => Region::Ie,
+        }
+    }
+
+    /* How far into its own region this is */
+    pub const fn offset(self) -> usize {
+        (self.0 - self.region().base().0) as usize
+    }
+
+    /* How far into `region` this is, None when it lies in another */
+    pub fn offset_in(self, region: Region) -> Option<usize> {
+        region.contains(self).then(|| (self.0 - region.base().0) as usize)
+    }
+
+    /* Echo RAM as the WRAM address it mirrors; anything else as is */
+    pub const fn unecho(self) -> Self {
+        match self.0 {
+            0xE000..=0xFDFF => Self(self.0 - 0x2000),
+            _ => self,
+        }
+    }
+
+    pub const fn wrapping_add(self, n: u16) -> Self {
+        Self(self.0.wrapping_add(n))
+    }
+
+    /* Every address from here to `end`, inclusive */
+    pub fn to(self, end: Self) -> impl Iterator<Item = Self> {
+        (self.0..=end.0).map(Self)
+    }
+}
+
+impl From<u16> for Address {
+    fn from(addr: u16) -> Self {
+        Self(addr)
+    }
+}
+
+impl From<Address> for u16 {
+    fn from(addr: Address) -> Self {
+        addr.0
+    }
+}
+
+impl From<Address> for usize {
+    fn from(addr: Address) -> Self {
+        addr.0 as usize
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "${:04X}", self.0)
+    }
+}
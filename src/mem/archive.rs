@@ -0,0 +1,90 @@
+use std::io::ErrorKind;
+
+use crate::{error::prelude::GbError, ppu::image::{crc32, inflate}};
+
+/* ROMs are often distributed zipped. Only what a ROM archive needs is
+ * read: the central directory, and stored or deflated entries. */
+
+const LOCAL_HEADER: u32 = 0x0403_4B50;
+const CENTRAL_HEADER: u32 = 0x0201_4B50;
+const END_OF_DIRECTORY: u32 = 0x0605_4B50;
+/* The end record without its trailing comment */
+const END_LEN: usize = 22;
+
+const ROM_EXTENSIONS: [&str; 3] = [".gb", ".gbc", ".sgb"];
+/* The largest ROM size a header can declare, 8MB */
+const MAX_ROM_LEN: usize = 0x80_0000;
+
+/* Whether `data` is a zip archive, going by the magic and not the file name */
+pub fn is_zip(data: &[u8]) -> bool {
+    data.len() >= 4 && u32_at(data, 0) == LOCAL_HEADER
+}
+
+/* The first .gb, .gbc or .sgb entry in directory order, decompressed */
+pub fn extract_rom(data: &[u8]) -> Result<Vec<u8>, GbError> {
+    let end = find_end(data).ok_or(ErrorKind::InvalidData)?;
+    let entries = u16_at(data, end + 10) as usize;
+    let mut pos = u32_at(data, end + 16) as usize;
+
+    for _ in 0..entries {
+        let header = data.get(pos..pos + 46).ok_or(ErrorKind::UnexpectedEof)?;
+        if u32_at(header, 0) != CENTRAL_HEADER {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let method = u16_at(header, 10);
+        let crc = u32_at(header, 16);
+        let compressed = u32_at(header, 20) as usize;
+        let len = u32_at(header, 24) as usize;
+        let name_len = u16_at(header, 28) as usize;
+        let skip = u16_at(header, 30) as usize + u16_at(header, 32) as usize;
+        let local = u32_at(header, 42) as usize;
+        let name = data.get(pos + 46..pos + 46 + name_len).ok_or(ErrorKind::UnexpectedEof)?;
+        pos += 46 + name_len + skip;
+
+        let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+        if !ROM_EXTENSIONS.iter().any(|ext| name.ends_with(ext)) {
+            continue;
+        }
+        if len > MAX_ROM_LEN {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let rom = match method {
+            0 => entry(data, local, compressed)?.to_vec(),
+            /* Stops at the size the directory declares, so a bomb fails early */
+            8 => inflate(entry(data, local, compressed)?, len)?,
+            _ => return Err(GbError::Unsupported(format!("zip compression method {}", method))),
+        };
+        if rom.len() != len || crc32(rom.iter().copied()) != crc {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        return Ok(rom);
+    }
+    Err(ErrorKind::NotFound.into())
+}
+
+/* The compressed bytes of the entry whose local header is at `local`.
+ * Sizes come from the central directory since the local header's may be
+ * left zero when a data descriptor follows. */
+fn entry(data: &[u8], local: usize, compressed: usize) -> Result<&[u8], ErrorKind> {
+    let header = data.get(local..local + 30).ok_or(ErrorKind::UnexpectedEof)?;
+    if u32_at(header, 0) != LOCAL_HEADER {
+        return Err(ErrorKind::InvalidData);
+    }
+    let start = local + 30 + u16_at(header, 26) as usize + u16_at(header, 28) as usize;
+    data.get(start..start + compressed).ok_or(ErrorKind::UnexpectedEof)
+}
+
+/* The end record sits before a comment of up to 64kB, so search back */
+fn find_end(data: &[u8]) -> Option<usize> {
+    let last = data.len().checked_sub(END_LEN)?;
+    let first = last.saturating_sub(0xFFFF);
+    (first..=last).rev().find(|&pos| u32_at(data, pos) == END_OF_DIRECTORY)
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
@@ -1,18 +1,94 @@
+use std::{fs, io::ErrorKind, path::Path};
+
+use crate::{error::prelude::GbError, ppu::image::crc32};
+
+pub const DMG_BOOT_LEN: usize = 0x100;
+pub const CGB_BOOT_LEN: usize = 0x900;
+
+/* CRC32s of the known official dumps */
+const KNOWN_BOOT_ROMS: [(u32, &str); 6] = [
+    (0xC2F5CC97, "DMG0"),
+    (0x59C8598E, "DMG"),
+    (0xE6920754, "MGB"),
+    (0xEC8A83B9, "SGB"),
+    (0x53D0DD63, "SGB2"),
+    (0x41884E46, "CGB"),
+];
+
 pub static BOOT_ROM: [u8; 256] = [
-    0xfe, 0x31, 0xaf, 0xff, 0xff, 0x21, 0x32, 0x9f, 0x7c, 0xcb, 0xfb, 0x20, 0x26, 0x21, 0x0e, 0xff,
-    0x3e, 0x11, 0x32, 0x80, 0x0c, 0xe2, 0xf3, 0x3e, 0x32, 0xe2, 0x77, 0x3e, 0x3e, 0x77, 0xe0, 0xfc,
-    0x11, 0x47, 0x01, 0x04, 0x10, 0x21, 0x1a, 0x80, 0x95, 0xcd, 0xcd, 0x00, 0x00, 0x96, 0x7b, 0x13,
-    0x34, 0xfe, 0xf3, 0x20, 0xd8, 0x11, 0x06, 0x00, 0x1a, 0x08, 0x22, 0x13, 0x05, 0x23, 0xf9, 0x20,
-    0x19, 0x3e, 0x10, 0xea, 0x21, 0x99, 0x99, 0x2f, 0x0c, 0x0e, 0x28, 0x3d, 0x32, 0x08, 0x20, 0x0d,
-    0x2e, 0xf9, 0x18, 0x0f, 0x67, 0xf3, 0x64, 0x3e, 0xe0, 0x57, 0x3e, 0x42, 0xe0, 0x91, 0x04, 0x40,
-    0x02, 0x1e, 0x0c, 0x0e, 0x44, 0xf0, 0x90, 0xfe, 0xfa, 0x20, 0x20, 0x0d, 0x1d, 0xf7, 0xf2, 0x20,
-    0x13, 0x0e, 0x7c, 0x24, 0x83, 0x1e, 0x62, 0xfe, 0x06, 0x28, 0xc1, 0x1e, 0x64, 0xfe, 0x06, 0x20,
-    0xe2, 0x7b, 0x3e, 0x0c, 0xe2, 0x87, 0x42, 0xf0, 0xe0, 0x90, 0x15, 0x42, 0xd2, 0x20, 0x20, 0x05,
-    0x16, 0x4f, 0x18, 0x20, 0x4f, 0xcb, 0x04, 0x06, 0xcb, 0xc5, 0x17, 0x11, 0xcb, 0xc1, 0x17, 0x11,
-    0x20, 0x05, 0x22, 0xf5, 0x22, 0x23, 0xc9, 0x23, 0xed, 0xce, 0x66, 0x66, 0x0d, 0xcc, 0x0b, 0x00,
-    0x73, 0x03, 0x83, 0x00, 0x0c, 0x00, 0x0d, 0x00, 0x08, 0x00, 0x1f, 0x11, 0x89, 0x88, 0x0e, 0x00,
-    0xcc, 0xdc, 0xe6, 0x6e, 0xdd, 0xdd, 0x99, 0xd9, 0xbb, 0xbb, 0x63, 0x67, 0x0e, 0x6e, 0xcc, 0xec,
-    0xdc, 0xdd, 0x9f, 0x99, 0xb9, 0xbb, 0x3e, 0x33, 0x42, 0x3c, 0xa5, 0xb9, 0xa5, 0xb9, 0x3c, 0x42,
-    0x04, 0x21, 0x11, 0x01, 0x00, 0xa8, 0x13, 0x1a, 0x20, 0xbe, 0x23, 0xfe, 0xfe, 0x7d, 0x20, 0x34,
-    0x06, 0xf5, 0x78, 0x19, 0x23, 0x86, 0x20, 0x05, 0x86, 0xfb, 0xfe, 0x20, 0x01, 0x3e, 0x50, 0xe0,
+    0x31, 0xfe, 0xff, 0xaf, 0x21, 0xff, 0x9f, 0x32, 0xcb, 0x7c, 0x20, 0xfb, 0x21, 0x26, 0xff, 0x0e,
+    0x11, 0x3e, 0x80, 0x32, 0xe2, 0x0c, 0x3e, 0xf3, 0xe2, 0x32, 0x3e, 0x77, 0x77, 0x3e, 0xfc, 0xe0,
+    0x47, 0x11, 0x04, 0x01, 0x21, 0x10, 0x80, 0x1a, 0xcd, 0x95, 0x00, 0xcd, 0x96, 0x00, 0x13, 0x7b,
+    0xfe, 0x34, 0x20, 0xf3, 0x11, 0xd8, 0x00, 0x06, 0x08, 0x1a, 0x13, 0x22, 0x23, 0x05, 0x20, 0xf9,
+    0x3e, 0x19, 0xea, 0x10, 0x99, 0x21, 0x2f, 0x99, 0x0e, 0x0c, 0x3d, 0x28, 0x08, 0x32, 0x0d, 0x20,
+    0xf9, 0x2e, 0x0f, 0x18, 0xf3, 0x67, 0x3e, 0x64, 0x57, 0xe0, 0x42, 0x3e, 0x91, 0xe0, 0x40, 0x04,
+    0x1e, 0x02, 0x0e, 0x0c, 0xf0, 0x44, 0xfe, 0x90, 0x20, 0xfa, 0x0d, 0x20, 0xf7, 0x1d, 0x20, 0xf2,
+    0x0e, 0x13, 0x24, 0x7c, 0x1e, 0x83, 0xfe, 0x62, 0x28, 0x06, 0x1e, 0xc1, 0xfe, 0x64, 0x20, 0x06,
+    0x7b, 0xe2, 0x0c, 0x3e, 0x87, 0xe2, 0xf0, 0x42, 0x90, 0xe0, 0x42, 0x15, 0x20, 0xd2, 0x05, 0x20,
+    0x4f, 0x16, 0x20, 0x18, 0xcb, 0x4f, 0x06, 0x04, 0xc5, 0xcb, 0x11, 0x17, 0xc1, 0xcb, 0x11, 0x17,
+    0x05, 0x20, 0xf5, 0x22, 0x23, 0x22, 0x23, 0xc9, 0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b,
+    0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d, 0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e,
+    0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99, 0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc,
+    0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e, 0x3c, 0x42, 0xb9, 0xa5, 0xb9, 0xa5, 0x42, 0x3c,
+    0x21, 0x04, 0x01, 0x11, 0xa8, 0x00, 0x1a, 0x13, 0xbe, 0x20, 0xfe, 0x23, 0x7d, 0xfe, 0x34, 0x20,
+    0xf5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xfb, 0x86, 0x20, 0xfe, 0x3e, 0x01, 0xe0, 0x50,
 ];
+
+/* A boot ROM image, mapped over the cart from $0000 until the write to
+ * $FF50. CGB images leave $0100-$01FF to the cart header. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn embedded() -> Self {
+        Self { data: BOOT_ROM.to_vec() }
+    }
+
+    /* Only the size is enforced so that homebrew replacements load too */
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, GbError> {
+        match data.len() {
+            DMG_BOOT_LEN | CGB_BOOT_LEN => Ok(Self { data }),
+            _ => Err(GbError::Io(ErrorKind::InvalidData)),
+        }
+    }
+
+    #[cfg(feature = "io")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, GbError> {
+        Self::from_bytes(fs::read(path)?)
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_BOOT_LEN
+    }
+
+    /* Model name when the checksum matches an official dump */
+    pub fn model(&self) -> Option<&'static str> {
+        let crc = crc32(self.data.iter().copied());
+        KNOWN_BOOT_ROMS.iter().find(|&&(known, _)| known == crc).map(|&(_, name)| name)
+    }
+
+    /* The byte at `addr` if the boot ROM covers it */
+    pub fn get(&self, addr: usize) -> Option<&u8> {
+        match addr {
+            0x100..=0x1FF => None,
+            _ => self.data.get(addr),
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/* What the console does on power-up */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Boot {
+    /* Run the built-in DMG boot ROM */
+    #[default]
+    Embedded,
+    Rom(BootRom),
+    /* Start at $0100 with registers as the DMG boot ROM leaves them */
+    Skip,
+}
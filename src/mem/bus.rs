@@ -0,0 +1,99 @@
+use std::ops::{Index, IndexMut};
+
+/* Everything the CPU needs from the address space. `Mem` is the real
+ * console; `FlatBus` is a bare 64kB array for fuzzing and CPU tests. */
+pub trait Bus {
+    fn get_u8(&self, addr: u16) -> u8;
+    fn set_u8(&mut self, addr: u16, value: u8);
+
+    /* A read made by the CPU, which may first bring peripherals up to date */
+    fn read(&mut self, addr: u16) -> u8 {
+        self.get_u8(addr)
+    }
+
+    /* A read from PC, `opcode` for the first byte of an instruction */
+    fn fetch(&mut self, addr: u16, opcode: bool) -> u8 {
+        self.read(addr)
+    }
+
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        self.read(addr) as u16 | ((self.read(addr.wrapping_add(1)) as u16) << 8)
+    }
+
+    fn get_u16(&self, addr: u16) -> u16 {
+        self.get_u8(addr) as u16 | ((self.get_u8(addr.wrapping_add(1)) as u16) << 8)
+    }
+
+    fn set_u16(&mut self, addr: u16, value: u16) {
+        self.set_u8(addr, (value & 0x00FF) as u8);
+        self.set_u8(addr.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    /* Read without side effects or hooks, for CPU bookkeeping and debuggers */
+    fn peek(&self, addr: u16) -> u8 {
+        self.get_u8(addr)
+    }
+
+    /* Advance any peripherals by `cycles` M-cycles; they may lag behind
+     * until `sync` or until something they do becomes visible */
+    fn tick(&mut self, cycles: usize) {}
+
+    fn sync(&mut self) {}
+
+    /* M-cycles that can pass before any peripheral does something the CPU
+     * would notice, None if nothing is pending */
+    fn until_event(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    /* A 16-bit inc/dec put `addr` on the bus without reading or writing it */
+    fn idu_access(&mut self, addr: u16) {}
+
+    /* STOP with a CGB speed switch armed; true if the speed changed */
+    fn speed_switch(&mut self) -> bool {
+        false
+    }
+
+    fn double_speed(&self) -> bool {
+        false
+    }
+
+    /* Entering stop mode clears the divider */
+    fn reset_div(&mut self) {}
+
+    /* The ROM bank mapped at `addr`, for profilers and debuggers */
+    fn rom_bank_at(&self, addr: u16) -> Option<usize> {
+        None
+    }
+}
+
+/* Flat, fully writable RAM with no peripherals */
+pub struct FlatBus {
+    pub data: Box<[u8; 0x10000]>,
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self { data: Box::new([0; 0x10000]) }
+    }
+}
+
+impl FlatBus {
+    /* `image` is copied in from $0000 */
+    pub fn new(image: &[u8]) -> Self {
+        let mut bus = Self::default();
+        let len = image.len().min(bus.data.len());
+        bus.data[..len].copy_from_slice(&image[..len]);
+        bus
+    }
+}
+
+impl Bus for FlatBus {
+    fn get_u8(&self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+
+    fn set_u8(&mut self, addr: u16, value: u8) {
+        self.data[addr as usize] = value;
+    }
+}
@@ -0,0 +1,232 @@
+use std::io::ErrorKind;
+
+use crate::state::prelude::{Savestate, StateReader, StateWriter};
+
+/* The Pocket Camera's sensor, a Mitsubishi M64282FP behind the cart's own
+ * mapper. With bit 4 of the RAM bank register set, $A000-$BFFF holds its
+ * registers instead of RAM:
+ *
+ *   $A000     bit 0 starts a capture and reads back set until it is done
+ *   $A001     bit 7 N, bits 5-6 VH, bits 0-4 gain
+ *   $A002-3   exposure time, big endian
+ *   $A004-5   edge and offset settings
+ *   $A006-35  4x4 dither matrix, three thresholds per position
+ *
+ * mirrored every $80 bytes. A capture is written to RAM bank 0 at $A100
+ * as 16x14 tiles. Gain, edge enhancement and offset are not emulated; the
+ * image is only scaled by exposure and dithered through the matrix. */
+
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+/* Where in cart RAM a capture goes */
+pub const IMAGE_OFFSET: usize = 0x100;
+pub const IMAGE_LEN: usize = SENSOR_WIDTH * SENSOR_HEIGHT / 4;
+
+const REGISTERS: usize = 0x36;
+const MATRIX: usize = 0x06;
+/* Exposure at which the sensor's light is taken as is */
+const EXPOSURE_UNITY: u32 = 0x0800;
+
+/* Where the camera's pictures come from: SENSOR_WIDTH x SENSOR_HEIGHT
+ * luminance bytes, 0 black, asked for once per capture. */
+pub trait Sensor {
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+/* The same picture every time, grey until a frontend sets one */
+#[derive(Debug, Clone)]
+pub struct StillImage(pub Vec<u8>);
+
+impl Default for StillImage {
+    fn default() -> Self {
+        Self(vec![0x80; SENSOR_WIDTH * SENSOR_HEIGHT])
+    }
+}
+
+impl StillImage {
+    /* Packed RGB888 of any size, scaled to cover the sensor and cropped */
+    pub fn from_rgb(rgb: &[u8], width: usize, height: usize) -> Self {
+        /* The larger scale, so the short side fills the sensor */
+        let (scale_num, scale_den) = match width * SENSOR_HEIGHT > height * SENSOR_WIDTH {
+            true => (height, SENSOR_HEIGHT),
+            false => (width, SENSOR_WIDTH),
+        };
+        let (left, top) = ((width - SENSOR_WIDTH * scale_num / scale_den) / 2, (height - SENSOR_HEIGHT * scale_num / scale_den) / 2);
+        let mut luma = Vec::with_capacity(SENSOR_WIDTH * SENSOR_HEIGHT);
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let (sx, sy) = (left + x * scale_num / scale_den, top + y * scale_num / scale_den);
+                let p = &rgb[(sy * width + sx) * 3..][..3];
+                luma.push(((p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000) as u8);
+            }
+        }
+        Self(luma)
+    }
+}
+
+impl Sensor for StillImage {
+    fn capture(&mut self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+pub struct Camera {
+    regs: [u8; REGISTERS],
+    /* M-cycles left of the capture in progress */
+    remaining: usize,
+    sensor: Box<dyn Sensor>,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self { regs: [0; REGISTERS], remaining: 0, sensor: Box::new(StillImage::default()) }
+    }
+}
+
+impl Camera {
+    pub fn set_sensor(&mut self, sensor: Box<dyn Sensor>) {
+        self.sensor = sensor;
+    }
+
+    pub fn capturing(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /* Only $A000 reads back; the rest are write-only and read as 0 */
+    pub fn register(&self, addr: usize) -> &u8 {
+        match addr & 0x7F {
+            0 => &self.regs[0],
+            _ => &0x00,
+        }
+    }
+
+    pub fn write(&mut self, addr: usize, value: u8) {
+        match addr & 0x7F {
+            0 => {
+                self.regs[0] = value & 0x07;
+                if value & 0x01 != 0 && !self.capturing() {
+                    self.remaining = self.capture_cycles();
+                }
+            },
+            reg @ 1..REGISTERS => self.regs[reg] = value,
+            _ => {},
+        }
+    }
+
+    /* As measured on hardware: longer with more exposure, and 512 more
+     * without the N bit */
+    fn capture_cycles(&self) -> usize {
+        let n = self.regs[1] & 0x80 != 0;
+        32446 + if n { 0 } else { 512 } + 16 * self.exposure() as usize
+    }
+
+    fn exposure(&self) -> u32 {
+        u16::from_be_bytes([self.regs[2], self.regs[3]]) as u32
+    }
+
+    /* M-cycles until a capture finishes */
+    pub fn cycles_to_event(&self) -> Option<usize> {
+        self.capturing().then_some(self.remaining)
+    }
+
+    /* Advance by `cycles` M-cycles, returning the tiles of a capture that
+     * finished, for RAM at IMAGE_OFFSET */
+    pub fn tick(&mut self, cycles: usize) -> Option<Vec<u8>> {
+        if !self.capturing() {
+            return None;
+        }
+        self.remaining = self.remaining.saturating_sub(cycles);
+        if self.capturing() {
+            return None;
+        }
+        self.regs[0] &= !0x01;
+        let luma = self.sensor.capture();
+        Some(self.process(&luma))
+    }
+
+    /* Each pixel against the three thresholds for its place in the
+     * matrix: under the first is black, past the last is white */
+    fn process(&self, luma: &[u8]) -> Vec<u8> {
+        let mut tiles = vec![0; IMAGE_LEN];
+        let exposure = self.exposure();
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let light = luma.get(y * SENSOR_WIDTH + x).copied().unwrap_or(0) as u32;
+                let value = (light * exposure / EXPOSURE_UNITY).min(0xFF) as u8;
+                let at = MATRIX + ((y % 4) * 4 + x % 4) * 3;
+                let color = 3 - self.regs[at..at + 3].iter().take_while(|&&threshold| value >= threshold).count() as u8;
+                let byte = ((y / 8) * (SENSOR_WIDTH / 8) + x / 8) * 16 + (y % 8) * 2;
+                let bit = 7 - x % 8;
+                tiles[byte] |= (color & 0x01) << bit;
+                tiles[byte + 1] |= (color >> 1) << bit;
+            }
+        }
+        tiles
+    }
+}
+
+impl Savestate for Camera {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.regs);
+        w.u32(self.remaining as u32);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        r.bytes(&mut self.regs)?;
+        self.remaining = r.u32()? as usize;
+        Ok(())
+    }
+}
+
+/* Live frames from a capture program writing raw SENSOR_WIDTH x
+ * SENSOR_HEIGHT 8-bit grey frames to its stdout, such as ffmpeg reading a
+ * webcam. A thread keeps the newest frame; until the first arrives the
+ * sensor sees grey. */
+#[cfg(feature = "webcam")]
+pub struct Webcam {
+    latest: std::sync::Arc<std::sync::Mutex<Vec<u8>>>,
+    child: std::process::Child,
+}
+
+#[cfg(feature = "webcam")]
+impl Webcam {
+    pub fn spawn(program: &str, args: &[String]) -> std::io::Result<Self> {
+        use std::{io::Read, process::{Command, Stdio}, sync::{Arc, Mutex}};
+
+        let mut child = Command::new(program).args(args).stdout(Stdio::piped()).stderr(Stdio::null()).spawn()?;
+        let mut stdout = child.stdout.take().ok_or(ErrorKind::BrokenPipe)?;
+        let latest = Arc::new(Mutex::new(StillImage::default().0));
+        let shared = latest.clone();
+        std::thread::spawn(move || {
+            let mut frame = vec![0; SENSOR_WIDTH * SENSOR_HEIGHT];
+            while stdout.read_exact(&mut frame).is_ok() {
+                if let Ok(mut latest) = shared.lock() {
+                    latest.copy_from_slice(&frame);
+                }
+            }
+        });
+        Ok(Self { latest, child })
+    }
+
+    /* ffmpeg reading a V4L2 device, e.g. /dev/video0, cropped and scaled */
+    pub fn ffmpeg(device: &str) -> std::io::Result<Self> {
+        let filter = format!("scale={0}:{1}:force_original_aspect_ratio=increase,crop={0}:{1}", SENSOR_WIDTH, SENSOR_HEIGHT);
+        let args = ["-loglevel", "quiet", "-f", "v4l2", "-i", device, "-vf", &filter, "-pix_fmt", "gray", "-f", "rawvideo", "-"];
+        Self::spawn("ffmpeg", &args.map(String::from))
+    }
+}
+
+#[cfg(feature = "webcam")]
+impl Sensor for Webcam {
+    fn capture(&mut self) -> Vec<u8> {
+        self.latest.lock().map(|latest| latest.clone()).unwrap_or_else(|_| StillImage::default().0)
+    }
+}
+
+#[cfg(feature = "webcam")]
+impl Drop for Webcam {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
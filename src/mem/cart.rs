@@ -1,10 +1,13 @@
-use std::{cell::RefCell, error::Error, fs::File, io::Read};
+use std::{cell::RefCell, error::Error, fmt::{self, Display}, fs::File, io::Read};
 
 pub use std::io::ErrorKind;
 
+use crate::{error::prelude::GbError, info, ppu::image::crc32, warn};
+
 use self::types::CartHeader;
+use super::{archive::{extract_rom, is_zip}, controller::Controller};
 
-static NINTENDO_GRAPHIC: [u8; 48] = [
+pub static NINTENDO_GRAPHIC: [u8; 48] = [
     0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 
     0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D, 
     0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 
@@ -16,11 +19,15 @@ static NINTENDO_GRAPHIC: [u8; 48] = [
 pub mod types {
     use std::mem::MaybeUninit;
 
-    use super::NINTENDO_GRAPHIC;
+    use super::{HeaderError, NINTENDO_GRAPHIC};
 
+    /* $0100-$014F, one field per byte range. `title` is the full 16 bytes
+     * up to $0143; on CGB carts the last of them is the CGB flag and the
+     * four before may be a manufacturer code, see `title_str`. */
+    #[derive(Clone)]
     pub struct CartHeader {
         pub entry_point: [u8; 4],
-        pub nintendo_graphic: &'static [u8],
+        pub nintendo_graphic: [u8; 48],
         pub title: [u8; 16],
         pub color_type: CartColorType,
         pub licensee: u16,
@@ -36,31 +43,86 @@ pub mod types {
     }
 
     impl CartHeader {
-        pub fn new(data: &[u8]) -> Self {
+        /* Only the ROM and RAM size codes can make a header unusable;
+         * every other field takes whatever byte it finds */
+        pub fn parse(data: &[u8]) -> Result<Self, HeaderError> {
+            if data.len() < 0x150 {
+                return Err(HeaderError::TooShort(data.len()));
+            }
             let mut s = Self {
                 entry_point: [0; 4],
-                nintendo_graphic: &NINTENDO_GRAPHIC,
+                nintendo_graphic: [0; 48],
                 title: [0; 16],
                 color_type: CartColorType::from(data[0x143]),
                 licensee: ((data[0x144] as u16) << 8) | data[0x145] as u16,
                 console_indicator: ConsoleIndicator::from(data[0x146]),
                 cart_type: CartType::from(data[0x147]),
-                rom_size: RomSize::from(data[0x148]),
-                ram_size: RamSize::from(data[0x149]),
+                rom_size: RomSize::try_from(data[0x148]).map_err(HeaderError::RomSize)?,
+                ram_size: RamSize::try_from(data[0x149]).map_err(HeaderError::RamSize)?,
                 destination_code: DestinationCode::from(data[0x14A]),
                 old_licensee_code: OldLicenseeCode::from(data[0x14B]),
                 mask_rom_version: data[0x14C],
                 compliment_check: data[0x14D],
                 checksum: ((data[0x14E] as u16) << 8) | data[0x14F] as u16,
             };
-            s.entry_point.clone_from_slice(&data[0x100..=0x104]);
-            s.entry_point.clone_from_slice(&data[0x134..=0x142]);
-            s
+            s.entry_point.copy_from_slice(&data[0x100..0x104]);
+            s.nintendo_graphic.copy_from_slice(&data[0x104..0x134]);
+            s.title.copy_from_slice(&data[0x134..0x144]);
+            Ok(s)
+        }
+
+        pub fn logo_valid(&self) -> bool {
+            self.nintendo_graphic == NINTENDO_GRAPHIC
+        }
+
+        pub fn is_cgb(&self) -> bool {
+            self.color_type != CartColorType::Other
+        }
+
+        /* The SGB only honours the flag when the old licensee defers to the new one */
+        pub fn supports_sgb(&self) -> bool {
+            self.console_indicator == ConsoleIndicator::SuperGameBoy
+                && self.old_licensee_code == OldLicenseeCode::CheckLicenseeCode
+        }
+
+        /* Taken as present when a CGB cart's 11-byte title is NUL padded
+         * and the four bytes after it are uppercase ASCII or digits */
+        pub fn manufacturer_code(&self) -> Option<&str> {
+            let code = &self.title[11..15];
+            let is_code = self.is_cgb() && self.title[10] == 0
+                && code.iter().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+            if is_code { std::str::from_utf8(code).ok() } else { None }
+        }
+
+        /* The title up to the first NUL or non-ASCII byte, with trailing
+         * spaces dropped; excludes the CGB flag and manufacturer code */
+        pub fn title_str(&self) -> &str {
+            let len = match (self.is_cgb(), self.manufacturer_code()) {
+                (_, Some(_)) => 11,
+                (true, None) => 15,
+                (false, None) => 16,
+            };
+            let title = &self.title[..len];
+            let end = title.iter().position(|&byte| byte == 0 || !byte.is_ascii()).unwrap_or(len);
+            std::str::from_utf8(&title[..end]).unwrap_or_default().trim_end()
+        }
+
+        /* Two ASCII characters for new codes, two hex digits for old ones */
+        pub fn licensee_code(&self) -> String {
+            match self.old_licensee_code {
+                OldLicenseeCode::CheckLicenseeCode => {
+                    self.licensee.to_be_bytes().iter().map(|&byte| if byte.is_ascii_graphic() { byte as char } else { '?' }).collect()
+                },
+                code => format!("{:02X}", code.code()),
+            }
         }
     }
 
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum CartColorType {
+        /* Runs on both, with CGB enhancements */
         GameBoyColor,
+        ColorOnly,
         Other,
     }
 
@@ -68,27 +130,29 @@ pub mod types {
         fn from(value: u8) -> Self {
             match value {
                 0x80 => Self::GameBoyColor,
+                0xC0 => Self::ColorOnly,
                 _ => Self::Other,
             }
         }
     }
 
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum ConsoleIndicator {
         GameBoy,
         SuperGameBoy,
     }
 
+    /* The SGB BIOS only looks for $03 */
     impl From<u8> for ConsoleIndicator {
         fn from(value: u8) -> Self {
             match value {
-                0x00 => Self::GameBoy,
                 0x03 => Self::SuperGameBoy,
-                _ => panic!("Unrecognized Console Indicator byte `${:#02X}` at position `$0146`", value),
+                _ => Self::GameBoy,
             }
         }
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum CartType {
         RomOnly,
         RomMbc1,
@@ -116,68 +180,137 @@ pub mod types {
         BandaiTama5,
         HudsonHuC3,
         HudsonHuC1,
+        Unknown(u8),
     }
 
     impl From<u8> for CartType {
         fn from(value: u8) -> Self {
             match value {
-                0x01 => Self::RomOnly,
-                0x02 => Self::RomMbc1,
-                0x03 => Self::RomMbc1Ram,
-                0x05 => Self::RomMbc1RamBatt,
-                0x06 => Self::RomMbc2,
-                0x08 => Self::RomMbc2Batt,
-                0x09 => Self::RomRam,
-                0x0B => Self::RomRamBatt,
-                0x0C => Self::RomMmmo1,
-                0x0D => Self::RomMmmo1Sram,
-                0x0F => Self::RomMmmo1SramBatt,
-                0x10 => Self::RomMbc3TimerBatt,
-                0x11 => Self::RomMbc3TimerRamBatt,
-                0x12 => Self::RomMbc3,
-                0x13 => Self::RomMbc3Ram,
-                0x19 => Self::RomMbc3RamBatt,
-                0x1A => Self::RomMbc5,
-                0x1B => Self::RomMbc5Ram,
-                0x1C => Self::RomMbc5RamBatt,
-                0x1D => Self::RomMbc5Rumble,
-                0x1E => Self::RomMbc5RumbleSram,
-                0x1F => Self::RomMbc5RumbleSramBatt,
-                0xFD => panic!("Cartridge Type `$FD` not supported"),
-                0xFE => panic!("Cartridge Type `$FE` not supported"),
-                0xFF => panic!("Cartridge Type `$FF` not supported"),
-                _ => panic!("Unrecognized Cartridge Type `${:#02X}` at position `$0147`", value),
+                0x00 => Self::RomOnly,
+                0x01 => Self::RomMbc1,
+                0x02 => Self::RomMbc1Ram,
+                0x03 => Self::RomMbc1RamBatt,
+                0x05 => Self::RomMbc2,
+                0x06 => Self::RomMbc2Batt,
+                0x08 => Self::RomRam,
+                0x09 => Self::RomRamBatt,
+                0x0B => Self::RomMmmo1,
+                0x0C => Self::RomMmmo1Sram,
+                0x0D => Self::RomMmmo1SramBatt,
+                0x0F => Self::RomMbc3TimerBatt,
+                0x10 => Self::RomMbc3TimerRamBatt,
+                0x11 => Self::RomMbc3,
+                0x12 => Self::RomMbc3Ram,
+                0x13 => Self::RomMbc3RamBatt,
+                0x19 => Self::RomMbc5,
+                0x1A => Self::RomMbc5Ram,
+                0x1B => Self::RomMbc5RamBatt,
+                0x1C => Self::RomMbc5Rumble,
+                0x1D => Self::RomMbc5RumbleSram,
+                0x1E => Self::RomMbc5RumbleSramBatt,
+                0xFC => Self::PocketCamera,
+                0xFD => Self::BandaiTama5,
+                0xFE => Self::HudsonHuC3,
+                0xFF => Self::HudsonHuC1,
+                _ => Self::Unknown(value),
+            }
+        }
+    }
+
+    impl CartType {
+        /* As printed in the usual header references */
+        pub fn name(&self) -> &'static str {
+            match self {
+                Self::RomOnly => "ROM ONLY",
+                Self::RomMbc1 => "MBC1",
+                Self::RomMbc1Ram => "MBC1+RAM",
+                Self::RomMbc1RamBatt => "MBC1+RAM+BATTERY",
+                Self::RomMbc2 => "MBC2",
+                Self::RomMbc2Batt => "MBC2+BATTERY",
+                Self::RomRam => "ROM+RAM",
+                Self::RomRamBatt => "ROM+RAM+BATTERY",
+                Self::RomMmmo1 => "MMM01",
+                Self::RomMmmo1Sram => "MMM01+RAM",
+                Self::RomMmmo1SramBatt => "MMM01+RAM+BATTERY",
+                Self::RomMbc3TimerBatt => "MBC3+TIMER+BATTERY",
+                Self::RomMbc3TimerRamBatt => "MBC3+TIMER+RAM+BATTERY",
+                Self::RomMbc3 => "MBC3",
+                Self::RomMbc3Ram => "MBC3+RAM",
+                Self::RomMbc3RamBatt => "MBC3+RAM+BATTERY",
+                Self::RomMbc5 => "MBC5",
+                Self::RomMbc5Ram => "MBC5+RAM",
+                Self::RomMbc5RamBatt => "MBC5+RAM+BATTERY",
+                Self::RomMbc5Rumble => "MBC5+RUMBLE",
+                Self::RomMbc5RumbleSram => "MBC5+RUMBLE+RAM",
+                Self::RomMbc5RumbleSramBatt => "MBC5+RUMBLE+RAM+BATTERY",
+                Self::PocketCamera => "POCKET CAMERA",
+                Self::BandaiTama5 => "BANDAI TAMA5",
+                Self::HudsonHuC3 => "HuC3",
+                Self::HudsonHuC1 => "HuC1+RAM+BATTERY",
+                Self::Unknown(_) => "UNKNOWN",
             }
         }
+
+        pub fn has_battery(&self) -> bool {
+            self.name().contains("BATTERY")
+        }
+
+        pub fn has_rtc(&self) -> bool {
+            self.name().contains("TIMER")
+        }
+
+        pub fn has_rumble(&self) -> bool {
+            self.name().contains("RUMBLE")
+        }
     }
 
+    /* Sizes in kB */
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub struct RomSize(u32);
 
-    impl From<u8> for RomSize {
-        fn from(value: u8) -> Self {
+    impl TryFrom<u8> for RomSize {
+        type Error = u8;
+
+        fn try_from(value: u8) -> Result<Self, u8> {
             match value {
-                0..=6 => Self(1 << (5 + value)),
-                0x52..=0x54 => panic!("Unsupported ROM Size: `${:#02X}`", value),
-                _ => panic!("Unrecognized ROM Size: `${:#02X}`", value),
+                0..=8 => Ok(Self(32 << value)),
+                _ => Err(value),
             }
         }
     }
 
+    impl RomSize {
+        pub fn bytes(&self) -> usize {
+            self.0 as usize * 1024
+        }
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub struct RamSize(u32);
 
-    impl From<u8> for RamSize {
-        fn from(value: u8) -> Self {
+    impl TryFrom<u8> for RamSize {
+        type Error = u8;
+
+        fn try_from(value: u8) -> Result<Self, u8> {
             match value {
-                0 => Self(0),
-                1 => Self(2),
-                2 => Self(8),
-                3 => Self(32),
-                4 => Self(128),
-                _ => panic!("Unrecognized RAM Size: `${:02X}`", value),
+                0 => Ok(Self(0)),
+                1 => Ok(Self(2)),
+                2 => Ok(Self(8)),
+                3 => Ok(Self(32)),
+                4 => Ok(Self(128)),
+                5 => Ok(Self(64)),
+                _ => Err(value),
             }
         }
     }
 
+    impl RamSize {
+        pub fn bytes(&self) -> usize {
+            self.0 as usize * 1024
+        }
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum DestinationCode {
         Japanese,
         NonJapanese,
@@ -187,16 +320,18 @@ pub mod types {
         fn from(value: u8) -> Self {
             match value {
                 0 => Self::Japanese,
-                1 => Self::NonJapanese,
-                _ => panic!("Expected Destination Code at `$014A` to be `$00` or `$01`, instead found `${:#02X}`", value),
+                _ => Self::NonJapanese,
             }
         }
     }
 
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub enum OldLicenseeCode {
+        /* The two ASCII bytes at $0144 hold the licensee instead */
         CheckLicenseeCode,
         Accolade,
         Konami,
+        Other(u8),
     }
 
     impl From<u8> for OldLicenseeCode {
@@ -205,34 +340,173 @@ pub mod types {
                 0x33 => Self::CheckLicenseeCode,
                 0x79 => Self::Accolade,
                 0xA4 => Self::Konami,
-                _ => panic!("Unrecognized Old Licensee Code at `$014B`: `${:02X}`", value),
+                _ => Self::Other(value),
+            }
+        }
+    }
+
+    impl OldLicenseeCode {
+        pub fn code(&self) -> u8 {
+            match self {
+                Self::CheckLicenseeCode => 0x33,
+                Self::Accolade => 0x79,
+                Self::Konami => 0xA4,
+                Self::Other(code) => *code,
             }
         }
     }
 }
 //}}}
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    TooShort(usize),
+    RomSize(u8),
+    RamSize(u8),
+    /* A raw binary running on to the given address, past the ROM at $7FFF */
+    PastRomEnd(usize),
+}
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort(len) => write!(f, "{} bytes is too short for a cartridge", len),
+            Self::RomSize(code) => write!(f, "unknown ROM size code ${:02X} at $0148", code),
+            Self::RamSize(code) => write!(f, "unknown RAM size code ${:02X} at $0149", code),
+            Self::PastRomEnd(end) => write!(f, "raw binary runs on to ${:X}, past the end of ROM", end),
+        }
+    }
+}
+
+impl Error for HeaderError {}
+
+/* The mapper-less hardware a raw binary runs on */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum RawMap {
+    /* 32kB of ROM and nothing at $A000-$BFFF */
+    #[default]
+    RomOnly,
+    /* The same with 8kB of RAM there */
+    RomRam,
+}
+
+impl RawMap {
+    /* The cart type and RAM size codes a header would give */
+    fn codes(self) -> (u8, u8) {
+        match self {
+            Self::RomOnly => (0x00, 0x00),
+            Self::RomRam => (0x08, 0x02),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Cart {
     pub data: Vec<u8>,
     pub data_len: usize,
     pub header: CartHeader,
+    /* Where a raw binary starts; None for a real cart, entered at $0100 */
+    pub raw_origin: Option<u16>,
 }
 
 impl Cart {
-    pub fn new(name: String) -> Result<Self, ErrorKind> {
-        let mut fs = match File::open(name) {
-            Ok(fs) => fs,
-            Err(_) => return Err(ErrorKind::PermissionDenied),
-        };
+    #[cfg(feature = "io")]
+    pub fn new(name: String) -> Result<Self, GbError> {
         let mut data: Vec<u8> = Vec::new();
-        if fs.read_to_end(&mut data).is_err() {
-            return Err(ErrorKind::PermissionDenied);
+        File::open(name)?.read_to_end(&mut data)?;
+        Self::from_bytes(data)
+    }
+
+    /* A cart that can run: a header, with an emulated mapper. A zip
+     * archive is opened and its first ROM loaded. Dumps shorter than
+     * their header says are padded out; see `pad`. */
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, GbError> {
+        let mut cart = Self::parse(data)?;
+        if Controller::for_cart(cart.header.cart_type).is_none() {
+            return Err(GbError::Unsupported(format!("{} cartridges", cart.header.cart_type.name())));
         }
+        cart.pad();
+        Ok(cart)
+    }
+
+    /* Header only, without the minimum size needed to run */
+    pub fn parse(data: Vec<u8>) -> Result<Self, GbError> {
+        let data = unzip(data)?;
         let data_len = data.len();
-        let header = CartHeader::new(&data);
+        let header = CartHeader::parse(&data)?;
 
         Ok(Self {
-            data, data_len, header, 
+            data, data_len, header, raw_origin: None,
         })
     }
+
+    /* A headerless program at `origin` in an otherwise blank 32kB ROM, for
+     * CPU tests and homebrew fragments. The header is made up to suit
+     * `map` rather than read from the data, which may well cover it;
+     * `data_len` runs to the end of the program. */
+    pub fn raw(program: &[u8], origin: u16, map: RawMap) -> Result<Self, GbError> {
+        let end = origin as usize + program.len();
+        if end > 0x8000 {
+            return Err(GbError::Rom(HeaderError::PastRomEnd(end - 1)));
+        }
+        let (cart_type, ram_size) = map.codes();
+        let mut header = [0; 0x150];
+        header[0x134..0x137].copy_from_slice(b"RAW");
+        header[0x147] = cart_type;
+        header[0x149] = ram_size;
+
+        /* Unprogrammed flash reads back as $FF */
+        let mut data = vec![0xFF; 0x8000];
+        data[origin as usize..end].copy_from_slice(program);
+        Ok(Self {
+            data, data_len: end, header: CartHeader::parse(&header)?, raw_origin: Some(origin),
+        })
+    }
+
+    /* Fills the ROM out to the size in its header, and to whole 16kB banks,
+     * with $FF as unprogrammed ROM reads, so banking wraps as it would on
+     * the real chip. `data_len` keeps the size of the file. */
+    fn pad(&mut self) {
+        let len = self.data.len().max(self.header.rom_size.bytes()).next_multiple_of(0x4000);
+        if len == self.data.len() {
+            return;
+        }
+        if self.data.len() % 0x4000 != 0 {
+            warn!(target: "gbemu::mem", "ROM is {} bytes, not whole 16kB banks; padding with $FF", self.data.len());
+        } else {
+            info!(target: "gbemu::mem", "ROM is {} bytes but its header says {}; padding with $FF", self.data.len(), len);
+        }
+        self.data.resize(len, 0xFF);
+    }
+
+    /* CRC-32 of $0100-$014F as 8 hex digits, naming the game's
+     * `[games.ID]` section in the config */
+    pub fn header_hash(&self) -> String {
+        format!("{:08x}", crc32(self.data[0x100..0x150].iter().copied()))
+    }
+
+    /* What the boot ROM checks: $0134-$014C summed as x - byte - 1 */
+    pub fn header_checksum(&self) -> u8 {
+        self.data[0x134..0x14D].iter().fold(0_u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1))
+    }
+
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum() == self.header.compliment_check
+    }
+
+    /* Sum of every byte of the file but the checksum itself; nothing on
+     * hardware checks it */
+    pub fn global_checksum(&self) -> u16 {
+        self.data[..self.data_len].iter().enumerate()
+            .filter(|&(i, _)| i != 0x14E && i != 0x14F)
+            .fold(0_u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16))
+    }
+
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum() == self.header.checksum
+    }
+}
+
+fn unzip(data: Vec<u8>) -> Result<Vec<u8>, GbError> {
+    if is_zip(&data) { extract_rom(&data) } else { Ok(data) }
 }
@@ -1,15 +1,207 @@
-use super::cart::types::CartType;
+use std::io::ErrorKind;
 
+use crate::state::prelude::{Savestate, StateReader, StateWriter};
+
+use super::cart::{types::CartType, Cart, NINTENDO_GRAPHIC};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Controller {
+    None,
     MBC1,
+    /* MBC1 multicart: BANK2 wired one bit lower to pick a 256kB game */
+    MBC1M,
+    MBC2,
+    MBC3,
+    /* MBC3 with an eighth ROM bank bit and eight RAM banks */
+    MBC30,
+    MBC5,
+    /* $0000-$1FFF picks RAM or the IR port rather than gating RAM */
+    HuC1,
+    /* RAM bank bit 4 maps the camera sensor's registers instead of RAM */
+    PocketCamera,
+}
+
+impl Controller {
+    /* None for carts whose mapper is not emulated */
+    pub fn for_cart(cart_type: CartType) -> Option<Self> {
+        use CartType::*;
+        match cart_type {
+            RomOnly | RomRam | RomRamBatt => Some(Self::None),
+            RomMbc1 | RomMbc1Ram | RomMbc1RamBatt => Some(Self::MBC1),
+            RomMbc2 | RomMbc2Batt => Some(Self::MBC2),
+            RomMbc3 | RomMbc3Ram | RomMbc3RamBatt | RomMbc3TimerBatt | RomMbc3TimerRamBatt => Some(Self::MBC3),
+            RomMbc5 | RomMbc5Ram | RomMbc5RamBatt
+                | RomMbc5Rumble | RomMbc5RumbleSram | RomMbc5RumbleSramBatt => Some(Self::MBC5),
+            HudsonHuC1 => Some(Self::HuC1),
+            PocketCamera => Some(Self::PocketCamera),
+            _ => Option::None,
+        }
+    }
+
+    /* As `for_cart`, telling MBC1 multicarts apart by the header each
+     * game keeps at the start of its 256kB, and MBC30 by the ROM or RAM
+     * only it can address */
+    pub fn detect(cart: &Cart) -> Option<Self> {
+        match Self::for_cart(cart.header.cart_type)? {
+            Self::MBC1 if mbc1_multicart(&cart.data) => Some(Self::MBC1M),
+            Self::MBC3 if cart.data.len() > 0x200000 || cart.header.ram_size.bytes() > 0x8000 => Some(Self::MBC30),
+            controller => Some(controller),
+        }
+    }
+}
+
+/* Every known multicart is 1MB; one logo besides the menu's is enough,
+ * as no single game repeats its header mid-ROM */
+fn mbc1_multicart(rom: &[u8]) -> bool {
+    let logos = rom.chunks(0x40000)
+        .filter(|game| game.get(0x104..0x134).is_some_and(|logo| logo == NINTENDO_GRAPHIC))
+        .count();
+    rom.len() == 0x100000 && logos >= 2
 }
 
 impl From<CartType> for Controller {
     fn from(value: CartType) -> Self {
-        use CartType::*;
-        match value {
-            RomMbc1 | RomMbc1Ram | RomMbc1RamBatt => Self::MBC1,
-            _ => panic!("Unsupported CartType: {:?}", value),
+        Self::for_cart(value).unwrap_or_else(|| panic!("Unsupported CartType: {:?}", value))
+    }
+}
+
+/* MBC2 has 512 half-byte cells built in, regardless of the header */
+pub const MBC2_RAM_LEN: usize = 0x200;
+
+/* The bank registers written through $0000-$7FFF, kept as the raw values
+ * so the mapped banks can be recomputed against any ROM/RAM size. */
+#[derive(Debug)]
+pub struct Mbc {
+    pub controller: Controller,
+    ram_enabled: bool,
+    /* MBC1 BANK1, MBC2/3 ROMB, MBC5 ROMB0 and ROMB1 combined */
+    rom_bank: u16,
+    /* MBC1 BANK2, MBC3 RAM bank or RTC select, MBC5 RAMB */
+    ram_bank: u8,
+    /* MBC5 with a rumble motor, which takes RAMB bit 3 for itself */
+    pub rumble: bool,
+    /* MBC1 banking mode; routes BANK2 to $0000 and to RAM when set */
+    mode: bool,
+    rom_banks: usize,
+    ram_banks: usize,
+}
+
+impl Mbc {
+    pub fn new(controller: Controller, rom_len: usize, ram_len: usize) -> Self {
+        Self {
+            controller,
+            /* HuC1 powers up with RAM, not the IR port, selected */
+            ram_enabled: controller == Controller::HuC1,
+            rom_bank: 1,
+            ram_bank: 0,
+            rumble: false,
+            mode: false,
+            rom_banks: rom_len.div_ceil(0x4000).max(1),
+            ram_banks: ram_len.div_ceil(0x2000).max(1),
         }
     }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        use Controller::*;
+        match (self.controller, addr) {
+            (None, _) => {},
+            /* MBC2 decodes both registers in $0000-$3FFF on address bit 8 */
+            (MBC2, 0x0000..=0x3FFF) if addr & 0x0100 == 0 => self.ram_enabled = value & 0x0F == 0x0A,
+            (MBC2, 0x0000..=0x3FFF) => self.rom_bank = (value & 0x0F).max(1) as u16,
+            (HuC1, 0x0000..=0x1FFF) => self.ram_enabled = value & 0x0F != 0x0E,
+            (_, 0x0000..=0x1FFF) => self.ram_enabled = value & 0x0F == 0x0A,
+            (MBC1 | MBC1M, 0x2000..=0x3FFF) => self.rom_bank = (value & 0x1F).max(1) as u16,
+            (MBC3, 0x2000..=0x3FFF) => self.rom_bank = (value & 0x7F).max(1) as u16,
+            (MBC30, 0x2000..=0x3FFF) => self.rom_bank = value.max(1) as u16,
+            (HuC1 | PocketCamera, 0x2000..=0x3FFF) => self.rom_bank = (value & 0x3F) as u16,
+            (MBC5, 0x2000..=0x2FFF) => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            (MBC5, 0x3000..=0x3FFF) => self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 1) << 8),
+            (MBC1 | MBC1M, 0x4000..=0x5FFF) => self.ram_bank = value & 0x03,
+            (MBC3 | MBC30 | MBC5, 0x4000..=0x5FFF) => self.ram_bank = value & 0x0F,
+            (HuC1, 0x4000..=0x5FFF) => self.ram_bank = value & 0x03,
+            (PocketCamera, 0x4000..=0x5FFF) => self.ram_bank = value & 0x1F,
+            (MBC1 | MBC1M, 0x6000..=0x7FFF) => self.mode = value & 0x01 != 0,
+            /* The MBC3 RTC latch belongs to the clock, see `Rtc::write_latch` */
+            _ => {},
+        }
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        self.controller == Controller::None || self.ram_enabled
+    }
+
+    /* HuC1 with the IR port at $A000-$BFFF instead of RAM */
+    pub fn ir_selected(&self) -> bool {
+        self.controller == Controller::HuC1 && !self.ram_enabled
+    }
+
+    /* Bank mapped at $4000-$7FFF */
+    pub fn rom_bank(&self) -> usize {
+        let bank = match self.controller {
+            Controller::None => 1,
+            Controller::MBC1 => ((self.ram_bank as usize) << 5) | self.rom_bank as usize,
+            /* BANK1's top bit is still checked for zero, but not wired */
+            Controller::MBC1M => ((self.ram_bank as usize) << 4) | (self.rom_bank & 0x0F) as usize,
+            _ => self.rom_bank as usize,
+        };
+        bank % self.rom_banks
+    }
+
+    /* Bank mapped at $0000-$3FFF, only ever non-zero on MBC1 in mode 1 */
+    pub fn rom_bank0(&self) -> usize {
+        match self.controller {
+            Controller::MBC1 if self.mode => ((self.ram_bank as usize) << 5) % self.rom_banks,
+            Controller::MBC1M if self.mode => ((self.ram_bank as usize) << 4) % self.rom_banks,
+            _ => 0,
+        }
+    }
+
+    /* Bank mapped at $A000-$BFFF, None while MBC3 has an RTC register selected */
+    pub fn ram_bank(&self) -> Option<usize> {
+        let bank = match self.controller {
+            Controller::MBC1 | Controller::MBC1M if self.mode => self.ram_bank as usize,
+            Controller::MBC3 | Controller::MBC30 if self.ram_bank >= 0x08 => return Option::None,
+            Controller::MBC5 if self.rumble => (self.ram_bank & 0x07) as usize,
+            Controller::MBC3 | Controller::MBC30 | Controller::MBC5 | Controller::HuC1 => self.ram_bank as usize,
+            Controller::PocketCamera if self.ram_bank & 0x10 != 0 => return Option::None,
+            Controller::PocketCamera => self.ram_bank as usize,
+            _ => 0,
+        };
+        Some(bank % self.ram_banks)
+    }
+
+    /* Whether the rumble motor is spinning */
+    pub fn motor_on(&self) -> bool {
+        self.rumble && self.ram_bank & 0x08 != 0
+    }
+
+    /* The camera's registers at $A000-$BFFF instead of RAM */
+    pub fn camera_registers(&self) -> bool {
+        self.controller == Controller::PocketCamera && self.ram_bank & 0x10 != 0
+    }
+
+    /* RTC register mapped at $A000-$BFFF, if any */
+    pub fn rtc_register(&self) -> Option<usize> {
+        match self.ram_bank {
+            0x08..=0x0C if matches!(self.controller, Controller::MBC3 | Controller::MBC30) && self.ram_enabled => Some(self.ram_bank as usize - 0x08),
+            _ => Option::None,
+        }
+    }
+}
+
+impl Savestate for Mbc {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.ram_enabled);
+        w.u16(self.rom_bank);
+        w.u8(self.ram_bank);
+        w.bool(self.mode);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.ram_enabled = r.bool()?;
+        self.rom_bank = r.u16()?;
+        self.ram_bank = r.u8()?;
+        self.mode = r.bool()?;
+        Ok(())
+    }
 }
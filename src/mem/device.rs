@@ -0,0 +1,82 @@
+use std::ops::RangeInclusive;
+
+/* A peripheral behind part of $FF00-$FF7F. Mem keeps a map from each I/O
+ * address to the device claiming it and routes CPU accesses and elapsed
+ * time through here; what a write does beyond the device itself, such as
+ * starting a DMA or clocking the APU off DIV, stays with Mem. */
+pub trait MemoryMappedDevice {
+    /* The I/O addresses answered, with the CGB-only ones when `cgb` is set */
+    fn claims(&self, cgb: bool) -> &'static [RangeInclusive<u16>];
+
+    /* The register at `addr` as stored, without unused bits set */
+    fn io_register(&self, addr: u16) -> &u8;
+
+    /* Raw access to the register at `addr`, with no side effects */
+    fn io_register_mut(&mut self, addr: u16) -> &mut u8;
+
+    /* A CPU write to `addr`, returning the IF bits it raises */
+    fn io_write(&mut self, addr: u16, value: u8) -> u8;
+
+    /* Advance by `cycles` M-cycles of the device's clock, returning the IF
+     * bits to raise */
+    fn tick(&mut self, _cycles: usize) -> u8 {
+        0
+    }
+
+    /* Whether the device runs off the CPU clock, and so twice as fast in
+     * double speed, rather than the fixed-rate one */
+    fn cpu_clocked(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DeviceId {
+    Joypad,
+    Serial,
+    Timer,
+    Ppu,
+    Apu,
+    Dma,
+}
+
+impl DeviceId {
+    /* Also the order they are ticked in: the APU goes after the timer
+     * whose DIV clocks its frame sequencer */
+    pub const ALL: [Self; 6] = [Self::Joypad, Self::Serial, Self::Timer, Self::Ppu, Self::Apu, Self::Dma];
+}
+
+/* Which device answers each address in $FF00-$FF7F; the rest belong to
+ * the console itself or to nothing */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceMap([Option<DeviceId>; 0x80]);
+
+impl Default for DeviceMap {
+    fn default() -> Self {
+        Self([None; 0x80])
+    }
+}
+
+impl DeviceMap {
+    /* Two devices claiming one address is a wiring mistake, not something
+     * a game can cause */
+    pub fn claim(&mut self, id: DeviceId, ranges: &[RangeInclusive<u16>]) {
+        for addr in ranges.iter().cloned().flatten() {
+            let slot = &mut self.0[(addr - 0xFF00) as usize];
+            if let Some(other) = slot.filter(|&other| other != id) {
+                panic!("${:04X} claimed by both {:?} and {:?}", addr, other, id);
+            }
+            *slot = Some(id);
+        }
+    }
+
+    #[inline(always)]
+    pub fn get(&self, addr: u16) -> Option<DeviceId> {
+        self.0.get(addr.wrapping_sub(0xFF00) as usize).copied().flatten()
+    }
+
+    /* The addresses `id` answers, in order */
+    pub fn claimed_by(&self, id: DeviceId) -> impl Iterator<Item = u16> + '_ {
+        (0xFF00..=0xFF7F).filter(move |&addr| self.get(addr) == Some(id))
+    }
+}
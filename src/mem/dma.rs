@@ -0,0 +1,106 @@
+use std::{io::ErrorKind, ops::RangeInclusive};
+
+use crate::state::prelude::{StateReader, StateWriter};
+
+use super::device::MemoryMappedDevice;
+
+pub const OAM_DMA_LEN: u8 = 0xA0;
+
+/* An OAM DMA in flight: a cycle of setup after the $FF46 write, then one
+ * byte a CPU M-cycle from $XX00 into OAM. The CPU sees OAM as $FF and
+ * cannot write it until the last byte is in. */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OamDma {
+    source: u16,
+    /* Bytes copied so far */
+    copied: u8,
+    starting: bool,
+}
+
+impl OamDma {
+    pub fn new(page: u8) -> Self {
+        Self { source: source(page), copied: 0, starting: true }
+    }
+
+    /* The next (source, OAM index) to copy, None during setup */
+    pub fn step(&mut self) -> Option<(u16, usize)> {
+        if std::mem::take(&mut self.starting) {
+            return None;
+        }
+        let index = self.copied;
+        self.copied += 1;
+        Some((self.source + index as u16, index as usize))
+    }
+
+    pub fn done(&self) -> bool {
+        self.copied == OAM_DMA_LEN
+    }
+
+    /* M-cycles until the last byte is in */
+    pub fn remaining(&self) -> usize {
+        (OAM_DMA_LEN - self.copied) as usize + self.starting as usize
+    }
+
+    pub fn save_state(dma: &Option<Self>, w: &mut StateWriter) {
+        w.bool(dma.is_some());
+        if let Some(dma) = dma {
+            w.u16(dma.source);
+            w.u8(dma.copied);
+            w.bool(dma.starting);
+        }
+    }
+
+    pub fn load_state(r: &mut StateReader) -> Result<Option<Self>, ErrorKind> {
+        if !r.bool()? {
+            return Ok(None);
+        }
+        let dma = Self { source: r.u16()?, copied: r.u8()?, starting: r.bool()? };
+        match dma.copied < OAM_DMA_LEN {
+            true => Ok(Some(dma)),
+            false => Err(ErrorKind::InvalidData),
+        }
+    }
+}
+
+/* The DMA register at $FF46, holding the last page written, and the
+ * transfer it started if still running. The copying itself needs the bus,
+ * so Mem does it. */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Dma {
+    pub page: u8,
+    pub transfer: Option<OamDma>,
+}
+
+const DMA_CLAIMS: &[RangeInclusive<u16>] = &[0xFF46..=0xFF46];
+
+impl MemoryMappedDevice for Dma {
+    fn claims(&self, _cgb: bool) -> &'static [RangeInclusive<u16>] {
+        DMA_CLAIMS
+    }
+
+    fn io_register(&self, _addr: u16) -> &u8 {
+        &self.page
+    }
+
+    fn io_register_mut(&mut self, _addr: u16) -> &mut u8 {
+        &mut self.page
+    }
+
+    fn io_write(&mut self, _addr: u16, value: u8) -> u8 {
+        self.page = value;
+        0
+    }
+
+    fn cpu_clocked(&self) -> bool {
+        true
+    }
+}
+
+/* Pages $E0-$FF read from WRAM, as the echo does */
+pub fn source(page: u8) -> u16 {
+    let source = (page as u16) << 8;
+    match source {
+        0xE000.. => source - 0x2000,
+        _ => source,
+    }
+}
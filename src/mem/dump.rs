@@ -0,0 +1,103 @@
+use std::fmt::{self, Display, Write};
+
+use super::fields::{decode_register, RegisterField};
+
+/* Names for every documented register in $FF00-$FF7F plus IE, wave RAM aside */
+pub static IO_REGISTERS: &[(u16, &str)] = &[
+    (0xFF00, "P1"),   (0xFF01, "SB"),   (0xFF02, "SC"),   (0xFF04, "DIV"),
+    (0xFF05, "TIMA"), (0xFF06, "TMA"),  (0xFF07, "TAC"),  (0xFF0F, "IF"),
+    (0xFF10, "NR10"), (0xFF11, "NR11"), (0xFF12, "NR12"), (0xFF13, "NR13"),
+    (0xFF14, "NR14"), (0xFF16, "NR21"), (0xFF17, "NR22"), (0xFF18, "NR23"),
+    (0xFF19, "NR24"), (0xFF1A, "NR30"), (0xFF1B, "NR31"), (0xFF1C, "NR32"),
+    (0xFF1D, "NR33"), (0xFF1E, "NR34"), (0xFF20, "NR41"), (0xFF21, "NR42"),
+    (0xFF22, "NR43"), (0xFF23, "NR44"), (0xFF24, "NR50"), (0xFF25, "NR51"),
+    (0xFF26, "NR52"), (0xFF40, "LCDC"), (0xFF41, "STAT"), (0xFF42, "SCY"),
+    (0xFF43, "SCX"),  (0xFF44, "LY"),   (0xFF45, "LYC"),  (0xFF46, "DMA"),
+    (0xFF47, "BGP"),  (0xFF48, "OBP0"), (0xFF49, "OBP1"), (0xFF4A, "WY"),
+    (0xFF4B, "WX"),   (0xFF4D, "KEY1"), (0xFF4F, "VBK"),  (0xFF50, "BOOT"),
+    (0xFF51, "HDMA1"), (0xFF52, "HDMA2"), (0xFF53, "HDMA3"), (0xFF54, "HDMA4"),
+    (0xFF55, "HDMA5"), (0xFF56, "RP"),  (0xFF68, "BCPS"), (0xFF69, "BCPD"),
+    (0xFF6A, "OCPS"), (0xFF6B, "OCPD"), (0xFF6C, "OPRI"), (0xFF70, "SVBK"),
+    (0xFF76, "PCM12"), (0xFF77, "PCM34"), (0xFFFF, "IE"),
+];
+
+pub fn io_register_name(addr: u16) -> Option<&'static str> {
+    match addr {
+        /* Wave RAM is one 16-byte block rather than named registers */
+        0xFF30..=0xFF3F => Some("WAVE"),
+        _ => IO_REGISTERS.iter().find(|&&(reg, _)| reg == addr).map(|&(_, name)| name),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IoRegister {
+    pub addr: u16,
+    pub name: &'static str,
+    pub value: u8,
+}
+
+impl IoRegister {
+    /* The value split into named bit fields, for register inspectors */
+    pub fn fields(&self) -> Vec<RegisterField> {
+        decode_register(self.addr, self.value)
+    }
+
+    pub fn field(&self, name: &str) -> Option<u8> {
+        self.fields().into_iter().find(|field| field.name == name).map(|field| field.value)
+    }
+}
+
+/* Snapshot of the banking state and IO registers for debugger UIs */
+#[derive(Debug, Clone)]
+pub struct MemoryMap {
+    pub rom_bank: usize,
+    pub ram_bank: usize,
+    pub io: Vec<IoRegister>,
+}
+
+impl Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "ROM bank: {:#04X}", self.rom_bank)?;
+        writeln!(f, "RAM bank: {:#04X}", self.ram_bank)?;
+        for (i, reg) in self.io.iter().enumerate() {
+            let sep = if i % 4 == 3 || i + 1 == self.io.len() { "\n" } else { "  " };
+            write!(f, "{:<5} ${:04X} = ${:02X}{}", reg.name, reg.addr, reg.value, sep)?;
+        }
+        Ok(())
+    }
+}
+
+/* 16 bytes per row with an ASCII column; rows touching IO registers list
+ * them by name, e.g. `LCDC=91 STAT=85` */
+pub fn hexdump(start: u16, data: &[u8]) -> String {
+    let mut out = String::new();
+    for (row, chunk) in data.chunks(16).enumerate() {
+        let base = start.wrapping_add((row * 16) as u16);
+        let _ = write!(out, "{:04X}:", base);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => { let _ = write!(out, " {:02X}", byte); },
+                None => out.push_str("   "),
+            }
+        }
+
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        out.push('|');
+
+        let mut wave = false;
+        for (i, &byte) in chunk.iter().enumerate() {
+            let addr = base.wrapping_add(i as u16);
+            match io_register_name(addr) {
+                Some("WAVE") if wave => (),
+                Some(name) => {
+                    wave |= name == "WAVE";
+                    let _ = write!(out, " {}={:02X}", name, byte);
+                },
+                None => (),
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
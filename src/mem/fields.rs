@@ -0,0 +1,107 @@
+use std::fmt::{self, Display};
+
+/* A named bit range within a register: (name, lowest bit, width) */
+type FieldSpec = (&'static str, u8, u8);
+
+const INTERRUPTS: &[FieldSpec] = &[
+    ("Joypad", 4, 1), ("Serial", 3, 1), ("Timer", 2, 1), ("STAT", 1, 1), ("VBlank", 0, 1),
+];
+const DUTY_LENGTH: &[FieldSpec] = &[("Duty", 6, 2), ("Length", 0, 6)];
+const ENVELOPE: &[FieldSpec] = &[("Volume", 4, 4), ("Envelope up", 3, 1), ("Envelope pace", 0, 3)];
+const PERIOD_LOW: &[FieldSpec] = &[("Period low", 0, 8)];
+const PERIOD_HIGH: &[FieldSpec] = &[("Trigger", 7, 1), ("Length enable", 6, 1), ("Period high", 0, 3)];
+const DMG_PALETTE: &[FieldSpec] = &[("Color 3", 6, 2), ("Color 2", 4, 2), ("Color 1", 2, 2), ("Color 0", 0, 2)];
+const PALETTE_INDEX: &[FieldSpec] = &[("Auto increment", 7, 1), ("Address", 0, 6)];
+
+/* Bit fields of every register with more than one thing in it; plain
+ * counters and addresses like DIV, LY or SCX have none */
+static REGISTER_FIELDS: &[(u16, &[FieldSpec])] = &[
+    (0xFF00, &[("Select buttons", 5, 1), ("Select d-pad", 4, 1), ("Inputs", 0, 4)]),
+    (0xFF02, &[("Transfer", 7, 1), ("Fast clock", 1, 1), ("Internal clock", 0, 1)]),
+    (0xFF07, &[("Enable", 2, 1), ("Clock select", 0, 2)]),
+    (0xFF0F, INTERRUPTS),
+    (0xFF10, &[("Sweep pace", 4, 3), ("Sweep down", 3, 1), ("Sweep step", 0, 3)]),
+    (0xFF11, DUTY_LENGTH),
+    (0xFF12, ENVELOPE),
+    (0xFF13, PERIOD_LOW),
+    (0xFF14, PERIOD_HIGH),
+    (0xFF16, DUTY_LENGTH),
+    (0xFF17, ENVELOPE),
+    (0xFF18, PERIOD_LOW),
+    (0xFF19, PERIOD_HIGH),
+    (0xFF1A, &[("DAC enable", 7, 1)]),
+    (0xFF1B, &[("Length", 0, 8)]),
+    (0xFF1C, &[("Output level", 5, 2)]),
+    (0xFF1D, PERIOD_LOW),
+    (0xFF1E, PERIOD_HIGH),
+    (0xFF20, &[("Length", 0, 6)]),
+    (0xFF21, ENVELOPE),
+    (0xFF22, &[("Clock shift", 4, 4), ("Short LFSR", 3, 1), ("Clock divider", 0, 3)]),
+    (0xFF23, &[("Trigger", 7, 1), ("Length enable", 6, 1)]),
+    (0xFF24, &[("VIN left", 7, 1), ("Left volume", 4, 3), ("VIN right", 3, 1), ("Right volume", 0, 3)]),
+    (0xFF25, &[
+        ("CH4 left", 7, 1), ("CH3 left", 6, 1), ("CH2 left", 5, 1), ("CH1 left", 4, 1),
+        ("CH4 right", 3, 1), ("CH3 right", 2, 1), ("CH2 right", 1, 1), ("CH1 right", 0, 1),
+    ]),
+    (0xFF26, &[("Audio on", 7, 1), ("CH4 on", 3, 1), ("CH3 on", 2, 1), ("CH2 on", 1, 1), ("CH1 on", 0, 1)]),
+    (0xFF40, &[
+        ("LCD enable", 7, 1), ("Window map $9C00", 6, 1), ("Window enable", 5, 1), ("Tiles $8000", 4, 1),
+        ("BG map $9C00", 3, 1), ("OBJ 8x16", 2, 1), ("OBJ enable", 1, 1), ("BG enable", 0, 1),
+    ]),
+    (0xFF41, &[
+        ("LYC interrupt", 6, 1), ("Mode 2 interrupt", 5, 1), ("Mode 1 interrupt", 4, 1),
+        ("Mode 0 interrupt", 3, 1), ("LYC=LY", 2, 1), ("Mode", 0, 2),
+    ]),
+    (0xFF47, DMG_PALETTE),
+    (0xFF48, DMG_PALETTE),
+    (0xFF49, DMG_PALETTE),
+    (0xFF4D, &[("Double speed", 7, 1), ("Switch armed", 0, 1)]),
+    (0xFF4F, &[("Bank", 0, 1)]),
+    (0xFF55, &[("HBlank mode", 7, 1), ("Length", 0, 7)]),
+    (0xFF68, PALETTE_INDEX),
+    (0xFF6A, PALETTE_INDEX),
+    (0xFF70, &[("Bank", 0, 3)]),
+    (0xFFFF, INTERRUPTS),
+];
+
+/* One decoded field, `value` shifted down to start at bit 0 */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegisterField {
+    pub name: &'static str,
+    pub shift: u8,
+    pub width: u8,
+    pub value: u8,
+}
+
+impl RegisterField {
+    pub fn is_flag(&self) -> bool {
+        self.width == 1
+    }
+
+    pub fn mask(&self) -> u8 {
+        (((1u16 << self.width) - 1) as u8) << self.shift
+    }
+}
+
+impl Display for RegisterField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.width {
+            1 => write!(f, "{}={}", self.name, self.value),
+            _ => write!(f, "{}=${:X}", self.name, self.value),
+        }
+    }
+}
+
+/* `value` split into the named fields of the register at `addr`, highest
+ * bits first; empty for registers that are a single number */
+pub fn decode_register(addr: u16, value: u8) -> Vec<RegisterField> {
+    REGISTER_FIELDS.iter()
+        .find(|&&(reg, _)| reg == addr)
+        .map_or(&[][..], |&(_, fields)| fields)
+        .iter()
+        .map(|&(name, shift, width)| RegisterField {
+            name, shift, width,
+            value: (value >> shift) & (((1u16 << width) - 1) as u8),
+        })
+        .collect()
+}
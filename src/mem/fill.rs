@@ -0,0 +1,46 @@
+/* What RAM holds at power on. Real hardware leaves a pattern that differs
+ * between units and boots; a fixed choice keeps runs reproducible. */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum RamFill {
+    #[default]
+    Zero,
+    Ones,
+    /* Noise that is the same for every run with the same seed */
+    Random(u64),
+}
+
+impl RamFill {
+    /* `zero`, `ones`, `random` or `random:SEED` */
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.split_once(':') {
+            None if name == "zero" => Some(Self::Zero),
+            None if name == "ones" => Some(Self::Ones),
+            None if name == "random" => Some(Self::Random(0)),
+            Some(("random", seed)) => seed.parse().ok().map(Self::Random),
+            _ => None,
+        }
+    }
+
+    /* `region` tells the areas apart so they get different noise */
+    pub fn fill(&self, ram: &mut [u8], region: u64) {
+        match *self {
+            Self::Zero => ram.fill(0x00),
+            Self::Ones => ram.fill(0xFF),
+            Self::Random(seed) => {
+                let mut state = seed ^ region.wrapping_mul(0xD1B5_4A32_D192_ED03);
+                for chunk in ram.chunks_mut(8) {
+                    let bytes = splitmix64(&mut state).to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+            },
+        }
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,68 @@
+use std::ops::RangeInclusive;
+
+pub type HookFn = Box<dyn FnMut(u16, u8)>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct HookId(usize);
+
+struct Hook {
+    id: HookId,
+    range: RangeInclusive<u16>,
+    callback: HookFn,
+}
+
+#[derive(Default)]
+pub struct MemHooks {
+    next_id: usize,
+    reads: Vec<Hook>,
+    writes: Vec<Hook>,
+}
+
+impl MemHooks {
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty() && self.writes.is_empty()
+    }
+
+    pub fn add_read(&mut self, range: RangeInclusive<u16>, callback: HookFn) -> HookId {
+        let id = self.next_id();
+        self.reads.push(Hook { id, range, callback });
+        id
+    }
+
+    pub fn add_write(&mut self, range: RangeInclusive<u16>, callback: HookFn) -> HookId {
+        let id = self.next_id();
+        self.writes.push(Hook { id, range, callback });
+        id
+    }
+
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let len = self.reads.len() + self.writes.len();
+        self.reads.retain(|hook| hook.id != id);
+        self.writes.retain(|hook| hook.id != id);
+        len != self.reads.len() + self.writes.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.reads.clear();
+        self.writes.clear();
+    }
+
+    pub fn on_read(&mut self, addr: u16, value: u8) {
+        Self::dispatch(&mut self.reads, addr, value);
+    }
+
+    pub fn on_write(&mut self, addr: u16, value: u8) {
+        Self::dispatch(&mut self.writes, addr, value);
+    }
+
+    fn dispatch(hooks: &mut [Hook], addr: u16, value: u8) {
+        for hook in hooks.iter_mut().filter(|hook| hook.range.contains(&addr)) {
+            (hook.callback)(addr, value);
+        }
+    }
+
+    fn next_id(&mut self) -> HookId {
+        self.next_id += 1;
+        HookId(self.next_id)
+    }
+}
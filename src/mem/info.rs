@@ -0,0 +1,149 @@
+use std::fmt::{self, Display};
+
+use super::{
+    cart::{types::{CartColorType, CartType, ConsoleIndicator, DestinationCode}, Cart},
+    controller::Controller,
+};
+
+/* Everything `gba info` reports about a cart, read straight off the header */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartInfo {
+    pub title: String,
+    pub manufacturer: Option<String>,
+    pub logo_valid: bool,
+    pub cart_type: CartType,
+    /* None when the mapper is not emulated */
+    pub mapper: Option<Controller>,
+    pub rom_size: usize,
+    pub file_size: usize,
+    pub ram_size: usize,
+    pub cgb: CartColorType,
+    pub sgb: bool,
+    pub licensee: String,
+    pub destination: DestinationCode,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub header_checksum_valid: bool,
+    pub global_checksum: u16,
+    pub global_checksum_valid: bool,
+    /* `Cart::header_hash`, for per-game settings */
+    pub header_hash: String,
+}
+
+impl CartInfo {
+    pub fn new(cart: &Cart) -> Self {
+        let header = &cart.header;
+        Self {
+            title: header.title_str().to_string(),
+            manufacturer: header.manufacturer_code().map(str::to_string),
+            logo_valid: header.logo_valid(),
+            cart_type: header.cart_type,
+            mapper: Controller::detect(cart),
+            rom_size: header.rom_size.bytes(),
+            file_size: cart.data_len,
+            ram_size: header.ram_size.bytes(),
+            cgb: header.color_type,
+            sgb: header.console_indicator == ConsoleIndicator::SuperGameBoy,
+            licensee: header.licensee_code(),
+            destination: header.destination_code,
+            version: header.mask_rom_version,
+            header_checksum: header.compliment_check,
+            header_checksum_valid: cart.header_checksum_valid(),
+            global_checksum: header.checksum,
+            global_checksum_valid: cart.global_checksum_valid(),
+            header_hash: cart.header_hash(),
+        }
+    }
+
+    fn cart_type_name(&self) -> String {
+        match self.cart_type {
+            CartType::Unknown(code) => format!("UNKNOWN (${:02X})", code),
+            cart_type => cart_type.name().to_string(),
+        }
+    }
+
+    fn mapper_name(&self) -> String {
+        self.mapper.map_or_else(|| "unsupported".to_string(), |mapper| format!("{:?}", mapper))
+    }
+
+    fn cgb_name(&self) -> &'static str {
+        match self.cgb {
+            CartColorType::GameBoyColor => "enhanced",
+            CartColorType::ColorOnly => "required",
+            CartColorType::Other => "no",
+        }
+    }
+
+    fn destination_name(&self) -> &'static str {
+        match self.destination {
+            DestinationCode::Japanese => "Japan",
+            DestinationCode::NonJapanese => "Overseas",
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let string = |value: &str| {
+            let mut out = String::from("\"");
+            for c in value.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    c if (c as u32) < 0x20 || c as u32 > 0x7E => out.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+            out
+        };
+        let fields = [
+            ("title", string(&self.title)),
+            ("manufacturer", self.manufacturer.as_deref().map_or_else(|| "null".to_string(), string)),
+            ("logo_valid", self.logo_valid.to_string()),
+            ("cart_type", string(&self.cart_type_name())),
+            ("mapper", self.mapper.map_or_else(|| "null".to_string(), |_| string(&self.mapper_name()))),
+            ("rom_size", self.rom_size.to_string()),
+            ("file_size", self.file_size.to_string()),
+            ("ram_size", self.ram_size.to_string()),
+            ("cgb", string(self.cgb_name())),
+            ("sgb", self.sgb.to_string()),
+            ("licensee", string(&self.licensee)),
+            ("destination", string(self.destination_name())),
+            ("version", self.version.to_string()),
+            ("header_checksum", self.header_checksum.to_string()),
+            ("header_checksum_valid", self.header_checksum_valid.to_string()),
+            ("global_checksum", self.global_checksum.to_string()),
+            ("global_checksum_valid", self.global_checksum_valid.to_string()),
+            ("header_hash", string(&self.header_hash)),
+        ];
+        let body: Vec<String> = fields.iter().map(|(key, value)| format!("  \"{}\": {}", key, value)).collect();
+        format!("{{\n{}\n}}", body.join(",\n"))
+    }
+}
+
+impl Display for CartInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ok = |valid: bool| if valid { "ok" } else { "BAD" };
+        let size = |bytes: usize| if bytes >= 0x100000 { format!("{} MiB", bytes >> 20) } else { format!("{} KiB", bytes >> 10) };
+        writeln!(f, "Title            {}", self.title)?;
+        if let Some(code) = &self.manufacturer {
+            writeln!(f, "Manufacturer     {}", code)?;
+        }
+        writeln!(f, "Logo             {}", ok(self.logo_valid))?;
+        writeln!(f, "Cartridge type   {}", self.cart_type_name())?;
+        writeln!(f, "Mapper           {}", self.mapper_name())?;
+        write!(f, "ROM size         {}", size(self.rom_size))?;
+        if self.file_size != self.rom_size {
+            write!(f, " (file is {} bytes)", self.file_size)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "RAM size         {}", size(self.ram_size))?;
+        writeln!(f, "CGB              {}", self.cgb_name())?;
+        writeln!(f, "SGB              {}", if self.sgb { "yes" } else { "no" })?;
+        writeln!(f, "Licensee         {}", self.licensee)?;
+        writeln!(f, "Destination      {}", self.destination_name())?;
+        writeln!(f, "Version          {}", self.version)?;
+        writeln!(f, "Header checksum  ${:02X} {}", self.header_checksum, ok(self.header_checksum_valid))?;
+        writeln!(f, "Global checksum  ${:04X} {}", self.global_checksum, ok(self.global_checksum_valid))?;
+        writeln!(f, "Settings         [games.{}]", self.header_hash)
+    }
+}
@@ -0,0 +1,45 @@
+/* Bits of each register in $FF00-$FF7F that read back as 1 on the DMG
+ * whatever was last written: unused bits, write-only bits, and all of
+ * any address with no register behind it. Sound is masked by the APU
+ * itself, which also tracks what powering off clears. */
+const READ_MASKS: [u8; 0x80] = [
+    /* P1    SB    SC    -     DIV   TIMA  TMA   TAC */
+    0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8,
+    /* -                                         IF */
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    /* $FF10-$FF3F: sound */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    /* LCDC  STAT  SCY   SCX   LY    LYC   DMA   BGP */
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    /* OBP0  OBP1  WY    WX    -     KEY1  -     VBK */
+    0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0xFF,
+    /* BOOT and the CGB's HDMA, RP and palettes */
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+/* What changes in CGB mode: SC's clock speed bit, the VRAM and WRAM bank
+ * selects and the palette index registers exist, the latter with an
+ * unused bit 6 */
+fn cgb_read_mask(addr: u16) -> Option<u8> {
+    match addr {
+        0xFF02 => Some(0x7C),
+        0xFF4F => Some(0xFE),
+        0xFF70 => Some(0xF8),
+        0xFF68 | 0xFF6A => Some(0x40),
+        0xFF69 | 0xFF6B => Some(0x00),
+        _ => None,
+    }
+}
+
+/* The always-set bits of I/O register `addr`, $FF00-$FF7F; $FF when
+ * nothing is there to read */
+pub fn read_mask(addr: u16, cgb: bool) -> u8 {
+    match cgb_read_mask(addr) {
+        Some(mask) if cgb => mask,
+        _ => READ_MASKS[(addr - 0xFF00) as usize & 0x7F],
+    }
+}
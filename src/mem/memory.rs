@@ -1,39 +1,111 @@
-use std::{borrow::Borrow, hint::unreachable_unchecked, ops::{Index, IndexMut}, slice::SliceIndex};
+use std::{borrow::Borrow, cell::RefCell, hint::unreachable_unchecked, io::ErrorKind, ops::{Index, IndexMut, RangeInclusive}, slice::SliceIndex};
 
-use super::prelude::Cart;
+use crate::{
+    apu::prelude::Apu,
+    cpu::interrupt::Interrupt,
+    debugger::prelude::CodeDataLog,
+    input::prelude::{Button, Joypad},
+    link::prelude::Serial,
+    ppu::prelude::Ppu,
+    scheduler::prelude::{Event, Scheduler},
+    sgb::prelude::Sgb,
+    state::prelude::{Savestate, StateReader, StateWriter, Xxh64},
+    timer::prelude::Timer,
+    debug, info,
+};
 
-pub struct Mem<'a> {
+use super::{address::{Address, Region}, boot_rom::BootRom, bus::Bus, camera::{Camera, IMAGE_OFFSET}, controller::{Controller, Mbc, MBC2_RAM_LEN}, device::{DeviceId, DeviceMap, MemoryMappedDevice}, dma::{Dma, OamDma}, dump::{IoRegister, MemoryMap, IO_REGISTERS}, fill::RamFill, hooks::{HookId, MemHooks}, io::read_mask, prelude::Cart, rtc::Rtc, rumble::Rumble};
+
+pub struct Mem {
     cart:         Cart,
-    rom_bank:     &'a [u8],
-    rom_switch:   &'a [u8],
-    ram:          [u8; 0x6000],
-    sprite_oam:   [u8; 0x00A0],
-    io_ports:     [u8; 0x004C],
+    mbc:          Mbc,
+    /* Offsets into the cart of the banks mapped at $0000 and $4000 */
+    rom_bank0:    usize,
+    rom_bank:     usize,
+    /* Offset into `cart_ram` of the bank at $A000, None when unmapped */
+    ram_bank:     Option<usize>,
+    cart_ram:     Vec<u8>,
+    /* MBC3 carts with a TIMER */
+    pub rtc:      Option<Rtc>,
+    pub camera:   Option<Camera>,
+    /* MBC5 carts with a motor */
+    rumble:       Option<Rumble>,
+    /* Eight 4kB banks; $C000 is always bank 0, $D000 the one SVBK picks */
+    wram:         [u8; 0x8000],
+    /* SVBK: the WRAM bank at $D000, 0 meaning 1; only the CGB switches */
+    svbk:         u8,
+    /* Target for writes to ROM, disabled cart RAM and unmapped addresses */
+    open_bus:     u8,
+    /* IF, the one I/O register below $FF10 that no device owns */
+    int_flag:     u8,
     ram_stack:    [u8; 0x0080],
+    pub ppu:      Ppu,
+    pub serial:   Serial,
+    pub joypad:   Joypad,
+    pub timer:    Timer,
+    pub apu:      Apu,
+    /* Present when running as a Super Game Boy */
+    pub sgb:      Option<Sgb>,
+    /* How the ROM gets used, while logging */
+    pub cdl:      Option<CodeDataLog>,
+    /* When the peripherals next need to catch up with the CPU */
+    scheduler:    Scheduler,
+    /* Accuracy option, see `idu_access` */
+    pub oam_bug:  bool,
+    dma:          Dma,
+    /* Which device answers each I/O address, redone when CGB mode changes */
+    devices:      DeviceMap,
+    /* Off, OAM DMA copies everything the moment it is started */
+    pub exact_dma: bool,
+    /* Running a CGB boot ROM on a CGB cart; gates KEY1 */
+    cgb_mode:     bool,
+    /* KEY1: bit 7 double speed, bit 0 switch armed */
+    key1:         u8,
+    /* Half a normal-speed M-cycle left over in double speed */
+    half_cycle:   bool,
+    boot_rom:     Option<BootRom>,
+    /* The boot ROM shadows the cart until $FF50 is written */
+    boot_mapped:  bool,
+    /* Power-on RAM contents, repeated on reset */
+    ram_fill:     RamFill,
+    hooks:        RefCell<MemHooks>,
+    /* Mirrors `!hooks.is_empty()` so unhooked accesses skip the RefCell */
+    hooked:       bool,
 }
 
-impl<'a, T> Index<T> for Mem<'a>
+impl<T> Index<T> for Mem
     where T: Into<u16>
 {
     type Output = u8;
 
     fn index(&self, index: T) -> &Self::Output {
-        let index = index.into() as usize;
+        let addr = Address(index.into());
+        let index = addr.0 as usize;
+        if self.boot_mapped && index < 0x900 {
+            if let Some(byte) = self.boot_rom.as_ref().and_then(|boot| boot.get(index)) {
+                return byte;
+            }
+        }
         match index {
-            0xFF80..=0xFFFF => &self.ram_stack[index - 0xFF80], /* Internal RAM */
-            0xFF4C..=0xFF7F => panic!("Accessing memory ${:#04X}: Empty but unusable for I/O", index),
-            0xFF00..=0xFF4B => &self.io_ports[index - 0xFF00], /* I/O Ports */
-            0xFEA0..=0xFEFF => panic!("Accessing memory ${:#04X}: Empty but unusable for I/O", index),
-            0xFE00..=0xFE9F => &self.sprite_oam[index - 0xFE00], /* Sprite Attrib Memory (OAM) */
+            0xFF80..=0xFFFF => Self::hram_offset(addr).map_or(&0xFF, |offset| &self.ram_stack[offset]), /* Internal RAM */
+            0xFF00..=0xFF7F => self.io_port(index as u16), /* I/O Registers */
+            0xFEA0..=0xFEFF => &0xFF, /* Empty but unusable, reads as open bus */
+            0xFE00..=0xFE9F if self.dma.transfer.is_some() => &0xFF, /* OAM taken by DMA */
+            0xFE00..=0xFE9F => addr.offset_in(Region::Oam).map_or(&0xFF, |offset| &self.ppu.oam[offset]), /* Sprite Attrib Memory (OAM) */
 
-            0xE000..=0xFDFF => &self.ram[index - 0xA000], /* Echo of 8kB Internal RAM */
-            0x8000..=0xDFFF => &self.ram[index - 0x8000],
-            //0xC000..=0xDFFF => self.ram_internal[index - 0xC000], /* 8kB Internal RAM */
-            //0xA000..=0xBFFF => self.ram_bank[index - 0xA000], /* 8kB Switchable RAM Bank */
-            //0x8000..=0x9FFF => self.ram_video[index - 0x8000], /* 8kB Video RAM */
+            0xC000..=0xFDFF => self.wram_offset(addr).map_or(&0xFF, |offset| &self.wram[offset]), /* 8kB Internal RAM and its echo */
+            /* Disabled or absent cart RAM reads as open bus */
+            0xA000..=0xBFFF => match (&self.rtc, self.mbc.rtc_register()) {
+                (Some(rtc), Some(register)) => rtc.latched(register),
+                /* The HuC1 IR receiver, stubbed as seeing no light */
+                _ if self.mbc.ir_selected() => &0xC0,
+                _ if self.mbc.camera_registers() => self.camera.as_ref().map_or(&0x00, |camera| camera.register(index)),
+                _ => self.cart_ram_offset(addr).map_or(&0xFF, |offset| &self.cart_ram[offset]),
+            },
+            0x8000..=0x9FFF => self.vram_offset(addr).map_or(&0xFF, |offset| &self.ppu.vram[offset]), /* 8kB Video RAM */
 
-            0x4000..=0x7FFF => &self.rom_switch[index - 0x4000],
-            0x0000..=0x3FFF => &self.rom_bank[index],
+            /* Past the end of a short ROM reads as open bus */
+            0x0000..=0x7FFF => self.cart_rom_offset(addr).and_then(|offset| self.cart.data.get(offset)).unwrap_or(&0xFF),
 
             /* Required due to matching on usize,
              * but gauranteed to be unreachable by
@@ -43,25 +115,38 @@ impl<'a, T> Index<T> for Mem<'a>
     }
 }
 
-impl<'a, T> IndexMut<T> for Mem<'a>
+impl<T> IndexMut<T> for Mem
     where T: Into<u16>
 {
     fn index_mut(&mut self, index: T) -> &mut Self::Output {
-        let index = index.into() as usize;
+        let addr = Address(index.into());
+        let index = addr.0 as usize;
         match index {
-            0xFF80..=0xFFFF => &mut self.ram_stack[index - 0xFF80], /* Internal RAM */
-            0xFF4C..=0xFF7F => panic!("Accessing memory ${:#04X}: Empty but unusable for I/O", index),
-            0xFF00..=0xFF4B => &mut self.io_ports[index - 0xFF00], /* I/O Ports */
-            0xFEA0..=0xFEFF => panic!("Accessing memory ${:#04X}: Empty but unusable for I/O", index),
-            0xFE00..=0xFE9F => &mut self.sprite_oam[index - 0xFE00], /* Sprite Attrib Memory (OAM) */
+            0xFF80..=0xFFFF => match Self::hram_offset(addr) { /* Internal RAM */
+                Some(offset) => &mut self.ram_stack[offset],
+                None => &mut self.open_bus,
+            },
+            0xFF00..=0xFF7F => self.io_port_mut(index as u16), /* I/O Registers */
+            0xFEA0..=0xFEFF => &mut self.open_bus, /* Empty but unusable, writes go nowhere */
+            0xFE00..=0xFE9F => match addr.offset_in(Region::Oam) { /* Sprite Attrib Memory (OAM) */
+                Some(offset) => &mut self.ppu.oam[offset],
+                None => &mut self.open_bus,
+            },
 
-            0xE000..=0xFDFF => &mut self.ram[index - 0xA000], /* Echo of 8kB Internal RAM */
-            0x8000..=0xDFFF => &mut self.ram[index - 0x8000],
-            //0xC000..=0xDFFF => self.ram_internal[index - 0xC000], /* 8kB Internal RAM */
-            //0xA000..=0xBFFF => self.ram_bank[index - 0xA000], /* 8kB Switchable RAM Bank */
-            //0x8000..=0x9FFF => self.ram_video[index - 0x8000], /* 8kB Video RAM */
+            0xC000..=0xFDFF => match self.wram_offset(addr) { /* 8kB Internal RAM and its echo */
+                Some(offset) => &mut self.wram[offset],
+                None => &mut self.open_bus,
+            },
+            0xA000..=0xBFFF => match self.cart_ram_offset(addr) {
+                Some(offset) => &mut self.cart_ram[offset],
+                None => &mut self.open_bus,
+            },
+            0x8000..=0x9FFF => match self.vram_offset(addr) { /* 8kB Video RAM */
+                Some(offset) => &mut self.ppu.vram[offset],
+                None => &mut self.open_bus,
+            },
 
-            0x0000..=0x7FFF => panic!("Modifying ROM memory ${:#04X}", index), /* 32kB ROM */
+            0x0000..=0x7FFF => &mut self.open_bus, /* 32kB ROM, read only */
 
             /* Required due to matching on usize,
              * but gauranteed to be unreachable by
@@ -71,49 +156,892 @@ impl<'a, T> IndexMut<T> for Mem<'a>
     }
 }
 
-impl<'a> Mem<'a> {
+impl Mem {
     pub fn new(cart: Cart) -> Self {
-        let rom_bank = unsafe { std::slice::from_raw_parts(cart.data.as_ptr(), 0x4000) };
-        let rom_switch = unsafe { std::slice::from_raw_parts(cart.data.as_ptr(), 0x4000) };
-
-        Self {
+        let controller = Controller::detect(&cart).unwrap_or_else(|| Controller::from(cart.header.cart_type));
+        let ram_len = match controller {
+            Controller::MBC2 => MBC2_RAM_LEN,
+            _ => cart.header.ram_size.bytes(),
+        };
+        let mut mbc = Mbc::new(controller, cart.data.len(), ram_len);
+        mbc.rumble = cart.header.cart_type.has_rumble();
+        let rumble = mbc.rumble.then(Rumble::default);
+        let rtc = cart.header.cart_type.has_rtc().then(Rtc::default);
+        let camera = (controller == Controller::PocketCamera).then(Camera::default);
+        let mut mem = Self {
             cart,
-            rom_bank,
-            rom_switch,
-            ram:          [0; 0x6000],
-            sprite_oam:   [0; 0x00A0],
-            io_ports:     [0; 0x004C],
+            mbc,
+            rom_bank0:    0,
+            rom_bank:     0x4000,
+            ram_bank:     Some(0),
+            cart_ram:     vec![0; ram_len],
+            rtc,
+            camera,
+            rumble,
+            wram:         [0; 0x8000],
+            svbk:         0,
+            open_bus:     0xFF,
+            int_flag:     0,
             ram_stack:    [0; 0x0080],
+            ppu:          Ppu::default(),
+            serial:       Serial::default(),
+            joypad:       Joypad::default(),
+            timer:        Timer::default(),
+            apu:          Apu::default(),
+            sgb:          None,
+            cdl:          None,
+            scheduler:    Scheduler::default(),
+            oam_bug:      false,
+            dma:          Dma::default(),
+            devices:      DeviceMap::default(),
+            exact_dma:    true,
+            cgb_mode:     false,
+            key1:         0xFF,
+            half_cycle:   false,
+            boot_rom:     None,
+            boot_mapped:  false,
+            ram_fill:     RamFill::default(),
+            hooks:        RefCell::new(MemHooks::default()),
+            hooked:       false,
+        };
+        mem.map_devices();
+        mem
+    }
+
+    pub fn cart(&self) -> &Cart {
+        &self.cart
+    }
+
+    pub fn controller(&self) -> Controller {
+        self.mbc.controller
+    }
+
+    /* Banks 0 and 1 on the DMG, all eight in CGB mode */
+    pub fn wram(&self) -> &[u8] {
+        match self.cgb_mode {
+            true => &self.wram,
+            false => &self.wram[..0x2000],
+        }
+    }
+
+    /* The WRAM bank mapped at $D000 */
+    pub fn wram_bank(&self) -> usize {
+        match self.svbk & 0x07 {
+            0 => 1,
+            bank => bank as usize,
+        }
+    }
+
+    /* Where in `wram` a WRAM or echo address lands with the current bank */
+    fn wram_offset(&self, addr: Address) -> Option<usize> {
+        let addr = addr.unecho();
+        addr.offset_in(Region::Wram0)
+            .or_else(|| addr.offset_in(Region::WramX).map(|offset| self.wram_bank() * Region::WramX.size() + offset))
+    }
+
+    /* Where in `ppu.vram` a VRAM address lands with the current bank */
+    fn vram_offset(&self, addr: Address) -> Option<usize> {
+        addr.offset_in(Region::Vram).map(|offset| self.ppu.vram_bank() + offset)
+    }
+
+    /* Where in `ram_stack` an HRAM address lands; IE is the byte after */
+    fn hram_offset(addr: Address) -> Option<usize> {
+        match addr.region() {
+            Region::Ie => Some(Region::Hram.size()),
+            _ => addr.offset_in(Region::Hram),
+        }
+    }
+
+    /* Where in the cart a ROM address lands with the current banks, which
+     * may be past the end of a short ROM */
+    fn cart_rom_offset(&self, addr: Address) -> Option<usize> {
+        addr.offset_in(Region::Rom0).map(|offset| self.rom_bank0 + offset)
+            .or_else(|| addr.offset_in(Region::RomX).map(|offset| self.rom_bank + offset))
+    }
+
+    pub fn cart_ram(&self) -> &[u8] {
+        &self.cart_ram
+    }
+
+    pub fn cart_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.cart_ram
+    }
+
+    /* Power-on contents of WRAM, HRAM and cart RAM; IE is left clear */
+    pub fn fill_ram(&mut self, fill: RamFill) {
+        self.ram_fill = fill;
+        fill.fill(&mut self.wram, 0);
+        fill.fill(&mut self.ram_stack[..0x7F], 1);
+        fill.fill(&mut self.cart_ram, 2);
+    }
+
+    /* What goes in the .sav: cart RAM, then the clock footer if the cart
+     * has one, stamped with `now` in UNIX seconds */
+    pub fn battery(&self, now: u64) -> Vec<u8> {
+        let mut save = self.cart_ram.clone();
+        if let Some(rtc) = &self.rtc {
+            save.extend_from_slice(&rtc.to_footer(now));
+        }
+        save
+    }
+
+    /* Restores a .sav, with or without a clock footer; the clock catches up
+     * on the time since the save was written */
+    pub fn load_battery(&mut self, save: &[u8], now: u64) -> Result<(), ErrorKind> {
+        let len = self.cart_ram.len();
+        if save.len() < len {
+            return Err(ErrorKind::InvalidData);
+        }
+        let (ram, footer) = save.split_at(len);
+        match (&mut self.rtc, footer.is_empty()) {
+            (_, true) => {},
+            (Some(rtc), false) => *rtc = Rtc::from_footer(footer, now).ok_or(ErrorKind::InvalidData)?,
+            /* Another emulator's clock on a cart without one */
+            (None, false) => {},
+        }
+        self.cart_ram.copy_from_slice(ram);
+        Ok(())
+    }
+
+    fn cart_ram_offset(&self, addr: Address) -> Option<usize> {
+        if !self.mbc.ram_enabled() || self.cart_ram.is_empty() {
+            return None;
         }
+        let offset = addr.offset_in(Region::CartRam)?;
+        let offset = match self.mbc.controller {
+            /* Only the low 9 address bits reach the MBC2 cells */
+            Controller::MBC2 => offset & 0x01FF,
+            _ => self.ram_bank? + offset,
+        };
+        /* 2kB carts mirror their RAM through the 8kB window */
+        Some(offset % self.cart_ram.len())
+    }
+
+    /* Recompute the mapped banks after an MBC register write */
+    fn remap(&mut self) {
+        self.rom_bank0 = self.mbc.rom_bank0() * 0x4000;
+        self.rom_bank = self.mbc.rom_bank() * 0x4000;
+        self.ram_bank = self.mbc.ram_bank().map(|bank| bank * 0x2000);
+    }
+
+    pub fn map_boot_rom(&mut self, boot: BootRom) {
+        self.boot_rom = Some(boot);
+        self.boot_mapped = true;
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_mapped
+    }
+
+    /* Whether there is a boot ROM to run at power-on, mapped or not */
+    pub fn has_boot_rom(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    /* Back to power-on with the same cart and boot ROM, which is mapped
+     * again. Everything a savestate leaves out stays: palettes, hooks, the
+     * link cable, the CDL and accuracy settings. Cart RAM and the clock
+     * survive a soft reset; a hard one clears RAM without a battery. */
+    pub fn reset(&mut self, hard: bool) {
+        let mut fresh = Mem::new(self.cart.clone());
+        fresh.set_cgb_mode(self.cgb_mode);
+        if let Some(boot) = &self.boot_rom {
+            fresh.map_boot_rom(boot.clone());
+        }
+        fresh.sgb = self.sgb.as_ref().map(|_| Sgb::default());
+        fresh.fill_ram(self.ram_fill);
+        if !hard || self.cart.header.cart_type.has_battery() {
+            fresh.cart_ram.copy_from_slice(&self.cart_ram);
+        }
+        fresh.rtc = self.rtc.clone();
+
+        let mut w = StateWriter::new();
+        fresh.save_state(&mut w);
+        let state = w.into_inner();
+        if let Err(e) = self.load_state(&mut StateReader::new(&state)) {
+            unreachable!("a fresh copy of the same console failed to load: {:?}", e);
+        }
+        info!(target: "gbemu::mem", "{} reset", if hard { "Hard" } else { "Soft" });
+    }
+
+    /* Puts a different cart in, with its own mapper, RAM and clock, and
+     * hands back the old one. CGB mode and the SGB follow what the new cart
+     * supports; the CDL is dropped. Nothing else changes until `reset`. */
+    pub fn swap_cart(&mut self, cart: Cart) -> Cart {
+        let fresh = Mem::new(cart);
+        let old = std::mem::replace(&mut self.cart, fresh.cart);
+        self.mbc = fresh.mbc;
+        self.cart_ram = fresh.cart_ram;
+        self.rtc = fresh.rtc;
+        self.camera = fresh.camera;
+        self.rumble = fresh.rumble;
+        self.cgb_mode = self.boot_rom.as_ref().is_some_and(BootRom::is_cgb) && self.cart.header.is_cgb();
+        self.map_devices();
+        if !self.cart.header.supports_sgb() {
+            self.sgb = None;
+        }
+        self.cdl = None;
+        info!(target: "gbemu::mem", "Swapped in {}", self.cart.header.title_str());
+        old
     }
 
     #[inline(always)]
     pub fn get_u8<T>(&self, index: T) -> u8 where T: Into<u16> {
-        self[index]
+        let index = index.into();
+        let value = match index {
+            0xFF00..=0xFF7F => self.io_read(index),
+            _ => self[index],
+        };
+        if self.hooked {
+            if let Ok(mut hooks) = self.hooks.try_borrow_mut() { hooks.on_read(index, value); }
+        }
+        value
     }
 
     pub fn get_u16<T>(&self, index: T) -> u16 where T: Into<u16> {
         let index = index.into();
         let low = self.get_u8(index) as u16;
-        low | ((self.get_u8(index + 1) as u16) << 8)
+        low | ((self.get_u8(index.wrapping_add(1)) as u16) << 8)
+    }
+
+    /* A CPU read: the peripherals catch up before their registers are read */
+    pub fn read(&mut self, addr: u16) -> u8 {
+        if Self::is_io(addr) {
+            self.sync();
+        }
+        self.log_rom(addr, CodeDataLog::DATA);
+        self.get_u8(addr)
+    }
+
+    /* A CPU read from PC */
+    pub fn fetch(&mut self, addr: u16, opcode: bool) -> u8 {
+        if Self::is_io(addr) {
+            self.sync();
+        }
+        self.log_rom(addr, if opcode { CodeDataLog::CODE | CodeDataLog::OPCODE } else { CodeDataLog::CODE });
+        self.get_u8(addr)
+    }
+
+    fn log_rom(&mut self, addr: u16, flags: u8) {
+        let Some(offset) = self.cdl.as_ref().and_then(|_| self.rom_offset(addr)) else { return };
+        if let Some(cdl) = &mut self.cdl {
+            cdl.mark(offset, flags);
+        }
+    }
+
+    /* What DMA reads back, without starting a transfer */
+    pub fn set_dma_page(&mut self, page: u8) {
+        self.dma.page = page;
+    }
+
+    fn is_io(addr: u16) -> bool {
+        Region::Io.contains(addr)
     }
 
     #[inline(always)]
     pub fn set_u8<T>(&mut self, index: T, value: u8) where T: Into<u16> {
-        self[index] = value;
+        let index = index.into();
+        let io = Self::is_io(index);
+        if io {
+            self.sync();
+        }
+        match index {
+            0xFF00..=0xFF7F => self.io_write(index, value),
+            0xFE00..=0xFE9F if self.dma.transfer.is_some() => (),
+            0xFEA0..=0xFEFF => (),
+            /* MBC2 cells are 4 bits wide, the upper half reads back set */
+            0xA000..=0xBFFF if self.mbc.controller == Controller::MBC2 => self[index] = value | 0xF0,
+            0xA000..=0xBFFF if self.rtc.is_some() && self.mbc.rtc_register().is_some() => {
+                self.sync();
+                if let (Some(rtc), Some(register)) = (&mut self.rtc, self.mbc.rtc_register()) {
+                    rtc.write(register, value);
+                }
+            },
+            0xA000..=0xBFFF if self.mbc.camera_registers() => {
+                self.sync();
+                if let Some(camera) = &mut self.camera {
+                    camera.write(index as usize, value);
+                }
+                self.reschedule();
+            },
+            0x6000..=0x7FFF if self.rtc.is_some() => {
+                self.sync();
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value);
+                }
+            },
+            /* Catch up first so time before the write counts toward the
+             * motor's old state */
+            0x4000..=0x5FFF if self.rumble.is_some() => {
+                self.sync();
+                self.mbc.write(index, value);
+                self.remap();
+            },
+            0x0000..=0x7FFF => {
+                self.mbc.write(index, value);
+                self.remap();
+                debug!(target: "gbemu::mem", "MBC write {:04X}={:02X}: ROM bank {}, RAM bank {:?}, RAM {}",
+                    index, value, self.rom_bank(), self.mbc.ram_bank(), if self.mbc.ram_enabled() { "on" } else { "off" });
+            },
+            _ => self[index] = value,
+        }
+        if io {
+            self.reschedule();
+        }
+        if self.hooked { self.hooks.get_mut().on_write(index, value); }
+    }
+
+    /* A write to $FF00-$FF7F: to the device claiming it, then whatever
+     * else the write sets off, or to one of the console's own registers */
+    fn io_write(&mut self, addr: u16, value: u8) {
+        if let Some(id) = self.devices.get(addr) {
+            /* The copy reads with `peek`, so the whole source is logged up front */
+            if id == DeviceId::Dma && self.cdl.is_some() {
+                let source = (value as u16) << 8;
+                (source..source + 0xA0).for_each(|addr| self.log_rom(addr, CodeDataLog::DMA));
+            }
+            self.int_flag |= self.device_mut(id).io_write(addr, value);
+            match id {
+                DeviceId::Dma => self.start_dma(value),
+                DeviceId::Timer => self.clock_div_apu(),
+                DeviceId::Joypad => if let Some(sgb) = &mut self.sgb {
+                    sgb.write_p1(value);
+                    self.joypad.set_idle_lines(sgb.idle_lines());
+                },
+                _ => (),
+            }
+            return;
+        }
+        match addr {
+            0xFF0F => self.int_flag = value,
+            /* Only the arm bit is writable, and only in CGB mode */
+            0xFF4D if self.cgb_mode => self.key1 = (self.key1 & 0x80) | 0x7E | (value & 0x01),
+            0xFF70 if self.cgb_mode => self.svbk = value & 0x07,
+            /* Boot ROM disable, one way until reset */
+            0xFF50 => {
+                if self.boot_mapped && value != 0 {
+                    info!(target: "gbemu::mem", "Boot ROM unmapped");
+                }
+                self.boot_mapped &= value == 0;
+            },
+            /* Nothing there to take the write */
+            _ => (),
+        }
+    }
+
+    fn io_port(&self, addr: u16) -> &u8 {
+        if let Some(id) = self.devices.get(addr) {
+            return self.device(id).io_register(addr);
+        }
+        match addr {
+            0xFF0F => &self.int_flag, /* Interrupt Flag */
+            0xFF4D => &self.key1, /* CGB speed switch */
+            0xFF70 if self.cgb_mode => &self.svbk, /* CGB WRAM bank */
+            _ => &0xFF, /* Nothing there, open bus */
+        }
+    }
+
+    fn io_port_mut(&mut self, addr: u16) -> &mut u8 {
+        if let Some(id) = self.devices.get(addr) {
+            return self.device_mut(id).io_register_mut(addr);
+        }
+        match addr {
+            0xFF0F => &mut self.int_flag, /* Interrupt Flag */
+            0xFF4D => &mut self.key1, /* CGB speed switch */
+            0xFF70 if self.cgb_mode => &mut self.svbk, /* CGB WRAM bank */
+            _ => &mut self.open_bus, /* Nothing there, writes go nowhere */
+        }
+    }
+
+    pub fn device(&self, id: DeviceId) -> &dyn MemoryMappedDevice {
+        match id {
+            DeviceId::Joypad => &self.joypad,
+            DeviceId::Serial => &self.serial,
+            DeviceId::Timer => &self.timer,
+            DeviceId::Ppu => &self.ppu,
+            DeviceId::Apu => &self.apu,
+            DeviceId::Dma => &self.dma,
+        }
+    }
+
+    pub fn device_mut(&mut self, id: DeviceId) -> &mut dyn MemoryMappedDevice {
+        match id {
+            DeviceId::Joypad => &mut self.joypad,
+            DeviceId::Serial => &mut self.serial,
+            DeviceId::Timer => &mut self.timer,
+            DeviceId::Ppu => &mut self.ppu,
+            DeviceId::Apu => &mut self.apu,
+            DeviceId::Dma => &mut self.dma,
+        }
+    }
+
+    pub fn devices(&self) -> &DeviceMap {
+        &self.devices
+    }
+
+    /* Every device claims its addresses again, CGB ones included or not */
+    fn map_devices(&mut self) {
+        let mut devices = DeviceMap::default();
+        for id in DeviceId::ALL {
+            devices.claim(id, self.device(id).claims(self.cgb_mode));
+        }
+        self.devices = devices;
     }
 
     pub fn set_u16<T>(&mut self, index: T, value: u16) where T: Into<u16> {
         let index = index.into();
         self.set_u8(index, (value & 0x00ff) as u8);
-        self.set_u8(index + 1, (value >> 8) as u8);
+        self.set_u8(index.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb_mode = cgb;
+        self.map_devices();
+        self.key1 = if cgb { 0x7E } else { 0xFF };
+        self.set_double_speed(false);
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.cgb_mode && self.key1 & 0x80 != 0
+    }
+
+    fn set_double_speed(&mut self, double_speed: bool) {
+        if self.cgb_mode {
+            self.key1 = if double_speed { 0xFE } else { 0x7E };
+        }
+        self.timer.set_double_speed(double_speed);
+        self.half_cycle = false;
+    }
+
+    /* STOP resets DIV as a write to it would, edges and all */
+    pub fn reset_div(&mut self) {
+        self.sync();
+        self.int_flag |= self.timer.write_register(0xFF04, 0);
+        self.clock_div_apu();
+        self.reschedule();
+    }
+
+    /* Toggles the speed if KEY1 was armed; STOP also resets DIV */
+    pub fn speed_switch(&mut self) -> bool {
+        if !self.cgb_mode || self.key1 & 0x01 == 0 {
+            return false;
+        }
+        self.sync();
+        self.set_double_speed(!self.double_speed());
+        self.reset_div();
+        debug!(target: "gbemu::mem", "Speed switch to {} speed", if self.double_speed() { "double" } else { "normal" });
+        true
+    }
+
+    /* CPU M-cycles to normal-speed M-cycles, the clock the PPU and APU
+     * keep in double speed. Odd cycle counts carry half a cycle over. */
+    fn normal_cycles(&mut self, cycles: usize) -> usize {
+        if !self.double_speed() {
+            return cycles;
+        }
+        let halves = cycles + self.half_cycle as usize;
+        self.half_cycle = halves & 1 != 0;
+        halves / 2
+    }
+
+    /* Advance the memory-mapped peripherals by `cycles` CPU M-cycles and
+     * bring them fully up to date */
+    pub fn tick(&mut self, cycles: usize) {
+        self.advance(cycles);
+        self.sync();
+    }
+
+    /* Moves the clock on, only running the peripherals once an event is due */
+    pub fn advance(&mut self, cycles: usize) {
+        self.scheduler.advance(cycles);
+        if self.scheduler.due() {
+            self.sync();
+        }
+    }
+
+    /* Runs the peripherals over the cycles they are behind and works out
+     * when they next need to run */
+    pub fn sync(&mut self) {
+        let lag = self.scheduler.lag();
+        if lag != 0 {
+            self.run_peripherals(lag);
+        }
+        self.scheduler.mark_synced();
+        self.reschedule();
+    }
+
+    /* Every event is recomputed from the peripherals' current state, which
+     * any I/O write or sync may have changed */
+    fn reschedule(&mut self) {
+        let ppu = self.ppu.cycles_to_event().map(|cycles| match self.double_speed() {
+            /* The PPU needs two CPU cycles per M-cycle, less any half cycle carried */
+            true => cycles * 2 - 1,
+            false => cycles,
+        });
+        let events = [
+            (Event::Ppu, ppu),
+            (Event::TimerOverflow, self.timer.cycles_to_overflow()),
+            (Event::DivApu, Some(self.timer.cycles_to_div_apu())),
+            (Event::Serial, self.serial.cycles_to_event()),
+            (Event::Dma, self.dma.transfer.map(|dma| dma.remaining())),
+            (Event::Camera, self.camera.as_ref().and_then(Camera::cycles_to_event)),
+        ];
+        for (event, cycles) in events {
+            match cycles {
+                Some(cycles) => self.scheduler.schedule(event, cycles),
+                None => self.scheduler.cancel(event),
+            }
+        }
+    }
+
+    fn start_dma(&mut self, page: u8) {
+        let mut dma = OamDma::new(page);
+        debug!(target: "gbemu::mem", "OAM DMA from {:04X}", super::dma::source(page));
+        if self.exact_dma {
+            self.dma.transfer = Some(dma);
+            return;
+        }
+        self.dma.transfer = None;
+        while !dma.done() {
+            if let Some((source, index)) = dma.step() {
+                self.ppu.oam[index] = self.peek(source);
+            }
+        }
+    }
+
+    /* DMA runs off the CPU clock, so it finishes twice as fast in double speed */
+    fn run_dma(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            let Some(dma) = self.dma.transfer.as_mut() else { return };
+            let step = dma.step();
+            let done = dma.done();
+            if let Some((source, index)) = step {
+                self.ppu.oam[index] = self.peek(source);
+            }
+            if done {
+                self.dma.transfer = None;
+            }
+        }
+    }
+
+    pub fn dma_active(&self) -> bool {
+        self.dma.transfer.is_some()
+    }
+
+    /* Rumble strength since last asked, None without a motor */
+    pub fn take_rumble(&mut self) -> Option<f32> {
+        self.sync();
+        self.rumble.as_mut().map(Rumble::take_strength)
+    }
+
+    /* CPU M-cycles until the next event, so an idle CPU can skip ahead */
+    pub fn until_event(&self) -> Option<usize> {
+        self.scheduler.until_next()
+    }
+
+    /* The timer and serial port follow the CPU clock, the PPU and APU do not */
+    fn run_peripherals(&mut self, cycles: usize) {
+        self.run_dma(cycles);
+        let normal = self.normal_cycles(cycles);
+        let mut irq = 0;
+        for id in DeviceId::ALL {
+            let device = self.device_mut(id);
+            irq |= device.tick(if device.cpu_clocked() { cycles } else { normal });
+            if id == DeviceId::Timer {
+                self.clock_div_apu();
+            }
+        }
+        if irq != 0 {
+            debug!(target: "gbemu::cpu::irq", "Requested {:02X}", irq);
+        }
+        self.int_flag |= irq;
+        if let Some(sgb) = self.sgb.as_mut().filter(|_| irq & Interrupt::VBlank as u8 != 0) {
+            sgb.vblank(&self.ppu);
+        }
+        let cycles = normal;
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+        if let Some(rumble) = &mut self.rumble {
+            rumble.tick(cycles, self.mbc.motor_on());
+        }
+        if let Some(image) = self.camera.as_mut().and_then(|camera| camera.tick(cycles)) {
+            let end = (IMAGE_OFFSET + image.len()).min(self.cart_ram.len());
+            if end > IMAGE_OFFSET {
+                self.cart_ram[IMAGE_OFFSET..end].copy_from_slice(&image[..end - IMAGE_OFFSET]);
+            }
+        }
+    }
+
+    /* OAM bug: a 16-bit inc/dec of a pointer into $FE00-$FEFF while the
+     * PPU scans OAM corrupts the row it is reading, like a write would */
+    pub fn idu_access(&mut self, addr: u16) {
+        if self.oam_bug && (0xFE00..=0xFEFF).contains(&addr) {
+            self.sync();
+            self.ppu.corrupt_oam_write();
+        }
+    }
+
+    /* The frame sequencer steps on DIV bit 4 rather than a clock of its own */
+    fn clock_div_apu(&mut self) {
+        for _ in 0..self.timer.take_div_apu_edges() {
+            self.apu.clock_frame_sequencer();
+        }
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.int_flag |= self.joypad.set_button(button, pressed);
+    }
+
+    /* Every button at once, one bit per `Button` */
+    pub fn set_pressed(&mut self, pressed: u8) {
+        self.int_flag |= self.joypad.set_pressed(pressed);
+    }
+
+    /* Hooks only observe accesses made through get_/set_, not raw indexing */
+    pub fn on_read<F>(&mut self, range: RangeInclusive<u16>, callback: F) -> HookId
+        where F: FnMut(u16, u8) + 'static
+    {
+        self.hooked = true;
+        self.hooks.get_mut().add_read(range, Box::new(callback))
+    }
+
+    pub fn on_write<F>(&mut self, range: RangeInclusive<u16>, callback: F) -> HookId
+        where F: FnMut(u16, u8) + 'static
+    {
+        self.hooked = true;
+        self.hooks.get_mut().add_write(range, Box::new(callback))
+    }
+
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let removed = self.hooks.get_mut().remove(id);
+        self.hooked = !self.hooks.get_mut().is_empty();
+        removed
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks.get_mut().clear();
+        self.hooked = false;
+    }
+
+    /* Reads without hooks; the unusable regions read as $FF instead of panicking */
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0xFEA0..=0xFEFF => 0xFF,
+            0xFF00..=0xFF7F => self.io_read(addr),
+            _ => self[addr],
+        }
+    }
+
+    /* An I/O register with its unused bits set, or $FF with none there */
+    fn io_read(&self, addr: u16) -> u8 {
+        match read_mask(addr, self.cgb_mode) {
+            0xFF => 0xFF,
+            mask => self[addr] | mask,
+        }
+    }
+
+    pub fn dump_region(&self, range: RangeInclusive<u16>) -> Vec<u8> {
+        range.map(|addr| self.peek(addr)).collect()
+    }
+
+    /* Everything a game could read back, for `Gba::state_hash`: the RAMs,
+     * the banks mapped, and the I/O registers as read. Scheduler timing and
+     * host-side state stay out, so only a real divergence moves the hash.
+     * Call `sync` first. */
+    pub fn hash_state(&self, hasher: &mut Xxh64) {
+        hasher.update(&self.wram);
+        hasher.update(&self.ppu.vram);
+        hasher.update(&self.ppu.oam);
+        hasher.update(&self.cart_ram);
+        hasher.update(&self.ram_stack);
+        hasher.update(&self.dump_region(0xFF00..=0xFF7F));
+        for bank in [self.rom_bank0, self.rom_bank, self.ram_bank.unwrap_or(usize::MAX)] {
+            hasher.update(&(bank as u64).to_le_bytes());
+        }
+    }
+
+    pub fn rom_bank(&self) -> usize {
+        self.rom_bank / 0x4000
+    }
+
+    pub fn ram_bank(&self) -> usize {
+        self.ram_bank.unwrap_or(0) / 0x2000
+    }
+
+    /* The cart ROM bank `addr` reads from; None outside ROM or under the boot ROM */
+    pub fn rom_bank_at(&self, addr: u16) -> Option<usize> {
+        self.rom_offset(addr).map(|offset| offset / 0x4000)
+    }
+
+    /* Offset into the cart file that `addr` reads from */
+    pub fn rom_offset(&self, addr: u16) -> Option<usize> {
+        let boot = self.boot_mapped && self.boot_rom.as_ref().is_some_and(|boot| boot.get(addr as usize).is_some());
+        if boot {
+            return None;
+        }
+        self.cart_rom_offset(Address(addr)).filter(|&offset| offset < self.cart.data.len())
+    }
+
+    pub fn memory_map(&self) -> MemoryMap {
+        MemoryMap {
+            rom_bank: self.rom_bank(),
+            ram_bank: self.ram_bank(),
+            io: IO_REGISTERS.iter()
+                .map(|&(addr, name)| IoRegister { addr, name, value: self.peek(addr) })
+                .collect(),
+        }
     }
 
     pub fn switch_rom_bank(&mut self, bank: usize) {
-        todo!("Switch ROM bank")
+        self.rom_bank = bank * 0x4000;
     }
 
     pub fn switch_ram_bank(&mut self, bank: usize) {
-        todo!("Switch RAM bank")
+        self.ram_bank = Some(bank * 0x2000);
+    }
+}
+
+/* Inherent methods take precedence, so these only forward */
+impl Bus for Mem {
+    fn get_u8(&self, addr: u16) -> u8 {
+        self.get_u8(addr)
+    }
+
+    fn set_u8(&mut self, addr: u16, value: u8) {
+        self.set_u8(addr, value)
+    }
+
+    fn get_u16(&self, addr: u16) -> u16 {
+        self.get_u16(addr)
+    }
+
+    fn set_u16(&mut self, addr: u16, value: u16) {
+        self.set_u16(addr, value)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
+    fn fetch(&mut self, addr: u16, opcode: bool) -> u8 {
+        self.fetch(addr, opcode)
+    }
+
+    fn tick(&mut self, cycles: usize) {
+        self.advance(cycles)
+    }
+
+    fn sync(&mut self) {
+        self.sync()
+    }
+
+    fn until_event(&self) -> Option<usize> {
+        self.until_event()
+    }
+
+    fn idu_access(&mut self, addr: u16) {
+        self.idu_access(addr)
+    }
+
+    fn speed_switch(&mut self) -> bool {
+        self.speed_switch()
+    }
+
+    fn reset_div(&mut self) {
+        self.reset_div()
+    }
+
+    fn double_speed(&self) -> bool {
+        self.double_speed()
+    }
+
+    fn rom_bank_at(&self, addr: u16) -> Option<usize> {
+        self.rom_bank_at(addr)
+    }
+}
+
+/* ROM and cart data are not saved; the state belongs to the loaded cart */
+impl Savestate for Mem {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.mbc.save_state(w);
+        w.bool(self.boot_mapped);
+        w.bytes(&self.cart_ram);
+        w.bytes(&self.wram);
+        w.u8(self.svbk);
+        w.u8(self.int_flag);
+        w.bytes(&self.ram_stack);
+        w.bool(self.cgb_mode);
+        w.u8(self.key1);
+        w.bool(self.half_cycle);
+        self.ppu.save_state(w);
+        self.serial.save_state(w);
+        self.joypad.save_state(w);
+        self.timer.save_state(w);
+        self.apu.save_state(w);
+        w.u8(self.dma.page);
+        OamDma::save_state(&self.dma.transfer, w);
+        w.u32(self.scheduler.lag() as u32);
+        w.bool(self.sgb.is_some());
+        if let Some(sgb) = &self.sgb {
+            sgb.save_state(w);
+        }
+        if let Some(rtc) = &self.rtc {
+            rtc.save_state(w);
+        }
+        if let Some(camera) = &self.camera {
+            camera.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.mbc.load_state(r)?;
+        self.remap();
+        self.boot_mapped = r.bool()?;
+        if self.boot_mapped && self.boot_rom.is_none() {
+            return Err(ErrorKind::InvalidData);
+        }
+        r.bytes(&mut self.cart_ram)?;
+        r.bytes(&mut self.wram)?;
+        self.svbk = r.u8()? & 0x07;
+        self.int_flag = r.u8()?;
+        r.bytes(&mut self.ram_stack)?;
+        self.cgb_mode = r.bool()?;
+        self.map_devices();
+        self.key1 = r.u8()?;
+        self.half_cycle = r.bool()?;
+        self.ppu.load_state(r)?;
+        self.serial.load_state(r)?;
+        self.joypad.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.timer.set_double_speed(self.double_speed());
+        self.apu.load_state(r)?;
+        self.dma.page = r.u8()?;
+        self.dma.transfer = OamDma::load_state(r)?;
+        self.scheduler = Scheduler::default();
+        self.scheduler.advance(r.u32()? as usize);
+        if r.bool()? != self.sgb.is_some() {
+            return Err(ErrorKind::InvalidData);
+        }
+        if let Some(sgb) = &mut self.sgb {
+            sgb.load_state(r)?;
+            self.joypad.set_idle_lines(sgb.idle_lines());
+        }
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_state(r)?;
+        }
+        if let Some(camera) = &mut self.camera {
+            camera.load_state(r)?;
+        }
+        self.reschedule();
+        Ok(())
     }
 }
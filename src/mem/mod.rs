@@ -1,13 +1,45 @@
 #![allow(unused)]
 
+mod address;
+mod archive;
+mod bus;
+mod camera;
 mod memory;
 mod cart;
 mod boot_rom;
 mod controller;
+mod device;
+mod dma;
+mod dump;
+mod fields;
+mod fill;
+mod hooks;
+mod info;
+mod io;
+mod rtc;
+mod rumble;
+mod save;
 
 pub mod prelude {
+    pub use super::address::{Address, Region};
+    pub use super::archive::{extract_rom, is_zip};
+    pub use super::bus::{Bus, FlatBus};
+    pub use super::camera::{Camera, Sensor, StillImage, IMAGE_LEN, IMAGE_OFFSET, SENSOR_HEIGHT, SENSOR_WIDTH};
+    #[cfg(feature = "webcam")]
+    pub use super::camera::Webcam;
     pub use super::memory::Mem;
-    pub use super::controller::Controller;
-    pub use super::cart::{Cart, ErrorKind};
-    pub use super::boot_rom::BOOT_ROM;
+    pub use super::controller::{Controller, Mbc};
+    pub use super::cart::{types::{CartHeader, OldLicenseeCode}, Cart, ErrorKind, HeaderError, RawMap, NINTENDO_GRAPHIC};
+    pub use super::info::CartInfo;
+    pub use super::io::read_mask;
+    pub use super::boot_rom::{Boot, BootRom, BOOT_ROM, CGB_BOOT_LEN, DMG_BOOT_LEN};
+    pub use super::hooks::HookId;
+    pub use super::fill::RamFill;
+    pub use super::device::{DeviceId, DeviceMap, MemoryMappedDevice};
+    pub use super::dma::{Dma, OamDma, OAM_DMA_LEN};
+    pub use super::dump::{hexdump, io_register_name, IoRegister, MemoryMap};
+    pub use super::fields::{decode_register, RegisterField};
+    pub use super::rtc::{Rtc, RTC_FOOTER_LEN};
+    pub use super::rumble::Rumble;
+    pub use super::save::{convert_save, split_save, SaveFormat};
 }
@@ -0,0 +1,176 @@
+use std::io::ErrorKind;
+
+use crate::state::prelude::{Savestate, StateReader, StateWriter};
+
+/* The clock runs off its own 32768 Hz crystal, so it counts normal-speed
+ * M-cycles whatever speed the CPU is in */
+const CYCLES_PER_SECOND: u32 = 1 << 20;
+
+/* Appended to the .sav by VBA, BGB and SameBoy: the live and latched
+ * registers as ten little-endian u32s, then the UNIX time of the save.
+ * Older VBA builds wrote the time as a u32. */
+pub const RTC_FOOTER_LEN: usize = 48;
+const RTC_FOOTER_LEN_32: usize = 44;
+
+/* Writable bits of S, M, H, DL and DH */
+const MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+const DH_DAY_HIGH: u8 = 0x01;
+const DH_HALT: u8 = 0x40;
+const DH_CARRY: u8 = 0x80;
+
+/* The MBC3 real-time clock, mapped at $A000 while RAM bank $08-$0C is
+ * selected. Reads see the latched copy, writes go to the live counters. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rtc {
+    /* S, M, H, DL, DH */
+    registers: [u8; 5],
+    latched: [u8; 5],
+    /* Normal-speed M-cycles into the current second */
+    subsecond: u32,
+    /* Last write to $6000-$7FFF; $00 then $01 latches */
+    latch: u8,
+}
+
+impl Rtc {
+    pub fn latched(&self, register: usize) -> &u8 {
+        &self.latched[register]
+    }
+
+    pub fn write(&mut self, register: usize, value: u8) {
+        self.registers[register] = value & MASKS[register];
+        /* Writing the seconds restarts the divider */
+        if register == 0 {
+            self.subsecond = 0;
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch == 0x00 && value == 0x01 {
+            self.latched = self.registers;
+        }
+        self.latch = value;
+    }
+
+    pub fn halted(&self) -> bool {
+        self.registers[4] & DH_HALT != 0
+    }
+
+    /* Live seconds, minutes, hours and days */
+    pub fn time(&self) -> (u8, u8, u8, u16) {
+        (self.registers[0], self.registers[1], self.registers[2], self.days())
+    }
+
+    pub fn tick(&mut self, cycles: usize) {
+        if self.halted() {
+            return;
+        }
+        self.subsecond += cycles as u32;
+        while self.subsecond >= CYCLES_PER_SECOND {
+            self.subsecond -= CYCLES_PER_SECOND;
+            self.tick_second();
+        }
+    }
+
+    /* Moves the counters on by whole seconds, as for the time the
+     * emulator was closed */
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.halted() {
+            return;
+        }
+        /* Out-of-range values written by a game count up to their wrap
+         * without carrying, which only stepping gets right */
+        while seconds > 0 && !self.in_range() {
+            self.tick_second();
+            seconds -= 1;
+        }
+        let [s, m, h, ..] = self.registers.map(u64::from);
+        let total = s + m * 60 + h * 3600 + self.days() as u64 * 86400 + seconds;
+        let days = total / 86400;
+        if days >= 512 {
+            self.registers[4] |= DH_CARRY;
+        }
+        self.registers[0] = (total % 60) as u8;
+        self.registers[1] = (total / 60 % 60) as u8;
+        self.registers[2] = (total / 3600 % 24) as u8;
+        self.set_days((days % 512) as u16);
+    }
+
+    pub fn to_footer(&self, now: u64) -> [u8; RTC_FOOTER_LEN] {
+        let mut footer = [0; RTC_FOOTER_LEN];
+        for (i, &value) in self.registers.iter().chain(&self.latched).enumerate() {
+            footer[i * 4..i * 4 + 4].copy_from_slice(&(value as u32).to_le_bytes());
+        }
+        footer[40..].copy_from_slice(&now.to_le_bytes());
+        footer
+    }
+
+    /* When a footer was written, None if it is not one */
+    pub fn footer_time(footer: &[u8]) -> Option<u64> {
+        match footer.len() {
+            RTC_FOOTER_LEN => Some(u64::from_le_bytes(footer[40..48].try_into().unwrap())),
+            RTC_FOOTER_LEN_32 => Some(u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64),
+            _ => None,
+        }
+    }
+
+    /* Restores a footer and catches up on the time since it was written */
+    pub fn from_footer(footer: &[u8], now: u64) -> Option<Self> {
+        let saved = Self::footer_time(footer)?;
+        let mut rtc = Self::default();
+        for i in 0..10 {
+            let value = footer[i * 4] & MASKS[i % 5];
+            match i < 5 {
+                true => rtc.registers[i] = value,
+                false => rtc.latched[i - 5] = value,
+            }
+        }
+        rtc.advance(now.saturating_sub(saved));
+        Some(rtc)
+    }
+
+    fn days(&self) -> u16 {
+        self.registers[3] as u16 | ((self.registers[4] & DH_DAY_HIGH) as u16) << 8
+    }
+
+    fn set_days(&mut self, days: u16) {
+        self.registers[3] = days as u8;
+        self.registers[4] = (self.registers[4] & !DH_DAY_HIGH) | (days >> 8) as u8 & DH_DAY_HIGH;
+    }
+
+    fn in_range(&self) -> bool {
+        self.registers[0] < 60 && self.registers[1] < 60 && self.registers[2] < 24
+    }
+
+    fn tick_second(&mut self) {
+        let limits = [60, 60, 24];
+        for (register, limit) in limits.into_iter().enumerate() {
+            self.registers[register] = (self.registers[register] + 1) & MASKS[register];
+            if self.registers[register] != limit {
+                return;
+            }
+            self.registers[register] = 0;
+        }
+        let days = self.days() + 1;
+        if days == 512 {
+            self.registers[4] |= DH_CARRY;
+        }
+        self.set_days(days % 512);
+    }
+}
+
+impl Savestate for Rtc {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.registers);
+        w.bytes(&self.latched);
+        w.u32(self.subsecond);
+        w.u8(self.latch);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        r.bytes(&mut self.registers)?;
+        r.bytes(&mut self.latched)?;
+        self.subsecond = r.u32()?;
+        self.latch = r.u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+/* How hard an MBC5 cart's motor is running. Games have no strength
+ * control other than switching the motor on and off faster than it can
+ * spin up, so strength is the share of time it was on since last asked. */
+#[derive(Debug, Clone, Default)]
+pub struct Rumble {
+    on_cycles: usize,
+    cycles: usize,
+}
+
+impl Rumble {
+    pub fn tick(&mut self, cycles: usize, motor_on: bool) {
+        self.cycles += cycles;
+        if motor_on {
+            self.on_cycles += cycles;
+        }
+    }
+
+    /* From 0, off throughout, to 1, on throughout, and starts over */
+    pub fn take_strength(&mut self) -> f32 {
+        let strength = match self.cycles {
+            0 => 0.0,
+            cycles => self.on_cycles as f32 / cycles as f32,
+        };
+        *self = Self::default();
+        strength
+    }
+}
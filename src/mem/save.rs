@@ -0,0 +1,52 @@
+use super::rtc::Rtc;
+
+/* Every cart RAM size is a multiple of MBC2's 512 cells, so whatever is
+ * left over is a clock footer */
+const RAM_GRANULE: usize = 0x200;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum SaveFormat {
+    /* Cart RAM and nothing else, as most emulators and flash carts expect */
+    Raw,
+    /* Cart RAM then the 48-byte VBA/BGB/SameBoy clock footer */
+    #[default]
+    Rtc,
+}
+
+impl SaveFormat {
+    pub const NAMES: [&'static str; 2] = ["raw", "rtc"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "raw" => Some(Self::Raw),
+            "rtc" => Some(Self::Rtc),
+            _ => None,
+        }
+    }
+}
+
+/* A .sav's RAM and clock footer, if it has one */
+pub fn split_save(save: &[u8]) -> (&[u8], Option<&[u8]>) {
+    match save.len() % RAM_GRANULE {
+        0 => (save, None),
+        tail => {
+            let (ram, footer) = save.split_at(save.len() - tail);
+            (ram, Rtc::footer_time(footer).is_some().then_some(footer))
+        },
+    }
+}
+
+/* Rewrites a .sav from any emulator in `format`. A footer is kept as it
+ * was saved, widened to the 64-bit timestamp; one is made up for a clock
+ * stopped at zero if the save had none. */
+pub fn convert_save(save: &[u8], format: SaveFormat, now: u64) -> Vec<u8> {
+    let (ram, footer) = split_save(save);
+    let mut converted = ram.to_vec();
+    if format == SaveFormat::Rtc {
+        /* Restored as of its own timestamp, so no time passes */
+        let time = footer.and_then(Rtc::footer_time);
+        let rtc = footer.zip(time).and_then(|(footer, time)| Rtc::from_footer(footer, time));
+        converted.extend_from_slice(&rtc.unwrap_or_default().to_footer(time.unwrap_or(now)));
+    }
+    converted
+}
@@ -0,0 +1,251 @@
+use super::{lcd::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH}, palette::Palette};
+
+/* 0x8000-0x97FF holds 384 tiles of 16 bytes */
+pub const TILE_COUNT: usize = 384;
+pub const MAP_SIZE: usize = 256;
+/* Tile sheet is 16 tiles wide */
+pub const TILE_SHEET_WIDTH: usize = 16 * 8;
+pub const TILE_SHEET_HEIGHT: usize = TILE_COUNT / 16 * 8;
+
+/* Tints blended over each layer and the outline colors */
+const LAYER_TINTS: [[u8; 3]; 3] = [[0x40, 0x80, 0xFF], [0x40, 0xFF, 0x40], [0xFF, 0x40, 0x40]];
+const SPRITE_BOX: [u8; 3] = [0xFF, 0xFF, 0x00];
+const VIEWPORT: [u8; 3] = [0xFF, 0x00, 0xFF];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TileMap {
+    Background,
+    Window,
+}
+
+/* The two banks of CGB palette RAM */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PaletteRam {
+    Background,
+    Object,
+}
+
+/* Which layer drew a pixel of the last frame */
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Layer {
+    #[default]
+    Background = 0,
+    Window,
+    Sprite,
+}
+
+/* Debug drawing over the RGB output. The shades in the framebuffer are
+ * left alone, so these can be switched between any two frames. */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DebugOverlays {
+    /* Blend each pixel with blue, green or red for BG, window or sprite */
+    pub layers: bool,
+    /* Outline every sprite on screen */
+    pub sprite_boxes: bool,
+    /* Outline the visible area on the 256x256 map images */
+    pub viewport: bool,
+}
+
+/* One OAM entry as stored, positions offset by (8, 16) */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sprite {
+    pub index: u8,
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+}
+
+impl Sprite {
+    pub fn screen_x(&self) -> i16 {
+        self.x as i16 - 8
+    }
+
+    pub fn screen_y(&self) -> i16 {
+        self.y as i16 - 16
+    }
+
+    pub fn behind_background(&self) -> bool {
+        self.attributes & 0x80 != 0
+    }
+
+    pub fn flip_y(&self) -> bool {
+        self.attributes & 0x40 != 0
+    }
+
+    pub fn flip_x(&self) -> bool {
+        self.attributes & 0x20 != 0
+    }
+
+    /* OBP0 or OBP1 */
+    pub fn dmg_palette(&self) -> u8 {
+        (self.attributes >> 4) & 0x01
+    }
+
+    pub fn cgb_bank(&self) -> u8 {
+        (self.attributes >> 3) & 0x01
+    }
+
+    pub fn cgb_palette(&self) -> u8 {
+        self.attributes & 0x07
+    }
+}
+
+impl Ppu {
+    pub fn set_overlays(&mut self, overlays: DebugOverlays) {
+        self.overlays = overlays;
+    }
+
+    /* The 8 palettes of 4 colors in CGB palette RAM, as stored in RGB555 */
+    pub fn cgb_palettes(&self, ram: PaletteRam) -> [[u16; 4]; 8] {
+        let bytes = match ram {
+            PaletteRam::Background => &self.bg_palette_ram,
+            PaletteRam::Object => &self.obj_palette_ram,
+        };
+        std::array::from_fn(|palette| std::array::from_fn(|color| {
+            let at = palette * 8 + color * 2;
+            u16::from_le_bytes([bytes[at], bytes[at + 1]])
+        }))
+    }
+
+    /* `cgb_palettes` as RGB888 through the color correction in use */
+    pub fn cgb_palettes_rgb(&self, ram: PaletteRam) -> [[[u8; 3]; 4]; 8] {
+        self.cgb_palettes(ram).map(|colors| colors.map(|color| self.color_correction.apply(color)))
+    }
+
+    /* BGP, OBP0 and OBP1 as RGB for each color index; OBJ color 0 is
+     * transparent but still shown */
+    pub fn dmg_palettes_rgb(&self) -> [[[u8; 3]; 4]; 3] {
+        [self.bgp, self.obp0, self.obp1]
+            .map(|register| std::array::from_fn(|color| self.palette.rgb((register >> (color * 2)) & 0x03)))
+    }
+
+    /* Source of each pixel of the last frame, row-major */
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /* Draws the enabled screen overlays into a 160x144 RGB frame */
+    pub(super) fn draw_overlays(&self, rgb: &mut [u8]) {
+        if self.overlays.layers {
+            for (pixel, &layer) in rgb.chunks_exact_mut(3).zip(&self.layers) {
+                let tint = LAYER_TINTS[layer as usize];
+                for (channel, tint) in pixel.iter_mut().zip(tint) {
+                    *channel = ((*channel as u16 + tint as u16) / 2) as u8;
+                }
+            }
+        }
+        if self.overlays.sprite_boxes {
+            let height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+            for sprite in self.sprites() {
+                let rect = (sprite.screen_x() as i32, sprite.screen_y() as i32, 8, height);
+                outline(rgb, (SCREEN_WIDTH, SCREEN_HEIGHT), rect, false, SPRITE_BOX);
+            }
+        }
+    }
+
+    /* `map_image` through `palette` as RGB, with the part of the map on
+     * screen outlined when the viewport overlay is on. The background
+     * viewport wraps at the map edges like SCX and SCY do. */
+    pub fn map_rgb(&self, map: TileMap, palette: &Palette) -> Vec<u8> {
+        let mut rgb: Vec<u8> = self.map_image(map).iter().flat_map(|&shade| palette.rgb(shade)).collect();
+        if self.overlays.viewport {
+            let (rect, wrap) = match map {
+                TileMap::Background => ((self.scx as i32, self.scy as i32, SCREEN_WIDTH as i32, SCREEN_HEIGHT as i32), true),
+                TileMap::Window => ((0, 0, 167 - self.wx.min(167) as i32, SCREEN_HEIGHT as i32 - self.wy as i32), false),
+            };
+            outline(&mut rgb, (MAP_SIZE, MAP_SIZE), rect, wrap, VIEWPORT);
+        }
+        rgb
+    }
+
+    /* Color ids 0-3 of tile `index` in VRAM order, row-major */
+    pub fn tile_data(&self, index: usize) -> [u8; 64] {
+        let base = (index % TILE_COUNT) * 16;
+        std::array::from_fn(|i| self.tile_row_pixel(base, i % 8, i / 8))
+    }
+
+    pub fn tiles(&self) -> Vec<[u8; 64]> {
+        (0..TILE_COUNT).map(|index| self.tile_data(index)).collect()
+    }
+
+    /* All tiles laid out 16 per row as raw color ids */
+    pub fn tile_sheet(&self) -> Vec<u8> {
+        let mut sheet = vec![0; TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT];
+        for (index, tile) in self.tiles().iter().enumerate() {
+            let (tx, ty) = ((index % 16) * 8, (index / 16) * 8);
+            for (i, &color) in tile.iter().enumerate() {
+                sheet[(ty + i / 8) * TILE_SHEET_WIDTH + tx + i % 8] = color;
+            }
+        }
+        sheet
+    }
+
+    /* The full 256x256 map LCDC selects for `map`, as shades through BGP */
+    pub fn map_image(&self, map: TileMap) -> Vec<u8> {
+        let bit = match map {
+            TileMap::Background => 0x08,
+            TileMap::Window => 0x40,
+        };
+        let map_base = if self.lcdc & bit != 0 { 0x1C00 } else { 0x1800 };
+
+        let mut image = vec![0; MAP_SIZE * MAP_SIZE];
+        for (i, pixel) in image.iter_mut().enumerate() {
+            let (x, y) = (i % MAP_SIZE, i / MAP_SIZE);
+            let tile = self.vram[map_base + (y / 8) * 32 + x / 8];
+            let color = self.tile_pixel(tile, x % 8, y % 8);
+            *pixel = (self.bgp >> (color * 2)) & 0x03;
+        }
+        image
+    }
+
+    /* Raw bytes of the first `count` BG tiles in map order, 20 per row as
+     * laid out on screen; what an SGB VRAM transfer picks up */
+    pub fn screen_tile_data(&self, count: usize) -> Vec<u8> {
+        let map_base = if self.lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+        (0..count).flat_map(|i| {
+            let tile = self.vram[map_base + (i / 20) * 32 + i % 20];
+            let base = self.tile_address(tile);
+            self.vram[base..base + 16].iter().copied()
+        }).collect()
+    }
+
+    pub fn sprites(&self) -> Vec<Sprite> {
+        self.oam.chunks_exact(4).enumerate()
+            .map(|(index, entry)| Sprite {
+                index: index as u8,
+                y: entry[0],
+                x: entry[1],
+                tile: entry[2],
+                attributes: entry[3],
+            })
+            .collect()
+    }
+}
+
+/* A one pixel rectangle on an RGB image, clipped to its edges or wrapped
+ * around them */
+fn outline(rgb: &mut [u8], (width, height): (usize, usize), (x, y, w, h): (i32, i32, i32, i32), wrap: bool, color: [u8; 3]) {
+    if w <= 0 || h <= 0 {
+        return;
+    }
+    let mut plot = |px: i32, py: i32| {
+        let (px, py) = match wrap {
+            true => (px.rem_euclid(width as i32), py.rem_euclid(height as i32)),
+            false => (px, py),
+        };
+        if (0..width as i32).contains(&px) && (0..height as i32).contains(&py) {
+            let i = (py as usize * width + px as usize) * 3;
+            rgb[i..i + 3].copy_from_slice(&color);
+        }
+    };
+    for dx in 0..w {
+        plot(x + dx, y);
+        plot(x + dx, y + h - 1);
+    }
+    for dy in 0..h {
+        plot(x, y + dy);
+        plot(x + w - 1, y + dy);
+    }
+}
@@ -0,0 +1,334 @@
+use std::{collections::VecDeque, io::ErrorKind};
+
+use crate::state::prelude::{StateReader, StateWriter};
+
+use super::{debug::{Layer, Sprite}, lcd::{Ppu, SCREEN_WIDTH}};
+
+/* Dots the first tile fetch of a line is thrown away for */
+const STARTUP_DOTS: u8 = 6;
+/* Stall while the fetcher reads a sprite's tile row */
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Renderer {
+    /* Pixel FIFO clocked every dot; mid-line register writes land on the right pixel */
+    #[default]
+    Fifo,
+    /* Whole line at once at the end of mode 3, with a fixed mode 3 length */
+    Scanline,
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+enum FetchStep {
+    #[default]
+    Tile = 0,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+impl From<u8> for FetchStep {
+    fn from(value: u8) -> Self {
+        match value {
+            0..=3 => unsafe { std::mem::transmute::<u8, FetchStep>(value) },
+            _ => panic!("Invalid value for FetchStep: {:?}", value),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct ObjPixel {
+    color: u8,
+    palette: u8,
+    behind: bool,
+}
+
+/* BG/window fetcher feeding an 8+ pixel FIFO, with sprites mixed in from
+ * a second FIFO as the LCD reaches them. Registers are read at the point the
+ * hardware would: map and tile data at fetch time, palettes and the window
+ * enable as each pixel is shifted out. */
+#[derive(Debug, Clone, Default)]
+pub(super) struct PixelFifo {
+    bg: VecDeque<u8>,
+    /* obj[0] lines up with the next pixel sent to the LCD */
+    obj: [ObjPixel; 8],
+    step: FetchStep,
+    step_dots: u8,
+    /* Tile column the fetcher is on, relative to SCX or the window's left edge */
+    fetch_x: u8,
+    tile: u8,
+    low: u8,
+    high: u8,
+    window: bool,
+    /* Pixels shifted out to the LCD so far this line */
+    x: u8,
+    /* Pixels still to drop off the front: SCX & 7, or the window's clipped edge */
+    discard: u8,
+    delay: u8,
+    stall: u8,
+    sprites: Vec<Sprite>,
+    next_sprite: usize,
+}
+
+impl Ppu {
+    /* Called as mode 3 begins */
+    pub(super) fn fifo_start_line(&mut self) {
+        let sprites = self.line_sprites();
+        self.fifo = PixelFifo {
+            discard: self.scx & 0x07,
+            delay: STARTUP_DOTS,
+            sprites,
+            ..PixelFifo::default()
+        };
+        /* WX < 7 starts the window straight away with its left edge dropped */
+        if self.window_visible() && self.wx < 7 {
+            self.fifo.window = true;
+            self.fifo.discard = 7 - self.wx;
+        }
+    }
+
+    /* DMG fetch order: the smaller X first, ties to the lower OAM index */
+    pub(super) fn line_sprites(&self) -> Vec<Sprite> {
+        let mut sprites = self.scan_oam();
+        sprites.sort_by_key(|sprite| (sprite.x, sprite.index));
+        sprites
+    }
+
+    fn window_visible(&self) -> bool {
+        self.lcdc & 0x21 == 0x21 && self.window_triggered && self.wx < 167
+    }
+
+    /* Advances one dot, returning true once all 160 pixels are out */
+    pub(super) fn fifo_dot(&mut self) -> bool {
+        /* Only reachable when switching renderers mid-line */
+        if self.fifo.x as usize == SCREEN_WIDTH {
+            return true;
+        }
+        let mut fifo = std::mem::take(&mut self.fifo);
+        self.fifo_step(&mut fifo);
+        let done = fifo.x as usize == SCREEN_WIDTH;
+        if done && fifo.window {
+            self.window_line = self.window_line.wrapping_add(1);
+        }
+        self.fifo = fifo;
+        done
+    }
+
+    fn fifo_step(&mut self, fifo: &mut PixelFifo) {
+        if fifo.delay > 0 {
+            fifo.delay -= 1;
+            return;
+        }
+        if fifo.stall > 0 {
+            fifo.stall -= 1;
+            if fifo.stall == 0 {
+                self.fetch_sprites(fifo);
+            }
+            return;
+        }
+
+        self.fetcher_step(fifo);
+        if fifo.bg.is_empty() {
+            return;
+        }
+
+        /* The window restarts the fetcher on its own map once the LCD reaches WX-7 */
+        if !fifo.window && self.wx >= 7 && fifo.x + 7 == self.wx && self.window_visible() {
+            fifo.window = true;
+            fifo.bg.clear();
+            fifo.step = FetchStep::Tile;
+            fifo.step_dots = 0;
+            fifo.fetch_x = 0;
+            fifo.discard = 0;
+            return;
+        }
+
+        if fifo.discard == 0 && self.sprite_due(fifo) {
+            if self.lcdc & 0x02 != 0 {
+                fifo.stall = SPRITE_FETCH_DOTS;
+                return;
+            }
+            self.skip_sprites(fifo);
+        }
+
+        let color = fifo.bg.pop_front().unwrap_or(0);
+        if fifo.discard > 0 {
+            fifo.discard -= 1;
+            return;
+        }
+
+        let obj = fifo.obj[0];
+        fifo.obj.rotate_left(1);
+        fifo.obj[7] = ObjPixel::default();
+
+        let color = if self.lcdc & 0x01 != 0 { color } else { 0 };
+        let (shade, layer) = if obj.color != 0 && self.lcdc & 0x02 != 0 && !(obj.behind && color != 0) {
+            let palette = if obj.palette == 0 { self.obp0 } else { self.obp1 };
+            ((palette >> (obj.color * 2)) & 0x03, Layer::Sprite)
+        } else {
+            ((self.bgp >> (color * 2)) & 0x03, if fifo.window { Layer::Window } else { Layer::Background })
+        };
+        if !self.skip_render {
+            let pixel = self.ly as usize * SCREEN_WIDTH + fifo.x as usize;
+            self.framebuffer[pixel] = shade;
+            self.layers[pixel] = layer;
+            self.obp1_pixels[pixel] = obj.palette != 0;
+        }
+        fifo.x += 1;
+    }
+
+    /* Tile number, low byte and high byte take two dots each; the push
+     * then waits until the FIFO has drained. */
+    fn fetcher_step(&self, fifo: &mut PixelFifo) {
+        if fifo.step == FetchStep::Push {
+            if fifo.bg.is_empty() {
+                for bit in (0..8).rev() {
+                    fifo.bg.push_back((((fifo.high >> bit) & 1) << 1) | ((fifo.low >> bit) & 1));
+                }
+                fifo.fetch_x = fifo.fetch_x.wrapping_add(1);
+                fifo.step = FetchStep::Tile;
+            }
+            return;
+        }
+
+        fifo.step_dots += 1;
+        if fifo.step_dots < 2 {
+            return;
+        }
+        fifo.step_dots = 0;
+
+        let row = self.fetch_row(fifo);
+        match fifo.step {
+            FetchStep::Tile => {
+                let (map_base, column) = if fifo.window {
+                    (if self.lcdc & 0x40 != 0 { 0x1C00 } else { 0x1800 }, fifo.fetch_x as usize)
+                } else {
+                    (if self.lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 }, (self.scx >> 3) as usize + fifo.fetch_x as usize)
+                };
+                fifo.tile = self.vram[map_base + (row / 8) * 32 + column % 32];
+                fifo.step = FetchStep::DataLow;
+            },
+            FetchStep::DataLow => {
+                fifo.low = self.vram[self.tile_address(fifo.tile) + (row % 8) * 2];
+                fifo.step = FetchStep::DataHigh;
+            },
+            FetchStep::DataHigh => {
+                fifo.high = self.vram[self.tile_address(fifo.tile) + (row % 8) * 2 + 1];
+                fifo.step = FetchStep::Push;
+            },
+            FetchStep::Push => unreachable!(),
+        }
+    }
+
+    /* Map row being fetched: the window's own counter, or LY scrolled by SCY */
+    fn fetch_row(&self, fifo: &PixelFifo) -> usize {
+        if fifo.window {
+            self.window_line as usize
+        } else {
+            self.ly.wrapping_add(self.scy) as usize
+        }
+    }
+
+    fn sprite_due(&self, fifo: &PixelFifo) -> bool {
+        fifo.sprites.get(fifo.next_sprite)
+            .is_some_and(|sprite| sprite.screen_x() <= fifo.x as i16)
+    }
+
+    fn skip_sprites(&self, fifo: &mut PixelFifo) {
+        while self.sprite_due(fifo) {
+            fifo.next_sprite += 1;
+        }
+    }
+
+    /* Merges every sprite starting at the current pixel into the OBJ FIFO.
+     * Pixels already held by an earlier sprite stay, which gives the DMG
+     * X-then-OAM-index priority. */
+    fn fetch_sprites(&self, fifo: &mut PixelFifo) {
+        let height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+        while self.sprite_due(fifo) {
+            let sprite = fifo.sprites[fifo.next_sprite];
+            fifo.next_sprite += 1;
+
+            let mut y = self.ly as i16 - sprite.screen_y();
+            if sprite.flip_y() {
+                y = height - 1 - y;
+            }
+            let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile } as usize;
+            for dx in 0..8 {
+                let slot = sprite.screen_x() + dx - fifo.x as i16;
+                if slot < 0 || fifo.obj[slot as usize].color != 0 {
+                    continue;
+                }
+                let tx = if sprite.flip_x() { 7 - dx } else { dx };
+                fifo.obj[slot as usize] = ObjPixel {
+                    color: self.tile_row_pixel(tile * 16, tx as usize, y as usize),
+                    palette: sprite.dmg_palette(),
+                    behind: sprite.behind_background(),
+                };
+            }
+        }
+    }
+}
+
+impl PixelFifo {
+    pub(super) fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.bg.len() as u8);
+        for &color in &self.bg {
+            w.u8(color);
+        }
+        for obj in &self.obj {
+            w.u8(obj.color);
+            w.u8(obj.palette);
+            w.bool(obj.behind);
+        }
+        w.u8(self.step as u8);
+        w.u8(self.step_dots);
+        w.u8(self.fetch_x);
+        w.u8(self.tile);
+        w.u8(self.low);
+        w.u8(self.high);
+        w.bool(self.window);
+        w.u8(self.x);
+        w.u8(self.discard);
+        w.u8(self.delay);
+        w.u8(self.stall);
+        w.u8(self.next_sprite as u8);
+    }
+
+    /* The line's sprites are not stored; they are scanned again from OAM */
+    pub(super) fn load_state(&mut self, r: &mut StateReader, sprites: Vec<Sprite>) -> Result<(), ErrorKind> {
+        let len = r.u8()?;
+        if len > 8 {
+            return Err(ErrorKind::InvalidData);
+        }
+        self.bg.clear();
+        for _ in 0..len {
+            self.bg.push_back(r.u8()? & 0x03);
+        }
+        for obj in self.obj.iter_mut() {
+            *obj = ObjPixel { color: r.u8()? & 0x03, palette: r.u8()? & 0x01, behind: r.bool()? };
+        }
+        let step = r.u8()?;
+        if step > 3 {
+            return Err(ErrorKind::InvalidData);
+        }
+        self.step = FetchStep::from(step);
+        self.step_dots = r.u8()?;
+        self.fetch_x = r.u8()?;
+        self.tile = r.u8()?;
+        self.low = r.u8()?;
+        self.high = r.u8()?;
+        self.window = r.bool()?;
+        self.x = r.u8()?;
+        self.discard = r.u8()?;
+        self.delay = r.u8()?;
+        self.stall = r.u8()?;
+        self.next_sprite = r.u8()? as usize;
+        if self.x as usize > SCREEN_WIDTH || self.next_sprite > sprites.len() {
+            return Err(ErrorKind::InvalidData);
+        }
+        self.sprites = sprites;
+        Ok(())
+    }
+}
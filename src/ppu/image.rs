@@ -0,0 +1,363 @@
+use std::{fs::File, io::{BufWriter, ErrorKind, Write}, path::Path};
+
+/* Minimal image encoders for RGB8 buffers. PNG output is uncompressed
+ * (stored deflate blocks), which keeps it dependency free. The decoder
+ * reads any non-interlaced 8-bit or palette PNG, enough for reference
+ * screenshots made by other tools. */
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+pub fn write_ppm<W: Write>(out: &mut W, width: usize, height: usize, rgb: &[u8]) -> std::io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    out.write_all(&rgb[..width * height * 3])
+}
+
+pub fn write_png<W: Write>(out: &mut W, width: usize, height: usize, rgb: &[u8]) -> std::io::Result<()> {
+    out.write_all(&PNG_SIGNATURE)?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    /* 8-bit depth, truecolor, deflate, no filter, no interlace */
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &ihdr)?;
+
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb[..width * height * 3].chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+/* Nearest-neighbour upscale of an RGB8 buffer by a whole factor */
+pub fn scale(rgb: &[u8], width: usize, height: usize, factor: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rgb.len() * factor * factor);
+    for row in rgb[..width * height * 3].chunks(width * 3) {
+        let scaled: Vec<u8> = row.chunks(3).flat_map(|pixel| pixel.repeat(factor)).collect();
+        for _ in 0..factor {
+            out.extend_from_slice(&scaled);
+        }
+    }
+    out
+}
+
+/* Choose the encoder from the file extension, defaulting to PNG */
+#[cfg(feature = "io")]
+pub fn save<P: AsRef<Path>>(path: P, width: usize, height: usize, rgb: &[u8]) -> Result<(), ErrorKind> {
+    let path = path.as_ref();
+    let mut out = BufWriter::new(File::create(path).map_err(|e| e.kind())?);
+    let ppm = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ppm"));
+    if ppm { write_ppm(&mut out, width, height, rgb) } else { write_png(&mut out, width, height, rgb) }
+        .and_then(|_| out.flush())
+        .map_err(|e| e.kind())
+}
+
+/* Decodes a PNG to (width, height, RGB8), dropping any alpha */
+pub fn read_png(data: &[u8]) -> Result<(usize, usize, Vec<u8>), ErrorKind> {
+    if !data.starts_with(&PNG_SIGNATURE) {
+        return Err(ErrorKind::InvalidData);
+    }
+    let (mut header, mut palette, mut compressed) = (None, Vec::new(), Vec::new());
+    let mut rest = &data[8..];
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let body = rest.get(8..8 + len).ok_or(ErrorKind::UnexpectedEof)?;
+        match &rest[4..8] {
+            b"IHDR" if len == 13 => header = Some((
+                u32::from_be_bytes(body[..4].try_into().unwrap()) as usize,
+                u32::from_be_bytes(body[4..8].try_into().unwrap()) as usize,
+                body[8], body[9], body[12],
+            )),
+            b"PLTE" => palette = body.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {},
+        }
+        rest = rest.get(12 + len..).ok_or(ErrorKind::UnexpectedEof)?;
+    }
+
+    let (width, height, depth, color, interlace) = header.ok_or(ErrorKind::InvalidData)?;
+    let channels = match (color, depth) {
+        (0, 1 | 2 | 4 | 8) | (3, 1 | 2 | 4 | 8) => 1,
+        (4, 8) => 2,
+        (2, 8) => 3,
+        (6, 8) => 4,
+        _ => return Err(ErrorKind::Unsupported),
+    };
+    if interlace != 0 || compressed.len() < 2 || compressed[0] & 0x0F != 8 {
+        return Err(ErrorKind::Unsupported);
+    }
+    let row = (width.saturating_mul(channels * depth as usize)).div_ceil(8);
+    let raw = inflate(&compressed[2..], height.saturating_mul(row + 1))?;
+    let pixels = unfilter(&raw, width, height, channels * depth as usize)?;
+
+    let max = (1 << depth) - 1;
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for row in pixels.chunks((width * channels * depth as usize).div_ceil(8)) {
+        for x in 0..width {
+            let sample = |i: usize| match depth {
+                8 => row[x * channels + i] as usize,
+                _ => {
+                    let bit = x * depth as usize;
+                    (row[bit / 8] as usize >> (8 - depth as usize - bit % 8)) & max
+                },
+            };
+            match color {
+                0 | 4 => rgb.extend_from_slice(&[(sample(0) * 255 / max) as u8; 3]),
+                3 => rgb.extend_from_slice(palette.get(sample(0) * 3..sample(0) * 3 + 3).ok_or(ErrorKind::InvalidData)?),
+                _ => rgb.extend_from_slice(&[sample(0) as u8, sample(1) as u8, sample(2) as u8]),
+            }
+        }
+    }
+    Ok((width, height, rgb))
+}
+
+#[cfg(feature = "io")]
+pub fn load<P: AsRef<Path>>(path: P) -> Result<(usize, usize, Vec<u8>), ErrorKind> {
+    read_png(&std::fs::read(path).map_err(|e| e.kind())?)
+}
+
+/* Undoes the per-row PNG filters; `bits` per pixel */
+fn unfilter(raw: &[u8], width: usize, height: usize, bits: usize) -> Result<Vec<u8>, ErrorKind> {
+    let stride = (width * bits).div_ceil(8);
+    let bpp = bits.div_ceil(8);
+    if raw.len() < height * (stride + 1) {
+        return Err(ErrorKind::UnexpectedEof);
+    }
+    let mut out = vec![0u8; height * stride];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for x in 0..stride {
+            let a = if x >= bpp { out[y * stride + x - bpp] } else { 0 };
+            let b = if y > 0 { out[(y - 1) * stride + x] } else { 0 };
+            let c = if x >= bpp && y > 0 { out[(y - 1) * stride + x - bpp] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(ErrorKind::InvalidData),
+            };
+            out[y * stride + x] = line[x].wrapping_add(predicted);
+        }
+    }
+    Ok(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+/* Order the code length code lengths are stored in */
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/* Deflate packs bits least significant first */
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl Bits<'_> {
+    fn read(&mut self, count: u32) -> Result<u32, ErrorKind> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos).ok_or(ErrorKind::UnexpectedEof)?;
+            value |= ((byte >> self.bit) as u32 & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                (self.pos, self.bit) = (self.pos + 1, 0);
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            (self.pos, self.bit) = (self.pos + 1, 0);
+        }
+    }
+}
+
+/* Canonical Huffman code, decoded a bit at a time */
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        lengths.iter().for_each(|&len| counts[len as usize] += 1);
+        counts[0] = 0;
+        let mut offsets = [0; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate().filter(|(_, &len)| len != 0) {
+            symbols[offsets[len as usize] as usize] = symbol as u16;
+            offsets[len as usize] += 1;
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, ErrorKind> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as usize;
+            if code < first + count as usize {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count as usize;
+            first = (first + count as usize) << 1;
+            code <<= 1;
+        }
+        Err(ErrorKind::InvalidData)
+    }
+}
+
+/* Raw deflate, as in zlib streams after the header and zip entries.
+ * Output past `limit` bytes is an error, raised before it is all in
+ * memory, so a small stream cannot expand into gigabytes. */
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, ErrorKind> {
+    let mut bits = Bits { data, pos: 0, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.read(1)? == 1;
+        let (literals, distances) = match bits.read(2)? {
+            0 => {
+                bits.align();
+                let header = data.get(bits.pos..bits.pos + 4).ok_or(ErrorKind::UnexpectedEof)?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let block = data.get(bits.pos + 4..bits.pos + 4 + len).ok_or(ErrorKind::UnexpectedEof)?;
+                if out.len() + len > limit {
+                    return Err(ErrorKind::InvalidData);
+                }
+                out.extend_from_slice(block);
+                bits.pos += 4 + len;
+                if last {
+                    return Ok(out);
+                }
+                continue;
+            },
+            1 => {
+                let lengths: Vec<u8> = (0..288).map(|symbol| match symbol {
+                    0..=143 => 8,
+                    144..=255 => 9,
+                    256..=279 => 7,
+                    _ => 8,
+                })
+                .collect();
+                (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+            },
+            2 => dynamic_tables(&mut bits)?,
+            _ => return Err(ErrorKind::InvalidData),
+        };
+
+        loop {
+            let symbol = literals.decode(&mut bits)? as usize;
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => break,
+                257..=285 => {
+                    let i = symbol - 257;
+                    let len = LENGTH_BASE[i] as usize + bits.read(LENGTH_EXTRA[i] as u32)? as usize;
+                    let i = distances.decode(&mut bits)? as usize;
+                    let dist = *DIST_BASE.get(i).ok_or(ErrorKind::InvalidData)? as usize + bits.read(DIST_EXTRA[i] as u32)? as usize;
+                    let start = out.len().checked_sub(dist).ok_or(ErrorKind::InvalidData)?;
+                    (start..start + len).for_each(|i| out.push(out[i]));
+                },
+                _ => return Err(ErrorKind::InvalidData),
+            }
+            if out.len() > limit {
+                return Err(ErrorKind::InvalidData);
+            }
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn dynamic_tables(bits: &mut Bits) -> Result<(Huffman, Huffman), ErrorKind> {
+    let literals = bits.read(5)? as usize + 257;
+    let distances = bits.read(5)? as usize + 1;
+    let code_lengths = bits.read(4)? as usize + 4;
+
+    let mut lengths = [0; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[i] = bits.read(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (len, repeat) = match code.decode(bits)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => (*lengths.last().ok_or(ErrorKind::InvalidData)?, 3 + bits.read(2)?),
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths.len() != literals + distances {
+        return Err(ErrorKind::InvalidData);
+    }
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data.iter()).copied());
+    out.write_all(&crc.to_be_bytes())
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32<I: IntoIterator<Item = u8>>(data: I) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,516 @@
+use std::{io::ErrorKind, ops::RangeInclusive};
+
+use crate::{cpu::interrupt::Interrupt, debug, mem::prelude::MemoryMappedDevice, state::prelude::{Savestate, StateReader, StateWriter}};
+
+use super::{debug::{DebugOverlays, Layer}, fifo::{PixelFifo, Renderer}, output::RgbaFrame, colorize::Colorization, palette::{ColorCorrection, Palette}};
+
+pub const DOTS_PER_LINE: usize = 456;
+pub const LINES_PER_FRAME: u8 = 154;
+pub const VISIBLE_LINES: u8 = 144;
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const OAM_SCAN_DOTS: usize = 80;
+const DRAWING_DOTS: usize = 172;
+const HBLANK_START: usize = OAM_SCAN_DOTS + DRAWING_DOTS;
+
+/* $FF46 between them is the DMA's */
+const DMG_CLAIMS: &[RangeInclusive<u16>] = &[0xFF40..=0xFF45, 0xFF47..=0xFF4B];
+const CGB_CLAIMS: &[RangeInclusive<u16>] = &[0xFF40..=0xFF45, 0xFF47..=0xFF4B, 0xFF4F..=0xFF4F, 0xFF68..=0xFF6B];
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LcdMode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/* Walks LY through all 154 lines, raising the VBlank and LYC=LY
+ * interrupts, and draws each visible line during mode 3. With the FIFO
+ * renderer mode 3 runs until the last pixel is out, so its length varies
+ * with SCX, the window and sprites as on hardware. */
+#[derive(Debug)]
+pub struct Ppu {
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+    /* Two 8kB banks; the CGB's bank 1 holds more tiles and the map attributes */
+    pub vram: Vec<u8>,
+    /* VBK: which bank the CPU sees at $8000, bit 0 */
+    pub vbk: u8,
+    pub oam: Vec<u8>,
+    /* CGB palette RAM, 8 palettes of 4 RGB555 colors each, and the
+     * BCPS/OCPS index registers addressing it through BCPD/OCPD */
+    pub bg_palette_ram: [u8; 64],
+    pub obj_palette_ram: [u8; 64],
+    pub bcps: u8,
+    pub ocps: u8,
+    /* Shades 0-3 after palette mapping, one byte per pixel */
+    pub(super) framebuffer: Vec<u8>,
+    /* Which layer each framebuffer pixel came from */
+    pub(super) layers: Vec<Layer>,
+    /* Sprite pixels drawn through OBP1 rather than OBP0 */
+    pub(super) obp1_pixels: Vec<bool>,
+    pub overlays: DebugOverlays,
+    pub palette: Palette,
+    /* CGB colors for a DMG game, used instead of `palette` when set */
+    pub colorization: Option<Colorization>,
+    pub color_correction: ColorCorrection,
+    /* Keep timing but drop pixel output, used for frame skipping */
+    pub skip_render: bool,
+    /* Debug: draw every sprite on a line instead of the first ten */
+    pub sprite_limit: bool,
+    pub renderer: Renderer,
+    /* Frames finished since power-on, counted at the start of VBlank */
+    pub frames: u64,
+    dots: usize,
+    /* Mode 3 of a visible line is still in progress */
+    drawing: bool,
+    pub(super) fifo: PixelFifo,
+    /* Window rows drawn so far this frame; only advances on lines showing it */
+    pub(super) window_line: u8,
+    /* Set once LY has matched WY this frame */
+    pub(super) window_triggered: bool,
+    /* OR of every enabled STAT source; the interrupt fires on its rising edge only */
+    stat_line: bool,
+    /* STAT interrupts raised by register writes, delivered on the next tick */
+    pending_irq: u8,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self {
+            lcdc: 0, stat: 0, scy: 0, scx: 0, ly: 0, lyc: 0,
+            bgp: 0, obp0: 0, obp1: 0, wy: 0, wx: 0,
+            vram: vec![0; 0x4000],
+            vbk: 0,
+            oam: vec![0; 0x00A0],
+            bg_palette_ram: [0; 64],
+            obj_palette_ram: [0; 64],
+            bcps: 0x40,
+            ocps: 0x40,
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            layers: vec![Layer::Background; SCREEN_WIDTH * SCREEN_HEIGHT],
+            obp1_pixels: vec![false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            overlays: DebugOverlays::default(),
+            palette: Palette::default(),
+            colorization: None,
+            color_correction: ColorCorrection::default(),
+            skip_render: false,
+            sprite_limit: true,
+            renderer: Renderer::default(),
+            frames: 0,
+            dots: 0,
+            drawing: false,
+            fifo: PixelFifo::default(),
+            window_line: 0,
+            window_triggered: false,
+            stat_line: false,
+            pending_irq: 0,
+        }
+    }
+}
+
+impl Ppu {
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.color_correction = correction;
+    }
+
+    /* Current frame as packed RGB888 through the configured palette, or
+     * the colorization if there is one */
+    pub fn rgb_framebuffer(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        self.render_rgb(&mut rgb);
+        rgb
+    }
+
+    /* The same into `rgb`, keeping its allocation from frame to frame */
+    pub fn render_rgb(&self, rgb: &mut Vec<u8>) {
+        match &self.colorization {
+            Some(colorization) => self.render_colorized(colorization, rgb),
+            None => self.render_with(&self.palette, rgb),
+        }
+    }
+
+    /* Each pixel through the palette for its layer, as the CGB shows a
+     * DMG game */
+    pub fn colorized_framebuffer(&self, colorization: &Colorization) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        self.render_colorized(colorization, &mut rgb);
+        rgb
+    }
+
+    pub fn rgb_framebuffer_with(&self, palette: &Palette) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        self.render_with(palette, &mut rgb);
+        rgb
+    }
+
+    fn render_colorized(&self, colorization: &Colorization, rgb: &mut Vec<u8>) {
+        let pixels = self.framebuffer.iter().zip(&self.layers).zip(&self.obp1_pixels);
+        rgb.clear();
+        rgb.extend(pixels.flat_map(|((&shade, &layer), &obp1)| {
+            let palette = match layer {
+                Layer::Sprite if obp1 => &colorization.obj1,
+                Layer::Sprite => &colorization.obj0,
+                _ => &colorization.bg,
+            };
+            palette[(shade & 0x03) as usize]
+        }));
+        if self.overlays != DebugOverlays::default() {
+            self.draw_overlays(rgb);
+        }
+    }
+
+    fn render_with(&self, palette: &Palette, rgb: &mut Vec<u8>) {
+        rgb.clear();
+        rgb.extend(self.framebuffer.iter().flat_map(|&shade| palette.rgb(shade)));
+        if self.overlays != DebugOverlays::default() {
+            self.draw_overlays(rgb);
+        }
+    }
+
+    /* The same as a texture, tightly packed RGBA8 */
+    pub fn rgba_framebuffer(&self) -> RgbaFrame {
+        RgbaFrame::from_rgb(&self.rgb_framebuffer(), SCREEN_WIDTH, SCREEN_HEIGHT)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    pub fn mode(&self) -> LcdMode {
+        match self.stat & 0x03 {
+            0 => LcdMode::HBlank,
+            1 => LcdMode::VBlank,
+            2 => LcdMode::OamScan,
+            _ => LcdMode::Drawing,
+        }
+    }
+
+    /* Offset into `vram` of the bank the CPU sees */
+    pub fn vram_bank(&self) -> usize {
+        (self.vbk & 0x01) as usize * 0x2000
+    }
+
+    pub fn register(&self, addr: u16) -> &u8 {
+        match addr {
+            0xFF40 => &self.lcdc,
+            0xFF41 => &self.stat,
+            0xFF42 => &self.scy,
+            0xFF43 => &self.scx,
+            0xFF44 => &self.ly,
+            0xFF45 => &self.lyc,
+            0xFF47 => &self.bgp,
+            0xFF48 => &self.obp0,
+            0xFF49 => &self.obp1,
+            0xFF4A => &self.wy,
+            0xFF4B => &self.wx,
+            0xFF4F => &self.vbk,
+            0xFF68 => &self.bcps,
+            0xFF69 => &self.bg_palette_ram[(self.bcps & 0x3F) as usize],
+            0xFF6A => &self.ocps,
+            0xFF6B => &self.obj_palette_ram[(self.ocps & 0x3F) as usize],
+            _ => panic!("Accessing memory ${:#04X}: Not an LCD register", addr),
+        }
+    }
+
+    pub fn register_mut(&mut self, addr: u16) -> &mut u8 {
+        match addr {
+            0xFF40 => &mut self.lcdc,
+            0xFF41 => &mut self.stat,
+            0xFF42 => &mut self.scy,
+            0xFF43 => &mut self.scx,
+            0xFF44 => &mut self.ly,
+            0xFF45 => &mut self.lyc,
+            0xFF47 => &mut self.bgp,
+            0xFF48 => &mut self.obp0,
+            0xFF49 => &mut self.obp1,
+            0xFF4A => &mut self.wy,
+            0xFF4B => &mut self.wx,
+            0xFF4F => &mut self.vbk,
+            0xFF68 => &mut self.bcps,
+            0xFF69 => &mut self.bg_palette_ram[(self.bcps & 0x3F) as usize],
+            0xFF6A => &mut self.ocps,
+            0xFF6B => &mut self.obj_palette_ram[(self.ocps & 0x3F) as usize],
+            _ => panic!("Accessing memory ${:#04X}: Not an LCD register", addr),
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            /* LY is read-only */
+            0xFF44 => (),
+            /* Mode and coincidence bits are read-only */
+            0xFF41 => {
+                self.stat = (value & 0x78) | (self.stat & 0x07);
+                self.pending_irq |= self.update_stat_line();
+            },
+            0xFF40 => {
+                let was_enabled = self.enabled();
+                self.lcdc = value;
+                if was_enabled != self.enabled() {
+                    debug!(target: "gbemu::ppu", "LCD {}", if self.enabled() { "on" } else { "off" });
+                }
+                if was_enabled && !self.enabled() {
+                    self.ly = 0;
+                    self.dots = 0;
+                    self.drawing = false;
+                    self.set_mode(LcdMode::HBlank);
+                    self.stat_line = false;
+                    self.window_line = 0;
+                    self.window_triggered = false;
+                }
+            },
+            0xFF45 => {
+                self.lyc = value;
+                self.update_coincidence();
+                self.pending_irq |= self.update_stat_line();
+            },
+            0xFF4F => self.vbk = value & 0x01,
+            /* Bit 6 is unused and reads back set */
+            0xFF68 => self.bcps = value | 0x40,
+            0xFF6A => self.ocps = value | 0x40,
+            /* With bit 7 of the index set, each data write moves it on */
+            0xFF69 | 0xFF6B => {
+                *self.register_mut(addr) = value;
+                let index = if addr == 0xFF69 { &mut self.bcps } else { &mut self.ocps };
+                if *index & 0x80 != 0 {
+                    *index = (*index & 0xC0) | (index.wrapping_add(1) & 0x3F);
+                }
+            },
+            _ => *self.register_mut(addr) = value,
+        }
+    }
+
+    /* Advance by `cycles` M-cycles, returning the IF bits to raise */
+    pub fn tick(&mut self, cycles: usize) -> u8 {
+        let mut irq = std::mem::take(&mut self.pending_irq);
+        if !self.enabled() {
+            return irq;
+        }
+
+        let mut remaining = cycles * 4;
+        while remaining > 0 {
+            /* Stop at every mode change so each STAT edge is seen; the FIFO is clocked every dot */
+            let fifo = self.renderer == Renderer::Fifo;
+            let boundary = if self.dots < OAM_SCAN_DOTS {
+                OAM_SCAN_DOTS
+            } else if self.drawing {
+                if fifo { self.dots + 1 } else { HBLANK_START }
+            } else {
+                DOTS_PER_LINE
+            };
+            let step = remaining.min(boundary - self.dots);
+            self.dots += step;
+            remaining -= step;
+
+            if self.drawing {
+                if fifo {
+                    self.drawing = !self.fifo_dot();
+                } else if self.dots == HBLANK_START {
+                    if !self.skip_render {
+                        self.render_scanline();
+                    }
+                    self.drawing = false;
+                }
+            } else if self.dots == OAM_SCAN_DOTS && self.ly < VISIBLE_LINES {
+                if self.ly == self.wy {
+                    self.window_triggered = true;
+                }
+                self.drawing = true;
+                if fifo {
+                    self.fifo_start_line();
+                }
+            }
+
+            if self.dots == DOTS_PER_LINE {
+                self.dots = 0;
+                self.ly = (self.ly + 1) % LINES_PER_FRAME;
+                if self.ly == 0 {
+                    self.window_line = 0;
+                    self.window_triggered = false;
+                }
+                if self.ly == VISIBLE_LINES {
+                    irq |= Interrupt::VBlank as u8;
+                    self.frames += 1;
+                }
+                self.update_coincidence();
+            }
+            self.set_mode(self.current_mode());
+            irq |= self.update_stat_line();
+        }
+        irq
+    }
+
+    /* M-cycles until the next mode change or end of line, where STAT and
+     * VBlank interrupts can fire; None while the LCD is off */
+    pub fn cycles_to_event(&self) -> Option<usize> {
+        if self.pending_irq != 0 {
+            return Some(1);
+        }
+        if !self.enabled() {
+            return None;
+        }
+        let boundary = if self.dots < OAM_SCAN_DOTS {
+            OAM_SCAN_DOTS
+        } else if self.drawing {
+            if self.renderer == Renderer::Fifo { self.dots + 1 } else { HBLANK_START }
+        } else {
+            DOTS_PER_LINE
+        };
+        Some((boundary - self.dots).div_ceil(4))
+    }
+
+    /* OAM is read as 20 rows of four words, one row per M-cycle of mode 2.
+     * A spurious write access replaces the first word of the row being
+     * read with a mix of it and the previous row, and copies the previous
+     * row's other three words over it. Row 0 is never affected. */
+    pub fn corrupt_oam_write(&mut self) {
+        if !self.enabled() || self.current_mode() != LcdMode::OamScan {
+            return;
+        }
+        let row = self.dots / 4 * 8;
+        if row == 0 {
+            return;
+        }
+        let word = |oam: &[u8], at: usize| u16::from_le_bytes([oam[at], oam[at + 1]]);
+        let (a, b, c) = (word(&self.oam, row), word(&self.oam, row - 8), word(&self.oam, row - 4));
+        let glitched = ((a ^ c) & (b ^ c)) ^ c;
+        self.oam[row..row + 2].copy_from_slice(&glitched.to_le_bytes());
+        self.oam.copy_within(row - 6..row, row + 2);
+    }
+
+    fn current_mode(&self) -> LcdMode {
+        if self.ly >= VISIBLE_LINES {
+            LcdMode::VBlank
+        } else if self.dots < OAM_SCAN_DOTS {
+            LcdMode::OamScan
+        } else if self.drawing {
+            LcdMode::Drawing
+        } else {
+            LcdMode::HBlank
+        }
+    }
+
+    fn set_mode(&mut self, mode: LcdMode) {
+        self.stat = (self.stat & !0x03) | mode as u8;
+    }
+
+    fn update_coincidence(&mut self) {
+        if self.ly == self.lyc { self.stat |= 0x04; } else { self.stat &= !0x04; }
+    }
+
+    /* Recomputes the shared STAT line, returning the IF bit on a rising edge.
+     * Sources that overlap or follow each other back to back (HBlank into
+     * VBlank, HBlank into LY=LYC) keep the line high and so block the next. */
+    fn update_stat_line(&mut self) -> u8 {
+        let mode = match self.mode() {
+            LcdMode::HBlank => self.stat & 0x08 != 0,
+            LcdMode::VBlank => self.stat & 0x10 != 0,
+            LcdMode::OamScan => self.stat & 0x20 != 0,
+            LcdMode::Drawing => false,
+        };
+        let line = self.enabled() && (mode || (self.stat & 0x44 == 0x44));
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        if rising { Interrupt::LcdStat as u8 } else { 0 }
+    }
+}
+
+impl MemoryMappedDevice for Ppu {
+    fn claims(&self, cgb: bool) -> &'static [RangeInclusive<u16>] {
+        if cgb { CGB_CLAIMS } else { DMG_CLAIMS }
+    }
+
+    fn io_register(&self, addr: u16) -> &u8 {
+        self.register(addr)
+    }
+
+    fn io_register_mut(&mut self, addr: u16) -> &mut u8 {
+        self.register_mut(addr)
+    }
+
+    fn io_write(&mut self, addr: u16, value: u8) -> u8 {
+        self.write_register(addr, value);
+        0
+    }
+
+    fn tick(&mut self, cycles: usize) -> u8 {
+        Ppu::tick(self, cycles)
+    }
+}
+
+impl Savestate for Ppu {
+    fn save_state(&self, w: &mut StateWriter) {
+        for addr in DMG_CLAIMS.iter().cloned().flatten() {
+            w.u8(*self.register(addr));
+        }
+        w.bytes(&self.vram);
+        w.u8(self.vbk);
+        w.bytes(&self.oam);
+        w.bytes(&self.bg_palette_ram);
+        w.bytes(&self.obj_palette_ram);
+        w.u8(self.bcps);
+        w.u8(self.ocps);
+        w.bytes(&self.framebuffer);
+        w.u16(self.dots as u16);
+        w.bool(self.stat_line);
+        w.u8(self.pending_irq);
+        w.u8(self.window_line);
+        w.bool(self.window_triggered);
+        w.bool(self.drawing);
+        self.fifo.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        for addr in DMG_CLAIMS.iter().cloned().flatten() {
+            *self.register_mut(addr) = r.u8()?;
+        }
+        r.bytes(&mut self.vram)?;
+        self.vbk = r.u8()? & 0x01;
+        r.bytes(&mut self.oam)?;
+        r.bytes(&mut self.bg_palette_ram)?;
+        r.bytes(&mut self.obj_palette_ram)?;
+        self.bcps = r.u8()?;
+        self.ocps = r.u8()?;
+        r.bytes(&mut self.framebuffer)?;
+        self.dots = r.u16()? as usize;
+        if self.dots >= DOTS_PER_LINE {
+            return Err(ErrorKind::InvalidData);
+        }
+        self.stat_line = r.bool()?;
+        self.pending_irq = r.u8()?;
+        self.window_line = r.u8()?;
+        self.window_triggered = r.bool()?;
+        self.drawing = r.bool()?;
+        let sprites = self.line_sprites();
+        self.fifo.load_state(r, sprites)
+    }
+}
@@ -0,0 +1,22 @@
+#![allow(unused)]
+
+mod colorize;
+mod debug;
+mod fifo;
+pub mod image;
+mod lcd;
+mod output;
+mod palette;
+mod render;
+mod video;
+
+pub mod prelude {
+    pub use super::lcd::{Ppu, LcdMode, SCREEN_WIDTH, SCREEN_HEIGHT};
+    pub use super::palette::{ColorCorrection, Palette};
+    pub use super::colorize::{Colorization, ColorizeMode, COMBINATION_COUNT};
+    pub use super::fifo::Renderer;
+    pub use super::render::SPRITES_PER_LINE;
+    pub use super::output::{RgbaFrame, ScaleMode, Shader, Viewport};
+    pub use super::video::{VideoFormat, VideoRecorder};
+    pub use super::debug::{DebugOverlays, Layer, PaletteRam, Sprite, TileMap, MAP_SIZE, TILE_COUNT, TILE_SHEET_HEIGHT, TILE_SHEET_WIDTH};
+}
@@ -0,0 +1,111 @@
+/* What frontends draw: frames as RGBA8 textures, where to put them in a
+ * window, and an optional post-processing pass in between. */
+
+/* A frame ready to upload as a texture: RGBA8 with rows `stride` bytes
+ * apart. Frames made here are tightly packed, so `stride` is `width * 4`. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaFrame {
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaFrame {
+    /* Packed RGB888 made opaque */
+    pub fn from_rgb(rgb: &[u8], width: usize, height: usize) -> Self {
+        let pixels = rgb[..width * height * 3].chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 0xFF]).collect();
+        Self { width, height, stride: width * 4, pixels }
+    }
+
+    pub fn row(&self, y: usize) -> &[u8] {
+        &self.pixels[y * self.stride..y * self.stride + self.width * 4]
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let at = y * self.stride + x * 4;
+        [self.pixels[at], self.pixels[at + 1], self.pixels[at + 2], self.pixels[at + 3]]
+    }
+
+    /* Nearest-neighbour upscale by a whole factor */
+    pub fn scale(&self, factor: usize) -> Self {
+        let width = self.width * factor;
+        let mut pixels = Vec::with_capacity(width * self.height * factor * 4);
+        for y in 0..self.height {
+            let row: Vec<u8> = self.row(y).chunks_exact(4).flat_map(|pixel| pixel.repeat(factor)).collect();
+            for _ in 0..factor {
+                pixels.extend_from_slice(&row);
+            }
+        }
+        Self { width, height: self.height * factor, stride: width * 4, pixels }
+    }
+}
+
+/* How a frame fills a window larger than itself */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ScaleMode {
+    /* The largest whole multiple that fits, so every pixel is the same size */
+    #[default]
+    Integer,
+    /* As large as fits at the frame's own aspect ratio, 10:9 for the
+     * Game Boy screen, with pixels of slightly uneven size. */
+    Aspect,
+    /* The whole window, whatever its shape */
+    Stretch,
+}
+
+impl ScaleMode {
+    pub const NAMES: [&'static str; 3] = ["integer", "aspect", "stretch"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "integer" => Some(Self::Integer),
+            "aspect" => Some(Self::Aspect),
+            "stretch" => Some(Self::Stretch),
+            _ => None,
+        }
+    }
+
+    /* The rectangle a `frame`-sized image is drawn to in `window`,
+     * centred; both as (width, height) */
+    pub fn viewport(self, window: (usize, usize), frame: (usize, usize)) -> Viewport {
+        let (width, height) = match self {
+            Self::Integer => {
+                let factor = (window.0 / frame.0).min(window.1 / frame.1).max(1);
+                (frame.0 * factor, frame.1 * factor)
+            },
+            /* Compare window.0 / window.1 with frame.0 / frame.1 without dividing */
+            Self::Aspect if window.0 * frame.1 > window.1 * frame.0 => (window.1 * frame.0 / frame.1, window.1),
+            Self::Aspect => (window.0, window.0 * frame.1 / frame.0),
+            Self::Stretch => window,
+        };
+        Viewport {
+            x: window.0.saturating_sub(width) / 2,
+            y: window.1.saturating_sub(height) / 2,
+            width,
+            height,
+        }
+    }
+}
+
+/* Where in the window a frame goes, in window pixels */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/* A post-processing pass run on each frame before the frontend sees it,
+ * on the CPU, or as a stand-in that hands the texture to a GPU shader.
+ * It may change the frame's size. */
+pub trait Shader {
+    fn apply(&mut self, frame: RgbaFrame) -> RgbaFrame;
+}
+
+impl<F> Shader for F where F: FnMut(RgbaFrame) -> RgbaFrame {
+    fn apply(&mut self, frame: RgbaFrame) -> RgbaFrame {
+        self(frame)
+    }
+}
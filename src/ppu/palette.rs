@@ -0,0 +1,99 @@
+/* RGB colors for the four DMG shades, lightest first */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Palette {
+    pub const GRAYSCALE: Self = Self([
+        [0xFF, 0xFF, 0xFF],
+        [0xAA, 0xAA, 0xAA],
+        [0x55, 0x55, 0x55],
+        [0x00, 0x00, 0x00],
+    ]);
+
+    pub const CLASSIC_GREEN: Self = Self([
+        [0x9B, 0xBC, 0x0F],
+        [0x8B, 0xAC, 0x0F],
+        [0x30, 0x62, 0x30],
+        [0x0F, 0x38, 0x0F],
+    ]);
+
+    pub const POCKET: Self = Self([
+        [0xC4, 0xCF, 0xA1],
+        [0x8B, 0x95, 0x6D],
+        [0x4D, 0x53, 0x3C],
+        [0x1F, 0x1F, 0x1F],
+    ]);
+
+    pub const PRESETS: [(&'static str, Self); 3] = [
+        ("grayscale", Self::GRAYSCALE),
+        ("green", Self::CLASSIC_GREEN),
+        ("pocket", Self::POCKET),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::PRESETS.iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+            .map(|&(_, palette)| palette)
+    }
+
+    /* Parses four `RRGGBB` hex colors separated by commas, lightest first */
+    pub fn from_hex(spec: &str) -> Option<Self> {
+        let mut colors = [[0; 3]; 4];
+        let mut parts = spec.split(',');
+        for color in colors.iter_mut() {
+            let hex = parts.next()?.trim().trim_start_matches('#');
+            let value = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6)?;
+            *color = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+        }
+        parts.next().is_none().then_some(Self(colors))
+    }
+
+    pub fn rgb(&self, shade: u8) -> [u8; 3] {
+        self.0[(shade & 0x03) as usize]
+    }
+
+    pub fn set_shade(&mut self, shade: u8, rgb: [u8; 3]) {
+        self.0[(shade & 0x03) as usize] = rgb;
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::GRAYSCALE
+    }
+}
+
+/* Mapping of CGB RGB555 colors to host RGB888 */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ColorCorrection {
+    /* Plain 5 to 8 bit expansion */
+    #[default]
+    Off,
+    /* Approximates the washed-out, color-bled look of the CGB LCD */
+    Lcd,
+}
+
+impl ColorCorrection {
+    pub fn apply(&self, rgb555: u16) -> [u8; 3] {
+        let r = (rgb555 & 0x1F) as u32;
+        let g = ((rgb555 >> 5) & 0x1F) as u32;
+        let b = ((rgb555 >> 10) & 0x1F) as u32;
+        match self {
+            Self::Off => [expand(r), expand(g), expand(b)],
+            Self::Lcd => {
+                /* Each channel peaks at 248 for pure white */
+                let scale = |v: u32| (v * 255 / 248).min(255) as u8;
+                [
+                    scale((r * 13 + g * 2 + b) >> 1),
+                    scale((g * 3 + b) << 1),
+                    scale((r * 3 + g * 2 + b * 11) >> 1),
+                ]
+            },
+        }
+    }
+}
+
+#[inline(always)]
+fn expand(c: u32) -> u8 {
+    ((c << 3) | (c >> 2)) as u8
+}
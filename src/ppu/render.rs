@@ -0,0 +1,127 @@
+use super::{debug::{Layer, Sprite}, lcd::{Ppu, SCREEN_WIDTH}};
+
+pub const SPRITES_PER_LINE: usize = 10;
+
+impl Ppu {
+    pub(super) fn render_scanline(&mut self) {
+        let line = self.ly as usize;
+        let row = line * SCREEN_WIDTH;
+
+        /* Color ids before palette mapping, needed for OBJ-to-BG priority */
+        let mut bg = [0_u8; SCREEN_WIDTH];
+        if self.lcdc & 0x01 != 0 {
+            let map_base = if self.lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+            let y = self.scy.wrapping_add(self.ly) as usize;
+            for (x, color) in bg.iter_mut().enumerate() {
+                let px = self.scx.wrapping_add(x as u8) as usize;
+                let tile = self.vram[map_base + (y / 8) * 32 + px / 8];
+                *color = self.tile_pixel(tile, px % 8, y % 8);
+            }
+        }
+        self.layers[row..row + SCREEN_WIDTH].fill(Layer::Background);
+        self.render_window(&mut bg);
+        for (x, &color) in bg.iter().enumerate() {
+            self.framebuffer[row + x] = (self.bgp >> (color * 2)) & 0x03;
+        }
+
+        if self.lcdc & 0x02 != 0 {
+            self.render_sprites(row, &bg);
+        }
+    }
+
+    /* Overlays the window from WX-7, which may start left of the screen
+     * when WX < 7. Hidden lines do not advance the window's own row counter,
+     * so toggling it mid-frame resumes where it left off. */
+    fn render_window(&mut self, bg: &mut [u8; SCREEN_WIDTH]) {
+        let visible = self.lcdc & 0x21 == 0x21 && self.window_triggered && self.wx < 167;
+        if !visible {
+            return;
+        }
+
+        let map_base = if self.lcdc & 0x40 != 0 { 0x1C00 } else { 0x1800 };
+        let y = self.window_line as usize;
+        let left = self.wx as i16 - 7;
+        let row = self.ly as usize * SCREEN_WIDTH;
+        self.layers[row + left.max(0) as usize..row + SCREEN_WIDTH].fill(Layer::Window);
+        for (x, color) in bg.iter_mut().enumerate().skip(left.max(0) as usize) {
+            let wx = (x as i16 - left) as usize;
+            let tile = self.vram[map_base + (y / 8) * 32 + wx / 8];
+            *color = self.tile_pixel(tile, wx % 8, y % 8);
+        }
+        self.window_line = self.window_line.wrapping_add(1);
+    }
+
+    /* Sprites on the current line in OAM order, at most ten unless the limit is off */
+    pub fn scan_oam(&self) -> Vec<Sprite> {
+        let height = self.sprite_height();
+        let line = self.ly as i16;
+        let limit = if self.sprite_limit { SPRITES_PER_LINE } else { usize::MAX };
+        self.sprites().into_iter()
+            .filter(|sprite| (sprite.screen_y()..sprite.screen_y() + height).contains(&line))
+            .take(limit)
+            .collect()
+    }
+
+    fn sprite_height(&self) -> i16 {
+        if self.lcdc & 0x04 != 0 { 16 } else { 8 }
+    }
+
+    fn render_sprites(&mut self, row: usize, bg: &[u8; SCREEN_WIDTH]) {
+        let sprites = self.line_sprites();
+
+        let height = self.sprite_height();
+        let mut taken = [false; SCREEN_WIDTH];
+        for sprite in sprites {
+            let mut y = self.ly as i16 - sprite.screen_y();
+            if sprite.flip_y() {
+                y = height - 1 - y;
+            }
+            /* 8x16 sprites ignore bit 0 of the tile index */
+            let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile } as usize;
+            let palette = if sprite.dmg_palette() == 0 { self.obp0 } else { self.obp1 };
+
+            for dx in 0..8 {
+                let x = sprite.screen_x() + dx;
+                if !(0..SCREEN_WIDTH as i16).contains(&x) || taken[x as usize] {
+                    continue;
+                }
+                let tx = if sprite.flip_x() { 7 - dx } else { dx };
+                let color = self.tile_row_pixel(tile * 16, tx as usize, y as usize);
+                if color == 0 {
+                    continue;
+                }
+
+                /* The highest priority opaque sprite owns the pixel even when the BG hides it */
+                let x = x as usize;
+                taken[x] = true;
+                if !(sprite.behind_background() && bg[x] != 0) {
+                    self.framebuffer[row + x] = (palette >> (color * 2)) & 0x03;
+                    self.layers[row + x] = Layer::Sprite;
+                    self.obp1_pixels[row + x] = sprite.dmg_palette() != 0;
+                }
+            }
+        }
+    }
+
+    /* Color id of a BG/window tile pixel */
+    pub(super) fn tile_pixel(&self, tile: u8, x: usize, y: usize) -> u8 {
+        self.tile_row_pixel(self.tile_address(tile), x, y)
+    }
+
+    /* VRAM offset of a BG/window tile, honouring the LCDC addressing mode */
+    pub(super) fn tile_address(&self, tile: u8) -> usize {
+        if self.lcdc & 0x10 != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8 as isize) * 16) as usize
+        }
+    }
+
+    /* Color id at (x, y) of the tile starting at VRAM offset `base` */
+    pub(super) fn tile_row_pixel(&self, base: usize, x: usize, y: usize) -> u8 {
+        let low = self.vram[base + y * 2];
+        let high = self.vram[base + y * 2 + 1];
+        let bit = 7 - x;
+        (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+    }
+}
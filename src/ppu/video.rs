@@ -0,0 +1,240 @@
+use std::{collections::HashMap, path::Path};
+
+/* Gameplay clips from RGB8 frames, encoded in memory without any codecs:
+ * Y4M is uncompressed 4:4:4 video that ffmpeg and players take as is,
+ * GIF is palettized and LZW compressed, and small for Game Boy frames. */
+
+/* 4 MiHz over the 70224 T-cycles of a frame, about 59.73 fps */
+const FRAME_RATE: (u32, u32) = (1 << 22, 70224);
+/* GIF delays are in hundredths of a second */
+const GIF_TICKS: u64 = 100;
+const GIF_MAX_CODES: u16 = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VideoFormat {
+    Y4m,
+    Gif,
+}
+
+impl VideoFormat {
+    /* GIF for a .gif path, Y4M for anything else */
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif")) {
+            true => Self::Gif,
+            false => Self::Y4m,
+        }
+    }
+}
+
+/* Encodes frames as they are pushed, up to an optional limit */
+#[derive(Debug, Clone)]
+pub struct VideoRecorder {
+    format: VideoFormat,
+    width: usize,
+    height: usize,
+    limit: Option<u32>,
+    frames: u32,
+    out: Vec<u8>,
+    /* GIF only: the last frame written, and where its delay is so a
+     * repeat can lengthen it instead of storing the frame again */
+    last: Vec<u8>,
+    delay_at: usize,
+    /* Frame time not yet given to a delay, in GIF ticks times FRAME_RATE.0 */
+    owed: u64,
+}
+
+impl VideoRecorder {
+    pub fn new(format: VideoFormat, width: usize, height: usize, limit: Option<u32>) -> Self {
+        let mut out = Vec::new();
+        match format {
+            VideoFormat::Y4m => out.extend_from_slice(format!(
+                "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444\n", width, height, FRAME_RATE.0, FRAME_RATE.1).as_bytes()),
+            VideoFormat::Gif => {
+                out.extend_from_slice(b"GIF89a");
+                out.extend_from_slice(&(width as u16).to_le_bytes());
+                out.extend_from_slice(&(height as u16).to_le_bytes());
+                /* No global color table; each frame has its own */
+                out.extend_from_slice(&[0x00, 0x00, 0x00]);
+                /* Loop forever */
+                out.extend_from_slice(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+            },
+        }
+        Self { format, width, height, limit, frames: 0, out, last: Vec::new(), delay_at: 0, owed: 0 }
+    }
+
+    pub fn format(&self) -> VideoFormat {
+        self.format
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /* Reached the frame limit; further frames are ignored */
+    pub fn full(&self) -> bool {
+        self.limit.is_some_and(|limit| self.frames >= limit)
+    }
+
+    pub fn push(&mut self, rgb: &[u8]) {
+        if self.full() {
+            return;
+        }
+        let rgb = &rgb[..self.width * self.height * 3];
+        match self.format {
+            VideoFormat::Y4m => self.push_y4m(rgb),
+            VideoFormat::Gif => self.push_gif(rgb),
+        }
+        self.frames += 1;
+    }
+
+    /* The finished file */
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.format == VideoFormat::Gif {
+            self.out.push(0x3B);
+        }
+        self.out
+    }
+
+    /* BT.601 full range, one plane at a time */
+    fn push_y4m(&mut self, rgb: &[u8]) {
+        self.out.extend_from_slice(b"FRAME\n");
+        let pixels = || rgb.chunks_exact(3).map(|p| (p[0] as f32, p[1] as f32, p[2] as f32));
+        let planes: [fn(f32, f32, f32) -> f32; 3] = [
+            |r, g, b| 0.299 * r + 0.587 * g + 0.114 * b,
+            |r, g, b| 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b,
+            |r, g, b| 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b,
+        ];
+        for plane in planes {
+            self.out.extend(pixels().map(|(r, g, b)| plane(r, g, b).round().clamp(0.0, 255.0) as u8));
+        }
+    }
+
+    fn push_gif(&mut self, rgb: &[u8]) {
+        /* Whole ticks this frame is shown for, carrying the remainder */
+        self.owed += GIF_TICKS * FRAME_RATE.1 as u64;
+        let ticks = self.owed / FRAME_RATE.0 as u64;
+        self.owed %= FRAME_RATE.0 as u64;
+
+        if rgb == self.last.as_slice() {
+            let delay = u16::from_le_bytes([self.out[self.delay_at], self.out[self.delay_at + 1]]);
+            if let Some(delay) = delay.checked_add(ticks as u16) {
+                self.out[self.delay_at..self.delay_at + 2].copy_from_slice(&delay.to_le_bytes());
+                return;
+            }
+        }
+        self.last = rgb.to_vec();
+
+        let (palette, indices) = palettize(rgb);
+        /* At least 2 bits per code, enough for the palette */
+        let bits = (usize::BITS - (palette.len() - 1).leading_zeros()).max(2) as u8;
+
+        self.out.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+        self.delay_at = self.out.len();
+        self.out.extend_from_slice(&(ticks as u16).to_le_bytes());
+        self.out.extend_from_slice(&[0x00, 0x00]);
+
+        self.out.push(0x2C);
+        self.out.extend_from_slice(&[0; 4]);
+        self.out.extend_from_slice(&(self.width as u16).to_le_bytes());
+        self.out.extend_from_slice(&(self.height as u16).to_le_bytes());
+        self.out.push(0x80 | (bits - 1));
+        for i in 0..1 << bits {
+            self.out.extend_from_slice(palette.get(i).map_or(&[0; 3], |color| color));
+        }
+
+        self.out.push(bits);
+        for block in lzw(&indices, bits).chunks(255) {
+            self.out.push(block.len() as u8);
+            self.out.extend_from_slice(block);
+        }
+        self.out.push(0x00);
+    }
+}
+
+/* Up to 256 colors and an index per pixel. Frames with more, which only
+ * CGB mid-frame palette tricks make, are cut down to RGB 3-3-2. */
+fn palettize(rgb: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut palette = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::with_capacity(rgb.len() / 3);
+    for pixel in rgb.chunks_exact(3) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = *lookup.entry(color).or_insert_with(|| {
+            palette.push(color);
+            palette.len() - 1
+        });
+        if index > 0xFF {
+            return quantize(rgb);
+        }
+        indices.push(index as u8);
+    }
+    (palette, indices)
+}
+
+fn quantize(rgb: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let level = |value: u16, max: u16| (value * 255 / max) as u8;
+    let palette = (0..=255_u16).map(|i| [level(i >> 5, 7), level(i >> 2 & 0x07, 7), level(i & 0x03, 3)]).collect();
+    let indices = rgb.chunks_exact(3).map(|p| (p[0] & 0xE0) | (p[1] >> 5) << 2 | p[2] >> 6).collect();
+    (palette, indices)
+}
+
+/* GIF's LZW: variable width codes from `bits + 1`, packed LSB first, with
+ * a clear code whenever the table fills */
+fn lzw(indices: &[u8], bits: u8) -> Vec<u8> {
+    let clear = 1_u16 << bits;
+    let end = clear + 1;
+    let mut out = Vec::new();
+    let (mut acc, mut acc_bits) = (0_u32, 0);
+    let mut emit = |code: u16, width: u32, out: &mut Vec<u8>| {
+        acc |= (code as u32) << acc_bits;
+        acc_bits += width;
+        while acc_bits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            acc_bits -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut width = bits as u32 + 1;
+    emit(clear, width, &mut out);
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        let Some(current) = prefix else {
+            prefix = Some(index as u16);
+            continue;
+        };
+        if let Some(&code) = table.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+        emit(current, width, &mut out);
+        if next == GIF_MAX_CODES {
+            emit(clear, width, &mut out);
+            table.clear();
+            next = end + 1;
+            width = bits as u32 + 1;
+        } else {
+            table.insert((current, index), next);
+            /* The decoder widens once it has assigned the last code of a width */
+            if next == 1 << width {
+                width += 1;
+            }
+            next += 1;
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(code) = prefix {
+        emit(code, width, &mut out);
+        /* The decoder adds an entry for this code too, and may widen for it */
+        if next < GIF_MAX_CODES && next == 1 << width {
+            width += 1;
+        }
+    }
+    emit(end, width, &mut out);
+    if acc_bits > 0 {
+        out.push(acc as u8);
+    }
+    out
+}
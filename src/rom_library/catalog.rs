@@ -0,0 +1,114 @@
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    config::prelude::Config,
+    error::prelude::GbError,
+    mem::prelude::{Cart, Controller},
+    state::prelude::{slot_path, Slot, SLOTS},
+};
+
+/* What a ROM picker lists: every ROM under a directory, read only as far
+ * as its header, with what the player has left behind for it. Files that
+ * cannot be read or have no usable header are set aside, not fatal. */
+
+const EXTENSIONS: [&str; 4] = ["gb", "gbc", "sgb", "zip"];
+
+/* A filled save slot, with the picture to show for it */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotPreview {
+    pub slot: u8,
+    /* UNIX seconds */
+    pub timestamp: u64,
+    /* THUMBNAIL_WIDTH x THUMBNAIL_HEIGHT RGB888 */
+    pub thumbnail: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub title: String,
+    pub cart_type: String,
+    /* None when the mapper is not emulated */
+    pub mapper: Option<Controller>,
+    /* Of the ROM itself, unzipped */
+    pub size: usize,
+    pub cgb: bool,
+    /* `Cart::header_hash`, naming the game's `[games.ID]` section */
+    pub header_hash: String,
+    /* UNIX seconds of the newest battery save or slot, None if never played */
+    pub last_played: Option<u64>,
+    pub slots: Vec<SlotPreview>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Library {
+    /* By title, ignoring case, then by path */
+    pub roms: Vec<RomEntry>,
+    /* Files that looked like ROMs but could not be catalogued */
+    pub skipped: Vec<(PathBuf, GbError)>,
+}
+
+/* Every ROM under `dir` and its subdirectories, with saves and slots
+ * found where `config` puts them */
+pub fn scan(dir: &Path, config: &Config) -> Library {
+    let mut paths = Vec::new();
+    collect(dir, &mut paths);
+    let mut library = Library::default();
+    for path in paths {
+        match entry(&path, config) {
+            Ok(rom) => library.roms.push(rom),
+            Err(e) => library.skipped.push((path, e)),
+        }
+    }
+    library.roms.sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()).then_with(|| a.path.cmp(&b.path)));
+    library.skipped.sort_by(|a, b| a.0.cmp(&b.0));
+    library
+}
+
+fn collect(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            collect(&path, paths);
+        } else if path.extension().and_then(|ext| ext.to_str())
+            .is_some_and(|ext| EXTENSIONS.iter().any(|known| known.eq_ignore_ascii_case(ext))) {
+            paths.push(path);
+        }
+    }
+}
+
+fn entry(path: &Path, config: &Config) -> Result<RomEntry, GbError> {
+    let cart = Cart::parse(std::fs::read(path)?)?;
+    let header_hash = cart.header_hash();
+    let save = config.save_path(path);
+    let slot_dir = config.for_game(&header_hash).slot_dir;
+    let slots: Vec<SlotPreview> = (0..SLOTS)
+        .filter_map(|slot| {
+            let file = match &slot_dir {
+                Some(dir) => dir.join(slot_path(&save, slot).file_name()?),
+                None => slot_path(&save, slot),
+            };
+            let Slot { timestamp, thumbnail, .. } = Slot::from_bytes(&std::fs::read(file).ok()?).ok()?;
+            Some(SlotPreview { slot, timestamp, thumbnail })
+        })
+        .collect();
+    let saved = std::fs::metadata(&save).and_then(|meta| meta.modified()).ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs());
+    let last_played = slots.iter().map(|slot| slot.timestamp).chain(saved).max();
+
+    Ok(RomEntry {
+        path: path.to_path_buf(),
+        title: cart.header.title_str().to_string(),
+        cart_type: cart.header.cart_type.name().to_string(),
+        mapper: Controller::detect(&cart),
+        size: cart.data_len,
+        cgb: cart.header.is_cgb(),
+        header_hash,
+        last_played,
+        slots,
+    })
+}
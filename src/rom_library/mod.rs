@@ -0,0 +1,7 @@
+#![allow(unused)]
+
+mod catalog;
+
+pub mod prelude {
+    pub use super::catalog::{scan, Library, RomEntry, SlotPreview};
+}
@@ -0,0 +1,7 @@
+#![allow(unused)]
+
+mod queue;
+
+pub mod prelude {
+    pub use super::queue::{Event, Scheduler};
+}
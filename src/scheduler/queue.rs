@@ -0,0 +1,72 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+/* The points at which a peripheral can raise an interrupt or otherwise
+ * change something the CPU would see without touching its registers */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /* PPU mode change or end of line */
+    Ppu,
+    TimerOverflow,
+    /* DIV-APU edge clocking the frame sequencer */
+    DivApu,
+    /* Transfer complete, or the next poll of a connected cable */
+    Serial,
+    /* OAM DMA done, giving OAM back to the CPU */
+    Dma,
+    /* Pocket Camera capture done */
+    Camera,
+}
+
+/* Future events keyed by the CPU M-cycle they fall on. The clock runs
+ * ahead of the peripherals, which only catch up (sync) once an event is
+ * due or their registers are accessed, so deadlines are given relative
+ * to the last sync. */
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    now: u64,
+    synced: u64,
+    queue: BinaryHeap<Reverse<(u64, Event)>>,
+}
+
+impl Scheduler {
+    pub fn advance(&mut self, cycles: usize) {
+        self.now += cycles as u64;
+    }
+
+    /* CPU M-cycles the peripherals are behind */
+    pub fn lag(&self) -> usize {
+        (self.now - self.synced) as usize
+    }
+
+    /* The peripherals have caught up; everything queued is stale */
+    pub fn mark_synced(&mut self) {
+        self.synced = self.now;
+        self.queue.clear();
+    }
+
+    /* `event` fires `cycles` after the last sync, replacing any earlier schedule */
+    pub fn schedule(&mut self, event: Event, cycles: usize) {
+        self.cancel(event);
+        self.queue.push(Reverse((self.synced + cycles as u64, event)));
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.queue.retain(|&Reverse((_, queued))| queued != event);
+    }
+
+    pub fn due(&self) -> bool {
+        self.queue.peek().is_some_and(|&Reverse((at, _))| at <= self.now)
+    }
+
+    pub fn pop_due(&mut self) -> Option<Event> {
+        if !self.due() {
+            return None;
+        }
+        self.queue.pop().map(|Reverse((_, event))| event)
+    }
+
+    /* CPU M-cycles from now until the earliest event, None with nothing queued */
+    pub fn until_next(&self) -> Option<usize> {
+        self.queue.peek().map(|&Reverse((at, _))| at.saturating_sub(self.now) as usize)
+    }
+}
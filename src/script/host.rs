@@ -0,0 +1,172 @@
+use std::{cell::RefCell, ops::RangeInclusive, rc::Rc};
+
+use crate::{
+    cpu::prelude::Registers,
+    error::prelude::GbError,
+    gba::prelude::{Emulator, Gba},
+    mem::prelude::{HookId, Mem},
+};
+
+use super::overlay::Overlay;
+
+/* What a hook may touch: the console, and text over the current frame */
+pub struct ScriptContext<'a> {
+    pub gba: &'a mut Gba,
+    pub overlay: &'a mut Overlay,
+    /* Frames run since the host was created */
+    pub frame: u64,
+}
+
+impl ScriptContext<'_> {
+    /* Reads without side effects */
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.gba.mem.peek(addr)
+    }
+
+    /* Writes as the CPU would; script writes do not fire write hooks */
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.gba.mem.set_u8(addr, value);
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.gba.cpu.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.gba.cpu.registers
+    }
+
+    pub fn text(&mut self, x: i32, y: i32, text: &str) {
+        self.overlay.text(x, y, text, [0xFF; 3]);
+    }
+}
+
+/* Event handlers driven by a `ScriptHost`, either written in Rust or
+ * loaded from a `TextScript` */
+pub trait Script {
+    /* Ranges whose CPU reads (false) or writes (true) reach `on_read` and `on_write` */
+    fn watches(&self) -> Vec<(RangeInclusive<u16>, bool)> {
+        Vec::new()
+    }
+
+    fn on_frame_start(&mut self, ctx: &mut ScriptContext) {}
+
+    fn on_frame_end(&mut self, ctx: &mut ScriptContext) {}
+
+    /* Called after the instruction that made the access */
+    fn on_read(&mut self, ctx: &mut ScriptContext, addr: u16, value: u8) {}
+
+    fn on_write(&mut self, ctx: &mut ScriptContext, addr: u16, value: u8) {}
+
+    fn on_save_state(&mut self, ctx: &mut ScriptContext) {}
+
+    fn on_load_state(&mut self, ctx: &mut ScriptContext) {}
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Access {
+    addr: u16,
+    value: u8,
+    write: bool,
+}
+
+struct Loaded {
+    script: Box<dyn Script>,
+    watches: Vec<(RangeInclusive<u16>, bool)>,
+    hooks: Vec<HookId>,
+}
+
+/* Runs frames with scripts attached. Memory hooks only log accesses; the
+ * scripts see them between instructions, with the whole console in reach. */
+#[derive(Default)]
+pub struct ScriptHost {
+    scripts: Vec<Loaded>,
+    overlay: Overlay,
+    frame: u64,
+    accesses: Rc<RefCell<Vec<Access>>>,
+}
+
+impl ScriptHost {
+    pub fn add(&mut self, mem: &mut Mem, script: Box<dyn Script>) {
+        let watches = script.watches();
+        let hooks = watches.iter().map(|(range, write)| {
+            let (accesses, write) = (Rc::clone(&self.accesses), *write);
+            let log = move |addr, value| accesses.borrow_mut().push(Access { addr, value, write });
+            match write {
+                true => mem.on_write(range.clone(), log),
+                false => mem.on_read(range.clone(), log),
+            }
+        })
+        .collect();
+        self.scripts.push(Loaded { script, watches, hooks });
+    }
+
+    pub fn clear(&mut self, mem: &mut Mem) {
+        for loaded in self.scripts.drain(..) {
+            loaded.hooks.into_iter().for_each(|hook| { mem.remove_hook(hook); });
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /* What the scripts drew during the last frame */
+    pub fn overlay(&self) -> &Overlay {
+        &self.overlay
+    }
+
+    pub fn run_frame(&mut self, gba: &mut Gba) -> usize {
+        self.overlay.clear();
+        self.accesses.borrow_mut().clear();
+        self.each(gba, |script, ctx| script.on_frame_start(ctx));
+        let cycles = gba.run_frame_with(|gba| self.dispatch(gba));
+        self.each(gba, |script, ctx| script.on_frame_end(ctx));
+        self.frame += 1;
+        cycles
+    }
+
+    pub fn save_state(&mut self, emulator: &mut Emulator) -> Vec<u8> {
+        let state = emulator.save_state();
+        self.each(emulator.console_mut(), |script, ctx| script.on_save_state(ctx));
+        state
+    }
+
+    pub fn load_state(&mut self, emulator: &mut Emulator, state: &[u8]) -> Result<(), GbError> {
+        emulator.load_state(state)?;
+        self.each(emulator.console_mut(), |script, ctx| script.on_load_state(ctx));
+        Ok(())
+    }
+
+    fn dispatch(&mut self, gba: &mut Gba) {
+        let accesses = std::mem::take(&mut *self.accesses.borrow_mut());
+        if accesses.is_empty() {
+            return;
+        }
+        let mut ctx = ScriptContext { gba, overlay: &mut self.overlay, frame: self.frame };
+        for access in accesses {
+            for loaded in &mut self.scripts {
+                let watched = loaded.watches.iter().any(|(range, write)| *write == access.write && range.contains(&access.addr));
+                match (watched, access.write) {
+                    (false, _) => {},
+                    (true, true) => loaded.script.on_write(&mut ctx, access.addr, access.value),
+                    (true, false) => loaded.script.on_read(&mut ctx, access.addr, access.value),
+                }
+            }
+        }
+        /* Drop whatever the handlers themselves touched */
+        self.accesses.borrow_mut().clear();
+    }
+
+    fn each<F: FnMut(&mut dyn Script, &mut ScriptContext)>(&mut self, gba: &mut Gba, mut f: F) {
+        let mut ctx = ScriptContext { gba, overlay: &mut self.overlay, frame: self.frame };
+        for loaded in &mut self.scripts {
+            f(loaded.script.as_mut(), &mut ctx);
+        }
+        self.accesses.borrow_mut().clear();
+    }
+}
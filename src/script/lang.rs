@@ -0,0 +1,360 @@
+use std::{fmt, ops::RangeInclusive};
+
+use crate::{
+    cpu::prelude::{Flags, Register16, Register8},
+    debugger::prelude::{Expr, UnaryOp},
+    info,
+};
+
+use super::host::{Script, ScriptContext};
+
+/* Names every handler can read besides the script's own variables */
+const BUILTINS: [&str; 3] = ["addr", "value", "frame"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Trigger {
+    FrameStart,
+    FrameEnd,
+    Read(RangeInclusive<u16>),
+    Write(RangeInclusive<u16>),
+    Save,
+    Load,
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Register(Register8),
+    Pair(Register16),
+    Flag(Flags),
+    Variable(usize),
+    Memory(Expr),
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Literal(String),
+    Value(Expr, bool),
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Assign(Target, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    Text(Expr, Expr, Vec<Piece>),
+    Log(Vec<Piece>),
+}
+
+/* A script in the built-in language, loaded at run time:
+ *
+ *     var lives = 3
+ *     on write $C0A0..$C0A1        # also read, frame_start, frame_end, save, load
+ *         if value == 0 && lives > 0
+ *             [addr] = 9           # memory; registers and flags assign by name
+ *             lives = lives - 1
+ *         end
+ *     end
+ *     on frame_end
+ *         text 2, 2, "HP {[$C0A0]} PC {PC:x}"
+ *     end
+ *
+ * Expressions are debugger expressions plus the script's variables and
+ * `addr`, `value` (the access that fired the handler) and `frame`.
+ *
+ * A language of our own rather than embedded Lua or Rhai: the crate takes
+ * no dependencies, and the debugger's expression parser already covers
+ * most of what a hook needs. Another engine can still plug in through
+ * `Script`. */
+#[derive(Debug, Clone)]
+pub struct TextScript {
+    names: Vec<String>,
+    values: Vec<i64>,
+    handlers: Vec<(Trigger, Vec<Stmt>)>,
+}
+
+impl TextScript {
+    pub fn parse(source: &str) -> Result<Self, ScriptError> {
+        let mut script = Self { names: Vec::new(), values: Vec::new(), handlers: Vec::new() };
+        let mut lines = source.lines().enumerate().map(|(i, line)| (i + 1, strip_comment(line).trim())).filter(|(_, line)| !line.is_empty());
+
+        while let Some((number, line)) = lines.next() {
+            let error = |message: String| ScriptError { line: number, message };
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match keyword {
+                "var" => {
+                    let (name, value) = rest.split_once('=').ok_or_else(|| error("expected `var NAME = NUMBER`".to_string()))?;
+                    let name = name.trim();
+                    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                    if !valid || Expr::parse(name).is_ok() || BUILTINS.contains(&name) || script.names.iter().any(|known| known == name) {
+                        return Err(error(format!("`{}` cannot be a variable name", name)));
+                    }
+                    let value = constant(value).ok_or_else(|| error("variables start as a number".to_string()))?;
+                    script.names.push(name.to_string());
+                    script.values.push(value);
+                },
+                "on" => {
+                    let trigger = trigger(rest.trim()).map_err(error)?;
+                    let (body, end) = script.block(&mut lines)?;
+                    if end != "end" {
+                        return Err(ScriptError { line: number, message: "handler must close with `end`".to_string() });
+                    }
+                    script.handlers.push((trigger, body));
+                },
+                _ => return Err(error(format!("expected `var` or `on`, found `{}`", keyword))),
+            }
+        }
+        Ok(script)
+    }
+
+    /* Statements up to the `end` or `else` closing the block, returned with it */
+    fn block<'a, I: Iterator<Item = (usize, &'a str)>>(&self, lines: &mut I) -> Result<(Vec<Stmt>, &'a str), ScriptError> {
+        let mut body = Vec::new();
+        let mut last = 0;
+        while let Some((number, line)) = lines.next() {
+            last = number;
+            let error = |message: String| ScriptError { line: number, message };
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match keyword {
+                "end" | "else" if rest.is_empty() => return Ok((body, keyword)),
+                "if" => {
+                    let condition = self.expr(rest).map_err(error)?;
+                    let (then, end) = self.block(lines)?;
+                    let otherwise = match end {
+                        "else" => match self.block(lines)? {
+                            (otherwise, "end") => otherwise,
+                            _ => return Err(error("`if` has two `else`s".to_string())),
+                        },
+                        _ => Vec::new(),
+                    };
+                    body.push(Stmt::If(condition, then, otherwise));
+                },
+                "text" => {
+                    let (position, format) = rest.split_once('"').ok_or_else(|| error("expected `text X, Y, \"TEXT\"`".to_string()))?;
+                    let mut position = position.trim().trim_end_matches(',').split(',');
+                    let (Some(x), Some(y), None) = (position.next(), position.next(), position.next()) else {
+                        return Err(error("expected `text X, Y, \"TEXT\"`".to_string()));
+                    };
+                    let text = self.format(&format!("\"{}", format)).map_err(error)?;
+                    body.push(Stmt::Text(self.expr(x).map_err(error)?, self.expr(y).map_err(error)?, text));
+                },
+                "log" => body.push(Stmt::Log(self.format(rest).map_err(error)?)),
+                _ => {
+                    let (target, value) = split_assignment(line).ok_or_else(|| error(format!("unknown statement `{}`", line)))?;
+                    let target = match self.expr(target).map_err(error)? {
+                        Expr::Register(reg) => Target::Register(reg),
+                        Expr::Pair(reg) => Target::Pair(reg),
+                        Expr::Flag(flag) => Target::Flag(flag),
+                        Expr::Read(addr) => Target::Memory(*addr),
+                        Expr::Variable(name) => match self.names.iter().position(|known| *known == name) {
+                            Some(index) => Target::Variable(index),
+                            None => return Err(error(format!("`{}` is read-only", name))),
+                        },
+                        _ => return Err(error(format!("cannot assign to `{}`", target.trim()))),
+                    };
+                    body.push(Stmt::Assign(target, self.expr(value).map_err(error)?));
+                },
+            }
+        }
+        Err(ScriptError { line: last, message: "missing `end`".to_string() })
+    }
+
+    fn expr(&self, text: &str) -> Result<Expr, String> {
+        let names: Vec<&str> = self.names.iter().map(String::as_str).chain(BUILTINS).collect();
+        Expr::parse_with(text.trim(), &names).map_err(|e| format!("`{}`: {}", text.trim(), e))
+    }
+
+    /* A quoted string with `{expr}` or `{expr:x}` for hex */
+    fn format(&self, text: &str) -> Result<Vec<Piece>, String> {
+        let text = text.trim();
+        let inner = text.strip_prefix('"').and_then(|text| text.strip_suffix('"')).ok_or("expected a quoted string")?;
+        let mut pieces = Vec::new();
+        let mut rest = inner;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                pieces.push(Piece::Literal(rest[..open].to_string()));
+            }
+            let close = rest[open..].find('}').ok_or("unclosed `{`")? + open;
+            let field = &rest[open + 1..close];
+            let (field, hex) = match field.strip_suffix(":x") {
+                Some(field) => (field, true),
+                None => (field, false),
+            };
+            pieces.push(Piece::Value(self.expr(field)?, hex));
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            pieces.push(Piece::Literal(rest.to_string()));
+        }
+        Ok(pieces)
+    }
+
+    fn run(&mut self, ctx: &mut ScriptContext, wanted: impl Fn(&Trigger) -> bool, addr: u16, value: u8) {
+        let mut env = Env { names: &self.names, values: &mut self.values, addr, value };
+        for (trigger, body) in &self.handlers {
+            if wanted(trigger) {
+                env.exec(ctx, body);
+            }
+        }
+    }
+}
+
+impl Script for TextScript {
+    fn watches(&self) -> Vec<(RangeInclusive<u16>, bool)> {
+        self.handlers.iter().filter_map(|(trigger, _)| match trigger {
+            Trigger::Read(range) => Some((range.clone(), false)),
+            Trigger::Write(range) => Some((range.clone(), true)),
+            _ => None,
+        })
+        .collect()
+    }
+
+    fn on_frame_start(&mut self, ctx: &mut ScriptContext) {
+        self.run(ctx, |trigger| *trigger == Trigger::FrameStart, 0, 0);
+    }
+
+    fn on_frame_end(&mut self, ctx: &mut ScriptContext) {
+        self.run(ctx, |trigger| *trigger == Trigger::FrameEnd, 0, 0);
+    }
+
+    fn on_read(&mut self, ctx: &mut ScriptContext, addr: u16, value: u8) {
+        self.run(ctx, |trigger| matches!(trigger, Trigger::Read(range) if range.contains(&addr)), addr, value);
+    }
+
+    fn on_write(&mut self, ctx: &mut ScriptContext, addr: u16, value: u8) {
+        self.run(ctx, |trigger| matches!(trigger, Trigger::Write(range) if range.contains(&addr)), addr, value);
+    }
+
+    fn on_save_state(&mut self, ctx: &mut ScriptContext) {
+        self.run(ctx, |trigger| *trigger == Trigger::Save, 0, 0);
+    }
+
+    fn on_load_state(&mut self, ctx: &mut ScriptContext) {
+        self.run(ctx, |trigger| *trigger == Trigger::Load, 0, 0);
+    }
+}
+
+/* Variables in scope while a handler runs */
+struct Env<'a> {
+    names: &'a [String],
+    values: &'a mut [i64],
+    addr: u16,
+    value: u8,
+}
+
+impl Env<'_> {
+    fn eval(&self, ctx: &ScriptContext, expr: &Expr) -> i64 {
+        expr.eval_with(&*ctx.gba, &|name| match name {
+            "addr" => self.addr as i64,
+            "value" => self.value as i64,
+            "frame" => ctx.frame as i64,
+            _ => self.names.iter().position(|known| known == name).map_or(0, |index| self.values[index]),
+        })
+    }
+
+    fn exec(&mut self, ctx: &mut ScriptContext, body: &[Stmt]) {
+        for stmt in body {
+            match stmt {
+                Stmt::Assign(target, expr) => {
+                    let value = self.eval(ctx, expr);
+                    let registers = ctx.registers_mut();
+                    match target {
+                        Target::Register(reg) => registers.set_r8(*reg, value as u8),
+                        Target::Pair(reg) => registers.set_r16(*reg, value as u16),
+                        Target::Flag(flag) if value != 0 => registers.f.set(*flag),
+                        Target::Flag(flag) => registers.f.unset(*flag),
+                        Target::Variable(index) => self.values[*index] = value,
+                        Target::Memory(addr) => {
+                            let addr = self.eval(ctx, addr) as u16;
+                            ctx.poke(addr, value as u8);
+                        },
+                    }
+                },
+                Stmt::If(condition, then, otherwise) => {
+                    let body = if self.eval(ctx, condition) != 0 { then } else { otherwise };
+                    self.exec(ctx, body);
+                },
+                Stmt::Text(x, y, pieces) => {
+                    let (x, y, text) = (self.eval(ctx, x), self.eval(ctx, y), self.render(ctx, pieces));
+                    ctx.text(x as i32, y as i32, &text);
+                },
+                Stmt::Log(pieces) => info!(target: "gbemu::script", "{}", self.render(ctx, pieces)),
+            }
+        }
+    }
+
+    fn render(&self, ctx: &ScriptContext, pieces: &[Piece]) -> String {
+        pieces.iter().map(|piece| match piece {
+            Piece::Literal(text) => text.clone(),
+            Piece::Value(expr, true) => format!("{:X}", self.eval(ctx, expr)),
+            Piece::Value(expr, false) => self.eval(ctx, expr).to_string(),
+        })
+        .collect()
+    }
+}
+
+fn trigger(text: &str) -> Result<Trigger, String> {
+    let (event, range) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let range = || -> Result<RangeInclusive<u16>, String> {
+        let (start, end) = range.split_once("..").unwrap_or((range, range));
+        let addr = |text: &str| match constant(text) {
+            Some(addr @ 0..=0xFFFF) => Ok(addr as u16),
+            _ => Err(format!("`{}` is not an address", text.trim())),
+        };
+        Ok(addr(start)?..=addr(end)?)
+    };
+    match event {
+        "frame_start" => Ok(Trigger::FrameStart),
+        "frame_end" => Ok(Trigger::FrameEnd),
+        "save" => Ok(Trigger::Save),
+        "load" => Ok(Trigger::Load),
+        "read" => Ok(Trigger::Read(range()?)),
+        "write" => Ok(Trigger::Write(range()?)),
+        _ => Err(format!("unknown event `{}`", event)),
+    }
+}
+
+fn constant(text: &str) -> Option<i64> {
+    match Expr::parse(text).ok()? {
+        Expr::Number(value) => Some(value),
+        Expr::Unary(UnaryOp::Negate, operand) => match *operand {
+            Expr::Number(value) => Some(-value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/* Splits `target = value` at an `=` that is not part of a comparison */
+fn split_assignment(line: &str) -> Option<(&str, &str)> {
+    let bytes = line.as_bytes();
+    let at = (0..bytes.len()).find(|&i| {
+        bytes[i] == b'='
+            && (i == 0 || !b"=!<>".contains(&bytes[i - 1]))
+            && bytes.get(i + 1) != Some(&b'=')
+    })?;
+    Some((&line[..at], &line[at + 1..]))
+}
+
+/* Everything before a `#` outside quotes */
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {},
+        }
+    }
+    line
+}
@@ -0,0 +1,11 @@
+#![allow(unused)]
+
+mod host;
+mod lang;
+mod overlay;
+
+pub mod prelude {
+    pub use super::host::{Script, ScriptContext, ScriptHost};
+    pub use super::lang::{ScriptError, TextScript};
+    pub use super::overlay::{Overlay, OverlayText, GLYPH_HEIGHT, GLYPH_WIDTH};
+}
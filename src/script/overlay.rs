@@ -0,0 +1,91 @@
+/* 3x5 glyphs for ' ' through '_', one row per byte with bit 2 leftmost */
+const FONT: [[u8; 5]; 64] = [
+    [0, 0, 0, 0, 0], [2, 2, 2, 0, 2], [5, 5, 0, 0, 0], [5, 7, 5, 7, 5], /*   ! " # */
+    [3, 6, 2, 3, 6], [5, 1, 2, 4, 5], [2, 5, 2, 5, 3], [2, 2, 0, 0, 0], /* $ % & ' */
+    [1, 2, 2, 2, 1], [4, 2, 2, 2, 4], [0, 5, 2, 5, 0], [0, 2, 7, 2, 0], /* ( ) * + */
+    [0, 0, 0, 2, 4], [0, 0, 7, 0, 0], [0, 0, 0, 0, 2], [1, 1, 2, 4, 4], /* , - . / */
+    [7, 5, 5, 5, 7], [2, 6, 2, 2, 7], [7, 1, 7, 4, 7], [7, 1, 3, 1, 7], /* 0 1 2 3 */
+    [5, 5, 7, 1, 1], [7, 4, 7, 1, 7], [7, 4, 7, 5, 7], [7, 1, 1, 1, 1], /* 4 5 6 7 */
+    [7, 5, 7, 5, 7], [7, 5, 7, 1, 7], [0, 2, 0, 2, 0], [0, 2, 0, 2, 4], /* 8 9 : ; */
+    [1, 2, 4, 2, 1], [0, 7, 0, 7, 0], [4, 2, 1, 2, 4], [7, 1, 3, 0, 2], /* < = > ? */
+    [7, 5, 7, 4, 7], [2, 5, 7, 5, 5], [6, 5, 6, 5, 6], [3, 4, 4, 4, 3], /* @ A B C */
+    [6, 5, 5, 5, 6], [7, 4, 6, 4, 7], [7, 4, 6, 4, 4], [3, 4, 5, 5, 3], /* D E F G */
+    [5, 5, 7, 5, 5], [7, 2, 2, 2, 7], [1, 1, 1, 5, 2], [5, 5, 6, 5, 5], /* H I J K */
+    [4, 4, 4, 4, 7], [5, 7, 7, 5, 5], [6, 5, 5, 5, 5], [2, 5, 5, 5, 2], /* L M N O */
+    [6, 5, 6, 4, 4], [2, 5, 5, 6, 3], [6, 5, 6, 5, 5], [3, 4, 2, 1, 6], /* P Q R S */
+    [7, 2, 2, 2, 2], [5, 5, 5, 5, 7], [5, 5, 5, 5, 2], [5, 5, 7, 7, 5], /* T U V W */
+    [5, 5, 2, 5, 5], [5, 5, 2, 2, 2], [7, 1, 2, 4, 7], [3, 2, 2, 2, 3], /* X Y Z [ */
+    [4, 4, 2, 1, 1], [6, 2, 2, 2, 6], [2, 5, 0, 0, 0], [0, 0, 0, 0, 7], /* \ ] ^ _ */
+];
+
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlayText {
+    pub x: i32,
+    pub y: i32,
+    pub text: String,
+    pub color: [u8; 3],
+}
+
+/* Text drawn by scripts over the frame, kept separate from the emulated
+ * picture so screenshots and movies can leave it out */
+#[derive(Debug, Clone, Default)]
+pub struct Overlay {
+    texts: Vec<OverlayText>,
+}
+
+impl Overlay {
+    pub fn text(&mut self, x: i32, y: i32, text: &str, color: [u8; 3]) {
+        self.texts.push(OverlayText { x, y, text: text.to_string(), color });
+    }
+
+    pub fn texts(&self) -> &[OverlayText] {
+        &self.texts
+    }
+
+    pub fn clear(&mut self) {
+        self.texts.clear();
+    }
+
+    /* Draws every text onto a packed RGB888 frame, each glyph shadowed
+     * so it stays readable on any background. Lower case is drawn as
+     * upper case and anything else outside the font as `?`. */
+    pub fn draw(&self, rgb: &mut [u8], width: usize, height: usize) {
+        let mut plot = |x: i32, y: i32, color: [u8; 3]| {
+            if (0..width as i32).contains(&x) && (0..height as i32).contains(&y) {
+                let i = (y as usize * width + x as usize) * 3;
+                rgb[i..i + 3].copy_from_slice(&color);
+            }
+        };
+        for text in &self.texts {
+            let (mut x, mut y) = (text.x, text.y);
+            for c in text.text.chars() {
+                if c == '\n' {
+                    (x, y) = (text.x, y + GLYPH_HEIGHT as i32);
+                    continue;
+                }
+                let glyph = glyph(c);
+                for (shadow, color) in [(1, [0; 3]), (0, text.color)] {
+                    for (row, bits) in glyph.iter().enumerate() {
+                        for col in 0..3 {
+                            if bits & (4 >> col) != 0 {
+                                plot(x + col + shadow, y + row as i32 + shadow, color);
+                            }
+                        }
+                    }
+                }
+                x += GLYPH_WIDTH as i32;
+            }
+        }
+    }
+}
+
+fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    match c {
+        ' '..='_' => FONT[c as usize - ' ' as usize],
+        _ => FONT['?' as usize - ' ' as usize],
+    }
+}
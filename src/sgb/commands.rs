@@ -0,0 +1,293 @@
+use std::io::ErrorKind;
+
+use crate::{
+    debug,
+    ppu::prelude::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH},
+    state::prelude::{Savestate, StateReader, StateWriter},
+};
+
+use super::packet::{PacketReceiver, PACKET_LEN};
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+/* VRAM transfers always move 4 KiB, the first 256 tiles on screen */
+pub const TRANSFER_LEN: usize = 0x1000;
+/* 512 system palettes of 4 colors */
+pub const SYSTEM_PALETTES: usize = TRANSFER_LEN / 8;
+/* 256 4bpp SNES tiles, sent as two CHR_TRN halves */
+pub const BORDER_TILES_LEN: usize = 0x2000;
+/* 32x28 map entries, only the first 0x700 bytes of PCT_TRN */
+pub const BORDER_MAP_LEN: usize = 32 * 28;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mask {
+    None,
+    Freeze,
+    Black,
+    Color0,
+}
+
+impl From<u8> for Mask {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::None,
+            1 => Self::Freeze,
+            2 => Self::Black,
+            3 => Self::Color0,
+            _ => panic!("Invalid value for Mask: {:?}", value),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Transfer {
+    Palettes,
+    Tiles(bool),
+    Border,
+}
+
+/* Super Game Boy side of the cart: command packets sent over P1, the
+ * palettes and border they set up, and the multiplayer ID read back
+ * through P1. Attribute files and sound commands are not handled. */
+#[derive(Debug)]
+pub struct Sgb {
+    receiver: PacketReceiver,
+    /* Continuation packets still to come for the current command */
+    remaining: u8,
+    /* BGR555; color 0 is shared between all four */
+    pub palettes: [[u16; 4]; 4],
+    system_palettes: Vec<u16>,
+    pub border_tiles: Vec<u8>,
+    pub border_map: Vec<u16>,
+    /* Border palettes 4-7 */
+    pub border_palettes: [[u16; 16]; 4],
+    pub mask: Mask,
+    /* Shades held on screen while the mask is frozen */
+    frozen: Option<Vec<u8>>,
+    players: u8,
+    player: u8,
+    last_p1: u8,
+    pending: Option<Transfer>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self {
+            receiver: PacketReceiver::default(),
+            remaining: 0,
+            palettes: [[0x7FFF, 0x56B5, 0x294A, 0x0000]; 4],
+            system_palettes: vec![0; SYSTEM_PALETTES * 4],
+            border_tiles: vec![0; BORDER_TILES_LEN],
+            border_map: vec![0; BORDER_MAP_LEN],
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::None,
+            frozen: None,
+            players: 1,
+            player: 0,
+            last_p1: 0x30,
+            pending: None,
+        }
+    }
+}
+
+impl Sgb {
+    pub fn write_p1(&mut self, value: u8) {
+        let rising = value & !self.last_p1 & 0x20 != 0;
+        if rising && self.players > 1 && !self.receiver.receiving() {
+            self.player = (self.player + 1) % self.players;
+        }
+        self.last_p1 = value & 0x30;
+        if let Some(packet) = self.receiver.write(value) {
+            self.packet(&packet);
+        }
+    }
+
+    /* Low nibble P1 reads with neither row selected */
+    pub fn idle_lines(&self) -> u8 {
+        0x0F - self.player
+    }
+
+    pub fn players(&self) -> u8 {
+        self.players
+    }
+
+    fn packet(&mut self, packet: &[u8; PACKET_LEN]) {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            return;
+        }
+        let command = packet[0] >> 3;
+        self.remaining = (packet[0] & 0x07).max(1) - 1;
+        let data = &packet[1..];
+        match command {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            PAL_SET => {
+                for (i, palette) in self.palettes.iter_mut().enumerate() {
+                    let index = word(data, i * 2) as usize & 0x1FF;
+                    palette.copy_from_slice(&self.system_palettes[index * 4..index * 4 + 4]);
+                }
+                let color0 = self.palettes[0][0];
+                self.palettes.iter_mut().for_each(|palette| palette[0] = color0);
+                if data[8] & 0x40 != 0 {
+                    self.set_mask(Mask::None);
+                }
+            },
+            PAL_TRN => self.pending = Some(Transfer::Palettes),
+            MLT_REQ => {
+                self.players = match data[0] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            },
+            CHR_TRN => self.pending = Some(Transfer::Tiles(data[0] & 0x01 != 0)),
+            PCT_TRN => self.pending = Some(Transfer::Border),
+            MASK_EN => self.set_mask(Mask::from(data[0] & 0x03)),
+            _ => debug!(target: "gbemu::sgb", "Ignored command {:02X}", command),
+        }
+    }
+
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color0 = word(data, 0);
+        for i in 0..3 {
+            self.palettes[first][i + 1] = word(data, 2 + i * 2);
+            self.palettes[second][i + 1] = word(data, 8 + i * 2);
+        }
+        self.palettes.iter_mut().for_each(|palette| palette[0] = color0);
+    }
+
+    fn set_mask(&mut self, mask: Mask) {
+        self.mask = mask;
+        if mask != Mask::Freeze {
+            self.frozen = None;
+        }
+    }
+
+    /* Transfers read VRAM once the frame that set it up has been drawn */
+    pub fn vblank(&mut self, ppu: &Ppu) {
+        if self.mask == Mask::Freeze && self.frozen.is_none() {
+            self.frozen = Some(ppu.framebuffer().to_vec());
+        }
+        let Some(transfer) = self.pending.take() else { return };
+        let data = ppu.screen_tile_data(TRANSFER_LEN / 16);
+        match transfer {
+            Transfer::Palettes => {
+                for (color, bytes) in self.system_palettes.iter_mut().zip(data.chunks_exact(2)) {
+                    *color = word(bytes, 0);
+                }
+            },
+            Transfer::Tiles(high) => {
+                let base = if high { TRANSFER_LEN } else { 0 };
+                self.border_tiles[base..base + TRANSFER_LEN].copy_from_slice(&data);
+            },
+            Transfer::Border => {
+                for (entry, bytes) in self.border_map.iter_mut().zip(data.chunks_exact(2)) {
+                    *entry = word(bytes, 0);
+                }
+                for (i, palette) in self.border_palettes.iter_mut().enumerate() {
+                    for (j, color) in palette.iter_mut().enumerate() {
+                        *color = word(&data, 0x800 + (i * 16 + j) * 2);
+                    }
+                }
+            },
+        }
+        debug!(target: "gbemu::sgb", "VRAM transfer {:?}", transfer);
+    }
+
+    /* Shades to show in the game window, honouring a frozen mask */
+    pub fn screen<'a>(&'a self, shades: &'a [u8]) -> &'a [u8] {
+        match &self.frozen {
+            Some(frozen) if self.mask == Mask::Freeze => frozen,
+            _ => shades,
+        }
+    }
+}
+
+fn word(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+impl Savestate for Sgb {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.receiver.save_state(w);
+        w.u8(self.remaining);
+        self.palettes.iter().flatten().for_each(|&color| w.u16(color));
+        self.system_palettes.iter().for_each(|&color| w.u16(color));
+        w.bytes(&self.border_tiles);
+        self.border_map.iter().for_each(|&entry| w.u16(entry));
+        self.border_palettes.iter().flatten().for_each(|&color| w.u16(color));
+        w.u8(self.mask as u8);
+        w.bool(self.frozen.is_some());
+        if let Some(frozen) = &self.frozen {
+            w.bytes(frozen);
+        }
+        w.u8(self.players);
+        w.u8(self.player);
+        w.u8(self.last_p1);
+        w.u8(match self.pending {
+            None => 0,
+            Some(Transfer::Palettes) => 1,
+            Some(Transfer::Tiles(false)) => 2,
+            Some(Transfer::Tiles(true)) => 3,
+            Some(Transfer::Border) => 4,
+        });
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.receiver.load_state(r)?;
+        self.remaining = r.u8()?;
+        for color in self.palettes.iter_mut().flatten() {
+            *color = r.u16()?;
+        }
+        for color in self.system_palettes.iter_mut() {
+            *color = r.u16()?;
+        }
+        r.bytes(&mut self.border_tiles)?;
+        for entry in self.border_map.iter_mut() {
+            *entry = r.u16()?;
+        }
+        for color in self.border_palettes.iter_mut().flatten() {
+            *color = r.u16()?;
+        }
+        self.mask = match r.u8()? {
+            mask @ 0..=3 => Mask::from(mask),
+            _ => return Err(ErrorKind::InvalidData),
+        };
+        self.frozen = match r.bool()? {
+            true => {
+                let mut frozen = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+                r.bytes(&mut frozen)?;
+                Some(frozen)
+            },
+            false => None,
+        };
+        self.players = r.u8()?;
+        self.player = r.u8()?;
+        if !matches!(self.players, 1 | 2 | 4) || self.player >= self.players {
+            return Err(ErrorKind::InvalidData);
+        }
+        self.last_p1 = r.u8()?;
+        self.pending = match r.u8()? {
+            0 => None,
+            1 => Some(Transfer::Palettes),
+            2 => Some(Transfer::Tiles(false)),
+            3 => Some(Transfer::Tiles(true)),
+            4 => Some(Transfer::Border),
+            _ => return Err(ErrorKind::InvalidData),
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,11 @@
+#![allow(unused)]
+
+mod commands;
+mod packet;
+mod render;
+
+pub mod prelude {
+    pub use super::commands::{Mask, Sgb};
+    pub use super::packet::PacketReceiver;
+    pub use super::render::{bgr555_to_rgb, SGB_HEIGHT, SGB_WIDTH};
+}
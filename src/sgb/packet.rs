@@ -0,0 +1,71 @@
+use std::io::ErrorKind;
+
+use crate::state::prelude::{Savestate, StateReader, StateWriter};
+
+pub const PACKET_LEN: usize = 16;
+
+/* Turns P1 writes into 16-byte SGB packets. Both lines low starts a
+ * packet; then each bit is P14 low for 0 or P15 low for 1, with both
+ * lines high in between. 128 data bits are followed by a 0 stop bit. */
+#[derive(Debug, Default)]
+pub struct PacketReceiver {
+    receiving: bool,
+    bits: usize,
+    packet: [u8; PACKET_LEN],
+    /* Lines as last written, so a bit only counts after an idle */
+    last: u8,
+}
+
+impl PacketReceiver {
+    /* Returns a packet once its stop bit arrives */
+    pub fn write(&mut self, p1: u8) -> Option<[u8; PACKET_LEN]> {
+        let lines = p1 & 0x30;
+        let idle = self.last == 0x30;
+        self.last = lines;
+        match lines {
+            0x00 => {
+                self.receiving = true;
+                self.bits = 0;
+                self.packet = [0; PACKET_LEN];
+                None
+            },
+            0x10 | 0x20 if self.receiving && idle => {
+                let one = lines == 0x10;
+                if self.bits < PACKET_LEN * 8 {
+                    self.packet[self.bits / 8] |= (one as u8) << (self.bits % 8);
+                    self.bits += 1;
+                    None
+                } else {
+                    /* A 1 where the stop bit should be drops the packet */
+                    self.receiving = false;
+                    if one { None } else { Some(self.packet) }
+                }
+            },
+            _ => None,
+        }
+    }
+
+    pub fn receiving(&self) -> bool {
+        self.receiving
+    }
+}
+
+impl Savestate for PacketReceiver {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.receiving);
+        w.u8(self.bits as u8);
+        w.bytes(&self.packet);
+        w.u8(self.last);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.receiving = r.bool()?;
+        self.bits = r.u8()? as usize;
+        if self.bits > PACKET_LEN * 8 {
+            return Err(ErrorKind::InvalidData);
+        }
+        r.bytes(&mut self.packet)?;
+        self.last = r.u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use crate::ppu::prelude::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use super::commands::{Mask, Sgb};
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+/* Top-left corner of the game window inside the border */
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+/* Expands 5-bit channels to 8 */
+pub fn bgr555_to_rgb(color: u16) -> [u8; 3] {
+    let channel = |shift: u16| {
+        let value = ((color >> shift) & 0x1F) as u8;
+        (value << 3) | (value >> 2)
+    };
+    [channel(0), channel(5), channel(10)]
+}
+
+impl Sgb {
+    /* The 256x224 bordered frame as packed RGB888, with the game's shades
+     * colored through SGB palette 0 */
+    pub fn render(&self, shades: &[u8]) -> Vec<u8> {
+        let backdrop = bgr555_to_rgb(self.palettes[0][0]);
+        let mut frame: Vec<[u8; 3]> = vec![backdrop; SGB_WIDTH * SGB_HEIGHT];
+
+        let screen = self.screen(shades);
+        for (i, &shade) in screen.iter().enumerate().take(SCREEN_WIDTH * SCREEN_HEIGHT) {
+            let color = match self.mask {
+                Mask::Black => 0,
+                Mask::Color0 => self.palettes[0][0],
+                _ => self.palettes[0][(shade & 0x03) as usize],
+            };
+            let (x, y) = (SCREEN_X + i % SCREEN_WIDTH, SCREEN_Y + i / SCREEN_WIDTH);
+            frame[y * SGB_WIDTH + x] = bgr555_to_rgb(color);
+        }
+
+        for (i, &entry) in self.border_map.iter().enumerate() {
+            let (tx, ty) = ((i % 32) * 8, (i / 32) * 8);
+            let palette = &self.border_palettes[((entry >> 10) & 0x03) as usize];
+            for y in 0..8 {
+                for x in 0..8 {
+                    let color = self.border_pixel(entry, x, y);
+                    if color != 0 {
+                        frame[(ty + y) * SGB_WIDTH + tx + x] = bgr555_to_rgb(palette[color as usize]);
+                    }
+                }
+            }
+        }
+        frame.into_iter().flatten().collect()
+    }
+
+    /* Color index 0-15 of a map entry's 4bpp SNES tile, honouring its flips */
+    fn border_pixel(&self, entry: u16, x: usize, y: usize) -> u8 {
+        let x = if entry & 0x4000 != 0 { 7 - x } else { x };
+        let y = if entry & 0x8000 != 0 { 7 - y } else { y };
+        let base = (entry & 0xFF) as usize * 32;
+        let bit = 7 - x;
+        [0, 1, 16, 17].iter().enumerate().fold(0, |color, (plane, &offset)| {
+            color | (((self.border_tiles[base + y * 2 + offset] >> bit) & 1) << plane)
+        })
+    }
+}
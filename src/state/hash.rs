@@ -0,0 +1,165 @@
+use std::{collections::VecDeque, hash::Hasher};
+
+const P1: u64 = 0x9E37_79B1_85EB_CA87;
+const P2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const P3: u64 = 0x1656_67B1_9E37_79F9;
+const P4: u64 = 0x85EB_CA77_C2B2_AE63;
+const P5: u64 = 0x27D4_EB2F_1656_67C5;
+
+/* XXH64, fed a piece at a time. Fast enough to run over all of RAM every
+ * frame, and the same on every host, so two machines can compare digests. */
+#[derive(Debug, Clone)]
+pub struct Xxh64 {
+    seed: u64,
+    acc: [u64; 4],
+    buffer: [u8; 32],
+    buffered: usize,
+    total: u64,
+}
+
+impl Default for Xxh64 {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Xxh64 {
+    pub fn new(seed: u64) -> Self {
+        let acc = [seed.wrapping_add(P1).wrapping_add(P2), seed.wrapping_add(P2), seed, seed.wrapping_sub(P1)];
+        Self { seed, acc, buffer: [0; 32], buffered: 0, total: 0 }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.total += bytes.len() as u64;
+        if self.buffered > 0 {
+            let take = bytes.len().min(32 - self.buffered);
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&bytes[..take]);
+            self.buffered += take;
+            bytes = &bytes[take..];
+            if self.buffered < 32 {
+                return;
+            }
+            let buffer = self.buffer;
+            self.stripe(&buffer);
+            self.buffered = 0;
+        }
+        let mut stripes = bytes.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    pub fn digest(&self) -> u64 {
+        let mut hash = match self.total {
+            0..32 => self.seed.wrapping_add(P5),
+            _ => {
+                let [a, b, c, d] = self.acc;
+                let hash = a.rotate_left(1).wrapping_add(b.rotate_left(7)).wrapping_add(c.rotate_left(12)).wrapping_add(d.rotate_left(18));
+                self.acc.iter().fold(hash, |hash, &acc| merge(hash, acc))
+            },
+        };
+        hash = hash.wrapping_add(self.total);
+
+        let mut rest = &self.buffer[..self.buffered];
+        while let [a, b, c, d, e, f, g, h, tail @ ..] = rest {
+            hash ^= round(0, u64::from_le_bytes([*a, *b, *c, *d, *e, *f, *g, *h]));
+            hash = hash.rotate_left(27).wrapping_mul(P1).wrapping_add(P4);
+            rest = tail;
+        }
+        if let [a, b, c, d, tail @ ..] = rest {
+            hash ^= (u32::from_le_bytes([*a, *b, *c, *d]) as u64).wrapping_mul(P1);
+            hash = hash.rotate_left(23).wrapping_mul(P2).wrapping_add(P3);
+            rest = tail;
+        }
+        for &byte in rest {
+            hash ^= (byte as u64).wrapping_mul(P5);
+            hash = hash.rotate_left(11).wrapping_mul(P1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(P2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(P3);
+        hash ^ (hash >> 32)
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (acc, lane) in self.acc.iter_mut().zip(stripe.chunks_exact(8)) {
+            *acc = round(*acc, u64::from_le_bytes(lane.try_into().expect("8 bytes")));
+        }
+    }
+}
+
+impl Hasher for Xxh64 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.digest()
+    }
+}
+
+pub fn xxh64(bytes: &[u8], seed: u64) -> u64 {
+    let mut hasher = Xxh64::new(seed);
+    hasher.update(bytes);
+    hasher.digest()
+}
+
+fn round(acc: u64, lane: u64) -> u64 {
+    acc.wrapping_add(lane.wrapping_mul(P2)).rotate_left(31).wrapping_mul(P1)
+}
+
+fn merge(hash: u64, acc: u64) -> u64 {
+    (hash ^ round(0, acc)).wrapping_mul(P1).wrapping_add(P4)
+}
+
+/* The state hash at each of the last `capacity` frame boundaries, oldest
+ * first, to answer a peer or a movie that reports its hash late */
+#[derive(Debug, Clone)]
+pub struct StateHashes {
+    capacity: usize,
+    hashes: VecDeque<(u64, u64)>,
+}
+
+impl StateHashes {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), hashes: VecDeque::new() }
+    }
+
+    /* Forgets anything recorded for `frame` or later first, so rewinding
+     * and running again replaces the old future */
+    pub fn push(&mut self, frame: u64, hash: u64) {
+        while self.hashes.back().is_some_and(|&(last, _)| last >= frame) {
+            self.hashes.pop_back();
+        }
+        if self.hashes.len() == self.capacity {
+            self.hashes.pop_front();
+        }
+        self.hashes.push_back((frame, hash));
+    }
+
+    pub fn get(&self, frame: u64) -> Option<u64> {
+        let index = self.hashes.binary_search_by_key(&frame, |&(frame, _)| frame).ok()?;
+        Some(self.hashes[index].1)
+    }
+
+    pub fn last(&self) -> Option<(u64, u64)> {
+        self.hashes.back().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.hashes.iter().copied()
+    }
+}
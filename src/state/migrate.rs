@@ -0,0 +1,44 @@
+use std::{borrow::Cow, io::ErrorKind};
+
+use super::STATE_VERSION;
+
+/* The oldest state `migrate` can bring up to date. Version 9 kept I/O as
+ * a flat port array, which no longer maps onto the devices' own state. */
+pub const OLDEST_STATE_VERSION: u8 = 10;
+
+/* Rewrites a state body, everything after the header, from one version to
+ * the next. Each knows only the change its own version bump made. */
+type Migration = fn(&[u8]) -> Result<Vec<u8>, ErrorKind>;
+
+/* MIGRATIONS[i] takes version OLDEST_STATE_VERSION + i to the one after.
+ * The length keeps a STATE_VERSION bump from building without one; a bump
+ * also adds a state from the old version to `fixtures/` for the tests. */
+const MIGRATIONS: [Migration; (STATE_VERSION - OLDEST_STATE_VERSION) as usize] = [
+    v10_microcode,
+];
+
+/* Bytes of CPU state in versions 10 and earlier, at the start of the body */
+const V10_CPU_LEN: usize = 19;
+
+/* A body saved by `version` as STATE_VERSION would have written it;
+ * current states come back untouched. The caller checks that `version`
+ * lies between OLDEST_STATE_VERSION and STATE_VERSION. */
+pub fn migrate(version: u8, body: &[u8]) -> Result<Cow<'_, [u8]>, ErrorKind> {
+    let first = version.checked_sub(OLDEST_STATE_VERSION).ok_or(ErrorKind::InvalidData)? as usize;
+    let mut body = Cow::Borrowed(body);
+    for migration in MIGRATIONS.get(first..).ok_or(ErrorKind::InvalidData)? {
+        body = Cow::Owned(migration(&body)?);
+    }
+    Ok(body)
+}
+
+/* 11 ran instructions as microcode and saves the one in flight after the
+ * CPU registers. Version 10 only ever saved between instructions, so
+ * there is none: an idle routine, all zeros. */
+fn v10_microcode(body: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+    if body.len() < V10_CPU_LEN {
+        return Err(ErrorKind::UnexpectedEof);
+    }
+    let (cpu, rest) = body.split_at(V10_CPU_LEN);
+    Ok([cpu, &[0; 4], rest].concat())
+}
@@ -0,0 +1,31 @@
+#![allow(unused)]
+
+mod hash;
+mod migrate;
+mod slots;
+mod stream;
+
+use std::io::ErrorKind;
+
+use self::stream::{StateReader, StateWriter};
+
+pub const STATE_MAGIC: [u8; 4] = *b"GBST";
+pub const STATE_VERSION: u8 = 11;
+
+/* Implemented by every component that owns emulated state. Fields are
+ * written and read back in the same fixed order; host-side settings such
+ * as palettes, hooks and link cables are not part of a state. */
+pub trait Savestate {
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind>;
+}
+
+pub mod prelude {
+    pub use super::hash::{xxh64, StateHashes, Xxh64};
+    pub use super::migrate::{migrate, OLDEST_STATE_VERSION};
+    pub use super::slots::{
+        slot_path, thumbnail, Slot, AUTOSAVE_SLOT, SLOTS, SLOT_MAGIC, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH,
+    };
+    pub use super::stream::{StateReader, StateWriter};
+    pub use super::{Savestate, STATE_MAGIC, STATE_VERSION};
+}
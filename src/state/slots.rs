@@ -0,0 +1,76 @@
+use std::{io::ErrorKind, path::{Path, PathBuf}};
+
+use crate::ppu::prelude::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use super::stream::{StateReader, StateWriter};
+
+pub const SLOT_MAGIC: [u8; 4] = *b"GBSL";
+/* Half the screen each way, RGB888 */
+pub const THUMBNAIL_WIDTH: usize = SCREEN_WIDTH / 2;
+pub const THUMBNAIL_HEIGHT: usize = SCREEN_HEIGHT / 2;
+/* Written by the autosave timer; 1 to 9 are left for the player */
+pub const AUTOSAVE_SLOT: u8 = 0;
+pub const SLOTS: u8 = 10;
+
+/* A savestate with what a slot picker shows next to it */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slot {
+    /* UNIX seconds, from the emulator's time source */
+    pub timestamp: u64,
+    pub thumbnail: Vec<u8>,
+    pub state: Vec<u8>,
+}
+
+impl Slot {
+    /* `frame` is the 160x144 RGB888 picture on screen when saving */
+    pub fn new(state: Vec<u8>, timestamp: u64, frame: &[u8]) -> Self {
+        Self { timestamp, thumbnail: thumbnail(frame), state }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(&SLOT_MAGIC);
+        w.u64(self.timestamp);
+        w.bytes(&self.thumbnail);
+        w.u32(self.state.len() as u32);
+        w.bytes(&self.state);
+        w.into_inner()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ErrorKind> {
+        let mut r = StateReader::new(data);
+        let mut magic = [0; 4];
+        r.bytes(&mut magic)?;
+        if magic != SLOT_MAGIC {
+            return Err(ErrorKind::InvalidData);
+        }
+        let timestamp = r.u64()?;
+        let mut thumbnail = vec![0; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3];
+        r.bytes(&mut thumbnail)?;
+        let mut state = vec![0; r.u32()? as usize];
+        r.bytes(&mut state)?;
+        match r.remaining() {
+            0 => Ok(Self { timestamp, thumbnail, state }),
+            _ => Err(ErrorKind::InvalidData),
+        }
+    }
+}
+
+/* Each thumbnail pixel averages a 2x2 block of the frame */
+pub fn thumbnail(frame: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 3);
+    for y in 0..THUMBNAIL_HEIGHT {
+        for x in 0..THUMBNAIL_WIDTH {
+            for channel in 0..3 {
+                let at = |dx, dy| frame[((y * 2 + dy) * SCREEN_WIDTH + x * 2 + dx) * 3 + channel] as u16;
+                out.push(((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) / 4) as u8);
+            }
+        }
+    }
+    out
+}
+
+/* Slots sit beside the battery save: game.ss0 through game.ss9 */
+pub fn slot_path(save_path: &Path, slot: u8) -> PathBuf {
+    save_path.with_extension(format!("ss{}", slot))
+}
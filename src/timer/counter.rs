@@ -0,0 +1,263 @@
+use std::{io::ErrorKind, ops::RangeInclusive};
+
+use crate::{cpu::interrupt::Interrupt, debug, mem::prelude::MemoryMappedDevice, state::prelude::{Savestate, StateReader, StateWriter}, trace};
+
+/* Counter bit whose falling edge clocks TIMA, by TAC bits 0-1 */
+const TIMA_BITS: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+/* DIV bit 4, the 512 Hz DIV-APU clock; bit 5 in double speed to keep the rate */
+const DIV_APU_BIT: u16 = 1 << 12;
+const DIV_APU_BIT_DOUBLE: u16 = 1 << 13;
+
+/* Where TIMA is in the M-cycles after it overflows */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+enum Reload {
+    #[default]
+    Idle,
+    /* TIMA reads 0 for a cycle; a write now cancels the reload and interrupt */
+    Pending,
+    /* TMA was just copied in; TIMA writes are lost and TMA writes go through */
+    Reloading,
+}
+
+/* DIV is the top byte of a 16-bit counter running at the T-cycle rate.
+ * TIMA and the APU frame sequencer both count falling edges of its bits,
+ * so writing DIV (which zeroes the counter) can clock either early. */
+#[derive(Debug)]
+pub struct Timer {
+    pub div: u8,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: u8,
+    counter: u16,
+    /* DIV-APU edges not yet handed to the APU */
+    div_apu_edges: usize,
+    reload: Reload,
+    /* Off, TIMA reloads and interrupts the moment it overflows */
+    exact: bool,
+    /* CGB double speed, owned by `Mem` and mirrored here */
+    double_speed: bool,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self { div: 0, tima: 0, tma: 0, tac: 0xF8, counter: 0, div_apu_edges: 0, reload: Reload::Idle, exact: true, double_speed: false }
+    }
+}
+
+impl Timer {
+    pub fn register(&self, addr: u16) -> &u8 {
+        match addr {
+            0xFF04 => &self.div,
+            0xFF05 => &self.tima,
+            0xFF06 => &self.tma,
+            0xFF07 => &self.tac,
+            _ => panic!("Accessing memory ${:#04X}: Not a timer register", addr),
+        }
+    }
+
+    pub fn register_mut(&mut self, addr: u16) -> &mut u8 {
+        match addr {
+            0xFF04 => &mut self.div,
+            0xFF05 => &mut self.tima,
+            0xFF06 => &mut self.tma,
+            0xFF07 => &mut self.tac,
+            _ => panic!("Accessing memory ${:#04X}: Not a timer register", addr),
+        }
+    }
+
+    /* Returns the IF bits to raise */
+    pub fn write_register(&mut self, addr: u16, value: u8) -> u8 {
+        match addr {
+            0xFF04 => self.set_counter(0),
+            0xFF07 => {
+                /* Switching the selected bit can drop the line and clock TIMA */
+                let before = self.tima_line();
+                self.tac = 0xF8 | (value & 0x07);
+                debug!(target: "gbemu::timer", "TAC={:02X}", self.tac);
+                if before && !self.tima_line() { self.increment_tima() } else { 0 }
+            },
+            0xFF05 => {
+                match self.reload {
+                    Reload::Pending => self.reload = Reload::Idle,
+                    Reload::Reloading => return 0,
+                    Reload::Idle => (),
+                }
+                self.tima = value;
+                0
+            },
+            0xFF06 => {
+                self.tma = value;
+                if self.reload == Reload::Reloading {
+                    self.tima = value;
+                }
+                0
+            },
+            _ => {
+                *self.register_mut(addr) = value;
+                0
+            },
+        }
+    }
+
+    /* Advance by `cycles` M-cycles, returning the IF bits to raise */
+    pub fn tick(&mut self, cycles: usize) -> u8 {
+        let mut irq = 0;
+        for _ in 0..cycles {
+            irq |= self.step_reload();
+            irq |= self.set_counter(self.counter.wrapping_add(4));
+        }
+        irq
+    }
+
+    /* Sets the internal counter without clocking anything off the change */
+    pub fn set_internal_counter(&mut self, counter: u16) {
+        self.counter = counter;
+        self.div = (counter >> 8) as u8;
+    }
+
+    /* The 16-bit divider DIV is the top of, in T-cycles */
+    pub fn internal_counter(&self) -> u16 {
+        self.counter
+    }
+
+    pub fn set_exact(&mut self, exact: bool) {
+        self.exact = exact;
+    }
+
+    /* In double speed the APU's frame sequencer steps off DIV bit 5 */
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.double_speed = double_speed;
+    }
+
+    /* Falling edges of DIV bit 4, or bit 5 in double speed, since the last call */
+    pub fn take_div_apu_edges(&mut self) -> usize {
+        std::mem::take(&mut self.div_apu_edges)
+    }
+
+    /* M-cycles until the next timer interrupt, None while stopped */
+    pub fn cycles_to_overflow(&self) -> Option<usize> {
+        if self.reload == Reload::Pending {
+            return Some(1);
+        }
+        if self.tac & 0x04 == 0 {
+            return None;
+        }
+        let bit = TIMA_BITS[(self.tac & 0x03) as usize];
+        let edges = 0x100 - self.tima as usize;
+        /* The interrupt comes with the reload, a cycle after the overflow */
+        Some(self.cycles_to_edge(bit) + (edges - 1) * bit as usize / 2 + self.exact as usize)
+    }
+
+    /* M-cycles until the next DIV-APU edge */
+    pub fn cycles_to_div_apu(&self) -> usize {
+        self.cycles_to_edge(if self.double_speed { DIV_APU_BIT_DOUBLE } else { DIV_APU_BIT })
+    }
+
+    /* A bit falls each time the counter reaches a multiple of twice its value */
+    fn cycles_to_edge(&self, bit: u16) -> usize {
+        let period = bit as usize * 2;
+        (period - self.counter as usize % period).div_ceil(4)
+    }
+
+    fn tima_line(&self) -> bool {
+        self.tac & 0x04 != 0 && self.counter & TIMA_BITS[(self.tac & 0x03) as usize] != 0
+    }
+
+    fn set_counter(&mut self, counter: u16) -> u8 {
+        let before = self.tima_line();
+        let bit = if self.double_speed { DIV_APU_BIT_DOUBLE } else { DIV_APU_BIT };
+        let div_apu = self.counter & bit != 0;
+        self.counter = counter;
+        self.div = (counter >> 8) as u8;
+
+        if div_apu && counter & bit == 0 {
+            self.div_apu_edges += 1;
+        }
+        if before && !self.tima_line() { self.increment_tima() } else { 0 }
+    }
+
+    /* Overflow leaves TIMA at 0 for a cycle before TMA and the interrupt land */
+    fn increment_tima(&mut self) -> u8 {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        match overflow {
+            true if self.exact => self.reload = Reload::Pending,
+            true => {
+                self.tima = self.tma;
+                return Interrupt::Timer as u8;
+            },
+            false => (),
+        }
+        0
+    }
+
+    fn step_reload(&mut self) -> u8 {
+        match self.reload {
+            Reload::Pending => {
+                trace!(target: "gbemu::timer", "TIMA overflow, reload {:02X}", self.tma);
+                self.tima = self.tma;
+                self.reload = Reload::Reloading;
+                Interrupt::Timer as u8
+            },
+            Reload::Reloading => {
+                self.reload = Reload::Idle;
+                0
+            },
+            Reload::Idle => 0,
+        }
+    }
+}
+
+const CLAIMS: &[RangeInclusive<u16>] = &[0xFF04..=0xFF07];
+
+impl MemoryMappedDevice for Timer {
+    fn claims(&self, _cgb: bool) -> &'static [RangeInclusive<u16>] {
+        CLAIMS
+    }
+
+    fn io_register(&self, addr: u16) -> &u8 {
+        self.register(addr)
+    }
+
+    fn io_register_mut(&mut self, addr: u16) -> &mut u8 {
+        self.register_mut(addr)
+    }
+
+    fn io_write(&mut self, addr: u16, value: u8) -> u8 {
+        self.write_register(addr, value)
+    }
+
+    fn tick(&mut self, cycles: usize) -> u8 {
+        Timer::tick(self, cycles)
+    }
+
+    fn cpu_clocked(&self) -> bool {
+        true
+    }
+}
+
+impl Savestate for Timer {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.counter);
+        w.u8(self.tima);
+        w.u8(self.tma);
+        w.u8(self.tac);
+        w.u8(self.reload as u8);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), ErrorKind> {
+        self.counter = r.u16()?;
+        self.div = (self.counter >> 8) as u8;
+        self.tima = r.u8()?;
+        self.tma = r.u8()?;
+        self.tac = r.u8()?;
+        self.reload = match r.u8()? {
+            0 => Reload::Idle,
+            1 => Reload::Pending,
+            2 => Reload::Reloading,
+            _ => return Err(ErrorKind::InvalidData),
+        };
+        self.div_apu_edges = 0;
+        Ok(())
+    }
+}
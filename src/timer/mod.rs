@@ -0,0 +1,7 @@
+#![allow(unused)]
+
+mod counter;
+
+pub mod prelude {
+    pub use super::counter::Timer;
+}
@@ -0,0 +1,9 @@
+#![allow(unused)]
+
+mod terminal;
+mod view;
+
+pub mod prelude {
+    pub use super::terminal::{key_names, ChannelKey, Terminal, TerminalKeys, HOLD_FRAMES};
+    pub use super::view::{TuiView, DEFAULT_TRACE_LINES};
+}
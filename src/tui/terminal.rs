@@ -0,0 +1,160 @@
+use std::{
+    io::{self, Read, Write},
+    process::{Command, Stdio},
+    sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, Sender}, Arc},
+};
+
+use crate::{
+    apu::prelude::Channel,
+    config::prelude::KeyBindings,
+    input::prelude::{ButtonState, InputSource},
+};
+
+/* Terminals report key presses and repeats but never releases, so a press
+ * holds its button this long; the key repeat keeps a held key down */
+pub const HOLD_FRAMES: u64 = 10;
+
+/* The controlling terminal in raw mode on the alternate screen, put back
+ * as it was on drop. Ctrl-C arrives as a key and asks to quit. */
+pub struct Terminal {
+    saved: String,
+    quit: Arc<AtomicBool>,
+    channel_keys: Receiver<ChannelKey>,
+}
+
+/* Sound channel hotkeys, for keys no button is bound to: 1 to 4 mute or
+ * unmute that channel, 5 to 8 solo it or stop soloing it */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChannelKey {
+    Mute(Channel),
+    Solo(Channel),
+}
+
+impl ChannelKey {
+    pub fn from_key(name: &str) -> Option<Self> {
+        let digit = match name.as_bytes() {
+            &[digit @ b'1'..=b'8'] => (digit - b'1') as usize,
+            _ => return None,
+        };
+        Some(match digit {
+            0..4 => Self::Mute(Channel::ALL[digit]),
+            _ => Self::Solo(Channel::ALL[digit - 4]),
+        })
+    }
+}
+
+/* Keys read from stdin by a thread of their own, as buttons through the
+ * `[keys]` bindings */
+pub struct TerminalKeys {
+    keys: KeyBindings,
+    pressed: Receiver<String>,
+    held_until: [u64; 8],
+    quit: Arc<AtomicBool>,
+    channel_keys: Sender<ChannelKey>,
+}
+
+impl Terminal {
+    pub fn open(keys: KeyBindings) -> io::Result<(Self, TerminalKeys)> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        stdout.flush()?;
+
+        let (sender, pressed) = mpsc::channel();
+        let quit = Arc::new(AtomicBool::new(false));
+        std::thread::spawn(move || {
+            let mut stdin = io::stdin();
+            let mut buf = [0; 64];
+            while let Ok(len @ 1..) = stdin.read(&mut buf) {
+                if key_names(&buf[..len]).into_iter().any(|name| sender.send(name).is_err()) {
+                    break;
+                }
+            }
+        });
+        let (channel_sender, channel_keys) = mpsc::channel();
+        let keys = TerminalKeys { keys, pressed, held_until: [0; 8], quit: quit.clone(), channel_keys: channel_sender };
+        Ok((Self { saved, quit, channel_keys }, keys))
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.quit.load(Ordering::Relaxed)
+    }
+
+    /* Channel hotkeys pressed since the last call */
+    pub fn take_channel_keys(&self) -> Vec<ChannelKey> {
+        self.channel_keys.try_iter().collect()
+    }
+
+    pub fn draw(&mut self, text: &str) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+impl InputSource for TerminalKeys {
+    fn poll(&mut self, frame: u64) -> ButtonState {
+        for name in self.pressed.try_iter() {
+            if name == "Ctrl-C" {
+                self.quit.store(true, Ordering::Relaxed);
+            } else if let Some(button) = self.keys.button(&name) {
+                self.held_until[button as usize] = frame + HOLD_FRAMES;
+            } else if let Some(key) = ChannelKey::from_key(&name) {
+                let _ = self.channel_keys.send(key);
+            }
+        }
+        let mut state = ButtonState::default();
+        for (bit, &until) in self.held_until.iter().enumerate() {
+            state.set((bit as u8).into(), frame < until);
+        }
+        state
+    }
+}
+
+/* Keys in a chunk read from a raw terminal, named as `[keys]` spells
+ * them: arrows, `Return`, `Backspace`, `Space`, `Tab`, `Escape` and
+ * letters and digits as themselves; anything else is dropped */
+pub fn key_names(bytes: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let name = match bytes[i..] {
+            [0x1B, b'[' | b'O', arrow @ b'A'..=b'D', ..] => {
+                i += 2;
+                ["Up", "Down", "Right", "Left"][(arrow - b'A') as usize].to_string()
+            },
+            [0x1B, ..] => "Escape".to_string(),
+            [0x03, ..] => "Ctrl-C".to_string(),
+            [b'\r' | b'\n', ..] => "Return".to_string(),
+            [0x7F | 0x08, ..] => "Backspace".to_string(),
+            [b' ', ..] => "Space".to_string(),
+            [b'\t', ..] => "Tab".to_string(),
+            [byte, ..] if byte.is_ascii_alphanumeric() => (byte.to_ascii_uppercase() as char).to_string(),
+            _ => String::new(),
+        };
+        if !name.is_empty() {
+            names.push(name);
+        }
+        i += 1;
+    }
+    names
+}
+
+/* `stty` on the terminal this process reads from */
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).stderr(Stdio::piped()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,123 @@
+use crate::{
+    cpu::register::types::{Flags, Register16},
+    gba::prelude::Gba,
+};
+
+pub const DEFAULT_TRACE_LINES: usize = 8;
+
+/* I/O registers shown beside the screen, two to a line */
+const IO: [(&str, u16); 16] = [
+    ("LCDC", 0xFF40), ("STAT", 0xFF41),
+    ("SCY", 0xFF42), ("SCX", 0xFF43),
+    ("LY", 0xFF44), ("LYC", 0xFF45),
+    ("WY", 0xFF4A), ("WX", 0xFF4B),
+    ("BGP", 0xFF47), ("OBP0", 0xFF48),
+    ("DIV", 0xFF04), ("TIMA", 0xFF05),
+    ("TMA", 0xFF06), ("TAC", 0xFF07),
+    ("IF", 0xFF0F), ("IE", 0xFFFF),
+];
+
+/* The console as text: the screen in half blocks, two pixels to a cell,
+ * with the registers and I/O beside it and the last instructions below.
+ * Remembers what it last drew so a frame only rewrites the lines that
+ * changed, which keeps it usable over SSH. */
+#[derive(Debug, Clone)]
+pub struct TuiView {
+    pub trace_lines: usize,
+    previous: Vec<String>,
+}
+
+impl Default for TuiView {
+    fn default() -> Self {
+        Self { trace_lines: DEFAULT_TRACE_LINES, previous: Vec::new() }
+    }
+}
+
+impl TuiView {
+    /* Every line of the view, `rgb` being the frame shown, `width` by
+     * `height` pixels */
+    pub fn lines(&self, gba: &mut Gba, rgb: &[u8], width: usize, height: usize) -> Vec<String> {
+        gba.mem.sync();
+        let panel = panel(gba);
+        let mut lines: Vec<String> = (0..height.div_ceil(2)).map(|row| {
+            let screen = half_blocks(rgb, width, height, row);
+            match panel.get(row) {
+                Some(text) => format!("{} {}", screen, text),
+                None => screen,
+            }
+        }).collect();
+
+        let entries: Vec<_> = gba.trace.iter().collect();
+        let skip = entries.len().saturating_sub(self.trace_lines);
+        lines.extend(entries[skip..].iter().map(|entry| entry.to_string()));
+        lines
+    }
+
+    /* Escape codes taking the terminal from the last frame drawn to
+     * `lines`; the first call clears it */
+    pub fn draw(&mut self, lines: Vec<String>) -> String {
+        let mut out = String::new();
+        if self.previous.is_empty() {
+            out.push_str("\x1b[2J");
+        }
+        for (row, line) in lines.iter().enumerate() {
+            if self.previous.get(row) != Some(line) {
+                out.push_str(&format!("\x1b[{};1H{}\x1b[K", row + 1, line));
+            }
+        }
+        for row in lines.len()..self.previous.len() {
+            out.push_str(&format!("\x1b[{};1H\x1b[K", row + 1));
+        }
+        self.previous = lines;
+        out
+    }
+
+    /* Forget what is on the terminal, so the next draw repaints it all */
+    pub fn invalidate(&mut self) {
+        self.previous.clear();
+    }
+}
+
+/* One row of cells: the upper pixel as the foreground of `▀`, the lower
+ * as its background, with a color code only where the color changes */
+fn half_blocks(rgb: &[u8], width: usize, height: usize, row: usize) -> String {
+    let pixel = |x: usize, y: usize| {
+        let i = (y.min(height - 1) * width + x) * 3;
+        rgb.get(i..i + 3).map_or((0, 0, 0), |p| (p[0], p[1], p[2]))
+    };
+    let mut out = String::new();
+    let mut current = None;
+    for x in 0..width {
+        let cell = (pixel(x, row * 2), pixel(x, row * 2 + 1));
+        if current != Some(cell) {
+            let ((r, g, b), (r2, g2, b2)) = cell;
+            out.push_str(&format!("\x1b[38;2;{};{};{};48;2;{};{};{}m", r, g, b, r2, g2, b2));
+            current = Some(cell);
+        }
+        out.push('▀');
+    }
+    out.push_str("\x1b[0m");
+    out
+}
+
+fn panel(gba: &Gba) -> Vec<String> {
+    let registers = &gba.cpu.registers;
+    let r16 = |register| registers.get_r16(register);
+    let flags: String = [(Flags::Zero, 'Z'), (Flags::Subtract, 'N'), (Flags::HalfCarry, 'H'), (Flags::Carry, 'C')]
+        .iter()
+        .map(|&(flag, name)| if registers.f.is_set(flag) { name } else { '-' })
+        .collect();
+    let mut lines = vec![
+        format!("AF={:04X} BC={:04X}", r16(Register16::AF), r16(Register16::BC)),
+        format!("DE={:04X} HL={:04X}", r16(Register16::DE), r16(Register16::HL)),
+        format!("SP={:04X} PC={:04X}", registers.sp, registers.pc),
+        format!("{} IME={} {:?}", flags, gba.cpu.ime, gba.cpu.state),
+        gba.disassemble_at(registers.pc),
+        String::new(),
+    ];
+    lines.extend(IO.chunks(2).map(|pair| {
+        let cells: Vec<String> = pair.iter().map(|&(name, addr)| format!("{:<5}{:02X}", name, gba.mem.peek(addr))).collect();
+        cells.join("  ")
+    }));
+    lines
+}
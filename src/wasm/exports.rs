@@ -0,0 +1,100 @@
+use crate::{
+    gba::prelude::{Emulator, RomSource},
+    input::prelude::Button,
+    ppu::prelude::{RgbaFrame, SCREEN_HEIGHT, SCREEN_WIDTH},
+};
+
+/* Emulator plus the last RGBA frame, whose tight packing is what canvas
+ * ImageData expects */
+pub struct WebEmulator {
+    emulator: Emulator,
+    rgba: RgbaFrame,
+}
+
+impl WebEmulator {
+    pub fn new(rom: Vec<u8>) -> Option<Self> {
+        let emulator = Emulator::new(RomSource::Bytes(rom)).ok()?;
+        let rgba = RgbaFrame::from_rgb(&[0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 3], SCREEN_WIDTH, SCREEN_HEIGHT);
+        Some(Self { emulator, rgba })
+    }
+
+    pub fn emulator(&mut self) -> &mut Emulator {
+        &mut self.emulator
+    }
+
+    pub fn rgba_framebuffer(&mut self) -> &[u8] {
+        self.rgba = self.emulator.rgba_frame();
+        &self.rgba.pixels
+    }
+}
+
+/* Reserve `len` bytes in linear memory for the host to copy a ROM into */
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn gb_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0_u8; len].into_boxed_slice()) as *mut u8
+}
+
+/* Takes ownership of a buffer from `gb_alloc`. Returns null if the ROM is rejected.
+ *
+ * # Safety
+ * `rom` and `len` must come from a single, unfreed `gb_alloc` call. */
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub unsafe extern "C" fn gb_new(rom: *mut u8, len: usize) -> *mut WebEmulator {
+    let rom = Box::from_raw(std::ptr::slice_from_raw_parts_mut(rom, len));
+    match WebEmulator::new(rom.into_vec()) {
+        Some(web) => Box::into_raw(Box::new(web)),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/* # Safety
+ * `web` must come from `gb_new` and not be used afterwards. */
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub unsafe extern "C" fn gb_free(web: *mut WebEmulator) {
+    if !web.is_null() {
+        drop(Box::from_raw(web));
+    }
+}
+
+/* # Safety
+ * `web` must be a live pointer from `gb_new`. */
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub unsafe extern "C" fn gb_step(web: *mut WebEmulator) -> u32 {
+    /* 0 once the CPU has locked up */
+    (*web).emulator.step().unwrap_or(0) as u32
+}
+
+/* # Safety
+ * `web` must be a live pointer from `gb_new`. */
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub unsafe extern "C" fn gb_run_frame(web: *mut WebEmulator) -> u32 {
+    (*web).emulator.run_frame().unwrap_or(0) as u32
+}
+
+/* # Safety
+ * `web` must be a live pointer from `gb_new`. */
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub unsafe extern "C" fn gb_set_button(web: *mut WebEmulator, button: u8, pressed: bool) {
+    if button < 8 {
+        (*web).emulator.set_button(Button::from(button), pressed);
+    }
+}
+
+/* Pointer to SCREEN_WIDTH * SCREEN_HEIGHT RGBA pixels, valid until the next call.
+ *
+ * # Safety
+ * `web` must be a live pointer from `gb_new`. */
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub unsafe extern "C" fn gb_framebuffer(web: *mut WebEmulator) -> *const u8 {
+    (*web).rgba_framebuffer().as_ptr()
+}
+
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn gb_screen_width() -> u32 {
+    SCREEN_WIDTH as u32
+}
+
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub extern "C" fn gb_screen_height() -> u32 {
+    SCREEN_HEIGHT as u32
+}
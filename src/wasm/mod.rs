@@ -0,0 +1,12 @@
+#![allow(unused)]
+
+/* Plain C ABI for wasm32-unknown-unknown hosts. The exports only take and
+ * return integers and linear-memory pointers, so they can be instantiated
+ * straight from JavaScript without any generated bindings; see
+ * `web/index.html`. Build with `cargo build --release --lib
+ * --target wasm32-unknown-unknown --no-default-features --features audio`. */
+pub(crate) mod exports;
+
+pub mod prelude {
+    pub use super::exports::WebEmulator;
+}